@@ -0,0 +1,104 @@
+//! Serves k-nearest-neighbor searches over TCP from many threads at once.
+//!
+//! The server handles each connection on its own thread, with its own clone of
+//! a `SharedCakes`. Each request is a line holding `k` followed by the
+//! coordinates of the query, separated by spaces, and each response is a line
+//! of `index:distance` pairs for the hits.
+//!
+//! Run with `cargo run --release --example search_server`. The example starts
+//! the server on a free port, sends it queries from several clients at once,
+//! and prints what they receive.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+use abd_clam::{knn, PartitionCriteria, SharedCakes, VecDataset};
+use rand::prelude::*;
+
+/// The dimensionality of the instances.
+const DIM: usize = 10;
+
+#[allow(clippy::ptr_arg)]
+fn euclidean(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    distances::vectors::euclidean(x, y)
+}
+
+/// Handles the requests on one connection until the client hangs up.
+fn serve(cakes: &SharedCakes<Vec<f32>, f32, VecDataset<Vec<f32>, f32, usize>>, stream: TcpStream) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines().map_while(Result::ok) {
+        let mut fields = line.split_whitespace();
+        let k = fields.next().and_then(|k| k.parse::<usize>().ok());
+        let query = fields.map(str::parse::<f32>).collect::<Result<Vec<_>, _>>();
+        let response = match (k, query) {
+            (Some(k), Ok(query)) if query.len() == DIM => cakes
+                .knn_search(&query, k, knn::Algorithm::default())
+                .into_iter()
+                .map(|(i, d)| format!("{i}:{d}"))
+                .collect::<Vec<_>>()
+                .join(" "),
+            _ => format!("error: expected k followed by {DIM} coordinates"),
+        };
+        if writeln!(writer, "{response}").is_err() {
+            return;
+        }
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let mut rng = StdRng::seed_from_u64(42);
+    let data = symagen::random_data::random_tabular(100_000, DIM, -1., 1., &mut rng);
+    let data = VecDataset::new("server".to_string(), data, euclidean, false);
+
+    let mut cakes = abd_clam::Cakes::new(data, Some(42), &PartitionCriteria::default());
+    cakes.auto_tune_knn(10, 10);
+    let cakes = cakes.into_shared();
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+    println!("Serving searches on {address}.");
+
+    let server = cakes.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().map_while(Result::ok) {
+            let cakes = server.clone();
+            thread::spawn(move || serve(&cakes, stream));
+        }
+    });
+
+    let clients = (0..8_u64)
+        .map(|client| {
+            thread::spawn(move || -> std::io::Result<Vec<String>> {
+                let mut rng = StdRng::seed_from_u64(client);
+                let mut stream = TcpStream::connect(address)?;
+                let mut reader = BufReader::new(stream.try_clone()?);
+                let mut responses = Vec::new();
+                for _ in 0..4 {
+                    let query = (0..DIM).map(|_| rng.gen_range(-1.0..1.0).to_string());
+                    writeln!(stream, "5 {}", query.collect::<Vec<_>>().join(" "))?;
+                    let mut response = String::new();
+                    reader.read_line(&mut response)?;
+                    responses.push(response.trim().to_string());
+                }
+                Ok(responses)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for (client, handle) in clients.into_iter().enumerate() {
+        let responses = handle
+            .join()
+            .unwrap_or_else(|_| unreachable!("Clients do not panic."))?;
+        for response in responses {
+            println!("client {client}: {response}");
+        }
+    }
+    println!("{} handles to the index are still open.", cakes.num_handles());
+
+    Ok(())
+}
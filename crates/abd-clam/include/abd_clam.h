@@ -0,0 +1,152 @@
+#ifndef ABD_CLAM_H
+#define ABD_CLAM_H
+
+/* Declarations for src/ffi.rs. Regenerate with cbindgen, see cbindgen.toml. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+// The outcome of a call.
+typedef enum ClamStatus {
+  // The call succeeded.
+  CLAM_STATUS_OK = 0,
+  // An argument was null or invalid, e.g. the name of an unknown metric.
+  CLAM_STATUS_INVALID_ARGUMENT = 1,
+  // The library panicked. Any index passed to the call may only be freed.
+  CLAM_STATUS_PANIC = 2,
+} ClamStatus;
+
+// An opaque search index over the rows of a buffer.
+typedef struct ClamIndex ClamIndex;
+
+// The hits of a search, sorted by increasing distance.
+typedef struct ClamHits {
+  // The indices of the hits.
+  size_t *indices;
+  // The distances from the query to the hits.
+  float *distances;
+  // The number of hits.
+  size_t len;
+} ClamHits;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Returns the message of the last error on the calling thread, or null if
+// there has been none.
+//
+// The message is owned by the library and remains valid until the next call
+// which fails on the same thread.
+const char *clam_last_error(void);
+
+// Builds an index over the rows of a buffer.
+//
+// The rows are copied, so the buffer may be freed after this returns.
+//
+// # Arguments
+//
+// * `data`: A row-major buffer of `cardinality * dim` floats.
+// * `cardinality`: The number of rows.
+// * `dim`: The number of columns.
+// * `metric`: One of `"euclidean"`, `"manhattan"` or `"cosine"`, or null
+//   for `"euclidean"`.
+// * `seed`: A pointer to the seed for building the tree, or null for a
+//   random seed.
+// * `min_cardinality`: Clusters with fewer instances are not partitioned.
+// * `out`: Where to write the index, or null on failure.
+//
+// # Safety
+//
+// * `data` must point to `cardinality * dim` readable floats.
+// * `metric` must be null or a valid nul-terminated string.
+// * `seed` must be null or point to a readable `uint64_t`.
+// * `out` must point to a writable `ClamIndex *`.
+ClamStatus clam_build(const float *data,
+                      size_t cardinality,
+                      size_t dim,
+                      const char *metric,
+                      const uint64_t *seed,
+                      size_t min_cardinality,
+                      ClamIndex **out);
+
+// Returns the number of instances in an index.
+//
+// # Safety
+//
+// `index` must have been returned by `clam_build` and not yet freed.
+size_t clam_cardinality(const ClamIndex *index);
+
+// Returns the dimensionality of the instances in an index.
+//
+// # Safety
+//
+// `index` must have been returned by `clam_build` and not yet freed.
+size_t clam_dim(const ClamIndex *index);
+
+// Searches for the `k` nearest neighbors of a query.
+//
+// # Arguments
+//
+// * `index`: The index to search.
+// * `query`: A buffer of `clam_dim(index)` floats.
+// * `k`: The number of neighbors to search for.
+// * `algorithm`: The name of a `knn::Algorithm`, or null for the default.
+// * `out`: Where to write the hits, to be freed with `clam_hits_free`, or
+//   null on failure.
+//
+// # Safety
+//
+// * `index` must have been returned by `clam_build` and not yet freed.
+// * `query` must point to `clam_dim(index)` readable floats.
+// * `algorithm` must be null or a valid nul-terminated string.
+// * `out` must point to a writable `ClamHits *`.
+ClamStatus clam_knn_search(const ClamIndex *index,
+                           const float *query,
+                           size_t k,
+                           const char *algorithm,
+                           ClamHits **out);
+
+// Searches for all neighbors of a query within a radius.
+//
+// # Arguments
+//
+// * `index`: The index to search.
+// * `query`: A buffer of `clam_dim(index)` floats.
+// * `radius`: The radius to search within.
+// * `algorithm`: The name of an `rnn::Algorithm`, or null for the default.
+// * `out`: Where to write the hits, to be freed with `clam_hits_free`, or
+//   null on failure.
+//
+// # Safety
+//
+// * `index` must have been returned by `clam_build` and not yet freed.
+// * `query` must point to `clam_dim(index)` readable floats.
+// * `algorithm` must be null or a valid nul-terminated string.
+// * `out` must point to a writable `ClamHits *`.
+ClamStatus clam_rnn_search(const ClamIndex *index,
+                           const float *query,
+                           float radius,
+                           const char *algorithm,
+                           ClamHits **out);
+
+// Frees the hits of a search. Passing null does nothing.
+//
+// # Safety
+//
+// `hits` must be null or have been returned by a search and not yet freed.
+ClamStatus clam_hits_free(ClamHits *hits);
+
+// Frees an index. Passing null does nothing.
+//
+// # Safety
+//
+// `index` must be null or have been returned by `clam_build` and not yet
+// freed.
+ClamStatus clam_free(ClamIndex *index);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif // ABD_CLAM_H
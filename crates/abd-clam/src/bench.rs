@@ -0,0 +1,320 @@
+//! A harness for benchmarking the search algorithms in `cakes` on any dataset.
+//!
+//! The criterion benchmarks in the repository measure the algorithms on
+//! synthetic data. A `Sweep` runs the same kind of measurement on a `Cakes`
+//! built from the caller's own data and queries: every algorithm is timed at
+//! every `k` and every radius, and its hits are scored against those of
+//! `Linear` search. The results are collected in a `BenchReport`, which may be
+//! written as JSON or CSV, so that the choice of default algorithms can be
+//! checked, and reproduced, on the data they will actually be used with.
+
+use std::time::Instant;
+
+use distances::Number;
+use serde::{Deserialize, Serialize};
+
+use crate::{eval, knn, rnn, Cakes, Dataset, Instance};
+
+/// The parameters of a benchmark sweep.
+///
+/// Every KNN algorithm is run at every `k`, and every RNN algorithm at every
+/// radius. Each batch of queries is searched `repeats` times, and the median
+/// time is reported.
+#[derive(Clone, Debug)]
+pub struct Sweep<U: Number> {
+    /// The KNN algorithms to run.
+    knn_algorithms: Vec<knn::Algorithm>,
+    /// The numbers of neighbors to search for.
+    ks: Vec<usize>,
+    /// The RNN algorithms to run.
+    rnn_algorithms: Vec<rnn::Algorithm>,
+    /// The radii to search within.
+    radii: Vec<U>,
+    /// The number of times each batch of queries is searched.
+    repeats: usize,
+}
+
+impl<U: Number> Default for Sweep<U> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<U: Number> Sweep<U> {
+    /// Creates a sweep over all the KNN algorithms, with `Linear` and the
+    /// exact variants, at `k` of `1`, `10` and `100`, and over all the RNN
+    /// algorithms, with no radii. Each batch of queries is searched three
+    /// times.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            knn_algorithms: core::iter::once(knn::Algorithm::Linear)
+                .chain(knn::Algorithm::variants().iter().copied())
+                .collect(),
+            ks: vec![1, 10, 100],
+            rnn_algorithms: core::iter::once(rnn::Algorithm::Linear)
+                .chain(rnn::Algorithm::variants().iter().copied())
+                .collect(),
+            radii: Vec::new(),
+            repeats: 3,
+        }
+    }
+
+    /// Sets the KNN algorithms to run.
+    #[must_use]
+    pub fn with_knn_algorithms(mut self, algorithms: &[knn::Algorithm]) -> Self {
+        self.knn_algorithms = algorithms.to_vec();
+        self
+    }
+
+    /// Sets the numbers of neighbors to search for.
+    #[must_use]
+    pub fn with_ks(mut self, ks: &[usize]) -> Self {
+        self.ks = ks.to_vec();
+        self
+    }
+
+    /// Sets the RNN algorithms to run.
+    #[must_use]
+    pub fn with_rnn_algorithms(mut self, algorithms: &[rnn::Algorithm]) -> Self {
+        self.rnn_algorithms = algorithms.to_vec();
+        self
+    }
+
+    /// Sets the radii to search within.
+    #[must_use]
+    pub fn with_radii(mut self, radii: &[U]) -> Self {
+        self.radii = radii.to_vec();
+        self
+    }
+
+    /// Sets the number of times each batch of queries is searched. This is at
+    /// least one.
+    #[must_use]
+    pub fn with_repeats(mut self, repeats: usize) -> Self {
+        self.repeats = repeats.max(1);
+        self
+    }
+
+    /// Runs the sweep.
+    ///
+    /// The ground truth for each `k` and radius is found with `Linear` search
+    /// before any algorithm is timed.
+    ///
+    /// # Arguments
+    ///
+    /// * `cakes` - The search structure, built from the dataset to benchmark.
+    /// * `queries` - The queries to search around.
+    ///
+    /// # Errors
+    ///
+    /// * If there are no queries.
+    pub fn run<I: Instance, D: Dataset<I, U>>(
+        &self,
+        cakes: &Cakes<I, U, D>,
+        queries: &[&I],
+    ) -> Result<BenchReport, String> {
+        if queries.is_empty() {
+            return Err("Cannot benchmark search without queries".to_string());
+        }
+
+        let mut measurements = Vec::new();
+        for &k in &self.ks {
+            let truth = cakes.batch_knn_search(queries, k, knn::Algorithm::Linear);
+            for &algo in &self.knn_algorithms {
+                let (seconds, hits) = self.time(|| cakes.batch_knn_search(queries, k, algo));
+                measurements.push(Measurement::new(
+                    "knn",
+                    algo.name(),
+                    Some(k),
+                    None,
+                    seconds,
+                    &hits,
+                    &truth,
+                ));
+            }
+        }
+        for &radius in &self.radii {
+            let truth = cakes.batch_linear_rnn_search(queries, radius);
+            for &algo in &self.rnn_algorithms {
+                let (seconds, hits) = self.time(|| cakes.batch_rnn_search(queries, radius, algo));
+                let radius = Some(radius.as_f64());
+                measurements.push(Measurement::new(
+                    "rnn",
+                    algo.name(),
+                    None,
+                    radius,
+                    seconds,
+                    &hits,
+                    &truth,
+                ));
+            }
+        }
+
+        let dataset = cakes
+            .shards()
+            .first()
+            .map_or_else(String::new, |d| d.name().to_string());
+        Ok(BenchReport {
+            dataset,
+            cardinality: cakes.total_cardinality(),
+            num_shards: cakes.num_shards(),
+            num_queries: queries.len(),
+            repeats: self.repeats,
+            measurements,
+        })
+    }
+
+    /// Runs a batch search `repeats` times, returning the median time in
+    /// seconds and the hits of the last run.
+    fn time<F: Fn() -> Vec<Vec<(usize, U)>>>(&self, search: F) -> (f64, Vec<Vec<(usize, U)>>) {
+        let mut times = Vec::with_capacity(self.repeats);
+        let mut hits = Vec::new();
+        for _ in 0..self.repeats {
+            let start = Instant::now();
+            hits = search();
+            times.push(start.elapsed().as_secs_f64());
+        }
+        times.sort_by(f64::total_cmp);
+        (times[times.len() / 2], hits)
+    }
+}
+
+/// The measurement of one algorithm at one `k` or radius.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Measurement {
+    /// The kind of search, either `"knn"` or `"rnn"`.
+    pub search: String,
+    /// The name of the algorithm.
+    pub algorithm: String,
+    /// The number of neighbors searched for, for KNN search.
+    pub k: Option<usize>,
+    /// The radius searched within, for RNN search.
+    pub radius: Option<f64>,
+    /// The median time, in seconds, to search the whole batch of queries.
+    pub seconds: f64,
+    /// The number of queries searched per second.
+    pub throughput: f64,
+    /// The mean number of hits per query.
+    pub mean_hits: f64,
+    /// The mean recall against `Linear` search, as computed by
+    /// `eval::recall_at_k`.
+    pub recall: f64,
+}
+
+impl Measurement {
+    /// Creates a measurement from the hits of a batch search and the ground
+    /// truth for the same queries.
+    fn new<U: Number>(
+        search: &str,
+        algorithm: &str,
+        k: Option<usize>,
+        radius: Option<f64>,
+        seconds: f64,
+        hits: &[Vec<(usize, U)>],
+        truth: &[Vec<(usize, U)>],
+    ) -> Self {
+        let n = hits.len().as_f64();
+        let recall = hits
+            .iter()
+            .zip(truth)
+            .map(|(h, t)| eval::recall_at_k(h, t))
+            .sum::<f64>();
+        Self {
+            search: search.to_string(),
+            algorithm: algorithm.to_string(),
+            k,
+            radius,
+            seconds,
+            throughput: if seconds > 0.0 { n / seconds } else { f64::INFINITY },
+            mean_hits: hits.iter().map(Vec::len).sum::<usize>().as_f64() / n,
+            recall: recall / n,
+        }
+    }
+}
+
+/// The results of a `Sweep`, as returned by `Sweep::run`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchReport {
+    /// The name of the dataset, or of its first shard.
+    pub dataset: String,
+    /// The number of instances in the dataset.
+    pub cardinality: usize,
+    /// The number of shards of the dataset.
+    pub num_shards: usize,
+    /// The number of queries searched.
+    pub num_queries: usize,
+    /// The number of times each batch of queries was searched.
+    pub repeats: usize,
+    /// The measurements, for each `k` and then each radius, in the order of
+    /// the algorithms of the `Sweep`.
+    pub measurements: Vec<Measurement>,
+}
+
+impl BenchReport {
+    /// Returns the fastest measurement of KNN search at `k` with a recall of
+    /// one, i.e. the algorithm which the measurements recommend for `k`.
+    ///
+    /// # Arguments
+    ///
+    /// * `k` - The number of neighbors searched for.
+    #[must_use]
+    pub fn fastest_knn(&self, k: usize) -> Option<&Measurement> {
+        self.fastest_exact(|m| m.search == "knn" && m.k == Some(k))
+    }
+
+    /// Returns the fastest measurement of RNN search at `radius` with a recall
+    /// of one.
+    ///
+    /// # Arguments
+    ///
+    /// * `radius` - The radius searched within.
+    #[must_use]
+    pub fn fastest_rnn<U: Number>(&self, radius: U) -> Option<&Measurement> {
+        let radius = radius.as_f64();
+        self.fastest_exact(|m| m.search == "rnn" && m.radius == Some(radius))
+    }
+
+    /// Returns the fastest of the measurements which pass the `filter` and
+    /// have a recall of one.
+    fn fastest_exact<F: Fn(&Measurement) -> bool>(&self, filter: F) -> Option<&Measurement> {
+        self.measurements
+            .iter()
+            .filter(|&m| filter(m) && m.recall >= 1.0)
+            .min_by(|a, b| a.seconds.total_cmp(&b.seconds))
+    }
+
+    /// Serializes the report as pretty-printed JSON.
+    ///
+    /// # Errors
+    ///
+    /// * If the report could not be serialized.
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+
+    /// Deserializes a report from JSON.
+    ///
+    /// # Errors
+    ///
+    /// * If the JSON is not a valid report.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+
+    /// Writes the measurements as CSV, with a header row and one row per
+    /// measurement. A missing `k` or radius is an empty field.
+    #[must_use]
+    pub fn to_csv(&self) -> String {
+        let mut csv = "search,algorithm,k,radius,seconds,throughput,mean_hits,recall\n".to_string();
+        csv.extend(self.measurements.iter().map(|m| {
+            let k = m.k.map_or_else(String::new, |k| k.to_string());
+            let radius = m.radius.map_or_else(String::new, |r| r.to_string());
+            format!(
+                "{},{},{k},{radius},{},{},{},{}\n",
+                m.search, m.algorithm, m.seconds, m.throughput, m.mean_hits, m.recall
+            )
+        }));
+        csv
+    }
+}
@@ -0,0 +1,192 @@
+//! Search with queries of a different type than the instances.
+//!
+//! The distance from a query of type `Q` to an instance of type `I` is given
+//! by a function `fn(&Q, &I) -> U`, e.g. from an uncompressed query to a
+//! compressed instance, or from a short query string to a document. Queries
+//! need never be converted into instances.
+//!
+//! The tree is pruned with the triangle inequality, so the distance from a
+//! query must be consistent with the metric of the dataset: for any instances
+//! `x` and `y`, `|f(q, x) - d(x, y)| <= f(q, y) <= f(q, x) + d(x, y)`. This
+//! holds whenever `f(q, x)` is the distance `d(e(q), x)` for some embedding
+//! `e` of queries as instances, even if `e` is never computed. If the metric
+//! of the dataset does not obey the triangle inequality, the search is linear.
+
+use distances::Number;
+
+use crate::par::prelude::*;
+use crate::{Cluster, Dataset, Instance, Tree};
+
+use super::knn::{greedy_sieve::d_min, Hits};
+
+/// Searches for the nearest neighbors of a query of a different type than the
+/// instances.
+///
+/// The traversal is that of `knn::Algorithm::DepthFirstSieve`, with distances
+/// from the query computed by `metric`.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search.
+/// * `query` - The query to search around.
+/// * `metric` - The distance from the query to an instance.
+/// * `k` - The number of neighbors to search for.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is the distance from the query to the instance.
+/// Instances which have been removed from the `tree` are never returned.
+pub fn knn_search<Q, I, U, D, C>(
+    tree: &Tree<I, U, D, C>,
+    query: &Q,
+    metric: fn(&Q, &I) -> U,
+    k: usize,
+) -> Vec<(usize, U)>
+where
+    Q: Sync,
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let mut hits = Hits::new(k);
+    if k == 0 {
+        return hits.extract();
+    }
+
+    let data = tree.data();
+    if !data.is_metric() {
+        let indices = (0..tree.cardinality()).collect::<Vec<_>>();
+        hits.push_batch(distances_to(tree, query, metric, &indices).into_iter());
+        return hits.extract();
+    }
+
+    let root = &tree.root;
+    let d = metric(query, &data.get(root.arg_center()));
+    let mut stack = vec![(root, d, d_min(root, d))];
+
+    while let Some((c, d, bound)) = stack.pop() {
+        if hits.len() == k && bound > hits.peek() {
+            continue;
+        }
+
+        if let Some(children) = c.children() {
+            let mut children = children
+                .into_iter()
+                .map(|c| {
+                    let d = metric(query, &data.get(c.arg_center()));
+                    (c, d, d_min(c, d))
+                })
+                .collect::<Vec<_>>();
+            children.sort_by(|(_, _, a), (_, _, b)| a.partial_cmp(b).unwrap_or(core::cmp::Ordering::Less));
+            // The closer children are pushed last so that they are visited first.
+            stack.extend(children.into_iter().rev());
+        } else if c.is_singleton() {
+            hits.push_batch(c.indices().filter(|&i| !tree.is_removed(i)).map(|i| (i, d)));
+        } else {
+            let indices = c.indices().collect::<Vec<_>>();
+            hits.push_batch(distances_to(tree, query, metric, &indices).into_iter());
+        }
+    }
+
+    hits.extract()
+}
+
+/// Searches for the instances within a radius of a query of a different type
+/// than the instances.
+///
+/// The traversal is that of `rnn::Algorithm::Clustered`, with distances from
+/// the query computed by `metric`.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search.
+/// * `query` - The query to search around.
+/// * `metric` - The distance from the query to an instance.
+/// * `radius` - The radius to search within.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is the distance from the query to the instance.
+/// Instances which have been removed from the `tree` are never returned.
+pub fn rnn_search<Q, I, U, D, C>(
+    tree: &Tree<I, U, D, C>,
+    query: &Q,
+    metric: fn(&Q, &I) -> U,
+    radius: U,
+) -> Vec<(usize, U)>
+where
+    Q: Sync,
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let data = tree.data();
+    if !data.is_metric() {
+        let indices = (0..tree.cardinality()).collect::<Vec<_>>();
+        return distances_to(tree, query, metric, &indices)
+            .into_iter()
+            .filter(|&(_, d)| d <= radius)
+            .collect();
+    }
+
+    let mut hits = Vec::new();
+    let mut straddlers = Vec::new();
+    let mut candidates = vec![&tree.root];
+    while let Some(c) = candidates.pop() {
+        let d = metric(query, &data.get(c.arg_center()));
+        if d > c.radius() + radius {
+            continue;
+        }
+
+        if c.radius() + d <= radius && c.is_singleton() {
+            // Every instance is at the same distance as the center.
+            hits.extend(c.indices().filter(|&i| !tree.is_removed(i)).map(|i| (i, d)));
+        } else if c.radius() + d <= radius || c.is_leaf() {
+            straddlers.extend(c.indices());
+        } else {
+            candidates.extend(
+                c.children()
+                    .unwrap_or_else(|| unreachable!("Non-leaf cluster without children")),
+            );
+        }
+    }
+
+    hits.extend(
+        distances_to(tree, query, metric, &straddlers)
+            .into_iter()
+            .filter(|&(_, d)| d <= radius),
+    );
+    hits
+}
+
+/// Computes the distances from the query to the instances at `indices`, other
+/// than those which have been removed from the `tree`.
+///
+/// The distances are computed in parallel if the metric of the dataset is
+/// expensive.
+fn distances_to<Q, I, U, D, C>(
+    tree: &Tree<I, U, D, C>,
+    query: &Q,
+    metric: fn(&Q, &I) -> U,
+    indices: &[usize],
+) -> Vec<(usize, U)>
+where
+    Q: Sync,
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let data = tree.data();
+    let distance = |&i: &usize| (i, metric(query, &data.get(i)));
+    let indices = indices.iter().filter(|&&i| !tree.is_removed(i));
+    if data.is_metric_expensive() {
+        indices.copied().collect::<Vec<_>>().par_iter().map(distance).collect()
+    } else {
+        indices.map(distance).collect()
+    }
+}
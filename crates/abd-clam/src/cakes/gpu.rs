@@ -0,0 +1,492 @@
+//! Offloading the leaf scans of batch searches to a GPU.
+//!
+//! For large batches of queries, most of the time in search is spent in the
+//! leaves, computing the distances from each query to every instance in the
+//! leaves which the tree could not prune. The searches here use the `Tree` on
+//! the CPU to find those instances for every query in the batch, and then
+//! compute all of the distances in a few large dispatches to a GPU, through
+//! `wgpu`. The GPU computes the Euclidean or cosine distance between `f32`
+//! vectors, given by a `GpuMetric`, which must be the distance function of the
+//! `Tree`.
+//!
+//! If no GPU is given, or it fails, the distances are computed on the CPU with
+//! the distance function of the `Tree`, so the searches always return the
+//! same hits as `rnn::Algorithm::Clustered` and `knn::Algorithm::Linear`, up
+//! to rounding.
+
+use std::{collections::HashMap, sync::mpsc};
+
+use wgpu::util::DeviceExt;
+
+use crate::par::prelude::*;
+use crate::{
+    cakes::{rnn::clustered, tiled},
+    Cluster, Dataset, Tree,
+};
+
+/// The number of invocations in each workgroup of the shader.
+const WORKGROUP_SIZE: usize = 64;
+
+/// The largest number of workgroups in one dimension of a dispatch.
+const MAX_WORKGROUPS: usize = 65_535;
+
+/// The shader which computes the distance between each pair of a query and an
+/// instance.
+const SHADER: &str = r"
+struct Params {
+    dim: u32,
+    num_pairs: u32,
+    metric: u32,
+    padding: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> queries: array<f32>;
+@group(0) @binding(2) var<storage, read> rows: array<f32>;
+@group(0) @binding(3) var<storage, read> pairs: array<vec2<u32>>;
+@group(0) @binding(4) var<storage, read_write> distances: array<f32>;
+
+const EPSILON: f32 = 1.1920929e-7;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let p = id.x;
+    if p >= params.num_pairs {
+        return;
+    }
+    let q = pairs[p].x * params.dim;
+    let r = pairs[p].y * params.dim;
+
+    var xy: f32 = 0.0;
+    var xx: f32 = 0.0;
+    var yy: f32 = 0.0;
+    for (var j = 0u; j < params.dim; j++) {
+        let x = queries[q + j];
+        let y = rows[r + j];
+        if params.metric == 0u {
+            let d = x - y;
+            xy += d * d;
+        } else {
+            xy += x * y;
+            xx += x * x;
+            yy += y * y;
+        }
+    }
+
+    if params.metric == 0u {
+        distances[p] = sqrt(xy);
+    } else if xx < EPSILON || yy < EPSILON || xy < EPSILON {
+        distances[p] = 1.0;
+    } else {
+        let d = 1.0 - xy * inverseSqrt(xx * yy);
+        distances[p] = select(d, 0.0, d < EPSILON);
+    }
+}
+";
+
+/// The distance functions which the GPU can compute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuMetric {
+    /// The Euclidean distance, as in `distances::vectors::euclidean`.
+    Euclidean,
+    /// The cosine distance, as in `distances::vectors::cosine`.
+    Cosine,
+}
+
+impl GpuMetric {
+    /// The code of the distance function in the shader.
+    const fn code(self) -> u32 {
+        match self {
+            Self::Euclidean => 0,
+            Self::Cosine => 1,
+        }
+    }
+}
+
+/// A GPU, with the shader compiled for it.
+#[derive(Debug)]
+pub struct GpuContext {
+    /// The logical device.
+    device: wgpu::Device,
+    /// The queue of commands for the device.
+    queue: wgpu::Queue,
+    /// The compiled shader.
+    pipeline: wgpu::ComputePipeline,
+    /// The largest number of bytes in each buffer bound to the shader.
+    max_binding: usize,
+}
+
+impl GpuContext {
+    /// Connects to the most powerful GPU available and compiles the shader.
+    ///
+    /// # Errors
+    ///
+    /// * If there is no GPU, or it cannot be connected to.
+    pub fn new() -> Result<Self, String> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            compatible_surface: None,
+        }))
+        .ok_or_else(|| "No GPU is available.".to_string())?;
+
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("clam"),
+                required_features: wgpu::Features::empty(),
+                required_limits: adapter.limits(),
+                memory_hints: wgpu::MemoryHints::Performance,
+            },
+            None,
+        ))
+        .map_err(|e| e.to_string())?;
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("clam-distances"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("clam-distances"),
+            layout: None,
+            module: &module,
+            entry_point: "main",
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+        let max_binding = device.limits().max_storage_buffer_binding_size as usize;
+
+        Ok(Self {
+            device,
+            queue,
+            pipeline,
+            max_binding,
+        })
+    }
+
+    /// Computes the distances between pairs of queries and instances.
+    ///
+    /// # Arguments
+    ///
+    /// * `metric` - The distance function.
+    /// * `queries` - The queries.
+    /// * `rows` - The instances.
+    /// * `pairs` - The pairs of the index of a query and of an instance.
+    ///
+    /// # Returns
+    ///
+    /// The distance for each pair.
+    ///
+    /// # Errors
+    ///
+    /// * If the queries and instances do not all have the same dimension.
+    /// * If an instance is too large for the GPU.
+    /// * If the GPU fails.
+    pub fn distances(
+        &self,
+        metric: GpuMetric,
+        queries: &[&[f32]],
+        rows: &[&[f32]],
+        pairs: &[(usize, usize)],
+    ) -> Result<Vec<f32>, String> {
+        let dim = queries.first().map_or(0, |q| q.len());
+        if queries.iter().chain(rows).any(|x| x.len() != dim) {
+            return Err("The queries and instances must all have the same dimension.".to_string());
+        }
+        if pairs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let row_bytes = dim.max(1) * core::mem::size_of::<f32>();
+        if row_bytes > self.max_binding {
+            return Err(format!("Instances of dimension {dim} are too large for the GPU."));
+        }
+
+        // Each pair in a chunk may bring its own query and instance, so a
+        // chunk is small enough that they, and the pairs, always fit in a
+        // buffer.
+        let pair_bytes = row_bytes.max(2 * core::mem::size_of::<u32>());
+        let chunk_size = (MAX_WORKGROUPS * WORKGROUP_SIZE).min(self.max_binding / pair_bytes);
+        let mut distances = Vec::with_capacity(pairs.len());
+        for chunk in pairs.chunks(chunk_size) {
+            distances.extend(self.dispatch(metric, dim, queries, rows, chunk)?);
+        }
+        Ok(distances)
+    }
+
+    /// Computes the distances for one chunk of pairs in a single dispatch,
+    /// uploading only the queries and instances in the chunk.
+    fn dispatch(
+        &self,
+        metric: GpuMetric,
+        dim: usize,
+        queries: &[&[f32]],
+        rows: &[&[f32]],
+        pairs: &[(usize, usize)],
+    ) -> Result<Vec<f32>, String> {
+        let to_u32 = |x: usize| u32::try_from(x).map_err(|e| e.to_string());
+
+        let (mut query_positions, mut row_positions) = (HashMap::new(), HashMap::new());
+        let (mut query_values, mut row_values) = (Vec::new(), Vec::new());
+        let mut local_pairs = Vec::with_capacity(2 * pairs.len());
+        for &(q, r) in pairs {
+            let n = query_positions.len();
+            let q = *query_positions.entry(q).or_insert_with(|| {
+                query_values.extend_from_slice(queries[q]);
+                n
+            });
+            let n = row_positions.len();
+            let r = *row_positions.entry(r).or_insert_with(|| {
+                row_values.extend_from_slice(rows[r]);
+                n
+            });
+            local_pairs.extend([to_u32(q)?, to_u32(r)?]);
+        }
+        // Buffers bound to a shader may not be empty.
+        query_values.resize(query_values.len().max(1), 0.0);
+        row_values.resize(row_values.len().max(1), 0.0);
+
+        let params = [to_u32(dim)?, to_u32(pairs.len())?, metric.code(), 0];
+        let storage = |label: &str, contents: &[u8], usage: wgpu::BufferUsages| {
+            self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents,
+                usage,
+            })
+        };
+        let params = storage("params", bytemuck::cast_slice(&params), wgpu::BufferUsages::UNIFORM);
+        let queries = storage(
+            "queries",
+            bytemuck::cast_slice(&query_values),
+            wgpu::BufferUsages::STORAGE,
+        );
+        let rows = storage("rows", bytemuck::cast_slice(&row_values), wgpu::BufferUsages::STORAGE);
+        let pairs_buffer = storage("pairs", bytemuck::cast_slice(&local_pairs), wgpu::BufferUsages::STORAGE);
+
+        let size = (pairs.len() * core::mem::size_of::<f32>()) as u64;
+        let output = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("distances"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("staging"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let buffers = [&params, &queries, &rows, &pairs_buffer, &output];
+        let entries = (0..)
+            .zip(buffers)
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect::<Vec<_>>();
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("clam-distances"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &entries,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(to_u32(pairs.len().div_ceil(WORKGROUP_SIZE))?, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&output, 0, &staging, 0, size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = staging.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            // The receiver outlives the mapping, as it is waited on below.
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.recv().map_err(|e| e.to_string())?.map_err(|e| e.to_string())?;
+
+        let distances = bytemuck::cast_slice::<u8, f32>(&slice.get_mapped_range()).to_vec();
+        staging.unmap();
+        Ok(distances)
+    }
+}
+
+/// Searches for the ranged nearest neighbors of a batch of queries, scanning
+/// the leaves on the GPU.
+///
+/// # Arguments
+///
+/// * `gpu` - The GPU, or `None` to compute all distances on the CPU.
+/// * `metric` - The distance function of the `tree`, for the GPU.
+/// * `tree` - The tree to search.
+/// * `queries` - The queries to search around.
+/// * `radius` - The radius to search within.
+///
+/// # Returns
+///
+/// The hits of each query, as returned by `rnn::Algorithm::Clustered`.
+pub fn batch_rnn_search<D, C>(
+    gpu: Option<&GpuContext>,
+    metric: GpuMetric,
+    tree: &Tree<Vec<f32>, f32, D, C>,
+    queries: &[&Vec<f32>],
+    radius: f32,
+) -> Vec<Vec<(usize, f32)>>
+where
+    D: Dataset<Vec<f32>, f32>,
+    C: Cluster<f32>,
+{
+    let data = tree.data();
+    let live = move |indices: core::ops::Range<usize>| indices.filter(move |&i| !tree.is_removed(i));
+
+    // The instances of confirmed `Cluster`s are hits, whatever the rounding
+    // of their distances, as in `rnn::Algorithm::Clustered`.
+    let candidates = queries
+        .par_iter()
+        .map(|&query| {
+            if data.is_metric() {
+                let [confirmed, straddlers] = clustered::tree_search(data, &tree.root, query, radius, &());
+                let confirmed = confirmed.into_iter().flat_map(|(c, _)| live(c.indices()));
+                let straddlers = straddlers.into_iter().flat_map(|(c, _)| live(c.indices()));
+                confirmed
+                    .map(|i| (i, true))
+                    .chain(straddlers.map(|i| (i, false)))
+                    .collect::<Vec<_>>()
+            } else {
+                live(0..tree.cardinality()).map(|i| (i, false)).collect()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let indices = candidates
+        .iter()
+        .map(|c| c.iter().map(|&(i, _)| i).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let distances = leaf_distances(gpu, metric, data, queries, &indices);
+
+    candidates
+        .into_iter()
+        .zip(distances)
+        .map(|(candidates, distances)| {
+            candidates
+                .into_iter()
+                .zip(distances)
+                .filter(|&((_, confirmed), d)| confirmed || d <= radius)
+                .map(|((i, _), d)| (i, d))
+                .collect()
+        })
+        .collect()
+}
+
+/// Searches for the `k` nearest neighbors of a batch of queries, scanning the
+/// leaves on the GPU.
+///
+/// The leaves to scan for each query are found by a sieve on the CPU. At each
+/// level of the tree, the distance from the query to the `k`-th nearest
+/// instance is bounded above by the distances to the `Cluster`s farthest
+/// instances, and every `Cluster` whose nearest instance may be within that
+/// bound is kept. The leaves which are left are then scanned on the GPU.
+///
+/// # Arguments
+///
+/// * `gpu` - The GPU, or `None` to compute all distances on the CPU.
+/// * `metric` - The distance function of the `tree`, for the GPU.
+/// * `tree` - The tree to search.
+/// * `queries` - The queries to search around.
+/// * `k` - The number of neighbors to search for.
+///
+/// # Returns
+///
+/// The hits of each query, sorted by increasing distance.
+pub fn batch_knn_search<D, C>(
+    gpu: Option<&GpuContext>,
+    metric: GpuMetric,
+    tree: &Tree<Vec<f32>, f32, D, C>,
+    queries: &[&Vec<f32>],
+    k: usize,
+) -> Vec<Vec<(usize, f32)>>
+where
+    D: Dataset<Vec<f32>, f32>,
+    C: Cluster<f32>,
+{
+    let indices = queries
+        .par_iter()
+        .map(|&query| knn_candidates(tree, query, k))
+        .collect::<Vec<_>>();
+    let distances = leaf_distances(gpu, metric, tree.data(), queries, &indices);
+
+    indices
+        .into_iter()
+        .zip(distances)
+        .map(|(indices, distances)| {
+            let mut hits = indices.into_iter().zip(distances).collect::<Vec<_>>();
+            hits.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+            hits.truncate(k);
+            hits
+        })
+        .collect()
+}
+
+/// Finds the instances which may be among the `k` nearest neighbors of a
+/// query, excluding removed instances.
+fn knn_candidates<D, C>(tree: &Tree<Vec<f32>, f32, D, C>, query: &Vec<f32>, k: usize) -> Vec<usize>
+where
+    D: Dataset<Vec<f32>, f32>,
+    C: Cluster<f32>,
+{
+    tiled::knn_leaves(tree, query, k)
+        .into_iter()
+        .flat_map(|c| c.indices())
+        .filter(|&i| !tree.is_removed(i))
+        .collect()
+}
+
+/// Computes the distances from each query to its candidates, on the GPU if
+/// there is one, and on the CPU if not or if the GPU fails.
+fn leaf_distances<D: Dataset<Vec<f32>, f32>>(
+    gpu: Option<&GpuContext>,
+    metric: GpuMetric,
+    data: &D,
+    queries: &[&Vec<f32>],
+    candidates: &[Vec<usize>],
+) -> Vec<Vec<f32>> {
+    if let Some(gpu) = gpu {
+        let mut positions = HashMap::new();
+        let mut rows = Vec::new();
+        let pairs = candidates
+            .iter()
+            .enumerate()
+            .flat_map(|(q, indices)| indices.iter().map(move |&i| (q, i)))
+            .map(|(q, i)| {
+                let n = positions.len();
+                let r = *positions.entry(i).or_insert_with(|| {
+                    rows.push(data.get(i));
+                    n
+                });
+                (q, r)
+            })
+            .collect::<Vec<_>>();
+        let query_slices = queries.iter().map(|q| q.as_slice()).collect::<Vec<_>>();
+        let row_slices = rows.iter().map(|r| r.as_slice()).collect::<Vec<_>>();
+
+        if let Ok(distances) = gpu.distances(metric, &query_slices, &row_slices, &pairs) {
+            let mut distances = distances.into_iter();
+            return candidates
+                .iter()
+                .map(|indices| distances.by_ref().take(indices.len()).collect())
+                .collect();
+        }
+    }
+
+    queries
+        .par_iter()
+        .zip(candidates.par_iter())
+        .map(|(&query, indices)| data.query_to_many(query, indices))
+        .collect()
+}
@@ -0,0 +1,227 @@
+//! Hybrid search with a cheap and an expensive distance function.
+//!
+//! A `HybridTree` is a `Tree` built under a cheap distance function, whose
+//! `Cluster`s additionally store their radii under an expensive distance
+//! function. K-Nearest Neighbor search happens in two stages: the cheap
+//! distance function is used to generate candidates, and the expensive one is
+//! used to rank them.
+//!
+//! If the cheap distance function is Lipschitz with respect to the expensive
+//! one, i.e. `cheap(x, y) <= factor * expensive(x, y)` for all `x` and `y`,
+//! then the search is exact under the expensive distance function. Otherwise,
+//! the results are approximate.
+
+use std::collections::HashMap;
+
+use distances::Number;
+
+use crate::par::prelude::*;
+use crate::{cakes::knn, Cluster, Dataset, Instance, Metric, SharedMetric, Tree};
+
+/// The default number of candidates, per requested neighbor, that are ranked
+/// under the expensive distance function when no Lipschitz factor is known.
+pub const DEFAULT_OVERSAMPLING: usize = 4;
+
+/// A `Tree` whose `Cluster`s store their radii under a second, expensive,
+/// distance function.
+///
+/// # Type Parameters
+///
+/// - `I`: The type of the instances in the `Tree`.
+/// - `U`: The type of the distance values under the cheap distance function.
+/// - `V`: The type of the distance values under the expensive distance function.
+/// - `D`: The type of the `Dataset` from which the `Tree` is built.
+/// - `C`: The type of the `Cluster`s in the `Tree`.
+#[derive(Debug)]
+pub struct HybridTree<I: Instance, U: Number, V: Number, D: Dataset<I, U>, C: Cluster<U>> {
+    /// The tree, built under the cheap distance function of the dataset.
+    tree: Tree<I, U, D, C>,
+    /// The expensive distance function.
+    metric: SharedMetric<I, V>,
+    /// The radius, under the expensive distance function, of each `Cluster`,
+    /// keyed by its `offset` and `cardinality`.
+    radii: HashMap<(usize, usize), V>,
+    /// The Lipschitz factor relating the two distance functions, if known.
+    lipschitz: Option<f64>,
+    /// The number of candidates to rank per neighbor, if `lipschitz` is `None`.
+    oversampling: usize,
+}
+
+impl<I: Instance, U: Number, V: Number, D: Dataset<I, U>, C: Cluster<U>> HybridTree<I, U, V, D, C> {
+    /// Creates a new `HybridTree`, computing the radius of every `Cluster`
+    /// under the expensive distance function.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree` - A partitioned tree, built under the cheap distance function.
+    /// * `metric` - The expensive distance function.
+    pub fn new<M: Into<SharedMetric<I, V>>>(tree: Tree<I, U, D, C>, metric: M) -> Self {
+        let metric = metric.into();
+        let data = tree.data();
+        let radii = tree
+            .root()
+            .subtree()
+            .into_par_iter()
+            .map(|c| {
+                let center = data.get(c.arg_center());
+                let radius = c
+                    .indices()
+                    .map(|i| metric.distance(&center, &data.get(i)))
+                    .fold(V::zero(), |r, d| if d > r { d } else { r });
+                ((c.offset(), c.cardinality()), radius)
+            })
+            .collect();
+
+        Self {
+            tree,
+            metric,
+            radii,
+            lipschitz: None,
+            oversampling: DEFAULT_OVERSAMPLING,
+        }
+    }
+
+    /// Declares that `cheap(x, y) <= factor * expensive(x, y)` for all `x` and
+    /// `y`, which makes search exact under the expensive distance function.
+    #[must_use]
+    pub const fn with_lipschitz(mut self, factor: f64) -> Self {
+        self.lipschitz = Some(factor);
+        self
+    }
+
+    /// Sets the number of candidates, per requested neighbor, to rank under
+    /// the expensive distance function when no Lipschitz factor is known.
+    #[must_use]
+    pub fn with_oversampling(mut self, oversampling: usize) -> Self {
+        self.oversampling = oversampling.max(1);
+        self
+    }
+
+    /// Returns the underlying tree.
+    pub const fn tree(&self) -> &Tree<I, U, D, C> {
+        &self.tree
+    }
+
+    /// Returns the expensive distance function.
+    pub const fn metric(&self) -> &SharedMetric<I, V> {
+        &self.metric
+    }
+
+    /// Returns the radius of a `Cluster` under the expensive distance function.
+    ///
+    /// # Arguments
+    ///
+    /// * `c` - A `Cluster` in the tree.
+    pub fn secondary_radius(&self, c: &C) -> V {
+        self.radii
+            .get(&(c.offset(), c.cardinality()))
+            .copied()
+            .unwrap_or_else(|| unreachable!("Every cluster in the tree has a secondary radius."))
+    }
+
+    /// Returns the Lipschitz factor relating the two distance functions, if known.
+    pub const fn lipschitz(&self) -> Option<f64> {
+        self.lipschitz
+    }
+
+    /// Searches for the nearest neighbors of a query under the expensive
+    /// distance function.
+    ///
+    /// Candidates are generated with `algo` under the cheap distance function
+    /// and ranked under the expensive one. If a Lipschitz factor is known, the
+    /// candidates are then widened to every instance within `factor * tau` of
+    /// the query under the cheap distance function, where `tau` is the
+    /// distance to the `k`-th candidate under the expensive distance function.
+    /// This guarantees that the results are exact.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query to search around.
+    /// * `k` - The number of neighbors to search for.
+    /// * `algo` - The algorithm to use for generating candidates.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 2-tuples, where the first element is the index of the
+    /// instance and the second element is the distance from the query to the
+    /// instance under the expensive distance function.
+    pub fn knn_search(&self, query: &I, k: usize, algo: knn::Algorithm) -> Vec<(usize, V)> {
+        let num_candidates = if self.lipschitz.is_some() {
+            k
+        } else {
+            k * self.oversampling
+        };
+        let candidates = algo.search(&self.tree, query, num_candidates);
+        let num_found = candidates.len();
+
+        let data = self.tree.data();
+        let mut ranked = candidates
+            .into_iter()
+            .map(|(i, _)| (i, self.metric.distance(query, &data.get(i))))
+            .collect::<HashMap<_, _>>();
+
+        if let Some(factor) = self.lipschitz {
+            // If fewer than `k` candidates were found, there are no other
+            // instances left to find.
+            if num_found == k {
+                let tau = knn::Hits::from_vec(k, ranked.iter().map(|(&i, &d)| (i, d)).collect()).peek();
+                self.widen(query, factor * tau.as_f64(), &mut ranked);
+            }
+        }
+
+        knn::Hits::from_vec(k, ranked.into_iter().collect()).extract()
+    }
+
+    /// Ranks, under the expensive distance function, every instance within
+    /// `radius` of the query under the cheap distance function.
+    ///
+    /// `Cluster`s are skipped if their secondary radii show that none of their
+    /// instances can be closer than the current `k`-th neighbor under the
+    /// expensive distance function.
+    fn widen(&self, query: &I, radius: f64, ranked: &mut HashMap<usize, V>) {
+        let data = self.tree.data();
+        let k = ranked.len();
+
+        // Collect the clusters that overlap the query ball under the cheap distance function.
+        let mut overlapping = Vec::new();
+        let mut stack = vec![self.tree.root()];
+        while let Some(c) = stack.pop() {
+            let d = c.distance_to_instance(data, query).as_f64();
+            let r = c.radius().as_f64();
+            if d > r + radius {
+                continue;
+            }
+            if d + r <= radius {
+                overlapping.push((c, d, true));
+            } else if let Some(children) = c.children() {
+                stack.extend(children);
+            } else {
+                overlapping.push((c, d, false));
+            }
+        }
+
+        // Visit the closer clusters first so that `tau` shrinks quickly.
+        overlapping.sort_by(|(_, a, _), (_, b, _)| a.total_cmp(b));
+
+        let mut hits = knn::Hits::from_vec(k, ranked.iter().map(|(&i, &d)| (i, d)).collect());
+        for (c, _, confirmed) in overlapping {
+            if self.metric.is_metric() && c.cardinality() > 1 {
+                let d = self.metric.distance(query, &data.get(c.arg_center()));
+                if d > hits.peek() + self.secondary_radius(c) {
+                    continue;
+                }
+            }
+
+            let indices = c
+                .indices()
+                .filter(|&i| !self.tree.is_removed(i) && !ranked.contains_key(&i))
+                .filter(|&i| confirmed || data.query_to_one(query, i).as_f64() <= radius)
+                .collect::<Vec<_>>();
+            for i in indices {
+                let d = self.metric.distance(query, &data.get(i));
+                ranked.insert(i, d);
+                hits.push(i, d);
+            }
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Stable, user-provided IDs for the instances of an index.
+
+use core::hash::Hash;
+
+use std::collections::{HashMap, HashSet};
+
+use distances::Number;
+
+use crate::{cakes::knn, cakes::rnn, Dataset, Instance, PartitionCriterion, Tree, UniBall, VecDataset};
+
+/// The type of the `Tree` searched by an `IdCakes`.
+type IdTree<I, U, M> = Tree<I, U, VecDataset<I, U, M>, UniBall<U>>;
+
+/// An index whose searches report user-provided IDs, such as `u64`s or
+/// `String`s, rather than indices into the reordered dataset.
+///
+/// Building, inserting into and compacting a `Tree` reorder its instances, so
+/// the index of an instance is an implementation detail which may change. An
+/// `IdCakes` maps the original index of each instance, which is stable, to its
+/// ID, and keeps the current index of each ID up to date, so that instances
+/// are inserted, removed and found by their IDs alone.
+///
+/// # Type Parameters
+///
+/// - `I`: The type of the instances.
+/// - `U`: The type of the distance values between instances.
+/// - `M`: The type of the metadata of the instances.
+/// - `Id`: The type of the IDs of the instances.
+/// - `P`: The type of the criteria used to partition leaves which grow when
+///   instances are inserted, or subtrees which are rebuilt by compaction.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct IdCakes<I: Instance, U: Number, M: Instance, Id: Clone + Eq + Hash, P: PartitionCriterion<U>> {
+    /// The `Tree` to search.
+    tree: IdTree<I, U, M>,
+    /// The ID of each instance, by its original index.
+    ids: HashMap<usize, Id>,
+    /// The current index of each ID of an instance which has not been
+    /// removed.
+    indices: HashMap<Id, usize>,
+    /// The criteria used to partition leaves when instances are inserted.
+    criteria: P,
+    /// The seed used to partition leaves when instances are inserted.
+    seed: Option<u64>,
+}
+
+impl<I: Instance, U: Number, M: Instance, Id: Clone + Eq + Hash, P: PartitionCriterion<U>> IdCakes<I, U, M, Id, P> {
+    /// Creates a new `IdCakes`.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree` - The `Tree` to search.
+    /// * `ids` - The ID of each instance, in the order in which the instances
+    ///   were given, i.e. by increasing `Dataset::original_index`.
+    /// * `criteria` - The criteria used to partition leaves when instances are
+    ///   inserted.
+    /// * `seed` - The seed used to partition leaves when instances are
+    ///   inserted.
+    ///
+    /// # Errors
+    ///
+    /// * If the number of IDs is not the cardinality of the `Tree`.
+    /// * If any ID is given more than once.
+    pub fn new(tree: IdTree<I, U, M>, ids: Vec<Id>, criteria: P, seed: Option<u64>) -> Result<Self, String> {
+        let n = tree.cardinality();
+        if ids.len() != n {
+            return Err(format!("Expected {n} IDs, got {}", ids.len()));
+        }
+
+        let data = tree.data();
+        let mut originals = (0..n).map(|i| data.original_index(i)).collect::<Vec<_>>();
+        originals.sort_unstable();
+        let ids = originals.into_iter().zip(ids).collect::<HashMap<_, _>>();
+        let mut cakes = Self {
+            tree,
+            ids,
+            indices: HashMap::new(),
+            criteria,
+            seed,
+        };
+        cakes.reindex();
+        if cakes.indices.len() + cakes.tree.num_removed() == n {
+            Ok(cakes)
+        } else {
+            Err("The IDs are not unique.".to_string())
+        }
+    }
+
+    /// Returns the `Tree` being searched.
+    pub const fn tree(&self) -> &IdTree<I, U, M> {
+        &self.tree
+    }
+
+    /// Returns the number of instances which have not been removed.
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// Returns whether every instance has been removed.
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// Returns the ID of the instance at the given index in the `Tree`.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index of an instance in the `Tree`.
+    pub fn id_of(&self, index: usize) -> &Id {
+        &self.ids[&self.tree.data().original_index(index)]
+    }
+
+    /// Returns the current index in the `Tree` of the instance with the given
+    /// ID, or `None` if there is no such instance or it has been removed.
+    pub fn index_of(&self, id: &Id) -> Option<usize> {
+        self.indices.get(id).copied()
+    }
+
+    /// Returns the instance with the given ID, or `None` if there is no such
+    /// instance or it has been removed.
+    pub fn get(&self, id: &Id) -> Option<&I> {
+        self.index_of(id).map(|i| &self.tree.data()[i])
+    }
+
+    /// Inserts an instance into the `Tree` under the given ID.
+    ///
+    /// # Errors
+    ///
+    /// * If an instance which has not been removed already has the ID.
+    /// * See `Tree::insert`.
+    pub fn insert(&mut self, id: Id, instance: I, metadata: M) -> Result<(), String> {
+        if self.indices.contains_key(&id) {
+            return Err("An instance with this ID already exists.".to_string());
+        }
+        self.tree.insert(instance, metadata, &self.criteria, self.seed)?;
+
+        // The new instance has the only original index without an ID.
+        let data = self.tree.data();
+        let original = (0..data.cardinality())
+            .map(|i| data.original_index(i))
+            .find(|o| !self.ids.contains_key(o))
+            .unwrap_or_else(|| unreachable!("The new instance has an original index."));
+        self.ids.insert(original, id);
+        self.reindex();
+        Ok(())
+    }
+
+    /// Removes the instance with the given ID. See `Tree::remove`.
+    ///
+    /// The ID may then be given to a new instance.
+    ///
+    /// # Errors
+    ///
+    /// * If there is no instance with the ID, or it has already been removed.
+    pub fn remove(&mut self, id: &Id) -> Result<(), String> {
+        let index = self
+            .indices
+            .remove(id)
+            .ok_or_else(|| "There is no instance with this ID.".to_string())?;
+        self.tree.remove(index)
+    }
+
+    /// Compacts the `Tree`, forgetting the IDs of the instances which are
+    /// permanently removed. See `Tree::compact`.
+    ///
+    /// # Errors
+    ///
+    /// * See `Tree::compact`.
+    pub fn compact(&mut self, threshold: f64) -> Result<(), String> {
+        self.tree.compact(threshold, &self.criteria, self.seed)?;
+        self.reindex();
+        Ok(())
+    }
+
+    /// Performs a KNN search.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 2-tuples, where the first element is the ID of an instance
+    /// and the second is its distance from the query.
+    pub fn knn_search(&self, query: &I, k: usize, algo: knn::Algorithm) -> Vec<(Id, U)> {
+        self.attach(algo.search(&self.tree, query, k))
+    }
+
+    /// Performs an RNN search.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 2-tuples, where the first element is the ID of an instance
+    /// and the second is its distance from the query.
+    pub fn rnn_search(&self, query: &I, radius: U, algo: rnn::Algorithm) -> Vec<(Id, U)> {
+        self.attach(algo.search(query, radius, &self.tree))
+    }
+
+    /// Replaces the indices of hits with their IDs.
+    fn attach(&self, hits: Vec<(usize, U)>) -> Vec<(Id, U)> {
+        hits.into_iter().map(|(i, d)| (self.id_of(i).clone(), d)).collect()
+    }
+
+    /// Recomputes the current index of each ID after the instances have been
+    /// reordered, and forgets the IDs of instances which are no longer in the
+    /// `Tree`.
+    fn reindex(&mut self) {
+        let data = self.tree.data();
+        let originals = (0..data.cardinality())
+            .map(|i| data.original_index(i))
+            .collect::<Vec<_>>();
+        let present = originals.iter().copied().collect::<HashSet<_>>();
+        self.ids.retain(|o, _| present.contains(o));
+
+        self.indices = originals
+            .into_iter()
+            .enumerate()
+            .filter(|&(i, _)| !self.tree.is_removed(i))
+            .map(|(i, o)| (self.ids[&o].clone(), i))
+            .collect();
+    }
+}
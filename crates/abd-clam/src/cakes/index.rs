@@ -0,0 +1,263 @@
+//! The manifest of a saved `Cakes` index.
+//!
+//! `Cakes::save` writes the trees and datasets into a directory and then
+//! writes a manifest, `index.clam`, at its root. `Cakes::load` reads the
+//! manifest first and refuses to load the index unless the format version,
+//! the distance function, the type of the dataset and the layout of the shards
+//! all match, and every file in the index has the recorded checksum.
+//!
+//! # On-disk layout
+//!
+//! | Field            | Content                                                  |
+//! |------------------|----------------------------------------------------------|
+//! | `magic`          | The magic bytes `CLAMINDX`.                              |
+//! | `version`        | The version of the format, currently `1`, as a `u64`.    |
+//! | `crate_version`  | The version of the crate which wrote the index.          |
+//! | `metric`         | The name of the distance function.                       |
+//! | `dataset`        | The type name of the dataset, from `Dataset::type_name`. |
+//! | `cardinalities`  | The cardinality of each shard.                           |
+//! | `files`          | The path, length and checksum of each file in the index. |
+//!
+//! Strings and lists are prefixed with their length, and all numbers are
+//! little-endian `u64`s. Paths are relative to the directory of the index,
+//! with `/` as the separator. Checksums are the 64-bit FNV-1a hashes of the
+//! contents of the files.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+/// The name of the manifest file in the directory of a saved index.
+pub const MANIFEST: &str = "index.clam";
+
+/// The magic bytes at the start of a manifest.
+const MAGIC: &[u8; 8] = b"CLAMINDX";
+
+/// The version of the on-disk layout.
+const FORMAT_VERSION: u64 = 1;
+
+/// The offset basis of the 64-bit FNV-1a hash.
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// The prime of the 64-bit FNV-1a hash.
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// The identity of a saved index, which must match when it is loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    /// The name of the distance function.
+    pub metric: String,
+    /// The type name of the dataset.
+    pub dataset: String,
+    /// The cardinality of each shard.
+    pub cardinalities: Vec<usize>,
+}
+
+impl Manifest {
+    /// Writes the manifest of the index in the directory at `path`, with the
+    /// checksums of all files in the directory.
+    ///
+    /// # Errors
+    ///
+    /// * If the files in the directory cannot be read.
+    /// * If the manifest cannot be written.
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let files = list_files(path, "")?
+            .into_iter()
+            .map(|name| checksum(&path.join(&name)).map(|(len, sum)| (name, len, sum)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut bytes = MAGIC.to_vec();
+        write_u64(&mut bytes, FORMAT_VERSION);
+        write_str(&mut bytes, crate::VERSION);
+        write_str(&mut bytes, &self.metric);
+        write_str(&mut bytes, &self.dataset);
+        write_u64(&mut bytes, self.cardinalities.len() as u64);
+        for &c in &self.cardinalities {
+            write_u64(&mut bytes, c as u64);
+        }
+        write_u64(&mut bytes, files.len() as u64);
+        for (name, len, sum) in files {
+            write_str(&mut bytes, &name);
+            write_u64(&mut bytes, len);
+            write_u64(&mut bytes, sum);
+        }
+
+        let mut writer = BufWriter::new(File::create(path.join(MANIFEST)).map_err(|e| e.to_string())?);
+        writer.write_all(&bytes).map_err(|e| e.to_string())
+    }
+
+    /// Reads the manifest of the index in the directory at `path` and checks
+    /// the files of the index against it.
+    ///
+    /// # Errors
+    ///
+    /// * If there is no manifest, or it is not a valid manifest.
+    /// * If the manifest has a different format version.
+    /// * If any file in the manifest is missing or has a different checksum.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let manifest_path = path.join(MANIFEST);
+        if !manifest_path.exists() {
+            return Err(format!("The index at '{}' has no manifest.", path.display()));
+        }
+        let mut bytes = Vec::new();
+        BufReader::new(File::open(&manifest_path).map_err(|e| e.to_string())?)
+            .read_to_end(&mut bytes)
+            .map_err(|e| e.to_string())?;
+        let mut reader = Reader { bytes: &bytes };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(format!("'{}' is not a CLAM index manifest.", manifest_path.display()));
+        }
+        let version = reader.u64()?;
+        if version != FORMAT_VERSION {
+            return Err(format!(
+                "Unsupported index format version. Expected {FORMAT_VERSION}, got {version}"
+            ));
+        }
+        // The version of the crate is only informative.
+        reader.string()?;
+
+        let metric = reader.string()?;
+        let dataset = reader.string()?;
+        let num_shards = reader.usize()?;
+        let cardinalities = (0..num_shards).map(|_| reader.usize()).collect::<Result<Vec<_>, _>>()?;
+
+        let num_files = reader.usize()?;
+        for _ in 0..num_files {
+            let name = reader.string()?;
+            let (len, sum) = (reader.u64()?, reader.u64()?);
+            let file = path.join(&name);
+            if !file.exists() {
+                return Err(format!("The file '{name}' of the index is missing."));
+            }
+            if checksum(&file)? != (len, sum) {
+                return Err(format!(
+                    "Checksum mismatch. The file '{name}' of the index is corrupted."
+                ));
+            }
+        }
+
+        Ok(Self {
+            metric,
+            dataset,
+            cardinalities,
+        })
+    }
+
+    /// Checks that the index was saved with the given distance function and
+    /// type of dataset.
+    ///
+    /// # Errors
+    ///
+    /// * If either does not match.
+    pub fn expect(&self, metric: &str, dataset: &str) -> Result<(), String> {
+        if self.metric != metric {
+            return Err(format!(
+                "Metric mismatch. The index was saved with {} but is being loaded with {metric}",
+                self.metric
+            ));
+        }
+        if self.dataset != dataset {
+            return Err(format!(
+                "Dataset type mismatch. The index was saved with {} but is being loaded as {dataset}",
+                self.dataset
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Lists the files under a directory, recursively and in sorted order, with
+/// paths relative to the directory and excluding the manifest.
+fn list_files(root: &Path, prefix: &str) -> Result<Vec<String>, String> {
+    let mut entries = std::fs::read_dir(root.join(prefix))
+        .map_err(|e| e.to_string())?
+        .map(|e| e.map(|e| e.path()).map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let name = entry
+            .file_name()
+            .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+        let name = if prefix.is_empty() {
+            name
+        } else {
+            format!("{prefix}/{name}")
+        };
+        if entry.is_dir() {
+            files.extend(list_files(root, &name)?);
+        } else if name != MANIFEST {
+            files.push(name);
+        }
+    }
+    Ok(files)
+}
+
+/// Computes the length and 64-bit FNV-1a hash of the contents of a file.
+fn checksum(path: &Path) -> Result<(u64, u64), String> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let mut buffer = vec![0; 1 << 16];
+    let (mut len, mut hash) = (0, FNV_OFFSET);
+    loop {
+        let n = reader.read(&mut buffer).map_err(|e| e.to_string())?;
+        if n == 0 {
+            return Ok((len, hash));
+        }
+        for &b in &buffer[..n] {
+            hash = (hash ^ u64::from(b)).wrapping_mul(FNV_PRIME);
+        }
+        len += n as u64;
+    }
+}
+
+/// Appends a little-endian `u64` to `bytes`.
+fn write_u64(bytes: &mut Vec<u8>, value: u64) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Appends a string, prefixed with its length, to `bytes`.
+fn write_str(bytes: &mut Vec<u8>, value: &str) {
+    write_u64(bytes, value.len() as u64);
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+/// Reads the fields of a manifest in order.
+struct Reader<'a> {
+    /// The bytes which have not yet been read.
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Reads the next `n` bytes.
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < n {
+            return Err("The index manifest is truncated.".to_string());
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    /// Reads a little-endian `u64`.
+    fn u64(&mut self) -> Result<u64, String> {
+        let mut value = [0; 8];
+        value.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(value))
+    }
+
+    /// Reads a little-endian `u64` as a `usize`.
+    fn usize(&mut self) -> Result<usize, String> {
+        self.u64().and_then(|v| usize::try_from(v).map_err(|e| e.to_string()))
+    }
+
+    /// Reads a string prefixed with its length.
+    fn string(&mut self) -> Result<String, String> {
+        let n = self.usize()?;
+        String::from_utf8(self.take(n)?.to_vec()).map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,153 @@
+//! K-Nearest Neighbor search which stops when it runs out of a budget.
+
+use std::time::{Duration, Instant};
+
+use distances::Number;
+use priority_queue::PriorityQueue;
+
+use crate::{Cluster, Dataset, Instance};
+
+use super::{greedy_sieve::d_min, Hits, OffsetTree, RevNumber};
+
+/// The most work an anytime search may do, as given to
+/// `Cakes::knn_search_anytime`.
+///
+/// A search stops as soon as any of its limits is reached. The default has
+/// no limits.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Budget {
+    /// The largest number of distances to compute.
+    pub max_distances: Option<usize>,
+    /// The longest time to search for.
+    pub max_time: Option<Duration>,
+}
+
+impl Budget {
+    /// Creates a budget with no limits.
+    #[must_use]
+    pub const fn unlimited() -> Self {
+        Self {
+            max_distances: None,
+            max_time: None,
+        }
+    }
+
+    /// Limits the number of distances to compute.
+    #[must_use]
+    pub const fn with_max_distances(mut self, max_distances: usize) -> Self {
+        self.max_distances = Some(max_distances);
+        self
+    }
+
+    /// Limits the time to search for.
+    #[must_use]
+    pub const fn with_max_time(mut self, max_time: Duration) -> Self {
+        self.max_time = Some(max_time);
+        self
+    }
+
+    /// Whether a search which has computed `distances` distances since `start`
+    /// has used up the budget.
+    fn is_spent(&self, distances: usize, start: Instant) -> bool {
+        self.max_distances.is_some_and(|m| distances >= m) || self.max_time.is_some_and(|t| start.elapsed() >= t)
+    }
+}
+
+/// The result of an anytime search.
+#[derive(Clone, Debug)]
+pub struct AnytimeHits<U: Number> {
+    /// The nearest neighbors found before the search stopped, as tuples of the
+    /// index of the instance and its distance to the query.
+    pub hits: Vec<(usize, U)>,
+    /// Whether the search finished within its budget, so that the `hits` are
+    /// the exact nearest neighbors.
+    pub is_exact: bool,
+    /// A lower bound on the distance from the query to any instance which the
+    /// search did not reach. Every hit no farther than this is one of the true
+    /// nearest neighbors. This is `None` if the search is exact.
+    pub lower_bound: Option<U>,
+    /// The number of distances computed by the search.
+    pub distance_computations: usize,
+}
+
+/// K-Nearest Neighbor search which stops when it runs out of a budget.
+///
+/// The traversal is that of `GreedySieve`, over one or more trees at once:
+/// the `Cluster` with the smallest `d_min` among all the trees is always
+/// expanded next. The budget is checked before each expansion, so the search
+/// may overrun its limit on distances by the size of a leaf or the fan-out of
+/// the tree.
+///
+/// If the metric of a tree does not obey the triangle inequality, the `d_min`
+/// of each of its `Cluster`s is taken to be zero, so that the search is exact
+/// only if it visits every instance.
+///
+/// # Arguments
+///
+/// * `trees` - The trees to search, each with the offset to add to the
+///   indices of its instances.
+/// * `query` - The query to search around.
+/// * `k` - The number of neighbors to search for.
+/// * `budget` - The most work the search may do.
+pub fn search<I, U, D, C>(trees: &[OffsetTree<I, U, D, C>], query: &I, k: usize, budget: Budget) -> AnytimeHits<U>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let start = Instant::now();
+    let mut distance_computations = 0;
+
+    let mut candidates = PriorityQueue::<(usize, &C), RevNumber<U>>::new();
+    let mut hits = Hits::new(k);
+
+    let bound = |t: usize, c: &C, distance_computations: &mut usize| {
+        let data = trees[t].0.data();
+        if data.is_metric() {
+            *distance_computations += 1;
+            d_min(c, c.distance_to_instance(data, query))
+        } else {
+            U::zero()
+        }
+    };
+
+    for (t, &(tree, _)) in trees.iter().enumerate() {
+        let root = &tree.root;
+        candidates.push((t, root), RevNumber(bound(t, root, &mut distance_computations)));
+    }
+
+    let lower_bound = loop {
+        let Some((_, &RevNumber(closest))) = candidates.peek() else {
+            break None;
+        };
+        if k == 0 || (hits.len() == k && hits.peek() < closest) {
+            break None;
+        }
+        if budget.is_spent(distance_computations, start) {
+            break Some(closest);
+        }
+
+        let ((t, c), _) = candidates
+            .pop()
+            .unwrap_or_else(|| unreachable!("`candidates` is non-empty."));
+        if let Some(children) = c.children() {
+            for child in children {
+                candidates.push((t, child), RevNumber(bound(t, child, &mut distance_computations)));
+            }
+        } else {
+            let (tree, offset) = trees[t];
+            let indices = c.indices().filter(|&i| !tree.is_removed(i)).collect::<Vec<_>>();
+            distance_computations += indices.len();
+            let distances = tree.data().query_to_many(query, &indices);
+            hits.push_batch(indices.into_iter().map(|i| i + offset).zip(distances));
+        }
+    };
+
+    AnytimeHits {
+        hits: hits.extract(),
+        is_exact: lower_bound.is_none(),
+        lower_bound,
+        distance_computations,
+    }
+}
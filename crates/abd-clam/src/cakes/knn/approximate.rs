@@ -0,0 +1,124 @@
+//! Approximate K-Nearest Neighbor search with a user-specified recall target.
+
+use distances::Number;
+use priority_queue::PriorityQueue;
+
+use crate::{
+    cakes::probe::{Probe, ProbeExt},
+    Cluster, Dataset, Instance, Tree,
+};
+
+use super::{greedy_sieve, OrdNumber, RevNumber};
+
+/// The recall target used when none is specified, e.g. when parsing the
+/// algorithm from its name.
+pub const DEFAULT_RECALL: f32 = 0.95;
+
+/// Approximate K-Nearest Neighbor search.
+///
+/// This follows the same best-first traversal as `GreedySieve`, but it stops
+/// as soon as the estimated number of true neighbors remaining in unexplored
+/// clusters drops below the number of misses permitted by `recall`.
+///
+/// The number of true neighbors in a candidate `Cluster` is estimated from its
+/// local fractal dimension: a ball of radius `t` placed inside a `Cluster` of
+/// radius `r` and cardinality `n` is expected to contain `n * (t / r)^lfd`
+/// instances, where `t` is the distance to the current `k`-th nearest hit.
+///
+/// If `recall` is at least `1.0`, this is equivalent to `GreedySieve`.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search.
+/// * `query` - The query to search around.
+/// * `k` - The number of neighbors to search for.
+/// * `recall` - The target recall, in the range `(0, 1]`.
+/// * `probe` - Receives the events of the search.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is the distance from the query to the instance.
+pub fn search<I, U, D, C, P>(tree: &Tree<I, U, D, C>, query: &I, k: usize, recall: f32, probe: &P) -> Vec<(usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+    P: Probe,
+{
+    if recall >= 1.0 {
+        return greedy_sieve::search(tree, query, k, probe);
+    }
+
+    let mut candidates = PriorityQueue::<&C, RevNumber<U>>::new();
+    let mut hits = PriorityQueue::<usize, OrdNumber<U>>::new();
+
+    let (data, root) = (tree.data(), &tree.root);
+
+    let d = probe.distance_to_center(root, data, query);
+    candidates.push(root, RevNumber(greedy_sieve::d_min(root, d)));
+
+    // The number of true neighbors we are allowed to miss.
+    let budget = (1.0 - recall.max(0.0)).as_f64() * k.as_f64();
+
+    while !candidates.is_empty() && (hits.len() < k || should_continue(tree, &hits, &candidates, budget)) {
+        greedy_sieve::pop_till_leaf(tree, query, &mut candidates, probe);
+        greedy_sieve::leaf_into_hits(tree, query, k, &mut hits, &mut candidates, probe);
+        greedy_sieve::trim_hits(k, &mut hits);
+    }
+
+    hits.into_iter().map(|(i, OrdNumber(d))| (i, d)).collect()
+}
+
+/// Whether the search should continue, i.e. whether the closest candidate may
+/// contain a hit and the estimated number of missed neighbors exceeds the
+/// `budget`.
+fn should_continue<I, U, D, C>(
+    tree: &Tree<I, U, D, C>,
+    hits: &PriorityQueue<usize, OrdNumber<U>>,
+    candidates: &PriorityQueue<&C, RevNumber<U>>,
+    budget: f64,
+) -> bool
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let threshold = hits
+        .peek()
+        .map_or_else(|| unreachable!("`hits` is non-empty."), |(_, &OrdNumber(d))| d);
+    let closest = candidates
+        .peek()
+        .map_or_else(|| unreachable!("`candidates` is non-empty."), |(_, &RevNumber(d))| d);
+
+    threshold >= closest && estimated_misses(tree, candidates, threshold) > budget
+}
+
+/// Estimates the number of instances, which have not been removed, in the
+/// `candidates` that are within the `threshold` distance of the query.
+fn estimated_misses<I, U, D, C>(
+    tree: &Tree<I, U, D, C>,
+    candidates: &PriorityQueue<&C, RevNumber<U>>,
+    threshold: U,
+) -> f64
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    candidates
+        .iter()
+        .filter(|&(_, &RevNumber(d_min))| d_min <= threshold)
+        .map(|(c, _)| {
+            let fraction = if c.radius() == U::zero() {
+                1.0
+            } else {
+                (threshold.as_f64() / c.radius().as_f64()).powf(c.lfd()).min(1.0)
+            };
+            tree.num_live_in(c).as_f64() * fraction
+        })
+        .sum()
+}
@@ -0,0 +1,74 @@
+//! Search function for knn with depth-first descent and best-bound backtracking.
+
+use distances::Number;
+
+use crate::{
+    cakes::probe::{Probe, ProbeExt},
+    Cluster, Dataset, Instance, Tree,
+};
+
+use super::{greedy_sieve::d_min, Hits};
+
+/// K-Nearest Neighbor search with depth-first descent and best-bound backtracking.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search.
+/// * `query` - The query to search around.
+/// * `k` - The number of neighbors to search for.
+/// * `probe` - Receives the events of the search.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is the distance from the query to the instance.
+///
+/// The search first descends greedily, always into the child with the lowest
+/// `d_min`, to the closest leaf. The instances in that leaf seed the hits. The
+/// search then backtracks, visiting the remaining children in the order of
+/// their `d_min` and pruning any whose `d_min` exceeds the distance to the
+/// current `k`-th nearest hit.
+pub fn search<I, U, D, C, P>(tree: &Tree<I, U, D, C>, query: &I, k: usize, probe: &P) -> Vec<(usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+    P: Probe,
+{
+    let mut hits = Hits::new(k);
+
+    let (data, root) = (tree.data(), &tree.root);
+
+    let d = probe.distance_to_center(root, data, query);
+    let mut stack = vec![(root, d, d_min(root, d))];
+
+    while let Some((c, d, bound)) = stack.pop() {
+        if hits.len() == k && bound > hits.peek() {
+            continue;
+        }
+
+        if let Some(children) = c.children() {
+            let mut children = children
+                .into_iter()
+                .filter(|&c| tree.num_live_in(c) > 0)
+                .map(|c| {
+                    let d = probe.distance_to_center(c, data, query);
+                    (c, d, d_min(c, d))
+                })
+                .collect::<Vec<_>>();
+            children.sort_by(|(_, _, a), (_, _, b)| a.partial_cmp(b).unwrap_or(core::cmp::Ordering::Less));
+            // The closer children are pushed last so that they are visited first.
+            stack.extend(children.into_iter().rev());
+        } else {
+            let threshold = (hits.len() == k).then(|| hits.peek());
+            hits.push_batch(
+                probe
+                    .distances_to_leaf_within(c, d, tree, query, k, threshold)
+                    .into_iter(),
+            );
+        }
+    }
+
+    hits.extract()
+}
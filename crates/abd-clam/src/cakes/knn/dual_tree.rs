@@ -0,0 +1,128 @@
+//! K-Nearest Neighbor search for every instance of one tree in others.
+
+use core::cmp::Ordering;
+
+use distances::Number;
+
+use crate::par::prelude::*;
+use crate::{Cluster, Dataset, Instance, Tree};
+
+use super::{Hits, OffsetTree};
+
+/// Searches for the `k` nearest neighbors, in the `references`, of every
+/// instance in the `queries` tree.
+///
+/// The search is a dual-tree traversal, with one traversal of the references
+/// for each leaf `L` of the queries, on behalf of all of its instances. A
+/// `Cluster` `C` of the references is pruned when `d(L, C) - r(L) - r(C)`
+/// exceeds the distance from any instance in `L` to its `k`-th nearest hit.
+/// The remaining leaves of the references are compared to `L` in order of
+/// this lower bound, so that the bound shrinks as quickly as possible. The
+/// leaves of the queries are searched in parallel.
+///
+/// The pruning is only valid if the distance function obeys the triangle
+/// inequality. Otherwise, every pair of leaves is compared.
+///
+/// # Arguments
+///
+/// * `queries` - The tree of the queries.
+/// * `references` - The trees to search, each with the offset to add to the
+///   indices of its instances. They must have the same distance function as
+///   the `queries`.
+/// * `k` - The number of neighbors to search for.
+///
+/// # Returns
+///
+/// A vector with a row for every index in the `queries` tree, each with the
+/// hits of that instance as 2-tuples of the index of a reference instance and
+/// its distance from the query. Instances removed from the `queries` tree
+/// have no hits.
+pub fn search<I, U, Dq, Cq, D, C>(
+    queries: &Tree<I, U, Dq, Cq>,
+    references: &[OffsetTree<I, U, D, C>],
+    k: usize,
+) -> Vec<Vec<(usize, U)>>
+where
+    I: Instance,
+    U: Number,
+    Dq: Dataset<I, U>,
+    Cq: Cluster<U>,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let mut rows = (0..queries.cardinality()).map(|_| Vec::new()).collect::<Vec<_>>();
+    if k == 0 {
+        return rows;
+    }
+
+    let query_data = queries.data();
+    let leaves = queries
+        .leaves()
+        .into_iter()
+        .map(|c| {
+            let indices = c.indices().filter(|&i| !queries.is_removed(i)).collect::<Vec<_>>();
+            (c, indices)
+        })
+        .filter(|(_, indices)| !indices.is_empty())
+        .collect::<Vec<_>>();
+
+    let hits = leaves
+        .par_iter()
+        .map(|(leaf, indices)| {
+            let instances = indices.iter().map(|&i| query_data.get(i)).collect::<Vec<_>>();
+            let center = query_data.get(leaf.arg_center());
+            let mut hits = indices.iter().map(|_| Hits::new(k)).collect::<Vec<_>>();
+
+            // The distance to the farthest `k`-th hit of any instance in the
+            // leaf, or `None` if some instance has fewer than `k` hits.
+            let bound = |hits: &[Hits<usize, U>]| {
+                hits.iter().try_fold(U::zero(), |bound, h| {
+                    (h.len() == k).then(|| if bound < h.peek() { h.peek() } else { bound })
+                })
+            };
+
+            for &(tree, offset) in references {
+                let data = tree.data();
+                let is_metric = data.is_metric();
+                let is_pruned = |lower: U, hits: &[Hits<usize, U>]| is_metric && bound(hits).is_some_and(|b| lower > b);
+
+                // A single traversal of the tree for all instances in the leaf.
+                let mut candidates = Vec::new();
+                let mut stack = vec![tree.root()];
+                while let Some(c) = stack.pop() {
+                    let d = c.distance_to_instance(data, &*center);
+                    let radii = leaf.radius() + c.radius();
+                    let lower = if d > radii { d - radii } else { U::zero() };
+                    if is_pruned(lower, &hits) {
+                        continue;
+                    }
+                    match c.children() {
+                        Some(children) => stack.extend(children),
+                        None => candidates.push((lower, c)),
+                    }
+                }
+
+                candidates.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Greater));
+                for (lower, c) in candidates {
+                    if is_pruned(lower, &hits) {
+                        continue;
+                    }
+                    let others = c.indices().filter(|&i| !tree.is_removed(i)).collect::<Vec<_>>();
+                    for (h, instance) in hits.iter_mut().zip(&instances) {
+                        let distances = data.query_to_many(instance, &others);
+                        h.push_batch(others.iter().map(|&i| i + offset).zip(distances));
+                    }
+                }
+            }
+
+            hits.iter().map(Hits::extract).collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    for ((_, indices), hits) in leaves.iter().zip(hits) {
+        for (&i, h) in indices.iter().zip(hits) {
+            rows[i] = h;
+        }
+    }
+    rows
+}
@@ -0,0 +1,75 @@
+//! K-Nearest Neighbor search among the instances which pass a filter.
+
+use distances::Number;
+
+use crate::{Cluster, Dataset, Instance, Tree};
+
+use super::{
+    greedy_sieve::{d_min, pop_till_leaf, trim_hits},
+    OrdNumber, RevNumber,
+};
+
+/// K-Nearest Neighbor search among the instances for which `filter` returns
+/// `true`.
+///
+/// The traversal is the same as for `GreedySieve`, but instances which do not
+/// pass the `filter` never become hits, so the search keeps expanding until
+/// `k` passing hits are found or the tree is exhausted. The distance to an
+/// instance is only calculated if it passes the `filter`.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search.
+/// * `query` - The query to search around.
+/// * `k` - The number of neighbors to search for.
+/// * `filter` - A predicate on the indices of instances in the `tree`.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is the distance from the query to the instance.
+/// There may be fewer than `k` hits if fewer than `k` instances pass the filter.
+pub fn search<I, U, D, C, F>(tree: &Tree<I, U, D, C>, query: &I, k: usize, filter: F) -> Vec<(usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+    F: Fn(usize) -> bool,
+{
+    let mut candidates = priority_queue::PriorityQueue::<&C, RevNumber<U>>::new();
+    let mut hits = priority_queue::PriorityQueue::<usize, OrdNumber<U>>::new();
+
+    let (data, root) = (tree.data(), &tree.root);
+
+    let d = root.distance_to_instance(data, query);
+    candidates.push(root, RevNumber(d_min(root, d)));
+
+    while !candidates.is_empty()
+        && (hits.len() < k
+            || hits
+                .peek()
+                .map_or_else(|| unreachable!("`hits` is non-empty."), |(_, &OrdNumber(d))| d)
+                >= candidates
+                    .peek()
+                    .map_or_else(|| unreachable!("`candidates` is non-empty."), |(_, &RevNumber(d))| d))
+    {
+        pop_till_leaf(tree, query, &mut candidates, &());
+
+        let (leaf, _) = candidates
+            .pop()
+            .unwrap_or_else(|| unreachable!("`candidates` is non-empty."));
+        let indices = leaf
+            .indices()
+            .filter(|&i| !tree.is_removed(i) && filter(i))
+            .collect::<Vec<_>>();
+        let distances = data.query_to_many(query, &indices);
+        indices.into_iter().zip(distances).for_each(|(i, d)| {
+            hits.push(i, OrdNumber(d));
+        });
+
+        trim_hits(k, &mut hits);
+    }
+
+    hits.into_iter().map(|(i, OrdNumber(d))| (i, d)).collect()
+}
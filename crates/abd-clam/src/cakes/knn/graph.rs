@@ -0,0 +1,245 @@
+//! The k-nearest-neighbor graph of all instances in a `Tree`.
+
+use core::cmp::Ordering;
+
+use std::collections::HashSet;
+
+use distances::Number;
+
+use crate::par::prelude::*;
+use crate::{Cluster, Dataset, Instance, Tree};
+
+use super::Hits;
+
+/// The k-nearest-neighbor graph of a dataset, in compressed sparse row form.
+///
+/// The neighbors of the instance at index `i` are those at
+/// `neighbors[offsets[i]..offsets[i + 1]]`, sorted by increasing distance and
+/// then by index, with the distances at the same positions of `distances`. An
+/// instance is never its own neighbor.
+#[derive(Debug, Clone)]
+pub struct KnnGraph<U: Number> {
+    /// The number of neighbors of each instance.
+    k: usize,
+    /// The start of the row of each instance, followed by the total number of
+    /// edges.
+    offsets: Vec<usize>,
+    /// The indices of the neighbors of all instances, row by row.
+    neighbors: Vec<usize>,
+    /// The distances to the neighbors of all instances, row by row.
+    distances: Vec<U>,
+}
+
+impl<U: Number> KnnGraph<U> {
+    /// Creates a graph from the hits of each instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `k` - The number of neighbors of each instance.
+    /// * `rows` - The hits of each instance, in any order.
+    pub(crate) fn from_rows(k: usize, rows: Vec<Vec<(usize, U)>>) -> Self {
+        let mut offsets = Vec::with_capacity(rows.len() + 1);
+        offsets.push(0);
+        let mut neighbors = Vec::with_capacity(rows.len() * k);
+        let mut distances = Vec::with_capacity(rows.len() * k);
+
+        for mut row in rows {
+            row.sort_by(|(i, a), (j, b)| a.partial_cmp(b).unwrap_or(Ordering::Greater).then(i.cmp(j)));
+            neighbors.extend(row.iter().map(|&(i, _)| i));
+            distances.extend(row.iter().map(|&(_, d)| d));
+            offsets.push(neighbors.len());
+        }
+
+        Self {
+            k,
+            offsets,
+            neighbors,
+            distances,
+        }
+    }
+
+    /// Computes the `k` nearest neighbors of every instance in a `Tree`.
+    ///
+    /// The graph is built leaf by leaf. The hits of the instances in each leaf are
+    /// seeded with the distances among them. Then, for each leaf `L`, the tree is
+    /// traversed once on behalf of all of its instances: a `Cluster` `C` is pruned
+    /// when `d(L, C) - r(L) - r(C)` exceeds the distance to the farthest `k`-th
+    /// neighbor of any instance in `L`. The distances between the instances in `L`
+    /// and those in each remaining leaf are computed as one block, and each
+    /// distance is offered to the hits of both of its instances, so that no pair
+    /// of leaves is compared twice.
+    ///
+    /// The pruning is only valid if the distance function obeys the triangle
+    /// inequality. Otherwise, every pair of leaves is compared. If the distance
+    /// function is not symmetric, each block is computed in both directions.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree` - The tree whose instances are the vertices of the graph.
+    /// * `k` - The number of neighbors of each instance.
+    ///
+    /// # Returns
+    ///
+    /// The graph, with a row for every index in the `tree`. Instances which have
+    /// been removed from the `tree` have no neighbors and are never neighbors.
+    pub fn from_tree<I, D, C>(tree: &Tree<I, U, D, C>, k: usize) -> Self
+    where
+        I: Instance,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        let data = tree.data();
+        let (is_metric, is_symmetric) = (data.is_metric(), data.is_metric_symmetric());
+        let mut hits = (0..tree.cardinality()).map(|_| Hits::new(k)).collect::<Vec<_>>();
+        if k == 0 {
+            return Self::from_rows(k, hits.iter().map(Hits::extract).collect());
+        }
+
+        let leaves = tree
+            .leaves()
+            .into_iter()
+            .map(|c| {
+                let indices = c.indices().filter(|&i| !tree.is_removed(i)).collect::<Vec<_>>();
+                (c, indices)
+            })
+            .filter(|(_, indices)| !indices.is_empty())
+            .collect::<Vec<_>>();
+
+        // Seed the hits with the distances within each leaf.
+        let blocks = leaves
+            .par_iter()
+            .map(|(_, indices)| data.pairwise(indices))
+            .collect::<Vec<_>>();
+        for ((_, indices), block) in leaves.iter().zip(blocks) {
+            for (&i, row) in indices.iter().zip(block) {
+                let row = indices.iter().copied().zip(row).filter(|&(j, _)| j != i);
+                hits[i].push_batch(row);
+            }
+        }
+
+        // The offsets of the pairs of leaves whose distances have been computed.
+        let mut compared = HashSet::new();
+        let root = tree.root();
+
+        for (leaf, indices) in &leaves {
+            // The distance to the farthest `k`-th neighbor of any instance in the
+            // leaf, or `None` if some instance has fewer than `k` hits.
+            let bound = |hits: &[Hits<usize, U>]| {
+                indices.iter().try_fold(U::zero(), |bound, &i| {
+                    let h = &hits[i];
+                    (h.len() == k).then(|| if bound < h.peek() { h.peek() } else { bound })
+                })
+            };
+            let is_pruned = |lower: U, hits: &[Hits<usize, U>]| is_metric && bound(hits).is_some_and(|b| lower > b);
+
+            // A single traversal of the tree for all instances in the leaf.
+            let mut candidates = Vec::new();
+            let mut stack = vec![root];
+            while let Some(c) = stack.pop() {
+                let d = leaf.distance_to_other(data, c);
+                let lower = d - leaf.radius() - c.radius();
+                let lower = if lower < U::zero() { U::zero() } else { lower };
+                if is_pruned(lower, &hits) {
+                    continue;
+                }
+                if let Some(children) = c.children() {
+                    stack.extend(children);
+                } else if c.offset() != leaf.offset() {
+                    let pair = (c.offset().min(leaf.offset()), c.offset().max(leaf.offset()));
+                    if !compared.contains(&pair) {
+                        candidates.push((lower, c, pair));
+                    }
+                }
+            }
+
+            // The nearest leaves are compared first, so that the bound shrinks as
+            // quickly as possible.
+            candidates.sort_by(|(a, _, _), (b, _, _)| a.partial_cmp(b).unwrap_or(Ordering::Greater));
+            for (lower, other, pair) in candidates {
+                if is_pruned(lower, &hits) {
+                    continue;
+                }
+                compared.insert(pair);
+
+                let others = other.indices().filter(|&i| !tree.is_removed(i)).collect::<Vec<_>>();
+                let block = many_to_many(data, indices, &others);
+                for (&i, row) in indices.iter().zip(&block) {
+                    hits[i].push_batch(others.iter().copied().zip(row.iter().copied()));
+                }
+
+                if is_symmetric {
+                    for (j, &o) in others.iter().enumerate() {
+                        hits[o].push_batch(indices.iter().copied().zip(block.iter().map(|row| row[j])));
+                    }
+                } else {
+                    let block = many_to_many(data, &others, indices);
+                    for (&o, row) in others.iter().zip(block) {
+                        hits[o].push_batch(indices.iter().copied().zip(row));
+                    }
+                }
+            }
+        }
+
+        Self::from_rows(k, hits.iter().map(Hits::extract).collect())
+    }
+
+    /// The number of neighbors requested for each instance.
+    #[must_use]
+    pub const fn k(&self) -> usize {
+        self.k
+    }
+
+    /// The number of vertices, i.e. instances, in the graph.
+    #[must_use]
+    pub fn num_vertices(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    /// The number of directed edges in the graph.
+    #[must_use]
+    pub fn num_edges(&self) -> usize {
+        self.neighbors.len()
+    }
+
+    /// The start of the row of each instance, followed by the number of edges.
+    #[must_use]
+    pub fn offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    /// The indices of the neighbors of all instances, row by row.
+    #[must_use]
+    pub fn neighbor_indices(&self) -> &[usize] {
+        &self.neighbors
+    }
+
+    /// The distances to the neighbors of all instances, row by row.
+    #[must_use]
+    pub fn neighbor_distances(&self) -> &[U] {
+        &self.distances
+    }
+
+    /// The neighbors of an instance and the distances to them, nearest first.
+    ///
+    /// There are fewer than `k` neighbors if there are fewer than `k` other
+    /// instances, and none if the instance has been removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index of the instance, as returned by search.
+    ///
+    /// # Panics
+    ///
+    /// * If `index` is not less than the number of vertices.
+    #[must_use]
+    pub fn neighbors_of(&self, index: usize) -> (&[usize], &[U]) {
+        let range = self.offsets[index]..self.offsets[index + 1];
+        (&self.neighbors[range.clone()], &self.distances[range])
+    }
+}
+
+/// Computes the distances between two sets of instances, with the rows in
+/// parallel.
+fn many_to_many<I: Instance, U: Number, D: Dataset<I, U>>(data: &D, left: &[usize], right: &[usize]) -> Vec<Vec<U>> {
+    left.par_iter().map(|&l| data.one_to_many(l, right)).collect()
+}
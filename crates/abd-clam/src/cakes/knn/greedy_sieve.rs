@@ -2,17 +2,22 @@
 
 use distances::Number;
 
-use crate::{Cluster, Dataset, Instance, Tree};
+use crate::par::prelude::*;
+use crate::{
+    cakes::probe::{Probe, ProbeExt},
+    Cluster, Dataset, Instance, Tree,
+};
 
 use super::{OrdNumber, RevNumber};
 
 /// K-Nearest Neighbor search with expanding threshold.
 ///
-/// /// # Arguments
+/// # Arguments
 ///
 /// * `tree` - The tree to search.
 /// * `query` - The query to search around.
 /// * `k` - The number of neighbors to search for.
+/// * `probe` - Receives the events of the search.
 ///
 /// # Returns
 ///
@@ -20,19 +25,28 @@ use super::{OrdNumber, RevNumber};
 /// and the second element is the distance from the query to the instance.
 ///
 /// Contrast this to `SieveV1` and `SieveV2`, which use a (mostly) decreasing threshold.
-pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, k: usize) -> Vec<(usize, U)>
+///
+/// If the distance function of the dataset is expensive, the search switches
+/// to `search_parallel`, which spreads the work for a single query across
+/// threads.
+pub fn search<I, U, D, C, P>(tree: &Tree<I, U, D, C>, query: &I, k: usize, probe: &P) -> Vec<(usize, U)>
 where
     I: Instance,
     U: Number,
     D: Dataset<I, U>,
     C: Cluster<U>,
+    P: Probe,
 {
+    if tree.data().is_metric_expensive() {
+        return search_parallel(tree, query, k, probe);
+    }
+
     let mut candidates = priority_queue::PriorityQueue::<&C, RevNumber<U>>::new();
     let mut hits = priority_queue::PriorityQueue::<usize, OrdNumber<U>>::new();
 
     let (data, root) = (tree.data(), &tree.root);
 
-    let d = root.distance_to_instance(data, query);
+    let d = probe.distance_to_center(root, data, query);
     candidates.push(root, RevNumber(d_min(root, d)));
 
     // Stop if we have enough hits and the farthest hit is closer than the closest cluster (closeness determined by d_min).
@@ -45,10 +59,96 @@ where
                     .peek()
                     .map_or_else(|| unreachable!("`candidates` is non-empty."), |(_, &RevNumber(d))| d))
     {
-        pop_till_leaf(tree, query, &mut candidates);
-        leaf_into_hits(tree, query, &mut hits, &mut candidates);
+        pop_till_leaf(tree, query, &mut candidates, probe);
+        leaf_into_hits(tree, query, k, &mut hits, &mut candidates, probe);
+        trim_hits(k, &mut hits);
+    }
+    hits.into_iter().map(|(i, OrdNumber(d))| (i, d)).collect()
+}
+
+/// K-Nearest Neighbor search with expanding threshold, parallelized within a
+/// single query.
+///
+/// Instead of expanding one candidate at a time, each step pops up to one
+/// candidate per thread, among those which may still contain one of the `k`
+/// nearest neighbors. The distances to the children of the popped non-leaves,
+/// and to the instances in the popped leaves, are then all computed in
+/// parallel. This does some extra work compared to `search`, but for
+/// expensive distance functions it greatly reduces the latency of a query.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search.
+/// * `query` - The query to search around.
+/// * `k` - The number of neighbors to search for.
+/// * `probe` - Receives the events of the search.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is the distance from the query to the instance.
+pub fn search_parallel<I, U, D, C, P>(tree: &Tree<I, U, D, C>, query: &I, k: usize, probe: &P) -> Vec<(usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+    P: Probe,
+{
+    let mut candidates = priority_queue::PriorityQueue::<&C, RevNumber<U>>::new();
+    let mut hits = priority_queue::PriorityQueue::<usize, OrdNumber<U>>::new();
+
+    let (data, root) = (tree.data(), &tree.root);
+    let batch_size = crate::par::current_num_threads().max(1);
+
+    let d = probe.distance_to_center(root, data, query);
+    candidates.push(root, RevNumber(d_min(root, d)));
+
+    while let Some((_, &RevNumber(closest))) = candidates.peek() {
+        let threshold = hits.peek().map(|(_, &OrdNumber(d))| d);
+        if hits.len() >= k && threshold.is_some_and(|t| t < closest) {
+            break;
+        }
+
+        // Pop the closest candidates which could still contain a hit.
+        let mut batch = Vec::with_capacity(batch_size);
+        while batch.len() < batch_size {
+            match candidates.peek() {
+                Some((_, &RevNumber(d))) if hits.len() < k || threshold.map_or(true, |t| d <= t) => {
+                    let (c, RevNumber(d)) = candidates
+                        .pop()
+                        .unwrap_or_else(|| unreachable!("`candidates` is non-empty."));
+                    batch.push((c, d));
+                }
+                _ => break,
+            }
+        }
+
+        let (leaves, parents): (Vec<_>, Vec<_>) = batch.into_iter().partition(|(c, _)| c.is_leaf());
+
+        let children = parents
+            .into_par_iter()
+            .flat_map(|(c, _)| c.children().unwrap_or_else(|| unreachable!("elements are non-leaves")))
+            .filter(|&child| tree.num_live_in(child) > 0)
+            .map(|child| (child, d_min(child, probe.distance_to_center(child, data, query))))
+            .collect::<Vec<_>>();
+        for (child, d) in children {
+            candidates.push(child, RevNumber(d));
+        }
+
+        // The threshold may only be used to skip instances once there are `k` hits.
+        let threshold = if hits.len() < k { None } else { threshold };
+        let new_hits = leaves
+            .into_par_iter()
+            .flat_map(|(leaf, d)| probe.distances_to_leaf_within(leaf, d, tree, query, k, threshold))
+            .collect::<Vec<_>>();
+        for (i, d) in new_hits {
+            hits.push(i, OrdNumber(d));
+        }
+
         trim_hits(k, &mut hits);
     }
+
     hits.into_iter().map(|(i, OrdNumber(d))| (i, d)).collect()
 }
 
@@ -63,60 +163,67 @@ pub fn d_min<U: Number, C: Cluster<U>>(c: &C, d: U) -> U {
 }
 
 /// Pops from the top of `candidates` until the top candidate is a leaf cluster.
-fn pop_till_leaf<I, U, D, C>(
+pub(super) fn pop_till_leaf<I, U, D, C, P>(
     tree: &Tree<I, U, D, C>,
     query: &I,
     candidates: &mut priority_queue::PriorityQueue<&C, RevNumber<U>>,
+    probe: &P,
 ) where
     I: Instance,
     U: Number,
     D: Dataset<I, U>,
     C: Cluster<U>,
+    P: Probe,
 {
     while !candidates
         .peek()
         .map_or_else(|| unreachable!("`candidates` is non-empty"), |(c, _)| c.is_leaf())
     {
-        let [l, r] = candidates.pop().map_or_else(
+        let children = candidates.pop().map_or_else(
             || unreachable!("`candidates` is non-empty"),
             |(c, _)| c.children().unwrap_or_else(|| unreachable!("elements are non-leaves")),
         );
-        let [dl, dr] = [
-            l.distance_to_instance(tree.data(), query),
-            r.distance_to_instance(tree.data(), query),
-        ];
-        candidates.push(l, RevNumber(d_min(l, dl)));
-        candidates.push(r, RevNumber(d_min(r, dr)));
+        // `Cluster`s whose instances have all been removed cannot hold a hit.
+        for c in children.into_iter().filter(|&c| tree.num_live_in(c) > 0) {
+            let d = probe.distance_to_center(c, tree.data(), query);
+            candidates.push(c, RevNumber(d_min(c, d)));
+        }
     }
 }
 
 /// Pops a single leaf from the top of `candidates` and add those points to `hits`.
-fn leaf_into_hits<I, U, D, C>(
+///
+/// Once there are `k` hits, instances which a lower bound on the metric shows
+/// to be farther than all of them are skipped.
+pub(super) fn leaf_into_hits<I, U, D, C, P>(
     tree: &Tree<I, U, D, C>,
     query: &I,
+    k: usize,
     hits: &mut priority_queue::PriorityQueue<usize, OrdNumber<U>>,
     candidates: &mut priority_queue::PriorityQueue<&C, RevNumber<U>>,
+    probe: &P,
 ) where
     I: Instance,
     U: Number,
     D: Dataset<I, U>,
     C: Cluster<U>,
+    P: Probe,
 {
     let (leaf, RevNumber(d)) = candidates
         .pop()
         .unwrap_or_else(|| unreachable!("candidates is non-empty"));
-    let distances = if leaf.is_singleton() {
-        vec![d; leaf.indices().len()]
+    let threshold = if hits.len() < k {
+        None
     } else {
-        tree.data().query_to_many(query, &leaf.indices().collect::<Vec<_>>())
+        hits.peek().map(|(_, &OrdNumber(d))| d)
     };
-    leaf.indices().zip(distances).for_each(|(i, d)| {
+    for (i, d) in probe.distances_to_leaf_within(leaf, d, tree, query, k, threshold) {
         hits.push(i, OrdNumber(d));
-    });
+    }
 }
 
 /// Trims `hits` to contain only the k nearest neighbors.
-fn trim_hits<U: Number>(k: usize, hits: &mut priority_queue::PriorityQueue<usize, OrdNumber<U>>) {
+pub(super) fn trim_hits<U: Number>(k: usize, hits: &mut priority_queue::PriorityQueue<usize, OrdNumber<U>>) {
     while hits.len() > k {
         hits.pop()
             .unwrap_or_else(|| unreachable!("`hits` is non-empty and has at least k elements."));
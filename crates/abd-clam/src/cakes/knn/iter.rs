@@ -0,0 +1,121 @@
+//! A lazy iterator over the nearest neighbors of a query.
+
+use distances::Number;
+use priority_queue::PriorityQueue;
+
+use crate::{Cluster, Dataset, Instance, Tree};
+
+use super::{greedy_sieve::d_min, OffsetTree, RevNumber};
+
+/// An entry in the queue of a `NeighborsIter`.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum Entry<'a, C> {
+    /// A `Cluster` which has not been expanded, along with the position of its
+    /// tree in `NeighborsIter::trees`.
+    Cluster(usize, &'a C),
+    /// An instance, by its index as returned by search.
+    Instance(usize),
+}
+
+/// An iterator over the neighbors of a query in increasing order of distance,
+/// as made by `Cakes::neighbors`.
+///
+/// A single priority queue holds both `Cluster`s, ranked by their `d_min`, and
+/// instances, ranked by their distance to the query. An instance is yielded
+/// once it is at the front of the queue, because every instance left in the
+/// queue, or in a `Cluster` in the queue, is at least as far from the query.
+/// `Cluster`s are expanded only as needed, so the same traversal can yield 10
+/// neighbors or 10,000 without knowing in advance how many will be taken.
+///
+/// Instances which have been removed from the tree(s) are never yielded. If
+/// the metric does not obey the triangle inequality, every distance is
+/// computed before the first neighbor is yielded.
+#[derive(Debug)]
+pub struct NeighborsIter<'a, I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> {
+    /// The trees to search, each with the offset of its indices.
+    trees: Vec<OffsetTree<'a, I, U, D, C>>,
+    /// The query.
+    query: &'a I,
+    /// The `Cluster`s and instances which have yet to be expanded or yielded.
+    queue: PriorityQueue<Entry<'a, C>, RevNumber<U>>,
+}
+
+impl<'a, I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> NeighborsIter<'a, I, U, D, C> {
+    /// Creates an iterator over the neighbors of a query in a tree.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree` - The tree to search.
+    /// * `query` - The query to search around.
+    pub fn new(tree: &'a Tree<I, U, D, C>, query: &'a I) -> Self {
+        Self::from_trees(vec![(tree, 0)], query)
+    }
+
+    /// Creates an iterator over the neighbors of a query in several trees.
+    ///
+    /// # Arguments
+    ///
+    /// * `trees` - The trees to search, each with the offset to add to the
+    ///   indices of its instances.
+    /// * `query` - The query to search around.
+    pub(crate) fn from_trees(trees: Vec<OffsetTree<'a, I, U, D, C>>, query: &'a I) -> Self {
+        let mut iter = Self {
+            trees,
+            query,
+            queue: PriorityQueue::new(),
+        };
+
+        for (t, (tree, _)) in iter.trees.clone().into_iter().enumerate() {
+            if tree.data().is_metric() {
+                let root = &tree.root;
+                let d = root.distance_to_instance(tree.data(), query);
+                iter.queue.push(Entry::Cluster(t, root), RevNumber(d_min(root, d)));
+            } else {
+                iter.push_instances(t, 0..tree.cardinality());
+            }
+        }
+
+        iter
+    }
+
+    /// Pushes the instances at `indices` in the tree at position `t` onto the
+    /// queue.
+    fn push_instances(&mut self, t: usize, indices: impl Iterator<Item = usize>) {
+        let (tree, offset) = self.trees[t];
+        let indices = indices.filter(|&i| !tree.is_removed(i)).collect::<Vec<_>>();
+        let distances = tree.data().query_to_many(self.query, &indices);
+        for (i, d) in indices.into_iter().zip(distances) {
+            self.queue.push(Entry::Instance(i + offset), RevNumber(d));
+        }
+    }
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Iterator for NeighborsIter<'_, I, U, D, C> {
+    type Item = (usize, U);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((entry, RevNumber(d))) = self.queue.pop() {
+            match entry {
+                Entry::Instance(i) => return Some((i, d)),
+                Entry::Cluster(t, c) => {
+                    let (tree, offset) = self.trees[t];
+                    if let Some(children) = c.children() {
+                        for child in children {
+                            let d = child.distance_to_instance(tree.data(), self.query);
+                            self.queue.push(Entry::Cluster(t, child), RevNumber(d_min(child, d)));
+                        }
+                    } else if c.is_singleton() {
+                        // Every instance is as far from the query as the center,
+                        // which is `d` away as the radius is zero.
+                        for i in c.indices().filter(|&i| !tree.is_removed(i)) {
+                            self.queue.push(Entry::Instance(i + offset), RevNumber(d));
+                        }
+                    } else {
+                        self.push_instances(t, c.indices());
+                    }
+                }
+            }
+        }
+        None
+    }
+}
@@ -2,7 +2,10 @@
 
 use distances::Number;
 
-use crate::{Dataset, Instance};
+use crate::{
+    cakes::probe::{Probe, ProbeExt},
+    Dataset, Instance,
+};
 
 use super::Hits;
 
@@ -14,18 +17,27 @@ use super::Hits;
 /// * `query` - The query to search around.
 /// * `k` - The number of neighbors to search for.
 /// * `indices` - The indices to search.
+/// * `probe` - Receives the events of the search.
 ///
 /// # Returns
 ///
 /// A vector of 2-tuples, where the first element is the index of the instance
 /// and the second element is the distance from the query to the instance.
-pub fn search<I, U, D>(data: &D, query: &I, k: usize, indices: &[usize]) -> Vec<(usize, U)>
+///
+/// If the dataset has a lower bound on its metric, the search switches to
+/// `search_by_lower_bound`.
+pub fn search<I, U, D, P>(data: &D, query: &I, k: usize, indices: &[usize], probe: &P) -> Vec<(usize, U)>
 where
     I: Instance,
     U: Number,
     D: Dataset<I, U>,
+    P: Probe,
 {
-    let distances = data.query_to_many(query, indices);
+    if let Some(lower_bound) = data.lower_bound() {
+        return search_by_lower_bound(data, query, k, indices, lower_bound, probe);
+    }
+
+    let distances = probe.distances_to(data, query, indices);
 
     let mut hits = Hits::new(k);
     indices
@@ -34,3 +46,60 @@ where
         .for_each(|(&i, &d)| hits.push(i, d));
     hits.extract()
 }
+
+/// Linear search which computes distances in increasing order of a lower
+/// bound on the metric, and stops once the lower bound exceeds the distance
+/// to the `k`-th nearest hit.
+///
+/// The distances are computed in batches of at least one per thread, so that
+/// a single query may still use all threads.
+///
+/// # Arguments
+///
+/// * `data` - The dataset to search.
+/// * `query` - The query to search around.
+/// * `k` - The number of neighbors to search for.
+/// * `indices` - The indices to search.
+/// * `lower_bound` - A function which never exceeds the metric.
+/// * `probe` - Receives the events of the search.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is the distance from the query to the instance.
+pub fn search_by_lower_bound<I, U, D, P>(
+    data: &D,
+    query: &I,
+    k: usize,
+    indices: &[usize],
+    lower_bound: fn(&I, &I) -> U,
+    probe: &P,
+) -> Vec<(usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    P: Probe,
+{
+    let mut bounds = indices
+        .iter()
+        .map(|&i| (i, lower_bound(query, &data.get(i))))
+        .collect::<Vec<_>>();
+    bounds.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(core::cmp::Ordering::Less));
+
+    let batch_size = k.max(crate::par::current_num_threads()).max(1);
+    let mut hits = Hits::new(k);
+    for batch in bounds.chunks(batch_size) {
+        let batch = batch
+            .iter()
+            .filter(|&&(_, b)| hits.len() < k || b <= hits.peek())
+            .map(|&(i, _)| i)
+            .collect::<Vec<_>>();
+        if batch.is_empty() {
+            break;
+        }
+        let distances = probe.distances_to(data, query, &batch);
+        batch.into_iter().zip(distances).for_each(|(i, d)| hits.push(i, d));
+    }
+    hits.extract()
+}
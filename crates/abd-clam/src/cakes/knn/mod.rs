@@ -13,13 +13,73 @@ use core::{cmp::Ordering, hash::Hash};
 use distances::Number;
 use priority_queue::PriorityQueue;
 
-use crate::{Cluster, Dataset, Instance, Tree};
-
+use crate::cakes::probe::{Counter, Probe, SearchStats};
+use crate::par::prelude::*;
+use crate::{Cluster, Dataset, Error, Instance, Tree};
+
+pub(crate) mod anytime;
+pub(crate) mod approximate;
+pub(crate) mod depth_first_sieve;
+pub(crate) mod dual_tree;
+pub(crate) mod filtered;
+pub(crate) mod graph;
 pub(crate) mod greedy_sieve;
+pub(crate) mod iter;
 pub(crate) mod linear;
 pub(crate) mod repeated_rnn;
+pub(crate) mod seeded;
 pub(crate) mod sieve;
 pub(crate) mod sieve_sep_center;
+pub(crate) mod within;
+
+pub use anytime::{AnytimeHits, Budget};
+pub use graph::KnnGraph;
+pub use iter::NeighborsIter;
+
+/// A tree, with the offset to add to the indices of its instances to get the
+/// indices returned by search.
+pub(crate) type OffsetTree<'a, I, U, D, C> = (&'a Tree<I, U, D, C>, usize);
+
+/// A strategy for K-Nearest Neighbor search over a `Tree`.
+///
+/// This is the extension point for search algorithms defined outside the
+/// crate. `Algorithm` implements it for the built-in algorithms, and
+/// `Cakes::knn_search_with` accepts any implementation.
+pub trait KnnSearch<I: Instance, U: Number, D: Dataset<I, U>>: Send + Sync {
+    /// Returns the name of the strategy.
+    fn name(&self) -> String;
+
+    /// Searches for the nearest neighbors of a query.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree` - The tree to search.
+    /// * `query` - The query to search around.
+    /// * `k` - The number of neighbors to search for.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 2-tuples, where the first element is the index of the instance
+    /// and the second element is the distance from the query to the instance.
+    fn search<C: Cluster<U>>(&self, tree: &Tree<I, U, D, C>, query: &I, k: usize) -> Vec<(usize, U)>;
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>> KnnSearch<I, U, D> for Algorithm {
+    fn name(&self) -> String {
+        Self::name(self).to_string()
+    }
+
+    fn search<C: Cluster<U>>(&self, tree: &Tree<I, U, D, C>, query: &I, k: usize) -> Vec<(usize, U)> {
+        Self::search(*self, tree, query, k)
+    }
+}
+
+/// The default `lfd_threshold` of the algorithms which use one.
+///
+/// These are `Algorithm::RepeatedRnn`, `Algorithm::Sieve` and
+/// `Algorithm::SieveSepCenter`. With this threshold, the local fractal
+/// dimension of a `Cluster` never stops it from being scanned entirely.
+pub const DEFAULT_LFD_THRESHOLD: f64 = f64::INFINITY;
 
 /// The algorithm to use for K-Nearest Neighbor search.
 // TODO(Morgan): Update the docs for each algorithm.
@@ -42,8 +102,16 @@ pub enum Algorithm {
     /// dimension of the neighbors found until enough neighbors are found. This
     /// factor is capped at 2. Once enough neighbors are found, the neighbors
     /// are sorted by distance and the first `k` neighbors are returned. Ties
-    /// are broken arbitrarily.
-    RepeatedRnn,
+    /// are broken arbitrarily, unless a `TieBreaking` policy is used.
+    ///
+    /// `Cluster`s within the final radius whose local fractal dimension is
+    /// greater than `lfd_threshold` are descended into, with pruning, instead
+    /// of being scanned entirely.
+    RepeatedRnn {
+        /// The local fractal dimension above which a `Cluster` is descended
+        /// into instead of scanned.
+        lfd_threshold: f64,
+    },
 
     /// Uses two priority queues and an increasing threshold to perform search.
     ///
@@ -59,6 +127,19 @@ pub enum Algorithm {
     /// until candidates is empty or the closest candidate is worse than the furthest hit.
     GreedySieve,
 
+    /// Descends greedily to the closest leaf to seed the hits, then backtracks,
+    /// pruning by `d_min` against the current `k`-th nearest hit.
+    ///
+    /// This algorithm is not stable.
+    ///
+    /// At each `Cluster`, the child with the lower `d_min` is visited first, so
+    /// the first leaf reached is the one most likely to contain the nearest
+    /// neighbors. Once `k` hits have been found, any `Cluster` whose `d_min` is
+    /// greater than the distance to the farthest hit is skipped. On data with
+    /// low local fractal dimension, the seeded hits are usually close to the
+    /// true neighbors, so most of the tree is pruned.
+    DepthFirstSieve,
+
     /// Like `SieveV1`, but without the separate priority queue for hits.
     ///
     /// This algorithm is not stable.
@@ -71,7 +152,14 @@ pub enum Algorithm {
     ///
     /// This approach does not treat the center of a cluster separately from the rest
     /// of the points in the cluster.
-    Sieve,
+    ///
+    /// A `Cluster` with at most `k` instances is scanned entirely only if its
+    /// local fractal dimension is at most `lfd_threshold`.
+    Sieve {
+        /// The local fractal dimension above which a small `Cluster` is
+        /// partitioned instead of scanned.
+        lfd_threshold: f64,
+    },
 
     /// Like `SieveV2`, but without the separate priority queue for hits.
     ///
@@ -85,7 +173,29 @@ pub enum Algorithm {
     ///
     /// This approach treats the center of a cluster separately from the rest
     /// of the points in the cluster.
-    SieveSepCenter,
+    ///
+    /// A `Cluster` with at most `k` instances is scanned entirely only if its
+    /// local fractal dimension is at most `lfd_threshold`.
+    SieveSepCenter {
+        /// The local fractal dimension above which a small `Cluster` is
+        /// partitioned instead of scanned.
+        lfd_threshold: f64,
+    },
+
+    /// Trades accuracy for speed by stopping the search once the estimated
+    /// recall meets the given target.
+    ///
+    /// This algorithm is not stable.
+    ///
+    /// The traversal is the same as for `GreedySieve`, but search terminates as
+    /// soon as the expected number of true neighbors remaining in unexplored
+    /// clusters, estimated from their local fractal dimensions, is small enough
+    /// to meet the `recall` target. A `recall` of `1.0` or more makes this
+    /// equivalent to `GreedySieve`.
+    Approximate {
+        /// The target recall, in the range `(0, 1]`.
+        recall: f32,
+    },
 }
 
 impl Default for Algorithm {
@@ -107,6 +217,7 @@ impl Algorithm {
     ///
     /// A vector of 2-tuples, where the first element is the index of the instance
     /// and the second element is the distance from the query to the instance.
+    /// Instances which have been removed from the `tree` are never returned.
     pub fn search<I, U, D, C>(self, tree: &Tree<I, U, D, C>, query: &I, k: usize) -> Vec<(usize, U)>
     where
         I: Instance,
@@ -114,33 +225,292 @@ impl Algorithm {
         D: Dataset<I, U>,
         C: Cluster<U>,
     {
-        match self {
+        self.search_probed(tree, query, k, &())
+    }
+
+    /// Searches for the nearest neighbors of a query, handling a `k` larger
+    /// than the number of instances according to the `policy`.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree` - The tree to search.
+    /// * `query` - The query to search around.
+    /// * `k` - The number of neighbors to search for.
+    /// * `policy` - What to do if `k` is larger than the number of instances
+    ///   which may be searched.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as returned by `search`.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::KTooLarge` if the `policy` is `KPolicy::Error` and `k` is
+    ///   larger than the number of instances which have not been removed from
+    ///   the `tree`.
+    pub fn search_with_policy<I, U, D, C>(
+        self,
+        tree: &Tree<I, U, D, C>,
+        query: &I,
+        k: usize,
+        policy: KPolicy,
+    ) -> Result<Vec<(usize, U)>, Error>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        policy.check(k, tree.cardinality() - tree.num_removed())?;
+        Ok(self.search(tree, query, k))
+    }
+
+    /// Searches for the nearest neighbors of a query, and counts the work done
+    /// by the search.
+    ///
+    /// This is slightly slower than `search`, because the counts are kept with
+    /// atomic operations so that they remain correct when a single search is
+    /// spread across threads.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree` - The tree to search.
+    /// * `query` - The query to search around.
+    /// * `k` - The number of neighbors to search for.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as returned by `search`, and the number of distance
+    /// computations, `Cluster`s visited and leaves scanned by the search.
+    pub fn search_with_stats<I, U, D, C>(
+        self,
+        tree: &Tree<I, U, D, C>,
+        query: &I,
+        k: usize,
+    ) -> (Vec<(usize, U)>, SearchStats)
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        let counter = Counter::default();
+        let hits = self.search_probed(tree, query, k, &counter);
+        (hits, counter.stats())
+    }
+
+    /// Searches for the nearest neighbors of a query, reporting the events of
+    /// the search to a `Probe`.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree` - The tree to search.
+    /// * `query` - The query to search around.
+    /// * `k` - The number of neighbors to search for.
+    /// * `probe` - Receives the events of the search.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as returned by `search`.
+    #[cfg(feature = "profiling")]
+    pub fn search_with_probe<I, U, D, C, P>(
+        self,
+        tree: &Tree<I, U, D, C>,
+        query: &I,
+        k: usize,
+        probe: &P,
+    ) -> Vec<(usize, U)>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+        P: Probe,
+    {
+        self.search_probed(tree, query, k, probe)
+    }
+
+    /// Searches for the nearest neighbors of a query, and checks the hits
+    /// against those of `Linear` search.
+    ///
+    /// This is meant for catching pruning bugs, e.g. in the unstable
+    /// algorithms or with a metric which does not obey the triangle inequality
+    /// although it is declared to. It does the work of a linear search for
+    /// every query.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree` - The tree to search.
+    /// * `query` - The query to search around.
+    /// * `k` - The number of neighbors to search for.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as returned by `search`.
+    ///
+    /// # Errors
+    ///
+    /// * If a hit is repeated, or its distance is not that from the query to
+    ///   its instance.
+    /// * If the distances of the hits are not those of the true `k` nearest
+    ///   neighbors. The error lists some of the missing neighbors.
+    #[cfg(feature = "verify")]
+    pub fn search_verified<I, U, D, C>(
+        self,
+        tree: &Tree<I, U, D, C>,
+        query: &I,
+        k: usize,
+    ) -> Result<Vec<(usize, U)>, String>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        let hits = self.search(tree, query, k);
+        let expected = Self::Linear.search(tree, query, k);
+        crate::cakes::verify::knn(self.name(), tree.data(), query, &hits, &expected)?;
+        Ok(hits)
+    }
+
+    /// Searches for the nearest neighbors of a query, reporting the events of
+    /// the search to the `probe`.
+    fn search_probed<I, U, D, C, P>(self, tree: &Tree<I, U, D, C>, query: &I, k: usize, probe: &P) -> Vec<(usize, U)>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+        P: Probe,
+    {
+        // When every instance is a hit, nothing can be pruned, and some of the
+        // algorithms would never stop looking for more hits than there are.
+        let cardinality = tree.cardinality() - tree.num_removed();
+        if k >= cardinality {
+            let indices = (0..tree.cardinality())
+                .filter(|&i| !tree.is_removed(i))
+                .collect::<Vec<_>>();
+            return linear::search(tree.data(), query, cardinality, &indices, probe);
+        }
+
+        // The traversals skip instances which have been removed, and any
+        // `Cluster` whose instances have all been removed.
+        match self.adapted_to(tree.data()) {
             Self::Linear => {
-                let indices = (0..tree.cardinality()).collect::<Vec<_>>();
-                linear::search(tree.data(), query, k, &indices)
+                let indices = (0..tree.cardinality())
+                    .filter(|&i| !tree.is_removed(i))
+                    .collect::<Vec<_>>();
+                linear::search(tree.data(), query, k, &indices, probe)
             }
-            Self::RepeatedRnn => repeated_rnn::search(tree, query, k),
-            Self::GreedySieve => greedy_sieve::search(tree, query, k),
-            Self::Sieve => sieve::search(tree, query, k),
-            Self::SieveSepCenter => sieve_sep_center::search(tree, query, k),
+            Self::RepeatedRnn { lfd_threshold } => repeated_rnn::search(tree, query, k, lfd_threshold, probe),
+            Self::GreedySieve => greedy_sieve::search(tree, query, k, probe),
+            Self::DepthFirstSieve => depth_first_sieve::search(tree, query, k, probe),
+            Self::Sieve { lfd_threshold } => sieve::search(tree, query, k, lfd_threshold, probe),
+            Self::SieveSepCenter { lfd_threshold } => sieve_sep_center::search(tree, query, k, lfd_threshold, probe),
+            Self::Approximate { recall } => approximate::search(tree, query, k, recall, probe),
+        }
+    }
+
+    /// Searches for the nearest neighbors of a query, and certifies whether
+    /// the hits are exact.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree` - The tree to search.
+    /// * `query` - The query to search around.
+    /// * `k` - The number of neighbors to search for.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as returned by `search`, along with whether they are the
+    /// exact nearest neighbors. See `is_exact_for`.
+    pub fn search_certified<I, U, D, C>(self, tree: &Tree<I, U, D, C>, query: &I, k: usize) -> CertifiedHits<U>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        CertifiedHits {
+            hits: self.search(tree, query, k),
+            is_exact: self.is_exact_for(tree.data()),
+        }
+    }
+
+    /// Whether the hits of this algorithm are the exact nearest neighbors of
+    /// any query over `data`.
+    ///
+    /// This is `false` only for `Approximate` search with a `recall` below
+    /// `1.0`. Every other algorithm is exact, because those which would prune
+    /// `Cluster`s with the triangle inequality fall back to `Linear` search
+    /// when the metric of `data` does not obey it.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The dataset to be searched.
+    pub fn is_exact_for<I: Instance, U: Number, D: Dataset<I, U>>(self, data: &D) -> bool {
+        !matches!(self.adapted_to(data), Self::Approximate { recall } if recall < 1.0)
+    }
+
+    /// Returns the algorithm to use for the properties of the metric of `data`.
+    ///
+    /// The exact clustered algorithms prune `Cluster`s with the triangle
+    /// inequality, so they fall back to `Linear` search for non-metric distance
+    /// functions. This includes `Approximate` search with a `recall` of `1.0`
+    /// or more, which would otherwise claim to be exact. `Approximate` search
+    /// with a lower `recall` is used as-is.
+    fn adapted_to<I: Instance, U: Number, D: Dataset<I, U>>(self, data: &D) -> Self {
+        match self {
+            Self::Linear => self,
+            Self::Approximate { recall } if recall < 1.0 => self,
+            _ if data.is_metric() => self,
+            _ => Self::Linear,
         }
     }
 
+    /// Searches for the nearest neighbors of a batch of queries.
+    ///
+    /// The queries are searched in parallel.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree` - The tree to search.
+    /// * `queries` - The queries to search around.
+    /// * `k` - The number of neighbors to search for.
+    ///
+    /// # Returns
+    ///
+    /// A vector of vectors of 2-tuples, one inner vector for each query, in the
+    /// same order as the `queries`.
+    pub fn batch_search<I, U, D, C>(self, tree: &Tree<I, U, D, C>, queries: &[&I], k: usize) -> Vec<Vec<(usize, U)>>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        queries.par_iter().map(|&query| self.search(tree, query, k)).collect()
+    }
+
     /// Returns the name of the algorithm.
     #[must_use]
     pub const fn name(&self) -> &str {
         match self {
             Self::Linear => "Linear",
-            Self::RepeatedRnn => "RepeatedRnn",
+            Self::RepeatedRnn { .. } => "RepeatedRnn",
             Self::GreedySieve => "GreedySieve",
-            Self::Sieve => "Sieve",
-            Self::SieveSepCenter => "SieveSepCenter",
+            Self::DepthFirstSieve => "DepthFirstSieve",
+            Self::Sieve { .. } => "Sieve",
+            Self::SieveSepCenter { .. } => "SieveSepCenter",
+            Self::Approximate { .. } => "Approximate",
         }
     }
 
     /// Returns the algorithm from a string representation of the name.
     ///
-    /// The string representation is case-insensitive.
+    /// The string representation is case-insensitive. `Approximate` is parsed
+    /// with a recall target of `0.95`, and the algorithms with an
+    /// `lfd_threshold` are parsed with `DEFAULT_LFD_THRESHOLD`.
     ///
     /// # Arguments
     ///
@@ -156,18 +526,220 @@ impl Algorithm {
     pub fn from_name(s: &str) -> Result<Self, String> {
         match s.to_lowercase().as_str() {
             "linear" => Ok(Self::Linear),
-            "repeatedrnn" => Ok(Self::RepeatedRnn),
+            "repeatedrnn" => Ok(Self::RepeatedRnn {
+                lfd_threshold: DEFAULT_LFD_THRESHOLD,
+            }),
             "greedysieve" => Ok(Self::GreedySieve),
-            "sieve" => Ok(Self::Sieve),
-            "sievesepcenter" => Ok(Self::SieveSepCenter),
+            "depthfirstsieve" => Ok(Self::DepthFirstSieve),
+            "sieve" => Ok(Self::Sieve {
+                lfd_threshold: DEFAULT_LFD_THRESHOLD,
+            }),
+            "sievesepcenter" => Ok(Self::SieveSepCenter {
+                lfd_threshold: DEFAULT_LFD_THRESHOLD,
+            }),
+            "approximate" => Ok(Self::Approximate {
+                recall: approximate::DEFAULT_RECALL,
+            }),
             _ => Err(format!("Unknown algorithm: {s}")),
         }
     }
 
-    /// Returns a list of all the algorithms, excluding Linear.
+    /// Returns a list of all the exact algorithms, excluding Linear.
     #[must_use]
     pub const fn variants<'a>() -> &'a [Self] {
-        &[Self::RepeatedRnn, Self::GreedySieve, Self::Sieve, Self::SieveSepCenter]
+        &[
+            Self::RepeatedRnn {
+                lfd_threshold: DEFAULT_LFD_THRESHOLD,
+            },
+            Self::GreedySieve,
+            Self::DepthFirstSieve,
+            Self::Sieve {
+                lfd_threshold: DEFAULT_LFD_THRESHOLD,
+            },
+            Self::SieveSepCenter {
+                lfd_threshold: DEFAULT_LFD_THRESHOLD,
+            },
+        ]
+    }
+
+    /// Sets the `lfd_threshold` of the algorithms which have one, and returns
+    /// any other algorithm unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `lfd_threshold` - The local fractal dimension above which a `Cluster`
+    ///   is descended into instead of scanned entirely.
+    #[must_use]
+    pub const fn with_lfd_threshold(self, lfd_threshold: f64) -> Self {
+        match self {
+            Self::RepeatedRnn { .. } => Self::RepeatedRnn { lfd_threshold },
+            Self::Sieve { .. } => Self::Sieve { lfd_threshold },
+            Self::SieveSepCenter { .. } => Self::SieveSepCenter { lfd_threshold },
+            _ => self,
+        }
+    }
+}
+
+/// The hits of a KNN search, along with whether they are certified to be the
+/// exact nearest neighbors of the query.
+#[derive(Clone, Debug)]
+pub struct CertifiedHits<U: Number> {
+    /// The hits, as tuples of the index of the instance and its distance to
+    /// the query.
+    pub hits: Vec<(usize, U)>,
+    /// Whether the `hits` are the exact nearest neighbors. If this is `false`,
+    /// some of the true nearest neighbors may be missing from the `hits`.
+    pub is_exact: bool,
+}
+
+/// What a KNN search does when `k` is at least the number of instances which
+/// may be searched.
+///
+/// Every instance is then a hit, so all the algorithms, including the
+/// approximate ones, return every instance, found with a linear scan. `k`
+/// equal to the number of instances is always valid; the policy decides
+/// whether a larger `k` is too.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KPolicy {
+    /// Clamp `k` to the number of instances, and return all of them.
+    #[default]
+    Clamp,
+
+    /// Return `Error::KTooLarge` if `k` is larger than the number of
+    /// instances.
+    Error,
+}
+
+impl KPolicy {
+    /// Checks `k` against the number of instances which may be searched.
+    ///
+    /// # Arguments
+    ///
+    /// * `k` - The number of neighbors to search for.
+    /// * `cardinality` - The number of instances which may be searched.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::KTooLarge` if the policy is `Error` and `k` is larger than
+    ///   `cardinality`.
+    pub const fn check(self, k: usize, cardinality: usize) -> Result<(), Error> {
+        match self {
+            Self::Error if k > cardinality => Err(Error::KTooLarge { k, cardinality }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The padding, relative to the radius of the tree, of the radius within which
+/// ties are searched for.
+const TIE_PADDING: f64 = 1e-6;
+
+/// How to choose among instances at the same distance from the query as the
+/// `k`-th nearest neighbor.
+///
+/// The search algorithms keep `k` hits and break such ties arbitrarily, so
+/// which of the tied instances are returned may vary between algorithms and
+/// trees. The other policies resolve ties with an RNN search at the distance
+/// of the `k`-th hit, and return the hits sorted by distance and then index.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TieBreaking {
+    /// Return every instance tied with the `k`-th nearest neighbor, so there
+    /// may be more than `k` hits.
+    ReturnAll,
+
+    /// Return `k` hits, with ties broken arbitrarily. This needs no extra work.
+    #[default]
+    Arbitrary,
+
+    /// Return `k` hits, breaking ties in favor of smaller indices, so that the
+    /// result is deterministic for a given tree.
+    ByIndex,
+}
+
+impl TieBreaking {
+    /// Resolves the ties among the hits of a KNN search.
+    ///
+    /// # Arguments
+    ///
+    /// * `k` - The number of neighbors searched for.
+    /// * `hits` - The hits of the search.
+    /// * `tree_radius` - The largest radius of the trees searched.
+    /// * `rnn` - Performs an RNN search around the same query.
+    pub(crate) fn resolve<U: Number, F: FnOnce(U) -> Vec<(usize, U)>>(
+        self,
+        k: usize,
+        hits: Vec<(usize, U)>,
+        tree_radius: U,
+        rnn: F,
+    ) -> Vec<(usize, U)> {
+        if matches!(self, Self::Arbitrary) || hits.len() < k || k == 0 {
+            return hits;
+        }
+
+        let radius = hits
+            .iter()
+            .map(|&(_, d)| d)
+            .fold(U::zero(), |r, d| if d > r { d } else { r });
+
+        // Clusters are pruned with floating-point arithmetic, which may exclude
+        // instances at exactly the `radius`, so the radius is padded slightly
+        // and the hits beyond it are dropped below.
+        let padding = tree_radius.as_f64() * TIE_PADDING;
+        let mut hits = rnn(U::from(radius.as_f64() + padding));
+        hits.retain(|&(_, d)| d <= radius);
+        hits.sort_by(|(i, a), (j, b)| a.partial_cmp(b).unwrap_or(Ordering::Greater).then(i.cmp(j)));
+
+        // An approximate search may have missed some of the true neighbors, in
+        // which case the RNN search finds more than `k` hits which are nearer
+        // than the `k`-th hit.
+        match self {
+            Self::ReturnAll => {
+                let kth = hits[k - 1].1;
+                hits.retain(|&(_, d)| d <= kth);
+            }
+            Self::ByIndex | Self::Arbitrary => hits.truncate(k),
+        }
+        hits
+    }
+}
+
+/// Searches for the nearest neighbors of a query among the instances for which
+/// `filter` returns `true`.
+///
+/// The `filter` is applied during the traversal of the tree, so the search
+/// keeps going until `k` passing hits are found, instead of discarding hits
+/// after the fact. For non-metric distance functions, this falls back to a
+/// linear search over the passing instances.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search.
+/// * `query` - The query to search around.
+/// * `k` - The number of neighbors to search for.
+/// * `filter` - A predicate on the indices of instances in the `tree`.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is the distance from the query to the instance.
+/// There may be fewer than `k` hits if fewer than `k` instances pass the
+/// `filter`. Instances which have been removed from the `tree` are never
+/// returned.
+pub fn search_filtered<I, U, D, C, F>(tree: &Tree<I, U, D, C>, query: &I, k: usize, filter: F) -> Vec<(usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+    F: Fn(usize) -> bool,
+{
+    if tree.data().is_metric() {
+        filtered::search(tree, query, k, filter)
+    } else {
+        let indices = (0..tree.cardinality())
+            .filter(|&i| !tree.is_removed(i) && filter(i))
+            .collect::<Vec<_>>();
+        linear::search(tree.data(), query, k, &indices, &())
     }
 }
 
@@ -2,9 +2,15 @@
 
 use distances::Number;
 
-use crate::{cakes::rnn::clustered, utils, Cluster, Dataset, Instance, Tree};
+use crate::{
+    cakes::{
+        probe::{Probe, ProbeExt},
+        rnn::clustered,
+    },
+    utils, Cluster, Dataset, Instance, Tree,
+};
 
-use super::Hits;
+use super::{greedy_sieve::d_min, Hits};
 
 /// The multiplier to use for increasing the radius in the repeated RNN algorithm.
 const MULTIPLIER: f64 = 2.0;
@@ -16,27 +22,37 @@ const MULTIPLIER: f64 = 2.0;
 /// * `tree` - The tree to search.
 /// * `query` - The query to search around.
 /// * `k` - The number of neighbors to search for.
+/// * `lfd_threshold` - The local fractal dimension above which a `Cluster`
+///   within the final radius is descended into instead of scanned.
+/// * `probe` - Receives the events of the search.
 ///
 /// # Returns
 ///
 /// A vector of 2-tuples, where the first element is the index of the instance
 /// and the second element is the distance from the query to the instance.
-pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, k: usize) -> Vec<(usize, U)>
+pub fn search<I, U, D, C, P>(
+    tree: &Tree<I, U, D, C>,
+    query: &I,
+    k: usize,
+    lfd_threshold: f64,
+    probe: &P,
+) -> Vec<(usize, U)>
 where
     I: Instance,
     U: Number,
     D: Dataset<I, U>,
     C: Cluster<U>,
+    P: Probe,
 {
     let mut radius = f64::EPSILON + tree.radius().as_f64() / tree.cardinality().as_f64();
-    let [mut confirmed, mut straddlers] = clustered::tree_search(tree.data(), &tree.root, query, U::from(radius));
+    let [mut confirmed, mut straddlers] = clustered::tree_search(tree.data(), &tree.root, query, U::from(radius), probe);
 
-    let mut num_confirmed = count_hits(&confirmed);
+    let mut num_confirmed = count_hits(tree, &confirmed);
 
     while num_confirmed == 0 {
         radius *= MULTIPLIER;
-        [confirmed, straddlers] = clustered::tree_search(tree.data(), &tree.root, query, U::from(radius));
-        num_confirmed = count_hits(&confirmed);
+        [confirmed, straddlers] = clustered::tree_search(tree.data(), &tree.root, query, U::from(radius), probe);
+        num_confirmed = count_hits(tree, &confirmed);
     }
 
     while num_confirmed < k {
@@ -50,18 +66,71 @@ where
         let factor = (k.as_f64() / num_confirmed.as_f64()).powf(1. / (lfd + f64::EPSILON));
 
         radius *= if factor < MULTIPLIER { factor } else { MULTIPLIER };
-        [confirmed, straddlers] = clustered::tree_search(tree.data(), &tree.root, query, U::from(radius));
-        num_confirmed = count_hits(&confirmed);
+        [confirmed, straddlers] = clustered::tree_search(tree.data(), &tree.root, query, U::from(radius), probe);
+        num_confirmed = count_hits(tree, &confirmed);
     }
 
-    Hits::from_vec(
+    // `Cluster`s with a high local fractal dimension are descended into, so
+    // that their sub-clusters may be pruned by the hits from the rest.
+    let (scanned, descended) = confirmed
+        .into_iter()
+        .partition::<Vec<_>, _>(|&(c, _)| c.is_leaf() || c.lfd() <= lfd_threshold);
+
+    let mut hits = Hits::from_vec(
         k,
-        clustered::leaf_search(&tree.data, confirmed, straddlers, query, U::from(radius)),
-    )
-    .extract()
+        clustered::leaf_search(tree, scanned, straddlers, query, U::from(radius), probe),
+    );
+    descend(tree, descended, query, lfd_threshold, &mut hits, probe);
+    hits.extract()
+}
+
+/// Adds the instances of `Cluster`s entirely within the search radius to the
+/// `hits`, scanning those which are leaves or whose local fractal dimension is
+/// at most `lfd_threshold`, and descending depth-first into the rest while
+/// pruning by `d_min` against the `k`-th hit.
+fn descend<I, U, D, C, P>(
+    tree: &Tree<I, U, D, C>,
+    clusters: Vec<(&C, U)>,
+    query: &I,
+    lfd_threshold: f64,
+    hits: &mut Hits<usize, U>,
+    probe: &P,
+) where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+    P: Probe,
+{
+    let mut stack = clusters;
+    while let Some((c, d)) = stack.pop() {
+        if hits.len() == hits.capacity && d_min(c, d) > hits.peek() {
+            continue;
+        }
+
+        match c.children() {
+            Some(children) if c.lfd() > lfd_threshold => {
+                stack.extend(
+                    children
+                        .into_iter()
+                        .filter(|&c| tree.num_live_in(c) > 0)
+                        .map(|c| (c, probe.distance_to_center(c, tree.data(), query))),
+                );
+            }
+            _ => {
+                hits.push_batch(probe.distances_to_leaf(c, d, tree, query).into_iter());
+            }
+        }
+    }
 }
 
-/// Count the total cardinality of the clusters.
-fn count_hits<U: Number, C: Cluster<U>>(clusters: &[(&C, U)]) -> usize {
-    clusters.iter().map(|(c, _)| c.cardinality()).sum()
+/// Count the instances in the clusters which have not been removed.
+fn count_hits<I, U, D, C>(tree: &Tree<I, U, D, C>, clusters: &[(&C, U)]) -> usize
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    clusters.iter().map(|(c, _)| tree.num_live_in(c)).sum()
 }
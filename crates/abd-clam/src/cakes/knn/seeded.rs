@@ -0,0 +1,92 @@
+//! K-Nearest Neighbor search which starts from the hits of a similar query.
+
+use distances::Number;
+
+use crate::{Cluster, Dataset, Instance};
+
+use super::{greedy_sieve::d_min, Hits, OffsetTree};
+
+/// K-Nearest Neighbor search whose hits start with some seed instances, e.g.
+/// the neighbors of the previous query in a sequence of similar queries.
+///
+/// The distances from the query to the seeds are computed first, so that the
+/// `k`-th nearest seed prunes the trees from the start. The traversal is then
+/// that of `DepthFirstSieve`, over each tree in turn. The closer the seeds
+/// are to the true neighbors, the less of the trees is visited, but the hits
+/// are exact whatever the seeds.
+///
+/// # Arguments
+///
+/// * `trees` - The trees to search, each with the offset to add to the
+///   indices of its instances.
+/// * `query` - The query to search around.
+/// * `k` - The number of neighbors to search for.
+/// * `seeds` - The indices of the seed instances, as returned by search.
+///   Indices of removed instances, or beyond the trees, are ignored.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is the distance from the query to the instance.
+pub fn search<I, U, D, C>(trees: &[OffsetTree<I, U, D, C>], query: &I, k: usize, seeds: &[usize]) -> Vec<(usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let mut hits = Hits::new(k);
+    if k == 0 {
+        return hits.extract();
+    }
+
+    for &i in seeds {
+        let located = trees
+            .iter()
+            .find(|&&(tree, o)| o <= i && i < o + tree.cardinality())
+            .map(|&(tree, o)| (tree, i - o));
+        if let Some((tree, j)) = located {
+            if !tree.is_removed(j) {
+                hits.push(i, tree.data().query_to_one(query, j));
+            }
+        }
+    }
+
+    for &(tree, offset) in trees {
+        let (data, root) = (tree.data(), &tree.root);
+
+        // Without the triangle inequality, nothing can be pruned.
+        if !data.is_metric() {
+            let indices = (0..tree.cardinality())
+                .filter(|&i| !tree.is_removed(i))
+                .collect::<Vec<_>>();
+            let distances = data.query_to_many(query, &indices);
+            hits.push_batch(indices.into_iter().map(|i| i + offset).zip(distances));
+            continue;
+        }
+
+        let d = root.distance_to_instance(data, query);
+        let mut stack = vec![(root, d_min(root, d))];
+        while let Some((c, bound)) = stack.pop() {
+            if hits.len() == k && bound > hits.peek() {
+                continue;
+            }
+
+            if let Some(children) = c.children() {
+                let mut children = children
+                    .into_iter()
+                    .map(|c| (c, d_min(c, c.distance_to_instance(data, query))))
+                    .collect::<Vec<_>>();
+                children.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(core::cmp::Ordering::Less));
+                // The closer children are pushed last so that they are visited first.
+                stack.extend(children.into_iter().rev());
+            } else {
+                let indices = c.indices().filter(|&i| !tree.is_removed(i)).collect::<Vec<_>>();
+                let distances = data.query_to_many(query, &indices);
+                hits.push_batch(indices.into_iter().map(|i| i + offset).zip(distances));
+            }
+        }
+    }
+
+    hits.extract()
+}
@@ -4,7 +4,10 @@
 use core::cmp::{min, Ordering};
 use distances::Number;
 
-use crate::{Cluster, Dataset, Instance, Tree};
+use crate::{
+    cakes::probe::{Probe, ProbeExt},
+    Cluster, Dataset, Instance, Tree,
+};
 
 /// A Grain is an element of the sieve. It is either a hit or a cluster.
 #[derive(Clone, Copy, Debug)]
@@ -24,7 +27,7 @@ enum Grain<'a, U: Number, C: Cluster<U>> {
         d: U,
         /// The diameter of the cluster.
         diameter: U,
-        /// The number of instances in the cluster.
+        /// The number of instances in the cluster which have not been removed.
         multiplicity: usize,
         /// Whether the cluster is a leaf.
         is_leaf: bool,
@@ -32,14 +35,15 @@ enum Grain<'a, U: Number, C: Cluster<U>> {
 }
 
 impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
-    /// Creates a new `Grain` from a cluster.
-    fn new_cluster(c: &'a C, d: U) -> Self {
+    /// Creates a new `Grain` from a cluster with `multiplicity` instances
+    /// which have not been removed.
+    fn new_cluster(c: &'a C, d: U, multiplicity: usize) -> Self {
         let r = c.radius();
         Self::Cluster {
             c,
             d: d + r,
             diameter: r + r,
-            multiplicity: c.cardinality(),
+            multiplicity,
             is_leaf: c.is_leaf(),
         }
     }
@@ -55,7 +59,13 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
     fn d_min(&self) -> U {
         match self {
             Grain::Hit { d, .. } => *d,
-            Grain::Cluster { d, diameter, .. } => *d - *diameter,
+            Grain::Cluster { d, diameter, .. } => {
+                if *d > *diameter {
+                    *d - *diameter
+                } else {
+                    U::zero()
+                }
+            }
         }
     }
 
@@ -68,13 +78,17 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
         }
     }
 
-    /// Returns whether the `Grain` has k or fewer instances or is a leaf.
-    const fn is_small(&self, k: usize) -> bool {
+    /// Returns whether the `Grain` is a leaf, or has k or fewer instances and
+    /// a local fractal dimension no greater than `lfd_threshold`.
+    fn is_small(&self, k: usize, lfd_threshold: f64) -> bool {
         match self {
             Grain::Hit { .. } => true,
             Grain::Cluster {
-                multiplicity, is_leaf, ..
-            } => *multiplicity <= k || *is_leaf,
+                c,
+                multiplicity,
+                is_leaf,
+                ..
+            } => *is_leaf || (*multiplicity <= k && c.lfd() <= lfd_threshold),
         }
     }
 
@@ -84,14 +98,31 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
     }
 
     /// Returns the indices of the instances in the cluster if the `Grain` is of
-    /// the `Cluster` variant
-    fn cluster_to_hits<I: Instance, D: Dataset<I, U>>(self, data: &D, query: &I) -> Vec<Self> {
+    /// the `Cluster` variant.
+    ///
+    /// Instances which a lower bound on the metric shows to be outside the
+    /// `threshold` are skipped. The instances of a singleton are all at the
+    /// distance to its center, so at most `k` of them are returned without
+    /// computing any more distances.
+    fn cluster_to_hits<I: Instance, D: Dataset<I, U>, P: Probe>(
+        self,
+        tree: &Tree<I, U, D, C>,
+        query: &I,
+        k: usize,
+        threshold: U,
+        probe: &P,
+    ) -> Vec<Self> {
         match self {
             Grain::Hit { .. } => unreachable!("This is only called on non-hits."),
+            Grain::Cluster { c, d, .. } if c.is_singleton() => {
+                probe.on_leaf_scanned(c);
+                tree.live_indices(c).take(k).map(|i| Grain::new_hit(d, i)).collect()
+            }
             Grain::Cluster { c, .. } => {
-                let distances = data.query_to_many(query, &c.indices().collect::<Vec<_>>());
-                c.indices()
-                    .zip(distances)
+                probe.on_leaf_scanned(c);
+                probe
+                    .nearest_within(tree.data(), query, tree.live_indices(c).collect(), k, Some(threshold))
+                    .into_iter()
                     .map(|(index, d)| Grain::new_hit(d, index))
                     .collect::<Vec<_>>()
             }
@@ -99,7 +130,7 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
     }
 
     /// Returns the children of the cluster if the `Grain` is of the `Cluster`
-    fn cluster_to_children(self) -> [&'a C; 2] {
+    fn cluster_to_children(self) -> Vec<&'a C> {
         match self {
             Grain::Hit { .. } => unreachable!("This is only called on non-hits."),
             Grain::Cluster { c, .. } => c
@@ -140,10 +171,10 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
                 Ordering::Equal => p,
                 Ordering::Less => Self::_partition(grains, k, p + 1, r),
                 Ordering::Greater => {
-                    if (p > 0) && (g > (k + grains[p - 1].multiplicity())) {
+                    // The grains before `p` are not sorted, so the threshold may
+                    // only move left of `p` if they alone hold `k` hits.
+                    if (p > l) && (g - grains[p].multiplicity() >= k) {
                         Self::_partition(grains, k, l, p - 1)
-                    } else if (p > 0) && (g == k + grains[p - 1].multiplicity()) {
-                        p - 1
                     } else {
                         p
                     }
@@ -204,24 +235,34 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
 /// * `tree` - The tree to search.
 /// * `query` - The query to search around.
 /// * `k` - The number of neighbors to search for.
+/// * `lfd_threshold` - The local fractal dimension above which a `Cluster`
+///   with at most `k` instances is partitioned instead of scanned.
+/// * `probe` - Receives the events of the search.
 ///
 /// # Returns
 ///
 /// A vector of 2-tuples, where the first element is an index of an instance,
 /// and the second element is the distance from the query to the instance.
 #[allow(clippy::many_single_char_names)]
-pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, k: usize) -> Vec<(usize, U)>
+pub fn search<I, U, D, C, P>(
+    tree: &Tree<I, U, D, C>,
+    query: &I,
+    k: usize,
+    lfd_threshold: f64,
+    probe: &P,
+) -> Vec<(usize, U)>
 where
     I: Instance,
     U: Number,
     D: Dataset<I, U>,
     C: Cluster<U>,
+    P: Probe,
 {
     let data = tree.data();
     let c = &tree.root;
-    let d = c.distance_to_instance(data, query);
+    let d = probe.distance_to_center(c, data, query);
 
-    let mut grains = vec![Grain::new_cluster(c, d)];
+    let mut grains = vec![Grain::new_cluster(c, d, tree.num_live_in(c))];
     let [mut insiders, mut non_insiders]: [Vec<_>; 2];
 
     loop {
@@ -242,11 +283,13 @@ where
             .partition::<Vec<_>, _>(|g| matches!(g, Grain::Cluster { .. }));
 
         // Separate small (cardinality less than k or leaf) clusters from the rest
-        let (small_clusters, clusters) = clusters.into_iter().partition::<Vec<_>, _>(|g| g.is_small(k));
+        let (small_clusters, clusters) = clusters
+            .into_iter()
+            .partition::<Vec<_>, _>(|g| g.is_small(k, lfd_threshold));
 
         // Convert small clusters to hits.
         for cluster in small_clusters {
-            hits.append(&mut cluster.cluster_to_hits(data, query));
+            hits.append(&mut cluster.cluster_to_hits(tree, query, k, threshold, probe));
         }
 
         // If there are no more cluster grains, then the search is complete.
@@ -259,12 +302,14 @@ where
             return hits[..l].iter().map(|g| (g.index(), g.d())).collect();
         }
 
-        // Partition clusters into children and convert to grains.
+        // Partition clusters into children and convert to grains, skipping
+        // those whose instances have all been removed.
         grains = clusters
             .into_iter()
             .flat_map(Grain::cluster_to_children)
-            .map(|c| (c, c.distance_to_instance(data, query)))
-            .map(|(c, d)| Grain::new_cluster(c, d))
+            .map(|c| (c, tree.num_live_in(c)))
+            .filter(|&(_, multiplicity)| multiplicity > 0)
+            .map(|(c, multiplicity)| Grain::new_cluster(c, probe.distance_to_center(c, data, query), multiplicity))
             .chain(hits)
             .collect();
     }
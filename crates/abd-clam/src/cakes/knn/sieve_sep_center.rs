@@ -5,7 +5,10 @@ use core::cmp::{min, Ordering};
 
 use distances::Number;
 
-use crate::{Cluster, Dataset, Instance, Tree};
+use crate::{
+    cakes::probe::{Probe, ProbeExt},
+    Cluster, Dataset, Instance, Tree,
+};
 
 /// A Grain is an element of the sieve. It is either a hit or a cluster.
 #[derive(Debug)]
@@ -26,7 +29,8 @@ enum Grain<'a, U: Number, C: Cluster<U>> {
         d_max: U,
         /// Theoretical best case distance from the query to a point in the cluster.
         d_min: U,
-        /// The number of instances in the cluster minus 1 for the center.
+        /// The number of instances in the cluster which have not been removed,
+        /// minus 1 for the center if it has not been removed.
         multiplicity: usize,
         /// Whether the cluster is a leaf.
         is_leaf: bool,
@@ -40,14 +44,15 @@ enum Grain<'a, U: Number, C: Cluster<U>> {
 }
 
 impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
-    /// Creates a new `Grain` from a cluster.
-    fn new_cluster(c: &'a C, d: U) -> Self {
+    /// Creates a new `Grain` from a cluster with `multiplicity` instances,
+    /// besides its center, which have not been removed.
+    fn new_cluster(c: &'a C, d: U, multiplicity: usize) -> Self {
         let r = c.radius();
         Self::Cluster {
             c,
             d_max: d + r,
             d_min: if d > r { d - r } else { U::zero() },
-            multiplicity: c.cardinality() - 1,
+            multiplicity,
             is_leaf: c.is_leaf(),
         }
     }
@@ -63,16 +68,38 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
     }
 
     /// Creates center and cluster grains from a cluster.
-    fn new_grains<I: Instance, D: Dataset<I, U>>(c: &'a C, data: &D, query: &I) -> Vec<Self> {
-        if c.is_singleton() {
-            let d = c.distance_to_instance(data, query);
-            c.indices().map(|i| Self::new_hit(d, i)).collect()
+    ///
+    /// A cluster whose instances have all been removed yields no grains, and
+    /// the center yields a grain only if it has not been removed.
+    fn new_grains<I: Instance, D: Dataset<I, U>, P: Probe>(
+        c: &'a C,
+        tree: &Tree<I, U, D, C>,
+        query: &I,
+        k: usize,
+        probe: &P,
+    ) -> Vec<Self> {
+        let data = tree.data();
+        let live = tree.num_live_in(c);
+        if live == 0 {
+            Vec::new()
+        } else if c.is_singleton() {
+            let d = probe.distance_to_center(c, data, query);
+            probe.on_leaf_scanned(c);
+            tree.live_indices(c).take(k).map(|i| Self::new_hit(d, i)).collect()
         } else if c.is_leaf() {
-            let distances = data.query_to_many(query, &c.indices().collect::<Vec<_>>());
-            c.indices().zip(distances).map(|(i, d)| Self::new_hit(d, i)).collect()
+            probe.on_leaf_scanned(c);
+            probe
+                .nearest_within(data, query, tree.live_indices(c).collect(), k, None)
+                .into_iter()
+                .map(|(i, d)| Self::new_hit(d, i))
+                .collect()
         } else {
-            let d = c.distance_to_instance(data, query);
-            vec![Self::new_cluster(c, d), Self::new_center(d)]
+            let d = probe.distance_to_center(c, data, query);
+            if tree.is_removed(c.arg_center()) {
+                vec![Self::new_cluster(c, d, live)]
+            } else {
+                vec![Self::new_cluster(c, d, live - 1), Self::new_center(d)]
+            }
         }
     }
 
@@ -94,13 +121,17 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
         }
     }
 
-    /// Returns whether the `Grain` has k or fewer instances or is a leaf.
-    const fn is_small(&self, k: usize) -> bool {
+    /// Returns whether the `Grain` is a leaf, or has k or fewer instances and
+    /// a local fractal dimension no greater than `lfd_threshold`.
+    fn is_small(&self, k: usize, lfd_threshold: f64) -> bool {
         match self {
             Grain::Hit { .. } | Grain::Center { .. } => true,
             Grain::Cluster {
-                multiplicity, is_leaf, ..
-            } => *multiplicity <= k || *is_leaf,
+                c,
+                multiplicity,
+                is_leaf,
+                ..
+            } => *is_leaf || (*multiplicity <= k && c.lfd() <= lfd_threshold),
         }
     }
 
@@ -111,26 +142,29 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
 
     /// Returns the indices of the instances in the cluster if the `Grain` is of
     /// the `Cluster` variant
-    fn cluster_to_hits<I: Instance, D: Dataset<I, U>>(self, data: &D, query: &I) -> Vec<Self> {
+    ///
+    /// Instances which a lower bound on the metric shows to be outside the
+    /// `threshold` are skipped.
+    fn cluster_to_hits<I: Instance, D: Dataset<I, U>, P: Probe>(
+        self,
+        tree: &Tree<I, U, D, C>,
+        query: &I,
+        k: usize,
+        threshold: U,
+        probe: &P,
+    ) -> Vec<Self> {
         match self {
             Grain::Hit { .. } | Grain::Center { .. } => unreachable!("This is only called on Clusters."),
-            Grain::Cluster { c, d_max, .. } => {
-                if c.is_singleton() {
-                    let d = d_max - c.radius();
-                    c.indices().map(|index| Grain::new_hit(d, index)).collect()
-                } else {
-                    let distances = data.query_to_many(query, &c.indices().collect::<Vec<_>>());
-                    c.indices()
-                        .zip(distances)
-                        .map(|(index, d)| Grain::new_hit(d, index))
-                        .collect()
-                }
-            }
+            Grain::Cluster { c, d_max, .. } => probe
+                .distances_to_leaf_within(c, d_max - c.radius(), tree, query, k, Some(threshold))
+                .into_iter()
+                .map(|(index, d)| Grain::new_hit(d, index))
+                .collect(),
         }
     }
 
     /// Returns the children of the cluster if the `Grain` is of the `Cluster`
-    fn cluster_to_children(self) -> [&'a C; 2] {
+    fn cluster_to_children(self) -> Vec<&'a C> {
         match self {
             Grain::Hit { .. } | Grain::Center { .. } => unreachable!("This is only called on Clusters."),
             Grain::Cluster { c, .. } => c
@@ -173,12 +207,10 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
                 Ordering::Equal => p,
                 Ordering::Less => Self::_partition(grains, k, p + 1, r),
                 Ordering::Greater => {
-                    if p > l {
-                        match g.cmp(&(k + grains[p - 1].multiplicity())) {
-                            Ordering::Equal => p - 1,
-                            Ordering::Less => p,
-                            Ordering::Greater => Self::_partition(grains, k, l, p - 1),
-                        }
+                    // The grains before `p` are not sorted, so the threshold may
+                    // only move left of `p` if they alone hold `k` hits.
+                    if (p > l) && (g - grains[p].multiplicity() >= k) {
+                        Self::_partition(grains, k, l, p - 1)
                     } else {
                         p
                     }
@@ -239,20 +271,29 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
 /// * `tree` - The tree to search.
 /// * `query` - The query to search around.
 /// * `k` - The number of neighbors to search for.
+/// * `lfd_threshold` - The local fractal dimension above which a `Cluster`
+///   with at most `k` instances is partitioned instead of scanned.
+/// * `probe` - Receives the events of the search.
 ///
 /// # Returns
 ///
 /// A vector of 2-tuples, where the first element is the index of the instance
 /// and the second element is the distance from the query to the instance.
-pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, k: usize) -> Vec<(usize, U)>
+pub fn search<I, U, D, C, P>(
+    tree: &Tree<I, U, D, C>,
+    query: &I,
+    k: usize,
+    lfd_threshold: f64,
+    probe: &P,
+) -> Vec<(usize, U)>
 where
     I: Instance,
     U: Number,
     D: Dataset<I, U>,
     C: Cluster<U>,
+    P: Probe,
 {
-    let data = tree.data();
-    let mut grains = Grain::new_grains(&tree.root, data, query);
+    let mut grains = Grain::new_grains(&tree.root, tree, query, k, probe);
     let [mut insiders, mut non_insiders]: [Vec<_>; 2];
 
     loop {
@@ -274,11 +315,13 @@ where
             .partition::<Vec<_>, _>(|g| matches!(g, Grain::Cluster { .. }));
 
         // Separate small (cardinality <=k or leaf) clusters from the rest.
-        let (small_clusters, clusters) = clusters.into_iter().partition::<Vec<_>, _>(|g| g.is_small(k));
+        let (small_clusters, clusters) = clusters
+            .into_iter()
+            .partition::<Vec<_>, _>(|g| g.is_small(k, lfd_threshold));
 
         // Convert small clusters to hits.
         for cluster in small_clusters {
-            hits.append(&mut cluster.cluster_to_hits(data, query));
+            hits.append(&mut cluster.cluster_to_hits(tree, query, k, threshold, probe));
         }
 
         // If there are no more cluster grains, then the search is complete.
@@ -295,7 +338,7 @@ where
         grains = clusters
             .into_iter()
             .flat_map(Grain::cluster_to_children)
-            .flat_map(|c| Grain::new_grains(c, data, query))
+            .flat_map(|c| Grain::new_grains(c, tree, query, k, probe))
             .chain(hits)
             .collect();
     }
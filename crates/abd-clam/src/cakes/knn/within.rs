@@ -0,0 +1,94 @@
+//! Search for at most `k` nearest neighbors within a radius of a query.
+
+use distances::Number;
+
+use crate::{
+    cakes::probe::{Probe, ProbeExt},
+    Cluster, Dataset, Instance, Tree,
+};
+
+use super::{greedy_sieve::d_min, Hits};
+
+/// Searches for at most `k` nearest neighbors of a query, none of which are
+/// farther from it than `radius`.
+///
+/// The traversal is that of `DepthFirstSieve`, but a `Cluster` is pruned if
+/// its `d_min` exceeds the smaller of `radius` and the distance to the
+/// current `k`-th nearest hit. Before `k` hits are found, the `radius` prunes
+/// as an RNN search would, and after, the hits prune as a KNN search would.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search.
+/// * `query` - The query to search around.
+/// * `k` - The largest number of neighbors to return.
+/// * `radius` - The largest distance from the query to a neighbor.
+/// * `probe` - Receives the events of the search.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is the distance from the query to the instance.
+/// Instances which have been removed from the `tree` are never returned.
+pub fn search<I, U, D, C, P>(tree: &Tree<I, U, D, C>, query: &I, k: usize, radius: U, probe: &P) -> Vec<(usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+    P: Probe,
+{
+    let mut hits = Hits::new(k);
+    if k == 0 {
+        return hits.extract();
+    }
+
+    let threshold = |hits: &Hits<usize, U>| {
+        if hits.len() == k && hits.peek() < radius {
+            hits.peek()
+        } else {
+            radius
+        }
+    };
+
+    let (data, root) = (tree.data(), &tree.root);
+
+    // Without the triangle inequality, nothing can be pruned.
+    if !data.is_metric() {
+        let indices = (0..tree.cardinality())
+            .filter(|&i| !tree.is_removed(i))
+            .collect::<Vec<_>>();
+        let distances = probe.distances_to(data, query, &indices);
+        hits.push_batch(indices.into_iter().zip(distances).filter(|&(_, d)| d <= radius));
+        return hits.extract();
+    }
+
+    let d = probe.distance_to_center(root, data, query);
+    let mut stack = vec![(root, d, d_min(root, d))];
+
+    while let Some((c, d, bound)) = stack.pop() {
+        if bound > threshold(&hits) {
+            continue;
+        }
+
+        if let Some(children) = c.children() {
+            let mut children = children
+                .into_iter()
+                .filter(|&c| tree.num_live_in(c) > 0)
+                .map(|c| {
+                    let d = probe.distance_to_center(c, data, query);
+                    (c, d, d_min(c, d))
+                })
+                .filter(|&(_, _, bound)| bound <= radius)
+                .collect::<Vec<_>>();
+            children.sort_by(|(_, _, a), (_, _, b)| a.partial_cmp(b).unwrap_or(core::cmp::Ordering::Less));
+            // The closer children are pushed last so that they are visited first.
+            stack.extend(children.into_iter().rev());
+        } else {
+            let distances = probe.distances_to_leaf(c, d, tree, query);
+            hits.push_batch(distances.into_iter().filter(|&(_, d)| d <= radius));
+        }
+    }
+
+    hits.extract()
+}
@@ -0,0 +1,176 @@
+//! Near-real-time indexing, with searches against immutable snapshots.
+
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock};
+
+use distances::Number;
+
+use crate::{cakes::knn, cakes::rnn, Dataset, Instance, PartitionCriterion, Tree, UniBall, VecDataset};
+
+/// The type of the `Tree` in each snapshot.
+type Snapshot<I, U, M> = Tree<I, U, VecDataset<I, U, M>, UniBall<U>>;
+
+/// An index which takes new instances while it is being searched.
+///
+/// Searches run against an immutable snapshot of the `Tree`. New instances
+/// are staged with `insert`, and `merge` builds a new snapshot which includes
+/// them, on a copy of the current snapshot, and then swaps it in. Searches
+/// which started before the swap finish against the old snapshot, and those
+/// which start after it see the new instances. Readers only ever wait for the
+/// swap itself, never for the building of a snapshot.
+///
+/// Inserting an instance may reorder the instances in the `Tree`, so the
+/// searches of a `LiveCakes` return the original indices of the hits, which
+/// do not change between snapshots. The original index of an instance is the
+/// number of instances inserted before it, including those in the first
+/// snapshot.
+///
+/// # Type Parameters
+///
+/// - `I`: The type of the instances.
+/// - `U`: The type of the distance values between instances.
+/// - `M`: The type of the metadata of the instances.
+/// - `P`: The type of the criteria used to partition leaves which grow when
+///   instances are inserted.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct LiveCakes<I: Instance, U: Number, M: Instance, P: PartitionCriterion<U>> {
+    /// The snapshot which new searches run against.
+    current: RwLock<Arc<Snapshot<I, U, M>>>,
+    /// The instances staged for the next snapshot, with their metadata.
+    pending: Mutex<Vec<(I, M)>>,
+    /// Held while building a new snapshot, so that only one is built at a
+    /// time.
+    writer: Mutex<()>,
+    /// The criteria used to partition leaves when instances are inserted.
+    criteria: P,
+    /// The seed used to partition leaves when instances are inserted.
+    seed: Option<u64>,
+}
+
+impl<I: Instance, U: Number, M: Instance, P: PartitionCriterion<U>> LiveCakes<I, U, M, P> {
+    /// Creates a new `LiveCakes` whose first snapshot is the given `Tree`.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree` - The first snapshot.
+    /// * `criteria` - The criteria used to partition leaves when instances are
+    ///   inserted.
+    /// * `seed` - The seed used to partition leaves when instances are
+    ///   inserted.
+    pub fn new(tree: Snapshot<I, U, M>, criteria: P, seed: Option<u64>) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(tree)),
+            pending: Mutex::new(Vec::new()),
+            writer: Mutex::new(()),
+            criteria,
+            seed,
+        }
+    }
+
+    /// Returns the current snapshot.
+    ///
+    /// The snapshot is not changed by later merges, so several searches which
+    /// must agree with each other may be run against it.
+    pub fn snapshot(&self) -> Arc<Snapshot<I, U, M>> {
+        Arc::clone(&self.current.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Stages an instance for the next snapshot.
+    ///
+    /// The instance is not searchable until the next `merge`.
+    ///
+    /// # Arguments
+    ///
+    /// * `instance` - The instance to insert.
+    /// * `metadata` - The metadata of the instance.
+    pub fn insert(&self, instance: I, metadata: M) {
+        self.lock_pending().push((instance, metadata));
+    }
+
+    /// Returns the number of instances staged for the next snapshot.
+    pub fn num_pending(&self) -> usize {
+        self.lock_pending().len()
+    }
+
+    /// Builds a new snapshot which includes all staged instances, and swaps it
+    /// in for the current snapshot.
+    ///
+    /// Instances staged while the snapshot is being built are left for the
+    /// next merge.
+    ///
+    /// # Returns
+    ///
+    /// The number of instances added to the new snapshot.
+    ///
+    /// # Errors
+    ///
+    /// * If any of the staged instances could not be inserted into the `Tree`.
+    ///   The current snapshot is kept, and the instances stay staged.
+    pub fn merge(&self) -> Result<usize, String> {
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let staged = core::mem::take(&mut *self.lock_pending());
+        if staged.is_empty() {
+            return Ok(0);
+        }
+        let num_staged = staged.len();
+
+        let mut tree = Snapshot::clone(&self.snapshot());
+        if let Err(e) = tree.insert_batch(staged.clone(), &self.criteria, self.seed) {
+            // The staged instances go back ahead of any staged since.
+            self.lock_pending().splice(0..0, staged);
+            return Err(e);
+        }
+
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(tree);
+        Ok(num_staged)
+    }
+
+    /// Performs a KNN search against the current snapshot.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the original index of the instance and
+    /// the distance to the query.
+    pub fn knn_search(&self, query: &I, k: usize, algo: knn::Algorithm) -> Vec<(usize, U)> {
+        let tree = self.snapshot();
+        let hits = algo.search(&tree, query, k);
+        Self::original_indices(&tree, hits)
+    }
+
+    /// Performs an RNN search against the current snapshot.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `radius` - The search radius.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the original index of the instance and
+    /// the distance to the query.
+    pub fn rnn_search(&self, query: &I, radius: U, algo: rnn::Algorithm) -> Vec<(usize, U)> {
+        let tree = self.snapshot();
+        let hits = algo.search(query, radius, &tree);
+        Self::original_indices(&tree, hits)
+    }
+
+    /// Replaces the indices of hits in a snapshot with their original indices.
+    fn original_indices(tree: &Snapshot<I, U, M>, hits: Vec<(usize, U)>) -> Vec<(usize, U)> {
+        hits.into_iter()
+            .map(|(i, d)| (tree.data().original_index(i), d))
+            .collect()
+    }
+
+    /// Locks the staged instances.
+    fn lock_pending(&self) -> MutexGuard<'_, Vec<(I, M)>> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,97 @@
+//! Maximum Inner Product Search.
+//!
+//! The inner product is not a metric, but a tree built with the Euclidean
+//! distance still bounds it. For a `Cluster` with center `c` and radius `r`,
+//! and any instance `x` in it, the Cauchy-Schwarz inequality gives
+//! `<q, x> = <q, c> + <q, x - c> <= <q, c> + |q| r`. Clusters are expanded in
+//! decreasing order of this bound, and search stops once the `k`-th best
+//! inner product found is at least the bound of every remaining cluster.
+//!
+//! The bound only holds if the radii are Euclidean distances, so the tree must
+//! have been built with the Euclidean distance. If the metric of the tree is
+//! declared not to obey the triangle inequality, the radii are not trusted and
+//! every leaf is scanned.
+
+use distances::{number::Float, Number};
+use priority_queue::PriorityQueue;
+
+use crate::{Cluster, Dataset, Tree};
+
+use super::knn::{OrdNumber, RevNumber};
+
+/// Searches for the instances with the largest inner products with a query.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search, built with the Euclidean distance.
+/// * `query` - The query to search around.
+/// * `k` - The number of instances to search for.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is its inner product with the query, sorted by
+/// decreasing inner product. Instances which have been removed from the `tree`
+/// are never returned.
+pub fn search<T, U, D, C>(tree: &Tree<Vec<T>, U, D, C>, query: &[T], k: usize) -> Vec<(usize, U)>
+where
+    T: Number,
+    U: Float,
+    D: Dataset<Vec<T>, U>,
+    C: Cluster<U>,
+{
+    // The highest bound is at the top of `candidates` and the lowest inner
+    // product is at the top of `hits`.
+    let mut candidates = PriorityQueue::<&C, OrdNumber<U>>::new();
+    let mut hits = PriorityQueue::<usize, RevNumber<U>>::new();
+    if k == 0 {
+        return Vec::new();
+    }
+
+    let (data, root) = (tree.data(), &tree.root);
+    let norm = inner_product::<T, U>(query, query).sqrt();
+    let is_metric = data.is_metric();
+    let bound = |c: &C| {
+        if is_metric {
+            inner_product::<T, U>(query, &data.get(c.arg_center())) + norm * c.radius()
+        } else {
+            U::from(f64::INFINITY)
+        }
+    };
+    candidates.push(root, OrdNumber(bound(root)));
+
+    while let Some((c, OrdNumber(b))) = candidates.pop() {
+        if hits.len() == k && hits.peek().is_some_and(|(_, &RevNumber(worst))| worst >= b) {
+            break;
+        }
+
+        if let Some(children) = c.children() {
+            for child in children {
+                candidates.push(child, OrdNumber(bound(child)));
+            }
+        } else {
+            for i in c.indices().filter(|&i| !tree.is_removed(i)) {
+                hits.push(i, RevNumber(inner_product::<T, U>(query, &data.get(i))));
+                if hits.len() > k {
+                    hits.pop();
+                }
+            }
+        }
+    }
+
+    let mut hits = hits.into_iter().map(|(i, RevNumber(p))| (i, p)).collect::<Vec<_>>();
+    sort_descending(&mut hits);
+    hits
+}
+
+/// Sorts hits by decreasing inner product.
+pub(crate) fn sort_descending<U: Number>(hits: &mut [(usize, U)]) {
+    hits.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(core::cmp::Ordering::Equal));
+}
+
+/// The inner product of two vectors.
+fn inner_product<T: Number, U: Float>(x: &[T], y: &[T]) -> U {
+    x.iter()
+        .zip(y)
+        .fold(U::zero(), |acc, (&a, &b)| U::from(a).mul_add(U::from(b), acc))
+}
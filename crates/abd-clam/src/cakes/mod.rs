@@ -2,23 +2,69 @@
 
 use core::ops::Index;
 
-use std::path::Path;
+use std::{borrow::Cow, path::Path};
 
+pub mod asymmetric;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+pub mod hybrid;
+mod ids;
+mod index;
 pub mod knn;
+mod live;
+pub mod mips;
+mod multi_shard;
+#[cfg(feature = "tokio")]
+mod nonblocking;
+mod payload;
+pub mod planner;
+pub mod preprocess;
+#[cfg(feature = "profiling")]
+pub mod probe;
+#[cfg(not(feature = "profiling"))]
+mod probe;
 pub mod rnn;
 mod search;
 mod sharded;
+mod shared;
 mod singular;
+pub mod tiled;
+#[cfg(feature = "verify")]
+mod verify;
 
-use distances::Number;
-use rayon::prelude::*;
+use distances::{number::Float, Number};
+pub use ids::IdCakes;
+use index::Manifest;
+pub use live::LiveCakes;
+pub use multi_shard::ShardedCakes;
+pub use payload::PayloadCakes;
+use preprocess::{Preprocess, Preprocessed};
+pub use probe::SearchStats;
 use search::Search;
 use sharded::RandomlySharded;
+pub use shared::SharedCakes;
 use singular::SingleShard;
 
-use crate::{Dataset, Instance, PartitionCriterion, Tree, UniBall};
+use crate::par::prelude::*;
+#[cfg(feature = "parallel")]
+use crate::par::ThreadPool;
+use crate::{
+    CancellationToken, Cluster, Dataset, Error, Instance, PartitionCriterion, QuantizedDataset, Tree, UniBall,
+    VecDataset,
+};
 
 /// CAKES search.
+///
+/// # Concurrency
+///
+/// `Cakes` is `Send` and `Sync`, and every search takes `&self`, so any number
+/// of threads may search one `Cakes` at once. Searches hold no locks and
+/// mutate nothing, so they never wait on each other. The one exception is
+/// `LeafStore`, which keeps a cache of the blocks it has read behind a lock.
+///
+/// To serve searches from many threads, tune the `Cakes` and then share it
+/// with `into_shared`.
+#[derive(Debug)]
 pub enum Cakes<I: Instance, U: Number, D: Dataset<I, U>> {
     /// Search with a single shard.
     SingleShard(SingleShard<I, U, D>),
@@ -34,25 +80,80 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     /// * `data` - The dataset to search.
     /// * `seed` - The seed to use for the random number generator.
     /// * `criteria` - The criteria to use for partitioning the tree.
+    ///
+    /// # Panics
+    ///
+    /// * If the dataset is empty. Use `try_new` to get an error instead.
     pub fn new<P: PartitionCriterion<U>>(data: D, seed: Option<u64>, criteria: &P) -> Self {
         Self::SingleShard(SingleShard::new(data, seed, criteria))
     }
 
+    /// Creates a new CAKES instance with a single shard dataset, or returns an
+    /// error instead of panicking.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The dataset to search.
+    /// * `seed` - The seed to use for the random number generator.
+    /// * `criteria` - The criteria to use for partitioning the tree.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::EmptyDataset` if the dataset has no instances.
+    /// * `Error::Cancelled` if the token of the `criteria` was cancelled
+    ///   before the tree was built.
+    pub fn try_new<P: PartitionCriterion<U>>(data: D, seed: Option<u64>, criteria: &P) -> Result<Self, Error> {
+        if data.cardinality() == 0 {
+            return Err(Error::EmptyDataset);
+        }
+        let cakes = Self::new(data, seed, criteria);
+        if criteria.cancellation().is_some_and(CancellationToken::is_cancelled) {
+            return Err(Error::Cancelled);
+        }
+        Ok(cakes)
+    }
+
+    /// Shares the `Cakes` between threads, e.g. those of a server.
+    #[must_use]
+    pub fn into_shared(self) -> SharedCakes<I, U, D> {
+        SharedCakes::new(self)
+    }
+
     /// Saves the Cakes structure to the given path.
     ///
+    /// The trees and datasets are written into the directory at `path`, along
+    /// with a versioned manifest, `index.clam`. The manifest records the name
+    /// of the distance function, the type of the dataset, the cardinality of
+    /// each shard and a checksum of every file, so that `Cakes::load` can
+    /// refuse an index which does not match or has been corrupted.
+    ///
     /// # Arguments
     ///
     /// * `path` - The path to save the Cakes structure to.
+    /// * `metric_name` - The name of the distance function, which must be
+    ///   given again to `Cakes::load`.
     ///
     /// # Errors
     ///
     /// * If the `path` does not exist.
     /// * If the `path` is not a valid directory.
-    pub fn save(&self, path: &Path) -> Result<(), String> {
+    /// * If the manifest cannot be written.
+    ///
+    /// All of these are reported as `Error::Io`.
+    pub fn save(&self, path: &Path, metric_name: &str) -> Result<(), Error> {
         match self {
             Self::SingleShard(ss) => ss.save(path),
             Self::RandomlySharded(rs) => rs.save(path),
         }
+        .map_err(Error::Io)?;
+
+        Manifest {
+            metric: metric_name.to_string(),
+            dataset: D::type_name(),
+            cardinalities: self.shard_cardinalities(),
+        }
+        .write(path)
+        .map_err(Error::Io)
     }
 
     /// Loads the Cakes structure from the given path.
@@ -60,6 +161,8 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     /// # Arguments
     ///
     /// * `path` - The path to load the Cakes structure from.
+    /// * `metric_name` - The name of the distance function, as given to
+    ///   `Cakes::save`.
     /// * `metric` - The metric to use for the search.
     /// * `is_expensive` - Whether the metric is expensive to compute.
     ///
@@ -71,25 +174,46 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     ///
     /// * If the `path` does not exist.
     /// * If the `path` is not a valid directory.
+    /// * If the `path` has no manifest, or one with a different format
+    ///   version.
+    /// * If the index was saved with a different `metric_name` or type of
+    ///   dataset.
+    /// * If any file in the index is missing or does not match its checksum.
+    /// * If the shards do not have the cardinalities in the manifest.
     /// * If the `path` does not contain a valid Cakes structure.
-    pub fn load(path: &Path, metric: fn(&I, &I) -> U, is_expensive: bool) -> Result<Self, String> {
+    ///
+    /// A different `metric_name` or type of dataset is reported as
+    /// `Error::Other`, and the others as `Error::Io`.
+    pub fn load(path: &Path, metric_name: &str, metric: fn(&I, &I) -> U, is_expensive: bool) -> Result<Self, Error> {
         if !path.exists() {
-            return Err(format!("Path '{}' does not exist.", path.display()));
+            return Err(Error::Io(format!("Path '{}' does not exist.", path.display())));
         }
 
         if !path.is_dir() {
-            return Err(format!("Path '{}' is not a directory.", path.display()));
+            return Err(Error::Io(format!("Path '{}' is not a directory.", path.display())));
         }
 
+        let manifest = Manifest::read(path).map_err(Error::Io)?;
+        manifest.expect(metric_name, &D::type_name())?;
+
         // Check if there is a subdirectory for `sample_shard`.
         let sample_shard_path = path.join("sample_shard");
-        if sample_shard_path.exists() {
-            let rs = RandomlySharded::load(path, metric, is_expensive)?;
-            Ok(Self::RandomlySharded(rs))
+        let cakes = if sample_shard_path.exists() {
+            RandomlySharded::load(path, metric, is_expensive).map(Self::RandomlySharded)
         } else {
-            let ss = SingleShard::load(path, metric, is_expensive)?;
-            Ok(Self::SingleShard(ss))
+            SingleShard::load(path, metric, is_expensive).map(Self::SingleShard)
         }
+        .map_err(Error::Io)?;
+
+        let cardinalities = cakes.shard_cardinalities();
+        if cardinalities != manifest.cardinalities {
+            return Err(Error::Io(format!(
+                "Layout mismatch. The manifest lists shards of cardinalities {:?} but the index has {cardinalities:?}",
+                manifest.cardinalities
+            )));
+        }
+
+        Ok(cakes)
     }
 
     /// Returns the references to the tree(s) of the dataset.
@@ -100,6 +224,23 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
         }
     }
 
+    /// Returns a `Planner` with the statistics of the tree(s).
+    ///
+    /// With multiple shards, the statistics are those of the tree of the
+    /// largest shard, as the shards are random samples of the same data. The
+    /// statistics are computed once, when the tree(s) are built or loaded.
+    pub fn planner(&self) -> planner::Planner {
+        let shard = match self {
+            Self::SingleShard(ss) => ss,
+            Self::RandomlySharded(rs) => rs
+                .shards()
+                .into_iter()
+                .max_by_key(|s| s.data().cardinality())
+                .unwrap_or_else(|| unreachable!("There is always at least one shard")),
+        };
+        planner::Planner::new(*shard.stats())
+    }
+
     /// Returns the references to the shard(s) of the dataset.
     pub fn shards(&self) -> Vec<&D> {
         match self {
@@ -108,6 +249,35 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
         }
     }
 
+    /// Returns the instance at the given index, across all shards, as
+    /// returned by search.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - An index in the dataset.
+    pub fn get(&self, index: usize) -> Cow<'_, I> {
+        match self {
+            Self::SingleShard(ss) => ss.data().get(index),
+            Self::RandomlySharded(rs) => {
+                let (i, index) = rs.locate(index);
+                rs.shards()[i].data().get(index)
+            }
+        }
+    }
+
+    /// Checks that a query has the dimensionality of the instances, if both
+    /// have one.
+    fn check_dim(&self, query: &I) -> Result<(), Error> {
+        let expected = match self {
+            Self::SingleShard(ss) => ss.dim(),
+            Self::RandomlySharded(rs) => rs.shards()[0].dim(),
+        };
+        match (expected, query.dim()) {
+            (Some(expected), Some(found)) if expected != found => Err(Error::DimensionMismatch { expected, found }),
+            _ => Ok(()),
+        }
+    }
+
     /// Creates a new CAKES instance with a randomly sharded dataset.
     ///
     /// # Arguments
@@ -115,6 +285,11 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     /// * `shards` - The shards of the dataset to search.
     /// * `seed` - The seed to use for the random number generator.
     /// * `criteria` - The criteria to use for partitioning the tree.
+    ///
+    /// # Panics
+    ///
+    /// * If there are no shards, or any shard is empty. Use
+    ///   `try_new_randomly_sharded` to get an error instead.
     #[must_use]
     pub fn new_randomly_sharded<P: PartitionCriterion<U>>(shards: Vec<D>, seed: Option<u64>, criteria: &P) -> Self {
         let shards = shards
@@ -124,6 +299,36 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
         Self::RandomlySharded(RandomlySharded::new(shards))
     }
 
+    /// Creates a new CAKES instance with a randomly sharded dataset, or
+    /// returns an error instead of panicking.
+    ///
+    /// # Arguments
+    ///
+    /// * `shards` - The shards of the dataset to search.
+    /// * `seed` - The seed to use for the random number generator.
+    /// * `criteria` - The criteria to use for partitioning the tree.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::EmptyDataset` if there are no shards, or any shard has no
+    ///   instances.
+    /// * `Error::Cancelled` if the token of the `criteria` was cancelled
+    ///   before the trees were built.
+    pub fn try_new_randomly_sharded<P: PartitionCriterion<U>>(
+        shards: Vec<D>,
+        seed: Option<u64>,
+        criteria: &P,
+    ) -> Result<Self, Error> {
+        if shards.is_empty() || shards.iter().any(|d| d.cardinality() == 0) {
+            return Err(Error::EmptyDataset);
+        }
+        let cakes = Self::new_randomly_sharded(shards, seed, criteria);
+        if criteria.cancellation().is_some_and(CancellationToken::is_cancelled) {
+            return Err(Error::Cancelled);
+        }
+        Ok(cakes)
+    }
+
     /// Returns the number of shards in the dataset.
     pub fn num_shards(&self) -> usize {
         match self {
@@ -145,6 +350,26 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
         self.shard_cardinalities().iter().sum()
     }
 
+    /// Returns the index of an instance before the dataset was reordered.
+    ///
+    /// Search results index into the reordered dataset. For sharded datasets,
+    /// the original index is that of the instance in the concatenation of the
+    /// shards, in the order in which they were given, before each was reordered.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index of an instance, as returned by a search.
+    pub fn original_index(&self, index: usize) -> usize {
+        match self {
+            Self::SingleShard(ss) => ss.data().original_index(index),
+            Self::RandomlySharded(rs) => {
+                let (i, local) = rs.locate(index);
+                let start = if i == 0 { 0 } else { rs.offsets()[i - 1] };
+                start + rs.shards()[i].data().original_index(local)
+            }
+        }
+    }
+
     /// Returns the tuned RNN algorithm.
     pub fn tuned_rnn_algorithm(&self) -> rnn::Algorithm {
         match self {
@@ -166,7 +391,68 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     /// A vector of vectors of tuples containing the index of the instance and
     /// the distance to the query.
     pub fn batch_rnn_search(&self, queries: &[&I], radius: U, algo: rnn::Algorithm) -> Vec<Vec<(usize, U)>> {
-        queries.par_iter().map(|q| self.rnn_search(q, radius, algo)).collect()
+        match self {
+            Self::SingleShard(ss) => ss.batch_rnn_search(queries, radius, algo),
+            Self::RandomlySharded(rs) => rs.batch_rnn_search(queries, radius, algo),
+        }
+    }
+
+    /// Performs RNN search on a batch of queries with the given algorithm,
+    /// stopping early if the `token` is cancelled.
+    ///
+    /// The `token` is checked before each query is searched.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The queries to search.
+    /// * `radius` - The search radius.
+    /// * `algo` - The algorithm to use.
+    /// * `token` - The token with which the search may be cancelled.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as returned by `batch_rnn_search`.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::Cancelled` if the `token` was cancelled before every query
+    ///   was searched.
+    pub fn batch_rnn_search_cancellable(
+        &self,
+        queries: &[&I],
+        radius: U,
+        algo: rnn::Algorithm,
+        token: &CancellationToken,
+    ) -> Result<Vec<Vec<(usize, U)>>, Error> {
+        queries
+            .par_iter()
+            .map(|q| (!token.is_cancelled()).then(|| self.rnn_search(q, radius, algo)))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(Error::Cancelled)
+    }
+
+    /// Performs RNN search on a batch of queries with the given algorithm, on
+    /// the given thread pool instead of the global pool of `rayon`.
+    ///
+    /// # Arguments
+    ///
+    /// * `pool` - The thread pool on which to search.
+    /// * `queries` - The queries to search.
+    /// * `radius` - The search radius.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as returned by `batch_rnn_search`.
+    #[cfg(feature = "parallel")]
+    pub fn batch_rnn_search_in(
+        &self,
+        pool: &ThreadPool,
+        queries: &[&I],
+        radius: U,
+        algo: rnn::Algorithm,
+    ) -> Vec<Vec<(usize, U)>> {
+        pool.install(|| self.batch_rnn_search(queries, radius, algo))
     }
 
     /// Performs an RNN search with the given algorithm.
@@ -188,6 +474,92 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
         }
     }
 
+    /// Performs an RNN search with the given algorithm, or returns an error
+    /// for a bad `query` or `radius`.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `radius` - The search radius.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as returned by `rnn_search`.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::DimensionMismatch` if the `query` has a different
+    ///   dimensionality than the instances. See `Instance::dim`.
+    /// * `Error::InvalidRadius` if the `radius` is negative or not a number.
+    pub fn try_rnn_search(&self, query: &I, radius: U, algo: rnn::Algorithm) -> Result<Vec<(usize, U)>, Error> {
+        self.check_dim(query)?;
+        if radius >= U::zero() {
+            Ok(self.rnn_search(query, radius, algo))
+        } else {
+            Err(Error::InvalidRadius(radius.to_string()))
+        }
+    }
+
+    /// Performs an RNN search with the given algorithm, returning references
+    /// to the instances instead of their indices.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `radius` - The search radius.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the instance and its distance to the query.
+    pub fn rnn_search_instances(&self, query: &I, radius: U, algo: rnn::Algorithm) -> Vec<(Cow<'_, I>, U)> {
+        self.rnn_search(query, radius, algo)
+            .into_iter()
+            .map(|(i, d)| (self.get(i), d))
+            .collect()
+    }
+
+    /// Counts the neighbors of a query within a radius with the given
+    /// algorithm, without collecting the hits.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `radius` - The search radius.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// The number of instances within the radius of the query.
+    pub fn rnn_count(&self, query: &I, radius: U, algo: rnn::Algorithm) -> usize {
+        match self {
+            Self::SingleShard(ss) => ss.rnn_count(query, radius, algo),
+            Self::RandomlySharded(rs) => rs.rnn_count(query, radius, algo),
+        }
+    }
+
+    /// Checks whether any instance lies within a radius of a query.
+    ///
+    /// The search terminates as soon as a single hit is found, making this
+    /// much cheaper than a full RNN search, e.g. for deduplication checks.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `radius` - The search radius.
+    ///
+    /// # Returns
+    ///
+    /// Whether there is an instance within the radius of the query.
+    pub fn exists_within(&self, query: &I, radius: U) -> bool {
+        let algo = rnn::Algorithm::Clustered;
+        match self {
+            Self::SingleShard(ss) => ss.rnn_exists(query, radius, algo),
+            Self::RandomlySharded(rs) => rs.rnn_exists(query, radius, algo),
+        }
+    }
+
     /// Performs Linear RNN search on a batch of queries.
     ///
     /// # Arguments
@@ -242,7 +614,68 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     /// A vector of vectors of tuples containing the index of the instance and
     /// the distance to the query.
     pub fn batch_knn_search(&self, queries: &[&I], k: usize, algo: knn::Algorithm) -> Vec<Vec<(usize, U)>> {
-        queries.par_iter().map(|q| self.knn_search(q, k, algo)).collect()
+        match self {
+            Self::SingleShard(ss) => ss.batch_knn_search(queries, k, algo),
+            Self::RandomlySharded(rs) => rs.batch_knn_search(queries, k, algo),
+        }
+    }
+
+    /// Performs KNN search on a batch of queries with the given algorithm,
+    /// stopping early if the `token` is cancelled.
+    ///
+    /// The `token` is checked before each query is searched.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The queries to search.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    /// * `token` - The token with which the search may be cancelled.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as returned by `batch_knn_search`.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::Cancelled` if the `token` was cancelled before every query
+    ///   was searched.
+    pub fn batch_knn_search_cancellable(
+        &self,
+        queries: &[&I],
+        k: usize,
+        algo: knn::Algorithm,
+        token: &CancellationToken,
+    ) -> Result<Vec<Vec<(usize, U)>>, Error> {
+        queries
+            .par_iter()
+            .map(|q| (!token.is_cancelled()).then(|| self.knn_search(q, k, algo)))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(Error::Cancelled)
+    }
+
+    /// Performs KNN search on a batch of queries with the given algorithm, on
+    /// the given thread pool instead of the global pool of `rayon`.
+    ///
+    /// # Arguments
+    ///
+    /// * `pool` - The thread pool on which to search.
+    /// * `queries` - The queries to search.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as returned by `batch_knn_search`.
+    #[cfg(feature = "parallel")]
+    pub fn batch_knn_search_in(
+        &self,
+        pool: &ThreadPool,
+        queries: &[&I],
+        k: usize,
+        algo: knn::Algorithm,
+    ) -> Vec<Vec<(usize, U)>> {
+        pool.install(|| self.batch_knn_search(queries, k, algo))
     }
 
     /// Performs a KNN search with the given algorithm.
@@ -263,6 +696,583 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
         }
     }
 
+    /// Performs a KNN search with the given algorithm, or returns an error for
+    /// a bad `query` or if there are not `k` instances to find.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as returned by `knn_search`.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::DimensionMismatch` if the `query` has a different
+    ///   dimensionality than the instances. See `Instance::dim`.
+    /// * `Error::KTooLarge` if `k` is greater than the total cardinality.
+    pub fn try_knn_search(&self, query: &I, k: usize, algo: knn::Algorithm) -> Result<Vec<(usize, U)>, Error> {
+        self.knn_search_with_policy(query, k, algo, knn::KPolicy::Error)
+    }
+
+    /// Performs a KNN search with the given algorithm, handling a `k` larger
+    /// than the total cardinality according to the `policy`.
+    ///
+    /// With `knn::KPolicy::Clamp`, this is the same as `knn_search`, which
+    /// returns every instance when `k` is at least the total cardinality.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    /// * `policy` - What to do if `k` is larger than the total cardinality.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as returned by `knn_search`.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::DimensionMismatch` if the `query` has a different
+    ///   dimensionality than the instances. See `Instance::dim`.
+    /// * `Error::KTooLarge` if the `policy` is `knn::KPolicy::Error` and `k`
+    ///   is greater than the total cardinality.
+    pub fn knn_search_with_policy(
+        &self,
+        query: &I,
+        k: usize,
+        algo: knn::Algorithm,
+        policy: knn::KPolicy,
+    ) -> Result<Vec<(usize, U)>, Error> {
+        self.check_dim(query)?;
+        policy.check(k, self.total_cardinality())?;
+        Ok(self.knn_search(query, k, algo))
+    }
+
+    /// Performs a KNN search with the given algorithm, and certifies whether
+    /// the hits are exact.
+    ///
+    /// Exact algorithms fall back to linear search over any shard whose metric
+    /// does not obey the triangle inequality, so only `Approximate` search
+    /// with a `recall` below `1.0` may be uncertified.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// The hits, along with whether they are the exact nearest neighbors.
+    pub fn knn_search_certified(&self, query: &I, k: usize, algo: knn::Algorithm) -> knn::CertifiedHits<U> {
+        knn::CertifiedHits {
+            hits: self.knn_search(query, k, algo),
+            is_exact: self.shards().into_iter().all(|data| algo.is_exact_for(data)),
+        }
+    }
+
+    /// Performs a KNN search with the given algorithm, resolving ties at the
+    /// distance of the `k`-th nearest neighbor with the given policy.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    /// * `ties` - How to choose among instances tied with the `k`-th nearest
+    ///   neighbor.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the index of the instance and the distance
+    /// to the query. Unless ties are broken arbitrarily, these are sorted by
+    /// distance and then by index.
+    pub fn knn_search_with_ties(
+        &self,
+        query: &I,
+        k: usize,
+        algo: knn::Algorithm,
+        ties: knn::TieBreaking,
+    ) -> Vec<(usize, U)> {
+        let hits = self.knn_search(query, k, algo);
+        let tree_radius = self
+            .trees()
+            .iter()
+            .map(|t| t.radius())
+            .fold(U::zero(), |r, t| if t > r { t } else { r });
+        ties.resolve(k, hits, tree_radius, |radius| {
+            self.rnn_search(query, radius, rnn::Algorithm::default())
+        })
+    }
+
+    /// Performs KNN search on a batch of queries with the given algorithm,
+    /// resolving ties with the given policy.
+    ///
+    /// See `knn_search_with_ties`.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The queries to search.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    /// * `ties` - How to choose among instances tied with the `k`-th nearest
+    ///   neighbor.
+    pub fn batch_knn_search_with_ties(
+        &self,
+        queries: &[&I],
+        k: usize,
+        algo: knn::Algorithm,
+        ties: knn::TieBreaking,
+    ) -> Vec<Vec<(usize, U)>> {
+        queries
+            .par_iter()
+            .map(|q| self.knn_search_with_ties(q, k, algo, ties))
+            .collect()
+    }
+
+    /// Performs a KNN search with a user-provided strategy.
+    ///
+    /// With multiple shards, the strategy searches each shard for `k`
+    /// neighbors and the results are merged.
+    ///
+    /// # Arguments
+    ///
+    /// * `algo` - The search strategy to use.
+    /// * `query` - The query instance.
+    /// * `k` - The number of nearest neighbors to return.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the index of the instance and the distance to the query.
+    pub fn knn_search_with<A: knn::KnnSearch<I, U, D>>(&self, algo: &A, query: &I, k: usize) -> Vec<(usize, U)> {
+        match self {
+            Self::SingleShard(ss) => algo.search(ss.tree(), query, k),
+            Self::RandomlySharded(rs) => {
+                let offsets = core::iter::once(0).chain(rs.offsets().iter().copied());
+                let hits = rs
+                    .shards()
+                    .into_par_iter()
+                    .zip(offsets.collect::<Vec<_>>())
+                    .flat_map(|(shard, o)| {
+                        algo.search(shard.tree(), query, k)
+                            .into_iter()
+                            .map(|(i, d)| (i + o, d))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect();
+                knn::Hits::from_vec(k, hits).extract()
+            }
+        }
+    }
+
+    /// Returns an iterator over the neighbors of a query, in increasing order
+    /// of distance.
+    ///
+    /// The tree(s) are traversed only as far as needed for the neighbors taken
+    /// from the iterator, so there is no need to choose `k` in advance. With
+    /// multiple shards, the traversals of all shards share one queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    pub fn neighbors<'a>(&'a self, query: &'a I) -> knn::NeighborsIter<'a, I, U, D, UniBall<U>> {
+        knn::NeighborsIter::from_trees(self.trees_with_offsets(), query)
+    }
+
+    /// Performs a KNN search which stops when it runs out of a budget of
+    /// distance computations or time, and returns the best hits found so far.
+    ///
+    /// With multiple shards, the trees of all shards are searched together,
+    /// always expanding the most promising `Cluster` among them, so that the
+    /// budget is spent where it is most likely to find neighbors.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `budget` - The most work the search may do.
+    ///
+    /// # Returns
+    ///
+    /// The hits, along with whether they are exact and, if not, a lower bound
+    /// on the distance to the instances which were not reached.
+    pub fn knn_search_anytime(&self, query: &I, k: usize, budget: knn::Budget) -> knn::AnytimeHits<U> {
+        knn::anytime::search(&self.trees_with_offsets(), query, k, budget)
+    }
+
+    /// Performs a KNN search whose hits start with those of a similar query,
+    /// e.g. the previous frame in a tracking application.
+    ///
+    /// The seed hits tighten the pruning threshold before any `Cluster` is
+    /// visited, so a sequence of similar queries is much cheaper to search
+    /// than the same queries from a cold start. The hits are exact whatever the
+    /// seeds.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `seed_hits` - The hits of a previous search. Their distances are
+    ///   computed again for the new `query`.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the index of the instance and the distance to the query.
+    pub fn knn_search_seeded(&self, query: &I, k: usize, seed_hits: &[(usize, U)]) -> Vec<(usize, U)> {
+        let seeds = seed_hits.iter().map(|&(i, _)| i).collect::<Vec<_>>();
+        knn::seeded::search(&self.trees_with_offsets(), query, k, &seeds)
+    }
+
+    /// Returns the tree(s), each with the offset to add to the indices of its
+    /// instances to get the indices returned by search.
+    fn trees_with_offsets(&self) -> Vec<knn::OffsetTree<'_, I, U, D, UniBall<U>>> {
+        match self {
+            Self::SingleShard(ss) => vec![(ss.tree(), 0)],
+            Self::RandomlySharded(rs) => {
+                let offsets = core::iter::once(0).chain(rs.offsets().iter().copied());
+                rs.shards().into_iter().map(SingleShard::tree).zip(offsets).collect()
+            }
+        }
+    }
+
+    /// Performs a KNN search for at most `k` neighbors, none of which are
+    /// farther from the query than `radius`.
+    ///
+    /// Both constraints prune the tree at once, so this does less work than
+    /// an RNN search whose hits are then trimmed to `k`, or a KNN search whose
+    /// hits are then filtered by `radius`. With multiple shards, the shards
+    /// are searched in turn, and the `k`-th nearest hit found so far tightens
+    /// the radius for the shards which remain.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The largest number of nearest neighbors to return.
+    /// * `radius` - The largest distance from the query to a neighbor.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the index of the instance and the distance to the query.
+    pub fn knn_within(&self, query: &I, k: usize, radius: U) -> Vec<(usize, U)> {
+        match self {
+            Self::SingleShard(ss) => knn::within::search(ss.tree(), query, k, radius, &()),
+            Self::RandomlySharded(rs) => {
+                let offsets = core::iter::once(0).chain(rs.offsets().iter().copied());
+                let mut hits = knn::Hits::new(k);
+                for (shard, o) in rs.shards().into_iter().zip(offsets) {
+                    let radius = if hits.len() == k && hits.peek() < radius {
+                        hits.peek()
+                    } else {
+                        radius
+                    };
+                    let new_hits = knn::within::search(shard.tree(), query, k, radius, &());
+                    hits.push_batch(new_hits.into_iter().map(|(i, d)| (i + o, d)));
+                }
+                hits.extract()
+            }
+        }
+    }
+
+    /// Performs a KNN search for a query of a different type than the
+    /// instances.
+    ///
+    /// See the `asymmetric` module for the properties `metric` must have for
+    /// the search to be exact.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query.
+    /// * `metric` - The distance from the query to an instance.
+    /// * `k` - The number of nearest neighbors to return.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the index of the instance and the distance to the query.
+    pub fn knn_search_asymmetric<Q: Sync>(&self, query: &Q, metric: fn(&Q, &I) -> U, k: usize) -> Vec<(usize, U)> {
+        match self {
+            Self::SingleShard(ss) => asymmetric::knn_search(ss.tree(), query, metric, k),
+            Self::RandomlySharded(rs) => {
+                let offsets = core::iter::once(0).chain(rs.offsets().iter().copied());
+                let hits = rs
+                    .shards()
+                    .into_par_iter()
+                    .zip(offsets.collect::<Vec<_>>())
+                    .flat_map(|(shard, o)| {
+                        asymmetric::knn_search(shard.tree(), query, metric, k)
+                            .into_iter()
+                            .map(|(i, d)| (i + o, d))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect();
+                knn::Hits::from_vec(k, hits).extract()
+            }
+        }
+    }
+
+    /// Performs an RNN search for a query of a different type than the
+    /// instances.
+    ///
+    /// See the `asymmetric` module for the properties `metric` must have for
+    /// the search to be exact.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query.
+    /// * `metric` - The distance from the query to an instance.
+    /// * `radius` - The search radius.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the index of the instance and the distance
+    /// to the query.
+    pub fn rnn_search_asymmetric<Q: Sync>(&self, query: &Q, metric: fn(&Q, &I) -> U, radius: U) -> Vec<(usize, U)> {
+        match self {
+            Self::SingleShard(ss) => asymmetric::rnn_search(ss.tree(), query, metric, radius),
+            Self::RandomlySharded(rs) => {
+                let offsets = core::iter::once(0).chain(rs.offsets().iter().copied());
+                rs.shards()
+                    .into_par_iter()
+                    .zip(offsets.collect::<Vec<_>>())
+                    .flat_map(|(shard, o)| {
+                        asymmetric::rnn_search(shard.tree(), query, metric, radius)
+                            .into_iter()
+                            .map(|(i, d)| (i + o, d))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Performs a KNN search for every instance of another tree, e.g. one built
+    /// on a large batch of queries.
+    ///
+    /// The query tree is traversed against the tree(s) of the `Cakes`, and
+    /// pairs of `Cluster`s are pruned using the radii of both, so that the
+    /// work for nearby queries is shared. See `knn::Algorithm` for search one
+    /// query at a time.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The tree of the queries. It must have the same distance
+    ///   function as the `Cakes`.
+    /// * `k` - The number of nearest neighbors to return for each query.
+    ///
+    /// # Returns
+    ///
+    /// A vector with a row for every index in `other`, each holding tuples of
+    /// the index of an instance and its distance to the query. Instances
+    /// removed from `other` have no hits.
+    pub fn tree_search<Dq: Dataset<I, U>, Cq: Cluster<U>>(
+        &self,
+        other: &Tree<I, U, Dq, Cq>,
+        k: usize,
+    ) -> Vec<Vec<(usize, U)>> {
+        knn::dual_tree::search(other, &self.trees_with_offsets(), k)
+    }
+
+    /// Computes the `k` nearest neighbors of every instance in the dataset.
+    ///
+    /// For a single shard, the tree is traversed once per leaf on behalf of
+    /// all of its instances, and each distance is offered to both of its
+    /// instances. For randomly sharded data, each instance is searched for
+    /// separately across all shards.
+    ///
+    /// # Arguments
+    ///
+    /// * `k` - The number of neighbors of each instance. An instance is never
+    ///   its own neighbor.
+    ///
+    /// # Returns
+    ///
+    /// The graph in compressed sparse row form, with a row for every index
+    /// returned by search.
+    pub fn knn_graph(&self, k: usize) -> knn::KnnGraph<U> {
+        match self {
+            Self::SingleShard(ss) => knn::KnnGraph::from_tree(ss.tree(), k),
+            Self::RandomlySharded(rs) => {
+                let rows = (0..self.total_cardinality())
+                    .into_par_iter()
+                    .map(|i| {
+                        let (s, j) = rs.locate(i);
+                        if k == 0 || rs.shards()[s].tree().is_removed(j) {
+                            Vec::new()
+                        } else {
+                            rs.knn_search_filtered(&self.get(i), k, &|o| o != i)
+                        }
+                    })
+                    .collect();
+                knn::KnnGraph::from_rows(k, rows)
+            }
+        }
+    }
+
+    /// Finds all pairs of distinct instances which are within a threshold
+    /// distance of each other.
+    ///
+    /// Pairs of `Cluster`s which are too far apart are pruned, so that this is
+    /// much faster than comparing every pair of instances. For randomly
+    /// sharded data, the trees of each pair of shards are also traversed
+    /// against each other.
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold` - The largest distance between the instances in a pair.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 3-tuples `(i, j, d)` with `i < j`, where `i` and `j` are the
+    /// indices of the instances, as returned by search, and `d` is the distance
+    /// between them.
+    pub fn pairs_within(&self, threshold: U) -> Vec<(usize, usize, U)> {
+        match self {
+            Self::SingleShard(ss) => rnn::pairs_within(ss.tree(), threshold),
+            Self::RandomlySharded(rs) => {
+                let shards = rs.shards();
+                let offsets = core::iter::once(0)
+                    .chain(rs.offsets().iter().copied())
+                    .collect::<Vec<_>>();
+                let mut pairs = Vec::new();
+                for (s, (left, &l)) in shards.iter().zip(&offsets).enumerate() {
+                    let within = rnn::pairs_within(left.tree(), threshold);
+                    pairs.extend(within.into_iter().map(|(i, j, d)| (i + l, j + l, d)));
+                    for (right, &r) in shards.iter().zip(&offsets).skip(s + 1) {
+                        let between = rnn::pairs_between(left.tree(), right.tree(), threshold);
+                        pairs.extend(between.into_iter().map(|(i, j, d)| (i + l, j + r, d)));
+                    }
+                }
+                pairs
+            }
+        }
+    }
+
+    /// Finds the groups of duplicate, or near-duplicate, instances.
+    ///
+    /// The pairs of instances within the `threshold` of each other are found
+    /// as by `pairs_within`, so that clusters which are too far apart are
+    /// never compared. The groups are then chained together from these pairs,
+    /// so that an instance is in the same group as each of its duplicates,
+    /// even if the other members of the group are farther than the
+    /// `threshold`. Keeping the first instance of each group removes all the
+    /// duplicates.
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold` - The largest distance between duplicate instances. A
+    ///   `threshold` of zero finds exact duplicates.
+    ///
+    /// # Returns
+    ///
+    /// The groups of indices, as returned by search, each with at least two
+    /// indices in increasing order. The groups are sorted by their first
+    /// index, and instances with no duplicates are in no group.
+    pub fn find_duplicates(&self, threshold: U) -> Vec<Vec<usize>> {
+        rnn::group_pairs(&self.pairs_within(threshold))
+    }
+
+    /// Performs a KNN search among the instances for which `filter` returns
+    /// `true`.
+    ///
+    /// The `filter` is applied while searching, so that `k` passing hits are
+    /// found whenever at least `k` instances pass it.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `filter` - A predicate on the indices of instances, i.e. the same
+    ///   indices as are returned by search.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the index of the instance and the distance to the query.
+    pub fn knn_search_filtered<F: Fn(usize) -> bool + Sync>(&self, query: &I, k: usize, filter: F) -> Vec<(usize, U)> {
+        match self {
+            Self::SingleShard(ss) => ss.knn_search_filtered(query, k, &filter),
+            Self::RandomlySharded(rs) => rs.knn_search_filtered(query, k, &filter),
+        }
+    }
+
+    /// Performs a KNN search with the given algorithm, returning references to
+    /// the instances instead of their indices.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the instance and its distance to the query.
+    pub fn knn_search_instances(&self, query: &I, k: usize, algo: knn::Algorithm) -> Vec<(Cow<'_, I>, U)> {
+        self.knn_search(query, k, algo)
+            .into_iter()
+            .map(|(i, d)| (self.get(i), d))
+            .collect()
+    }
+
+    /// Returns the weight of the instance at the given index, which is one
+    /// unless the dataset is weighted. See `Dataset::weights`.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index of the instance, as returned by search.
+    pub fn weight(&self, index: usize) -> f64 {
+        match self {
+            Self::SingleShard(ss) => ss.data().weight(index),
+            Self::RandomlySharded(rs) => {
+                let (i, index) = rs.locate(index);
+                rs.shards()[i].data().weight(index)
+            }
+        }
+    }
+
+    /// Performs a KNN search with the given algorithm, returning the weight of
+    /// each hit along with it.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 3-tuples containing the index of the instance, its distance
+    /// to the query and its weight.
+    pub fn knn_search_weighted(&self, query: &I, k: usize, algo: knn::Algorithm) -> Vec<(usize, U, f64)> {
+        self.knn_search(query, k, algo)
+            .into_iter()
+            .map(|(i, d)| (i, d, self.weight(i)))
+            .collect()
+    }
+
+    /// Performs an RNN search with the given algorithm, returning the weight
+    /// of each hit along with it.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `radius` - The search radius.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 3-tuples containing the index of the instance, its distance
+    /// to the query and its weight.
+    pub fn rnn_search_weighted(&self, query: &I, radius: U, algo: rnn::Algorithm) -> Vec<(usize, U, f64)> {
+        self.rnn_search(query, radius, algo)
+            .into_iter()
+            .map(|(i, d)| (i, d, self.weight(i)))
+            .collect()
+    }
+
     /// Automatically finds the best RNN algorithm to use.
     ///
     /// # Arguments
@@ -289,6 +1299,49 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
         }
     }
 
+    /// Benchmarks every KNN and RNN algorithm on a sample of queries and
+    /// remembers the fastest for the regime of `k`.
+    ///
+    /// The tuned search methods, e.g. `tuned_knn_search`, then use the
+    /// algorithm tuned for the closest regime. The RNN regime for `k` is the
+    /// mean distance from the queries to their `k`-th nearest neighbors.
+    /// Calling this with several values of `k` tunes several regimes.
+    ///
+    /// # Arguments
+    ///
+    /// * `sample_queries` - The queries to benchmark with.
+    /// * `k` - The number of nearest neighbors to tune for.
+    pub fn auto_tune(&mut self, sample_queries: &[&I], k: usize) {
+        match self {
+            Self::SingleShard(ss) => ss.auto_tune(sample_queries, k),
+            Self::RandomlySharded(rs) => rs.auto_tune(sample_queries, k),
+        }
+    }
+
+    /// Returns the tuned KNN algorithm for the regime closest to `k`.
+    ///
+    /// If no regime has been tuned, this is the algorithm tuned by
+    /// `auto_tune_knn`, if any, or else the one chosen by `Cakes::planner` for
+    /// `k`.
+    pub fn tuned_knn_algorithm_for(&self, k: usize) -> knn::Algorithm {
+        match self {
+            Self::SingleShard(ss) => ss.tuned_knn_algorithm_for(k),
+            Self::RandomlySharded(rs) => rs.tuned_knn_algorithm_for(k),
+        }
+    }
+
+    /// Returns the tuned RNN algorithm for the regime closest to `radius`.
+    ///
+    /// If no regime has been tuned, this is the algorithm tuned by
+    /// `auto_tune_rnn`, if any, or else the one chosen by `Cakes::planner` for
+    /// `radius`.
+    pub fn tuned_rnn_algorithm_for(&self, radius: U) -> rnn::Algorithm {
+        match self {
+            Self::SingleShard(ss) => ss.tuned_rnn_algorithm_for(radius),
+            Self::RandomlySharded(rs) => rs.tuned_rnn_algorithm_for(radius),
+        }
+    }
+
     /// Performs Linear KNN search on a batch of queries.
     ///
     /// # Arguments
@@ -323,7 +1376,8 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
 
     /// Performs RNN search on a batch of queries with the tuned algorithm.
     ///
-    /// If the algorithm has not been tuned, this will use the default algorithm.
+    /// If the algorithm has not been tuned, this will use the one chosen by
+    /// `Cakes::planner`.
     ///
     /// # Arguments
     ///
@@ -335,12 +1389,13 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     /// A vector of vectors of tuples containing the index of the instance and
     /// the distance to the query.
     pub fn batch_tuned_rnn_search(&self, queries: &[&I], radius: U) -> Vec<Vec<(usize, U)>> {
-        queries.par_iter().map(|q| self.tuned_rnn_search(q, radius)).collect()
+        self.batch_rnn_search(queries, radius, self.tuned_rnn_algorithm_for(radius))
     }
 
     /// Performs a RNN search with the tuned algorithm.
     ///
-    /// If the algorithm has not been tuned, this will use the default algorithm.
+    /// If the algorithm has not been tuned, this will use the one chosen by
+    /// `Cakes::planner`.
     ///
     /// # Arguments
     ///
@@ -351,13 +1406,14 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     ///
     /// A vector of tuples containing the index of the instance and the distance to the query.
     pub fn tuned_rnn_search(&self, query: &I, radius: U) -> Vec<(usize, U)> {
-        let algo = self.tuned_rnn_algorithm();
+        let algo = self.tuned_rnn_algorithm_for(radius);
         self.rnn_search(query, radius, algo)
     }
 
     /// Performs KNN search on a batch of queries with the tuned algorithm.
     ///
-    /// If the algorithm has not been tuned, this will use the default algorithm.
+    /// If the algorithm has not been tuned, this will use the one chosen by
+    /// `Cakes::planner`.
     ///
     /// # Arguments
     ///
@@ -369,12 +1425,13 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     /// A vector of vectors of tuples containing the index of the instance and
     /// the distance to the query.
     pub fn batch_tuned_knn_search(&self, queries: &[&I], k: usize) -> Vec<Vec<(usize, U)>> {
-        queries.par_iter().map(|q| self.tuned_knn_search(q, k)).collect()
+        self.batch_knn_search(queries, k, self.tuned_knn_algorithm_for(k))
     }
 
     /// Performs a KNN search with the tuned algorithm.
     ///
-    /// If the algorithm has not been tuned, this will use the default algorithm.
+    /// If the algorithm has not been tuned, this will use the one chosen by
+    /// `Cakes::planner`.
     ///
     /// # Arguments
     ///
@@ -385,16 +1442,130 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     ///
     /// A vector of tuples containing the index of the instance and the distance to the query.
     pub fn tuned_knn_search(&self, query: &I, k: usize) -> Vec<(usize, U)> {
-        let algo = self.tuned_knn_algorithm();
+        let algo = self.tuned_knn_algorithm_for(k);
         self.knn_search(query, k, algo)
     }
 }
 
+impl<I: Instance, U: Number, M: Instance> Cakes<I, U, VecDataset<I, U, M>> {
+    /// Creates a new CAKES instance over preprocessed instances, which applies
+    /// the same preprocessing to every query.
+    ///
+    /// See `Preprocessed::new`.
+    pub fn with_preprocess<P, C>(
+        data: VecDataset<I, U, M>,
+        preprocess: P,
+        seed: Option<u64>,
+        criteria: &C,
+    ) -> Preprocessed<I, U, VecDataset<I, U, M>, P>
+    where
+        P: Preprocess<I>,
+        C: PartitionCriterion<U>,
+    {
+        Preprocessed::new(data, preprocess, seed, criteria)
+    }
+}
+
+impl<T: Number, U: Float, D: Dataset<Vec<T>, U>> Cakes<Vec<T>, U, D> {
+    /// Performs a Maximum Inner Product Search, for the instances with the
+    /// largest inner products with the query.
+    ///
+    /// The tree(s) must have been built with the Euclidean distance. See the
+    /// `mips` module.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of instances to return.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the index of the instance and its inner
+    /// product with the query, sorted by decreasing inner product.
+    pub fn mips_search(&self, query: &[T], k: usize) -> Vec<(usize, U)> {
+        match self {
+            Self::SingleShard(ss) => mips::search(ss.tree(), query, k),
+            Self::RandomlySharded(rs) => {
+                let offsets = core::iter::once(0).chain(rs.offsets().iter().copied());
+                let mut hits = rs
+                    .shards()
+                    .into_par_iter()
+                    .zip(offsets.collect::<Vec<_>>())
+                    .flat_map(|(shard, o)| {
+                        mips::search(shard.tree(), query, k)
+                            .into_iter()
+                            .map(|(i, p)| (i + o, p))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>();
+                mips::sort_descending(&mut hits);
+                hits.truncate(k);
+                hits
+            }
+        }
+    }
+}
+
+impl<M: Instance> Cakes<Vec<i8>, f32, QuantizedDataset<M>> {
+    /// Performs a KNN search over the quantized codes and rescores the
+    /// candidates with their full-precision distances to the query.
+    ///
+    /// The `num_candidates` nearest neighbors of the quantized query are found
+    /// with `algo`, and the `k` of them which are nearest to the full-precision
+    /// query are returned. Larger values of `num_candidates` recover more of
+    /// the true nearest neighbors at the cost of more full-precision distance
+    /// computations.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The full-precision query.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `num_candidates` - The number of candidates to rescore. This is
+    ///   raised to `k` if it is smaller.
+    /// * `algo` - The algorithm to use for searching the codes.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the index of the instance and its exact
+    /// euclidean distance to the query.
+    ///
+    /// # Errors
+    ///
+    /// * If the full-precision instances were not kept in the dataset.
+    pub fn knn_search_rescored(
+        &self,
+        query: &[f32],
+        k: usize,
+        num_candidates: usize,
+        algo: knn::Algorithm,
+    ) -> Result<Vec<(usize, f32)>, String> {
+        let shards = self.shards();
+        let codes = shards[0].quantize(query);
+        let candidates = self.knn_search(&codes, num_candidates.max(k), algo);
+
+        let hits = candidates
+            .into_iter()
+            .map(|(i, _)| {
+                let (shard, local) = match self {
+                    Self::SingleShard(_) => (0, i),
+                    Self::RandomlySharded(rs) => rs.locate(i),
+                };
+                shards[shard]
+                    .full_distance(query, local)
+                    .map(|d| (i, d))
+                    .ok_or_else(|| "Full-precision instances were not kept for rescoring".to_string())
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(knn::Hits::from_vec(k, hits).extract())
+    }
+}
+
 impl<I, U, D> Index<usize> for Cakes<I, U, D>
 where
     I: Instance,
     U: Number,
-    D: Dataset<I, U>,
+    D: Dataset<I, U> + Index<usize, Output = I>,
 {
     type Output = I;
 
@@ -402,14 +1573,7 @@ where
         match self {
             Self::SingleShard(ss) => ss.data().index(index),
             Self::RandomlySharded(rs) => {
-                let i = rs
-                    .offsets()
-                    .iter()
-                    .enumerate()
-                    .find(|(_, &o)| o > index)
-                    .map_or_else(|| rs.num_shards() - 1, |(i, _)| i - 1);
-
-                let index = index - rs.offsets()[i];
+                let (i, index) = rs.locate(index);
                 rs.shards()[i].data().index(index)
             }
         }
@@ -0,0 +1,218 @@
+//! CAKES search over several independently built `Cakes`.
+
+use distances::Number;
+
+use super::Cakes;
+use crate::par::prelude::*;
+use crate::{cakes::knn, cakes::rnn, Dataset, Instance};
+
+/// CAKES search over several `Cakes`, each of which is a shard of the full
+/// dataset.
+///
+/// Unlike `Cakes::new_randomly_sharded`, which splits one dataset into shards
+/// of similar contents, the shards here may be built independently, e.g. to
+/// fit each in its own memory budget, or one at a time as the data arrives.
+/// Every search is run on all shards in parallel, and the results are merged.
+///
+/// The indices of hits are those of the shard which holds the instance, offset
+/// by the total cardinality of the shards before it. They may be mapped back to
+/// the shards with `locate`.
+///
+/// # Type Parameters
+///
+/// - `I`: The type of the instances.
+/// - `U`: The type of the distance values.
+/// - `D`: The type of the datasets.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ShardedCakes<I: Instance, U: Number, D: Dataset<I, U>> {
+    /// The shards.
+    shards: Vec<Cakes<I, U, D>>,
+    /// The index at which each shard starts.
+    offsets: Vec<usize>,
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>> ShardedCakes<I, U, D> {
+    /// Creates a new `ShardedCakes` from its shards.
+    ///
+    /// # Arguments
+    ///
+    /// * `shards` - The shards, in the order in which their indices are
+    ///   offset.
+    #[must_use]
+    pub fn new(shards: Vec<Cakes<I, U, D>>) -> Self {
+        let mut sharded = Self {
+            shards: Vec::with_capacity(shards.len()),
+            offsets: Vec::with_capacity(shards.len()),
+        };
+        for shard in shards {
+            sharded.push(shard);
+        }
+        sharded
+    }
+
+    /// Adds a shard after all others.
+    ///
+    /// The indices of the instances in earlier shards are unchanged.
+    pub fn push(&mut self, shard: Cakes<I, U, D>) {
+        self.offsets.push(self.total_cardinality());
+        self.shards.push(shard);
+    }
+
+    /// Returns the shards.
+    #[must_use]
+    pub fn shards(&self) -> &[Cakes<I, U, D>] {
+        &self.shards
+    }
+
+    /// Returns the number of shards.
+    #[must_use]
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the index at which each shard starts.
+    #[must_use]
+    pub fn offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    /// Returns the total number of instances in all shards.
+    #[must_use]
+    pub fn total_cardinality(&self) -> usize {
+        self.offsets
+            .last()
+            .zip(self.shards.last())
+            .map_or(0, |(&o, s)| o + s.total_cardinality())
+    }
+
+    /// Returns the position, in `shards`, of the shard containing the instance
+    /// at `index`, along with the index of the instance within that shard.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index of an instance, as returned by a search.
+    #[must_use]
+    pub fn locate(&self, index: usize) -> (usize, usize) {
+        let i = self.offsets.partition_point(|&o| o <= index).saturating_sub(1);
+        (i, index - self.offsets.get(i).copied().unwrap_or_default())
+    }
+
+    /// Returns the index of an instance before the shards were reordered.
+    ///
+    /// This is the index of the instance in the concatenation of the shards,
+    /// in order, before each was reordered.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index of an instance, as returned by a search.
+    #[must_use]
+    pub fn original_index(&self, index: usize) -> usize {
+        let (i, local) = self.locate(index);
+        self.offsets[i] + self.shards[i].original_index(local)
+    }
+
+    /// Performs an RNN search on all shards.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `radius` - The search radius.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the index of the instance and the distance
+    /// to the query.
+    pub fn rnn_search(&self, query: &I, radius: U, algo: rnn::Algorithm) -> Vec<(usize, U)> {
+        self.shards
+            .par_iter()
+            .zip(self.offsets.par_iter())
+            .flat_map(|(shard, &o)| {
+                shard
+                    .rnn_search(query, radius, algo)
+                    .into_par_iter()
+                    .map(move |(i, d)| (i + o, d))
+            })
+            .collect()
+    }
+
+    /// Performs RNN searches for a batch of queries on all shards.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The queries to search.
+    /// * `radius` - The search radius.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// The hits for each query, as returned by `rnn_search`.
+    pub fn batch_rnn_search(&self, queries: &[&I], radius: U, algo: rnn::Algorithm) -> Vec<Vec<(usize, U)>> {
+        queries
+            .par_iter()
+            .map(|query| self.rnn_search(query, radius, algo))
+            .collect()
+    }
+
+    /// Counts the neighbors of a query within a radius, on all shards.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `radius` - The search radius.
+    /// * `algo` - The algorithm to use.
+    pub fn rnn_count(&self, query: &I, radius: U, algo: rnn::Algorithm) -> usize {
+        self.shards
+            .par_iter()
+            .map(|shard| shard.rnn_count(query, radius, algo))
+            .sum()
+    }
+
+    /// Performs a KNN search on all shards, and keeps the `k` nearest hits.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the index of the instance and the distance
+    /// to the query.
+    pub fn knn_search(&self, query: &I, k: usize, algo: knn::Algorithm) -> Vec<(usize, U)> {
+        // The `k` nearest neighbors in all shards are among the `k` nearest
+        // neighbors in each shard.
+        let hits = self
+            .shards
+            .par_iter()
+            .zip(self.offsets.par_iter())
+            .flat_map(|(shard, &o)| {
+                shard
+                    .knn_search(query, k, algo)
+                    .into_par_iter()
+                    .map(move |(i, d)| (i + o, d))
+            })
+            .collect();
+        knn::Hits::from_vec(k, hits).extract()
+    }
+
+    /// Performs KNN searches for a batch of queries on all shards.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The queries to search.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// The hits for each query, as returned by `knn_search`.
+    pub fn batch_knn_search(&self, queries: &[&I], k: usize, algo: knn::Algorithm) -> Vec<Vec<(usize, U)>> {
+        queries
+            .par_iter()
+            .map(|query| self.knn_search(query, k, algo))
+            .collect()
+    }
+}
@@ -0,0 +1,140 @@
+//! Searches for async runtimes, which run on the blocking thread pool of
+//! `tokio` instead of the executor threads.
+//!
+//! A long traversal on an executor thread would stall every other task on
+//! that thread. These methods move the search to `tokio::task::spawn_blocking`,
+//! so a web service can `await` them like any other I/O. They take a
+//! `SharedCakes` and owned queries, because the blocking task may outlive the
+//! caller's borrows.
+
+use distances::Number;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::SharedCakes;
+use crate::par::prelude::*;
+use crate::{knn, rnn, Dataset, Error, Instance};
+
+/// The number of results of a streamed batch search which may wait to be
+/// consumed before the search pauses.
+const STREAM_BUFFER: usize = 64;
+
+impl<I, U, D> SharedCakes<I, U, D>
+where
+    I: Instance + 'static,
+    U: Number + 'static,
+    D: Dataset<I, U> + 'static,
+{
+    /// Performs a KNN search on the blocking thread pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as returned by `Cakes::knn_search`.
+    ///
+    /// # Errors
+    ///
+    /// * If the search panicked, or the runtime is shutting down.
+    pub async fn knn_search_async(&self, query: I, k: usize, algo: knn::Algorithm) -> Result<Vec<(usize, U)>, Error> {
+        let cakes = self.clone();
+        tokio::task::spawn_blocking(move || cakes.knn_search(&query, k, algo))
+            .await
+            .map_err(|e| Error::Other(e.to_string()))
+    }
+
+    /// Performs an RNN search on the blocking thread pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `radius` - The search radius.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as returned by `Cakes::rnn_search`.
+    ///
+    /// # Errors
+    ///
+    /// * If the search panicked, or the runtime is shutting down.
+    pub async fn rnn_search_async(&self, query: I, radius: U, algo: rnn::Algorithm) -> Result<Vec<(usize, U)>, Error> {
+        let cakes = self.clone();
+        tokio::task::spawn_blocking(move || cakes.rnn_search(&query, radius, algo))
+            .await
+            .map_err(|e| Error::Other(e.to_string()))
+    }
+
+    /// Performs KNN search on a batch of queries on the blocking thread pool,
+    /// streaming the hits of each query as they are found.
+    ///
+    /// The queries are searched in parallel, a few at a time, and the hits are
+    /// yielded in the order of the `queries`. If the stream is dropped, the
+    /// search stops after the queries in progress.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The queries to search.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    #[must_use]
+    pub fn batch_knn_search_stream(
+        &self,
+        queries: Vec<I>,
+        k: usize,
+        algo: knn::Algorithm,
+    ) -> ReceiverStream<Vec<(usize, U)>> {
+        let cakes = self.clone();
+        stream(queries, move |query| cakes.knn_search(query, k, algo))
+    }
+
+    /// Performs RNN search on a batch of queries on the blocking thread pool,
+    /// streaming the hits of each query as they are found.
+    ///
+    /// See `batch_knn_search_stream`.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The queries to search.
+    /// * `radius` - The search radius.
+    /// * `algo` - The algorithm to use.
+    #[must_use]
+    pub fn batch_rnn_search_stream(
+        &self,
+        queries: Vec<I>,
+        radius: U,
+        algo: rnn::Algorithm,
+    ) -> ReceiverStream<Vec<(usize, U)>> {
+        let cakes = self.clone();
+        stream(queries, move |query| cakes.rnn_search(query, radius, algo))
+    }
+}
+
+/// Runs `search` over the `queries` on the blocking thread pool, in chunks of
+/// one query per thread, and sends the hits of each query, in order, to the
+/// returned stream.
+fn stream<I, U, F>(queries: Vec<I>, search: F) -> ReceiverStream<Vec<(usize, U)>>
+where
+    I: Instance + 'static,
+    U: Number + 'static,
+    F: Fn(&I) -> Vec<(usize, U)> + Send + Sync + 'static,
+{
+    let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+    tokio::task::spawn_blocking(move || {
+        let chunk_size = crate::par::current_num_threads().max(1);
+        for chunk in queries.chunks(chunk_size) {
+            let hits = chunk.par_iter().map(&search).collect::<Vec<_>>();
+            for h in hits {
+                // The receiver was dropped, so nobody wants the rest.
+                if tx.blocking_send(h).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    ReceiverStream::new(rx)
+}
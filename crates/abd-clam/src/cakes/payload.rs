@@ -0,0 +1,174 @@
+//! Payloads, such as labels or records, stored alongside the instances of a
+//! `Cakes` and returned with search hits.
+
+use core::ops::{Deref, DerefMut};
+
+use std::path::Path;
+
+use distances::Number;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{knn, rnn, Cakes};
+use crate::core::tree::{load_bincode, save_bincode};
+use crate::{Dataset, Error, Instance};
+
+/// The name of the file in which the payloads are saved.
+const PAYLOADS: &str = "payloads.bin";
+
+/// A `Cakes` with a payload of type `M` for each instance.
+///
+/// The payloads are given in the original order of the instances, and are
+/// kept in the order of the search results, so that the payload of a hit is
+/// found without looking up its original index. The handle dereferences to
+/// the `Cakes`, so all searches are available through it.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct PayloadCakes<I: Instance, U: Number, D: Dataset<I, U>, M> {
+    /// The `Cakes` to search.
+    cakes: Cakes<I, U, D>,
+    /// The payload of each instance, by the index returned by searches.
+    payloads: Vec<M>,
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>, M> PayloadCakes<I, U, D, M> {
+    /// Attaches payloads to the instances of a `Cakes`.
+    ///
+    /// # Arguments
+    ///
+    /// * `cakes` - The `Cakes` to search.
+    /// * `payloads` - The payload of each instance, in the order in which the
+    ///   instances were given, i.e. by `Cakes::original_index`.
+    ///
+    /// # Errors
+    ///
+    /// * If the number of payloads is not the number of instances.
+    pub fn new(cakes: Cakes<I, U, D>, payloads: Vec<M>) -> Result<Self, String> {
+        let n = cakes.total_cardinality();
+        if payloads.len() != n {
+            return Err(format!("Expected {n} payloads, got {}", payloads.len()));
+        }
+
+        let mut payloads = payloads.into_iter().map(Some).collect::<Vec<_>>();
+        let payloads = (0..n)
+            .map(|i| {
+                payloads[cakes.original_index(i)]
+                    .take()
+                    .unwrap_or_else(|| unreachable!("The original indices are a permutation."))
+            })
+            .collect();
+
+        Ok(Self { cakes, payloads })
+    }
+
+    /// Returns the payload of the instance at the given index, as returned by
+    /// a search.
+    pub fn payload(&self, index: usize) -> &M {
+        &self.payloads[index]
+    }
+
+    /// Returns the payloads, in the order of the indices returned by searches.
+    pub fn payloads(&self) -> &[M] {
+        &self.payloads
+    }
+
+    /// Detaches the payloads, returning the `Cakes` and the payloads in the
+    /// order of the indices returned by searches.
+    pub fn into_parts(self) -> (Cakes<I, U, D>, Vec<M>) {
+        (self.cakes, self.payloads)
+    }
+
+    /// Performs RNN search, returning the payload of each hit.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 3-tuples, where the first element is the index of the
+    /// instance, the second is its distance from the query and the third is
+    /// its payload.
+    pub fn rnn_search_with_payloads(&self, query: &I, radius: U, algo: rnn::Algorithm) -> Vec<(usize, U, &M)> {
+        self.attach(self.cakes.rnn_search(query, radius, algo))
+    }
+
+    /// Performs KNN search, returning the payload of each hit.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 3-tuples, where the first element is the index of the
+    /// instance, the second is its distance from the query and the third is
+    /// its payload.
+    pub fn knn_search_with_payloads(&self, query: &I, k: usize, algo: knn::Algorithm) -> Vec<(usize, U, &M)> {
+        self.attach(self.cakes.knn_search(query, k, algo))
+    }
+
+    /// Pairs each hit with its payload.
+    fn attach(&self, hits: Vec<(usize, U)>) -> Vec<(usize, U, &M)> {
+        hits.into_iter().map(|(i, d)| (i, d, &self.payloads[i])).collect()
+    }
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>, M: Serialize + DeserializeOwned> PayloadCakes<I, U, D, M> {
+    /// Saves the `Cakes` and the payloads to the given directory.
+    ///
+    /// The payloads are written before the manifest, so that they are covered
+    /// by its checksums.
+    ///
+    /// # Errors
+    ///
+    /// * See `Cakes::save`.
+    /// * `Error::Io` if the payloads cannot be serialized.
+    pub fn save(&self, path: &Path, metric_name: &str) -> Result<(), Error> {
+        if !path.is_dir() {
+            return Err(Error::Io(format!("Path '{}' is not a directory.", path.display())));
+        }
+        save_bincode(&path.join(PAYLOADS), &self.payloads)?;
+        self.cakes.save(path, metric_name)
+    }
+
+    /// Loads a `Cakes` and its payloads from the given directory.
+    ///
+    /// # Errors
+    ///
+    /// * See `Cakes::load`.
+    /// * `Error::Io` if the directory has no payloads, if they cannot be
+    ///   deserialized, or if the number of payloads is not the number of
+    ///   instances.
+    pub fn load(path: &Path, metric_name: &str, metric: fn(&I, &I) -> U, is_expensive: bool) -> Result<Self, Error> {
+        let cakes = Cakes::load(path, metric_name, metric, is_expensive)?;
+
+        let payloads_path = path.join(PAYLOADS);
+        if !payloads_path.exists() {
+            return Err(Error::Io(format!("The index at '{}' has no payloads.", path.display())));
+        }
+        let payloads: Vec<M> = load_bincode(&payloads_path)?;
+
+        let n = cakes.total_cardinality();
+        if payloads.len() != n {
+            return Err(Error::Io(format!("Expected {n} payloads, got {}", payloads.len())));
+        }
+        Ok(Self { cakes, payloads })
+    }
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>, M> Deref for PayloadCakes<I, U, D, M> {
+    type Target = Cakes<I, U, D>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.cakes
+    }
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>, M> DerefMut for PayloadCakes<I, U, D, M> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.cakes
+    }
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
+    /// Attaches a payload to each instance. See `PayloadCakes::new`.
+    ///
+    /// # Errors
+    ///
+    /// * If the number of payloads is not the number of instances.
+    pub fn with_payloads<M>(self, payloads: Vec<M>) -> Result<PayloadCakes<I, U, D, M>, String> {
+        PayloadCakes::new(self, payloads)
+    }
+}
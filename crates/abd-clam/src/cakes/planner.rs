@@ -0,0 +1,248 @@
+//! Choosing search algorithms from the shape of a tree and the query.
+//!
+//! The clustered algorithms pay for the traversal of the tree with the
+//! distance computations they avoid. On a shallow tree, few `Cluster`s can be
+//! pruned, and for large `k` or `radius` most of the instances are hits anyway,
+//! so `Linear` search is faster. `RepeatedRnn` grows its radius with the local
+//! fractal dimension, so it does well when that is uniformly low and `k` is
+//! small. Otherwise, `GreedySieve` is the best general-purpose algorithm.
+
+use distances::Number;
+
+use crate::{Cluster, Dataset, Instance, Tree};
+
+use super::{knn, rnn};
+
+/// The default depth at or below which a tree is considered shallow.
+pub const SHALLOW_DEPTH: usize = 4;
+
+/// The default largest `k` which is considered small.
+pub const SMALL_K: usize = 10;
+
+/// The default local fractal dimension at or below which it is considered low.
+pub const LOW_LFD: f64 = 3.0;
+
+/// The default fraction of the cardinality of a tree at or above which `k` is
+/// considered large.
+pub const LARGE_K_FRACTION: f64 = 0.1;
+
+/// The default fraction of the radius of a tree at or above which a search
+/// radius is considered large.
+pub const LARGE_RADIUS_FRACTION: f64 = 0.5;
+
+/// Statistics of a `Tree` which determine the cost of searching it.
+#[derive(Clone, Copy, Debug)]
+pub struct TreeStats {
+    /// The number of instances in the tree.
+    pub cardinality: usize,
+    /// The depth of the tree.
+    pub depth: usize,
+    /// The radius of the root of the tree.
+    pub radius: f64,
+    /// The first quartile, median and third quartile of the local fractal
+    /// dimensions of the non-leaf `Cluster`s, weighted by their cardinalities,
+    /// or that of the root if it is a leaf.
+    pub lfd_quartiles: [f64; 3],
+    /// Whether the distance function obeys the triangle inequality.
+    pub is_metric: bool,
+}
+
+impl TreeStats {
+    /// Computes the statistics of a tree.
+    ///
+    /// This visits every `Cluster` in the tree, so it should be computed once
+    /// and reused for many queries.
+    pub fn from_tree<I, U, D, C>(tree: &Tree<I, U, D, C>) -> Self
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        let mut lfds = tree
+            .root()
+            .subtree()
+            .into_iter()
+            .filter(|c| !c.is_leaf())
+            .map(|c| (c.lfd(), c.cardinality()))
+            .collect::<Vec<_>>();
+        if lfds.is_empty() {
+            lfds.push((tree.root().lfd(), tree.root().cardinality()));
+        }
+        lfds.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+        // Each `Cluster` is weighted by its cardinality, so that the many small
+        // `Cluster`s near the leaves do not swamp the rest of the tree.
+        let total = lfds.iter().map(|&(_, c)| c).sum::<usize>();
+        let quartile = |q: usize| {
+            let target = total * q / 4;
+            let mut seen = 0;
+            lfds.iter()
+                .find(|&&(_, c)| {
+                    seen += c;
+                    seen > target
+                })
+                .map_or(0.0, |&(lfd, _)| lfd)
+        };
+
+        Self {
+            cardinality: tree.cardinality(),
+            depth: tree.depth(),
+            radius: tree.radius().as_f64(),
+            lfd_quartiles: [quartile(1), quartile(2), quartile(3)],
+            is_metric: tree.data().is_metric(),
+        }
+    }
+
+    /// The median local fractal dimension of the non-leaf `Cluster`s.
+    #[must_use]
+    pub const fn median_lfd(&self) -> f64 {
+        self.lfd_quartiles[1]
+    }
+}
+
+/// Chooses the algorithm for each search from the statistics of a tree and
+/// the parameters of the query.
+///
+/// The thresholds between algorithms default to the constants in this module
+/// and may be changed with the `with_*` methods.
+#[derive(Clone, Copy, Debug)]
+pub struct Planner {
+    /// The statistics of the tree.
+    stats: TreeStats,
+    /// The depth at or below which the tree is considered shallow.
+    shallow_depth: usize,
+    /// The largest `k` which is considered small.
+    small_k: usize,
+    /// The local fractal dimension at or below which it is considered low.
+    low_lfd: f64,
+    /// The fraction of the cardinality at or above which `k` is large.
+    large_k_fraction: f64,
+    /// The fraction of the radius of the tree at or above which a search
+    /// radius is large.
+    large_radius_fraction: f64,
+}
+
+impl Planner {
+    /// Creates a new `Planner` with the default thresholds.
+    ///
+    /// # Arguments
+    ///
+    /// * `stats` - The statistics of the tree to be searched.
+    #[must_use]
+    pub const fn new(stats: TreeStats) -> Self {
+        Self {
+            stats,
+            shallow_depth: SHALLOW_DEPTH,
+            small_k: SMALL_K,
+            low_lfd: LOW_LFD,
+            large_k_fraction: LARGE_K_FRACTION,
+            large_radius_fraction: LARGE_RADIUS_FRACTION,
+        }
+    }
+
+    /// Creates a new `Planner` for a tree with the default thresholds.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree` - The tree to be searched.
+    pub fn from_tree<I, U, D, C>(tree: &Tree<I, U, D, C>) -> Self
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        Self::new(TreeStats::from_tree(tree))
+    }
+
+    /// Sets the depth at or below which the tree is considered shallow.
+    #[must_use]
+    pub const fn with_shallow_depth(mut self, depth: usize) -> Self {
+        self.shallow_depth = depth;
+        self
+    }
+
+    /// Sets the largest `k` which is considered small.
+    #[must_use]
+    pub const fn with_small_k(mut self, k: usize) -> Self {
+        self.small_k = k;
+        self
+    }
+
+    /// Sets the local fractal dimension at or below which it is considered low.
+    #[must_use]
+    pub const fn with_low_lfd(mut self, lfd: f64) -> Self {
+        self.low_lfd = lfd;
+        self
+    }
+
+    /// Sets the fraction of the cardinality of the tree at or above which `k`
+    /// is considered large.
+    #[must_use]
+    pub const fn with_large_k_fraction(mut self, fraction: f64) -> Self {
+        self.large_k_fraction = fraction;
+        self
+    }
+
+    /// Sets the fraction of the radius of the tree at or above which a search
+    /// radius is considered large.
+    #[must_use]
+    pub const fn with_large_radius_fraction(mut self, fraction: f64) -> Self {
+        self.large_radius_fraction = fraction;
+        self
+    }
+
+    /// Returns the statistics of the tree.
+    #[must_use]
+    pub const fn stats(&self) -> &TreeStats {
+        &self.stats
+    }
+
+    /// Chooses the algorithm for a KNN search.
+    ///
+    /// `Linear` is chosen for non-metric distance functions, for large `k`,
+    /// and for small `k` on a shallow tree. `RepeatedRnn` is chosen for small
+    /// `k` when the third quartile of the local fractal dimension is low.
+    /// `GreedySieve` is chosen otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `k` - The number of nearest neighbors to search for.
+    #[must_use]
+    pub fn knn_algorithm(&self, k: usize) -> knn::Algorithm {
+        let stats = &self.stats;
+        let is_small = k <= self.small_k;
+
+        if !stats.is_metric
+            || k.as_f64() >= self.large_k_fraction * stats.cardinality.as_f64()
+            || (is_small && stats.depth <= self.shallow_depth)
+        {
+            knn::Algorithm::Linear
+        } else if is_small && stats.lfd_quartiles[2] <= self.low_lfd {
+            knn::Algorithm::RepeatedRnn {
+                lfd_threshold: knn::DEFAULT_LFD_THRESHOLD,
+            }
+        } else {
+            knn::Algorithm::GreedySieve
+        }
+    }
+
+    /// Chooses the algorithm for an RNN search.
+    ///
+    /// `Linear` is chosen for non-metric distance functions and for radii
+    /// which are large relative to the radius of the tree. `Clustered` is
+    /// chosen otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `radius` - The radius to search within.
+    #[must_use]
+    pub fn rnn_algorithm<U: Number>(&self, radius: U) -> rnn::Algorithm {
+        if !self.stats.is_metric || radius.as_f64() >= self.large_radius_fraction * self.stats.radius {
+            rnn::Algorithm::Linear
+        } else {
+            rnn::Algorithm::Clustered
+        }
+    }
+}
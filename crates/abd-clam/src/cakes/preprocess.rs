@@ -0,0 +1,365 @@
+//! Preprocessing applied alike to the instances of an index and to queries.
+//!
+//! An index built on, e.g., normalized instances must be searched with
+//! normalized queries, or recall is lost without any error. `Preprocessed`
+//! keeps the `Preprocess` used to build a `Cakes` alongside it, and applies it
+//! to every query before searching.
+
+use distances::{number::Float, Number};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+use super::Cakes;
+use crate::par::prelude::*;
+use crate::{cakes::knn, cakes::rnn, Dataset, Instance, PartitionCriterion, VecDataset};
+
+/// The number of rounds of power iteration used to find each principal
+/// component.
+const PCA_ITERATIONS: usize = 100;
+
+/// A transformation of instances, applied before they are indexed or
+/// searched for.
+pub trait Preprocess<I>: Send + Sync {
+    /// Transforms an instance.
+    fn apply(&self, instance: &I) -> I;
+
+    /// Applies `self` and then `next`.
+    fn then<P: Preprocess<I>>(self, next: P) -> Then<Self, P>
+    where
+        Self: Sized,
+    {
+        Then(self, next)
+    }
+}
+
+/// Two `Preprocess`es applied one after the other, as made by
+/// `Preprocess::then`.
+#[derive(Debug, Clone)]
+pub struct Then<A, B>(A, B);
+
+impl<I, A: Preprocess<I>, B: Preprocess<I>> Preprocess<I> for Then<A, B> {
+    fn apply(&self, instance: &I) -> I {
+        self.1.apply(&self.0.apply(instance))
+    }
+}
+
+/// Scales vectors to unit Euclidean norm. The zero vector is left as it is.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct L2Normalize;
+
+impl<T: Float> Preprocess<Vec<T>> for L2Normalize {
+    fn apply(&self, instance: &Vec<T>) -> Vec<T> {
+        let norm = dot(instance, instance).sqrt();
+        if norm > T::zero() {
+            instance.iter().map(|&x| x / norm).collect()
+        } else {
+            instance.clone()
+        }
+    }
+}
+
+/// Subtracts a stored mean from vectors.
+#[derive(Debug, Clone)]
+pub struct Center<T: Float> {
+    /// The mean to subtract.
+    mean: Vec<T>,
+}
+
+impl<T: Float> Center<T> {
+    /// Creates a `Center` which subtracts the given mean.
+    #[must_use]
+    pub const fn new(mean: Vec<T>) -> Self {
+        Self { mean }
+    }
+
+    /// Creates a `Center` which subtracts the mean of the given instances.
+    #[must_use]
+    pub fn fit(instances: &[Vec<T>]) -> Self {
+        Self::new(mean(instances))
+    }
+
+    /// Returns the mean which is subtracted.
+    #[must_use]
+    pub fn mean(&self) -> &[T] {
+        &self.mean
+    }
+}
+
+impl<T: Float> Preprocess<Vec<T>> for Center<T> {
+    fn apply(&self, instance: &Vec<T>) -> Vec<T> {
+        instance.iter().zip(&self.mean).map(|(&x, &m)| x - m).collect()
+    }
+}
+
+/// Projects vectors onto a stored set of components, after subtracting a
+/// stored mean.
+///
+/// The projection of `x` has one coordinate for each component `w`, namely
+/// the dot product of `w` with `x - mean`.
+#[derive(Debug, Clone)]
+pub struct Project<T: Float> {
+    /// The mean to subtract before projecting.
+    mean: Vec<T>,
+    /// The components to project onto.
+    components: Vec<Vec<T>>,
+}
+
+impl<T: Float> Project<T> {
+    /// Creates a `Project` with the given mean and components.
+    ///
+    /// # Errors
+    ///
+    /// * If the mean and the components do not all have the same
+    ///   dimensionality.
+    pub fn new(mean: Vec<T>, components: Vec<Vec<T>>) -> Result<Self, String> {
+        if components.iter().any(|w| w.len() != mean.len()) {
+            return Err("The mean and the components must have the same dimensionality".to_string());
+        }
+        Ok(Self { mean, components })
+    }
+
+    /// Finds the leading principal components of the given instances, by
+    /// power iteration on their covariance.
+    ///
+    /// # Arguments
+    ///
+    /// * `instances` - The instances to fit.
+    /// * `dimensionality` - The number of components to keep. This is
+    ///   lowered to the dimensionality of the instances if it is larger.
+    /// * `seed` - The seed for the starting vectors of the power iteration.
+    #[must_use]
+    pub fn fit_pca(instances: &[Vec<T>], dimensionality: usize, seed: Option<u64>) -> Self {
+        let mean = mean(instances);
+        let centered = instances
+            .par_iter()
+            .map(|x| x.iter().zip(&mean).map(|(&x, &m)| x - m).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        let seed = seed.unwrap_or_else(rand::random);
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let mut components: Vec<Vec<T>> = Vec::with_capacity(dimensionality);
+        for _ in 0..dimensionality.min(mean.len()) {
+            let mut w = (0..mean.len())
+                .map(|_| T::from(rng.gen_range(-1.0..1.0)))
+                .collect::<Vec<_>>();
+            for _ in 0..PCA_ITERATIONS {
+                // The product of the covariance with `w`, without forming the
+                // covariance.
+                let projections = centered.par_iter().map(|x| dot(x, &w)).collect::<Vec<_>>();
+                w = (0..mean.len())
+                    .into_par_iter()
+                    .map(|j| {
+                        centered
+                            .iter()
+                            .zip(&projections)
+                            .fold(T::zero(), |acc, (x, &p)| x[j].mul_add(p, acc))
+                    })
+                    .collect();
+                orthonormalize(&mut w, &components);
+            }
+            components.push(w);
+        }
+
+        Self { mean, components }
+    }
+
+    /// Returns the mean which is subtracted before projecting.
+    #[must_use]
+    pub fn mean(&self) -> &[T] {
+        &self.mean
+    }
+
+    /// Returns the components onto which instances are projected.
+    #[must_use]
+    pub fn components(&self) -> &[Vec<T>] {
+        &self.components
+    }
+}
+
+impl<T: Float> Preprocess<Vec<T>> for Project<T> {
+    fn apply(&self, instance: &Vec<T>) -> Vec<T> {
+        let centered = instance
+            .iter()
+            .zip(&self.mean)
+            .map(|(&x, &m)| x - m)
+            .collect::<Vec<_>>();
+        self.components.iter().map(|w| dot(w, &centered)).collect()
+    }
+}
+
+/// A `Cakes` built on preprocessed instances, which applies the same
+/// preprocessing to every query.
+///
+/// # Type Parameters
+///
+/// - `I`: The type of the instances.
+/// - `U`: The type of the distance values.
+/// - `D`: The type of the dataset.
+/// - `P`: The type of the preprocessing.
+#[derive(Debug)]
+pub struct Preprocessed<I: Instance, U: Number, D: Dataset<I, U>, P: Preprocess<I>> {
+    /// The index over the preprocessed instances.
+    cakes: Cakes<I, U, D>,
+    /// The preprocessing applied to the instances and queries.
+    preprocess: P,
+}
+
+impl<I: Instance, U: Number, M: Instance, P: Preprocess<I>> Preprocessed<I, U, VecDataset<I, U, M>, P> {
+    /// Preprocesses the instances of a dataset and builds a `Cakes` on them.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The dataset, whose instances are replaced by their
+    ///   preprocessed forms.
+    /// * `preprocess` - The preprocessing to apply.
+    /// * `seed` - The seed to use for the random number generator.
+    /// * `criteria` - The criteria to use for partitioning the tree.
+    pub fn new<C: PartitionCriterion<U>>(
+        data: VecDataset<I, U, M>,
+        preprocess: P,
+        seed: Option<u64>,
+        criteria: &C,
+    ) -> Self {
+        let (name, metric) = (data.name().to_string(), data.metric().clone());
+        let metadata = data.metadata().to_vec();
+        let instances = data
+            .data_owned()
+            .par_iter()
+            .map(|x| preprocess.apply(x))
+            .collect::<Vec<_>>();
+        let data = VecDataset::from_metric(name, instances, metric)
+            .assign_metadata(metadata)
+            .unwrap_or_else(|_| unreachable!("The metadata are those of the same instances."));
+
+        Self {
+            cakes: Cakes::new(data, seed, criteria),
+            preprocess,
+        }
+    }
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>, P: Preprocess<I>> Preprocessed<I, U, D, P> {
+    /// Returns the underlying `Cakes`.
+    ///
+    /// Searching it directly skips the preprocessing of queries, so queries
+    /// given to it must already have been passed through `apply`.
+    pub const fn cakes(&self) -> &Cakes<I, U, D> {
+        &self.cakes
+    }
+
+    /// Returns the underlying `Cakes`, e.g. for tuning.
+    pub fn cakes_mut(&mut self) -> &mut Cakes<I, U, D> {
+        &mut self.cakes
+    }
+
+    /// Returns the preprocessing.
+    pub const fn preprocess(&self) -> &P {
+        &self.preprocess
+    }
+
+    /// Preprocesses a query.
+    pub fn apply(&self, query: &I) -> I {
+        self.preprocess.apply(query)
+    }
+
+    /// Performs a KNN search for a preprocessed query.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query, before preprocessing.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the index of the instance and the distance
+    /// to the preprocessed query.
+    pub fn knn_search(&self, query: &I, k: usize, algo: knn::Algorithm) -> Vec<(usize, U)> {
+        self.cakes.knn_search(&self.apply(query), k, algo)
+    }
+
+    /// Performs KNN searches for a batch of preprocessed queries.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The queries, before preprocessing.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    pub fn batch_knn_search(&self, queries: &[&I], k: usize, algo: knn::Algorithm) -> Vec<Vec<(usize, U)>> {
+        let queries = queries.par_iter().map(|q| self.apply(q)).collect::<Vec<_>>();
+        self.cakes
+            .batch_knn_search(&queries.iter().collect::<Vec<_>>(), k, algo)
+    }
+
+    /// Performs an RNN search for a preprocessed query.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query, before preprocessing.
+    /// * `radius` - The search radius, in the preprocessed space.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the index of the instance and the distance
+    /// to the preprocessed query.
+    pub fn rnn_search(&self, query: &I, radius: U, algo: rnn::Algorithm) -> Vec<(usize, U)> {
+        self.cakes.rnn_search(&self.apply(query), radius, algo)
+    }
+
+    /// Performs RNN searches for a batch of preprocessed queries.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The queries, before preprocessing.
+    /// * `radius` - The search radius, in the preprocessed space.
+    /// * `algo` - The algorithm to use.
+    pub fn batch_rnn_search(&self, queries: &[&I], radius: U, algo: rnn::Algorithm) -> Vec<Vec<(usize, U)>> {
+        let queries = queries.par_iter().map(|q| self.apply(q)).collect::<Vec<_>>();
+        self.cakes
+            .batch_rnn_search(&queries.iter().collect::<Vec<_>>(), radius, algo)
+    }
+
+    /// Counts the neighbors of a preprocessed query within a radius.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query, before preprocessing.
+    /// * `radius` - The search radius, in the preprocessed space.
+    /// * `algo` - The algorithm to use.
+    pub fn rnn_count(&self, query: &I, radius: U, algo: rnn::Algorithm) -> usize {
+        self.cakes.rnn_count(&self.apply(query), radius, algo)
+    }
+}
+
+/// The dot product of two vectors.
+fn dot<T: Float>(x: &[T], y: &[T]) -> T {
+    x.iter().zip(y).fold(T::zero(), |acc, (&a, &b)| a.mul_add(b, acc))
+}
+
+/// The mean of a set of vectors.
+fn mean<T: Float>(instances: &[Vec<T>]) -> Vec<T> {
+    let dimensionality = instances.first().map_or(0, Vec::len);
+    let n = T::from(instances.len().max(1));
+    (0..dimensionality)
+        .into_par_iter()
+        .map(|j| instances.iter().fold(T::zero(), |acc, x| acc + x[j]) / n)
+        .collect()
+}
+
+/// Makes `w` orthogonal to each of the `basis` vectors, which must be
+/// orthonormal, and then scales it to unit norm.
+fn orthonormalize<T: Float>(w: &mut [T], basis: &[Vec<T>]) {
+    for b in basis {
+        let p = dot(w, b);
+        for (x, &y) in w.iter_mut().zip(b) {
+            *x -= p * y;
+        }
+    }
+    let norm = dot(w, w).sqrt();
+    if norm > T::zero() {
+        for x in w.iter_mut() {
+            *x /= norm;
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Instrumentation of the work done by search.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use distances::Number;
+
+use crate::{Cluster, Dataset, Instance, PartitionStrategy, Tree};
+
+/// The work done by a single search, as returned by, e.g.,
+/// `knn::Algorithm::search_with_stats`.
+///
+/// These counts do not depend on the machine or its load, so they are a more
+/// reliable basis than wall-clock time for comparing search algorithms.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchStats {
+    /// The number of distances computed from the query.
+    pub distance_computations: usize,
+    /// The number of `Cluster`s whose centers the query was compared to.
+    pub clusters_visited: usize,
+    /// The number of `Cluster`s whose instances all became candidate hits,
+    /// including singletons, for which no distances beyond the one to the
+    /// center are computed. These are leaves, except that the `Sieve` algorithms also scan
+    /// `Cluster`s with at most `k` instances, and `RepeatedRnn` scans any
+    /// `Cluster` entirely within its search radius.
+    pub leaves_scanned: usize,
+}
+
+/// Receives events from the traversal of a `Tree` during search.
+///
+/// With the `profiling` feature, a `Probe` may be given to
+/// `knn::Algorithm::search_with_probe` or `rnn::Algorithm::search_with_probe`
+/// to trace a search, e.g. for a profiler or a dashboard. The events of a
+/// search which is spread across threads may arrive from any of them, and in
+/// any order. All events are ignored by default, and the unit type ignores all
+/// of them, so search without a `Probe` costs nothing extra.
+pub trait Probe: Sync {
+    /// Called when the query is compared to the center of a `Cluster`.
+    fn on_cluster_visited<U: Number, C: Cluster<U>>(&self, c: &C) {
+        let _ = c;
+    }
+
+    /// Called when the query is compared to all instances of a `Cluster`.
+    fn on_leaf_scanned<U: Number, C: Cluster<U>>(&self, c: &C) {
+        let _ = c;
+    }
+
+    /// Called with the number of distances about to be computed.
+    fn on_distances(&self, count: usize) {
+        let _ = count;
+    }
+}
+
+impl Probe for () {}
+
+/// Computes distances for the search algorithms, reporting them to a `Probe`
+/// before computing them.
+pub(super) trait ProbeExt: Probe {
+    /// Computes the distance from the query to the center of a `Cluster`.
+    fn distance_to_center<I, U, D, C>(&self, c: &C, data: &D, query: &I) -> U
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        self.on_cluster_visited(c);
+        self.on_distances(1);
+        c.distance_to_instance(data, query)
+    }
+
+    /// Computes the distances from the query to all instances of a `Cluster`
+    /// which have not been removed from the `tree`.
+    ///
+    /// The distance `d` from the query to the center of the `Cluster` is used
+    /// for all instances of a singleton.
+    fn distances_to_leaf<I, U, D, C>(&self, c: &C, d: U, tree: &Tree<I, U, D, C>, query: &I) -> Vec<(usize, U)>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        self.on_leaf_scanned(c);
+        let indices = tree.live_indices(c);
+        if c.is_singleton() {
+            indices.map(|i| (i, d)).collect()
+        } else {
+            let indices = indices.collect::<Vec<_>>();
+            let distances = self.distances_to(tree.data(), query, &indices);
+            indices.into_iter().zip(distances).collect()
+        }
+    }
+
+    /// Computes the distances from the query to the instances at `indices`.
+    fn distances_to<I, U, D>(&self, data: &D, query: &I, indices: &[usize]) -> Vec<U>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+    {
+        self.on_distances(indices.len());
+        data.query_to_many(query, indices)
+    }
+
+    /// Computes the distances from the query to those instances at `indices`
+    /// which may be within `threshold` of it.
+    ///
+    /// If the dataset has a lower bound on its metric, the instances whose
+    /// lower bound exceeds the `threshold` are skipped, and only the other
+    /// distances are computed and reported. Without a `threshold`, every
+    /// distance is computed.
+    fn distances_within<I, U, D>(
+        &self,
+        data: &D,
+        query: &I,
+        indices: Vec<usize>,
+        threshold: Option<U>,
+    ) -> Vec<(usize, U)>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+    {
+        let indices = match (data.lower_bound(), threshold) {
+            (Some(lower_bound), Some(threshold)) => indices
+                .into_iter()
+                .filter(|&i| lower_bound(query, &data.get(i)) <= threshold)
+                .collect(),
+            _ => indices,
+        };
+        let distances = self.distances_to(data, query, &indices);
+        indices.into_iter().zip(distances).collect()
+    }
+
+    /// Computes the distances from the query to those instances at `indices`
+    /// which may be among its `k` nearest neighbors, given that those are all
+    /// within `threshold` of it.
+    ///
+    /// If the dataset has an upper bound on its metric, and there are at
+    /// least `k` instances, the threshold is tightened to the `k`-th smallest
+    /// upper bound before instances are skipped with `distances_within`.
+    fn nearest_within<I, U, D>(
+        &self,
+        data: &D,
+        query: &I,
+        indices: Vec<usize>,
+        k: usize,
+        threshold: Option<U>,
+    ) -> Vec<(usize, U)>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+    {
+        let threshold = match data.upper_bound() {
+            Some(upper_bound) if data.lower_bound().is_some() && k > 0 && indices.len() >= k => {
+                let mut bounds = indices
+                    .iter()
+                    .map(|&i| upper_bound(query, &data.get(i)))
+                    .collect::<Vec<_>>();
+                let (_, &mut tau, _) =
+                    bounds.select_nth_unstable_by(k - 1, |a, b| a.partial_cmp(b).unwrap_or(core::cmp::Ordering::Less));
+                Some(threshold.map_or(tau, |t| if tau < t { tau } else { t }))
+            }
+            _ => threshold,
+        };
+        self.distances_within(data, query, indices, threshold)
+    }
+
+    /// Computes the distances from the query to the instances of a leaf, as
+    /// `distances_to_leaf` does, but skips instances with `nearest_within`.
+    ///
+    /// The instances of a singleton are all at the distance `d`, so at most
+    /// `k` of them are returned.
+    fn distances_to_leaf_within<I, U, D, C>(
+        &self,
+        c: &C,
+        d: U,
+        tree: &Tree<I, U, D, C>,
+        query: &I,
+        k: usize,
+        threshold: Option<U>,
+    ) -> Vec<(usize, U)>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        self.on_leaf_scanned(c);
+        let indices = tree.live_indices(c);
+        if c.is_singleton() {
+            indices.take(k).map(|i| (i, d)).collect()
+        } else {
+            self.nearest_within(tree.data(), query, indices.collect(), k, threshold)
+        }
+    }
+
+    /// Returns the children of a `Cluster` which may overlap the query ball,
+    /// as `Cluster::overlapping_children` does.
+    fn overlapping_children<'a, I, U, D, C>(&self, c: &'a C, data: &D, query: &I, radius: U) -> Vec<&'a C>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        // The query is compared to the poles of the `Cluster` only if the
+        // children were split by their nearest poles.
+        if c.strategy() == Some(PartitionStrategy::MaxSeparation) {
+            self.on_distances(c.arg_poles().map_or(0, |poles| poles.len()));
+        }
+        c.overlapping_children(data, query, radius)
+    }
+}
+
+impl<P: Probe> ProbeExt for P {}
+
+/// A `Probe` which counts the events of a search, from any number of threads.
+///
+/// The counts accumulate over all searches given the same `Counter`, so one
+/// `Counter` may be shared by many searches to count the distances computed by
+/// all of them.
+#[derive(Debug, Default)]
+pub struct Counter {
+    /// The number of distances computed.
+    distances: AtomicUsize,
+    /// The number of `Cluster`s visited.
+    clusters: AtomicUsize,
+    /// The number of leaves scanned.
+    leaves: AtomicUsize,
+}
+
+impl Counter {
+    /// Returns the counts so far.
+    pub fn stats(&self) -> SearchStats {
+        SearchStats {
+            distance_computations: self.distances.load(Ordering::Relaxed),
+            clusters_visited: self.clusters.load(Ordering::Relaxed),
+            leaves_scanned: self.leaves.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the counts so far, and resets them to zero.
+    #[cfg(feature = "profiling")]
+    pub fn reset(&self) -> SearchStats {
+        SearchStats {
+            distance_computations: self.distances.swap(0, Ordering::Relaxed),
+            clusters_visited: self.clusters.swap(0, Ordering::Relaxed),
+            leaves_scanned: self.leaves.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+impl Probe for Counter {
+    fn on_cluster_visited<U: Number, C: Cluster<U>>(&self, _: &C) {
+        self.clusters.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_leaf_scanned<U: Number, C: Cluster<U>>(&self, _: &C) {
+        self.leaves.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_distances(&self, count: usize) {
+        self.distances.fetch_add(count, Ordering::Relaxed);
+    }
+}
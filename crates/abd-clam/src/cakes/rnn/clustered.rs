@@ -2,7 +2,11 @@
 
 use distances::Number;
 
-use crate::{Cluster, Dataset, Instance, Tree};
+use crate::par::prelude::*;
+use crate::{
+    cakes::probe::{Probe, ProbeExt},
+    Cluster, Dataset, Instance, Tree,
+};
 
 use super::linear;
 
@@ -13,20 +17,115 @@ use super::linear;
 /// * `tree` - The tree to search.
 /// * `query` - The query to search around.
 /// * `radius` - The radius to search within.
+/// * `probe` - Receives the events of the search.
 ///
 /// # Returns
 ///
 /// A vector of 2-tuples, where the first element is the index of the instance
 /// and the second element is the distance from the query to the instance.
-pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, radius: U) -> Vec<(usize, U)>
+pub fn search<I, U, D, C, P>(tree: &Tree<I, U, D, C>, query: &I, radius: U, probe: &P) -> Vec<(usize, U)>
 where
     I: Instance,
     U: Number,
     D: Dataset<I, U>,
     C: Cluster<U>,
+    P: Probe,
 {
-    let [confirmed, straddlers] = tree_search(tree.data(), &tree.root, query, radius);
-    leaf_search(tree.data(), confirmed, straddlers, query, radius)
+    let [confirmed, straddlers] = tree_search(tree.data(), &tree.root, query, radius, probe);
+    leaf_search(tree, confirmed, straddlers, query, radius, probe)
+}
+
+/// Clustered search for the number of neighbors of a query within a radius.
+///
+/// `Cluster`s which are entirely within the query ball are counted wholesale
+/// by their cardinalities, without computing distances to their instances.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search.
+/// * `query` - The query to search around.
+/// * `radius` - The radius to search within.
+///
+/// # Returns
+///
+/// The number of instances, excluding removed instances, within the radius.
+pub fn count<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, radius: U) -> usize
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let [confirmed, straddlers] = tree_search(tree.data(), &tree.root, query, radius, &());
+
+    let num_confirmed = confirmed
+        .into_iter()
+        .map(|(c, _)| c.cardinality() - tree.num_removed_in(c.offset(), c.cardinality()))
+        .sum::<usize>();
+
+    let indices = straddlers
+        .into_iter()
+        .flat_map(|(c, _)| c.indices())
+        .filter(|&i| !tree.is_removed(i))
+        .collect::<Vec<_>>();
+
+    num_confirmed + linear::count(tree.data(), query, radius, &indices)
+}
+
+/// Clustered search for whether any instance lies within a radius of a query.
+///
+/// The tree is traversed depth-first, visiting the closest child first, and
+/// the traversal stops as soon as a single hit is found.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search.
+/// * `query` - The query to search around.
+/// * `radius` - The radius to search within.
+///
+/// # Returns
+///
+/// Whether there is an instance, excluding removed instances, within the radius.
+pub fn exists<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, radius: U) -> bool
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let data = tree.data();
+    let mut stack = vec![(&tree.root, tree.root.distance_to_instance(data, query))];
+
+    while let Some((c, d)) = stack.pop() {
+        if d > (c.radius() + radius) {
+            continue;
+        }
+
+        if d <= radius && !tree.is_removed(c.arg_center()) {
+            return true;
+        }
+
+        if (c.radius() + d) <= radius && tree.num_removed_in(c.offset(), c.cardinality()) < c.cardinality() {
+            return true;
+        }
+
+        if let Some(children) = c.children() {
+            let mut children = children
+                .into_iter()
+                .map(|c| (c, c.distance_to_instance(data, query)))
+                .collect::<Vec<_>>();
+            // Push the farther children first so that the closer children are visited first.
+            children.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(core::cmp::Ordering::Less));
+            stack.extend(children);
+        } else {
+            let indices = c.indices().filter(|&i| !tree.is_removed(i)).collect::<Vec<_>>();
+            if linear::exists(data, query, radius, &indices) {
+                return true;
+            }
+        }
+    }
+
+    false
 }
 
 /// Perform coarse-grained tree search.
@@ -37,6 +136,7 @@ where
 /// * `root` - The root of the tree to search.
 /// * `query` - The query to search around.
 /// * `radius` - The radius to search within.
+/// * `probe` - Receives the events of the search.
 ///
 /// # Returns
 ///
@@ -45,12 +145,13 @@ where
 /// query ball, and the second element is the straddlers, i.e. those that
 /// overlap the query ball. The 2-tuples are the clusters and the distance
 /// from the query to the cluster center.
-pub fn tree_search<'a, I, U, D, C>(data: &D, root: &'a C, query: &I, radius: U) -> [Vec<(&'a C, U)>; 2]
+pub fn tree_search<'a, I, U, D, C, P>(data: &D, root: &'a C, query: &I, radius: U, probe: &P) -> [Vec<(&'a C, U)>; 2]
 where
     I: Instance,
     U: Number,
     D: Dataset<I, U>,
     C: Cluster<U>,
+    P: Probe,
 {
     let mut confirmed = Vec::new();
     let mut straddlers = Vec::new();
@@ -58,9 +159,22 @@ where
 
     let (mut terminal, mut non_terminal): (Vec<_>, Vec<_>);
     while !candidates.is_empty() {
+        // For expensive distance functions, the distances to the centers of
+        // the clusters at each level are computed in parallel.
+        let distances = if data.is_metric_expensive() {
+            candidates
+                .par_iter()
+                .map(|c| probe.distance_to_center(*c, data, query))
+                .collect::<Vec<_>>()
+        } else {
+            candidates
+                .iter()
+                .map(|c| probe.distance_to_center(*c, data, query))
+                .collect::<Vec<_>>()
+        };
         (terminal, non_terminal) = candidates
             .into_iter()
-            .map(|c| (c, c.distance_to_instance(data, query)))
+            .zip(distances)
             .filter(|&(c, d)| d <= (c.radius() + radius))
             .partition(|&(c, d)| (c.radius() + d) <= radius);
         confirmed.append(&mut terminal);
@@ -72,10 +186,10 @@ where
             .into_iter()
             .flat_map(|(c, d)| {
                 if d < c.radius() {
-                    c.overlapping_children(data, query, radius)
+                    probe.overlapping_children(c, data, query, radius)
                 } else {
                     c.children()
-                        .map_or_else(|| unreachable!("Non-leaf cluster without children"), |v| v.to_vec())
+                        .unwrap_or_else(|| unreachable!("Non-leaf cluster without children"))
                 }
             })
             .collect();
@@ -84,33 +198,36 @@ where
     [confirmed, straddlers]
 }
 
-/// Perform fine-grained leaf search
-pub fn leaf_search<I, U, D, C>(
-    data: &D,
+/// Perform fine-grained leaf search, skipping instances which have been
+/// removed from the `tree`.
+pub fn leaf_search<I, U, D, C, P>(
+    tree: &Tree<I, U, D, C>,
     confirmed: Vec<(&C, U)>,
     straddlers: Vec<(&C, U)>,
     query: &I,
     radius: U,
+    probe: &P,
 ) -> Vec<(usize, U)>
 where
     I: Instance,
     U: Number,
     D: Dataset<I, U>,
     C: Cluster<U>,
+    P: Probe,
 {
-    let hits = confirmed.into_iter().flat_map(|(c, d)| {
-        let distances = if c.is_singleton() {
-            vec![d; c.cardinality()]
-        } else {
-            data.query_to_many(query, &c.indices().collect::<Vec<_>>())
-        };
-        c.indices().zip(distances)
-    });
+    let hits = confirmed
+        .into_iter()
+        .flat_map(|(c, d)| probe.distances_to_leaf(c, d, tree, query));
 
     let indices = straddlers
         .into_iter()
-        .flat_map(|(c, _)| c.indices())
+        .flat_map(|(c, _)| {
+            probe.on_leaf_scanned(c);
+            tree.live_indices(c)
+        })
         .collect::<Vec<_>>();
+    let distances = probe.distances_within(tree.data(), query, indices, Some(radius));
 
-    hits.chain(linear::search(data, query, radius, &indices)).collect()
+    hits.chain(distances.into_iter().filter(|&(_, d)| d <= radius))
+        .collect()
 }
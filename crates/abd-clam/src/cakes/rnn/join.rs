@@ -0,0 +1,253 @@
+//! Finding all pairs of instances within a threshold distance of each other.
+
+use std::collections::HashMap;
+
+use distances::Number;
+
+use crate::par::prelude::*;
+use crate::{Cluster, Dataset, Instance, Metric, Tree};
+
+/// Finds all pairs of distinct instances in a `Tree` which are within a
+/// threshold distance of each other.
+///
+/// The tree is traversed against itself. A pair of `Cluster`s is pruned when
+/// the distance between their centers exceeds the sum of their radii plus the
+/// `threshold`, since no instance in one can then be within the `threshold` of
+/// any instance in the other. The distances between the instances in each
+/// remaining pair of leaves are computed in parallel.
+///
+/// The pruning is only valid if the distance function obeys the triangle
+/// inequality. Otherwise, every pair of instances is compared.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search.
+/// * `threshold` - The largest distance between the instances in a pair.
+///
+/// # Returns
+///
+/// A vector of 3-tuples `(i, j, d)` with `i < j`, where `i` and `j` are the
+/// indices of the instances and `d` is the distance between them. Instances
+/// which have been removed from the `tree` are never returned.
+pub fn pairs_within<I, U, D, C>(tree: &Tree<I, U, D, C>, threshold: U) -> Vec<(usize, usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let data = tree.data();
+    let live = |c: &C| c.indices().filter(|&i| !tree.is_removed(i)).collect::<Vec<_>>();
+
+    let (leaves, blocks) = traverse(tree, tree, threshold, true);
+    leaves
+        .into_par_iter()
+        .flat_map(|c| {
+            let indices = live(c);
+            let distances = data.pairwise_submatrix(&indices);
+            indices
+                .iter()
+                .enumerate()
+                .flat_map(|(i, &p)| indices[(i + 1)..].iter().map(move |&q| (p, q)))
+                .zip(distances)
+                .filter(|&(_, d)| d <= threshold)
+                .map(|((p, q), d)| (p, q, d))
+                .collect::<Vec<_>>()
+        })
+        .chain(blocks.into_par_iter().flat_map(|(a, b)| {
+            let (left, right) = (live(a), live(b));
+            join_block(data, &left, data, &right, threshold)
+                .into_iter()
+                .map(|(p, q, d)| if p < q { (p, q, d) } else { (q, p, d) })
+                .collect::<Vec<_>>()
+        }))
+        .collect()
+}
+
+/// Finds all pairs of instances, one from each of two `Tree`s, which are
+/// within a threshold distance of each other.
+///
+/// The two trees are traversed against each other, with the same pruning as
+/// `pairs_within`. The trees must have the same distance function.
+///
+/// # Arguments
+///
+/// * `left` - The tree of the first instance in each pair.
+/// * `right` - The tree of the second instance in each pair.
+/// * `threshold` - The largest distance between the instances in a pair.
+///
+/// # Returns
+///
+/// A vector of 3-tuples `(i, j, d)`, where `i` is the index of an instance in
+/// `left`, `j` is the index of an instance in `right` and `d` is the distance
+/// between them. Instances which have been removed from either tree are never
+/// returned.
+pub fn pairs_between<I, U, D, C>(
+    left: &Tree<I, U, D, C>,
+    right: &Tree<I, U, D, C>,
+    threshold: U,
+) -> Vec<(usize, usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let (_, blocks) = traverse(left, right, threshold, false);
+    blocks
+        .into_par_iter()
+        .flat_map(|(a, b)| {
+            let l = a.indices().filter(|&i| !left.is_removed(i)).collect::<Vec<_>>();
+            let r = b.indices().filter(|&i| !right.is_removed(i)).collect::<Vec<_>>();
+            join_block(left.data(), &l, right.data(), &r, threshold)
+        })
+        .collect()
+}
+
+/// Groups instances which are linked by a chain of pairs, e.g. as found by
+/// `pairs_within`.
+///
+/// Two instances are in the same group if there is a sequence of pairs
+/// leading from one to the other, i.e. the groups are the connected
+/// components of the graph whose edges are the `pairs`. Instances which are
+/// in no pair are in no group.
+///
+/// # Arguments
+///
+/// * `pairs` - The 3-tuples `(i, j, d)` linking the instances `i` and `j`.
+///
+/// # Returns
+///
+/// The groups, each with at least two indices in increasing order, sorted by
+/// their first index.
+pub fn group_pairs<U: Number>(pairs: &[(usize, usize, U)]) -> Vec<Vec<usize>> {
+    // A union-find forest, where each index points towards the root of its
+    // group.
+    let mut parents = HashMap::<usize, usize>::new();
+    let root = |parents: &mut HashMap<usize, usize>, mut i: usize| loop {
+        let p = parents.get(&i).copied().unwrap_or(i);
+        if p == i {
+            break i;
+        }
+        // Path halving keeps the trees shallow.
+        let g = parents.get(&p).copied().unwrap_or(p);
+        parents.insert(i, g);
+        i = g;
+    };
+
+    for &(i, j, _) in pairs {
+        parents.entry(i).or_insert(i);
+        parents.entry(j).or_insert(j);
+        let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+        if a != b {
+            parents.insert(a.max(b), a.min(b));
+        }
+    }
+
+    let mut indices = parents.keys().copied().collect::<Vec<_>>();
+    indices.sort_unstable();
+
+    let mut groups = HashMap::<usize, Vec<usize>>::new();
+    for i in indices {
+        let r = root(&mut parents, i);
+        groups.entry(r).or_default().push(i);
+    }
+
+    let mut groups = groups.into_values().collect::<Vec<_>>();
+    groups.sort_unstable_by_key(|g| g[0]);
+    groups
+}
+
+/// The pairs of leaves found by `traverse`.
+///
+/// The first element holds the leaves which must be compared with themselves,
+/// and the second holds the pairs of distinct leaves which must be compared
+/// with each other.
+type Blocks<'a, C> = (Vec<&'a C>, Vec<(&'a C, &'a C)>);
+
+/// Traverses two trees against each other, returning the pairs of leaves which
+/// cannot be pruned.
+///
+/// If `same` is `true`, the trees must be the same, and each unordered pair of
+/// `Cluster`s is visited only once.
+fn traverse<'a, I, U, D, C>(
+    left: &'a Tree<I, U, D, C>,
+    right: &'a Tree<I, U, D, C>,
+    threshold: U,
+    same: bool,
+) -> Blocks<'a, C>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let is_metric = left.data().is_metric();
+    let metric = left.data().metric();
+
+    let mut leaves = Vec::new();
+    let mut blocks = Vec::new();
+    let mut stack = vec![(left.root(), right.root())];
+    while let Some((a, b)) = stack.pop() {
+        if same && a.offset() == b.offset() {
+            match a.children() {
+                Some(children) => {
+                    for (i, &c) in children.iter().enumerate() {
+                        stack.extend(children[i..].iter().map(|&o| (c, o)));
+                    }
+                }
+                None => leaves.push(a),
+            }
+            continue;
+        }
+
+        let d = metric.distance(&left.center_of(a), &right.center_of(b));
+        if is_metric && d > a.radius() + b.radius() + threshold {
+            continue;
+        }
+
+        // The larger of the two `Cluster`s is split, unless it is a leaf.
+        let split_left = match (a.is_leaf(), b.is_leaf()) {
+            (true, true) => {
+                blocks.push((a, b));
+                continue;
+            }
+            (false, true) => true,
+            (true, false) => false,
+            (false, false) => a.radius() >= b.radius(),
+        };
+        if split_left {
+            stack.extend(a.children().into_iter().flatten().map(|c| (c, b)));
+        } else {
+            stack.extend(b.children().into_iter().flatten().map(|c| (a, c)));
+        }
+    }
+
+    (leaves, blocks)
+}
+
+/// Computes the distances between the instances at `left` in one dataset and
+/// those at `right` in another, keeping the pairs within the `threshold`.
+fn join_block<I, U, D>(
+    left_data: &D,
+    left: &[usize],
+    right_data: &D,
+    right: &[usize],
+    threshold: U,
+) -> Vec<(usize, usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+{
+    left.iter()
+        .flat_map(|&p| {
+            right_data
+                .query_to_many(&left_data.get(p), right)
+                .into_iter()
+                .zip(right.iter())
+                .filter(|&(d, _)| d <= threshold)
+                .map(move |(d, &q)| (p, q, d))
+        })
+        .collect()
+}
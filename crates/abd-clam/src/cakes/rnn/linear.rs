@@ -31,3 +31,50 @@ where
         .filter(|&(_, d)| d <= radius)
         .collect()
 }
+
+/// Linear search for the number of neighbors of a query within a radius.
+///
+/// # Arguments
+///
+/// * `data` - The dataset to search.
+/// * `query` - The query to search around.
+/// * `radius` - The radius to search within.
+/// * `indices` - The indices to search.
+///
+/// # Returns
+///
+/// The number of instances within the radius.
+pub fn count<I, U, D>(data: &D, query: &I, radius: U, indices: &[usize]) -> usize
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+{
+    data.query_to_many(query, indices)
+        .into_iter()
+        .filter(|&d| d <= radius)
+        .count()
+}
+
+/// Linear search for whether any instance lies within a radius of a query.
+///
+/// Distances are computed one at a time, stopping at the first hit.
+///
+/// # Arguments
+///
+/// * `data` - The dataset to search.
+/// * `query` - The query to search around.
+/// * `radius` - The radius to search within.
+/// * `indices` - The indices to search.
+///
+/// # Returns
+///
+/// Whether any of the instances is within the radius.
+pub fn exists<I, U, D>(data: &D, query: &I, radius: U, indices: &[usize]) -> bool
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+{
+    indices.iter().any(|&i| data.query_to_one(query, i) <= radius)
+}
@@ -8,11 +8,16 @@
 
 use distances::Number;
 
+use crate::cakes::probe::Probe;
+use crate::par::prelude::*;
 use crate::{Cluster, Dataset, Instance, Tree};
 
 pub(crate) mod clustered;
+pub(crate) mod join;
 pub(crate) mod linear;
 
+pub use join::{group_pairs, pairs_between, pairs_within};
+
 /// The algorithm to use for Ranged Nearest Neighbor search.
 ///
 /// The default is `Clustered`, as determined by the benchmarks in the crate.
@@ -48,6 +53,7 @@ impl Algorithm {
     ///
     /// A vector of 2-tuples, where the first element is the index of the instance
     /// and the second element is the distance from the query to the instance.
+    /// Instances which have been removed from the `tree` are never returned.
     pub fn search<I, U, D, C>(self, query: &I, radius: U, tree: &Tree<I, U, D, C>) -> Vec<(usize, U)>
     where
         I: Instance,
@@ -55,15 +61,208 @@ impl Algorithm {
         D: Dataset<I, U>,
         C: Cluster<U>,
     {
-        match self {
+        self.search_probed(query, radius, tree, &())
+    }
+
+    /// Searches for the nearest neighbors of a query, reporting the events of
+    /// the search to a `Probe`.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query to search around.
+    /// * `radius` - The radius to search within.
+    /// * `tree` - The tree to search.
+    /// * `probe` - Receives the events of the search.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as returned by `search`.
+    #[cfg(feature = "profiling")]
+    pub fn search_with_probe<I, U, D, C, P>(
+        self,
+        query: &I,
+        radius: U,
+        tree: &Tree<I, U, D, C>,
+        probe: &P,
+    ) -> Vec<(usize, U)>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+        P: Probe,
+    {
+        self.search_probed(query, radius, tree, probe)
+    }
+
+    /// Searches for the nearest neighbors of a query, and checks the hits
+    /// against those of `Linear` search.
+    ///
+    /// This is meant for catching pruning bugs, e.g. with a metric which does
+    /// not obey the triangle inequality although it is declared to. It does
+    /// the work of a linear search for every query.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query to search around.
+    /// * `radius` - The radius to search within.
+    /// * `tree` - The tree to search.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as returned by `search`.
+    ///
+    /// # Errors
+    ///
+    /// * If a hit is repeated, or its distance is not that from the query to
+    ///   its instance.
+    /// * If the hits are not the instances within the `radius`. The error
+    ///   lists some of the missing and extra hits.
+    #[cfg(feature = "verify")]
+    pub fn search_verified<I, U, D, C>(
+        self,
+        query: &I,
+        radius: U,
+        tree: &Tree<I, U, D, C>,
+    ) -> Result<Vec<(usize, U)>, String>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        let hits = self.search(query, radius, tree);
+        let expected = Self::Linear.search(query, radius, tree);
+        crate::cakes::verify::rnn(self.name(), tree.data(), query, radius, &hits, &expected)?;
+        Ok(hits)
+    }
+
+    /// Searches for the nearest neighbors of a query, reporting the events of
+    /// the search to the `probe`.
+    fn search_probed<I, U, D, C, P>(self, query: &I, radius: U, tree: &Tree<I, U, D, C>, probe: &P) -> Vec<(usize, U)>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+        P: Probe,
+    {
+        match self.adapted_to(tree.data()) {
             Self::Linear => {
-                let indices = (0..tree.cardinality()).collect::<Vec<_>>();
+                let indices = (0..tree.cardinality())
+                    .filter(|&i| !tree.is_removed(i))
+                    .collect::<Vec<_>>();
+                probe.on_distances(indices.len());
                 linear::search(tree.data(), query, radius, &indices)
             }
-            Self::Clustered => clustered::search(tree, query, radius),
+            Self::Clustered => clustered::search(tree, query, radius, probe),
         }
     }
 
+    /// Counts the neighbors of a query within a radius, without collecting
+    /// the hits.
+    ///
+    /// This is cheaper than `search` when only the number of neighbors is
+    /// needed, e.g. for density estimation, because `Clustered` counts any
+    /// `Cluster` which is entirely within the radius by its cardinality.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query to search around.
+    /// * `radius` - The radius to search within.
+    /// * `tree` - The tree to search.
+    ///
+    /// # Returns
+    ///
+    /// The number of instances within the radius. Instances which have been
+    /// removed from the `tree` are not counted.
+    pub fn count<I, U, D, C>(self, query: &I, radius: U, tree: &Tree<I, U, D, C>) -> usize
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        match self.adapted_to(tree.data()) {
+            Self::Linear => {
+                let indices = (0..tree.cardinality())
+                    .filter(|&i| !tree.is_removed(i))
+                    .collect::<Vec<_>>();
+                linear::count(tree.data(), query, radius, &indices)
+            }
+            Self::Clustered => clustered::count(tree, query, radius),
+        }
+    }
+
+    /// Checks whether any instance lies within a radius of a query.
+    ///
+    /// This terminates as soon as a single hit is found, making it much
+    /// cheaper than `search` for, e.g., deduplication checks.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query to search around.
+    /// * `radius` - The radius to search within.
+    /// * `tree` - The tree to search.
+    ///
+    /// # Returns
+    ///
+    /// Whether there is an instance within the radius. Instances which have
+    /// been removed from the `tree` are ignored.
+    pub fn exists<I, U, D, C>(self, query: &I, radius: U, tree: &Tree<I, U, D, C>) -> bool
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        match self.adapted_to(tree.data()) {
+            Self::Linear => {
+                let indices = (0..tree.cardinality())
+                    .filter(|&i| !tree.is_removed(i))
+                    .collect::<Vec<_>>();
+                linear::exists(tree.data(), query, radius, &indices)
+            }
+            Self::Clustered => clustered::exists(tree, query, radius),
+        }
+    }
+
+    /// Returns the algorithm to use for the properties of the metric of `data`.
+    ///
+    /// `Clustered` search prunes `Cluster`s with the triangle inequality, so it
+    /// falls back to `Linear` search for non-metric distance functions.
+    fn adapted_to<I: Instance, U: Number, D: Dataset<I, U>>(self, data: &D) -> Self {
+        if data.is_metric() {
+            self
+        } else {
+            Self::Linear
+        }
+    }
+
+    /// Searches for the ranged nearest neighbors of a batch of queries.
+    ///
+    /// The queries are searched in parallel.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The queries to search around.
+    /// * `radius` - The radius to search within.
+    /// * `tree` - The tree to search.
+    ///
+    /// # Returns
+    ///
+    /// A vector of vectors of 2-tuples, one inner vector for each query, in the
+    /// same order as the `queries`.
+    pub fn batch_search<I, U, D, C>(self, queries: &[&I], radius: U, tree: &Tree<I, U, D, C>) -> Vec<Vec<(usize, U)>>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        queries.par_iter().map(|&query| self.search(query, radius, tree)).collect()
+    }
+
     /// Returns the name of the algorithm.
     #[must_use]
     pub const fn name(&self) -> &str {
@@ -1,9 +1,12 @@
 //! Supplies the `Search` trait.
 
+use core::cmp::Ordering;
+
 use std::path::Path;
 
 use distances::Number;
 
+use crate::par::prelude::*;
 use crate::{cakes::knn, cakes::rnn, Dataset, Instance};
 
 /// A trait for performing RNN- and KNN-Search.
@@ -65,6 +68,48 @@ pub trait Search<I: Instance, U: Number, D: Dataset<I, U>>: Send + Sync {
     /// distance to the query.
     fn rnn_search(&self, query: &I, radius: U, algo: rnn::Algorithm) -> Vec<(usize, U)>;
 
+    /// Performs RNN-Search on a batch of queries.
+    ///
+    /// The default implementation searches the queries in parallel.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The query instances.
+    /// * `radius` - The radius to use for the search.
+    /// * `algo` - The algorithm to use for the search.
+    ///
+    /// # Returns
+    ///
+    /// A vector of vectors of 2-tuples, one for each query, in the same order
+    /// as the `queries`.
+    fn batch_rnn_search(&self, queries: &[&I], radius: U, algo: rnn::Algorithm) -> Vec<Vec<(usize, U)>> {
+        queries.par_iter().map(|q| self.rnn_search(q, radius, algo)).collect()
+    }
+
+    /// Counts the neighbors of a query within a radius, without collecting
+    /// the hits.
+    ///
+    /// The default implementation performs an RNN-Search and counts the hits.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `radius` - The radius to use for the search.
+    /// * `algo` - The algorithm to use for the search.
+    fn rnn_count(&self, query: &I, radius: U, algo: rnn::Algorithm) -> usize {
+        self.rnn_search(query, radius, algo).len()
+    }
+
+    /// Checks whether any instance lies within a radius of a query, stopping
+    /// at the first hit.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `radius` - The radius to use for the search.
+    /// * `algo` - The algorithm to use for the search.
+    fn rnn_exists(&self, query: &I, radius: U, algo: rnn::Algorithm) -> bool;
+
     /// Performs RNN-Search using the naive linear algorithm.
     fn linear_rnn_search(&self, query: &I, radius: U) -> Vec<(usize, U)>;
 
@@ -87,6 +132,59 @@ pub trait Search<I: Instance, U: Number, D: Dataset<I, U>>: Send + Sync {
     /// distance to the query.
     fn knn_search(&self, query: &I, k: usize, algo: knn::Algorithm) -> Vec<(usize, U)>;
 
+    /// Performs a KNN-Search among the instances which pass a filter.
+    ///
+    /// The default implementation repeats `knn_search`, with the tuned
+    /// algorithm, for twice as many neighbors each time, until `k` of them
+    /// pass the filter or every instance has been searched.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of neighbors to search for.
+    /// * `filter` - A predicate on the indices of instances.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 2-tuples containing the index of the instance and its
+    /// distance to the query.
+    fn knn_search_filtered(&self, query: &I, k: usize, filter: &(dyn Fn(usize) -> bool + Sync)) -> Vec<(usize, U)> {
+        let cardinality = self.shard_cardinalities().into_iter().sum::<usize>();
+        let algo = self.tuned_knn_algorithm();
+        let mut n = k.min(cardinality);
+        loop {
+            let mut hits = self
+                .knn_search(query, n, algo)
+                .into_iter()
+                .filter(|&(i, _)| filter(i))
+                .collect::<Vec<_>>();
+            if hits.len() >= k || n == cardinality {
+                hits.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Greater));
+                hits.truncate(k);
+                return hits;
+            }
+            n = (n * 2).min(cardinality);
+        }
+    }
+
+    /// Performs KNN-Search on a batch of queries.
+    ///
+    /// The default implementation searches the queries in parallel.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The query instances.
+    /// * `k` - The number of neighbors to search for.
+    /// * `algo` - The algorithm to use for the search.
+    ///
+    /// # Returns
+    ///
+    /// A vector of vectors of 2-tuples, one for each query, in the same order
+    /// as the `queries`.
+    fn batch_knn_search(&self, queries: &[&I], k: usize, algo: knn::Algorithm) -> Vec<Vec<(usize, U)>> {
+        queries.par_iter().map(|q| self.knn_search(q, k, algo)).collect()
+    }
+
     /// Auto-tunes the RNN-Search algorithm and sets it as the best.
     ///
     /// # Arguments
@@ -106,17 +204,44 @@ pub trait Search<I: Instance, U: Number, D: Dataset<I, U>>: Send + Sync {
     /// Performs KNN-Search using the naive linear algorithm.
     fn linear_knn_search(&self, query: &I, k: usize) -> Vec<(usize, U)>;
 
+    /// Benchmarks every KNN- and RNN-Search algorithm on a sample of queries
+    /// and remembers the fastest for the regime of `k`.
+    ///
+    /// The RNN regime is the mean distance from the queries to their `k`-th
+    /// nearest neighbors. The fastest algorithms also become the defaults
+    /// returned by `tuned_knn_algorithm` and `tuned_rnn_algorithm`.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The sample of queries to benchmark with.
+    /// * `k` - The number of neighbors to tune for.
+    fn auto_tune(&mut self, queries: &[&I], k: usize);
+
+    /// Returns the best KNN-Search algorithm for the tuned regime closest to `k`.
+    ///
+    /// If no regime has been tuned, this will return the algorithm tuned by
+    /// `auto_tune_knn`, if any, or else the one planned from the statistics of
+    /// the tree.
+    fn tuned_knn_algorithm_for(&self, k: usize) -> knn::Algorithm;
+
+    /// Returns the best RNN-Search algorithm for the tuned regime closest to `radius`.
+    ///
+    /// If no regime has been tuned, this will return the algorithm tuned by
+    /// `auto_tune_rnn`, if any, or else the one planned from the statistics of
+    /// the tree.
+    fn tuned_rnn_algorithm_for(&self, radius: U) -> rnn::Algorithm;
+
     /// Performs RNN-Search using the best algorithm.
     #[allow(dead_code)]
     fn tuned_rnn_search(&self, query: &I, radius: U) -> Vec<(usize, U)> {
-        let algo = self.tuned_rnn_algorithm();
+        let algo = self.tuned_rnn_algorithm_for(radius);
         self.rnn_search(query, radius, algo)
     }
 
     /// Performs KNN-Search using the best algorithm.
     #[allow(dead_code)]
     fn tuned_knn_search(&self, query: &I, k: usize) -> Vec<(usize, U)> {
-        let algo = self.tuned_knn_algorithm();
+        let algo = self.tuned_knn_algorithm_for(k);
         self.knn_search(query, k, algo)
     }
 }
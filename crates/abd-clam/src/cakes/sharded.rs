@@ -3,9 +3,9 @@
 use core::ops::AddAssign;
 
 use distances::Number;
-use rayon::prelude::*;
 
 use super::{Search, SingleShard};
+use crate::par::prelude::*;
 use crate::{cakes::knn, cakes::rnn, Dataset, Instance};
 
 /// Cakes search with sharded datasets.
@@ -44,8 +44,9 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> RandomlySharded<I, U, D> {
         let offsets = new_shards
             .iter()
             .scan(sample_shard.data().cardinality(), |o, d| {
+                let start = *o;
                 o.add_assign(d.data().cardinality());
-                Some(*o)
+                Some(start)
             })
             .collect::<Vec<_>>();
 
@@ -62,9 +63,23 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> RandomlySharded<I, U, D> {
     }
 
     /// Returns the offsets of the shard indices.
+    ///
+    /// These are the indices at which each shard, other than the sample shard,
+    /// starts.
     pub fn offsets(&self) -> &[usize] {
         &self.offsets
     }
+
+    /// Returns the position, in `shards`, of the shard containing the instance
+    /// at `index`, along with the index of the instance within that shard.
+    pub fn locate(&self, index: usize) -> (usize, usize) {
+        let i = self.offsets.partition_point(|&o| o <= index);
+        if i == 0 {
+            (0, index)
+        } else {
+            (i, index - self.offsets[i - 1])
+        }
+    }
 }
 
 impl<I: Instance, U: Number, D: Dataset<I, U>> Search<I, U, D> for RandomlySharded<I, U, D> {
@@ -161,6 +176,23 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Search<I, U, D> for RandomlyShard
             .collect()
     }
 
+    fn rnn_count(&self, query: &I, radius: U, algo: rnn::Algorithm) -> usize {
+        self.sample_shard.rnn_count(query, radius, algo)
+            + self
+                .shards
+                .par_iter()
+                .map(|shard| shard.rnn_count(query, radius, algo))
+                .sum::<usize>()
+    }
+
+    fn rnn_exists(&self, query: &I, radius: U, algo: rnn::Algorithm) -> bool {
+        self.sample_shard.rnn_exists(query, radius, algo)
+            || self
+                .shards
+                .par_iter()
+                .any(|shard| shard.rnn_exists(query, radius, algo))
+    }
+
     fn linear_rnn_search(&self, query: &I, radius: U) -> Vec<(usize, U)> {
         self.rnn_search(query, radius, rnn::Algorithm::Linear)
     }
@@ -174,14 +206,63 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Search<I, U, D> for RandomlyShard
         let mut hits_queue = knn::Hits::from_vec(k, initial_hits);
 
         for (shard, &o) in self.shards.iter().zip(self.offsets.iter()) {
-            let radius = hits_queue.peek();
-            let new_hits = shard.rnn_search(query, radius, rnn::Algorithm::Clustered);
+            // Until there are `k` hits, the farthest of them does not bound
+            // the distance to the `k`-th nearest neighbor.
+            let new_hits = if hits_queue.len() < k {
+                shard.knn_search(query, k, algo)
+            } else {
+                shard.rnn_search(query, hits_queue.peek(), rnn::Algorithm::Clustered)
+            };
             hits_queue.push_batch(new_hits.into_iter().map(|(i, d)| (i + o, d)));
         }
 
         hits_queue.extract()
     }
 
+    fn knn_search_filtered(&self, query: &I, k: usize, filter: &(dyn Fn(usize) -> bool + Sync)) -> Vec<(usize, U)> {
+        let initial_hits = self.sample_shard.knn_search_filtered(query, k, filter);
+        let mut hits_queue = knn::Hits::from_vec(k, initial_hits);
+
+        for (shard, &o) in self.shards.iter().zip(self.offsets.iter()) {
+            let new_hits = shard.knn_search_filtered(query, k, &|i| filter(i + o));
+            hits_queue.push_batch(new_hits.into_iter().map(|(i, d)| (i + o, d)));
+        }
+
+        hits_queue.extract()
+    }
+
+    fn batch_knn_search(&self, queries: &[&I], k: usize, algo: knn::Algorithm) -> Vec<Vec<(usize, U)>> {
+        // Search the sample shard for the whole batch, then visit each of the
+        // remaining shards once for the whole batch, so that each shard's tree
+        // stays hot in the cache while its queries are being processed.
+        let mut hits_queues = self
+            .sample_shard
+            .batch_knn_search(queries, k, algo)
+            .into_iter()
+            .map(|hits| knn::Hits::from_vec(k, hits))
+            .collect::<Vec<_>>();
+
+        for (shard, &o) in self.shards.iter().zip(self.offsets.iter()) {
+            let new_hits = queries
+                .par_iter()
+                .zip(hits_queues.par_iter())
+                .map(|(query, hits)| {
+                    if hits.len() < k {
+                        shard.knn_search(query, k, algo)
+                    } else {
+                        shard.rnn_search(query, hits.peek(), rnn::Algorithm::Clustered)
+                    }
+                })
+                .collect::<Vec<_>>();
+            hits_queues
+                .iter_mut()
+                .zip(new_hits)
+                .for_each(|(hits, new_hits)| hits.push_batch(new_hits.into_iter().map(|(i, d)| (i + o, d))));
+        }
+
+        hits_queues.into_iter().map(|hits| hits.extract()).collect()
+    }
+
     fn auto_tune_rnn(&mut self, radius: U, tuning_depth: usize) {
         self.sample_shard.auto_tune_rnn(radius, tuning_depth);
     }
@@ -190,6 +271,18 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Search<I, U, D> for RandomlyShard
         self.sample_shard.auto_tune_knn(k, tuning_depth);
     }
 
+    fn auto_tune(&mut self, queries: &[&I], k: usize) {
+        self.sample_shard.auto_tune(queries, k);
+    }
+
+    fn tuned_knn_algorithm_for(&self, k: usize) -> knn::Algorithm {
+        self.sample_shard.tuned_knn_algorithm_for(k)
+    }
+
+    fn tuned_rnn_algorithm_for(&self, radius: U) -> rnn::Algorithm {
+        self.sample_shard.tuned_rnn_algorithm_for(radius)
+    }
+
     fn linear_knn_search(&self, query: &I, k: usize) -> Vec<(usize, U)> {
         let initial_hits = self.sample_shard.knn_search(query, k, knn::Algorithm::Linear);
         let mut hits_queue = knn::Hits::from_vec(k, initial_hits);
@@ -0,0 +1,69 @@
+//! A cheaply cloneable handle for searching one `Cakes` from many threads.
+
+use core::ops::Deref;
+
+use std::sync::Arc;
+
+use distances::Number;
+
+use super::Cakes;
+use crate::{Dataset, Instance};
+
+/// A shared, read-only handle to a `Cakes`.
+///
+/// Cloning the handle only increments a reference count, so each thread of a
+/// server, or each request it handles, may hold its own clone. The handle
+/// dereferences to the `Cakes`, so all searches are available through it.
+/// Searches only read the `Cakes` and hold no locks of their own, so they run
+/// concurrently. The exception is a `LeafStore`, whose searches wait on each
+/// other for the lock on its file and block cache.
+///
+/// Tuning takes `&mut self`, so a `Cakes` should be tuned before it is shared,
+/// or reclaimed with `try_unwrap` once all other handles have been dropped.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct SharedCakes<I: Instance, U: Number, D: Dataset<I, U>>(Arc<Cakes<I, U, D>>);
+
+impl<I: Instance, U: Number, D: Dataset<I, U>> SharedCakes<I, U, D> {
+    /// Shares a `Cakes`.
+    #[must_use]
+    pub fn new(cakes: Cakes<I, U, D>) -> Self {
+        Self(Arc::new(cakes))
+    }
+
+    /// Returns the `Cakes` if this is the only handle to it, or the handle
+    /// itself otherwise.
+    ///
+    /// # Errors
+    ///
+    /// * If there are other handles to the `Cakes`.
+    pub fn try_unwrap(self) -> Result<Cakes<I, U, D>, Self> {
+        Arc::try_unwrap(self.0).map_err(Self)
+    }
+
+    /// Returns the number of handles to the `Cakes`, including this one.
+    #[must_use]
+    pub fn num_handles(&self) -> usize {
+        Arc::strong_count(&self.0)
+    }
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>> Clone for SharedCakes<I, U, D> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>> Deref for SharedCakes<I, U, D> {
+    type Target = Cakes<I, U, D>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>> From<Cakes<I, U, D>> for SharedCakes<I, U, D> {
+    fn from(cakes: Cakes<I, U, D>) -> Self {
+        Self::new(cakes)
+    }
+}
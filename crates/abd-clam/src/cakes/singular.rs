@@ -2,14 +2,17 @@
 
 use core::cmp::Ordering;
 
-use std::path::Path;
+use std::{collections::BTreeMap, path::Path};
 
 use distances::Number;
-use rayon::prelude::*;
 
+use crate::par::prelude::*;
 use crate::{cakes::knn, cakes::rnn, Cluster, Dataset, Instance, PartitionCriterion, Tree, UniBall};
 
-use super::Search;
+use super::{
+    planner::{Planner, TreeStats},
+    Search,
+};
 
 /// CLAM-Accelerated K-nearest-neighbor Entropy-scaling Search.
 ///
@@ -24,10 +27,19 @@ use super::Search;
 pub struct SingleShard<I: Instance, U: Number, D: Dataset<I, U>> {
     /// The tree used for the search.
     tree: Tree<I, U, D, UniBall<U>>,
+    /// The statistics of the tree, with which untuned searches are planned.
+    stats: TreeStats,
+    /// The dimensionality of the instances, if they have one.
+    dim: Option<usize>,
     /// Best rnn-search algorithm.
     best_rnn: Option<rnn::Algorithm>,
     /// Best knn-search algorithm.
     best_knn: Option<knn::Algorithm>,
+    /// Best knn-search algorithm for each value of `k` passed to `auto_tune`.
+    knn_regimes: BTreeMap<usize, knn::Algorithm>,
+    /// Best rnn-search algorithm for each radius tuned by `auto_tune`, sorted
+    /// by radius.
+    rnn_regimes: Vec<(U, rnn::Algorithm)>,
 }
 
 impl<I: Instance, U: Number, D: Dataset<I, U>> SingleShard<I, U, D> {
@@ -39,10 +51,15 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> SingleShard<I, U, D> {
     /// * `seed` - The seed to use for the random number generator.
     /// * `criteria` - The criteria to use for partitioning the tree.
     pub fn new<P: PartitionCriterion<U>>(data: D, seed: Option<u64>, criteria: &P) -> Self {
+        let tree = Tree::build(data, criteria, seed);
         Self {
-            tree: Tree::new(data, seed).partition(criteria, seed),
+            stats: TreeStats::from_tree(&tree),
+            dim: first_dim(&tree),
+            tree,
             best_rnn: None,
             best_knn: None,
+            knn_regimes: BTreeMap::new(),
+            rnn_regimes: Vec::new(),
         }
     }
 
@@ -56,6 +73,17 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> SingleShard<I, U, D> {
         &self.tree
     }
 
+    /// Returns the statistics of the tree, computed when it was built.
+    pub const fn stats(&self) -> &TreeStats {
+        &self.stats
+    }
+
+    /// Returns the dimensionality of the instances, if they have one. See
+    /// `Instance::dim`.
+    pub const fn dim(&self) -> Option<usize> {
+        self.dim
+    }
+
     /// A helper function for sampling query indices for tuning.
     ///
     /// # Arguments
@@ -103,6 +131,15 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Search<I, U, D> for SingleShard<I
         let best_algo_file = path.join("best-algo.txt");
         std::fs::write(best_algo_file, format!("{best_rnn}\n{best_knn}")).map_err(|e| e.to_string())?;
 
+        let regimes = self
+            .knn_regimes
+            .iter()
+            .map(|(k, a)| format!("knn\t{k}\t{}", a.name()))
+            .chain(self.rnn_regimes.iter().map(|(r, a)| format!("rnn\t{r}\t{}", a.name())))
+            .collect::<Vec<_>>();
+        let regimes_file = path.join("tuned-regimes.txt");
+        std::fs::write(regimes_file, regimes.join("\n")).map_err(|e| e.to_string())?;
+
         Ok(())
     }
 
@@ -145,13 +182,40 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Search<I, U, D> for SingleShard<I
             Some(knn::Algorithm::from_name(best_knn)?)
         };
 
+        // The tuned regimes are optional, so that search structures saved
+        // before they were introduced can still be loaded.
+        let mut knn_regimes = BTreeMap::new();
+        let mut rnn_regimes = Vec::new();
+        let regimes_file = path.join("tuned-regimes.txt");
+        if regimes_file.exists() {
+            let contents = std::fs::read_to_string(&regimes_file).map_err(|e| e.to_string())?;
+            for line in contents.lines() {
+                let fields = line.split('\t').collect::<Vec<_>>();
+                match fields.as_slice() {
+                    ["knn", k, name] => {
+                        let k = k.parse::<usize>().map_err(|e| e.to_string())?;
+                        knn_regimes.insert(k, knn::Algorithm::from_name(name)?);
+                    }
+                    ["rnn", radius, name] => {
+                        let radius = U::from(radius.parse::<f64>().map_err(|e| e.to_string())?);
+                        rnn_regimes.push((radius, rnn::Algorithm::from_name(name)?));
+                    }
+                    _ => return Err(format!("Invalid tuned regime: {line}")),
+                }
+            }
+        }
+
         let tree_dir = path.join("tree");
         let tree = Tree::<I, U, D, UniBall<_>>::load(&tree_dir, metric, is_expensive)?;
 
         Ok(Self {
+            stats: TreeStats::from_tree(&tree),
+            dim: first_dim(&tree),
             tree,
             best_rnn,
             best_knn,
+            knn_regimes,
+            rnn_regimes,
         })
     }
 
@@ -167,7 +231,7 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Search<I, U, D> for SingleShard<I
         let queries = self
             .sample_query_indices(tuning_depth)
             .into_iter()
-            .map(|i| &self.data()[i])
+            .map(|i| self.data().get(i))
             .collect::<Vec<_>>();
 
         (self.best_rnn, _, _) = rnn::Algorithm::variants()
@@ -193,6 +257,18 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Search<I, U, D> for SingleShard<I
         algo.search(query, radius, &self.tree)
     }
 
+    fn batch_rnn_search(&self, queries: &[&I], radius: U, algo: rnn::Algorithm) -> Vec<Vec<(usize, U)>> {
+        algo.batch_search(queries, radius, &self.tree)
+    }
+
+    fn rnn_count(&self, query: &I, radius: U, algo: rnn::Algorithm) -> usize {
+        algo.count(query, radius, &self.tree)
+    }
+
+    fn rnn_exists(&self, query: &I, radius: U, algo: rnn::Algorithm) -> bool {
+        algo.exists(query, radius, &self.tree)
+    }
+
     fn linear_rnn_search(&self, query: &I, radius: U) -> Vec<(usize, U)> {
         self.rnn_search(query, radius, rnn::Algorithm::Linear)
     }
@@ -201,7 +277,7 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Search<I, U, D> for SingleShard<I
         let queries = self
             .sample_query_indices(tuning_depth)
             .into_iter()
-            .map(|i| &self.data()[i])
+            .map(|i| self.data().get(i))
             .collect::<Vec<_>>();
 
         (self.best_knn, _, _) = knn::Algorithm::variants()
@@ -223,11 +299,125 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Search<I, U, D> for SingleShard<I
         self.best_knn.unwrap_or_default()
     }
 
+    #[allow(clippy::similar_names)]
+    fn auto_tune(&mut self, queries: &[&I], k: usize) {
+        if queries.is_empty() {
+            return;
+        }
+
+        let knn_algorithms = knn::Algorithm::variants()
+            .iter()
+            .copied()
+            .chain(core::iter::once(knn::Algorithm::Linear))
+            .collect::<Vec<_>>();
+        let (best_knn, hits) = fastest(&knn_algorithms, queries, |query, algo| self.knn_search(query, k, algo));
+
+        // The radius regime that corresponds to `k` is the mean distance from
+        // the queries to their `k`-th nearest neighbors.
+        let radius = hits
+            .iter()
+            .map(|h| {
+                h.iter()
+                    .map(|&(_, d)| d)
+                    .fold(U::zero(), |a, d| if d > a { d } else { a })
+            })
+            .map(Number::as_f64)
+            .sum::<f64>()
+            / queries.len().as_f64();
+        let radius = U::from(radius);
+
+        let rnn_algorithms = rnn::Algorithm::variants()
+            .iter()
+            .copied()
+            .chain(core::iter::once(rnn::Algorithm::Linear))
+            .collect::<Vec<_>>();
+        let (best_rnn, _) = fastest(&rnn_algorithms, queries, |query, algo| {
+            self.rnn_search(query, radius, algo)
+        });
+
+        self.knn_regimes.insert(k, best_knn);
+        self.rnn_regimes.retain(|&(r, _)| r != radius);
+        self.rnn_regimes.push((radius, best_rnn));
+        self.rnn_regimes
+            .sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Greater));
+
+        self.best_knn = Some(best_knn);
+        self.best_rnn = Some(best_rnn);
+    }
+
+    fn tuned_knn_algorithm_for(&self, k: usize) -> knn::Algorithm {
+        self.knn_regimes
+            .iter()
+            .min_by_key(|(&t, _)| t.abs_diff(k))
+            .map(|(_, &a)| a)
+            .or(self.best_knn)
+            .unwrap_or_else(|| Planner::new(self.stats).knn_algorithm(k))
+    }
+
+    fn tuned_rnn_algorithm_for(&self, radius: U) -> rnn::Algorithm {
+        self.rnn_regimes
+            .iter()
+            .min_by(|(a, _), (b, _)| {
+                let [a, b] = [a, b].map(|&r| (r.as_f64() - radius.as_f64()).abs());
+                a.partial_cmp(&b).unwrap_or(Ordering::Greater)
+            })
+            .map(|&(_, a)| a)
+            .or(self.best_rnn)
+            .unwrap_or_else(|| Planner::new(self.stats).rnn_algorithm(radius))
+    }
+
     fn knn_search(&self, query: &I, k: usize, algo: knn::Algorithm) -> Vec<(usize, U)> {
         algo.search(&self.tree, query, k)
     }
 
+    fn knn_search_filtered(&self, query: &I, k: usize, filter: &(dyn Fn(usize) -> bool + Sync)) -> Vec<(usize, U)> {
+        knn::search_filtered(&self.tree, query, k, filter)
+    }
+
+    fn batch_knn_search(&self, queries: &[&I], k: usize, algo: knn::Algorithm) -> Vec<Vec<(usize, U)>> {
+        algo.batch_search(&self.tree, queries, k)
+    }
+
     fn linear_knn_search(&self, query: &I, k: usize) -> Vec<(usize, U)> {
         self.knn_search(query, k, knn::Algorithm::Linear)
     }
 }
+
+/// Returns the dimensionality of the first instance in a tree, if it has one.
+fn first_dim<I: Instance, U: Number, D: Dataset<I, U>>(tree: &Tree<I, U, D, UniBall<U>>) -> Option<usize> {
+    if tree.data().cardinality() == 0 {
+        None
+    } else {
+        tree.data().get(0).dim()
+    }
+}
+
+/// Returns the algorithm which takes the least time to search for all of the
+/// `queries`, along with the results of its search.
+///
+/// # Arguments
+///
+/// * `algorithms` - The algorithms to compare.
+/// * `queries` - The queries to search for.
+/// * `search` - A function which searches for a query with an algorithm.
+fn fastest<I, A, R, F>(algorithms: &[A], queries: &[&I], search: F) -> (A, Vec<R>)
+where
+    I: Instance,
+    A: Copy + Send + Sync,
+    R: Send,
+    F: Fn(&I, A) -> R + Sync,
+{
+    algorithms
+        .iter()
+        .map(|&algo| {
+            let start = std::time::Instant::now();
+            let results = queries.par_iter().map(|query| search(query, algo)).collect::<Vec<_>>();
+            let elapsed = start.elapsed().as_secs_f32();
+            (algo, results, elapsed)
+        })
+        .min_by(|(_, _, a), (_, _, b)| a.partial_cmp(b).unwrap_or(Ordering::Greater))
+        .map_or_else(
+            || unreachable!("There is at least one algorithm."),
+            |(algo, results, _)| (algo, results),
+        )
+}
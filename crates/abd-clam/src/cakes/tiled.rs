@@ -0,0 +1,258 @@
+//! Batch searches which scan each leaf once for all of the queries that reach
+//! it.
+//!
+//! A batch search which runs one query at a time reads the instances of a
+//! leaf from memory once for every query which reaches that leaf. The
+//! searches here first use the `Tree` to find the leaves to scan for every
+//! query, then group the queries by leaf, and scan each leaf in tiles of
+//! `INSTANCE_TILE` instances. A tile is read once and stays in cache while
+//! its distances to every query in the group are computed, in a tight loop
+//! over the tile which the compiler may vectorize when the distance function
+//! is simple enough to inline.
+//!
+//! The searches return the same hits as `rnn::Algorithm::Clustered` and
+//! `knn::Algorithm::Linear`. They help most when many queries fall in the same
+//! region of the dataset, and when the instances are large or are read from
+//! disk, e.g. from a `LeafStore`.
+
+use std::collections::HashMap;
+
+use distances::Number;
+
+use crate::cakes::knn::Hits;
+use crate::cakes::rnn::clustered;
+use crate::par::prelude::*;
+use crate::{Cluster, Dataset, Instance, Metric, Tree};
+
+/// The number of instances in a tile.
+const INSTANCE_TILE: usize = 64;
+
+/// Searches for the ranged nearest neighbors of a batch of queries, scanning
+/// each leaf once for all of the queries which reach it.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search.
+/// * `queries` - The queries to search around.
+/// * `radius` - The radius to search within.
+///
+/// # Returns
+///
+/// The hits of each query, as returned by `rnn::Algorithm::Clustered`, in the
+/// same order as the `queries`.
+pub fn batch_rnn_search<I, U, D, C>(tree: &Tree<I, U, D, C>, queries: &[&I], radius: U) -> Vec<Vec<(usize, U)>>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let data = tree.data();
+
+    // The instances of confirmed `Cluster`s are hits, whatever the rounding
+    // of their distances, as in `rnn::Algorithm::Clustered`.
+    let leaves = queries
+        .par_iter()
+        .map(|&query| {
+            if data.is_metric() {
+                let [confirmed, straddlers] = clustered::tree_search(data, &tree.root, query, radius, &());
+                confirmed
+                    .into_iter()
+                    .map(|(c, _)| (c, true))
+                    .chain(straddlers.into_iter().map(|(c, _)| (c, false)))
+                    .collect::<Vec<_>>()
+            } else {
+                vec![(&tree.root, false)]
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let clusters = leaves
+        .iter()
+        .map(|l| l.iter().map(|&(c, _)| c).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let distances = leaf_distances(tree, queries, &clusters);
+
+    leaves
+        .into_iter()
+        .zip(distances)
+        .map(|(leaves, distances)| {
+            leaves
+                .into_iter()
+                .zip(distances)
+                .flat_map(|((_, confirmed), distances)| {
+                    distances.into_iter().filter(move |&(_, d)| confirmed || d <= radius)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Searches for the `k` nearest neighbors of a batch of queries, scanning
+/// each leaf once for all of the queries which reach it.
+///
+/// The leaves to scan for each query are found by a sieve. At each level of
+/// the tree, the distance from the query to the `k`-th nearest instance is
+/// bounded above by the distances to the `Cluster`s farthest instances, and
+/// every `Cluster` whose nearest instance may be within that bound is kept.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search.
+/// * `queries` - The queries to search around.
+/// * `k` - The number of neighbors to search for.
+///
+/// # Returns
+///
+/// The hits of each query, sorted by increasing distance, in the same order
+/// as the `queries`.
+pub fn batch_knn_search<I, U, D, C>(tree: &Tree<I, U, D, C>, queries: &[&I], k: usize) -> Vec<Vec<(usize, U)>>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let leaves = queries
+        .par_iter()
+        .map(|&query| knn_leaves(tree, query, k))
+        .collect::<Vec<_>>();
+    let distances = leaf_distances(tree, queries, &leaves);
+
+    distances
+        .into_iter()
+        .map(|distances| {
+            let mut hits = Hits::new(k);
+            distances.into_iter().flatten().for_each(|(i, d)| hits.push(i, d));
+            let mut hits = hits.extract();
+            hits.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(core::cmp::Ordering::Greater));
+            hits
+        })
+        .collect()
+}
+
+/// Finds the `Cluster`s whose instances may be among the `k` nearest
+/// neighbors of a query.
+///
+/// Every `Cluster` returned has at least one instance which has not been
+/// removed. If the distance function of the `tree` is not a metric, the root
+/// is returned.
+pub(crate) fn knn_leaves<'a, I, U, D, C>(tree: &'a Tree<I, U, D, C>, query: &I, k: usize) -> Vec<&'a C>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let data = tree.data();
+    let live = |c: &C| c.cardinality() - tree.num_removed_in(c.offset(), c.cardinality());
+    if k == 0 {
+        return Vec::new();
+    }
+
+    let mut clusters = vec![(&tree.root, tree.root.distance_to_instance(data, query))];
+    if data.is_metric() {
+        loop {
+            // The bound on the distance to the `k`-th nearest neighbor, or
+            // `None` if there are fewer than `k` instances left.
+            let mut d_max = clusters
+                .iter()
+                .map(|&(c, d)| (d + c.radius(), live(c)))
+                .collect::<Vec<_>>();
+            d_max.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(core::cmp::Ordering::Less));
+            let mut count = 0;
+            let bound = d_max
+                .into_iter()
+                .find(|&(_, n)| {
+                    count += n;
+                    count >= k
+                })
+                .map(|(d, _)| d);
+
+            clusters.retain(|&(c, d)| {
+                let r = c.radius();
+                let d_min = if d > r { d - r } else { U::zero() };
+                live(c) > 0 && bound.map_or(true, |b| d_min <= b)
+            });
+            if clusters.iter().all(|(c, _)| c.is_leaf()) {
+                break;
+            }
+            clusters = clusters
+                .into_iter()
+                .flat_map(|(c, d)| {
+                    c.children().map_or_else(
+                        || vec![(c, d)],
+                        |children| {
+                            children
+                                .into_iter()
+                                .map(|child| (child, child.distance_to_instance(data, query)))
+                                .collect()
+                        },
+                    )
+                })
+                .collect();
+        }
+    }
+
+    clusters.into_iter().map(|(c, _)| c).collect()
+}
+
+/// Computes the distances from each query to the instances, which have not
+/// been removed, of each of its `Cluster`s.
+///
+/// The queries are grouped by `Cluster`, and the `Cluster`s are scanned in
+/// parallel, each in tiles of instances.
+///
+/// # Returns
+///
+/// For each query, and for each of its `Cluster`s in order, the indices of the
+/// instances in the `Cluster` and their distances to the query.
+fn leaf_distances<I, U, D, C>(tree: &Tree<I, U, D, C>, queries: &[&I], clusters: &[Vec<&C>]) -> Vec<Vec<Vec<(usize, U)>>>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    // The queries which reach each `Cluster`, with the position of the
+    // `Cluster` among those of the query.
+    let mut groups = HashMap::<(usize, usize), Vec<(usize, usize)>>::new();
+    for (q, clusters) in clusters.iter().enumerate() {
+        for (p, c) in clusters.iter().enumerate() {
+            groups.entry((c.offset(), c.cardinality())).or_default().push((q, p));
+        }
+    }
+
+    let data = tree.data();
+    let metric = data.metric();
+    let scanned = groups
+        .into_par_iter()
+        .map(|((offset, cardinality), members)| {
+            let indices = (offset..(offset + cardinality))
+                .filter(|&i| !tree.is_removed(i))
+                .collect::<Vec<_>>();
+            let mut distances = vec![Vec::with_capacity(indices.len()); members.len()];
+
+            for tile in indices.chunks(INSTANCE_TILE) {
+                let instances = tile.iter().map(|&i| data.get(i)).collect::<Vec<_>>();
+                for (&(q, _), row) in members.iter().zip(distances.iter_mut()) {
+                    let query = queries[q];
+                    row.extend(
+                        tile.iter()
+                            .zip(&instances)
+                            .map(|(&i, x)| (i, metric.distance(query, x))),
+                    );
+                }
+            }
+
+            members.into_iter().zip(distances).collect::<Vec<_>>()
+        })
+        .flatten()
+        .collect::<Vec<_>>();
+
+    let mut distances = clusters.iter().map(|c| vec![Vec::new(); c.len()]).collect::<Vec<_>>();
+    for ((q, p), row) in scanned {
+        distances[q][p] = row;
+    }
+    distances
+}
@@ -0,0 +1,167 @@
+//! Cross-checks of search results against `Linear` search.
+//!
+//! These are used by `knn::Algorithm::search_verified` and
+//! `rnn::Algorithm::search_verified`, which are only available with the
+//! `verify` feature. They catch pruning bugs on real metrics, at the cost of a
+//! linear search for every query.
+
+use core::cmp::Ordering;
+
+use std::collections::HashSet;
+
+use distances::Number;
+
+use crate::{Dataset, Instance};
+
+/// The most indices to list in an error message.
+const MAX_LISTED: usize = 10;
+
+/// The relative tolerance within which two distances are considered equal.
+const TOLERANCE: f64 = 1e-6;
+
+/// Checks the hits of a KNN search against those of a `Linear` search.
+///
+/// The hits must have distinct indices and correct distances, and their
+/// distances must be those of the true `k` nearest neighbors. Instances at the
+/// same distance are interchangeable, so the indices themselves are not
+/// compared.
+///
+/// # Arguments
+///
+/// * `name` - The name of the algorithm which found the `hits`.
+/// * `data` - The dataset which was searched.
+/// * `query` - The query which was searched around.
+/// * `hits` - The hits of the search.
+/// * `expected` - The hits of a `Linear` search for the same `k`.
+///
+/// # Errors
+///
+/// * If any of the checks fail, describing the first failure.
+pub fn knn<I: Instance, U: Number, D: Dataset<I, U>>(
+    name: &str,
+    data: &D,
+    query: &I,
+    hits: &[(usize, U)],
+    expected: &[(usize, U)],
+) -> Result<(), String> {
+    check_hits(name, data, query, hits)?;
+
+    if hits.len() != expected.len() {
+        return Err(format!(
+            "{name} found {} hits, but Linear search found {}",
+            hits.len(),
+            expected.len()
+        ));
+    }
+
+    let (actual, truth) = (sorted_distances(hits), sorted_distances(expected));
+    if let Some(rank) = actual.iter().zip(&truth).position(|(&a, &e)| !is_close(a, e)) {
+        // The true neighbors which are nearer than the farthest hit, but were
+        // not found.
+        let farthest = actual.last().copied().unwrap_or_else(U::zero);
+        let missing = expected
+            .iter()
+            .filter(|&&(i, d)| d < farthest && !hits.iter().any(|&(j, _)| j == i))
+            .take(MAX_LISTED)
+            .collect::<Vec<_>>();
+        return Err(format!(
+            "{name} found a hit at distance {} for rank {rank}, where Linear search found one at distance {}. \
+             Some true neighbors which are missing: {missing:?}",
+            actual[rank], truth[rank]
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks the hits of an RNN search against those of a `Linear` search.
+///
+/// The hits must have distinct indices and correct distances, and must be
+/// exactly the instances found by `Linear` search. Instances at the `radius`,
+/// up to `TOLERANCE`, may be found by either search and not the other.
+///
+/// # Arguments
+///
+/// * `name` - The name of the algorithm which found the `hits`.
+/// * `data` - The dataset which was searched.
+/// * `query` - The query which was searched around.
+/// * `radius` - The radius which was searched within.
+/// * `hits` - The hits of the search.
+/// * `expected` - The hits of a `Linear` search for the same radius.
+///
+/// # Errors
+///
+/// * If any of the checks fail, describing the first failure.
+pub fn rnn<I: Instance, U: Number, D: Dataset<I, U>>(
+    name: &str,
+    data: &D,
+    query: &I,
+    radius: U,
+    hits: &[(usize, U)],
+    expected: &[(usize, U)],
+) -> Result<(), String> {
+    check_hits(name, data, query, hits)?;
+
+    let actual = hits.iter().map(|&(i, _)| i).collect::<HashSet<_>>();
+    let truth = expected.iter().map(|&(i, _)| i).collect::<HashSet<_>>();
+    let missing = expected
+        .iter()
+        .filter(|&&(i, d)| !actual.contains(&i) && !is_close(d, radius))
+        .take(MAX_LISTED)
+        .collect::<Vec<_>>();
+    let extra = hits
+        .iter()
+        .filter(|&&(i, d)| !truth.contains(&i) && !is_close(d, radius))
+        .take(MAX_LISTED)
+        .collect::<Vec<_>>();
+    if !(missing.is_empty() && extra.is_empty()) {
+        return Err(format!(
+            "{name} found {} hits, but Linear search found {}. Some which are missing: {missing:?}. \
+             Some which should not have been found: {extra:?}",
+            hits.len(),
+            expected.len()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks that the hits have distinct indices, and that the distance of each
+/// is the distance from the query to its instance.
+fn check_hits<I: Instance, U: Number, D: Dataset<I, U>>(
+    name: &str,
+    data: &D,
+    query: &I,
+    hits: &[(usize, U)],
+) -> Result<(), String> {
+    let mut seen = HashSet::new();
+    for &(i, d) in hits {
+        if !seen.insert(i) {
+            return Err(format!("{name} found the instance at index {i} more than once"));
+        }
+        let true_d = data.query_to_one(query, i);
+        if !is_close(d, true_d) {
+            return Err(format!(
+                "{name} found the instance at index {i} at distance {d}, but it is at distance {true_d}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the distances of the hits, sorted in increasing order.
+fn sorted_distances<U: Number>(hits: &[(usize, U)]) -> Vec<U> {
+    let mut distances = hits.iter().map(|&(_, d)| d).collect::<Vec<_>>();
+    distances.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Greater));
+    distances
+}
+
+/// Whether two distances are equal up to `TOLERANCE`.
+///
+/// Some datasets compute distances to many instances at once with a different
+/// order of operations than to a single instance, so the same distance may
+/// differ in its last bits.
+fn is_close<U: Number>(a: U, b: U) -> bool {
+    let (a, b) = (a.as_f64(), b.as_f64());
+    (a - b).abs() <= TOLERANCE * a.abs().max(b.abs()).max(1.0)
+}
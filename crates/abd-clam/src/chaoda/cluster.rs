@@ -14,7 +14,9 @@ use serde::{
     Deserialize, Deserializer, Serialize, Serializer,
 };
 
-use crate::{core::cluster::Children, utils, Cluster, Dataset, Instance, PartitionCriterion, UniBall};
+use crate::{
+    core::cluster::Children, utils, Cluster, Dataset, Instance, PartitionCriterion, PartitionStrategy, UniBall,
+};
 
 /// An extension of the `Cluster` trait to allow for anomaly detection.
 pub trait OddBall<U: Number>: Cluster<U> {
@@ -89,14 +91,15 @@ impl<U: Number> Vertex<U> {
         match uni_ball.children {
             Some(children) => {
                 uni_ball.children = None;
-                let left = Box::new(Self::from_uni_ball(*children.left));
-                let right = Box::new(Self::from_uni_ball(*children.right));
                 let children = Children {
-                    left,
-                    right,
-                    arg_l: children.arg_l,
-                    arg_r: children.arg_r,
+                    clusters: children
+                        .clusters
+                        .into_iter()
+                        .map(|c| Box::new(Self::from_uni_ball(*c)))
+                        .collect(),
+                    arg_poles: children.arg_poles,
                     polar_distance: children.polar_distance,
+                    strategy: children.strategy,
                 };
                 Self::new(uni_ball, [1.0; 6], Some(children))
             }
@@ -123,21 +126,21 @@ impl<U: Number> Vertex<U> {
         self.accumulated_ratio = p_cp + c;
 
         if let Some(Children {
-            left,
-            right,
-            arg_l,
-            arg_r,
+            clusters,
+            arg_poles,
             polar_distance,
+            strategy,
         }) = self.children
         {
-            let left = Box::new(left.set_child_parent_ratios(ratios, self.accumulated_ratio));
-            let right = Box::new(right.set_child_parent_ratios(ratios, self.accumulated_ratio));
+            let clusters = clusters
+                .into_iter()
+                .map(|c| Box::new(c.set_child_parent_ratios(ratios, self.accumulated_ratio)))
+                .collect();
             let children = Children {
-                left,
-                right,
-                arg_l,
-                arg_r,
+                clusters,
+                arg_poles,
                 polar_distance,
+                strategy,
             };
             self.children = Some(children);
         }
@@ -181,8 +184,9 @@ impl<U: Number> Vertex<U> {
 
         match &mut self.children {
             Some(children) => {
-                children.left.set_normalized_ratios(means, sds);
-                children.right.set_normalized_ratios(means, sds);
+                for c in &mut children.clusters {
+                    c.set_normalized_ratios(means, sds);
+                }
             }
             None => (),
         }
@@ -196,6 +200,17 @@ impl<U: Number> Cluster<U> for Vertex<U> {
         Self::new(uni_ball, ratios, None)
     }
 
+    fn new_root_with<I, D, P>(data: &D, criteria: &P, seed: Option<u64>) -> Self
+    where
+        I: Instance,
+        D: Dataset<I, U>,
+        P: PartitionCriterion<U>,
+    {
+        let uni_ball = UniBall::new_root_with(data, criteria, seed);
+        let ratios = [0.0; 6];
+        Self::new(uni_ball, ratios, None)
+    }
+
     fn partition<I, D, P>(self, data: &mut D, criteria: &P, seed: Option<u64>) -> Self
     where
         I: Instance,
@@ -234,17 +249,23 @@ impl<U: Number> Cluster<U> for Vertex<U> {
         self.uni_ball.lfd()
     }
 
-    fn children(&self) -> Option<[&Self; 2]> {
-        self.children.as_ref().map(|c| [c.left.as_ref(), c.right.as_ref()])
+    fn children(&self) -> Option<Vec<&Self>> {
+        self.children
+            .as_ref()
+            .map(|c| c.clusters.iter().map(AsRef::as_ref).collect())
     }
 
     fn polar_distance(&self) -> Option<U> {
         self.uni_ball.polar_distance()
     }
 
-    fn arg_poles(&self) -> Option<[usize; 2]> {
+    fn arg_poles(&self) -> Option<Vec<usize>> {
         self.uni_ball.arg_poles()
     }
+
+    fn strategy(&self) -> Option<PartitionStrategy> {
+        self.children.as_ref().map(|c| c.strategy)
+    }
 }
 
 impl<U: Number> PartialEq for Vertex<U> {
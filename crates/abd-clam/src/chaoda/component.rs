@@ -4,8 +4,8 @@ use std::collections::{BTreeMap, BTreeSet};
 
 use distances::Number;
 use ndarray::prelude::*;
-use rayon::prelude::*;
 
+use crate::par::prelude::*;
 use crate::{Dataset, Instance};
 
 use super::OddBall;
@@ -108,7 +108,7 @@ impl<U: Number> Component<U> {
     fn partition(mut self) -> [Self; 2] {
         // Perform a traversal of the adjacency list to find a connected subgraph.
         let mut visited: BTreeSet<ClusterKey> = BTreeSet::new();
-        let mut stack: Vec<ClusterKey> = self.adjacency_list.keys().copied().collect();
+        let mut stack: Vec<ClusterKey> = self.adjacency_list.keys().next().copied().into_iter().collect();
         while let Some(k) = stack.pop() {
             // Check if the cluster has already been visited.
             if visited.contains(&k) {
@@ -207,6 +207,33 @@ impl<U: Number> Component<U> {
         self.population
     }
 
+    /// Check if the `Component` contains the `OddBall` with the given key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The `offset` and `cardinality` of the `OddBall`.
+    #[must_use]
+    pub fn contains(&self, key: &ClusterKey) -> bool {
+        self.adjacency_list.contains_key(key)
+    }
+
+    /// Get the neighbors of an `OddBall`, and the distances to them, if the
+    /// `OddBall` is in the `Component`.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The `offset` and `cardinality` of the `OddBall`.
+    #[must_use]
+    pub fn neighbors_of(&self, key: &ClusterKey) -> Option<&Neighbors<U>> {
+        self.adjacency_list.get(key)
+    }
+
+    /// Get the number of edges in the `Component`.
+    #[must_use]
+    pub fn num_edges(&self) -> usize {
+        self.adjacency_list.values().map(BTreeMap::len).sum::<usize>() / 2
+    }
+
     /// Get the diameter of the `Component`.
     pub fn diameter(&mut self) -> usize {
         if self.diameter.is_none() {
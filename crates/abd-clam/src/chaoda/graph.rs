@@ -5,8 +5,8 @@ use std::collections::{BTreeMap, BinaryHeap};
 
 use distances::Number;
 use ordered_float::OrderedFloat;
-use rayon::prelude::*;
 
+use crate::par::prelude::*;
 use crate::{Dataset, Instance};
 
 use super::{Component, OddBall};
@@ -117,10 +117,65 @@ impl<U: Number> Graph<U> {
     }
 
     /// Iterate over the `Component`s in the `Graph`.
-    pub(crate) fn iter_components(&self) -> impl Iterator<Item = &Component<U>> {
+    pub fn iter_components(&self) -> impl Iterator<Item = &Component<U>> {
         self.components.iter()
     }
 
+    /// Get the number of connected `Component`s in the `Graph`.
+    #[must_use]
+    pub fn num_components(&self) -> usize {
+        self.components.len()
+    }
+
+    /// Get the number of `OddBall`s in the `Graph`.
+    #[must_use]
+    pub fn cardinality(&self) -> usize {
+        self.components.iter().map(Component::cardinality).sum()
+    }
+
+    /// Get the number of edges in the `Graph`.
+    #[must_use]
+    pub fn num_edges(&self) -> usize {
+        self.components.iter().map(Component::num_edges).sum()
+    }
+
+    /// Get the neighbors of an `OddBall`, and the distances to them, if the
+    /// `OddBall` is in the `Graph`.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The `offset` and `cardinality` of the `OddBall`.
+    #[must_use]
+    pub fn neighbors_of(&self, key: &(usize, usize)) -> Option<&BTreeMap<(usize, usize), U>> {
+        self.components.iter().find_map(|c| c.neighbors_of(key))
+    }
+
+    /// Get the `Component` containing an `OddBall`, if the `OddBall` is in the
+    /// `Graph`.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The `offset` and `cardinality` of the `OddBall`.
+    #[must_use]
+    pub fn component_of(&self, key: &(usize, usize)) -> Option<&Component<U>> {
+        self.components.iter().find(|c| c.contains(key))
+    }
+
+    /// Get the key of the `OddBall` in the `Graph` which contains the instance
+    /// at the given index, if any.
+    ///
+    /// The `OddBall`s in a `Graph` built by `from_tree` never overlap in their
+    /// indices, so there is at most one such `OddBall`.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index of an instance in the `Tree`'s `Dataset`.
+    #[must_use]
+    pub fn cluster_containing(&self, index: usize) -> Option<&(usize, usize)> {
+        self.iter_clusters()
+            .find(|&&(offset, cardinality)| offset <= index && index < offset + cardinality)
+    }
+
     /// Compute the stationary probability of each `OddBall` in the `Graph`.
     #[must_use]
     pub fn compute_stationary_probabilities(&self, num_steps: usize) -> Vec<f32> {
@@ -16,11 +16,11 @@ pub use meta_ml::MlModel;
 
 use distances::Number;
 use ndarray::prelude::*;
-use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use smartcore::metrics::roc_auc_score;
 
-use crate::{Dataset, Instance, PartitionCriterion};
+use crate::par::prelude::*;
+use crate::{Dataset, Instance, PartitionCriterion, Tree};
 
 /// The training data for the ensemble.
 ///
@@ -37,6 +37,9 @@ pub struct Chaoda {
     algorithms: Vec<(Member, Vec<MlModel>)>,
     /// The minimum depth of `Cluster`s to consider for selection.
     min_depth: usize,
+    /// The anomaly scores from the most recent call to `fit`.
+    #[serde(skip)]
+    scores: Vec<f64>,
 }
 
 impl Default for Chaoda {
@@ -47,6 +50,7 @@ impl Default for Chaoda {
                 .map(|member| (member, MlModel::defaults()))
                 .collect(),
             min_depth: 4,
+            scores: Vec::new(),
         }
     }
 }
@@ -55,7 +59,11 @@ impl Chaoda {
     /// Create a new `Chaoda` ensemble.
     #[must_use]
     pub const fn new(algorithms: Vec<(Member, Vec<MlModel>)>, min_depth: usize) -> Self {
-        Self { algorithms, min_depth }
+        Self {
+            algorithms,
+            min_depth,
+            scores: Vec::new(),
+        }
     }
 
     /// Get the number of predictors in the ensemble.
@@ -142,6 +150,58 @@ impl Chaoda {
         Self::aggregate_predictions(&predictions)
     }
 
+    /// Fits the ensemble members to a `Tree`, scoring every instance for
+    /// anomalousness.
+    ///
+    /// The `Graph` is built from the `Cluster`s at `min_depth`, and each
+    /// member scores the instances from the cardinalities, radii, and
+    /// parent-child ratios of the `Cluster`s. The meta-ML models are not used,
+    /// so the ensemble does not need to have been trained. The scores are
+    /// retrieved with `score`.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree`: The `Tree` to fit to.
+    pub fn fit<I, U, D, C>(&mut self, tree: &Tree<I, U, D, C>)
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: OddBall<U>,
+    {
+        let data = tree.data();
+        let permutation = data
+            .permuted_indices()
+            .map_or_else(|| (0..data.cardinality()).collect(), <[usize]>::to_vec);
+
+        let graph = self.create_depth_graph(data, tree.root());
+        let predictions = self
+            .algorithms
+            .par_iter()
+            .map(|(member, _)| {
+                let scores = member.evaluate_points(&mut graph.clone());
+                let mut scores = scores.into_iter().zip(permutation.iter()).collect::<Vec<_>>();
+                scores.sort_by_key(|(_, &i)| i);
+                scores.into_iter().map(|(s, _)| s).collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        self.scores = Self::aggregate_predictions(&predictions)
+            .into_iter()
+            .map(<f64 as From<f32>>::from)
+            .collect();
+    }
+
+    /// Returns the anomaly scores from the most recent call to `fit`.
+    ///
+    /// The scores are for the instances in their original order, before the
+    /// dataset was reordered. Higher scores are more anomalous. If `fit` has
+    /// not been called, this is empty.
+    #[must_use]
+    pub fn score(&self) -> Vec<f64> {
+        self.scores.clone()
+    }
+
     /// Aggregate the predictions of the ensemble.
     ///
     /// For now, we take the mean of the anomaly scores for each point. Later,
@@ -213,25 +273,15 @@ impl Chaoda {
             for (data, labels) in datasets {
                 // Build the tree
                 let mut data = data.clone();
-                let seed = seed.map(|s| s + (e + data.cardinality()) as u64);
-                let root = C::new_root(&data, seed).partition(&mut data, criteria, seed);
+                let seed = seed
+                    .or_else(|| criteria.rng_seed())
+                    .map(|s| s + (e + data.cardinality()) as u64);
+                let root = C::new_root_with(&data, criteria, seed).partition(&mut data, criteria, seed);
 
                 // Create the graphs
                 graphs = if fresh_start {
                     fresh_start = false;
-                    let cluster_scorer = |clusters: &[&C]| {
-                        clusters
-                            .iter()
-                            .map(|c| {
-                                if c.depth() == self.min_depth || (c.is_leaf() && c.depth() < self.min_depth) {
-                                    1.0
-                                } else {
-                                    0.0
-                                }
-                            })
-                            .collect::<Vec<_>>()
-                    };
-                    let graph = Graph::from_tree(&root, &data, cluster_scorer, 4);
+                    let graph = self.create_depth_graph(&data, &root);
                     self.algorithms
                         .iter()
                         .map(|(_, models)| models.iter().map(|_| graph.clone()).collect::<Vec<_>>())
@@ -286,6 +336,30 @@ impl Chaoda {
         }
     }
 
+    /// Create a `Graph` from the `Cluster`s at `min_depth`, or from shallower
+    /// leaves.
+    fn create_depth_graph<I, U, D, C>(&self, data: &D, root: &C) -> Graph<U>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: OddBall<U>,
+    {
+        let cluster_scorer = |clusters: &[&C]| {
+            clusters
+                .iter()
+                .map(|c| {
+                    if c.depth() == self.min_depth || (c.is_leaf() && c.depth() < self.min_depth) {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+        Graph::from_tree(root, data, cluster_scorer, 4)
+    }
+
     /// Create `Graph`s for the ensemble.
     fn create_graphs<I, U, D, C>(&self, data: &D, root: &C) -> Vec<Vec<Graph<U>>>
     where
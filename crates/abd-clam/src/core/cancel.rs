@@ -0,0 +1,48 @@
+//! Cancellation of long tree builds and batch searches.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A flag, shared between threads, which asks a build or a batch search to
+/// stop.
+///
+/// Clones share the same flag, so one clone may be given to the build or
+/// search while another is cancelled, e.g. from a user interface or a
+/// `ProgressReporter`. The flag is checked periodically, so work stops soon
+/// after, not immediately.
+///
+/// * A `Tree` built with `PartitionCriteria::with_cancellation` stops
+///   partitioning once the token is cancelled. `Tree::try_build`,
+///   `Cakes::try_new` and `Cakes::try_new_randomly_sharded` then return
+///   `Error::Cancelled`. `Tree::build`, `Tree::partition` and `Cakes::new`
+///   return the partial `Tree`, which is valid, and searches over it are
+///   exact, but its leaves may be much larger than the criteria ask for.
+/// * The cancellable batch searches of `Cakes` check the token before each
+///   query, and return `Error::Cancelled` if it was cancelled.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Creates a token which has not been cancelled.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the work given this token or any of its clones.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether this token, or any of its clones, has been cancelled.
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl From<Arc<AtomicBool>> for CancellationToken {
+    fn from(flag: Arc<AtomicBool>) -> Self {
+        Self(flag)
+    }
+}
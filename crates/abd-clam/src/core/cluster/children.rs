@@ -12,59 +12,64 @@ use serde::{
     Deserialize, Deserializer, Serialize,
 };
 
-use crate::Cluster;
+use crate::{Cluster, PartitionStrategy};
 
 /// The `Children` of a `Cluster`.
 #[derive(Debug, Clone)]
 pub struct Children<U: Number, C: Cluster<U>> {
-    /// The left child of the `Cluster`.
-    pub left: Box<C>,
-    /// The right child of the `Cluster`.
-    pub right: Box<C>,
-    /// The left pole of the `Cluster` (i.e. the instance used to identify
-    /// instances for the left child).
-    pub arg_l: usize,
-    /// The right pole of the `Cluster` (i.e. the instance used to identify
-    /// instances for the right child).
-    pub arg_r: usize,
-    /// The distance from the `l_pole` to the `r_pole` instance.
+    /// The child `Cluster`s, in the order of their instances in the dataset.
+    /// There are at least two.
+    pub clusters: Vec<Box<C>>,
+    /// The poles of the `Cluster` (i.e. the instances used to identify
+    /// instances for each child), in the same order as the `clusters`.
+    pub arg_poles: Vec<usize>,
+    /// The distance between the first two poles.
     pub polar_distance: U,
+    /// The strategy with which instances were assigned to the children.
+    pub strategy: PartitionStrategy,
 }
 
 impl<U: Number, C: Cluster<U>> Display for Children<U, C> {
     fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
-        write!(f, "{} x {}", self.left.name(), self.right.name())
+        let names = self.clusters.iter().map(|c| c.name()).collect::<Vec<_>>();
+        write!(f, "{}", names.join(" x "))
     }
 }
 
 impl<U: Number, C: Cluster<U>> Serialize for Children<U, C> {
     fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let mut state = serializer.serialize_struct("Children", 5)?;
-        state.serialize_field("left", &self.left)?;
-        state.serialize_field("right", &self.right)?;
-        state.serialize_field("arg_l", &self.arg_l)?;
-        state.serialize_field("arg_r", &self.arg_r)?;
+        let mut state = serializer.serialize_struct("Children", 4)?;
+        state.serialize_field("clusters", &self.clusters)?;
+        state.serialize_field("arg_poles", &self.arg_poles)?;
         state.serialize_field("polar_distance", &self.polar_distance.to_le_bytes())?;
+        state.serialize_field("strategy", &self.strategy)?;
         state.end()
     }
 }
 
 impl<'de, U: Number, C: Cluster<U>> Deserialize<'de> for Children<U, C> {
+    #[allow(clippy::too_many_lines)]
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         /// The fields in the `Children` struct.
         #[derive(Deserialize)]
-        #[serde(field_identifier, rename_all = "lowercase")]
+        #[serde(field_identifier, rename_all = "snake_case")]
         enum Field {
-            /// The left child of the `Cluster`.
+            /// The child `Cluster`s.
+            Clusters,
+            /// The poles of the `Cluster`.
+            ArgPoles,
+            /// The distance between the first two poles.
+            PolarDistance,
+            /// The strategy with which instances were assigned to the children.
+            Strategy,
+            /// The left child, in trees saved before k-ary splits.
             Left,
-            /// The right child of the `Cluster`.
+            /// The right child, in trees saved before k-ary splits.
             Right,
-            /// The left pole of the `Cluster` (i.e. the instance used to identify
+            /// The left pole, in trees saved before k-ary splits.
             ArgL,
-            /// The right pole of the `Cluster` (i.e. the instance used to identify
+            /// The right pole, in trees saved before k-ary splits.
             ArgR,
-            /// The distance from the `l_pole` to the `r_pole` instance.
-            PolarDistance,
         }
 
         /// The `Children` visitor for deserialization.
@@ -78,65 +83,50 @@ impl<'de, U: Number, C: Cluster<U>> Deserialize<'de> for Children<U, C> {
             }
 
             fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
-                let left = seq
+                let clusters = seq
                     .next_element()?
                     .ok_or_else(|| serde::de::Error::invalid_length(0, &self))?;
-                let right = seq
+                let arg_poles = seq
                     .next_element()?
                     .ok_or_else(|| serde::de::Error::invalid_length(1, &self))?;
-                let arg_l = seq
-                    .next_element()?
-                    .ok_or_else(|| serde::de::Error::invalid_length(2, &self))?;
-                let arg_r = seq
-                    .next_element()?
-                    .ok_or_else(|| serde::de::Error::invalid_length(3, &self))?;
 
                 let polar_distance_bytes: Vec<u8> = seq
                     .next_element()?
-                    .ok_or_else(|| serde::de::Error::invalid_length(4, &self))?;
+                    .ok_or_else(|| serde::de::Error::invalid_length(2, &self))?;
                 let polar_distance = U::from_le_bytes(&polar_distance_bytes);
 
+                let strategy = seq
+                    .next_element()?
+                    .ok_or_else(|| serde::de::Error::invalid_length(3, &self))?;
+
                 Ok(Children {
-                    left,
-                    right,
-                    arg_l,
-                    arg_r,
+                    clusters,
+                    arg_poles,
                     polar_distance,
+                    strategy,
                 })
             }
 
             fn visit_map<V: MapAccess<'de>>(self, mut map: V) -> Result<Self::Value, V::Error> {
-                let mut left = None;
-                let mut right = None;
-                let mut arg_l = None;
-                let mut arg_r = None;
+                let mut clusters = None;
+                let mut arg_poles = None;
                 let mut polar_distance = None;
+                let mut strategy = None;
+                let (mut left, mut right, mut arg_l, mut arg_r) = (None, None, None, None);
 
                 while let Some(key) = map.next_key()? {
                     match key {
-                        Field::Left => {
-                            if left.is_some() {
-                                return Err(serde::de::Error::duplicate_field("left"));
-                            }
-                            left = Some(map.next_value()?);
-                        }
-                        Field::Right => {
-                            if right.is_some() {
-                                return Err(serde::de::Error::duplicate_field("right"));
-                            }
-                            right = Some(map.next_value()?);
-                        }
-                        Field::ArgL => {
-                            if arg_l.is_some() {
-                                return Err(serde::de::Error::duplicate_field("arg_l"));
+                        Field::Clusters => {
+                            if clusters.is_some() {
+                                return Err(serde::de::Error::duplicate_field("clusters"));
                             }
-                            arg_l = Some(map.next_value()?);
+                            clusters = Some(map.next_value()?);
                         }
-                        Field::ArgR => {
-                            if arg_r.is_some() {
-                                return Err(serde::de::Error::duplicate_field("arg_r"));
+                        Field::ArgPoles => {
+                            if arg_poles.is_some() {
+                                return Err(serde::de::Error::duplicate_field("arg_poles"));
                             }
-                            arg_r = Some(map.next_value()?);
+                            arg_poles = Some(map.next_value()?);
                         }
                         Field::PolarDistance => {
                             if polar_distance.is_some() {
@@ -144,30 +134,53 @@ impl<'de, U: Number, C: Cluster<U>> Deserialize<'de> for Children<U, C> {
                             }
                             polar_distance = Some(map.next_value()?);
                         }
+                        Field::Strategy => {
+                            if strategy.is_some() {
+                                return Err(serde::de::Error::duplicate_field("strategy"));
+                            }
+                            strategy = Some(map.next_value()?);
+                        }
+                        Field::Left => left = Some(map.next_value()?),
+                        Field::Right => right = Some(map.next_value()?),
+                        Field::ArgL => arg_l = Some(map.next_value()?),
+                        Field::ArgR => arg_r = Some(map.next_value()?),
                     }
                 }
 
-                let left = left.ok_or_else(|| serde::de::Error::missing_field("left"))?;
-                let right = right.ok_or_else(|| serde::de::Error::missing_field("right"))?;
-                let arg_l = arg_l.ok_or_else(|| serde::de::Error::missing_field("arg_l"))?;
-                let arg_r = arg_r.ok_or_else(|| serde::de::Error::missing_field("arg_r"))?;
+                // Trees saved before k-ary splits have a left and a right
+                // child, each with its pole.
+                let clusters = match (clusters, left, right) {
+                    (Some(clusters), None, None) => clusters,
+                    (None, Some(left), Some(right)) => vec![left, right],
+                    (Some(_), _, _) => return Err(serde::de::Error::custom("mixed k-ary and binary children")),
+                    (None, _, _) => return Err(serde::de::Error::missing_field("clusters")),
+                };
+                let arg_poles = match (arg_poles, arg_l, arg_r) {
+                    (Some(arg_poles), None, None) => arg_poles,
+                    (None, Some(arg_l), Some(arg_r)) => vec![arg_l, arg_r],
+                    (Some(_), _, _) => return Err(serde::de::Error::custom("mixed k-ary and binary poles")),
+                    (None, _, _) => return Err(serde::de::Error::missing_field("arg_poles")),
+                };
 
                 let polar_distance_bytes: Vec<u8> =
                     polar_distance.ok_or_else(|| serde::de::Error::missing_field("polar_distance"))?;
                 let polar_distance = U::from_le_bytes(&polar_distance_bytes);
 
+                // Trees saved before strategies were recorded were partitioned
+                // by separation.
+                let strategy = strategy.unwrap_or_default();
+
                 Ok(Children {
-                    left,
-                    right,
-                    arg_l,
-                    arg_r,
+                    clusters,
+                    arg_poles,
                     polar_distance,
+                    strategy,
                 })
             }
         }
 
         /// The fields in the `Children` struct.
-        const FIELDS: &[&str] = &["left", "right", "arg_l", "arg_r", "polar_distance"];
+        const FIELDS: &[&str] = &["clusters", "arg_poles", "polar_distance", "strategy"];
         deserializer.deserialize_struct("Children", FIELDS, ChildrenVisitor((PhantomData, PhantomData)))
     }
 }
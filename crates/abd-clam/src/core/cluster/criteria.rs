@@ -1,13 +1,114 @@
 //! Criteria used for partitioning `Cluster`s.
 
+use std::sync::Arc;
+
 use distances::Number;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "parallel")]
+use crate::par::{ThreadPool, ThreadPoolBuilder};
+use crate::{CancellationToken, Cluster, ProgressReporter, UniBall};
 
-use crate::{Cluster, UniBall};
+/// The default cardinality below which a `Cluster` is partitioned on a single
+/// task.
+pub const SEQUENTIAL_CUTOFF: usize = 1_000;
 
 /// A criterion used to decide when to partition a `Cluster`.
 pub trait PartitionCriterion<U: Number>: Send + Sync {
     /// Check whether a `Cluster` meets the criterion for partitioning.
     fn check(&self, c: &UniBall<U>) -> bool;
+
+    /// The seed for the random number generator used while partitioning.
+    ///
+    /// This is used when no seed is given explicitly to the method building
+    /// the tree. The default is `None`, i.e. the tree is not reproducible.
+    fn rng_seed(&self) -> Option<u64> {
+        None
+    }
+
+    /// The strategy used to assign the instances of a `Cluster` to its
+    /// children. The default is `PartitionStrategy::MaxSeparation`.
+    fn strategy(&self) -> PartitionStrategy {
+        PartitionStrategy::MaxSeparation
+    }
+
+    /// The maximum number of children into which a `Cluster` is split. The
+    /// default is 2, i.e. a binary tree.
+    fn fan_out(&self) -> usize {
+        2
+    }
+
+    /// The cardinality below which a `Cluster` and its subtree are built on a
+    /// single task. Larger `Cluster`s compute their distances in parallel and
+    /// build the subtrees of their children on separate tasks. The default is
+    /// 1,000.
+    fn sequential_cutoff(&self) -> usize {
+        SEQUENTIAL_CUTOFF
+    }
+
+    /// The reporter which receives the progress of the build. The default is
+    /// `None`, i.e. progress is not reported.
+    fn progress(&self) -> Option<&dyn ProgressReporter> {
+        None
+    }
+
+    /// The token with which the build may be cancelled. The default is
+    /// `None`, i.e. the build runs to completion.
+    fn cancellation(&self) -> Option<&CancellationToken> {
+        None
+    }
+
+    /// The thread pool on which the tree is built. The default is `None`,
+    /// i.e. the global pool of `rayon`.
+    #[cfg(feature = "parallel")]
+    fn thread_pool(&self) -> Option<&ThreadPool> {
+        None
+    }
+}
+
+/// Runs `op` on the thread pool of the `criteria`, if they have one, and on
+/// the current pool otherwise.
+pub fn in_thread_pool<U, P, R, F>(criteria: &P, op: F) -> R
+where
+    U: Number,
+    P: PartitionCriterion<U> + ?Sized,
+    R: Send,
+    F: FnOnce() -> R + Send,
+{
+    #[cfg(feature = "parallel")]
+    if let Some(pool) = criteria.thread_pool() {
+        return pool.install(op);
+    }
+    #[cfg(not(feature = "parallel"))]
+    let _ = criteria;
+    op()
+}
+
+/// How the instances of a `Cluster` are assigned to its children.
+///
+/// In every strategy, the first pole is the instance farthest from the center
+/// and the second pole is the instance farthest from the first pole. Each
+/// pole is in its own child, and the strategies differ in how the other poles
+/// are chosen when the fan-out is more than two.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PartitionStrategy {
+    /// Each instance goes to the child of the nearest pole, with ties going to
+    /// the earlier pole. Each further pole is the instance farthest from the
+    /// poles chosen so far. This separates the children best, but may produce
+    /// children of very different cardinalities, and so deep chains of
+    /// `Cluster`s on skewed data.
+    #[default]
+    MaxSeparation,
+    /// The instances are ordered by how much nearer they are to the first pole
+    /// than to the second, and split into children of equal cardinality. This
+    /// keeps the boundaries between the children across the line between the
+    /// first two poles. The first and last children have those poles, and the
+    /// pole of each middle child is its middle instance in this order.
+    Balanced,
+    /// The instances are ordered by their distance to the first pole and split
+    /// into children of equal cardinality, i.e. at the median for two children,
+    /// as in a vantage-point tree. The poles are chosen as for `Balanced`.
+    MedianSplit,
 }
 
 /// The maximum depth of a `Cluster` beyond which it may not be partitioned.
@@ -30,7 +131,42 @@ impl<U: Number> PartitionCriterion<U> for MinCardinality {
     }
 }
 
+/// The minimum radius of a `Cluster` at or below which it may not be partitioned.
+#[derive(Debug, Clone)]
+pub struct MinRadius<U: Number>(U);
+
+impl<U: Number> PartitionCriterion<U> for MinRadius<U> {
+    fn check(&self, c: &UniBall<U>) -> bool {
+        c.radius() > self.0
+    }
+}
+
+/// Any closure which takes a `Cluster` and returns whether it may be
+/// partitioned is a criterion.
+impl<U: Number, F: Fn(&UniBall<U>) -> bool + Send + Sync> PartitionCriterion<U> for F {
+    fn check(&self, c: &UniBall<U>) -> bool {
+        self(c)
+    }
+}
+
 /// A collection of criteria used to decide when to partition a `Cluster`.
+///
+/// The criteria describe when a `Cluster` may be partitioned, so stopping
+/// conditions are expressed by their negations. For example, to stop at depth
+/// 20, or when the cardinality is below 50, or when the radius is below
+/// `1e-6`, every criterion must be met to keep partitioning:
+///
+/// ```
+/// use abd_clam::PartitionCriteria;
+///
+/// let criteria = PartitionCriteria::<f32>::new(true)
+///     .with_max_depth(20)
+///     .with_min_cardinality(49)
+///     .with_min_radius(1e-6);
+/// ```
+///
+/// Collections are themselves criteria, so `and` and `or` combine them into
+/// larger expressions.
 #[allow(clippy::module_name_repetitions)]
 pub struct PartitionCriteria<U: Number> {
     /// The criteria used to decide when to partition a `Cluster`.
@@ -38,6 +174,21 @@ pub struct PartitionCriteria<U: Number> {
     /// Whether all criteria must be met for a `Cluster` to be partitioned or if any one criterion
     /// is sufficient.
     check_all: bool,
+    /// The seed for the random number generator used while partitioning.
+    rng_seed: Option<u64>,
+    /// The strategy used to assign instances to children.
+    strategy: PartitionStrategy,
+    /// The maximum number of children of a `Cluster`.
+    fan_out: usize,
+    /// The cardinality below which a `Cluster` is partitioned on a single task.
+    sequential_cutoff: usize,
+    /// The reporter which receives the progress of the build, if any.
+    progress: Option<Arc<dyn ProgressReporter>>,
+    /// The token with which the build may be cancelled, if any.
+    cancellation: Option<CancellationToken>,
+    /// The thread pool on which the tree is built, if not the global pool.
+    #[cfg(feature = "parallel")]
+    thread_pool: Option<Arc<ThreadPool>>,
 }
 
 impl<U: Number> PartitionCriterion<U> for PartitionCriteria<U> {
@@ -49,6 +200,35 @@ impl<U: Number> PartitionCriterion<U> for PartitionCriteria<U> {
                 self.criteria.iter().any(|c| c.check(cluster))
             }
     }
+
+    fn rng_seed(&self) -> Option<u64> {
+        self.rng_seed
+    }
+
+    fn strategy(&self) -> PartitionStrategy {
+        self.strategy
+    }
+
+    fn fan_out(&self) -> usize {
+        self.fan_out
+    }
+
+    fn sequential_cutoff(&self) -> usize {
+        self.sequential_cutoff
+    }
+
+    fn progress(&self) -> Option<&dyn ProgressReporter> {
+        self.progress.as_deref()
+    }
+
+    fn cancellation(&self) -> Option<&CancellationToken> {
+        self.cancellation.as_ref()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn thread_pool(&self) -> Option<&ThreadPool> {
+        self.thread_pool.as_deref()
+    }
 }
 
 impl<U: Number> Default for PartitionCriteria<U> {
@@ -69,9 +249,144 @@ impl<U: Number> PartitionCriteria<U> {
         Self {
             criteria: Vec::new(),
             check_all,
+            rng_seed: None,
+            strategy: PartitionStrategy::MaxSeparation,
+            fan_out: 2,
+            sequential_cutoff: SEQUENTIAL_CUTOFF,
+            progress: None,
+            cancellation: None,
+            #[cfg(feature = "parallel")]
+            thread_pool: None,
         }
     }
 
+    /// Sets the seed for the random number generator used while partitioning.
+    ///
+    /// With a seed, the sampling of centers and poles is reproducible, so that
+    /// the same data always produces the same tree, across runs and platforms.
+    /// A seed given explicitly when building the tree, e.g. to `Cakes::new`,
+    /// takes precedence over this one.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed`: The seed.
+    #[must_use]
+    pub const fn with_rng_seed(mut self, seed: u64) -> Self {
+        self.rng_seed = Some(seed);
+        self
+    }
+
+    /// Sets the strategy used to assign the instances of a `Cluster` to its
+    /// children.
+    ///
+    /// # Arguments
+    ///
+    /// * `strategy`: The strategy.
+    #[must_use]
+    pub const fn with_strategy(mut self, strategy: PartitionStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Sets the maximum number of children into which a `Cluster` is split.
+    ///
+    /// A larger fan-out gives a shallower tree, which is cheaper to traverse
+    /// when the dataset is very large. A `Cluster` gets fewer children if it
+    /// has too few distinct instances.
+    ///
+    /// # Arguments
+    ///
+    /// * `fan_out`: The maximum number of children. Values below 2 are
+    ///   treated as 2.
+    #[must_use]
+    pub const fn with_fan_out(mut self, fan_out: usize) -> Self {
+        self.fan_out = if fan_out < 2 { 2 } else { fan_out };
+        self
+    }
+
+    /// Sets the cardinality below which a `Cluster` and its subtree are built
+    /// on a single task.
+    ///
+    /// Larger `Cluster`s compute their distances in parallel, even if the
+    /// metric is cheap, and build the subtrees of their children on separate
+    /// tasks, which `rayon` balances across threads by work-stealing. The tree
+    /// is the same for any cutoff.
+    ///
+    /// # Arguments
+    ///
+    /// * `cardinality`: The cutoff. With 0, every `Cluster` is built in
+    ///   parallel, and with `usize::MAX`, the whole tree is built on the
+    ///   calling thread.
+    #[must_use]
+    pub const fn with_sequential_cutoff(mut self, cardinality: usize) -> Self {
+        self.sequential_cutoff = cardinality;
+        self
+    }
+
+    /// Sets the reporter which receives the progress of the build.
+    ///
+    /// # Arguments
+    ///
+    /// * `reporter`: The reporter, e.g. a closure taking a `BuildProgress`.
+    #[must_use]
+    pub fn with_progress<R: ProgressReporter + 'static>(mut self, reporter: R) -> Self {
+        self.progress = Some(Arc::new(reporter));
+        self
+    }
+
+    /// Sets the token with which the build may be cancelled.
+    ///
+    /// Once the token is cancelled, no more `Cluster`s are partitioned, so
+    /// the build finishes soon after. `Tree::try_build` and `Cakes::try_new`
+    /// then return `Error::Cancelled`, while `Tree::build` and `Cakes::new`
+    /// return a valid, but shallower, tree.
+    ///
+    /// # Arguments
+    ///
+    /// * `token`: The token.
+    #[must_use]
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Sets the thread pool on which the tree is built, instead of the global
+    /// pool of `rayon`.
+    ///
+    /// This keeps a large build from starving the other work of the host
+    /// application on the global pool. The pool may be shared with other
+    /// builds and with batch searches, e.g. `Cakes::batch_knn_search_in`.
+    ///
+    /// # Arguments
+    ///
+    /// * `pool`: The thread pool.
+    #[cfg(feature = "parallel")]
+    #[must_use]
+    pub fn with_thread_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.thread_pool = Some(pool);
+        self
+    }
+
+    /// Builds the tree on a new thread pool with at most `num_threads`
+    /// threads, instead of the global pool of `rayon`.
+    ///
+    /// # Arguments
+    ///
+    /// * `num_threads`: The maximum number of threads. With 0, `rayon`
+    ///   chooses the number of threads, as for the global pool.
+    ///
+    /// # Errors
+    ///
+    /// * If the thread pool could not be created.
+    #[cfg(feature = "parallel")]
+    pub fn with_max_threads(self, num_threads: usize) -> Result<Self, String> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(self.with_thread_pool(Arc::new(pool)))
+    }
+
     /// Add the `MaxDepth` criterion to the collection of criteria.
     ///
     /// # Arguments
@@ -94,14 +409,90 @@ impl<U: Number> PartitionCriteria<U> {
         self
     }
 
+    /// Add the `MinRadius` criterion to the collection of criteria.
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold`: the minimum radius of a `Cluster` at or below which it may not be partitioned.
+    #[must_use]
+    pub fn with_min_radius(mut self, threshold: U) -> Self
+    where
+        U: 'static,
+    {
+        self.criteria.push(Box::new(MinRadius(threshold)));
+        self
+    }
+
     /// Add a custom criterion to the collection of criteria.
     ///
+    /// Only the `check` method of the criterion is used. The seed, strategy
+    /// and fan-out are those of this collection.
+    ///
     /// # Arguments
     ///
     /// * `c`: the custom criterion to add.
-    #[allow(dead_code)]
-    pub(crate) fn with_custom(mut self, c: Box<dyn PartitionCriterion<U>>) -> Self {
-        self.criteria.push(c);
+    #[must_use]
+    pub fn with_custom<P: PartitionCriterion<U> + 'static>(mut self, c: P) -> Self {
+        self.criteria.push(Box::new(c));
         self
     }
+
+    /// Add a closure to the collection of criteria.
+    ///
+    /// # Arguments
+    ///
+    /// * `f`: a closure returning whether a `Cluster` may be partitioned.
+    #[must_use]
+    pub fn with_fn<F: Fn(&UniBall<U>) -> bool + Send + Sync + 'static>(self, f: F) -> Self {
+        self.with_custom(f)
+    }
+
+    /// Combines this collection with another criterion, so that a `Cluster`
+    /// is partitioned only if both are met.
+    ///
+    /// The seed, strategy and fan-out are those of this collection.
+    ///
+    /// # Arguments
+    ///
+    /// * `other`: the other criterion.
+    #[must_use]
+    pub fn and<P: PartitionCriterion<U> + 'static>(self, other: P) -> Self
+    where
+        U: 'static,
+    {
+        self.combine(true, other)
+    }
+
+    /// Combines this collection with another criterion, so that a `Cluster`
+    /// is partitioned if either is met.
+    ///
+    /// The seed, strategy and fan-out are those of this collection.
+    ///
+    /// # Arguments
+    ///
+    /// * `other`: the other criterion.
+    #[must_use]
+    pub fn or<P: PartitionCriterion<U> + 'static>(self, other: P) -> Self
+    where
+        U: 'static,
+    {
+        self.combine(false, other)
+    }
+
+    /// Combines this collection with another criterion, with `check_all`
+    /// deciding between `and` and `or`.
+    fn combine<P: PartitionCriterion<U> + 'static>(mut self, check_all: bool, other: P) -> Self
+    where
+        U: 'static,
+    {
+        if self.check_all != check_all && self.criteria.len() > 1 {
+            let inner = Self {
+                criteria: core::mem::take(&mut self.criteria),
+                ..Self::new(self.check_all)
+            };
+            self.criteria.push(Box::new(inner));
+        }
+        self.check_all = check_all;
+        self.with_custom(other)
+    }
 }
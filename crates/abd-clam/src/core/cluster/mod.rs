@@ -2,7 +2,7 @@
 //! a cluster.
 //!
 //! It also provides the `PartitionCriterion` trait, and implementations for
-//! `PartitionCriterion` for `MaxDepth` and `MinCardinality` which are used to
+//! `PartitionCriterion` for `MaxDepth`, `MinCardinality` and `MinRadius` which are used to
 //! determine when to stop partitioning the tree.
 
 mod children;
@@ -10,7 +10,8 @@ mod criteria;
 mod uni;
 
 pub use children::Children;
-pub use criteria::{MaxDepth, MinCardinality, PartitionCriteria, PartitionCriterion};
+pub use criteria::in_thread_pool;
+pub use criteria::{MaxDepth, MinCardinality, MinRadius, PartitionCriteria, PartitionCriterion, PartitionStrategy};
 #[allow(clippy::module_name_repetitions)]
 pub use uni::UniBall;
 
@@ -38,6 +39,21 @@ pub trait Cluster<U: Number>:
     /// Creates a new `Cluster` from a given dataset.
     fn new_root<I: Instance, D: Dataset<I, U>>(data: &D, seed: Option<u64>) -> Self;
 
+    /// Creates a new `Cluster` from a given dataset, as it would be built by
+    /// `partition` with the given `criteria`, e.g. with their
+    /// `sequential_cutoff`.
+    ///
+    /// The default ignores the `criteria` and calls `new_root`.
+    fn new_root_with<I, D, P>(data: &D, criteria: &P, seed: Option<u64>) -> Self
+    where
+        I: Instance,
+        D: Dataset<I, U>,
+        P: PartitionCriterion<U>,
+    {
+        let _ = criteria;
+        Self::new_root(data, seed)
+    }
+
     /// Recursively partitions the `Cluster` until the `PartitionCriteria` are met.
     #[must_use]
     fn partition<I, D, P>(self, data: &mut D, criteria: &P, seed: Option<u64>) -> Self
@@ -66,17 +82,23 @@ pub trait Cluster<U: Number>:
     /// The index of the instance with the maximum distance from the `center`
     fn arg_radial(&self) -> usize;
 
-    /// The local fractal dimension of the `å`.
+    /// The local fractal dimension of the `Cluster`.
     fn lfd(&self) -> f64;
 
-    /// The two child clusters.
-    fn children(&self) -> Option<[&Self; 2]>;
+    /// The child clusters, in the order of their instances in the dataset.
+    /// There are at least two, and at most the fan-out of the tree.
+    fn children(&self) -> Option<Vec<&Self>>;
 
-    /// The distance between the two poles of the `Cluster` used for partitioning.
+    /// The distance between the first two poles of the `Cluster` used for
+    /// partitioning.
     fn polar_distance(&self) -> Option<U>;
 
-    /// The indices of the instances used as poles for partitioning.
-    fn arg_poles(&self) -> Option<[usize; 2]>;
+    /// The indices of the instances used as poles for partitioning, one for
+    /// each child.
+    fn arg_poles(&self) -> Option<Vec<usize>>;
+
+    /// The strategy with which instances were assigned to the children.
+    fn strategy(&self) -> Option<PartitionStrategy>;
 
     /// The `name` of the `Cluster` String.
     ///
@@ -127,11 +149,44 @@ pub trait Cluster<U: Number>:
         self.cardinality() == 1 || self.radius() == U::zero()
     }
 
+    /// Whether the `Cluster` is a duplicate bucket, i.e. it has more than one
+    /// instance and a radius of zero.
+    ///
+    /// Under a metric, all instances of a duplicate bucket are identical, so
+    /// they are never partitioned and a search computes only the distance to
+    /// the center of the bucket.
+    fn is_duplicate_bucket(&self) -> bool {
+        self.cardinality() > 1 && self.radius() == U::zero()
+    }
+
+    /// The index, in the reordered dataset, of the instance at the `center` of
+    /// the `Cluster`.
+    ///
+    /// This is the same as `arg_center` and is the name to prefer from outside
+    /// the crate.
+    fn center_index(&self) -> usize {
+        self.arg_center()
+    }
+
     /// The indices of the instances in the `Cluster` after the dataset has been reordered.
     fn indices(&self) -> Range<usize> {
         self.offset()..(self.offset() + self.cardinality())
     }
 
+    /// The range of indices, in the reordered dataset, of the instances in the
+    /// `Cluster`.
+    ///
+    /// Building a `Tree` permutes the dataset depth-first, so the instances of
+    /// every `Cluster` are contiguous and the ranges of its children split its
+    /// own range, in order. No `Cluster` stores a list of its indices; they
+    /// are recovered from the `offset` and `cardinality` alone.
+    ///
+    /// This is the same as `indices` and is the name to prefer from outside
+    /// the crate.
+    fn index_range(&self) -> Range<usize> {
+        self.indices()
+    }
+
     /// The subtree of the `Cluster`.
     fn subtree(&self) -> Vec<&Self> {
         let subtree = vec![self];
@@ -168,16 +223,23 @@ pub trait Cluster<U: Number>:
 
     /// Assuming the `Cluster` overlaps with the query ball, we return only
     /// those children that also overlap with the query ball.
+    ///
+    /// Children are excluded by which side of the hyperplanes between the
+    /// poles the query ball is on, which is only valid if each instance went
+    /// to the child of its nearest pole, i.e. for
+    /// `PartitionStrategy::MaxSeparation`. Otherwise, all children are
+    /// returned.
     fn overlapping_children<I: Instance, D: Dataset<I, U>>(&self, data: &D, query: &I, radius: U) -> Vec<&Self> {
-        if self.is_leaf() {
-            Vec::new()
-        } else {
-            let [left, right] = self
-                .children()
-                .unwrap_or_else(|| unreachable!("We checked that the cluster is not a leaf."));
-            let [arg_l, arg_r] = self
-                .arg_poles()
-                .unwrap_or_else(|| unreachable!("We checked that the cluster is not a leaf."));
+        let Some(children) = self.children() else {
+            return Vec::new();
+        };
+        let arg_poles = self
+            .arg_poles()
+            .unwrap_or_else(|| unreachable!("We checked that the cluster is not a leaf."));
+
+        if self.strategy() != Some(PartitionStrategy::MaxSeparation) {
+            children
+        } else if let (&[left, right], &[arg_l, arg_r]) = (&children[..], &arg_poles[..]) {
             let polar_distance = self
                 .polar_distance()
                 .unwrap_or_else(|| unreachable!("We checked that the cluster is not a leaf."));
@@ -195,6 +257,22 @@ pub trait Cluster<U: Number>:
             } else {
                 vec![right]
             }
+        } else {
+            // An instance in the child of a pole `p` is at least half of
+            // `d(q, p) - d(q, p')` from the query for any other pole `p'`.
+            let distances = data.query_to_many(query, &arg_poles);
+            let nearest = distances
+                .iter()
+                .copied()
+                .reduce(|a, b| if b < a { b } else { a })
+                .unwrap_or_else(|| unreachable!("There is a pole for each child."));
+            let threshold = nearest + U::from(2) * radius;
+            children
+                .into_iter()
+                .zip(distances)
+                .filter(|&(_, d)| d <= threshold)
+                .map(|(c, _)| c)
+                .collect()
         }
     }
 
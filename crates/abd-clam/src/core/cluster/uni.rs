@@ -6,9 +6,12 @@ use core::{
     hash::{Hash, Hasher},
     marker::PhantomData,
 };
+use std::collections::BTreeSet;
+#[cfg(not(target_arch = "wasm32"))]
 use std::time::Instant;
 
 use distances::Number;
+#[cfg(not(target_arch = "wasm32"))]
 use mt_logger::{mt_log, Level};
 use serde::{
     de::{MapAccess, SeqAccess, Visitor},
@@ -16,9 +19,14 @@ use serde::{
     Deserialize, Deserializer, Serialize, Serializer,
 };
 
-use crate::{utils, Cluster, Dataset, Instance, PartitionCriterion};
+use crate::core::progress::Tracker;
+use crate::par::prelude::*;
+use crate::{utils, CancellationToken, Cluster, Dataset, Instance, PartitionCriterion, PartitionStrategy};
 
-use super::Children;
+use super::{
+    criteria::{in_thread_pool, SEQUENTIAL_CUTOFF},
+    Children,
+};
 
 /// A `UniBall` is a cluster that behaves as clusters used to before the introduction
 /// of the `Cluster` trait.
@@ -82,16 +90,24 @@ impl<U: Number> Display for UniBall<U> {
 
 impl<U: Number> UniBall<U> {
     /// Create a new `UniBall`.
+    ///
+    /// The distances are computed in parallel if there are at least
+    /// `sequential_cutoff` instances.
     fn new<I: Instance, D: Dataset<I, U>>(
         data: &D,
         seed: Option<u64>,
         offset: usize,
         indices: &[usize],
         depth: usize,
+        sequential_cutoff: usize,
     ) -> Self {
         let cardinality = indices.len();
 
+        // Clocks are not available on `wasm32-unknown-unknown`, so the timing
+        // and logging, which read them, are skipped there.
+        #[cfg(not(target_arch = "wasm32"))]
         let start = Instant::now();
+        #[cfg(not(target_arch = "wasm32"))]
         mt_log!(
             Level::Debug,
             "Creating a UniBall with depth {depth} and cardinality {cardinality} ..."
@@ -105,21 +121,28 @@ impl<U: Number> UniBall<U> {
             data.choose_unique(n, indices, seed)
         };
 
-        let Some(arg_center) = data.median(&arg_samples) else {
+        let is_parallel = cardinality >= sequential_cutoff;
+        let arg_center = if is_parallel {
+            data.par_median(&arg_samples)
+        } else {
+            data.median(&arg_samples)
+        };
+        let Some(arg_center) = arg_center else {
             unreachable!("The UniBall has at least one instance.")
         };
 
-        let center_distances = data.one_to_many(arg_center, indices);
+        let center_distances = distances_to_many(data, arg_center, indices, sequential_cutoff);
         let Some((arg_radial, radius)) = utils::arg_max(&center_distances).map(|(i, r)| (indices[i], r)) else {
             unreachable!("The UniBall has at least one instance.")
         };
 
         let lfd = utils::compute_lfd(radius, &center_distances);
 
-        let end = start.elapsed().as_secs_f32();
+        #[cfg(not(target_arch = "wasm32"))]
         mt_log!(
             Level::Debug,
-            "Finished creating a UniBall with depth {depth}, offset {offset} and cardinality {cardinality} in {end:.2e} seconds."
+            "Finished creating a UniBall with depth {depth}, offset {offset} and cardinality {cardinality} in {:.2e} seconds.",
+            start.elapsed().as_secs_f32()
         );
 
         Self {
@@ -138,79 +161,81 @@ impl<U: Number> UniBall<U> {
     ///
     /// # Arguments
     ///
-    /// * `l_indices`: The indices of the left child.
-    /// * `r_indices`: The indices of the right child.
+    /// * `groups`: The indices of the instances of each child.
     ///
     /// # Returns
     ///
     /// * `true` if all of the following conditions are met:
-    ///    * The `l_indices` are not empty.
-    ///    * The `r_indices` are not empty.
-    ///    * The total length of the `l_indices` and `r_indices` is equal to the
-    ///      cardinality of the `UniBall`.
-    const fn _check_partition(&self, l_indices: &[usize], r_indices: &[usize]) -> bool {
-        !l_indices.is_empty() && !r_indices.is_empty() && l_indices.len() + r_indices.len() == self.cardinality
-        // assert!(
-        //     !l_indices.is_empty(),
-        //     "Left child of {} at depth {} should not be empty.",
-        //     self.name(),
-        //     self.depth
-        // );
-        // assert!(
-        //     !r_indices.is_empty(),
-        //     "Right child of {} at depth {} should not be empty.",
-        //     self.name(),
-        //     self.depth
-        // );
-        // assert!(
-        //     l_indices.len() + r_indices.len() == self.cardinality,
-        //     "The sum of the left and right child of {} at depth {} should be equal to the cardinality of the cluster.",
-        //     self.name(),
-        //     self.depth
-        // );
-    }
-
-    /// Recursive helper function for `partition`.
+    ///    * There are at least two children.
+    ///    * None of the children are empty.
+    ///    * The total number of indices is equal to the cardinality of the
+    ///      `UniBall`.
+    fn _check_partition(&self, groups: &[(usize, Vec<usize>)]) -> bool {
+        groups.len() > 1
+            && groups.iter().all(|(_, g)| !g.is_empty())
+            && groups.iter().map(|(_, g)| g.len()).sum::<usize>() == self.cardinality
+    }
+
+    /// Recursive helper function for `partition`, reporting each finished
+    /// `UniBall` to the `tracker`, if any.
     fn _partition<I: Instance, D: Dataset<I, U>, P: PartitionCriterion<U>>(
         mut self,
         data: &D,
         criteria: &P,
         mut indices: Vec<usize>,
         seed: Option<u64>,
+        tracker: Option<&Tracker>,
     ) -> (Self, Vec<usize>) {
-        if criteria.check(&self) {
-            let ([(arg_l, l_indices), (arg_r, r_indices)], polar_distance) = self.partition_once(data, indices.clone());
-            if self._check_partition(&l_indices, &r_indices) {
+        // Partitioning a singleton could only split identical instances, and
+        // some strategies would do so down to single instances.
+        let is_cancelled = criteria.cancellation().is_some_and(CancellationToken::is_cancelled);
+        if !self.is_singleton() && !is_cancelled && criteria.check(&self) {
+            let (groups, polar_distance) = self.partition_once(data, indices.clone(), criteria);
+            if self._check_partition(&groups) {
                 core::mem::drop(indices);
 
-                let r_offset = self.offset + l_indices.len();
-
-                let ((left, l_indices), (right, r_indices)) = rayon::join(
-                    || {
-                        Self::new(data, seed, self.offset, &l_indices, self.depth + 1)
-                            ._partition(data, criteria, l_indices, seed)
-                    },
-                    || {
-                        Self::new(data, seed, r_offset, &r_indices, self.depth + 1)
-                            ._partition(data, criteria, r_indices, seed)
-                    },
-                );
-                self._check_partition(&l_indices, &r_indices);
-
-                let arg_l = utils::position_of(&l_indices, arg_l)
-                    .unwrap_or_else(|| unreachable!("We know the left pole is in the indices."));
-                let arg_r = utils::position_of(&r_indices, arg_r)
-                    .unwrap_or_else(|| unreachable!("We know the right pole is in the indices."));
+                let mut offset = self.offset;
+                let groups = groups
+                    .into_iter()
+                    .map(|(arg_pole, g)| {
+                        let child_offset = offset;
+                        offset += g.len();
+                        (arg_pole, g, child_offset)
+                    })
+                    .collect::<Vec<_>>();
+
+                // The subtrees of large `UniBall`s are built on separate tasks,
+                // and those of small ones on the current task, where the
+                // overhead of spawning tasks would outweigh the work.
+                let sequential_cutoff = criteria.sequential_cutoff();
+                let build = |(arg_pole, g, offset): (usize, Vec<usize>, usize)| {
+                    let (child, g) = Self::new(data, seed, offset, &g, self.depth + 1, sequential_cutoff)
+                        ._partition(data, criteria, g, seed, tracker);
+                    let arg_pole = utils::position_of(&g, arg_pole)
+                        .unwrap_or_else(|| unreachable!("We know the pole is in the indices."));
+                    (child, g, offset + arg_pole)
+                };
+                let children = if self.cardinality < sequential_cutoff {
+                    groups.into_iter().map(build).collect::<Vec<_>>()
+                } else {
+                    groups.into_par_iter().map(build).collect::<Vec<_>>()
+                };
+
+                let mut clusters = Vec::with_capacity(children.len());
+                let mut arg_poles = Vec::with_capacity(children.len());
+                indices = Vec::with_capacity(self.cardinality);
+                for (child, mut g, arg_pole) in children {
+                    clusters.push(Box::new(child));
+                    arg_poles.push(arg_pole);
+                    indices.append(&mut g);
+                }
 
                 self.children = Some(Children {
-                    left: Box::new(left),
-                    right: Box::new(right),
-                    arg_l: self.offset + arg_l,
-                    arg_r: r_offset + arg_r,
+                    clusters,
+                    arg_poles,
                     polar_distance,
+                    strategy: criteria.strategy(),
                 });
-
-                indices = l_indices.into_iter().chain(r_indices).collect::<Vec<_>>();
             }
         }
 
@@ -223,57 +248,535 @@ impl<U: Number> UniBall<U> {
             .unwrap_or_else(|| unreachable!("We know the radial is in the indices."));
         self.arg_radial = self.offset + arg_radial;
 
+        if let Some(tracker) = tracker {
+            tracker.on_cluster(if self.children.is_none() { self.cardinality } else { 0 });
+        }
+
         (self, indices)
     }
 
-    /// Partitions the `UniBall` into two children once.
-    fn partition_once<I: Instance, D: Dataset<I, U>>(
+    /// Partitions the `UniBall` into at most `fan_out` children once,
+    /// assigning instances to children with the given `strategy`.
+    ///
+    /// # Returns
+    ///
+    /// The pole and the indices of the instances of each child, and the
+    /// distance between the first two poles.
+    fn partition_once<I: Instance, D: Dataset<I, U>, P: PartitionCriterion<U>>(
         &self,
         data: &D,
         indices: Vec<usize>,
-    ) -> ([(usize, Vec<usize>); 2], U) {
-        let l_distances = data.one_to_many(self.arg_radial, &indices);
+        criteria: &P,
+    ) -> (Vec<(usize, Vec<usize>)>, U) {
+        let (fan_out, sequential_cutoff) = (criteria.fan_out(), criteria.sequential_cutoff());
+        let l_distances = distances_to_many(data, self.arg_radial, &indices, sequential_cutoff);
 
         let Some((arg_r, polar_distance)) = utils::arg_max(&l_distances) else {
             unreachable!("The cluster should have at least one instance.")
         };
         let arg_r = indices[arg_r];
-        let r_distances = data.one_to_many(arg_r, &indices);
+        let r_distances = distances_to_many(data, arg_r, &indices, sequential_cutoff);
+
+        let groups = match criteria.strategy() {
+            PartitionStrategy::MaxSeparation => self.nearest_pole_split(
+                data,
+                &indices,
+                [l_distances, r_distances],
+                arg_r,
+                [fan_out, sequential_cutoff],
+            ),
+            PartitionStrategy::Balanced => {
+                let keys = l_distances
+                    .iter()
+                    .zip(r_distances)
+                    .map(|(&l, r)| l.as_f64() - r.as_f64());
+                self.ordered_split(indices, keys.collect(), arg_r, fan_out)
+            }
+            PartitionStrategy::MedianSplit => {
+                let keys = l_distances.iter().map(|l| l.as_f64());
+                self.ordered_split(indices, keys.collect(), arg_r, fan_out)
+            }
+        };
+
+        (groups, polar_distance)
+    }
+
+    /// Assigns each instance to the child of its nearest pole, with ties going
+    /// to the earlier pole. Poles after the first two are chosen by
+    /// farthest-first traversal. The children are ordered by decreasing
+    /// cardinality.
+    fn nearest_pole_split<I: Instance, D: Dataset<I, U>>(
+        &self,
+        data: &D,
+        indices: &[usize],
+        [l_distances, r_distances]: [Vec<U>; 2],
+        arg_r: usize,
+        [fan_out, sequential_cutoff]: [usize; 2],
+    ) -> Vec<(usize, Vec<usize>)> {
+        let mut nearest = l_distances
+            .iter()
+            .zip(r_distances.iter())
+            .map(|(&l, &r)| if r < l { r } else { l })
+            .collect::<Vec<_>>();
+        let mut poles = vec![self.arg_radial, arg_r];
+        let mut pole_distances = vec![l_distances, r_distances];
+
+        while poles.len() < fan_out {
+            let Some((i, d)) = utils::arg_max(&nearest) else {
+                unreachable!("The cluster should have at least one instance.")
+            };
+            if d == U::zero() {
+                break;
+            }
+            let distances = distances_to_many(data, indices[i], indices, sequential_cutoff);
+            for (n, &d) in nearest.iter_mut().zip(distances.iter()) {
+                if d < *n {
+                    *n = d;
+                }
+            }
+            poles.push(indices[i]);
+            pole_distances.push(distances);
+        }
+
+        let mut groups = vec![Vec::new(); poles.len()];
+        for (j, &i) in indices.iter().enumerate() {
+            if !poles.contains(&i) {
+                let nearest_pole = (1..poles.len()).fold(0, |best, p| {
+                    if pole_distances[p][j] < pole_distances[best][j] {
+                        p
+                    } else {
+                        best
+                    }
+                });
+                groups[nearest_pole].push(i);
+            }
+        }
 
-        let (l_indices, r_indices) = indices
+        let mut groups = poles
             .into_iter()
-            .zip(l_distances)
-            .zip(r_distances)
-            .filter(|&((i, _), _)| i != self.arg_radial && i != arg_r)
-            .partition::<Vec<_>, _>(|&((_, l), r)| l <= r);
+            .zip(groups)
+            .map(|(p, mut g)| {
+                g.push(p);
+                (p, g)
+            })
+            .collect::<Vec<_>>();
+        groups.sort_by_key(|(_, g)| core::cmp::Reverse(g.len()));
+        groups
+    }
 
-        let (l_indices, r_indices) = {
-            let mut l_indices = Self::drop_distances(l_indices);
-            let mut r_indices = Self::drop_distances(r_indices);
+    /// Sorts the instances, other than the first two poles, by the given keys
+    /// and splits them into children of equal cardinality, once each child has
+    /// its pole. The first and last children get the first two poles, and each
+    /// middle child gets its middle instance as its pole. The children are
+    /// ordered by decreasing cardinality.
+    fn ordered_split(
+        &self,
+        indices: Vec<usize>,
+        keys: Vec<f64>,
+        arg_r: usize,
+        fan_out: usize,
+    ) -> Vec<(usize, Vec<usize>)> {
+        let mut instances = indices
+            .into_iter()
+            .zip(keys)
+            .filter(|&(i, _)| i != self.arg_radial && i != arg_r)
+            .collect::<Vec<_>>();
+        instances.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        let mut instances = instances.into_iter().map(|(i, _)| i);
+
+        let cardinality = instances.len() + 2;
+        let num_children = fan_out.min(cardinality);
+        (0..num_children)
+            .map(|c| {
+                let size = cardinality / num_children + <usize as From<bool>>::from(c < cardinality % num_children);
+                if c == 0 || c == num_children - 1 {
+                    let pole = if c == 0 { self.arg_radial } else { arg_r };
+                    let mut g = instances.by_ref().take(size - 1).collect::<Vec<_>>();
+                    g.push(pole);
+                    (pole, g)
+                } else {
+                    let g = instances.by_ref().take(size).collect::<Vec<_>>();
+                    (g[g.len() / 2], g)
+                }
+            })
+            .collect()
+    }
 
-            l_indices.push(self.arg_radial);
-            r_indices.push(arg_r);
+    /// Finds the index in the dataset at which a new instance should be
+    /// inserted so that it lands in the appropriate leaf of this subtree.
+    ///
+    /// The instance is routed down the tree to the child of its nearest pole
+    /// in each `UniBall`, with ties going to the earlier pole, in the same way
+    /// as instances are assigned to children by
+    /// `PartitionStrategy::MaxSeparation`. The returned index is the end of
+    /// the range of the leaf.
+    pub(crate) fn insertion_index<I: Instance, D: Dataset<I, U>>(&self, data: &D, instance: &I) -> usize {
+        let leaf = self.route(data, instance).pop().unwrap_or(self);
+        leaf.offset + leaf.cardinality
+    }
 
-            (l_indices, r_indices)
-        };
+    /// Returns the `UniBall`s from this one down to the leaf to which an
+    /// instance is routed, as in `insertion_index`.
+    pub(crate) fn route<'a, I: Instance, D: Dataset<I, U>>(&'a self, data: &D, instance: &I) -> Vec<&'a Self> {
+        let mut path = vec![self];
+        let mut current = self;
+        while let Some(children) = current.children.as_ref() {
+            let distances = data.query_to_many(instance, &children.arg_poles);
+            let nearest = (1..distances.len()).fold(0, |best, p| if distances[p] < distances[best] { p } else { best });
+            current = &children.clusters[nearest];
+            path.push(current);
+        }
+        path
+    }
+
+    /// Builds a partitioned tree over all instances of `data`, with its root
+    /// at the given `depth`, and reorders the instances to match.
+    pub(crate) fn new_subtree<I, D, P>(data: &mut D, criteria: &P, seed: Option<u64>, depth: usize) -> Self
+    where
+        I: Instance,
+        D: Dataset<I, U>,
+        P: PartitionCriterion<U>,
+    {
+        in_thread_pool(criteria, || {
+            let indices = (0..data.cardinality()).collect::<Vec<_>>();
+            Self::new(data, seed, 0, &indices, depth, criteria.sequential_cutoff()).partition(data, criteria, seed)
+        })
+    }
 
-        if l_indices.len() < r_indices.len() {
-            ([(arg_r, r_indices), (self.arg_radial, l_indices)], polar_distance)
+    /// Moves the subtree to start at `offset` in a larger dataset, i.e. adds
+    /// `offset` to all offsets and indices in the subtree.
+    pub(crate) fn shift(&mut self, offset: usize) {
+        self.offset += offset;
+        self.arg_center += offset;
+        self.arg_radial += offset;
+        if let Some(children) = self.children.as_mut() {
+            children.arg_poles.iter_mut().for_each(|p| *p += offset);
+            children.clusters.iter_mut().for_each(|c| c.shift(offset));
+        }
+    }
+
+    /// Creates a `UniBall` with the depth, local fractal dimension and
+    /// polar distance of this one, but over the given `children`, which must
+    /// be contiguous.
+    ///
+    /// # Arguments
+    ///
+    /// * `children`: The children of the new `UniBall`.
+    /// * `arg_center`: The index of the center.
+    /// * `(arg_radial, radius)`: The index of the instance farthest from the
+    ///   center, and its distance from the center.
+    /// * `arg_poles`: The indices of the poles of the children.
+    pub(crate) fn with_children(
+        &self,
+        children: Vec<Self>,
+        arg_center: usize,
+        (arg_radial, radius): (usize, U),
+        arg_poles: Vec<usize>,
+    ) -> Self {
+        let offset = children.first().map_or(0, |c| c.offset);
+        let cardinality = children.iter().map(|c| c.cardinality).sum();
+        let polar_distance = self.children.as_ref().map_or_else(U::zero, |c| c.polar_distance);
+        Self {
+            depth: self.depth,
+            offset,
+            cardinality,
+            arg_center,
+            arg_radial,
+            radius,
+            lfd: self.lfd,
+            children: Some(Children {
+                clusters: children.into_iter().map(Box::new).collect(),
+                arg_poles,
+                polar_distance,
+                strategy: PartitionStrategy::MaxSeparation,
+            }),
+        }
+    }
+
+    /// Updates the subtree after an instance was inserted into the dataset at
+    /// the given `index`, as found by `insertion_index`.
+    ///
+    /// `UniBall`s after the `index` have their offsets shifted. `UniBall`s
+    /// which contain the `index` have their cardinalities incremented and their
+    /// radii expanded if needed. Their local fractal dimensions are not
+    /// recomputed. If the leaf receiving the new instance meets the partition
+    /// `criteria`, it is partitioned and its instances are reordered.
+    ///
+    /// # Returns
+    ///
+    /// If a leaf was partitioned, the offset of the leaf and the permutation
+    /// that was applied to its instances.
+    pub(crate) fn accommodate_insertion<I, D, P>(
+        &mut self,
+        data: &mut D,
+        index: usize,
+        criteria: &P,
+        seed: Option<u64>,
+    ) -> Option<(usize, Vec<usize>)>
+    where
+        I: Instance,
+        D: Dataset<I, U>,
+        P: PartitionCriterion<U>,
+    {
+        if self.offset + self.cardinality < index {
+            // The `UniBall` is entirely before the new instance.
+            return None;
+        }
+
+        let shift = |i: usize| if i >= index { i + 1 } else { i };
+        self.arg_center = shift(self.arg_center);
+        self.arg_radial = shift(self.arg_radial);
+
+        let contains_index = self.offset < index;
+        if contains_index {
+            self.cardinality += 1;
+            let distance = data.one_to_one(self.arg_center, index);
+            if distance > self.radius {
+                self.radius = distance;
+                self.arg_radial = index;
+            }
+        } else {
+            self.offset += 1;
+        }
+
+        if let Some(children) = self.children.as_mut() {
+            children.arg_poles.iter_mut().for_each(|p| *p = shift(*p));
+            // Every child must be visited to shift its offsets, but at most one
+            // contains the `index` and may have been reordered.
+            let reordered = children
+                .clusters
+                .iter_mut()
+                .map(|c| c.accommodate_insertion(data, index, criteria, seed))
+                .fold(None, Option::or);
+
+            // The center, radial and poles may have been moved by a reordering.
+            if let Some((offset, permutation)) = reordered.as_ref() {
+                let remap = |i: usize| utils::position_of(permutation, i).map_or(i, |p| offset + p);
+                self.arg_center = remap(self.arg_center);
+                self.arg_radial = remap(self.arg_radial);
+                children.arg_poles.iter_mut().for_each(|p| *p = remap(*p));
+            }
+            reordered
+        } else if contains_index && criteria.check(self) {
+            let permutation = self.repartition(data, criteria, seed);
+            Some((self.offset, permutation))
         } else {
-            ([(self.arg_radial, l_indices), (arg_r, r_indices)], polar_distance)
+            None
         }
     }
 
-    /// Drops the distances from a vector, returning only the indices.
-    fn drop_distances(indices: Vec<((usize, U), U)>) -> Vec<usize> {
-        indices.into_iter().map(|((i, _), _)| i).collect()
+    /// Rebuilds the subtree of this `UniBall` from its instances and reorders
+    /// those instances in the dataset to match the new subtree.
+    ///
+    /// # Returns
+    ///
+    /// The permutation that was applied to the instances, i.e. the instance
+    /// now at `offset + i` was previously at `permutation[i]`.
+    pub(crate) fn repartition<I, D, P>(&mut self, data: &mut D, criteria: &P, seed: Option<u64>) -> Vec<usize>
+    where
+        I: Instance,
+        D: Dataset<I, U>,
+        P: PartitionCriterion<U>,
+    {
+        in_thread_pool(criteria, || {
+            let indices = self.indices().collect::<Vec<_>>();
+            let (ball, indices) = Self::new(
+                data,
+                seed,
+                self.offset,
+                &indices,
+                self.depth,
+                criteria.sequential_cutoff(),
+            )
+            ._partition(data, criteria, indices, seed, None);
+            *self = ball;
+            Self::permute_range(data, self.offset, &indices).unwrap_or_else(|e| unreachable!("{e}"));
+            indices
+        })
+    }
+
+    /// Finds the subtrees which need to be rebuilt to compact away the
+    /// instances marked by the `tombstones`.
+    ///
+    /// A subtree is rebuilt if the fraction of its instances which were
+    /// removed exceeds the `threshold`, if one of its children has had all of
+    /// its instances removed, or if its center, radial instance or poles were
+    /// removed.
+    ///
+    /// # Returns
+    ///
+    /// The offsets and cardinalities of the subtrees to rebuild, in order of
+    /// increasing offset.
+    pub(crate) fn compaction_ranges(&self, tombstones: &BTreeSet<usize>, threshold: f64) -> Vec<(usize, usize)> {
+        let count_removed = |c: &Self| tombstones.range(c.offset..(c.offset + c.cardinality)).count();
+
+        let num_removed = count_removed(self);
+        if num_removed == 0 {
+            return Vec::new();
+        }
+
+        let mut key_args = vec![self.arg_center, self.arg_radial];
+        let child_emptied = self.children.as_ref().is_some_and(|children| {
+            key_args.extend(children.arg_poles.iter().copied());
+            children.clusters.iter().any(|c| count_removed(c) == c.cardinality)
+        });
+
+        if num_removed.as_f64() > threshold * self.cardinality.as_f64()
+            || child_emptied
+            || key_args.iter().any(|i| tombstones.contains(i))
+        {
+            vec![(self.offset, self.cardinality)]
+        } else {
+            self.children.as_ref().map_or_else(Vec::new, |children| {
+                children
+                    .clusters
+                    .iter()
+                    .flat_map(|c| c.compaction_ranges(tombstones, threshold))
+                    .collect()
+            })
+        }
+    }
+
+    /// Updates the subtree after the instances at the `removed` indices were
+    /// removed from the dataset.
+    ///
+    /// The subtrees given by `ranges`, as found by `compaction_ranges`, are
+    /// rebuilt. All other `UniBall`s have their offsets and cardinalities
+    /// updated, and keep their radii as upper bounds. Their local fractal
+    /// dimensions are not recomputed.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The dataset after the removal.
+    /// * `removed`: The sorted indices of the removed instances, from before
+    ///   the removal.
+    /// * `ranges`: The offsets and cardinalities of the subtrees to rebuild,
+    ///   from before the removal.
+    /// * `criteria`: The criteria used to partition the rebuilt subtrees.
+    /// * `seed`: The seed to use when rebuilding subtrees.
+    ///
+    /// # Returns
+    ///
+    /// The offsets of the rebuilt subtrees and the permutations that were
+    /// applied to their instances.
+    pub(crate) fn accommodate_removal<I, D, P>(
+        &mut self,
+        data: &mut D,
+        removed: &[usize],
+        ranges: &[(usize, usize)],
+        criteria: &P,
+        seed: Option<u64>,
+    ) -> Vec<(usize, Vec<usize>)>
+    where
+        I: Instance,
+        D: Dataset<I, U>,
+        P: PartitionCriterion<U>,
+    {
+        let shift = |i: usize| i - removed.partition_point(|&r| r < i);
+
+        let is_rebuilt = ranges.binary_search(&(self.offset, self.cardinality)).is_ok();
+        let end = shift(self.offset + self.cardinality);
+        self.offset = shift(self.offset);
+        self.cardinality = end - self.offset;
+
+        if is_rebuilt {
+            let permutation = self.repartition(data, criteria, seed);
+            return vec![(self.offset, permutation)];
+        }
+
+        self.arg_center = shift(self.arg_center);
+        self.arg_radial = shift(self.arg_radial);
+
+        self.children.as_mut().map_or_else(Vec::new, |children| {
+            children.arg_poles.iter_mut().for_each(|p| *p = shift(*p));
+            let reordered = children
+                .clusters
+                .iter_mut()
+                .flat_map(|c| c.accommodate_removal(data, removed, ranges, criteria, seed))
+                .collect::<Vec<_>>();
+
+            // The center, radial and poles may have been moved by a reordering.
+            let remap = |i: usize| {
+                reordered
+                    .iter()
+                    .find(|(offset, permutation)| (*offset..(offset + permutation.len())).contains(&i))
+                    .and_then(|(offset, permutation)| utils::position_of(permutation, i).map(|p| offset + p))
+                    .unwrap_or(i)
+            };
+            self.arg_center = remap(self.arg_center);
+            self.arg_radial = remap(self.arg_radial);
+            children.arg_poles.iter_mut().for_each(|p| *p = remap(*p));
+
+            reordered
+        })
+    }
+
+    /// Reorders the instances in the range of the dataset starting at `offset`
+    /// so that the instance at `offset + i` is the one previously at
+    /// `permutation[i]`, and updates the permuted indices of the dataset to
+    /// match.
+    fn permute_range<I: Instance, D: Dataset<I, U>>(
+        data: &mut D,
+        offset: usize,
+        permutation: &[usize],
+    ) -> Result<(), String> {
+        let mut original = data
+            .permuted_indices()
+            .map_or_else(|| (0..data.cardinality()).collect(), <[usize]>::to_vec);
+        let moved = permutation.iter().map(|&i| original[i]).collect::<Vec<_>>();
+        original[offset..(offset + permutation.len())].copy_from_slice(&moved);
+
+        // Track where each instance currently is while swapping them into place.
+        let mut positions = (0..permutation.len()).collect::<Vec<_>>();
+        let mut occupants = positions.clone();
+        for (i, &source) in permutation.iter().enumerate() {
+            let (source, current) = (source - offset, positions[source - offset]);
+            if current != i {
+                data.swap(offset + i, offset + current)?;
+                let displaced = occupants[i];
+                occupants[current] = displaced;
+                positions[displaced] = current;
+                occupants[i] = source;
+                positions[source] = i;
+            }
+        }
+
+        data.set_permuted_indices(Some(&original));
+        Ok(())
+    }
+}
+
+/// Computes the distances from an indexed instance to many, in parallel if
+/// there are at least `sequential_cutoff` of them.
+fn distances_to_many<I: Instance, U: Number, D: Dataset<I, U>>(
+    data: &D,
+    left: usize,
+    right: &[usize],
+    sequential_cutoff: usize,
+) -> Vec<U> {
+    if right.len() < sequential_cutoff {
+        data.one_to_many(left, right)
+    } else {
+        data.par_one_to_many(left, right)
     }
 }
 
 impl<U: Number> Cluster<U> for UniBall<U> {
     fn new_root<I: Instance, D: Dataset<I, U>>(data: &D, seed: Option<u64>) -> Self {
         let indices = (0..data.cardinality()).collect::<Vec<usize>>();
-        Self::new(data, seed, 0, &indices, 0)
+        Self::new(data, seed, 0, &indices, 0, SEQUENTIAL_CUTOFF)
+    }
+
+    fn new_root_with<I, D, P>(data: &D, criteria: &P, seed: Option<u64>) -> Self
+    where
+        I: Instance,
+        D: Dataset<I, U>,
+        P: PartitionCriterion<U>,
+    {
+        in_thread_pool(criteria, || {
+            let indices = (0..data.cardinality()).collect::<Vec<usize>>();
+            Self::new(data, seed, 0, &indices, 0, criteria.sequential_cutoff())
+        })
     }
 
     fn partition<I: Instance, D: Dataset<I, U>, P: PartitionCriterion<U>>(
@@ -282,14 +785,19 @@ impl<U: Number> Cluster<U> for UniBall<U> {
         criteria: &P,
         seed: Option<u64>,
     ) -> Self {
-        let mut indices = (0..self.cardinality).collect::<Vec<_>>();
-        (self, indices) = self._partition(data, criteria, indices, seed);
-
-        mt_log!(Level::Debug, "Finished building tree. Starting data permutation.");
-        data.permute_instances(&indices).unwrap_or_else(|e| unreachable!("{e}"));
-        mt_log!(Level::Debug, "Finished data permutation.");
-
-        self
+        in_thread_pool(criteria, move || {
+            let tracker = criteria.progress().map(|r| Tracker::new(r, self.cardinality));
+            let mut indices = (0..self.cardinality).collect::<Vec<_>>();
+            (self, indices) = self._partition(data, criteria, indices, seed, tracker.as_ref());
+
+            #[cfg(not(target_arch = "wasm32"))]
+            mt_log!(Level::Debug, "Finished building tree. Starting data permutation.");
+            data.permute_instances(&indices).unwrap_or_else(|e| unreachable!("{e}"));
+            #[cfg(not(target_arch = "wasm32"))]
+            mt_log!(Level::Debug, "Finished data permutation.");
+
+            self
+        })
     }
 
     fn offset(&self) -> usize {
@@ -320,16 +828,22 @@ impl<U: Number> Cluster<U> for UniBall<U> {
         self.lfd
     }
 
-    fn children(&self) -> Option<[&Self; 2]> {
-        self.children.as_ref().map(|c| [c.left.as_ref(), c.right.as_ref()])
+    fn children(&self) -> Option<Vec<&Self>> {
+        self.children
+            .as_ref()
+            .map(|c| c.clusters.iter().map(AsRef::as_ref).collect())
     }
 
     fn polar_distance(&self) -> Option<U> {
         self.children.as_ref().map(|c| c.polar_distance)
     }
 
-    fn arg_poles(&self) -> Option<[usize; 2]> {
-        self.children.as_ref().map(|c| [c.arg_l, c.arg_r])
+    fn arg_poles(&self) -> Option<Vec<usize>> {
+        self.children.as_ref().map(|c| c.arg_poles.clone())
+    }
+
+    fn strategy(&self) -> Option<PartitionStrategy> {
+        self.children.as_ref().map(|c| c.strategy)
     }
 }
 
@@ -353,7 +867,7 @@ impl<'de, U: Number> Deserialize<'de> for UniBall<U> {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         /// The fields in the `UniBall` struct.
         #[derive(Deserialize)]
-        #[serde(field_identifier, rename_all = "lowercase")]
+        #[serde(field_identifier, rename_all = "snake_case")]
         enum Field {
             /// The depth of this `UniBall` in the tree.
             Depth,
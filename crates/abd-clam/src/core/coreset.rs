@@ -0,0 +1,106 @@
+//! Selecting a small, weighted set of instances which represents a `Tree`.
+
+use std::collections::BinaryHeap;
+
+use distances::Number;
+
+use crate::{Cluster, Dataset, Instance, Tree};
+
+impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
+    /// Selects at most `size` representative instances, each weighted by the
+    /// number of instances it stands for, e.g. to train on a subset of the
+    /// data with the same distribution.
+    ///
+    /// Starting from the root, the `Cluster` with the largest product of
+    /// cardinality and radius is repeatedly replaced by its children, and a
+    /// leaf by its instances, for as long as there would be at most `size`
+    /// representatives. Each `Cluster` is then represented by its center,
+    /// which stands for all of its instances. Dense regions of the data are
+    /// thus represented by few heavy instances, and sparse regions by many
+    /// light ones.
+    ///
+    /// Removed instances are never selected, and are not counted in the
+    /// weights, so that the weights sum to the number of instances which have
+    /// not been removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - The largest number of representatives to select.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 2-tuples, sorted by index, where the first element is the
+    /// index of a representative instance and the second element is its
+    /// weight.
+    pub fn coreset(&self, size: usize) -> Vec<(usize, usize)> {
+        let count = |c: &C| c.indices().filter(|&i| !self.is_removed(i)).count();
+        // Non-negative floats are ordered in the same way as their bits, so
+        // the spread of each `Cluster` can be kept in the heap as an integer.
+        let spread = |c: &C, n: usize| (n.as_f64() * c.radius().as_f64()).to_bits();
+
+        let n = count(&self.root);
+        if size == 0 || n == 0 {
+            return Vec::new();
+        }
+
+        // The `Cluster`s which are, or have been, representatives, with their
+        // numbers of instances, and the heap of the current ones by spread.
+        let mut frontier = vec![(&self.root, n)];
+        let mut heap = BinaryHeap::from([(spread(&self.root, n), 0)]);
+        let mut singles = Vec::new();
+
+        while heap.len() + singles.len() < size {
+            let Some(&(s, p)) = heap.peek() else {
+                break;
+            };
+            // Nothing is gained by splitting a `Cluster` of identical instances.
+            if s == 0 {
+                break;
+            }
+
+            let c = frontier[p].0;
+            let others = heap.len() - 1 + singles.len();
+            if let Some(children) = c.children() {
+                let children = children
+                    .into_iter()
+                    .map(|c| (c, count(c)))
+                    .filter(|&(_, n)| n > 0)
+                    .collect::<Vec<_>>();
+                if others + children.len() > size {
+                    break;
+                }
+                heap.pop();
+                for (c, n) in children {
+                    heap.push((spread(c, n), frontier.len()));
+                    frontier.push((c, n));
+                }
+            } else {
+                let indices = c.indices().filter(|&i| !self.is_removed(i)).collect::<Vec<_>>();
+                if others + indices.len() > size {
+                    break;
+                }
+                heap.pop();
+                singles.extend(indices.into_iter().map(|i| (i, 1)));
+            }
+        }
+
+        let mut coreset = heap
+            .into_iter()
+            .map(|(_, p)| {
+                let (c, n) = frontier[p];
+                let center = c.arg_center();
+                let i = if self.is_removed(center) {
+                    c.indices()
+                        .find(|&i| !self.is_removed(i))
+                        .unwrap_or_else(|| unreachable!("The `Cluster` has instances which were not removed."))
+                } else {
+                    center
+                };
+                (i, n)
+            })
+            .chain(singles)
+            .collect::<Vec<_>>();
+        coreset.sort_unstable();
+        coreset
+    }
+}
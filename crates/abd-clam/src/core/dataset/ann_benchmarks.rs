@@ -0,0 +1,234 @@
+//! Loading datasets in the HDF5 layout used by ann-benchmarks.
+//!
+//! Each file holds the instances to search in `train`, the queries in `test`,
+//! and, for each query, the indices of and distances to its true nearest
+//! neighbors in `neighbors` and `distances`, nearest first. The name of the
+//! distance function is stored in the `distance` attribute of the file.
+
+use std::path::Path;
+
+use distances::Number;
+use hdf5::{types::VarLenUnicode, File, H5Type};
+
+use crate::{metrics::vectors::dense_metric, Error, SharedMetric, VecDataset};
+
+/// A dataset from ann-benchmarks, with its queries and ground truth.
+#[derive(Debug, Clone)]
+pub struct AnnBenchmark<U: Number> {
+    /// The instances to search.
+    data: VecDataset<Vec<f32>, U, usize>,
+    /// The queries.
+    queries: Vec<Vec<f32>>,
+    /// The indices of the true nearest neighbors of each query.
+    neighbors: Vec<Vec<usize>>,
+    /// The distances from each query to its true nearest neighbors.
+    distances: Vec<Vec<f32>>,
+    /// The name of the distance function in the file.
+    distance: String,
+}
+
+impl AnnBenchmark<f32> {
+    /// Loads a dataset with the distance function named in the file.
+    ///
+    /// `"angular"` is read as the cosine distance.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path to the HDF5 file.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::Io` if the distance function is missing.
+    /// * `Error::Other` if the distance function is not one of those accepted
+    ///   by `dense_metric`.
+    /// * See `AnnBenchmark::load_with_metric`.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let file = open(path).map_err(Error::Io)?;
+        let distance = read_distance(&file).map_err(Error::Io)?;
+        let metric = dense_metric(if distance == "angular" { "cosine" } else { &distance })?;
+        Self::read(&file, path, metric.into(), distance)
+    }
+}
+
+impl<U: Number> AnnBenchmark<U> {
+    /// Loads a dataset with the given distance function.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path to the HDF5 file.
+    /// * `metric`: The distance function and its properties.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::DimensionMismatch` if the queries do not have the
+    ///   dimensionality of the instances.
+    /// * `Error::Io` otherwise:
+    ///   * If the file cannot be opened or is not a valid HDF5 file.
+    ///   * If any of `train`, `test`, `neighbors` or `distances` is missing or
+    ///     is not 2-dimensional.
+    ///   * If `neighbors` and `distances` do not have one row per query and
+    ///     the same shape.
+    ///   * If any neighbor is not the index of an instance.
+    pub fn load_with_metric(path: &Path, metric: impl Into<SharedMetric<Vec<f32>, U>>) -> Result<Self, Error> {
+        let file = open(path).map_err(Error::Io)?;
+        let distance = read_distance(&file).unwrap_or_default();
+        Self::read(&file, path, metric.into(), distance)
+    }
+
+    /// Reads the datasets of an open file.
+    fn read(file: &File, path: &Path, metric: SharedMetric<Vec<f32>, U>, distance: String) -> Result<Self, Error> {
+        let (train, dim) = read_rows::<f32>(file, "train").map_err(Error::Io)?;
+        let (queries, query_dim) = read_rows::<f32>(file, "test").map_err(Error::Io)?;
+        if query_dim != dim {
+            return Err(Error::DimensionMismatch {
+                expected: dim,
+                found: query_dim,
+            });
+        }
+
+        let (neighbors, k) = read_rows::<i64>(file, "neighbors").map_err(Error::Io)?;
+        let (distances, distances_k) = read_rows::<f32>(file, "distances").map_err(Error::Io)?;
+        if neighbors.len() != queries.len() || distances.len() != queries.len() || distances_k != k {
+            return Err(Error::Io(format!(
+                "Expected neighbors and distances of shape ({}, {k}), but they have shapes ({}, {k}) and ({}, {distances_k})",
+                queries.len(),
+                neighbors.len(),
+                distances.len()
+            )));
+        }
+
+        let cardinality = train.len();
+        let neighbors = neighbors
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|i| {
+                        usize::try_from(i)
+                            .ok()
+                            .filter(|&i| i < cardinality)
+                            .ok_or_else(|| format!("Neighbor {i} is not the index of one of {cardinality} instances"))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::Io)?;
+
+        let name = path
+            .file_stem()
+            .map_or_else(|| "ann-benchmarks".to_string(), |s| s.to_string_lossy().into_owned());
+        let data = VecDataset::from_metric(name, train, metric);
+
+        Ok(Self {
+            data,
+            queries,
+            neighbors,
+            distances,
+            distance,
+        })
+    }
+
+    /// Returns the instances to search.
+    #[must_use]
+    pub const fn data(&self) -> &VecDataset<Vec<f32>, U, usize> {
+        &self.data
+    }
+
+    /// Returns the queries.
+    #[must_use]
+    pub fn queries(&self) -> &[Vec<f32>] {
+        &self.queries
+    }
+
+    /// Returns the indices of the true nearest neighbors of each query,
+    /// nearest first.
+    #[must_use]
+    pub fn neighbors(&self) -> &[Vec<usize>] {
+        &self.neighbors
+    }
+
+    /// Returns the distances from each query to its true nearest neighbors,
+    /// nearest first.
+    #[must_use]
+    pub fn distances(&self) -> &[Vec<f32>] {
+        &self.distances
+    }
+
+    /// Returns the name of the distance function in the file, or an empty
+    /// string if the file does not name one.
+    #[must_use]
+    pub fn distance(&self) -> &str {
+        &self.distance
+    }
+
+    /// Returns the true `k` nearest neighbors of a query as `(index, distance)`
+    /// pairs, in the form of search results.
+    ///
+    /// The indices are those of instances in the file, so the hits of a search
+    /// must be mapped with `original_index` before they are compared.
+    ///
+    /// # Arguments
+    ///
+    /// * `query`: The index of the query.
+    /// * `k`: The number of neighbors. Fewer are returned if the file holds
+    ///   fewer.
+    ///
+    /// # Panics
+    ///
+    /// * If `query` is not the index of a query.
+    #[must_use]
+    pub fn ground_truth(&self, query: usize, k: usize) -> Vec<(usize, f32)> {
+        self.neighbors[query]
+            .iter()
+            .copied()
+            .zip(self.distances[query].iter().copied())
+            .take(k)
+            .collect()
+    }
+
+    /// Takes the instances to search, the queries and the ground truth.
+    #[allow(clippy::type_complexity)]
+    #[must_use]
+    pub fn into_parts(
+        self,
+    ) -> (
+        VecDataset<Vec<f32>, U, usize>,
+        Vec<Vec<f32>>,
+        Vec<Vec<usize>>,
+        Vec<Vec<f32>>,
+    ) {
+        (self.data, self.queries, self.neighbors, self.distances)
+    }
+}
+
+/// Opens an HDF5 file for reading.
+fn open(path: &Path) -> Result<File, String> {
+    File::open(path).map_err(|e| format!("Cannot open {}: {e}", path.display()))
+}
+
+/// Reads the name of the distance function from the attributes of a file.
+fn read_distance(file: &File) -> Result<String, String> {
+    file.attr("distance")
+        .and_then(|a| a.read_scalar::<VarLenUnicode>())
+        .map(|d| d.as_str().to_lowercase())
+        .map_err(|e| format!("Cannot read the distance attribute: {e}"))
+}
+
+/// Reads a 2-dimensional dataset as rows, returning them and the number of
+/// columns.
+fn read_rows<T: H5Type + Copy>(file: &File, name: &str) -> Result<(Vec<Vec<T>>, usize), String> {
+    let dataset = file.dataset(name).map_err(|e| format!("Cannot open {name}: {e}"))?;
+    let shape = dataset.shape();
+    let [n, dim] = shape[..] else {
+        return Err(format!("{name} must be 2-dimensional, but it has shape {shape:?}"));
+    };
+    let values = dataset
+        .read_raw::<T>()
+        .map_err(|e| format!("Cannot read {name}: {e}"))?;
+
+    let rows = if dim == 0 {
+        vec![Vec::new(); n]
+    } else {
+        values.chunks_exact(dim).map(<[T]>::to_vec).collect()
+    };
+    Ok((rows, dim))
+}
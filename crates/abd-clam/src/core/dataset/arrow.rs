@@ -0,0 +1,169 @@
+//! Ingestion of vectors from Arrow record batches and Parquet files.
+//!
+//! Vectors are read from columns of fixed-size lists of floats, which is how
+//! embeddings are usually stored. The values of each batch are copied straight
+//! from its Arrow buffer into the rows of the dataset, and Parquet files are
+//! read one batch at a time, so only one batch is held in Arrow form at once.
+
+use std::{fs::File, path::Path};
+
+use arrow_array::{
+    types::{Float32Type, Float64Type},
+    Array, ArrowNativeTypeOp, ArrowPrimitiveType, FixedSizeListArray, PrimitiveArray, RecordBatch,
+};
+use distances::Number;
+use parquet::arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ProjectionMask};
+
+use crate::{SharedMetric, VecDataset};
+
+/// A float type which may be read from an Arrow array.
+pub trait ArrowFloat: Number + ArrowNativeTypeOp {
+    /// The Arrow type with this native type.
+    type Arrow: ArrowPrimitiveType<Native = Self>;
+}
+
+impl ArrowFloat for f32 {
+    type Arrow = Float32Type;
+}
+
+impl ArrowFloat for f64 {
+    type Arrow = Float64Type;
+}
+
+impl<T: ArrowFloat, U: Number> VecDataset<Vec<T>, U, usize> {
+    /// Creates a new dataset from a column of a record batch.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the dataset.
+    /// * `batch`: The record batch.
+    /// * `column`: The name of a column of fixed-size lists of `T`.
+    /// * `metric`: The distance function and its properties.
+    ///
+    /// # Errors
+    ///
+    /// * If the batch has no such column.
+    /// * See `VecDataset::from_parquet`.
+    pub fn from_arrow(
+        name: String,
+        batch: &RecordBatch,
+        column: &str,
+        metric: impl Into<SharedMetric<Vec<T>, U>>,
+    ) -> Result<Self, String> {
+        let array = batch
+            .column_by_name(column)
+            .ok_or_else(|| format!("The record batch has no column named {column}"))?;
+
+        let mut data = Vec::with_capacity(array.len());
+        append_rows(array, column, None, &mut data)?;
+
+        Ok(Self::from_metric(name, data, metric))
+    }
+
+    /// Creates a new dataset from a column of a Parquet file.
+    ///
+    /// Only the given column is decoded, one batch of rows at a time.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the dataset.
+    /// * `path`: The path to the Parquet file.
+    /// * `column`: The name of a column of fixed-size lists of `T`.
+    /// * `metric`: The distance function and its properties.
+    ///
+    /// # Errors
+    ///
+    /// * If the file cannot be opened or is not a valid Parquet file.
+    /// * If the file has no such column.
+    /// * If the column is not a fixed-size list of `T`.
+    /// * If the column has any null lists or null elements.
+    pub fn from_parquet(
+        name: String,
+        path: &Path,
+        column: &str,
+        metric: impl Into<SharedMetric<Vec<T>, U>>,
+    ) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file).map_err(|e| e.to_string())?;
+
+        let index = builder
+            .schema()
+            .index_of(column)
+            .map_err(|_| format!("The Parquet file has no column named {column}"))?;
+        let mask = ProjectionMask::roots(builder.parquet_schema(), [index]);
+        let num_rows = usize::try_from(builder.metadata().file_metadata().num_rows()).unwrap_or_default();
+        let reader = builder.with_projection(mask).build().map_err(|e| e.to_string())?;
+
+        let mut data = Vec::with_capacity(num_rows);
+        let mut dim = None;
+        for batch in reader {
+            let batch = batch.map_err(|e| e.to_string())?;
+            dim = Some(append_rows(batch.column(0), column, dim, &mut data)?);
+        }
+
+        Ok(Self::from_metric(name, data, metric))
+    }
+}
+
+/// Appends the rows of a column of fixed-size lists to `data`, returning the
+/// length of the lists.
+///
+/// # Arguments
+///
+/// * `array`: The column.
+/// * `column`: The name of the column, for error messages.
+/// * `dim`: The length of the lists in previous batches, if any.
+/// * `data`: The rows read so far.
+fn append_rows<T: ArrowFloat>(
+    array: &dyn Array,
+    column: &str,
+    dim: Option<usize>,
+    data: &mut Vec<Vec<T>>,
+) -> Result<usize, String> {
+    let list = array.as_any().downcast_ref::<FixedSizeListArray>().ok_or_else(|| {
+        format!(
+            "Column {column} must be a fixed-size list, but it is {}",
+            array.data_type()
+        )
+    })?;
+    let values = list
+        .values()
+        .as_any()
+        .downcast_ref::<PrimitiveArray<T::Arrow>>()
+        .ok_or_else(|| {
+            format!(
+                "Column {column} must hold {}, but it holds {}",
+                T::Arrow::DATA_TYPE,
+                list.value_type()
+            )
+        })?;
+
+    let list_dim = usize::try_from(list.value_length()).map_err(|e| e.to_string())?;
+    if let Some(dim) = dim.filter(|&d| d != list_dim) {
+        return Err(format!(
+            "Column {column} has lists of length {list_dim} after lists of length {dim}"
+        ));
+    }
+    if let Some(i) = (0..list.len()).find(|&i| list.is_null(i)) {
+        return Err(format!("Column {column} has a null list at row {}", data.len() + i));
+    }
+
+    // A sliced list shares the values of the whole array, so only the values
+    // from its first list onwards are read.
+    let start = usize::try_from(list.value_offset(0)).map_err(|e| e.to_string())?;
+    let end = start + list.len() * list_dim;
+    if values
+        .nulls()
+        .is_some_and(|n| n.slice(start, end - start).null_count() > 0)
+    {
+        return Err(format!("Column {column} has null elements"));
+    }
+
+    if list_dim == 0 {
+        data.extend((0..list.len()).map(|_| Vec::new()));
+    } else {
+        data.extend(values.values()[start..end].chunks_exact(list_dim).map(<[T]>::to_vec));
+    }
+
+    Ok(list_dim)
+}
@@ -0,0 +1,377 @@
+//! A dataset of vectors laid out in fixed-size blocks for fast leaf scans.
+
+use core::ops::Index;
+
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Dataset, FnMetric, Instance, SharedMetric, VecDataset};
+
+/// The number of instances in each block.
+pub const BLOCK_LANES: usize = 8;
+
+/// One dimension of the instances in a block.
+///
+/// The alignment lets the compiler use aligned SIMD loads for a whole lane.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C, align(32))]
+struct Lanes([f32; BLOCK_LANES]);
+
+/// The distance functions which a `BlockedDataset` computes over its blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockKernel {
+    /// The euclidean distance, as in `distances::simd::euclidean_f32`.
+    Euclidean,
+    /// The cosine distance, as in `distances::simd::cosine_f32`.
+    ///
+    /// This is not a metric, so only `Linear` and `Approximate` knn search
+    /// should be used with it.
+    Cosine,
+}
+
+impl BlockKernel {
+    /// Returns the distance function, with its properties, which the kernel
+    /// computes.
+    #[must_use]
+    pub fn metric(self) -> FnMetric<Vec<f32>, f32> {
+        match self {
+            Self::Euclidean => FnMetric::new(euclidean),
+            Self::Cosine => FnMetric::new(cosine).with_is_metric(false),
+        }
+    }
+}
+
+/// The euclidean distance between two vectors.
+#[allow(clippy::ptr_arg)]
+fn euclidean(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    distances::simd::euclidean_f32(x, y)
+}
+
+/// The cosine distance between two vectors.
+#[allow(clippy::ptr_arg)]
+fn cosine(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    distances::simd::cosine_f32(x, y)
+}
+
+/// A `Dataset` of `f32` vectors which are also laid out in blocks, as a
+/// structure of arrays.
+///
+/// Each block holds `BLOCK_LANES` consecutive instances, one dimension after
+/// another, with the values of all instances in the block for a dimension
+/// adjacent in memory. The blocks are rebuilt whenever the instances are
+/// permuted, so after partitioning a `Tree` the instances of every leaf lie in
+/// consecutive blocks. Scanning a leaf, through `Dataset::query_to_many` with
+/// contiguous indices, then streams through memory once and computes the
+/// distances to all instances in a block together, instead of jumping between
+/// separately allocated vectors.
+///
+/// The blocks are a copy of the instances, so this takes twice the memory of a
+/// `VecDataset`. The instances are still kept as vectors so that they may be
+/// borrowed through `Index`.
+///
+/// # Type Parameters
+///
+/// - `M`: The type of the metadata associated with each instance.
+#[derive(Debug, Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct BlockedDataset<M: Instance = usize> {
+    /// The instances, as vectors.
+    rows: VecDataset<Vec<f32>, f32, M>,
+    /// The distance function computed over the blocks, or `None` if the
+    /// metric was changed to one which has no kernel.
+    kernel: Option<BlockKernel>,
+    /// The dimensionality of the instances.
+    dim: usize,
+    /// The blocks, each of which is `dim` consecutive `Lanes`.
+    blocks: Vec<Lanes>,
+    /// The squared norm of each instance, padded to a whole number of blocks,
+    /// for the cosine kernel.
+    norms: Vec<f32>,
+}
+
+impl BlockedDataset<usize> {
+    /// Creates a new dataset.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the dataset.
+    /// * `data`: The vectors.
+    /// * `kernel`: The distance function between vectors.
+    ///
+    /// # Errors
+    ///
+    /// * If the vectors do not all have the same dimensionality.
+    pub fn new(name: String, data: Vec<Vec<f32>>, kernel: BlockKernel) -> Result<Self, String> {
+        let dim = data.first().map_or(0, Vec::len);
+        if let Some((i, x)) = data.iter().enumerate().find(|(_, x)| x.len() != dim) {
+            return Err(format!("Instance {i} has dimensionality {}, expected {dim}", x.len()));
+        }
+
+        let rows = VecDataset::from_metric(name, data, kernel.metric());
+        Ok(Self::from_rows(rows, Some(kernel), dim))
+    }
+}
+
+impl<M: Instance> BlockedDataset<M> {
+    /// Lays out the given instances in blocks.
+    fn from_rows(rows: VecDataset<Vec<f32>, f32, M>, kernel: Option<BlockKernel>, dim: usize) -> Self {
+        let mut dataset = Self {
+            rows,
+            kernel,
+            dim,
+            blocks: Vec::new(),
+            norms: Vec::new(),
+        };
+        dataset.rebuild_blocks();
+        dataset
+    }
+
+    /// Assigns metadata to the dataset. See `VecDataset::assign_metadata`.
+    ///
+    /// # Errors
+    ///
+    /// * If the metadata is not the same length as the dataset.
+    pub fn assign_metadata<Mn: Instance>(self, metadata: Vec<Mn>) -> Result<BlockedDataset<Mn>, String> {
+        Ok(BlockedDataset {
+            rows: self.rows.assign_metadata(metadata)?,
+            kernel: self.kernel,
+            dim: self.dim,
+            blocks: self.blocks,
+            norms: self.norms,
+        })
+    }
+
+    /// Returns the distance function computed over the blocks, if any.
+    #[must_use]
+    pub const fn kernel(&self) -> Option<BlockKernel> {
+        self.kernel
+    }
+
+    /// Returns the dimensionality of the instances.
+    #[must_use]
+    pub const fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Returns the instances, as vectors.
+    #[must_use]
+    pub const fn rows(&self) -> &VecDataset<Vec<f32>, f32, M> {
+        &self.rows
+    }
+
+    /// Copies the instances into blocks, in their current order.
+    fn rebuild_blocks(&mut self) {
+        let num_blocks = self.rows.data.len().div_ceil(BLOCK_LANES);
+        let dim = self.dim;
+
+        self.blocks = vec![Lanes::default(); num_blocks * dim];
+        self.blocks
+            .chunks_mut(dim.max(1))
+            .zip(self.rows.data.chunks(BLOCK_LANES))
+            .for_each(|(block, instances)| {
+                for (l, x) in instances.iter().enumerate() {
+                    for (lanes, &v) in block.iter_mut().zip(x.iter()) {
+                        lanes.0[l] = v;
+                    }
+                }
+            });
+
+        self.norms = self
+            .rows
+            .data
+            .iter()
+            .map(|x| x.iter().map(|&v| v * v).sum())
+            .chain(core::iter::repeat(0.))
+            .take(num_blocks * BLOCK_LANES)
+            .collect();
+    }
+
+    /// Copies the instance at `index` into its place in the blocks.
+    fn write_lanes(&mut self, index: usize) {
+        let (b, l) = (index / BLOCK_LANES, index % BLOCK_LANES);
+        let x = &self.rows.data[index];
+        for (lanes, &v) in self.blocks[b * self.dim..(b + 1) * self.dim].iter_mut().zip(x.iter()) {
+            lanes.0[l] = v;
+        }
+        self.norms[index] = x.iter().map(|&v| v * v).sum();
+    }
+
+    /// Computes the distances from the query to all instances in a block.
+    // An explicit `mul_add` would be a slow library call on CPUs without FMA,
+    // while the sums below are vectorized across the lanes on all of them.
+    #[allow(clippy::suboptimal_flops)]
+    fn block_distances(&self, kernel: BlockKernel, query: &[f32], b: usize) -> [f32; BLOCK_LANES] {
+        let block = &self.blocks[b * self.dim..(b + 1) * self.dim];
+
+        match kernel {
+            BlockKernel::Euclidean => {
+                let mut acc = [0.; BLOCK_LANES];
+                for (lanes, &q) in block.iter().zip(query.iter()) {
+                    for (a, &v) in acc.iter_mut().zip(lanes.0.iter()) {
+                        let d = v - q;
+                        *a += d * d;
+                    }
+                }
+                acc.map(f32::sqrt)
+            }
+            BlockKernel::Cosine => {
+                let mut acc = [0.; BLOCK_LANES];
+                for (lanes, &q) in block.iter().zip(query.iter()) {
+                    for (a, &v) in acc.iter_mut().zip(lanes.0.iter()) {
+                        *a += v * q;
+                    }
+                }
+
+                // The same rules for degenerate vectors as in
+                // `distances::simd::cosine_f32`.
+                let qq = query.iter().map(|&v| v * v).sum::<f32>();
+                let norms = &self.norms[b * BLOCK_LANES..(b + 1) * BLOCK_LANES];
+                let mut distances = [1.; BLOCK_LANES];
+                for ((d, &xy), &xx) in distances.iter_mut().zip(acc.iter()).zip(norms.iter()) {
+                    if xx >= f32::EPSILON && qq >= f32::EPSILON && xy >= f32::EPSILON {
+                        let c = 1. - xy / (xx * qq).sqrt();
+                        *d = if c < f32::EPSILON { 0. } else { c };
+                    }
+                }
+                distances
+            }
+        }
+    }
+
+    /// Returns the path of the file in which the kernel is saved, alongside
+    /// the instances at `path`.
+    fn sidecar_path(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".soa");
+        PathBuf::from(name)
+    }
+}
+
+impl<M: Instance> Index<usize> for BlockedDataset<M> {
+    type Output = Vec<f32>;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        self.rows.index(index)
+    }
+}
+
+impl<M: Instance> Dataset<Vec<f32>, f32> for BlockedDataset<M> {
+    fn clone_with_new_metric(&self, metric: fn(&Vec<f32>, &Vec<f32>) -> f32, is_expensive: bool, name: String) -> Self {
+        // The new metric is opaque, so it is computed on the vectors.
+        Self {
+            rows: self.rows.clone_with_new_metric(metric, is_expensive, name),
+            kernel: None,
+            ..self.clone()
+        }
+    }
+
+    fn type_name() -> String {
+        format!("BlockedDataset<{}>", M::type_name())
+    }
+
+    fn name(&self) -> &str {
+        self.rows.name()
+    }
+
+    fn cardinality(&self) -> usize {
+        self.rows.cardinality()
+    }
+
+    fn get(&self, index: usize) -> Cow<'_, Vec<f32>> {
+        Cow::Borrowed(&self[index])
+    }
+
+    fn is_metric_expensive(&self) -> bool {
+        self.rows.is_metric_expensive()
+    }
+
+    fn metric(&self) -> &SharedMetric<Vec<f32>, f32> {
+        self.rows.metric()
+    }
+
+    fn is_metric(&self) -> bool {
+        self.rows.is_metric()
+    }
+
+    fn is_metric_symmetric(&self) -> bool {
+        self.rows.is_metric_symmetric()
+    }
+
+    fn weights(&self) -> Option<&[f64]> {
+        self.rows.weights()
+    }
+
+    fn set_permuted_indices(&mut self, indices: Option<&[usize]>) {
+        self.rows.set_permuted_indices(indices);
+    }
+
+    fn swap(&mut self, left: usize, right: usize) -> Result<(), String> {
+        self.rows.swap(left, right)?;
+        self.write_lanes(left);
+        self.write_lanes(right);
+        Ok(())
+    }
+
+    fn permuted_indices(&self) -> Option<&[usize]> {
+        self.rows.permuted_indices()
+    }
+
+    fn permute_instances(&mut self, permutation: &[usize]) -> Result<(), String> {
+        self.rows.permute_instances(permutation)?;
+        self.rebuild_blocks();
+        Ok(())
+    }
+
+    fn query_to_many(&self, query: &Vec<f32>, indices: &[usize]) -> Vec<f32> {
+        let contiguous = indices.windows(2).all(|w| w[1] == w[0] + 1);
+        match (self.kernel, indices.first()) {
+            (Some(kernel), Some(&start)) if contiguous && query.len() == self.dim => {
+                let end = start + indices.len();
+                let (first, last) = (start / BLOCK_LANES, (end - 1) / BLOCK_LANES);
+                let distances = (first..=last)
+                    .flat_map(|b| self.block_distances(kernel, query, b))
+                    .collect::<Vec<_>>();
+                let offset = start - first * BLOCK_LANES;
+                distances[offset..offset + indices.len()].to_vec()
+            }
+            _ => indices.iter().map(|&index| self.query_to_one(query, index)).collect(),
+        }
+    }
+
+    fn make_shards(self, max_cardinality: usize) -> Vec<Self> {
+        let Self { rows, kernel, dim, .. } = self;
+        rows.make_shards(max_cardinality)
+            .into_iter()
+            .map(|rows| Self::from_rows(rows, kernel, dim))
+            .collect()
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        self.rows.save(path)?;
+
+        // The blocks are rebuilt from the instances on loading.
+        let handle = BufWriter::new(File::create(Self::sidecar_path(path)).map_err(|e| e.to_string())?);
+        bincode::serialize_into(handle, &(self.kernel, self.dim)).map_err(|e| e.to_string())
+    }
+
+    fn load(path: &Path, metric: fn(&Vec<f32>, &Vec<f32>) -> f32, is_expensive: bool) -> Result<Self, String> {
+        let handle = BufReader::new(File::open(Self::sidecar_path(path)).map_err(|e| e.to_string())?);
+        let (kernel, dim): (Option<BlockKernel>, usize) =
+            bincode::deserialize_from(handle).map_err(|e| e.to_string())?;
+
+        // A kernel knows its own distance function, so the given one is only
+        // used if there is no kernel.
+        let rows = match kernel {
+            Some(kernel) => VecDataset::load_with_metric(path, kernel.metric())?,
+            None => VecDataset::load(path, metric, is_expensive)?,
+        };
+
+        Ok(Self::from_rows(rows, kernel, dim))
+    }
+}
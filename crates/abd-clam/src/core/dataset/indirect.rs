@@ -0,0 +1,296 @@
+//! A dataset which is reordered through an index layer, leaving the
+//! instances of another dataset in place.
+
+use core::{marker::PhantomData, ops::Index};
+
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+    sync::Arc,
+};
+
+use distances::Number;
+
+use crate::{Cluster, Dataset, SharedMetric, Tree};
+
+use super::Instance;
+
+/// A `Dataset` which wraps another and never moves its instances.
+///
+/// Partitioning a `Tree` reorders its dataset, depth-first, so that the
+/// instances of each `Cluster` are contiguous. This is fast to search but
+/// needs the instances to be moved, which is not possible if, e.g., they are
+/// in a region of memory shared with other processes. Reordering an
+/// `IndirectDataset` only permutes an index layer which maps each index to
+/// the position of its instance in the wrapped dataset, at the cost of one
+/// `usize` per instance and of an indirection on every access. The instances
+/// of a `Cluster` are then scattered in memory, so search is slower.
+///
+/// The wrapped dataset is shared, so several trees, e.g. with different
+/// partition criteria, may be built over it, and `make_shards` does not copy
+/// it. Once a `Tree` is built, `Tree::reorder` may be used to opt in to moving
+/// the instances after all.
+///
+/// Like a `SliceDataset`, `Dataset::save` only writes the index layer, and a
+/// saved `Tree` may be loaded over a new `IndirectDataset` with
+/// `Tree::load_with_data`.
+///
+/// # Type Parameters
+///
+/// - `I`: The type of the instances in the `Dataset`.
+/// - `U`: The type of the distance values between instances.
+/// - `D`: The type of the wrapped `Dataset`.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct IndirectDataset<I: Instance, U: Number, D: Dataset<I, U>> {
+    /// The name of the dataset.
+    name: String,
+    /// The wrapped dataset, whose instances are never moved.
+    inner: Arc<D>,
+    /// The position, in `inner`, of the instance at each index.
+    order: Vec<usize>,
+    /// The weights of the instances, in the order of the index layer, if the
+    /// wrapped dataset is weighted.
+    weights: Option<Vec<f64>>,
+    /// The reordering of the dataset after building the tree.
+    permuted_indices: Option<Vec<usize>>,
+    /// To satisfy the `Instance` trait bound.
+    _i: PhantomData<I>,
+    /// To satisfy the `Number` trait bound.
+    _u: PhantomData<U>,
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>> IndirectDataset<I, U, D> {
+    /// Wraps a dataset.
+    pub fn new(inner: D) -> Self {
+        Self::from_shared(Arc::new(inner))
+    }
+
+    /// Wraps a dataset which may be shared with other `IndirectDataset`s.
+    pub fn from_shared(inner: Arc<D>) -> Self {
+        Self {
+            name: inner.name().to_string(),
+            order: (0..inner.cardinality()).collect(),
+            weights: inner.weights().map(<[f64]>::to_vec),
+            permuted_indices: None,
+            inner,
+            _i: PhantomData,
+            _u: PhantomData,
+        }
+    }
+
+    /// The wrapped dataset, with its instances in the order in which they were
+    /// given.
+    #[must_use]
+    pub const fn inner(&self) -> &Arc<D> {
+        &self.inner
+    }
+
+    /// The position, in the wrapped dataset, of the instance at each index.
+    #[must_use]
+    pub fn order(&self) -> &[usize] {
+        &self.order
+    }
+}
+
+// A derived `Clone` would need the wrapped dataset to be `Clone`, but only the
+// handle to it is cloned.
+impl<I: Instance, U: Number, D: Dataset<I, U>> Clone for IndirectDataset<I, U, D> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            inner: Arc::clone(&self.inner),
+            order: self.order.clone(),
+            weights: self.weights.clone(),
+            permuted_indices: self.permuted_indices.clone(),
+            _i: PhantomData,
+            _u: PhantomData,
+        }
+    }
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U> + Index<usize, Output = I>> Index<usize> for IndirectDataset<I, U, D> {
+    type Output = I;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.inner[self.order[index]]
+    }
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>> Dataset<I, U> for IndirectDataset<I, U, D> {
+    fn clone_with_new_metric(&self, metric: fn(&I, &I) -> U, is_expensive: bool, name: String) -> Self {
+        let inner = self
+            .inner
+            .clone_with_new_metric(metric, is_expensive, self.inner.name().to_string());
+        Self {
+            name,
+            inner: Arc::new(inner),
+            ..self.clone()
+        }
+    }
+
+    fn type_name() -> String {
+        format!("IndirectDataset<{}>", D::type_name())
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn cardinality(&self) -> usize {
+        self.order.len()
+    }
+
+    fn get(&self, index: usize) -> Cow<'_, I> {
+        self.inner.get(self.order[index])
+    }
+
+    fn is_metric_expensive(&self) -> bool {
+        self.inner.is_metric_expensive()
+    }
+
+    fn metric(&self) -> &SharedMetric<I, U> {
+        self.inner.metric()
+    }
+
+    fn is_metric(&self) -> bool {
+        self.inner.is_metric()
+    }
+
+    fn is_metric_symmetric(&self) -> bool {
+        self.inner.is_metric_symmetric()
+    }
+
+    fn lower_bound(&self) -> Option<fn(&I, &I) -> U> {
+        self.inner.lower_bound()
+    }
+
+    fn upper_bound(&self) -> Option<fn(&I, &I) -> U> {
+        self.inner.upper_bound()
+    }
+
+    fn weights(&self) -> Option<&[f64]> {
+        self.weights.as_deref()
+    }
+
+    fn set_permuted_indices(&mut self, indices: Option<&[usize]>) {
+        self.permuted_indices = indices.map(<[usize]>::to_vec);
+    }
+
+    fn swap(&mut self, left: usize, right: usize) -> Result<(), String> {
+        if left.max(right) >= self.order.len() {
+            return Err(format!(
+                "Invalid indices. Expected indices less than {}, got {left} and {right}",
+                self.order.len()
+            ));
+        }
+        self.order.swap(left, right);
+        if let Some(weights) = self.weights.as_mut() {
+            weights.swap(left, right);
+        }
+        Ok(())
+    }
+
+    fn permuted_indices(&self) -> Option<&[usize]> {
+        self.permuted_indices.as_deref()
+    }
+
+    fn permute_instances(&mut self, permutation: &[usize]) -> Result<(), String> {
+        if permutation.len() != self.order.len() {
+            return Err(format!(
+                "Invalid permutation. Expected permutation of length {}, got permutation of length {}",
+                self.cardinality(),
+                permutation.len()
+            ));
+        }
+
+        self.order = permutation.iter().map(|&index| self.order[index]).collect();
+        if let Some(weights) = self.weights.as_ref() {
+            self.weights = Some(permutation.iter().map(|&index| weights[index]).collect());
+        }
+        self.set_permuted_indices(Some(permutation));
+
+        Ok(())
+    }
+
+    fn make_shards(mut self, max_cardinality: usize) -> Vec<Self> {
+        let mut shards = Vec::new();
+
+        while self.order.len() > max_cardinality {
+            let at = self.order.len() - max_cardinality;
+            shards.push(Self {
+                name: format!("{}-shard-{}", self.name, shards.len()),
+                inner: Arc::clone(&self.inner),
+                order: self.order.split_off(at),
+                weights: self.weights.as_mut().map(|weights| weights.split_off(at)),
+                permuted_indices: None,
+                _i: PhantomData,
+                _u: PhantomData,
+            });
+
+            if let Some(permutation) = self.permuted_indices.as_mut() {
+                permutation.truncate(at);
+            }
+        }
+
+        self.name = format!("{}-shard-{}", self.name, shards.len());
+        shards.push(self);
+
+        shards
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        let handle = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+        let contents = (Self::type_name(), &self.name, &self.order, &self.permuted_indices);
+        bincode::serialize_into(handle, &contents).map_err(|e| e.to_string())
+    }
+
+    fn load(path: &Path, _: fn(&I, &I) -> U, _: bool) -> Result<Self, String> {
+        // The file is read so that a missing or corrupted file is reported as
+        // such, rather than as a wrapped dataset.
+        let handle = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+        let (type_name, ..): (String, String, Vec<usize>, Option<Vec<usize>>) =
+            bincode::deserialize_from(handle).map_err(|e| e.to_string())?;
+        Err(format!(
+            "A {type_name} does not save the dataset it wraps and cannot be loaded from a file. Use `Tree::load_with_data` instead."
+        ))
+    }
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, IndirectDataset<I, U, D>, C> {
+    /// Moves the instances of the wrapped dataset into the order of the
+    /// `Tree`, and drops the index layer.
+    ///
+    /// This is the reordering which partitioning a `Tree` over any other
+    /// dataset does as it goes. Searching the returned `Tree` gives the same
+    /// results, with the same indices, but is faster.
+    ///
+    /// # Errors
+    ///
+    /// * If the wrapped dataset is shared with other `IndirectDataset`s.
+    /// * If the wrapped dataset could not be reordered.
+    pub fn reorder(self) -> Result<Tree<I, U, D, C>, String> {
+        let Self {
+            data, root, tombstones, ..
+        } = self;
+        let mut inner = Arc::try_unwrap(data.inner)
+            .map_err(|_| "The wrapped dataset is shared, so its instances cannot be moved.".to_string())?;
+        if data.order.len() != inner.cardinality() {
+            return Err("The dataset is a shard of the wrapped dataset, so its instances cannot be moved.".to_string());
+        }
+
+        // The original indices of the `Tree` are positions in the wrapped
+        // dataset, which was not necessarily in its own original order.
+        if data.permuted_indices.is_some() {
+            let original = data.order.iter().map(|&p| inner.original_index(p)).collect::<Vec<_>>();
+            inner.permute_instances(&data.order)?;
+            inner.set_permuted_indices(Some(&original));
+        }
+
+        let mut tree = Tree::from_root_and_data(root, inner);
+        tree.tombstones = tombstones;
+        Ok(tree)
+    }
+}
@@ -5,6 +5,11 @@ use core::fmt::Debug;
 use distances::Number;
 
 /// Trait for individual data points.
+///
+/// Instances need only be `Clone`, so owned types such as `String`, `Vec<u8>`
+/// or user-defined structs can be indexed and searched. A user-defined type
+/// implements `to_bytes` and `from_bytes` so that datasets of it can be saved,
+/// loaded and stored on disk, e.g. in a `LeafStore`.
 pub trait Instance: Debug + Send + Sync + Clone {
     /// Convert the instance to a byte vector.
     fn to_bytes(&self) -> Vec<u8>;
@@ -53,6 +58,29 @@ pub trait Instance: Debug + Send + Sync + Clone {
 
         Self::from_bytes(&buf)
     }
+
+    /// The dimensionality of the instance, if it is a vector.
+    ///
+    /// `Cakes::try_knn_search` and `Cakes::try_rnn_search` reject queries whose
+    /// dimensionality differs from that of the instances. Instances without a
+    /// dimensionality, e.g. strings, return `None` and are never rejected.
+    fn dim(&self) -> Option<usize> {
+        None
+    }
+
+    /// The elements of the instance, if it is a vector of `f32`s.
+    ///
+    /// Metrics with a `SimdKernel` use this to compute distances with the SIMD
+    /// kernels in `distances::simd`.
+    fn as_f32_slice(&self) -> Option<&[f32]> {
+        None
+    }
+
+    /// The elements of the instance, if it is a vector of `f64`s. See
+    /// `Instance::as_f32_slice`.
+    fn as_f64_slice(&self) -> Option<&[f64]> {
+        None
+    }
 }
 
 impl<T: Number> Instance for Vec<T> {
@@ -78,6 +106,52 @@ impl<T: Number> Instance for Vec<T> {
     fn type_name() -> String {
         format!("Vec<{}>", T::type_name())
     }
+
+    fn dim(&self) -> Option<usize> {
+        Some(self.len())
+    }
+
+    fn as_f32_slice(&self) -> Option<&[f32]> {
+        T::as_f32_slice(self)
+    }
+
+    fn as_f64_slice(&self) -> Option<&[f64]> {
+        T::as_f64_slice(self)
+    }
+}
+
+impl<T: Number, const N: usize> Instance for [T; N] {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.iter().flat_map(|x| x.to_le_bytes()).collect()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() == N * T::num_bytes() {
+            let mut instance = [T::zero(); N];
+            for (x, b) in instance.iter_mut().zip(bytes.chunks_exact(T::num_bytes())) {
+                *x = T::from_le_bytes(b);
+            }
+            Ok(instance)
+        } else {
+            Err(format!("Expected {} bytes, got {}", N * T::num_bytes(), bytes.len()))
+        }
+    }
+
+    fn type_name() -> String {
+        format!("[{}; {N}]", T::type_name())
+    }
+
+    fn dim(&self) -> Option<usize> {
+        Some(N)
+    }
+
+    fn as_f32_slice(&self) -> Option<&[f32]> {
+        T::as_f32_slice(self)
+    }
+
+    fn as_f64_slice(&self) -> Option<&[f64]> {
+        T::as_f64_slice(self)
+    }
 }
 
 impl Instance for String {
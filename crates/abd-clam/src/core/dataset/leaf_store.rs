@@ -0,0 +1,506 @@
+//! A dataset whose instances are read from disk, one leaf at a time, through
+//! an LRU cache.
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+};
+
+use distances::Number;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::tree::{load_bincode, save_bincode},
+    Dataset, FnMetric, Instance, Metric, SharedMetric,
+};
+
+/// A block of instances, i.e. the instances in one leaf, held in the cache.
+#[derive(Debug)]
+struct CachedBlock<I> {
+    /// The instances in the block.
+    instances: Arc<Vec<I>>,
+    /// The number of bytes the block occupies on disk.
+    num_bytes: usize,
+    /// The time at which the block was last used.
+    last_used: u64,
+}
+
+/// A least-recently-used cache of blocks, bounded by the number of bytes
+/// which the blocks occupy on disk.
+#[derive(Debug)]
+struct BlockCache<I> {
+    /// The maximum number of bytes in the cache.
+    capacity: usize,
+    /// The number of bytes currently in the cache.
+    size: usize,
+    /// A counter used to order the uses of blocks.
+    clock: u64,
+    /// The cached blocks, keyed by their positions.
+    blocks: HashMap<usize, CachedBlock<I>>,
+    /// The positions of the cached blocks, keyed by when they were last used.
+    recency: BTreeMap<u64, usize>,
+}
+
+impl<I> BlockCache<I> {
+    /// Creates an empty cache.
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            size: 0,
+            clock: 0,
+            blocks: HashMap::new(),
+            recency: BTreeMap::new(),
+        }
+    }
+
+    /// Returns a cached block, marking it as the most recently used.
+    fn get(&mut self, b: usize) -> Option<Arc<Vec<I>>> {
+        self.clock += 1;
+        let block = self.blocks.get_mut(&b)?;
+        self.recency.remove(&block.last_used);
+        block.last_used = self.clock;
+        self.recency.insert(self.clock, b);
+        Some(Arc::clone(&block.instances))
+    }
+
+    /// Adds a block to the cache, evicting the least recently used blocks to
+    /// make room for it. Blocks larger than the cache are not added.
+    fn insert(&mut self, b: usize, instances: Arc<Vec<I>>, num_bytes: usize) {
+        if num_bytes > self.capacity || self.blocks.contains_key(&b) {
+            return;
+        }
+
+        while self.size + num_bytes > self.capacity {
+            let Some((_, lru)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some(evicted) = self.blocks.remove(&lru) {
+                self.size -= evicted.num_bytes;
+            }
+        }
+
+        self.clock += 1;
+        self.size += num_bytes;
+        self.recency.insert(self.clock, b);
+        let block = CachedBlock {
+            instances,
+            num_bytes,
+            last_used: self.clock,
+        };
+        self.blocks.insert(b, block);
+    }
+}
+
+/// The parts of a `LeafStore` which are saved to disk with `Dataset::save`.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    /// The type name of the dataset, for basic protection against reading
+    /// bad data.
+    type_name: String,
+    /// The name of the dataset.
+    name: String,
+    /// The path to the file holding the instances.
+    path: PathBuf,
+    /// The byte offset of each instance in the file, followed by the end of
+    /// the last instance.
+    offsets: Vec<u64>,
+    /// The index of the first instance in each block.
+    blocks: Vec<usize>,
+    /// The maximum number of bytes in the cache.
+    cache_bytes: usize,
+    /// The reordering of the dataset after building the tree.
+    permuted_indices: Option<Vec<usize>>,
+}
+
+/// A `Dataset` whose instances live on disk and are read on demand, one block
+/// at a time, through an LRU cache of at most `cache_bytes`.
+///
+/// The blocks are the leaves of the `Tree` from which the store was built, so
+/// that a search which reaches a leaf reads all of its instances with one
+/// read. Only the byte offsets of the instances are kept in memory. Create a
+/// store with `Tree::with_leaf_store` after partitioning a tree.
+///
+/// Distances computed through the `Dataset` methods, as in all search
+/// algorithms, go through the cache, and `Dataset::get` returns a copy of an
+/// instance which is dropped with the returned value. No instance is held in
+/// memory outside the cache.
+///
+/// The instances are stored in the order of the tree and cannot be reordered,
+/// so `Dataset::swap` returns an error.
+///
+/// # Type Parameters
+///
+/// - `I`: The type of the instances.
+/// - `U`: The type of the distance values between instances.
+#[derive(Debug)]
+pub struct LeafStore<I: Instance, U: Number> {
+    /// The name of the dataset.
+    name: String,
+    /// The path to the file holding the instances.
+    path: PathBuf,
+    /// The file holding the instances, shared between shards.
+    file: Arc<Mutex<File>>,
+    /// The byte offset of each instance in the file, followed by the end of
+    /// the last instance.
+    offsets: Vec<u64>,
+    /// The index of the first instance in each block.
+    blocks: Vec<usize>,
+    /// The metric of the dataset.
+    metric: SharedMetric<I, U>,
+    /// The reordering of the dataset after building the tree.
+    permuted_indices: Option<Vec<usize>>,
+    /// The cache of blocks read from the file.
+    cache: Mutex<BlockCache<I>>,
+    /// The number of blocks read from the file.
+    num_reads: AtomicUsize,
+}
+
+impl<I: Instance, U: Number> LeafStore<I, U> {
+    /// Writes the instances of a dataset to a file, in their current order,
+    /// and opens the file as a `LeafStore`.
+    ///
+    /// The metric, its properties and the permutation of the dataset are
+    /// carried over.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The dataset to write.
+    /// * `blocks`: The index of the first instance in each block. These are
+    ///   sorted, and `0` is added if it is missing.
+    /// * `path`: The path to the file to create.
+    /// * `cache_bytes`: The maximum number of bytes of blocks in the cache.
+    ///
+    /// # Errors
+    ///
+    /// * If the file cannot be written to or opened.
+    /// * If any block starts beyond the cardinality of the dataset.
+    pub fn from_dataset<D: Dataset<I, U>>(
+        data: &D,
+        blocks: Vec<usize>,
+        path: &Path,
+        cache_bytes: usize,
+    ) -> Result<Self, String> {
+        let mut writer = LeafStoreWriter::create(path)?;
+        for i in 0..data.cardinality() {
+            writer.write(&*data.get(i))?;
+        }
+        writer.finish(
+            data.name().to_string(),
+            blocks,
+            cache_bytes,
+            data.metric().clone(),
+            data.permuted_indices().map(<[usize]>::to_vec),
+        )
+    }
+
+    /// Opens the file described by a header.
+    fn open(header: Header, metric: SharedMetric<I, U>) -> Result<Self, String> {
+        let file = File::open(&header.path).map_err(|e| e.to_string())?;
+        Ok(Self {
+            name: header.name,
+            path: header.path,
+            file: Arc::new(Mutex::new(file)),
+            offsets: header.offsets,
+            blocks: header.blocks,
+            metric,
+            permuted_indices: header.permuted_indices,
+            cache: Mutex::new(BlockCache::new(header.cache_bytes)),
+            num_reads: AtomicUsize::new(0),
+        })
+    }
+
+    /// Returns the path to the file holding the instances.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the number of blocks.
+    #[must_use]
+    pub fn num_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Returns the number of times a block has been read from the file.
+    #[must_use]
+    pub fn num_block_reads(&self) -> usize {
+        self.num_reads.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes of blocks currently in the cache.
+    #[must_use]
+    pub fn cached_bytes(&self) -> usize {
+        self.lock_cache().size
+    }
+
+    /// Locks the cache, recovering it if another thread panicked while
+    /// holding the lock.
+    fn lock_cache(&self) -> MutexGuard<'_, BlockCache<I>> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the range of instances in a block.
+    fn block_range(&self, b: usize) -> (usize, usize) {
+        let end = self.blocks.get(b + 1).copied().unwrap_or_else(|| self.cardinality());
+        (self.blocks[b], end)
+    }
+
+    /// Returns the block containing an instance, read from the cache or the
+    /// file, along with the position of the instance in the block.
+    ///
+    /// # Panics
+    ///
+    /// * If the block cannot be read from the file.
+    #[allow(clippy::panic)]
+    fn block_of(&self, index: usize) -> (Arc<Vec<I>>, usize) {
+        let b = self.blocks.partition_point(|&s| s <= index) - 1;
+        let (start, _) = self.block_range(b);
+
+        let cached = self.lock_cache().get(b);
+        if let Some(block) = cached {
+            return (block, index - start);
+        }
+
+        let (block, num_bytes) = self
+            .read_block(b)
+            .unwrap_or_else(|e| panic!("Failed to read block {b} of {}: {e}", self.path.display()));
+        let block = Arc::new(block);
+        self.lock_cache().insert(b, Arc::clone(&block), num_bytes);
+        (block, index - start)
+    }
+
+    /// Reads a block from the file, returning its instances and the number of
+    /// bytes they occupy.
+    fn read_block(&self, b: usize) -> Result<(Vec<I>, usize), String> {
+        let (start, end) = self.block_range(b);
+        let first = self.offsets[start];
+        let num_bytes = usize::try_from(self.offsets[end] - first).map_err(|e| e.to_string())?;
+
+        let mut bytes = vec![0; num_bytes];
+        {
+            let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+            file.seek(SeekFrom::Start(first)).map_err(|e| e.to_string())?;
+            file.read_exact(&mut bytes).map_err(|e| e.to_string())?;
+        }
+        self.num_reads.fetch_add(1, Ordering::Relaxed);
+
+        let instances = self.offsets[start..=end]
+            .windows(2)
+            .map(|w| {
+                let s = usize::try_from(w[0] - first).map_err(|e| e.to_string())?;
+                let e = usize::try_from(w[1] - first).map_err(|e| e.to_string())?;
+                I::from_bytes(&bytes[s..e])
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok((instances, num_bytes))
+    }
+
+    /// Returns a store over a contiguous range of the instances, sharing the
+    /// same file.
+    fn slice(&self, start: usize, end: usize, name: String) -> Self {
+        let blocks = self
+            .blocks
+            .iter()
+            .filter(|&&b| start < b && b < end)
+            .map(|&b| b - start);
+        Self {
+            name,
+            path: self.path.clone(),
+            file: Arc::clone(&self.file),
+            offsets: self.offsets[start..=end].to_vec(),
+            blocks: core::iter::once(0).chain(blocks).collect(),
+            metric: self.metric.clone(),
+            permuted_indices: None,
+            cache: Mutex::new(BlockCache::new(self.lock_cache().capacity)),
+            num_reads: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// Writes instances, in order, to the file of a new `LeafStore`.
+pub struct LeafStoreWriter {
+    /// The path to the file.
+    path: PathBuf,
+    /// The writer for the file.
+    writer: BufWriter<File>,
+    /// The byte offset of each instance written so far, followed by the end
+    /// of the last instance.
+    offsets: Vec<u64>,
+}
+
+impl LeafStoreWriter {
+    /// Creates the file at `path`, overwriting it if it exists.
+    pub(crate) fn create(path: &Path) -> Result<Self, String> {
+        let writer = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer,
+            offsets: vec![0],
+        })
+    }
+
+    /// Returns the number of instances written so far.
+    pub(crate) fn cardinality(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    /// Appends an instance to the file.
+    pub(crate) fn write<I: Instance>(&mut self, instance: &I) -> Result<(), String> {
+        let bytes = instance.to_bytes();
+        self.writer.write_all(&bytes).map_err(|e| e.to_string())?;
+        let end = self.offsets.last().copied().unwrap_or_default() + bytes.len() as u64;
+        self.offsets.push(end);
+        Ok(())
+    }
+
+    /// Flushes the file and opens it as a `LeafStore`.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the dataset.
+    /// * `blocks`: The index of the first instance in each block. These are
+    ///   sorted, and `0` is added if it is missing.
+    /// * `cache_bytes`: The maximum number of bytes of blocks in the cache.
+    /// * `metric`: The metric and its properties.
+    /// * `permuted_indices`: The original index of each instance, if any.
+    pub(crate) fn finish<I: Instance, U: Number>(
+        mut self,
+        name: String,
+        mut blocks: Vec<usize>,
+        cache_bytes: usize,
+        metric: SharedMetric<I, U>,
+        permuted_indices: Option<Vec<usize>>,
+    ) -> Result<LeafStore<I, U>, String> {
+        self.writer.flush().map_err(|e| e.to_string())?;
+        drop(self.writer);
+
+        let cardinality = self.offsets.len() - 1;
+        blocks.push(0);
+        blocks.sort_unstable();
+        blocks.dedup();
+        if cardinality > 0 && blocks.last() >= Some(&cardinality) {
+            return Err(format!("Blocks must start below the cardinality {cardinality}"));
+        }
+
+        let header = Header {
+            type_name: LeafStore::<I, U>::type_name(),
+            name,
+            path: self.path,
+            offsets: self.offsets,
+            blocks,
+            cache_bytes,
+            permuted_indices,
+        };
+        LeafStore::open(header, metric)
+    }
+}
+
+impl<I: Instance, U: Number> Dataset<I, U> for LeafStore<I, U> {
+    fn clone_with_new_metric(&self, metric: fn(&I, &I) -> U, is_expensive: bool, name: String) -> Self {
+        let mut store = self.slice(0, self.cardinality(), name);
+        store.metric = FnMetric::new(metric).with_is_expensive(is_expensive).into();
+        store.permuted_indices.clone_from(&self.permuted_indices);
+        store
+    }
+
+    fn type_name() -> String {
+        format!("LeafStore<{}, {}>", I::type_name(), U::type_name())
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn cardinality(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    fn get(&self, index: usize) -> Cow<'_, I> {
+        let (block, i) = self.block_of(index);
+        Cow::Owned(block[i].clone())
+    }
+
+    fn metric(&self) -> &SharedMetric<I, U> {
+        &self.metric
+    }
+
+    fn set_permuted_indices(&mut self, indices: Option<&[usize]>) {
+        self.permuted_indices = indices.map(<[usize]>::to_vec);
+    }
+
+    fn swap(&mut self, _: usize, _: usize) -> Result<(), String> {
+        Err("Instances in a LeafStore cannot be reordered".to_string())
+    }
+
+    fn permuted_indices(&self) -> Option<&[usize]> {
+        self.permuted_indices.as_deref()
+    }
+
+    fn one_to_one(&self, left: usize, right: usize) -> U {
+        let (block, l) = self.block_of(left);
+        self.query_to_one(&block[l], right)
+    }
+
+    fn query_to_one(&self, query: &I, index: usize) -> U {
+        let (block, i) = self.block_of(index);
+        self.metric.distance(query, &block[i])
+    }
+
+    fn one_to_many(&self, left: usize, right: &[usize]) -> Vec<U> {
+        let (block, l) = self.block_of(left);
+        self.query_to_many(&block[l], right)
+    }
+
+    fn make_shards(self, max_cardinality: usize) -> Vec<Self> {
+        // Shards are split off from the end, as in `VecDataset::make_shards`.
+        let mut shards = Vec::new();
+        let mut end = self.cardinality();
+        while end > max_cardinality {
+            let name = format!("{}-shard-{}", self.name, shards.len());
+            shards.push(self.slice(end - max_cardinality, end, name));
+            end -= max_cardinality;
+        }
+
+        let name = format!("{}-shard-{}", self.name, shards.len());
+        let mut head = self.slice(0, end, name);
+        head.permuted_indices = self.permuted_indices;
+        shards.push(head);
+
+        shards
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        let header = Header {
+            type_name: Self::type_name(),
+            name: self.name.clone(),
+            path: self.path.clone(),
+            offsets: self.offsets.clone(),
+            blocks: self.blocks.clone(),
+            cache_bytes: self.lock_cache().capacity,
+            permuted_indices: self.permuted_indices.clone(),
+        };
+        Ok(save_bincode(path, &header)?)
+    }
+
+    fn load(path: &Path, metric: fn(&I, &I) -> U, is_expensive: bool) -> Result<Self, String> {
+        let header: Header = load_bincode(path)?;
+
+        let actual_type_name = Self::type_name();
+        if header.type_name != actual_type_name {
+            return Err(format!(
+                "Invalid type. File has data of type {} but dataset was constructed with type {actual_type_name}",
+                header.type_name
+            ));
+        }
+
+        Self::open(header, FnMetric::new(metric).with_is_expensive(is_expensive).into())
+    }
+}
@@ -0,0 +1,491 @@
+//! A dataset of fixed-width vectors in a memory-mapped file.
+//!
+//! # On-disk layout
+//!
+//! The file begins with a header of 64 bytes, followed by the instances.
+//!
+//! | Bytes    | Content                                                    |
+//! |----------|------------------------------------------------------------|
+//! | `0..8`   | The magic bytes `CLAMMMAP`.                                |
+//! | `8..16`  | The version of the format, currently `1`, as a `u64`.      |
+//! | `16..24` | The number of bytes in each element, as a `u64`.           |
+//! | `24..32` | The dimensionality of each instance, as a `u64`.           |
+//! | `32..40` | The cardinality of the dataset, as a `u64`.                |
+//! | `40..64` | The type name of the elements in UTF-8, padded with zeros. |
+//! | `64..`   | The instances, in row-major order.                         |
+//!
+//! All numbers, including the elements of the instances, are little-endian.
+//! Since the header is a multiple of the size of each element, and memory maps
+//! are page-aligned, every instance is aligned in memory.
+
+use core::ops::Index;
+
+use std::{
+    borrow::Cow,
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bytemuck::Pod;
+use distances::Number;
+use memmap2::{MmapMut, MmapOptions};
+
+use crate::{Dataset, FnMetric, Instance, SharedMetric, VecDataset};
+
+/// The magic bytes at the start of a memory-mapped dataset file.
+const MAGIC: &[u8; 8] = b"CLAMMMAP";
+
+/// The version of the on-disk layout.
+const FORMAT_VERSION: u64 = 1;
+
+/// The number of bytes in the header of a memory-mapped dataset file.
+const HEADER_BYTES: usize = 64;
+
+/// The number of bytes reserved for the type name of the elements.
+const TYPE_NAME_BYTES: usize = 24;
+
+/// The memory map of a backing file, shared by a dataset and its shards and
+/// clones.
+///
+/// The instances are read and written through `ptr`, never through a
+/// reference to the whole map, so that each dataset only borrows the bytes of
+/// its own instances.
+#[derive(Debug)]
+struct SharedMmap {
+    /// The memory map, which is kept alive for as long as `ptr` is used.
+    mmap: MmapMut,
+    /// The start of the memory map.
+    ptr: *mut u8,
+}
+
+// SAFETY: The bytes behind `ptr` are owned by `mmap`, which is `Send` and
+// `Sync`. An instance is only written by `MmapDataset::swap`, which requires
+// exclusive access to the instances it writes.
+unsafe impl Send for SharedMmap {}
+// SAFETY: See `Send`.
+unsafe impl Sync for SharedMmap {}
+
+impl SharedMmap {
+    /// Shares the memory map.
+    fn new(mut mmap: MmapMut) -> Arc<Self> {
+        let ptr = mmap.as_mut_ptr();
+        Arc::new(Self { mmap, ptr })
+    }
+}
+
+/// A `Dataset` of fixed-width vectors stored in a memory-mapped file.
+///
+/// This may be used for datasets which are too large to fit in memory. The
+/// operating system pages instances in and out of memory as they are accessed.
+///
+/// Reordering the dataset, e.g. when partitioning a `Tree`, swaps instances
+/// in the backing file. The permutation itself is kept in memory and is saved
+/// with `Dataset::save`.
+///
+/// Shards and clones share the memory map of the dataset. Shards have disjoint
+/// ranges of instances, and may each be reordered. A dataset and its clones
+/// have the same range, and cannot be reordered while more than one of them
+/// exists.
+///
+/// The backing file must not be modified by other processes while it is
+/// mapped into memory.
+///
+/// See the module documentation for the on-disk layout.
+///
+/// # Type Parameters
+///
+/// - `T`: The type of the elements of the instances.
+/// - `U`: The type of the distance values between instances.
+/// - `DIM`: The dimensionality of the instances.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct MmapDataset<T: Number + Pod, U: Number, const DIM: usize> {
+    /// The name of the dataset.
+    name: String,
+    /// The path to the backing file.
+    path: PathBuf,
+    /// The memory map of the backing file.
+    mmap: Arc<SharedMmap>,
+    /// A token shared by the datasets with the same range of instances, i.e.
+    /// a dataset and its clones. Instances are only swapped by the dataset
+    /// which holds the only token.
+    range_token: Arc<()>,
+    /// The index, in the backing file, of the first instance in the dataset.
+    start: usize,
+    /// The number of instances in the dataset.
+    cardinality: usize,
+    /// The metric of the dataset.
+    metric: SharedMetric<[T; DIM], U>,
+    /// The reordering of the dataset after building the tree.
+    permuted_indices: Option<Vec<usize>>,
+}
+
+impl<T: Number + Pod, U: Number, const DIM: usize> MmapDataset<T, U, DIM> {
+    /// Opens a memory-mapped dataset from a file.
+    ///
+    /// The name of the dataset is the stem of the file name.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path to the file.
+    /// * `metric`: The metric for computing distances between instances.
+    /// * `is_expensive`: Whether the metric is expensive to compute.
+    ///
+    /// # Errors
+    ///
+    /// * If the file cannot be opened or mapped into memory.
+    /// * If the header is malformed or does not match `T` and `DIM`.
+    /// * If the file is too short for the cardinality in the header.
+    /// * If the target is not little-endian.
+    pub fn open(path: &Path, metric: fn(&[T; DIM], &[T; DIM]) -> U, is_expensive: bool) -> Result<Self, String> {
+        let mmap = Self::map(path)?;
+        let cardinality = Self::read_header(&mmap)?;
+
+        let name = path
+            .file_stem()
+            .map_or_else(String::new, |s| s.to_string_lossy().to_string());
+
+        Ok(Self {
+            name,
+            path: path.to_path_buf(),
+            mmap: SharedMmap::new(mmap),
+            range_token: Arc::new(()),
+            start: 0,
+            cardinality,
+            metric: FnMetric::new(metric).with_is_expensive(is_expensive).into(),
+            permuted_indices: None,
+        })
+    }
+
+    /// Writes a `VecDataset` to a file in the memory-mapped format and opens
+    /// the file as a `MmapDataset`.
+    ///
+    /// The instances are written in their current order, and any permutation
+    /// of the `VecDataset` is carried over. Metadata is not written.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The dataset to convert.
+    /// * `path`: The path to the file to create.
+    /// * `metric`: The metric for computing distances between instances.
+    /// * `is_expensive`: Whether the metric is expensive to compute.
+    ///
+    /// # Errors
+    ///
+    /// * If any instance does not have `DIM` elements.
+    /// * If the file cannot be written to.
+    /// * See `open`.
+    pub fn from_vec_dataset<V: Number, M: Instance>(
+        data: &VecDataset<Vec<T>, V, M>,
+        path: &Path,
+        metric: fn(&[T; DIM], &[T; DIM]) -> U,
+        is_expensive: bool,
+    ) -> Result<Self, String> {
+        if let Some((i, row)) = data.data().iter().enumerate().find(|(_, row)| row.len() != DIM) {
+            return Err(format!("Instance {i} has dimensionality {}, expected {DIM}", row.len()));
+        }
+
+        let mut handle = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+        handle
+            .write_all(&Self::header(data.cardinality()))
+            .map_err(|e| e.to_string())?;
+        for row in data.data() {
+            handle.write_all(&row.to_bytes()).map_err(|e| e.to_string())?;
+        }
+        handle.flush().map_err(|e| e.to_string())?;
+        drop(handle);
+
+        let mut mmap_data = Self::open(path, metric, is_expensive)?;
+        mmap_data.name = data.name().to_string();
+        mmap_data.permuted_indices = data.permuted_indices().map(<[usize]>::to_vec);
+        Ok(mmap_data)
+    }
+
+    /// Replaces the metric of the dataset, e.g. with a `Metric` which is not
+    /// a plain function.
+    #[must_use]
+    pub fn with_metric(mut self, metric: impl Into<SharedMetric<[T; DIM], U>>) -> Self {
+        self.metric = metric.into();
+        self
+    }
+
+    /// The path to the backing file.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Flushes any reordering of the instances to the backing file.
+    ///
+    /// # Errors
+    ///
+    /// * If the memory map cannot be flushed.
+    pub fn flush(&self) -> Result<(), String> {
+        self.mmap.mmap.flush().map_err(|e| e.to_string())
+    }
+
+    /// Maps the file at `path` into memory.
+    fn map(path: &Path) -> Result<MmapMut, String> {
+        if cfg!(target_endian = "big") {
+            return Err("Memory-mapped datasets are only supported on little-endian targets".to_string());
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| e.to_string())?;
+
+        // SAFETY: The mapping is only valid as long as no other process
+        // modifies the file. This is the documented contract of `MmapDataset`.
+        unsafe { MmapOptions::new().map_mut(&file) }.map_err(|e| e.to_string())
+    }
+
+    /// Creates the header for a file with the given `cardinality`.
+    fn header(cardinality: usize) -> [u8; HEADER_BYTES] {
+        let mut header = [0; HEADER_BYTES];
+        header[0..8].copy_from_slice(MAGIC);
+        header[8..16].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+        header[16..24].copy_from_slice(&(T::num_bytes() as u64).to_le_bytes());
+        header[24..32].copy_from_slice(&(DIM as u64).to_le_bytes());
+        header[32..40].copy_from_slice(&(cardinality as u64).to_le_bytes());
+
+        let type_name = T::type_name();
+        let n = type_name.len().min(TYPE_NAME_BYTES);
+        header[40..(40 + n)].copy_from_slice(&type_name.as_bytes()[..n]);
+
+        header
+    }
+
+    /// Validates the header of a memory-mapped file and returns the
+    /// cardinality of the dataset.
+    fn read_header(mmap: &[u8]) -> Result<usize, String> {
+        if mmap.len() < HEADER_BYTES || &mmap[0..8] != MAGIC {
+            return Err("File is not a memory-mapped dataset".to_string());
+        }
+
+        let read_u64 = |range: core::ops::Range<usize>| <u64 as Number>::from_le_bytes(&mmap[range]);
+
+        let version = read_u64(8..16);
+        if version != FORMAT_VERSION {
+            return Err(format!(
+                "Unsupported format version. Expected {FORMAT_VERSION}, got {version}"
+            ));
+        }
+
+        let expected = Self::header(0);
+        if mmap[16..24] != expected[16..24] || mmap[40..64] != expected[40..64] {
+            let type_name = String::from_utf8_lossy(&mmap[40..64]);
+            return Err(format!(
+                "Invalid type. File has elements of type {} but dataset was constructed with type {}",
+                type_name.trim_end_matches('\0'),
+                T::type_name()
+            ));
+        }
+
+        let dimensionality = read_u64(24..32);
+        if dimensionality != DIM as u64 {
+            return Err(format!(
+                "Invalid dimensionality. File has dimensionality {dimensionality} but dataset was constructed with {DIM}"
+            ));
+        }
+
+        let cardinality = usize::try_from(read_u64(32..40)).map_err(|e| e.to_string())?;
+        let expected_len = HEADER_BYTES + cardinality * DIM * T::num_bytes();
+        if mmap.len() < expected_len {
+            return Err(format!(
+                "File is too short. Expected at least {expected_len} bytes, got {}",
+                mmap.len()
+            ));
+        }
+
+        Ok(cardinality)
+    }
+
+    /// The number of bytes in each instance.
+    const fn row_bytes() -> usize {
+        DIM * core::mem::size_of::<T>()
+    }
+
+    /// The byte offset of the instance at `index` in the memory map.
+    const fn row_offset(&self, index: usize) -> usize {
+        HEADER_BYTES + (self.start + index) * Self::row_bytes()
+    }
+}
+
+impl<T: Number + Pod, U: Number, const DIM: usize> Index<usize> for MmapDataset<T, U, DIM> {
+    type Output = [T; DIM];
+
+    fn index(&self, index: usize) -> &Self::Output {
+        assert!(index < self.cardinality, "Index {index} out of bounds.");
+        let offset = self.row_offset(index);
+        // SAFETY: The instance is in the range of this dataset, and is within
+        // the memory map since its header was validated. It is not written
+        // while borrowed, since `swap` needs `&mut self` and the only token
+        // for the range.
+        let bytes = unsafe { core::slice::from_raw_parts(self.mmap.ptr.add(offset), Self::row_bytes()) };
+        bytemuck::from_bytes(bytes)
+    }
+}
+
+impl<T: Number + Pod, U: Number, const DIM: usize> Dataset<[T; DIM], U> for MmapDataset<T, U, DIM> {
+    fn clone_with_new_metric(&self, metric: fn(&[T; DIM], &[T; DIM]) -> U, is_expensive: bool, name: String) -> Self {
+        Self {
+            name,
+            path: self.path.clone(),
+            mmap: Arc::clone(&self.mmap),
+            range_token: Arc::clone(&self.range_token),
+            start: self.start,
+            cardinality: self.cardinality,
+            metric: FnMetric::new(metric).with_is_expensive(is_expensive).into(),
+            permuted_indices: self.permuted_indices.clone(),
+        }
+    }
+
+    fn type_name() -> String {
+        format!("MmapDataset<{}, {}, {DIM}>", T::type_name(), U::type_name())
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn cardinality(&self) -> usize {
+        self.cardinality
+    }
+
+    fn get(&self, index: usize) -> Cow<'_, [T; DIM]> {
+        Cow::Borrowed(&self[index])
+    }
+
+    fn metric(&self) -> &SharedMetric<[T; DIM], U> {
+        &self.metric
+    }
+
+    fn set_permuted_indices(&mut self, indices: Option<&[usize]>) {
+        self.permuted_indices = indices.map(<[usize]>::to_vec);
+    }
+
+    fn swap(&mut self, left: usize, right: usize) -> Result<(), String> {
+        if left.max(right) >= self.cardinality {
+            return Err(format!(
+                "Invalid indices. Expected indices less than {}, got {left} and {right}",
+                self.cardinality
+            ));
+        }
+        if left == right {
+            return Ok(());
+        }
+        if Arc::get_mut(&mut self.range_token).is_none() {
+            return Err(format!("The instances of {} are shared with a clone.", self.name));
+        }
+
+        let (left, right) = (self.row_offset(left), self.row_offset(right));
+        // SAFETY: Both instances are in the range of this dataset, which no
+        // other dataset reads or writes as this one holds the only token for
+        // it, and `&mut self` ensures that neither is borrowed. They do not
+        // overlap since `left != right`.
+        unsafe {
+            core::ptr::swap_nonoverlapping(self.mmap.ptr.add(left), self.mmap.ptr.add(right), Self::row_bytes());
+        }
+
+        Ok(())
+    }
+
+    fn permuted_indices(&self) -> Option<&[usize]> {
+        self.permuted_indices.as_deref()
+    }
+
+    fn make_shards(mut self, max_cardinality: usize) -> Vec<Self> {
+        let mut shards = Vec::new();
+
+        // The shards of a dataset which shares its range with a clone share
+        // the token of the range.
+        let is_exclusive = Arc::get_mut(&mut self.range_token).is_some();
+
+        while self.cardinality > max_cardinality {
+            let at = self.cardinality - max_cardinality;
+            let range_token = if is_exclusive {
+                Arc::new(())
+            } else {
+                Arc::clone(&self.range_token)
+            };
+            shards.push(Self {
+                name: format!("{}-shard-{}", self.name, shards.len()),
+                path: self.path.clone(),
+                mmap: Arc::clone(&self.mmap),
+                range_token,
+                start: self.start + at,
+                cardinality: max_cardinality,
+                metric: self.metric.clone(),
+                permuted_indices: None,
+            });
+
+            self.cardinality = at;
+            if let Some(permutation) = self.permuted_indices.as_mut() {
+                permutation.truncate(at);
+            }
+        }
+
+        self.name = format!("{}-shard-{}", self.name, shards.len());
+        shards.push(self);
+
+        shards
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        self.flush()?;
+
+        let handle = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+        let contents = (
+            Self::type_name(),
+            &self.name,
+            &self.path,
+            self.start,
+            self.cardinality,
+            &self.permuted_indices,
+        );
+        bincode::serialize_into(handle, &contents).map_err(|e| e.to_string())
+    }
+
+    fn load(path: &Path, metric: fn(&[T; DIM], &[T; DIM]) -> U, is_expensive: bool) -> Result<Self, String> {
+        let handle = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+        let (type_name, name, backing_path, start, cardinality, permuted_indices): (
+            String,
+            String,
+            PathBuf,
+            usize,
+            usize,
+            Option<Vec<usize>>,
+        ) = bincode::deserialize_from(handle).map_err(|e| e.to_string())?;
+
+        let actual_type_name = Self::type_name();
+        if type_name != actual_type_name {
+            return Err(format!(
+                "Invalid type. File has data of type {type_name} but dataset was constructed with type {actual_type_name}"
+            ));
+        }
+
+        let mmap = Self::map(&backing_path)?;
+        let num_instances = Self::read_header(&mmap)?;
+        if start + cardinality > num_instances {
+            return Err(format!(
+                "Backing file has {num_instances} instances, but the dataset needs {}",
+                start + cardinality
+            ));
+        }
+
+        Ok(Self {
+            name,
+            path: backing_path,
+            mmap: SharedMmap::new(mmap),
+            range_token: Arc::new(()),
+            start,
+            cardinality,
+            metric: FnMetric::new(metric).with_is_expensive(is_expensive).into(),
+            permuted_indices,
+        })
+    }
+}
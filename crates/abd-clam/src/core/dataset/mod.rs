@@ -1,22 +1,58 @@
-//! Provides the `Dataset` trait and an implementation for a vector of data.
+//! Provides the `Dataset` trait and implementations for a vector of data and
+//! for a memory-mapped file.
 
-use core::{fmt::Debug, ops::Index};
+use core::fmt::Debug;
 
-use std::path::Path;
+use std::{borrow::Cow, path::Path};
 
 use distances::Number;
 use rand::prelude::*;
-use rayon::prelude::*;
+use rand_chacha::ChaCha8Rng;
 
+use crate::par::prelude::*;
+use crate::{Metric, SharedMetric};
+
+#[cfg(feature = "hdf5")]
+mod ann_benchmarks;
+#[cfg(feature = "arrow")]
+mod arrow;
+mod blocked;
+mod indirect;
 mod instance;
+mod leaf_store;
+mod mmap;
+mod permutation;
+mod quantized;
+mod sequence;
+mod slice;
+mod validate;
 mod vec2d;
 
+#[cfg(feature = "hdf5")]
+pub use ann_benchmarks::AnnBenchmark;
+#[cfg(feature = "arrow")]
+pub use arrow::ArrowFloat;
+pub use blocked::{BlockKernel, BlockedDataset, BLOCK_LANES};
+pub use indirect::IndirectDataset;
 pub use instance::Instance;
+pub use leaf_store::LeafStore;
+pub use leaf_store::LeafStoreWriter;
+pub use mmap::MmapDataset;
+pub use permutation::Permutation;
+pub use quantized::{euclidean_i8, QuantizedDataset, ScalarQuantizer};
+pub use sequence::SequenceDataset;
+pub use slice::SliceDataset;
+pub use validate::{NonFinite, NonFinitePolicy};
 #[allow(clippy::module_name_repetitions)]
 pub use vec2d::VecDataset;
 
 /// A common interface for datasets used in CLAM.
-pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Output = I> {
+///
+/// All distances computed by the default methods, and hence by the search
+/// algorithms in `cakes`, read instances through `Dataset::get`. A dataset
+/// whose instances are computed on demand, e.g. decoded from a compressed
+/// store, never needs to hold on to an instance.
+pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync {
     /// Changes the metric used to calculate distances between instances.
     ///
     /// This method could potentially be very expensive with memory usage, as it
@@ -36,11 +72,24 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
     /// Returns the number of instances in the dataset.
     fn cardinality(&self) -> usize;
 
+    /// Returns the instance at the given index.
+    ///
+    /// Datasets whose instances are held in memory borrow the instance.
+    /// Others return an owned instance, computed on demand, so that the
+    /// instance may be dropped as soon as the caller is done with it.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - An index in the dataset.
+    fn get(&self, index: usize) -> Cow<'_, I>;
+
     /// Whether or not the metric is expensive to calculate.
     ///
     /// If the metric is expensive to calculate, CLAM will enable more parallelism
-    /// when calculating distances.
-    fn is_metric_expensive(&self) -> bool;
+    /// when calculating distances. See `Metric::is_expensive`.
+    fn is_metric_expensive(&self) -> bool {
+        self.metric().is_expensive()
+    }
 
     /// Returns the metric used to calculate distances between instances.
     ///
@@ -52,7 +101,57 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
     ///
     /// If the metric also obeys the triangle inequality, `d(x, z) <= d(x, y) + d(y, z)`,
     /// then CLAM can make certain guarantees about the exactness of search results.
-    fn metric(&self) -> fn(&I, &I) -> U;
+    ///
+    /// The metric may be cloned cheaply, e.g. to build another dataset with
+    /// the same distance function.
+    fn metric(&self) -> &SharedMetric<I, U>;
+
+    /// Whether the metric obeys the triangle inequality.
+    ///
+    /// The clustered search algorithms in `cakes` fall back to `Linear` search
+    /// when this is `false`. See `Metric::is_metric`.
+    fn is_metric(&self) -> bool {
+        self.metric().is_metric()
+    }
+
+    /// Whether the metric is symmetric. See `Metric::is_symmetric`.
+    fn is_metric_symmetric(&self) -> bool {
+        self.metric().is_symmetric()
+    }
+
+    /// A cheap lower bound on the metric, if the dataset has one. See
+    /// `FnMetric::with_lower_bound`.
+    fn lower_bound(&self) -> Option<fn(&I, &I) -> U> {
+        self.metric().lower_bound()
+    }
+
+    /// A cheap upper bound on the metric, if the dataset has one. See
+    /// `FnMetric::with_upper_bound`.
+    fn upper_bound(&self) -> Option<fn(&I, &I) -> U> {
+        self.metric().upper_bound()
+    }
+
+    /// The weights of the instances, in the same order as the instances, or
+    /// `None` if the dataset is not weighted.
+    ///
+    /// A weight is the importance of an instance, e.g. the number of
+    /// duplicates it stands for in a downsampled dataset. The weights pull the
+    /// center of each `Cluster` towards its heavier instances, and are
+    /// returned with the hits of, e.g., `Cakes::knn_search_weighted`. Without
+    /// weights, every instance has a weight of one.
+    fn weights(&self) -> Option<&[f64]> {
+        None
+    }
+
+    /// Returns the weight of the instance at the given index, which is one if
+    /// the dataset is not weighted.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - An index in the dataset.
+    fn weight(&self, index: usize) -> f64 {
+        self.weights().map_or(1.0, |weights| weights[index])
+    }
 
     /// Sets the permutation of indices that was used to reorder the dataset.
     ///
@@ -149,6 +248,18 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
         self.permuted_indices().map_or(index, |indices| indices[index])
     }
 
+    /// Returns the reordering of the dataset, which maps indices to original
+    /// indices and back. See `Permutation`.
+    fn permutation(&self) -> Permutation {
+        self.permuted_indices().map_or_else(
+            || Permutation::identity(self.cardinality()),
+            |indices| {
+                Permutation::new(indices.to_vec())
+                    .unwrap_or_else(|e| unreachable!("The permuted indices are distinct. {e}"))
+            },
+        )
+    }
+
     /// Calculates the distance between two indexed instances in the dataset.
     ///
     /// # Arguments
@@ -160,7 +271,7 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
     ///
     /// The distance between the instances at `left` and `right`.
     fn one_to_one(&self, left: usize, right: usize) -> U {
-        self.metric()(&self[left], &self[right])
+        self.metric().distance(&self.get(left), &self.get(right))
     }
 
     /// Returns whether or not two indexed instances in the dataset are equal.
@@ -191,7 +302,22 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
     ///
     /// A vector of distances between the instance at `left` and all instances at `right`
     fn one_to_many(&self, left: usize, right: &[usize]) -> Vec<U> {
-        self.query_to_many(&self[left], right)
+        self.query_to_many(&self.get(left), right)
+    }
+
+    /// Returns a vector of distances, computed in parallel even if the metric
+    /// is cheap.
+    ///
+    /// # Arguments
+    ///
+    /// * `left` - An index in the dataset
+    /// * `right` - A slice of indices in the dataset
+    ///
+    /// # Returns
+    ///
+    /// A vector of distances between the instance at `left` and all instances at `right`
+    fn par_one_to_many(&self, left: usize, right: &[usize]) -> Vec<U> {
+        right.par_iter().map(|&r| self.one_to_one(left, r)).collect()
     }
 
     /// Returns a vector of vectors of distances.
@@ -249,6 +375,15 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
                     matrix[i][j] = d;
                     matrix[j][i] = d;
                 });
+
+            if !self.is_metric_symmetric() {
+                let index_pairs = indices.iter().skip(i + 1).map(|&q| (q, p)).collect::<Vec<_>>();
+                let distances = self.pairs(&index_pairs);
+                distances
+                    .into_iter()
+                    .enumerate()
+                    .for_each(|(j, d)| matrix[j + i + 1][i] = d);
+            }
         }
 
         // compute the diagonal for non-metrics
@@ -259,6 +394,31 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
         matrix
     }
 
+    /// Returns the distances between all pairs of distinct indexed instances,
+    /// in the condensed form of the upper triangle of the pairwise matrix.
+    ///
+    /// This holds half as many distances as `pairwise` and computes each of
+    /// them once, so it is preferred for symmetric distance functions.
+    ///
+    /// # Arguments
+    ///
+    /// * `indices` - A slice of indices in the dataset.
+    ///
+    /// # Returns
+    ///
+    /// A vector of the distances from the instance at `indices[i]` to that at
+    /// `indices[j]` for all `i < j`, row by row. For `n` indices, it has
+    /// `n * (n - 1) / 2` elements, and the distance for `i < j` is at position
+    /// `n * i - i * (i + 1) / 2 + (j - i - 1)`.
+    fn pairwise_submatrix(&self, indices: &[usize]) -> Vec<U> {
+        let index_pairs = indices
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &p)| indices[(i + 1)..].iter().map(move |&q| (p, q)))
+            .collect::<Vec<_>>();
+        self.pairs(&index_pairs)
+    }
+
     /// Calculates the distance between a query and an indexed instance in the dataset.
     ///
     /// # Arguments
@@ -270,7 +430,7 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
     ///
     /// The distance between the query and the instance at `index`
     fn query_to_one(&self, query: &I, index: usize) -> U {
-        self.metric()(query, &self[index])
+        self.metric().distance(query, &self.get(index))
     }
 
     /// Returns a vector of distances between a query and all indexed instances.
@@ -300,7 +460,9 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
     ///
     /// * `n` - The number of unique indices to choose.
     /// * `indices` - A slice of indices in the dataset from which to choose.
-    /// * `seed` - An optional seed for the random number generator.
+    /// * `seed` - An optional seed for the random number generator. The
+    ///   generator is portable, so a seed gives the same choice on every
+    ///   platform.
     ///
     /// # Returns
     ///
@@ -312,7 +474,7 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
         let indices = {
             let mut indices = indices.to_vec();
             if let Some(seed) = seed {
-                indices.shuffle(&mut rand::rngs::StdRng::seed_from_u64(seed));
+                indices.shuffle(&mut ChaCha8Rng::seed_from_u64(seed));
             } else {
                 indices.shuffle(&mut rand::thread_rng());
             }
@@ -343,6 +505,9 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
     /// a value from the set of indices that is the index of the median in the
     /// dataset.
     ///
+    /// If the dataset is weighted, this is the instance which minimizes the
+    /// sum of its distances to the others, each multiplied by their weight.
+    ///
     /// Note: This default implementation does not scale well to arbitrarily large inputs.
     ///
     /// # Arguments
@@ -358,6 +523,15 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
     /// * The index of the median in the dataset, if `indices` is not empty.
     /// * `None`, if `indices` is empty.
     fn median(&self, indices: &[usize]) -> Option<usize> {
+        if let Some(weights) = self.weights() {
+            let distances = self
+                .pairwise(indices)
+                .iter()
+                .map(|v| weighted_sum(v, indices, weights))
+                .collect::<Vec<_>>();
+            return crate::utils::arg_min(&distances).map(|(i, _)| indices[i]);
+        }
+
         // TODO: Refactor this to scale for arbitrarily large n
         let distances = self
             .pairwise(indices)
@@ -370,6 +544,37 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
         crate::utils::arg_min(&distances).map(|(i, _)| indices[i])
     }
 
+    /// Finds the geometric median of a set of indexed instances, computing the
+    /// distances in parallel even if the metric is cheap.
+    ///
+    /// This gives the same result as `median` for symmetric distance
+    /// functions.
+    ///
+    /// # Arguments
+    ///
+    /// `indices` - A subset of indices from the dataset
+    ///
+    /// # Returns
+    ///
+    /// * The index of the median in the dataset, if `indices` is not empty.
+    /// * `None`, if `indices` is empty.
+    fn par_median(&self, indices: &[usize]) -> Option<usize> {
+        if let Some(weights) = self.weights() {
+            let distances = indices
+                .par_iter()
+                .map(|&i| weighted_sum(&self.one_to_many(i, indices), indices, weights))
+                .collect::<Vec<_>>();
+            return crate::utils::arg_min(&distances).map(|(i, _)| indices[i]);
+        }
+
+        let distances = indices
+            .par_iter()
+            .map(|&i| self.one_to_many(i, indices).into_iter().sum::<U>())
+            .collect::<Vec<_>>();
+
+        crate::utils::arg_min(&distances).map(|(i, _)| indices[i])
+    }
+
     /// Makes a vector of sharded datasets from the given dataset.
     ///
     /// Each shard will be a random subset of the dataset, and will have a
@@ -449,3 +654,13 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
             .collect()
     }
 }
+
+/// The sum of the `distances` to the instances at `indices`, each multiplied by
+/// the weight of the instance.
+fn weighted_sum<U: Number>(distances: &[U], indices: &[usize], weights: &[f64]) -> f64 {
+    distances
+        .iter()
+        .zip(indices)
+        .map(|(d, &i)| d.as_f64() * weights[i])
+        .sum()
+}
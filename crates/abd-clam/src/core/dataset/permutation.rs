@@ -0,0 +1,120 @@
+//! The mapping between the indices of a reordered dataset and the original
+//! indices of its instances.
+
+use serde::{Deserialize, Serialize};
+
+/// The reordering of a dataset, as returned by `Dataset::permutation`.
+///
+/// Partitioning a `Tree` reorders its dataset, depth-first, so that the
+/// instances of every `Cluster` have contiguous indices. Search results are
+/// indices into the reordered dataset. A `Permutation` maps each of them to
+/// the original index of its instance, i.e. its position in the order in
+/// which the instances were given, and back.
+///
+/// After instances are removed from a dataset, e.g. by `Tree::compact`, the
+/// original indices of the remaining instances are no longer contiguous, so
+/// some original indices have no index in the dataset.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Permutation {
+    /// The original index of the instance at each index.
+    to_original: Vec<usize>,
+    /// The index of the instance with each original index, if any.
+    from_original: Vec<Option<usize>>,
+}
+
+impl Permutation {
+    /// The permutation of a dataset of the given cardinality which has not
+    /// been reordered.
+    #[must_use]
+    pub fn identity(cardinality: usize) -> Self {
+        Self {
+            to_original: (0..cardinality).collect(),
+            from_original: (0..cardinality).map(Some).collect(),
+        }
+    }
+
+    /// Creates a permutation from the original index of the instance at each
+    /// index, as returned by `Dataset::permuted_indices`.
+    ///
+    /// # Errors
+    ///
+    /// * If any original index is given more than once.
+    pub fn new(to_original: Vec<usize>) -> Result<Self, String> {
+        let size = to_original.iter().max().map_or(0, |&o| o + 1);
+        let mut from_original = vec![None; size];
+        for (i, &o) in to_original.iter().enumerate() {
+            if from_original[o].replace(i).is_some() {
+                return Err(format!(
+                    "Invalid permutation. The original index {o} is given more than once."
+                ));
+            }
+        }
+        Ok(Self {
+            to_original,
+            from_original,
+        })
+    }
+
+    /// Returns the original index of the instance at the given index.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - An index in the dataset, e.g. as returned by a search.
+    #[must_use]
+    pub fn to_original(&self, index: usize) -> usize {
+        self.to_original[index]
+    }
+
+    /// Returns the index in the dataset of the instance with the given
+    /// original index, or `None` if there is no such instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `original` - The original index of an instance.
+    #[must_use]
+    pub fn from_original(&self, original: usize) -> Option<usize> {
+        self.from_original.get(original).copied().flatten()
+    }
+
+    /// Returns the number of instances in the dataset.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.to_original.len()
+    }
+
+    /// Returns whether the dataset is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.to_original.is_empty()
+    }
+
+    /// Returns whether every instance is at its original index.
+    #[must_use]
+    pub fn is_identity(&self) -> bool {
+        self.to_original.iter().enumerate().all(|(i, &o)| i == o)
+    }
+
+    /// Returns the original index of the instance at each index.
+    #[must_use]
+    pub fn as_slice(&self) -> &[usize] {
+        &self.to_original
+    }
+
+    /// Reorders items given by original index, e.g. labels, to match the
+    /// order of the dataset.
+    ///
+    /// # Errors
+    ///
+    /// * If there is no item for the original index of some instance.
+    pub fn apply<T: Clone>(&self, items: &[T]) -> Result<Vec<T>, String> {
+        self.to_original
+            .iter()
+            .map(|&o| {
+                items
+                    .get(o)
+                    .cloned()
+                    .ok_or_else(|| format!("There is no item for the original index {o}."))
+            })
+            .collect()
+    }
+}
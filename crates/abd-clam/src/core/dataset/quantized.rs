@@ -0,0 +1,400 @@
+//! A dataset of vectors stored as 8-bit scalar-quantized codes.
+
+use core::ops::Index;
+
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use crate::par::prelude::*;
+use crate::{Dataset, Instance, SharedMetric, VecDataset};
+
+/// Quantizes each dimension of a vector to an `i8` with its own scale and
+/// offset.
+///
+/// The offset of a dimension is the midpoint of its range in the training
+/// data, and the scale maps that range onto `-127..=127`. Values outside the
+/// range are clamped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScalarQuantizer {
+    /// The value which maps to a code of zero in each dimension.
+    offsets: Vec<f32>,
+    /// The width of a quantization step in each dimension.
+    scales: Vec<f32>,
+}
+
+impl ScalarQuantizer {
+    /// Fits a quantizer to the range of each dimension of the given vectors.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The vectors to fit the quantizer to.
+    ///
+    /// # Errors
+    ///
+    /// * If `data` is empty.
+    /// * If the vectors do not all have the same dimensionality.
+    pub fn fit(data: &[Vec<f32>]) -> Result<Self, String> {
+        let dim = data.first().ok_or("Cannot fit a quantizer to an empty dataset")?.len();
+        if let Some((i, x)) = data.iter().enumerate().find(|(_, x)| x.len() != dim) {
+            return Err(format!("Instance {i} has dimensionality {}, expected {dim}", x.len()));
+        }
+
+        let (offsets, scales) = (0..dim)
+            .map(|j| {
+                let (min, max) = data
+                    .iter()
+                    .map(|x| x[j])
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| {
+                        (min.min(v), max.max(v))
+                    });
+                let scale = (max - min) / 254.;
+                // A constant dimension quantizes to zero with any scale.
+                let scale = if scale > 0. { scale } else { 1. };
+                ((min + max) / 2., scale)
+            })
+            .unzip();
+
+        Ok(Self { offsets, scales })
+    }
+
+    /// Returns the dimensionality of the vectors which the quantizer accepts.
+    #[must_use]
+    pub fn dim(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Quantizes a vector.
+    ///
+    /// # Arguments
+    ///
+    /// * `x`: A vector with the dimensionality of the quantizer.
+    #[must_use]
+    pub fn quantize(&self, x: &[f32]) -> Vec<i8> {
+        x.iter()
+            .zip(self.offsets.iter().zip(self.scales.iter()))
+            .map(|(&v, (&o, &s))| {
+                #[allow(clippy::cast_possible_truncation)]
+                let code = ((v - o) / s).round().clamp(-127., 127.) as i8;
+                code
+            })
+            .collect()
+    }
+
+    /// Reconstructs an approximation of a vector from its codes.
+    ///
+    /// # Arguments
+    ///
+    /// * `codes`: The codes of a vector, as produced by `quantize`.
+    #[must_use]
+    pub fn dequantize(&self, codes: &[i8]) -> Vec<f32> {
+        codes
+            .iter()
+            .zip(self.offsets.iter().zip(self.scales.iter()))
+            .map(|(&c, (&o, &s))| f32::from(c).mul_add(s, o))
+            .collect()
+    }
+}
+
+impl Instance for ScalarQuantizer {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.offsets.to_bytes();
+        bytes.extend(self.scales.to_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() % 8 != 0 {
+            return Err(format!("Expected a multiple of 8 bytes, got {}", bytes.len()));
+        }
+        let (offsets, scales) = bytes.split_at(bytes.len() / 2);
+        Ok(Self {
+            offsets: Vec::from_bytes(offsets)?,
+            scales: Vec::from_bytes(scales)?,
+        })
+    }
+
+    fn type_name() -> String {
+        "ScalarQuantizer".to_string()
+    }
+}
+
+/// The euclidean distance between two vectors of quantized codes, measured in
+/// quantization steps.
+///
+/// The sum of squares is accumulated in integers, so this is much cheaper than
+/// the euclidean distance between the full-precision vectors. Since each
+/// dimension has its own scale, it approximates the euclidean distance after
+/// normalizing the range of each dimension. This preserves neighborhoods best
+/// when the dimensions have comparable ranges, as is typical of embeddings.
+#[allow(clippy::ptr_arg)]
+#[must_use]
+pub fn euclidean_i8(x: &Vec<i8>, y: &Vec<i8>) -> f32 {
+    // The square of a difference of two codes fits in a `u32`, but the sum
+    // would overflow one for vectors of more than 66,000 dimensions.
+    let d = x
+        .iter()
+        .zip(y.iter())
+        .map(|(&a, &b)| u64::from(u32::from(a.abs_diff(b)).pow(2)))
+        .sum::<u64>();
+    #[allow(clippy::cast_precision_loss)]
+    let d = d as f32;
+    d.sqrt()
+}
+
+/// A `Dataset` of vectors stored as 8-bit codes from a `ScalarQuantizer`.
+///
+/// The codes take a quarter of the memory of `f32` vectors. Distances between
+/// instances are `euclidean_i8` distances between their codes, so trees are
+/// built and searched entirely in the quantized space. Queries must be
+/// quantized with `quantize` before searching.
+///
+/// The full-precision vectors may optionally be kept, in which case the
+/// candidates from a search of the codes can be rescored with their exact
+/// euclidean distances to the query. See `Cakes::knn_search_rescored`.
+///
+/// # Type Parameters
+///
+/// - `M`: The type of the metadata associated with each instance.
+#[derive(Debug, Clone)]
+pub struct QuantizedDataset<M: Instance = usize> {
+    /// The quantized codes of the instances.
+    codes: VecDataset<Vec<i8>, f32, M>,
+    /// The quantizer used to produce the codes.
+    quantizer: ScalarQuantizer,
+    /// The full-precision instances, in the same order as the codes.
+    full: Option<Vec<Vec<f32>>>,
+}
+
+impl QuantizedDataset<usize> {
+    /// Quantizes a dataset of vectors.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the dataset.
+    /// * `data`: The full-precision vectors.
+    /// * `keep_full`: Whether to keep the full-precision vectors for
+    ///   rescoring.
+    ///
+    /// # Errors
+    ///
+    /// * See `ScalarQuantizer::fit`.
+    pub fn new(name: String, data: Vec<Vec<f32>>, keep_full: bool) -> Result<Self, String> {
+        let quantizer = ScalarQuantizer::fit(&data)?;
+        Ok(Self::with_quantizer(name, data, quantizer, keep_full))
+    }
+
+    /// Quantizes a dataset of vectors with an existing quantizer.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the dataset.
+    /// * `data`: The full-precision vectors.
+    /// * `quantizer`: The quantizer to use.
+    /// * `keep_full`: Whether to keep the full-precision vectors for
+    ///   rescoring.
+    #[must_use]
+    pub fn with_quantizer(name: String, data: Vec<Vec<f32>>, quantizer: ScalarQuantizer, keep_full: bool) -> Self {
+        let codes = data.par_iter().map(|x| quantizer.quantize(x)).collect();
+        let codes = VecDataset::new(name, codes, euclidean_i8, false);
+        let full = if keep_full { Some(data) } else { None };
+        Self { codes, quantizer, full }
+    }
+}
+
+impl<M: Instance> QuantizedDataset<M> {
+    /// Assigns metadata to the dataset. See `VecDataset::assign_metadata`.
+    ///
+    /// # Errors
+    ///
+    /// * If the metadata is not the same length as the dataset.
+    pub fn assign_metadata<Mn: Instance>(self, metadata: Vec<Mn>) -> Result<QuantizedDataset<Mn>, String> {
+        Ok(QuantizedDataset {
+            codes: self.codes.assign_metadata(metadata)?,
+            quantizer: self.quantizer,
+            full: self.full,
+        })
+    }
+
+    /// Returns the quantizer used to produce the codes.
+    #[must_use]
+    pub const fn quantizer(&self) -> &ScalarQuantizer {
+        &self.quantizer
+    }
+
+    /// Quantizes a query so that it may be used to search the dataset.
+    #[must_use]
+    pub fn quantize(&self, query: &[f32]) -> Vec<i8> {
+        self.quantizer.quantize(query)
+    }
+
+    /// Returns the quantized codes of the instances.
+    #[must_use]
+    pub const fn codes(&self) -> &VecDataset<Vec<i8>, f32, M> {
+        &self.codes
+    }
+
+    /// Returns the full-precision instances, if they were kept.
+    #[must_use]
+    pub fn full(&self) -> Option<&[Vec<f32>]> {
+        self.full.as_deref()
+    }
+
+    /// Returns the exact euclidean distance between a full-precision query and
+    /// an indexed instance, or `None` if the full-precision instances were not
+    /// kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `query`: A full-precision query.
+    /// * `index`: An index in the dataset.
+    #[must_use]
+    pub fn full_distance(&self, query: &[f32], index: usize) -> Option<f32> {
+        self.full
+            .as_ref()
+            .map(|full| distances::simd::euclidean_f32(query, &full[index]))
+    }
+
+    /// Returns the path of the file in which the quantizer and full-precision
+    /// instances are saved, alongside the codes at `path`.
+    fn sidecar_path(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".sq8");
+        PathBuf::from(name)
+    }
+}
+
+impl<M: Instance> Index<usize> for QuantizedDataset<M> {
+    type Output = Vec<i8>;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        self.codes.index(index)
+    }
+}
+
+impl<M: Instance> Dataset<Vec<i8>, f32> for QuantizedDataset<M> {
+    fn clone_with_new_metric(&self, metric: fn(&Vec<i8>, &Vec<i8>) -> f32, is_expensive: bool, name: String) -> Self {
+        Self {
+            codes: self.codes.clone_with_new_metric(metric, is_expensive, name),
+            quantizer: self.quantizer.clone(),
+            full: self.full.clone(),
+        }
+    }
+
+    fn type_name() -> String {
+        format!("QuantizedDataset<{}>", M::type_name())
+    }
+
+    fn name(&self) -> &str {
+        self.codes.name()
+    }
+
+    fn cardinality(&self) -> usize {
+        self.codes.cardinality()
+    }
+
+    fn get(&self, index: usize) -> Cow<'_, Vec<i8>> {
+        Cow::Borrowed(&self[index])
+    }
+
+    fn is_metric_expensive(&self) -> bool {
+        self.codes.is_metric_expensive()
+    }
+
+    fn metric(&self) -> &SharedMetric<Vec<i8>, f32> {
+        self.codes.metric()
+    }
+
+    fn is_metric(&self) -> bool {
+        self.codes.is_metric()
+    }
+
+    fn is_metric_symmetric(&self) -> bool {
+        self.codes.is_metric_symmetric()
+    }
+
+    fn set_permuted_indices(&mut self, indices: Option<&[usize]>) {
+        self.codes.set_permuted_indices(indices);
+    }
+
+    fn swap(&mut self, left: usize, right: usize) -> Result<(), String> {
+        self.codes.swap(left, right)?;
+        if let Some(full) = self.full.as_mut() {
+            full.swap(left, right);
+        }
+        Ok(())
+    }
+
+    fn permuted_indices(&self) -> Option<&[usize]> {
+        self.codes.permuted_indices()
+    }
+
+    fn permute_instances(&mut self, permutation: &[usize]) -> Result<(), String> {
+        self.codes.permute_instances(permutation)?;
+        if let Some(full) = self.full.as_mut() {
+            *full = permutation.par_iter().map(|&index| full[index].clone()).collect();
+        }
+        Ok(())
+    }
+
+    fn make_shards(self, max_cardinality: usize) -> Vec<Self> {
+        let Self {
+            codes,
+            quantizer,
+            mut full,
+        } = self;
+
+        // `VecDataset::make_shards` splits off shards from the end, so the
+        // full-precision instances are split in the same way.
+        codes
+            .make_shards(max_cardinality)
+            .into_iter()
+            .map(|codes| {
+                let full = full
+                    .as_mut()
+                    .map(|full| full.split_off(full.len() - codes.cardinality()));
+                Self {
+                    codes,
+                    quantizer: quantizer.clone(),
+                    full,
+                }
+            })
+            .collect()
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        self.codes.save(path)?;
+
+        let mut handle = BufWriter::new(File::create(Self::sidecar_path(path)).map_err(|e| e.to_string())?);
+        self.quantizer.save(&mut handle)?;
+        let num_full = self.full.as_ref().map_or(0, Vec::len);
+        num_full.save(&mut handle)?;
+        for x in self.full.iter().flatten() {
+            x.save(&mut handle)?;
+        }
+
+        Ok(())
+    }
+
+    fn load(path: &Path, metric: fn(&Vec<i8>, &Vec<i8>) -> f32, is_expensive: bool) -> Result<Self, String> {
+        let codes = VecDataset::load(path, metric, is_expensive)?;
+
+        let mut handle = BufReader::new(File::open(Self::sidecar_path(path)).map_err(|e| e.to_string())?);
+        let quantizer = ScalarQuantizer::load(&mut handle)?;
+        let num_full = usize::load(&mut handle)?;
+        let full = if num_full == 0 {
+            None
+        } else {
+            Some(
+                (0..num_full)
+                    .map(|_| Vec::load(&mut handle))
+                    .collect::<Result<_, _>>()?,
+            )
+        };
+
+        Ok(Self { codes, quantizer, full })
+    }
+}
@@ -0,0 +1,284 @@
+//! A dataset of byte strings stored contiguously in memory.
+
+use std::{borrow::Cow, path::Path};
+
+use distances::Number;
+use serde::Deserialize;
+
+use crate::{
+    core::tree::{load_bincode, save_bincode},
+    Dataset, FnMetric, SharedMetric,
+};
+
+/// The parts of a `SequenceDataset` which are saved to disk with
+/// `Dataset::save`.
+#[derive(Deserialize)]
+struct Header {
+    /// The type name of the dataset, for basic protection against reading
+    /// bad data.
+    type_name: String,
+    /// The name of the dataset.
+    name: String,
+    /// The concatenated sequences.
+    bytes: Vec<u8>,
+    /// The offset of each sequence in `bytes`, followed by the end of the last
+    /// sequence.
+    offsets: Vec<usize>,
+    /// The reordering of the dataset after building the tree.
+    permuted_indices: Option<Vec<usize>>,
+}
+
+/// A `Dataset` of byte strings, e.g. genomic sequences, concatenated into one
+/// buffer with the offset of each sequence kept alongside.
+///
+/// This avoids the allocation, and the 24 bytes of overhead, of a `Vec<u8>`
+/// for each of many short sequences. The distance functions in
+/// `metrics::sequences` are meant for use with this dataset.
+///
+/// The instances are `Vec<u8>`s. `Dataset::get`, and so all distances computed
+/// through the `Dataset` methods, copy a sequence out of the buffer for as long
+/// as it is needed. Use `sequence` to borrow a sequence without copying it.
+///
+/// # Type Parameters
+///
+/// - `U`: The type of the distance values between instances.
+#[derive(Debug, Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct SequenceDataset<U: Number> {
+    /// The name of the dataset.
+    name: String,
+    /// The concatenated sequences.
+    bytes: Vec<u8>,
+    /// The offset of each sequence in `bytes`, followed by the end of the last
+    /// sequence.
+    offsets: Vec<usize>,
+    /// The metric of the dataset.
+    metric: SharedMetric<Vec<u8>, U>,
+    /// The reordering of the dataset after building the tree.
+    permuted_indices: Option<Vec<usize>>,
+}
+
+impl<U: Number> SequenceDataset<U> {
+    /// Creates a new dataset by copying the given sequences into one buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the dataset.
+    /// * `sequences`: The sequences.
+    /// * `metric`: The metric for computing distances between sequences.
+    /// * `is_expensive`: Whether the metric is expensive to compute.
+    pub fn new<S: AsRef<[u8]>>(
+        name: String,
+        sequences: &[S],
+        metric: fn(&Vec<u8>, &Vec<u8>) -> U,
+        is_expensive: bool,
+    ) -> Self {
+        Self::from_metric(name, sequences, FnMetric::new(metric).with_is_expensive(is_expensive))
+    }
+
+    /// Creates a new dataset by copying the given sequences into one buffer,
+    /// with a metric whose properties are explicitly declared.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the dataset.
+    /// * `sequences`: The sequences.
+    /// * `metric`: The metric and its properties.
+    #[must_use]
+    pub fn from_metric<S: AsRef<[u8]>>(
+        name: String,
+        sequences: &[S],
+        metric: impl Into<SharedMetric<Vec<u8>, U>>,
+    ) -> Self {
+        let mut bytes = Vec::with_capacity(sequences.iter().map(|s| s.as_ref().len()).sum());
+        let mut offsets = Vec::with_capacity(sequences.len() + 1);
+        offsets.push(0);
+        for s in sequences {
+            bytes.extend_from_slice(s.as_ref());
+            offsets.push(bytes.len());
+        }
+        Self::from_parts(name, bytes, offsets, metric.into())
+    }
+
+    /// Creates a new dataset from already-concatenated sequences.
+    const fn from_parts(name: String, bytes: Vec<u8>, offsets: Vec<usize>, metric: SharedMetric<Vec<u8>, U>) -> Self {
+        Self {
+            name,
+            bytes,
+            offsets,
+            metric,
+            permuted_indices: None,
+        }
+    }
+
+    /// Returns the sequence at the given index, borrowed from the buffer.
+    ///
+    /// # Panics
+    ///
+    /// * If `index` is out of bounds.
+    #[must_use]
+    pub fn sequence(&self, index: usize) -> &[u8] {
+        &self.bytes[self.offsets[index]..self.offsets[index + 1]]
+    }
+
+    /// Returns the total number of bytes in the sequences.
+    #[must_use]
+    pub fn num_bytes(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+impl<U: Number> Dataset<Vec<u8>, U> for SequenceDataset<U> {
+    fn clone_with_new_metric(&self, metric: fn(&Vec<u8>, &Vec<u8>) -> U, is_expensive: bool, name: String) -> Self {
+        Self {
+            name,
+            metric: FnMetric::new(metric).with_is_expensive(is_expensive).into(),
+            ..self.clone()
+        }
+    }
+
+    fn type_name() -> String {
+        format!("SequenceDataset<{}>", U::type_name())
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn cardinality(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    fn get(&self, index: usize) -> Cow<'_, Vec<u8>> {
+        Cow::Owned(self.sequence(index).to_vec())
+    }
+
+    fn metric(&self) -> &SharedMetric<Vec<u8>, U> {
+        &self.metric
+    }
+
+    fn set_permuted_indices(&mut self, indices: Option<&[usize]>) {
+        self.permuted_indices = indices.map(<[usize]>::to_vec);
+    }
+
+    /// Swaps two sequences, moving all bytes between them if they have
+    /// different lengths.
+    fn swap(&mut self, left: usize, right: usize) -> Result<(), String> {
+        let (l, r) = (left.min(right), left.max(right));
+        if r >= self.cardinality() {
+            return Err(format!(
+                "Invalid indices. Expected indices less than {}, got {left} and {right}",
+                self.cardinality()
+            ));
+        }
+        if l == r {
+            return Ok(());
+        }
+
+        let (l_start, l_end) = (self.offsets[l], self.offsets[l + 1]);
+        let (r_start, r_end) = (self.offsets[r], self.offsets[r + 1]);
+
+        let mut swapped = Vec::with_capacity(r_end - l_start);
+        swapped.extend_from_slice(&self.bytes[r_start..r_end]);
+        swapped.extend_from_slice(&self.bytes[l_end..r_start]);
+        swapped.extend_from_slice(&self.bytes[l_start..l_end]);
+        self.bytes[l_start..r_end].copy_from_slice(&swapped);
+
+        for offset in &mut self.offsets[(l + 1)..=r] {
+            *offset = *offset - (l_end - l_start) + (r_end - r_start);
+        }
+
+        Ok(())
+    }
+
+    fn permuted_indices(&self) -> Option<&[usize]> {
+        self.permuted_indices.as_deref()
+    }
+
+    fn permute_instances(&mut self, permutation: &[usize]) -> Result<(), String> {
+        if permutation.len() != self.cardinality() {
+            return Err(format!(
+                "Invalid permutation. Expected permutation of length {}, got permutation of length {}",
+                self.cardinality(),
+                permutation.len()
+            ));
+        }
+
+        let mut bytes = Vec::with_capacity(self.bytes.len());
+        let mut offsets = Vec::with_capacity(self.offsets.len());
+        offsets.push(0);
+        for &index in permutation {
+            bytes.extend_from_slice(self.sequence(index));
+            offsets.push(bytes.len());
+        }
+        self.bytes = bytes;
+        self.offsets = offsets;
+
+        self.set_permuted_indices(Some(permutation));
+
+        Ok(())
+    }
+
+    fn make_shards(mut self, max_cardinality: usize) -> Vec<Self> {
+        let mut shards = Vec::new();
+
+        while self.cardinality() > max_cardinality {
+            let name = format!("{}-shard-{}", self.name, shards.len());
+
+            // Split the sequences, and their offsets, off from the end.
+            let at = self.cardinality() - max_cardinality;
+            let start = self.offsets[at];
+            let bytes = self.bytes.split_off(start);
+            let offsets = self.offsets.split_off(at + 1);
+            let offsets = core::iter::once(0)
+                .chain(offsets.into_iter().map(|o| o - start))
+                .collect();
+            if let Some(permutation) = self.permuted_indices.as_mut() {
+                permutation.truncate(at);
+            }
+
+            shards.push(Self::from_parts(name, bytes, offsets, self.metric.clone()));
+        }
+
+        self.name = format!("{}-shard-{}", self.name, shards.len());
+        shards.push(self);
+
+        shards
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        // A tuple of the fields is serialized in the same way as a `Header`,
+        // without copying the sequences.
+        let header = (
+            Self::type_name(),
+            &self.name,
+            &self.bytes,
+            &self.offsets,
+            &self.permuted_indices,
+        );
+        Ok(save_bincode(path, &header)?)
+    }
+
+    fn load(path: &Path, metric: fn(&Vec<u8>, &Vec<u8>) -> U, is_expensive: bool) -> Result<Self, String> {
+        let header: Header = load_bincode(path)?;
+
+        let actual_type_name = Self::type_name();
+        if header.type_name != actual_type_name {
+            return Err(format!(
+                "Invalid type. File has data of type {} but dataset was constructed with type {actual_type_name}",
+                header.type_name
+            ));
+        }
+        if header.offsets.first() != Some(&0)
+            || header.offsets.last() != Some(&header.bytes.len())
+            || header.offsets.windows(2).any(|w| w[0] > w[1])
+        {
+            return Err("Invalid offsets. The file may be corrupted.".to_string());
+        }
+
+        let metric = FnMetric::new(metric).with_is_expensive(is_expensive).into();
+        let mut dataset = Self::from_parts(header.name, header.bytes, header.offsets, metric);
+        dataset.permuted_indices = header.permuted_indices;
+        Ok(dataset)
+    }
+}
@@ -0,0 +1,228 @@
+//! A dataset of instances borrowed from a slice.
+
+use core::ops::Index;
+
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use bytemuck::Pod;
+use distances::Number;
+
+use crate::{Dataset, FnMetric, SharedMetric};
+
+use super::Instance;
+
+/// A `Dataset` over a slice of instances owned by the caller.
+///
+/// The instances are neither copied nor moved. Reordering the dataset, e.g.
+/// when partitioning a `Tree`, only permutes an index layer which maps each
+/// index in the dataset to the position of its instance in the slice. This
+/// costs one `usize` per instance.
+///
+/// Since the instances are borrowed, a `SliceDataset` cannot be loaded from a
+/// file. `Dataset::save` only writes the index layer, and a saved `Tree` may
+/// be loaded over a new `SliceDataset` with `Tree::load_with_data`.
+///
+/// # Type Parameters
+///
+/// - `I`: The type of the instances in the `Dataset`.
+/// - `U`: The type of the distance values between instances.
+#[derive(Debug, Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct SliceDataset<'a, I: Instance, U: Number> {
+    /// The name of the dataset.
+    name: String,
+    /// The borrowed instances, in the order in which they were given.
+    rows: &'a [I],
+    /// The position, in `rows`, of the instance at each index.
+    order: Vec<usize>,
+    /// The metric of the dataset.
+    metric: SharedMetric<I, U>,
+    /// The reordering of the dataset after building the tree.
+    permuted_indices: Option<Vec<usize>>,
+}
+
+impl<'a, I: Instance, U: Number> SliceDataset<'a, I, U> {
+    /// Creates a new dataset over a slice of instances.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the dataset.
+    /// * `rows`: The instances.
+    /// * `metric`: The metric for computing distances between instances.
+    /// * `is_expensive`: Whether the metric is expensive to compute.
+    pub fn new(name: String, rows: &'a [I], metric: fn(&I, &I) -> U, is_expensive: bool) -> Self {
+        Self::from_metric(name, rows, FnMetric::new(metric).with_is_expensive(is_expensive))
+    }
+
+    /// Creates a new dataset over a slice of instances, with a metric whose
+    /// properties are explicitly declared.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the dataset.
+    /// * `rows`: The instances.
+    /// * `metric`: The metric and its properties.
+    #[must_use]
+    pub fn from_metric(name: String, rows: &'a [I], metric: impl Into<SharedMetric<I, U>>) -> Self {
+        Self {
+            name,
+            rows,
+            order: (0..rows.len()).collect(),
+            metric: metric.into(),
+            permuted_indices: None,
+        }
+    }
+
+    /// The borrowed instances, in the order in which they were given.
+    #[must_use]
+    pub const fn rows(&self) -> &'a [I] {
+        self.rows
+    }
+}
+
+impl<'a, T: Number + Pod, U: Number, const DIM: usize> SliceDataset<'a, [T; DIM], U> {
+    /// Creates a new dataset over a flat slice of elements, in row-major
+    /// order, without copying them.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the dataset.
+    /// * `values`: The elements of the instances, `DIM` at a time.
+    /// * `metric`: The metric and its properties.
+    ///
+    /// # Errors
+    ///
+    /// * If the number of elements is not a multiple of `DIM`.
+    pub fn from_flat(
+        name: String,
+        values: &'a [T],
+        metric: impl Into<SharedMetric<[T; DIM], U>>,
+    ) -> Result<Self, String> {
+        if DIM == 0 || values.len() % DIM != 0 {
+            return Err(format!(
+                "Invalid number of elements. Expected a multiple of {DIM}, got {}",
+                values.len()
+            ));
+        }
+        let rows = bytemuck::try_cast_slice(values).map_err(|e| e.to_string())?;
+        Ok(Self::from_metric(name, rows, metric))
+    }
+}
+
+impl<I: Instance, U: Number> Index<usize> for SliceDataset<'_, I, U> {
+    type Output = I;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.rows[self.order[index]]
+    }
+}
+
+impl<I: Instance, U: Number> Dataset<I, U> for SliceDataset<'_, I, U> {
+    fn clone_with_new_metric(&self, metric: fn(&I, &I) -> U, is_expensive: bool, name: String) -> Self {
+        Self {
+            name,
+            metric: FnMetric::new(metric).with_is_expensive(is_expensive).into(),
+            ..self.clone()
+        }
+    }
+
+    fn type_name() -> String {
+        format!("SliceDataset<{}, {}>", I::type_name(), U::type_name())
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn cardinality(&self) -> usize {
+        self.order.len()
+    }
+
+    fn get(&self, index: usize) -> Cow<'_, I> {
+        Cow::Borrowed(&self[index])
+    }
+
+    fn metric(&self) -> &SharedMetric<I, U> {
+        &self.metric
+    }
+
+    fn set_permuted_indices(&mut self, indices: Option<&[usize]>) {
+        self.permuted_indices = indices.map(<[usize]>::to_vec);
+    }
+
+    fn swap(&mut self, left: usize, right: usize) -> Result<(), String> {
+        if left.max(right) >= self.order.len() {
+            return Err(format!(
+                "Invalid indices. Expected indices less than {}, got {left} and {right}",
+                self.order.len()
+            ));
+        }
+        self.order.swap(left, right);
+        Ok(())
+    }
+
+    fn permuted_indices(&self) -> Option<&[usize]> {
+        self.permuted_indices.as_deref()
+    }
+
+    fn permute_instances(&mut self, permutation: &[usize]) -> Result<(), String> {
+        if permutation.len() != self.order.len() {
+            return Err(format!(
+                "Invalid permutation. Expected permutation of length {}, got permutation of length {}",
+                self.cardinality(),
+                permutation.len()
+            ));
+        }
+
+        self.order = permutation.iter().map(|&index| self.order[index]).collect();
+        self.set_permuted_indices(Some(permutation));
+
+        Ok(())
+    }
+
+    fn make_shards(mut self, max_cardinality: usize) -> Vec<Self> {
+        let mut shards = Vec::new();
+
+        while self.order.len() > max_cardinality {
+            let at = self.order.len() - max_cardinality;
+            shards.push(Self {
+                name: format!("{}-shard-{}", self.name, shards.len()),
+                rows: self.rows,
+                order: self.order.split_off(at),
+                metric: self.metric.clone(),
+                permuted_indices: None,
+            });
+
+            if let Some(permutation) = self.permuted_indices.as_mut() {
+                permutation.truncate(at);
+            }
+        }
+
+        self.name = format!("{}-shard-{}", self.name, shards.len());
+        shards.push(self);
+
+        shards
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        let handle = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+        let contents = (Self::type_name(), &self.name, &self.order, &self.permuted_indices);
+        bincode::serialize_into(handle, &contents).map_err(|e| e.to_string())
+    }
+
+    fn load(path: &Path, _: fn(&I, &I) -> U, _: bool) -> Result<Self, String> {
+        // The file is read so that a missing or corrupted file is reported as
+        // such, rather than as a borrowed dataset.
+        let handle = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+        let (type_name, ..): (String, String, Vec<usize>, Option<Vec<usize>>) =
+            bincode::deserialize_from(handle).map_err(|e| e.to_string())?;
+        Err(format!(
+            "A {type_name} borrows its instances and cannot be loaded from a file. Use `Tree::load_with_data` instead."
+        ))
+    }
+}
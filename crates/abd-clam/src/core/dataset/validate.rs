@@ -0,0 +1,143 @@
+//! Detection and handling of NaN and infinite values in datasets of vectors.
+//!
+//! A NaN component makes every distance to its instance NaN, and NaN is
+//! neither less than nor greater than any distance. The center and radius of
+//! a `Cluster` may then be chosen arbitrarily, the triangle inequality no
+//! longer bounds anything, and the searches in `cakes` may silently miss true
+//! neighbors or return the instance at any rank. Infinite components do the
+//! same once two infinities are subtracted. `VecDataset::validate` should be
+//! called before building a tree from data which may hold such values.
+
+use distances::{number::Float, Number};
+
+use crate::{Instance, VecDataset};
+
+/// What `VecDataset::validate` does with instances which have a NaN or
+/// infinite component.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NonFinitePolicy {
+    /// Return an error listing the offending instances. The dataset is
+    /// unchanged, and search over it is exact once it passes.
+    #[default]
+    Reject,
+
+    /// Replace each non-finite component with the mean of the finite values of
+    /// that component over the dataset, or zero if there are none. Search is
+    /// exact over the imputed instances, but their distances are only
+    /// estimates of the distances to the instances as they were measured.
+    Impute,
+
+    /// Remove the offending instances, along with their metadata and weights,
+    /// and return them. The remaining instances keep their original indices,
+    /// and search never returns the quarantined ones.
+    Quarantine,
+}
+
+/// The instances found by `VecDataset::validate` to have a NaN or infinite
+/// component.
+#[derive(Clone, Debug)]
+pub struct NonFinite<I: Instance, M: Instance> {
+    /// The indices of the offending instances, in the dataset as it was before
+    /// validation.
+    pub indices: Vec<usize>,
+    /// The offending instances and their metadata, if they were quarantined.
+    /// This is empty for the other policies.
+    pub quarantined: Vec<(I, M)>,
+}
+
+impl<T: Float, U: Number, M: Instance> VecDataset<Vec<T>, U, M> {
+    /// Returns the indices of the instances with a NaN or infinite component.
+    #[must_use]
+    pub fn non_finite(&self) -> Vec<usize> {
+        self.data
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.iter().any(|v| !v.as_f64().is_finite()))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Checks the dataset for instances with a NaN or infinite component, and
+    /// handles them according to the `policy`.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy`: What to do with the offending instances.
+    ///
+    /// # Returns
+    ///
+    /// The validated dataset, and the offending instances.
+    ///
+    /// # Errors
+    ///
+    /// * If the `policy` is `NonFinitePolicy::Reject` and any instance has a
+    ///   NaN or infinite component. The error lists some of their indices.
+    pub fn validate(mut self, policy: NonFinitePolicy) -> Result<(Self, NonFinite<Vec<T>, M>), String> {
+        let indices = self.non_finite();
+        if indices.is_empty() {
+            return Ok((
+                self,
+                NonFinite {
+                    indices,
+                    quarantined: Vec::new(),
+                },
+            ));
+        }
+
+        let quarantined = match policy {
+            NonFinitePolicy::Reject => {
+                return Err(format!(
+                    "Found {} instances with NaN or infinite values, e.g. at indices {:?}",
+                    indices.len(),
+                    &indices[..indices.len().min(10)]
+                ));
+            }
+            NonFinitePolicy::Impute => {
+                let means = self.finite_means();
+                for &i in &indices {
+                    for (v, &m) in self.data[i].iter_mut().zip(&means) {
+                        if !v.as_f64().is_finite() {
+                            *v = m;
+                        }
+                    }
+                }
+                Vec::new()
+            }
+            NonFinitePolicy::Quarantine => {
+                let quarantined = indices
+                    .iter()
+                    .map(|&i| (self.data[i].clone(), self.metadata[i].clone()))
+                    .collect();
+                self.remove(&indices)?;
+                quarantined
+            }
+        };
+
+        Ok((self, NonFinite { indices, quarantined }))
+    }
+
+    /// The mean of the finite values of each component, or zero for a
+    /// component with none.
+    fn finite_means(&self) -> Vec<T> {
+        let dim = self.data.iter().map(Vec::len).max().unwrap_or(0);
+        let mut sums = vec![(0.0, 0_usize); dim];
+        for x in &self.data {
+            for ((sum, count), v) in sums.iter_mut().zip(x) {
+                let v = v.as_f64();
+                if v.is_finite() {
+                    *sum += v;
+                    *count += 1;
+                }
+            }
+        }
+        sums.into_iter()
+            .map(|(sum, count)| {
+                if count == 0 {
+                    T::zero()
+                } else {
+                    T::from(sum / count.as_f64())
+                }
+            })
+            .collect()
+    }
+}
@@ -3,15 +3,16 @@
 use core::{fmt::Debug, ops::Index};
 
 use std::{
+    borrow::Cow,
     fs::File,
     io::{BufWriter, Read, Write},
     path::Path,
 };
 
 use distances::Number;
-use rayon::prelude::*;
 
-use crate::Dataset;
+use crate::par::prelude::*;
+use crate::{Dataset, FnMetric, Metric, SharedMetric};
 
 use super::Instance;
 
@@ -31,13 +32,19 @@ pub struct VecDataset<I: Instance, U: Number, M: Instance> {
     /// The data of the dataset.
     pub(crate) data: Vec<I>,
     /// The metric of the dataset.
-    pub(crate) metric: fn(&I, &I) -> U,
-    /// Whether the metric is expensive to compute.
-    pub(crate) is_expensive: bool,
+    pub(crate) metric: SharedMetric<I, U>,
     /// The reordering of the dataset after building the tree.
     pub(crate) permuted_indices: Option<Vec<usize>>,
     /// Metadata about the dataset.
     pub(crate) metadata: Vec<M>,
+    /// The weights of the instances, if the dataset is weighted.
+    pub(crate) weights: Option<Vec<f64>>,
+    /// A cheap lower bound on the metric, if one was given besides that of
+    /// the metric.
+    pub(crate) lower_bound: Option<fn(&I, &I) -> U>,
+    /// A cheap upper bound on the metric, if one was given besides that of
+    /// the metric.
+    pub(crate) upper_bound: Option<fn(&I, &I) -> U>,
 }
 
 impl<I: Instance, U: Number> VecDataset<I, U, usize> {
@@ -50,19 +57,59 @@ impl<I: Instance, U: Number> VecDataset<I, U, usize> {
     /// * `metric`: The metric for computing distances between instances.
     /// * `is_expensive`: Whether the metric is expensive to compute.
     pub fn new(name: String, data: Vec<I>, metric: fn(&I, &I) -> U, is_expensive: bool) -> Self {
+        Self::from_metric(name, data, FnMetric::new(metric).with_is_expensive(is_expensive))
+    }
+
+    /// Creates a new dataset with a distance function whose properties are
+    /// explicitly declared.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the dataset.
+    /// * `data`: The vector of instances.
+    /// * `metric`: The distance function and its properties, e.g. a
+    ///   `FnMetric`, or any `Metric` wrapped in a `SharedMetric`.
+    #[must_use]
+    pub fn from_metric(name: String, data: Vec<I>, metric: impl Into<SharedMetric<I, U>>) -> Self {
         let metadata = (0..data.len()).collect();
         Self {
             name,
             data,
-            metric,
-            is_expensive,
+            metric: metric.into(),
             permuted_indices: None,
             metadata,
+            weights: None,
+            lower_bound: None,
+            upper_bound: None,
         }
     }
 }
 
 impl<I: Instance, U: Number, M: Instance> VecDataset<I, U, M> {
+    /// Clones the dataset with a new distance function, whose properties are
+    /// explicitly declared.
+    ///
+    /// `Dataset::clone_with_new_metric` declares a bare function to be a
+    /// symmetric, distance metric, as `VecDataset::new` does.
+    ///
+    /// # Arguments
+    ///
+    /// * `metric`: The distance function and its properties.
+    /// * `name`: The name of the new dataset.
+    #[must_use]
+    pub fn clone_with_metric(&self, metric: impl Into<SharedMetric<I, U>>, name: String) -> Self {
+        Self {
+            name,
+            data: self.data.clone(),
+            metric: metric.into(),
+            permuted_indices: self.permuted_indices.clone(),
+            metadata: self.metadata.clone(),
+            weights: self.weights.clone(),
+            lower_bound: None,
+            upper_bound: None,
+        }
+    }
+
     /// Assigns metadata to the dataset.
     ///
     /// # Arguments
@@ -89,9 +136,11 @@ impl<I: Instance, U: Number, M: Instance> VecDataset<I, U, M> {
                 name: self.name,
                 data: self.data,
                 metric: self.metric,
-                is_expensive: self.is_expensive,
                 permuted_indices: self.permuted_indices,
                 metadata,
+                weights: self.weights,
+                lower_bound: self.lower_bound,
+                upper_bound: self.upper_bound,
             })
         } else {
             Err(format!(
@@ -102,6 +151,97 @@ impl<I: Instance, U: Number, M: Instance> VecDataset<I, U, M> {
         }
     }
 
+    /// Assigns weights to the instances.
+    ///
+    /// The weights should be assigned before building a tree, so that they
+    /// may pull the centers of `Cluster`s towards the heavier instances.
+    ///
+    /// # Arguments
+    ///
+    /// * `weights`: The weight of each instance, in the original order of
+    ///   the instances.
+    ///
+    /// # Errors
+    ///
+    /// * If the weights are not the same length as the dataset.
+    /// * If any weight is negative or not finite.
+    pub fn with_weights(mut self, weights: Vec<f64>) -> Result<Self, String> {
+        if weights.len() != self.data.len() {
+            return Err(format!(
+                "Invalid weights. Expected weights of length {}, got weights of length {}",
+                self.cardinality(),
+                weights.len()
+            ));
+        }
+        if let Some(w) = weights.iter().find(|w| !w.is_finite() || **w < 0.0) {
+            return Err(format!(
+                "Invalid weight. Expected a finite, non-negative weight, got {w}"
+            ));
+        }
+
+        // If there is a permutation, permute the weights as well.
+        self.weights = Some(if let Some(permutation) = self.permuted_indices.as_ref() {
+            permutation.iter().map(|&index| weights[index]).collect()
+        } else {
+            weights
+        });
+        Ok(self)
+    }
+
+    /// Sets a cheap lower bound on the metric, e.g. after loading a dataset,
+    /// since functions are not saved. This takes precedence over any lower
+    /// bound of the metric. See `FnMetric::with_lower_bound`.
+    ///
+    /// # Arguments
+    ///
+    /// * `lower_bound`: A function which never exceeds the metric.
+    #[must_use]
+    pub fn with_lower_bound(mut self, lower_bound: fn(&I, &I) -> U) -> Self {
+        self.lower_bound = Some(lower_bound);
+        self
+    }
+
+    /// Sets a cheap upper bound on the metric, e.g. after loading a dataset,
+    /// since functions are not saved. This takes precedence over any upper
+    /// bound of the metric. See `FnMetric::with_upper_bound`.
+    ///
+    /// # Arguments
+    ///
+    /// * `upper_bound`: A function which is never less than the metric.
+    #[must_use]
+    pub fn with_upper_bound(mut self, upper_bound: fn(&I, &I) -> U) -> Self {
+        self.upper_bound = Some(upper_bound);
+        self
+    }
+
+    /// Sets the weight of the instance at the given index, making the dataset
+    /// weighted if it was not.
+    ///
+    /// # Arguments
+    ///
+    /// * `index`: The index of the instance.
+    /// * `weight`: The new weight of the instance.
+    ///
+    /// # Errors
+    ///
+    /// * If `index` is not a valid index into the dataset.
+    /// * If `weight` is negative or not finite.
+    pub fn set_weight(&mut self, index: usize, weight: f64) -> Result<(), String> {
+        let cardinality = self.data.len();
+        if index >= cardinality {
+            return Err(format!(
+                "Invalid index. Expected an index less than {cardinality}, got {index}"
+            ));
+        }
+        if !weight.is_finite() || weight < 0.0 {
+            return Err(format!(
+                "Invalid weight. Expected a finite, non-negative weight, got {weight}"
+            ));
+        }
+        self.weights.get_or_insert_with(|| vec![1.0; cardinality])[index] = weight;
+        Ok(())
+    }
+
     /// A reference to the underlying data.
     #[must_use]
     pub fn data(&self) -> &[I] {
@@ -131,6 +271,101 @@ impl<I: Instance, U: Number, M: Instance> VecDataset<I, U, M> {
     pub fn metadata_of(&self, index: usize) -> &M {
         &self.metadata[index]
     }
+
+    /// Inserts an instance, along with its metadata, at the given index,
+    /// shifting all instances after it.
+    ///
+    /// If the dataset is weighted, the new instance has a weight of one. See
+    /// `set_weight` to change it.
+    ///
+    /// The original index of the new instance, as reported by `original_index`,
+    /// is one more than the largest original index in the dataset, i.e. the
+    /// cardinality of the dataset before the insertion if no instances have
+    /// been removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `index`: The index at which to insert the instance.
+    /// * `instance`: The instance to insert.
+    /// * `metadata`: The metadata of the instance.
+    ///
+    /// # Errors
+    ///
+    /// * If `index` is greater than the cardinality of the dataset.
+    pub fn insert(&mut self, index: usize, instance: I, metadata: M) -> Result<(), String> {
+        let cardinality = self.data.len();
+        if index > cardinality {
+            return Err(format!(
+                "Invalid index. Expected an index of at most {cardinality}, got {index}"
+            ));
+        }
+
+        if self.permuted_indices.is_none() && index < cardinality {
+            self.permuted_indices = Some((0..cardinality).collect());
+        }
+        if let Some(permutation) = self.permuted_indices.as_mut() {
+            let original = permutation.iter().max().map_or(0, |&i| i + 1);
+            permutation.insert(index, original);
+        }
+
+        self.data.insert(index, instance);
+        self.metadata.insert(index, metadata);
+        if let Some(weights) = self.weights.as_mut() {
+            weights.insert(index, 1.0);
+        }
+
+        Ok(())
+    }
+
+    /// Removes the instances, along with their metadata, at the given indices,
+    /// shifting all instances after them.
+    ///
+    /// The remaining instances keep their original indices, as reported by
+    /// `original_index`.
+    ///
+    /// # Arguments
+    ///
+    /// * `indices`: The indices of the instances to remove.
+    ///
+    /// # Errors
+    ///
+    /// * If any of the `indices` is not a valid index into the dataset.
+    pub fn remove(&mut self, indices: &[usize]) -> Result<(), String> {
+        let cardinality = self.data.len();
+        let mut keep = vec![true; cardinality];
+        for &i in indices {
+            if i >= cardinality {
+                return Err(format!(
+                    "Invalid index. Expected an index less than {cardinality}, got {i}"
+                ));
+            }
+            keep[i] = false;
+        }
+
+        let permutation = self
+            .permuted_indices
+            .take()
+            .unwrap_or_else(|| (0..cardinality).collect());
+        self.permuted_indices = Some(
+            permutation
+                .into_iter()
+                .zip(keep.iter())
+                .filter(|&(_, &k)| k)
+                .map(|(i, _)| i)
+                .collect(),
+        );
+
+        let mut flags = keep.iter().copied();
+        self.data.retain(|_| flags.next().unwrap_or(true));
+        let mut flags = keep.iter().copied();
+        self.metadata.retain(|_| flags.next().unwrap_or(true));
+        if let Some(weights) = self.weights.as_mut() {
+            let mut flags = keep.iter().copied();
+            weights.retain(|_| flags.next().unwrap_or(true));
+        }
+
+        Ok(())
+    }
 }
 
 impl<I: Instance, U: Number, M: Instance> Index<usize> for VecDataset<I, U, M> {
@@ -143,14 +378,7 @@ impl<I: Instance, U: Number, M: Instance> Index<usize> for VecDataset<I, U, M> {
 
 impl<I: Instance, U: Number, M: Instance> Dataset<I, U> for VecDataset<I, U, M> {
     fn clone_with_new_metric(&self, metric: fn(&I, &I) -> U, is_expensive: bool, name: String) -> Self {
-        Self {
-            name,
-            data: self.data.clone(),
-            metric,
-            is_expensive,
-            permuted_indices: self.permuted_indices.clone(),
-            metadata: self.metadata.clone(),
-        }
+        self.clone_with_metric(FnMetric::new(metric).with_is_expensive(is_expensive), name)
     }
 
     fn type_name() -> String {
@@ -165,12 +393,24 @@ impl<I: Instance, U: Number, M: Instance> Dataset<I, U> for VecDataset<I, U, M>
         self.data.len()
     }
 
-    fn is_metric_expensive(&self) -> bool {
-        self.is_expensive
+    fn get(&self, index: usize) -> Cow<'_, I> {
+        Cow::Borrowed(&self[index])
+    }
+
+    fn metric(&self) -> &SharedMetric<I, U> {
+        &self.metric
+    }
+
+    fn lower_bound(&self) -> Option<fn(&I, &I) -> U> {
+        self.lower_bound.or_else(|| self.metric.lower_bound())
     }
 
-    fn metric(&self) -> fn(&I, &I) -> U {
-        self.metric
+    fn upper_bound(&self) -> Option<fn(&I, &I) -> U> {
+        self.upper_bound.or_else(|| self.metric.upper_bound())
+    }
+
+    fn weights(&self) -> Option<&[f64]> {
+        self.weights.as_deref()
     }
 
     fn set_permuted_indices(&mut self, indices: Option<&[usize]>) {
@@ -180,6 +420,9 @@ impl<I: Instance, U: Number, M: Instance> Dataset<I, U> for VecDataset<I, U, M>
     fn swap(&mut self, left: usize, right: usize) -> Result<(), String> {
         self.data.swap(left, right);
         self.metadata.swap(left, right);
+        if let Some(weights) = self.weights.as_mut() {
+            weights.swap(left, right);
+        }
         Ok(())
     }
 
@@ -201,6 +444,9 @@ impl<I: Instance, U: Number, M: Instance> Dataset<I, U> for VecDataset<I, U, M>
             .par_iter()
             .map(|&index| self.metadata[index].clone())
             .collect();
+        if let Some(weights) = self.weights.as_ref() {
+            self.weights = Some(permutation.iter().map(|&index| weights[index]).collect());
+        }
 
         self.set_permuted_indices(Some(permutation));
 
@@ -219,12 +465,15 @@ impl<I: Instance, U: Number, M: Instance> Dataset<I, U> for VecDataset<I, U, M>
             let at = self.data.len() - max_cardinality;
             let data = self.data.split_off(at);
 
-            // Create the shard, assign the metadata, and add it to the list of shards.
-            shards.push(
-                VecDataset::new(name, data, self.metric, self.is_expensive)
-                    .assign_metadata(metadata.split_off(at))
-                    .unwrap_or_else(|_| unreachable!("We just split this dataset at the same indices.")),
-            );
+            // Create the shard, assign the metadata and weights, and add it to
+            // the list of shards.
+            let mut shard = VecDataset::from_metric(name, data, self.metric.clone())
+                .assign_metadata(metadata.split_off(at))
+                .unwrap_or_else(|_| unreachable!("We just split this dataset at the same indices."));
+            shard.weights = self.weights.as_mut().map(|weights| weights.split_off(at));
+            shard.lower_bound = self.lower_bound;
+            shard.upper_bound = self.upper_bound;
+            shards.push(shard);
         }
 
         self.name = format!("{}-shard-{}", self.name, shards.len());
@@ -278,10 +527,63 @@ impl<I: Instance, U: Number, M: Instance> Dataset<I, U> for VecDataset<I, U, M>
             meta.save(&mut handle)?;
         }
 
+        // Write the weights, if any, with their number first.
+        let weights = self
+            .weights
+            .as_ref()
+            .map_or(Vec::new(), |w| w.iter().flat_map(|w| w.to_le_bytes()).collect());
+        handle
+            .write_all(&(weights.len() / 8).to_le_bytes())
+            .and_then(|()| handle.write_all(&weights))
+            .map_err(|e| e.to_string())?;
+
+        // Write the properties of the metric, which a function pointer does
+        // not carry.
+        handle
+            .write_all(&[
+                <u8 as From<bool>>::from(self.metric.is_metric()),
+                <u8 as From<bool>>::from(self.metric.is_symmetric()),
+            ])
+            .map_err(|e| e.to_string())?;
+
         Ok(())
     }
 
     fn load(path: &Path, metric: fn(&I, &I) -> U, is_expensive: bool) -> Result<Self, String> {
+        Self::load_parts(path, |is_metric, is_symmetric| {
+            FnMetric::new(metric)
+                .with_is_expensive(is_expensive)
+                .with_is_metric(is_metric)
+                .with_is_symmetric(is_symmetric)
+                .into()
+        })
+    }
+}
+
+impl<I: Instance, U: Number, M: Instance> VecDataset<I, U, M> {
+    /// Loads a dataset saved with `Dataset::save`, with a distance function
+    /// whose properties are explicitly declared.
+    ///
+    /// Unlike `Dataset::load`, the properties of the metric saved with the
+    /// dataset are ignored in favor of those of the given `metric`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path to the saved dataset.
+    /// * `metric`: The distance function and its properties.
+    ///
+    /// # Errors
+    ///
+    /// * If the file cannot be read, or does not hold a dataset of this type.
+    pub fn load_with_metric(path: &Path, metric: impl Into<SharedMetric<I, U>>) -> Result<Self, String> {
+        let metric = metric.into();
+        Self::load_parts(path, |_, _| metric)
+    }
+
+    /// Loads a dataset saved with `Dataset::save`, building its metric from
+    /// whether the saved metric obeyed the triangle inequality and was
+    /// symmetric.
+    fn load_parts(path: &Path, metric: impl FnOnce(bool, bool) -> SharedMetric<I, U>) -> Result<Self, String> {
         let mut handle = File::open(path).map_err(|e| e.to_string())?;
 
         // Check that the type name matches.
@@ -357,13 +659,42 @@ impl<I: Instance, U: Number, M: Instance> Dataset<I, U> for VecDataset<I, U, M>
             .map(|_| M::load(&mut handle))
             .collect::<Result<Vec<_>, _>>()?;
 
+        // Read the weights, if they exist. Files saved before weights were
+        // supported end after the metadata.
+        let weights = {
+            let mut num_weights_buf = vec![0; usize::num_bytes()];
+            match handle.read_exact(&mut num_weights_buf) {
+                Ok(()) if <usize as Number>::from_le_bytes(&num_weights_buf) > 0 => {
+                    let mut weights_buf = vec![0; 8 * cardinality];
+                    handle.read_exact(&mut weights_buf).map_err(|e| e.to_string())?;
+                    Some(weights_buf.chunks(8).map(<f64 as Number>::from_le_bytes).collect())
+                }
+                Ok(()) => None,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => None,
+                Err(e) => return Err(e.to_string()),
+            }
+        };
+
+        // Read the properties of the metric. Files saved before they were
+        // recorded end after the weights, and had a symmetric metric.
+        let [is_metric, is_symmetric] = {
+            let mut properties_buf = [0; 2];
+            match handle.read_exact(&mut properties_buf) {
+                Ok(()) => properties_buf.map(|b| b != 0),
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => [true; 2],
+                Err(e) => return Err(e.to_string()),
+            }
+        };
+
         Ok(Self {
             name,
             data,
-            metric,
-            is_expensive,
+            metric: metric(is_metric, is_symmetric),
             permuted_indices: permutation,
             metadata,
+            weights,
+            lower_bound: None,
+            upper_bound: None,
         })
     }
 }
@@ -0,0 +1,137 @@
+//! Exporting the hierarchy of `Cluster`s in a `Tree` for visualization.
+
+use core::fmt::Write;
+
+use distances::Number;
+use serde::{Deserialize, Serialize};
+
+use crate::{Cluster, Dataset, Instance, Tree};
+
+/// A `Cluster` in a `Dendrogram`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DendrogramNode {
+    /// The name of the `Cluster`, i.e. its offset and cardinality.
+    pub name: String,
+    /// The offset of the indices of the `Cluster`'s instances.
+    pub offset: usize,
+    /// The number of instances in the `Cluster`.
+    pub cardinality: usize,
+    /// The depth of the `Cluster` in the `Tree`.
+    pub depth: usize,
+    /// The radius of the `Cluster`.
+    pub radius: f64,
+    /// The local fractal dimension of the `Cluster`.
+    pub lfd: f64,
+    /// The index of the instance at the center of the `Cluster`.
+    pub arg_center: usize,
+    /// The position of the parent in `Dendrogram::nodes`, or `None` for the
+    /// root.
+    pub parent: Option<usize>,
+    /// The positions of the children in `Dendrogram::nodes`, empty for a leaf.
+    pub children: Vec<usize>,
+}
+
+/// The hierarchy of `Cluster`s in a `Tree`, as returned by `Tree::dendrogram`.
+///
+/// The nodes are kept in a flat list, rather than nested, so that trees of
+/// any depth may be serialized. It may be exported with `to_json`, or with
+/// `to_newick` for standard tree viewers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dendrogram {
+    /// The `Cluster`s in pre-order, so that the root is first and every node
+    /// comes before its children.
+    pub nodes: Vec<DendrogramNode>,
+}
+
+impl Dendrogram {
+    /// Serializes the dendrogram as pretty-printed JSON.
+    ///
+    /// # Errors
+    ///
+    /// * If the dendrogram could not be serialized.
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+
+    /// Deserializes a dendrogram from JSON.
+    ///
+    /// # Errors
+    ///
+    /// * If the JSON is not a valid dendrogram.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+
+    /// Writes the dendrogram in the Newick format.
+    ///
+    /// Each node is labelled with the name of its `Cluster`. The length of the
+    /// branch to a node is the radius of its parent minus its own radius, or
+    /// zero if that is negative, so that the height of each node is its
+    /// radius. The cardinality, radius, local fractal dimension and depth of
+    /// each `Cluster` are annotated in the NHX extension of the format, which
+    /// most viewers either display or ignore.
+    #[must_use]
+    pub fn to_newick(&self) -> String {
+        // Children come after their parents, so the text of every child is
+        // ready by the time its parent is reached in reverse.
+        let mut texts = vec![String::new(); self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate().rev() {
+            let mut text = if node.children.is_empty() {
+                String::new()
+            } else {
+                let children = node
+                    .children
+                    .iter()
+                    .map(|&c| core::mem::take(&mut texts[c]))
+                    .collect::<Vec<_>>();
+                format!("({})", children.join(","))
+            };
+            text.push_str(&node.name);
+            if let Some(p) = node.parent {
+                let length = self.nodes[p].radius - node.radius;
+                let _ = write!(text, ":{}", if length > 0.0 { length } else { 0.0 });
+            }
+            let _ = write!(
+                text,
+                "[&&NHX:cardinality={}:radius={}:lfd={}:depth={}]",
+                node.cardinality, node.radius, node.lfd, node.depth
+            );
+            texts[i] = text;
+        }
+
+        let mut newick = texts.into_iter().next().unwrap_or_default();
+        newick.push(';');
+        newick
+    }
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
+    /// Exports the hierarchy of `Cluster`s, with their radii, cardinalities
+    /// and local fractal dimensions, e.g. to draw the `Tree` as a dendrogram.
+    pub fn dendrogram(&self) -> Dendrogram {
+        let mut nodes = Vec::<DendrogramNode>::new();
+        let mut stack = vec![(&self.root, None::<usize>)];
+        while let Some((c, parent)) = stack.pop() {
+            let position = nodes.len();
+            if let Some(p) = parent {
+                nodes[p].children.push(position);
+            }
+            nodes.push(DendrogramNode {
+                name: c.name(),
+                offset: c.offset(),
+                cardinality: c.cardinality(),
+                depth: c.depth(),
+                radius: c.radius().as_f64(),
+                lfd: c.lfd(),
+                arg_center: c.arg_center(),
+                parent,
+                children: Vec::new(),
+            });
+            // The children are pushed in reverse so that they are visited in order.
+            if let Some(children) = c.children() {
+                stack.extend(children.into_iter().rev().map(|c| (c, Some(position))));
+            }
+        }
+        Dendrogram { nodes }
+    }
+}
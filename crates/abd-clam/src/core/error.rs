@@ -0,0 +1,77 @@
+//! The errors returned by the fallible APIs of the crate.
+
+use core::fmt::{Display, Formatter};
+
+/// An error from building a `Tree` or searching it.
+///
+/// The `try_` variants of the constructors and searches, e.g. `Cakes::try_new`
+/// and `Cakes::try_knn_search`, return this instead of panicking, so that a
+/// server can reject a bad request and keep serving. Most of the crate still
+/// reports errors as a `String`, and an `Error` converts into one with `?`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// A `Tree` cannot be built from a dataset with no instances.
+    EmptyDataset,
+    /// More neighbors were asked for than there are instances to search.
+    KTooLarge {
+        /// The number of neighbors asked for.
+        k: usize,
+        /// The number of instances which may be searched.
+        cardinality: usize,
+    },
+    /// A search radius was negative or not a number. A radius of zero is
+    /// valid, and finds the instances identical to the query.
+    InvalidRadius(String),
+    /// An instance or query has a different dimensionality than expected.
+    DimensionMismatch {
+        /// The dimensionality expected.
+        expected: usize,
+        /// The dimensionality found.
+        found: usize,
+    },
+    /// A file could not be read or written.
+    Io(String),
+    /// The work was cancelled with a `CancellationToken` before it finished.
+    Cancelled,
+    /// Any other error, with its message.
+    Other(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self {
+            Self::EmptyDataset => write!(f, "Expected a dataset with at least one instance"),
+            Self::KTooLarge { k, cardinality } => {
+                write!(f, "Cannot find {k} neighbors among {cardinality} instances")
+            }
+            Self::InvalidRadius(radius) => write!(f, "Expected a non-negative radius, got {radius}"),
+            Self::DimensionMismatch { expected, found } => {
+                write!(f, "Expected dimensionality {expected}, got {found}")
+            }
+            Self::Io(e) => write!(f, "IO error: {e}"),
+            Self::Cancelled => write!(f, "Cancelled before finishing"),
+            Self::Other(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e.to_string())
+    }
+}
+
+impl From<String> for Error {
+    fn from(e: String) -> Self {
+        Self::Other(e)
+    }
+}
+
+impl From<Error> for String {
+    fn from(e: Error) -> Self {
+        e.to_string()
+    }
+}
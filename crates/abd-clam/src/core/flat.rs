@@ -0,0 +1,241 @@
+//! Flat clusterings, cut from the hierarchy of `Cluster`s in a `Tree`.
+
+use std::collections::BinaryHeap;
+
+use distances::Number;
+
+use crate::par::prelude::*;
+use crate::{Cluster, Dataset, Instance, Tree};
+
+/// Where to cut the hierarchy of a `Tree` for `Tree::flat_clustering`.
+///
+/// Each cut selects a set of `Cluster`s, none of which is an ancestor of
+/// another, which together contain every instance. This is like cutting a
+/// dendrogram from hierarchical clustering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cut<U: Number> {
+    /// The `Cluster`s at the given depth, and the leaves above it.
+    Depth(usize),
+    /// The largest `Cluster`s whose radius is at most the given radius, and
+    /// any leaves with a larger radius.
+    Radius(U),
+    /// The given number of `Cluster`s, found by repeatedly splitting the
+    /// `Cluster` with the largest radius, starting from the root. There may be
+    /// more if splitting the last `Cluster` overshoots the number, and fewer if
+    /// the tree has too few leaves.
+    NumClusters(usize),
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
+    /// Cuts the hierarchy of `Cluster`s to give a flat clustering of the
+    /// instances.
+    ///
+    /// # Arguments
+    ///
+    /// * `cut` - Where to cut the hierarchy.
+    ///
+    /// # Returns
+    ///
+    /// The clusters, as vectors of the indices of their instances in
+    /// increasing order, sorted by their first index. Removed instances are in
+    /// no cluster, and clusters with no other instances are left out.
+    pub fn flat_clustering(&self, cut: Cut<U>) -> Vec<Vec<usize>> {
+        self.cut_clusters(cut).into_iter().map(|(_, indices)| indices).collect()
+    }
+
+    /// Computes the silhouette coefficient of the flat clustering given by a
+    /// `Cut`.
+    ///
+    /// For an instance, let `a` be its mean distance to the other instances in
+    /// its cluster, and `b` the smallest mean distance to the instances of
+    /// another cluster. Its silhouette is `(b - a) / max(a, b)`, or zero if it
+    /// is alone in its cluster. The coefficient is the mean silhouette of all
+    /// instances, in the range `[-1, 1]`, where higher is better. It is zero if
+    /// there are fewer than two clusters.
+    ///
+    /// The `Tree` speeds up the search for `b`. If the instances of a cluster
+    /// are at a mean distance `s` from its center `c`, then the triangle
+    /// inequality bounds the mean distance from an instance `x` to them below
+    /// by `d(x, c) - s`. The clusters are visited in increasing order of this
+    /// bound, and the rest are skipped once the bound exceeds the smallest
+    /// mean distance found. Without the triangle inequality, every mean
+    /// distance is computed.
+    ///
+    /// # Arguments
+    ///
+    /// * `cut` - Where to cut the hierarchy.
+    pub fn silhouette(&self, cut: Cut<U>) -> f64 {
+        let clusters = self.cut_clusters(cut);
+        if clusters.len() < 2 {
+            return 0.0;
+        }
+
+        let data = self.data();
+        let is_metric = data.is_metric();
+        let centers = clusters.iter().map(|(c, _)| c.arg_center()).collect::<Vec<_>>();
+        let spreads = clusters
+            .iter()
+            .zip(&centers)
+            .map(|((_, indices), &c)| sum_of_distances(data, c, indices) / indices.len().as_f64())
+            .collect::<Vec<_>>();
+
+        let total = clusters
+            .par_iter()
+            .enumerate()
+            .flat_map(|(own, (_, indices))| indices.par_iter().map(move |&x| (own, indices, x)))
+            .map(|(own, indices, x)| {
+                if indices.len() == 1 {
+                    return 0.0;
+                }
+                let a = sum_of_distances(data, x, indices) / (indices.len() - 1).as_f64();
+
+                let mut others = (0..clusters.len())
+                    .filter(|&j| j != own)
+                    .map(|j| {
+                        let bound = if is_metric {
+                            data.one_to_one(x, centers[j]).as_f64() - spreads[j]
+                        } else {
+                            0.0
+                        };
+                        (bound, j)
+                    })
+                    .collect::<Vec<_>>();
+                others.sort_by(|(p, _), (q, _)| p.total_cmp(q));
+
+                let mut b = f64::INFINITY;
+                for (bound, j) in others {
+                    if bound >= b {
+                        break;
+                    }
+                    let others = &clusters[j].1;
+                    b = b.min(sum_of_distances(data, x, others) / others.len().as_f64());
+                }
+
+                let scale = a.max(b);
+                if scale > 0.0 {
+                    (b - a) / scale
+                } else {
+                    0.0
+                }
+            })
+            .sum::<f64>();
+
+        let n = clusters.iter().map(|(_, indices)| indices.len()).sum::<usize>();
+        total / n.as_f64()
+    }
+
+    /// Computes the Davies-Bouldin index of the flat clustering given by a
+    /// `Cut`.
+    ///
+    /// Each cluster is represented by the center of its `Cluster` in the
+    /// `Tree`, and its scatter `s` is the mean distance from its instances to
+    /// that center. The index is the mean, over the clusters, of the largest
+    /// `(s_i + s_j) / d(c_i, c_j)` over the other clusters `j`. Lower is
+    /// better, and it is zero if there are fewer than two clusters. Using the
+    /// centers from the `Tree` means that only one distance per instance, and
+    /// one per pair of clusters, is computed.
+    ///
+    /// # Arguments
+    ///
+    /// * `cut` - Where to cut the hierarchy.
+    pub fn davies_bouldin(&self, cut: Cut<U>) -> f64 {
+        let clusters = self.cut_clusters(cut);
+        if clusters.len() < 2 {
+            return 0.0;
+        }
+
+        let data = self.data();
+        let centers = clusters.iter().map(|(c, _)| c.arg_center()).collect::<Vec<_>>();
+        let scatters = clusters
+            .iter()
+            .zip(&centers)
+            .map(|((_, indices), &c)| sum_of_distances(data, c, indices) / indices.len().as_f64())
+            .collect::<Vec<_>>();
+
+        let total = (0..clusters.len())
+            .into_par_iter()
+            .map(|i| {
+                let distances = data.one_to_many(centers[i], &centers);
+                (0..clusters.len())
+                    .filter(|&j| j != i)
+                    .map(|j| {
+                        let s = scatters[i] + scatters[j];
+                        if s > 0.0 {
+                            s / distances[j].as_f64()
+                        } else {
+                            0.0
+                        }
+                    })
+                    .fold(0.0, f64::max)
+            })
+            .sum::<f64>();
+
+        total / clusters.len().as_f64()
+    }
+
+    /// Returns the `Cluster`s selected by a `Cut`, each with the indices of
+    /// its instances which have not been removed, leaving out those with none.
+    fn cut_clusters(&self, cut: Cut<U>) -> Vec<(&C, Vec<usize>)> {
+        self.cut(cut)
+            .into_iter()
+            .map(|c| (c, c.indices().filter(|&i| !self.is_removed(i)).collect::<Vec<_>>()))
+            .filter(|(_, indices)| !indices.is_empty())
+            .collect()
+    }
+
+    /// Returns the `Cluster`s selected by a `Cut`, sorted by offset.
+    fn cut(&self, cut: Cut<U>) -> Vec<&C> {
+        let mut selected = Vec::new();
+        match cut {
+            Cut::Depth(depth) => {
+                let mut stack = vec![&self.root];
+                while let Some(c) = stack.pop() {
+                    match c.children() {
+                        Some(children) if c.depth() < depth => stack.extend(children),
+                        _ => selected.push(c),
+                    }
+                }
+            }
+            Cut::Radius(radius) => {
+                let mut stack = vec![&self.root];
+                while let Some(c) = stack.pop() {
+                    match c.children() {
+                        Some(children) if c.radius() > radius => stack.extend(children),
+                        _ => selected.push(c),
+                    }
+                }
+            }
+            Cut::NumClusters(n) => {
+                // Non-negative floats are ordered in the same way as their
+                // bits, so the radii can be kept in the heap as integers.
+                let mut clusters = vec![&self.root];
+                let mut heap = BinaryHeap::from([(self.root.radius().as_f64().to_bits(), 0)]);
+                let mut leaves = Vec::new();
+                while heap.len() + leaves.len() < n {
+                    let Some((_, p)) = heap.pop() else {
+                        break;
+                    };
+                    let c = clusters[p];
+                    match c.children() {
+                        Some(children) => {
+                            for child in children {
+                                heap.push((child.radius().as_f64().to_bits(), clusters.len()));
+                                clusters.push(child);
+                            }
+                        }
+                        None => leaves.push(c),
+                    }
+                }
+                selected = heap.into_iter().map(|(_, p)| clusters[p]).chain(leaves).collect();
+            }
+        }
+
+        selected.sort_by_key(|c| c.offset());
+        selected
+    }
+}
+
+/// The sum of the distances from the instance at `index` to those at `indices`.
+fn sum_of_distances<I: Instance, U: Number, D: Dataset<I, U>>(data: &D, index: usize, indices: &[usize]) -> f64 {
+    data.one_to_many(index, indices).into_iter().map(Number::as_f64).sum()
+}
@@ -0,0 +1,114 @@
+//! Estimates of the memory used to build and hold a `Tree`.
+
+use core::mem::size_of;
+
+use distances::Number;
+
+use crate::core::cluster::Children;
+use crate::{Cluster, Dataset, Instance, PartitionCriterion, Tree};
+
+/// The most instances whose sizes are measured to estimate the size of the
+/// dataset.
+const NUM_SAMPLES: usize = 100;
+
+/// An estimate, in bytes, of the memory needed to build and hold a `Tree`, as
+/// returned by `Tree::estimate_memory`.
+///
+/// The estimates of the `Tree` are loose, worst-case upper bounds. The
+/// criteria cannot be inspected without building the `Tree`, so they are
+/// assumed to partition the dataset until every leaf has a single instance,
+/// and every partitioned `Cluster` is assumed to have only two children.
+/// Criteria which stop earlier, e.g. at a `max_depth` or at a minimum
+/// cardinality of `m`, need far fewer `Cluster`s, roughly `m` times fewer in
+/// the latter case.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryEstimate {
+    /// The instances of the dataset, estimated from the serialized sizes of a
+    /// sample of them. Datasets which keep their instances on disk, e.g. in a
+    /// memory-mapped file, need much less than this in memory.
+    pub instances: usize,
+    /// The most `Cluster`s in the `Tree`.
+    pub num_clusters: usize,
+    /// The `Cluster`s of the `Tree`, with the lists of children and poles of
+    /// those which are partitioned. Every `Cluster` but the root is in the
+    /// lists of exactly one parent.
+    pub clusters: usize,
+    /// The permutation recorded by the dataset when the `Tree` reorders it.
+    pub permutation: usize,
+    /// The indices, distances to the poles and sort keys held while
+    /// partitioning. These are freed once the `Tree` is built.
+    ///
+    /// The `Cluster`s which are partitioned at the same time, on different
+    /// threads, have disjoint instances, and a `Cluster` frees its own
+    /// indices before its children are partitioned. So at any time, each
+    /// instance is held by at most one `Cluster` which is being split, and
+    /// this is bounded by what splitting the root holds: a few copies of the
+    /// indices, the distances from every instance to each of the `fan_out`
+    /// poles, and the keys by which the instances are sorted.
+    pub build: usize,
+}
+
+impl MemoryEstimate {
+    /// The bytes needed once the `Tree` is built.
+    #[must_use]
+    pub const fn resident(&self) -> usize {
+        self.instances + self.clusters + self.permutation
+    }
+
+    /// The most bytes needed at any time while the `Tree` is built, i.e. the
+    /// `resident` bytes along with those held only while partitioning.
+    #[must_use]
+    pub const fn peak(&self) -> usize {
+        self.resident() + self.build
+    }
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
+    /// Estimates the memory needed to build a `Tree` from `data` with the
+    /// `criteria`, without building it.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The dataset from which the `Tree` would be built.
+    /// * `criteria`: The criteria with which it would be partitioned.
+    pub fn estimate_memory<P: PartitionCriterion<U>>(data: &D, criteria: &P) -> MemoryEstimate {
+        let cardinality = data.cardinality();
+
+        let step = (cardinality / NUM_SAMPLES).max(1);
+        let samples = (0..cardinality).step_by(step).take(NUM_SAMPLES).collect::<Vec<_>>();
+        let instance_bytes = if samples.is_empty() {
+            0
+        } else {
+            let total_bytes = samples.iter().map(|&i| data.get(i).to_bytes().len()).sum::<usize>();
+            size_of::<I>() + total_bytes.div_ceil(samples.len())
+        };
+
+        // Every leaf has at least one instance, and every other `Cluster` has
+        // at least two children.
+        let num_leaves = cardinality;
+        let num_parents = cardinality.saturating_sub(1);
+        let num_clusters = num_leaves + num_parents;
+
+        // Each child is boxed in the list of its parent, with its pole.
+        let num_children = num_clusters.saturating_sub(1);
+        let clusters = num_clusters * size_of::<C>()
+            + num_parents * size_of::<Children<U, C>>()
+            + num_children * (size_of::<Box<C>>() + size_of::<usize>());
+
+        // While a `Cluster` is split, it holds its indices, a copy of them,
+        // the indices of its children and, for the ordered strategies, the
+        // indices paired with their sort keys. It also holds the distances
+        // from each instance to each pole and to its nearest pole, or the
+        // sort keys.
+        let fan_out = criteria.fan_out().max(2);
+        let build = cardinality * (4 * size_of::<usize>() + (fan_out + 1) * size_of::<U>() + 2 * size_of::<f64>());
+
+        MemoryEstimate {
+            instances: cardinality * instance_bytes,
+            num_clusters,
+            clusters,
+            permutation: cardinality * size_of::<usize>(),
+            build,
+        }
+    }
+}
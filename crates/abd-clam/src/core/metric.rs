@@ -0,0 +1,403 @@
+//! Provides the `Metric` trait for distance functions and their properties.
+
+use core::fmt::Debug;
+
+use std::sync::Arc;
+
+use distances::Number;
+
+use crate::Instance;
+
+/// A distance function along with the properties that CLAM algorithms may use
+/// to adapt their behavior.
+///
+/// A blanket implementation is provided for function pointers of the form
+/// `fn(&T, &T) -> U`, which are assumed to be cheap, symmetric, distance
+/// metrics. Use `FnMetric` to declare different properties for a function
+/// pointer. Any other implementation may be given to a dataset by wrapping it
+/// in a `SharedMetric`.
+pub trait Metric<T, U: Number>: Debug + Send + Sync {
+    /// Calculates the distance between two instances.
+    fn distance(&self, a: &T, b: &T) -> U;
+
+    /// Whether the distance function obeys the triangle inequality.
+    ///
+    /// The clustered search algorithms in `cakes` prune `Cluster`s using the
+    /// triangle inequality. When this returns `false`, they fall back to
+    /// `Linear` search so that results remain exact.
+    fn is_metric(&self) -> bool {
+        true
+    }
+
+    /// Whether the distance function is expensive to calculate.
+    ///
+    /// If so, CLAM will enable more parallelism when calculating distances.
+    fn is_expensive(&self) -> bool {
+        false
+    }
+
+    /// Whether the distance function is symmetric, i.e. `d(x, y) = d(y, x)`.
+    ///
+    /// If not, pairwise distances are calculated in both directions.
+    fn is_symmetric(&self) -> bool {
+        true
+    }
+
+    /// A cheap lower bound on the distance function, if any. See
+    /// `FnMetric::with_lower_bound`.
+    fn lower_bound(&self) -> Option<fn(&T, &T) -> U> {
+        None
+    }
+
+    /// A cheap upper bound on the distance function, if any. See
+    /// `FnMetric::with_upper_bound`.
+    fn upper_bound(&self) -> Option<fn(&T, &T) -> U> {
+        None
+    }
+
+    /// The SIMD kernel which computes the same distance function, if any. See
+    /// `FnMetric::with_kernel`.
+    fn kernel(&self) -> Option<SimdKernel> {
+        None
+    }
+}
+
+/// A distance function over dense vectors of `f32`s or `f64`s with a SIMD
+/// kernel in `distances::simd`.
+///
+/// A `SparseVecDataset` also computes these distances on its borrowed rows,
+/// with the kernels of `SparseRow`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimdKernel {
+    /// The euclidean distance.
+    Euclidean,
+    /// The squared euclidean distance.
+    SquaredEuclidean,
+    /// The cosine distance.
+    Cosine,
+}
+
+impl SimdKernel {
+    /// Computes the distance between two instances with the SIMD kernel.
+    ///
+    /// The distance is computed in the precision of the elements of the
+    /// instances.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the instances are not vectors of `f32`s or `f64`s, or have
+    /// different dimensionalities.
+    pub fn distance<I: Instance, U: Number>(self, a: &I, b: &I) -> Option<U> {
+        if let (Some(a), Some(b)) = (a.as_f32_slice(), b.as_f32_slice()) {
+            (a.len() == b.len()).then(|| {
+                U::from(match self {
+                    Self::Euclidean => distances::simd::euclidean_f32(a, b),
+                    Self::SquaredEuclidean => distances::simd::euclidean_sq_f32(a, b),
+                    Self::Cosine => distances::simd::cosine_f32(a, b),
+                })
+            })
+        } else if let (Some(a), Some(b)) = (a.as_f64_slice(), b.as_f64_slice()) {
+            (a.len() == b.len()).then(|| {
+                U::from(match self {
+                    Self::Euclidean => distances::simd::euclidean_f64(a, b),
+                    Self::SquaredEuclidean => distances::simd::euclidean_sq_f64(a, b),
+                    Self::Cosine => distances::simd::cosine_f64(a, b),
+                })
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl<T, U: Number> Metric<T, U> for fn(&T, &T) -> U {
+    fn distance(&self, a: &T, b: &T) -> U {
+        self(a, b)
+    }
+}
+
+/// A function pointer with explicitly declared `Metric` properties.
+pub struct FnMetric<T, U: Number> {
+    /// The distance function.
+    function: fn(&T, &T) -> U,
+    /// Whether the function obeys the triangle inequality.
+    is_metric: bool,
+    /// Whether the function is expensive to calculate.
+    is_expensive: bool,
+    /// Whether the function is symmetric.
+    is_symmetric: bool,
+    /// A cheap lower bound on the function, if any.
+    lower_bound: Option<fn(&T, &T) -> U>,
+    /// A cheap upper bound on the function, if any.
+    upper_bound: Option<fn(&T, &T) -> U>,
+    /// The SIMD kernel which computes the same function, if any.
+    kernel: Option<SimdKernel>,
+}
+
+impl<T, U: Number> Debug for FnMetric<T, U> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("FnMetric")
+            .field("function", &self.function)
+            .field("is_metric", &self.is_metric)
+            .field("is_expensive", &self.is_expensive)
+            .field("is_symmetric", &self.is_symmetric)
+            .field("lower_bound", &self.lower_bound)
+            .field("upper_bound", &self.upper_bound)
+            .field("kernel", &self.kernel)
+            .finish()
+    }
+}
+
+impl<T, U: Number> Clone for FnMetric<T, U> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, U: Number> Copy for FnMetric<T, U> {}
+
+impl<T, U: Number> FnMetric<T, U> {
+    /// Creates a new `FnMetric` for a cheap, symmetric, distance metric.
+    ///
+    /// # Arguments
+    ///
+    /// * `function` - The distance function.
+    pub const fn new(function: fn(&T, &T) -> U) -> Self {
+        Self {
+            function,
+            is_metric: true,
+            is_expensive: false,
+            is_symmetric: true,
+            lower_bound: None,
+            upper_bound: None,
+            kernel: None,
+        }
+    }
+
+    /// Sets whether the function obeys the triangle inequality.
+    #[must_use]
+    pub const fn with_is_metric(mut self, is_metric: bool) -> Self {
+        self.is_metric = is_metric;
+        self
+    }
+
+    /// Sets whether the function is expensive to calculate.
+    #[must_use]
+    pub const fn with_is_expensive(mut self, is_expensive: bool) -> Self {
+        self.is_expensive = is_expensive;
+        self
+    }
+
+    /// Sets whether the function is symmetric.
+    #[must_use]
+    pub const fn with_is_symmetric(mut self, is_symmetric: bool) -> Self {
+        self.is_symmetric = is_symmetric;
+        self
+    }
+
+    /// Sets a cheap lower bound on the function.
+    ///
+    /// The bound must never exceed the distance between the same instances.
+    /// Before computing the distances from a query to the instances of a leaf,
+    /// the sieve algorithms in `knn` and the clustered algorithm in `rnn` skip
+    /// any instance whose lower bound is already too large for it to be a
+    /// hit. This only pays off for an expensive function with a much cheaper
+    /// bound, e.g. `metrics::histograms::emd`.
+    #[must_use]
+    pub const fn with_lower_bound(mut self, lower_bound: fn(&T, &T) -> U) -> Self {
+        self.lower_bound = Some(lower_bound);
+        self
+    }
+
+    /// Sets a cheap upper bound on the function.
+    ///
+    /// The bound must never be less than the distance between the same
+    /// instances. It tightens the pruning by a lower bound in the `knn` sieve
+    /// algorithms: when a leaf has at least `k` instances, none of its
+    /// instances can be one of the `k` nearest neighbors if its lower bound
+    /// exceeds the `k`-th smallest upper bound in the leaf. The exact distance
+    /// is then computed only for the remaining instances. An upper bound has no
+    /// effect without a lower bound.
+    #[must_use]
+    pub const fn with_upper_bound(mut self, upper_bound: fn(&T, &T) -> U) -> Self {
+        self.upper_bound = Some(upper_bound);
+        self
+    }
+
+    /// Declares that the function computes the same distance as a SIMD
+    /// kernel.
+    ///
+    /// Datasets whose instances are vectors of `f32`s or `f64`s, e.g.
+    /// `Vec<f32>` or `[f64; N]`, then compute every distance with the kernel
+    /// instead of the function, including those computed by `Linear` search
+    /// and in the leaves of the other search algorithms. The function is still
+    /// used for any other instances. See `SimdKernel::distance`.
+    #[must_use]
+    pub const fn with_kernel(mut self, kernel: SimdKernel) -> Self {
+        self.kernel = Some(kernel);
+        self
+    }
+
+    /// Returns the underlying function pointer.
+    #[must_use]
+    pub const fn function(&self) -> fn(&T, &T) -> U {
+        self.function
+    }
+}
+
+impl<T, U: Number> From<fn(&T, &T) -> U> for FnMetric<T, U> {
+    fn from(function: fn(&T, &T) -> U) -> Self {
+        Self::new(function)
+    }
+}
+
+impl<T, U: Number> Metric<T, U> for FnMetric<T, U> {
+    fn distance(&self, a: &T, b: &T) -> U {
+        (self.function)(a, b)
+    }
+
+    fn is_metric(&self) -> bool {
+        self.is_metric
+    }
+
+    fn is_expensive(&self) -> bool {
+        self.is_expensive
+    }
+
+    fn is_symmetric(&self) -> bool {
+        self.is_symmetric
+    }
+
+    fn lower_bound(&self) -> Option<fn(&T, &T) -> U> {
+        self.lower_bound
+    }
+
+    fn upper_bound(&self) -> Option<fn(&T, &T) -> U> {
+        self.upper_bound
+    }
+
+    fn kernel(&self) -> Option<SimdKernel> {
+        self.kernel
+    }
+}
+
+/// A `Metric` which may be cheaply cloned and shared, e.g. between a dataset
+/// and the shards, stores or encodings made from it.
+///
+/// Datasets store their distance functions as `SharedMetric`s, and their
+/// constructors accept anything which converts into one: a function pointer,
+/// a `FnMetric`, or a `SharedMetric` made with `SharedMetric::new` from any
+/// other implementation of `Metric`, e.g. one which holds its own parameters.
+///
+/// If the metric has a `SimdKernel`, distances between vectors of `f32`s or
+/// `f64`s are computed with the kernel instead of the distance function.
+pub struct SharedMetric<T, U: Number>(Shared<T, U>);
+
+/// The distance function of a `SharedMetric`.
+enum Shared<T, U: Number> {
+    /// A function pointer, which need not be `'static`.
+    Fn(FnMetric<T, U>),
+    /// Any other `Metric`.
+    Dyn(Arc<dyn Metric<T, U>>),
+}
+
+impl<T, U: Number> SharedMetric<T, U> {
+    /// Creates a new `SharedMetric` from any `Metric`.
+    ///
+    /// # Arguments
+    ///
+    /// * `metric` - The distance function and its properties.
+    pub fn new<M: Metric<T, U> + 'static>(metric: M) -> Self {
+        Self(Shared::Dyn(Arc::new(metric)))
+    }
+
+    /// Returns the underlying `FnMetric`, if the distance function is a
+    /// function pointer.
+    #[must_use]
+    pub const fn as_fn_metric(&self) -> Option<&FnMetric<T, U>> {
+        match &self.0 {
+            Shared::Fn(metric) => Some(metric),
+            Shared::Dyn(_) => None,
+        }
+    }
+
+    /// Returns the underlying `Metric`.
+    fn inner(&self) -> &dyn Metric<T, U> {
+        match &self.0 {
+            Shared::Fn(metric) => metric,
+            Shared::Dyn(metric) => metric.as_ref(),
+        }
+    }
+}
+
+impl<T, U: Number> Debug for SharedMetric<T, U> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("SharedMetric").field(&self.inner()).finish()
+    }
+}
+
+impl<T, U: Number> Clone for SharedMetric<T, U> {
+    fn clone(&self) -> Self {
+        match &self.0 {
+            Shared::Fn(metric) => Self(Shared::Fn(*metric)),
+            Shared::Dyn(metric) => Self(Shared::Dyn(Arc::clone(metric))),
+        }
+    }
+}
+
+impl<T, U: Number> From<fn(&T, &T) -> U> for SharedMetric<T, U> {
+    fn from(function: fn(&T, &T) -> U) -> Self {
+        Self(Shared::Fn(FnMetric::new(function)))
+    }
+}
+
+impl<T, U: Number> From<FnMetric<T, U>> for SharedMetric<T, U> {
+    fn from(metric: FnMetric<T, U>) -> Self {
+        Self(Shared::Fn(metric))
+    }
+}
+
+impl<T, U: Number> From<Arc<dyn Metric<T, U>>> for SharedMetric<T, U> {
+    fn from(metric: Arc<dyn Metric<T, U>>) -> Self {
+        Self(Shared::Dyn(metric))
+    }
+}
+
+impl<T: Instance, U: Number> Metric<T, U> for SharedMetric<T, U> {
+    fn distance(&self, a: &T, b: &T) -> U {
+        match &self.0 {
+            Shared::Fn(metric) => metric
+                .kernel
+                .and_then(|kernel| kernel.distance(a, b))
+                .unwrap_or_else(|| (metric.function)(a, b)),
+            Shared::Dyn(metric) => metric
+                .kernel()
+                .and_then(|kernel| kernel.distance(a, b))
+                .unwrap_or_else(|| metric.distance(a, b)),
+        }
+    }
+
+    fn is_metric(&self) -> bool {
+        self.inner().is_metric()
+    }
+
+    fn is_expensive(&self) -> bool {
+        self.inner().is_expensive()
+    }
+
+    fn is_symmetric(&self) -> bool {
+        self.inner().is_symmetric()
+    }
+
+    fn lower_bound(&self) -> Option<fn(&T, &T) -> U> {
+        self.inner().lower_bound()
+    }
+
+    fn upper_bound(&self) -> Option<fn(&T, &T) -> U> {
+        self.inner().upper_bound()
+    }
+
+    fn kernel(&self) -> Option<SimdKernel> {
+        self.inner().kernel()
+    }
+}
@@ -1,5 +1,15 @@
 //! Core modules for the crate.
 
+pub mod cancel;
 pub mod cluster;
+pub mod coreset;
 pub mod dataset;
+pub mod dendrogram;
+pub mod error;
+pub mod flat;
+pub mod memory;
+pub mod metric;
+pub mod progress;
+pub mod report;
+pub mod streaming;
 pub mod tree;
@@ -0,0 +1,123 @@
+//! Progress reports from building a `Tree`.
+//!
+//! A `ProgressReporter` is given to the build through
+//! `PartitionCriteria::with_progress`, or by overriding
+//! `PartitionCriterion::progress` in a custom criterion, and receives a
+//! `BuildProgress` each time a `Cluster` is finished.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+
+use distances::Number;
+
+/// A snapshot of the progress of building a `Tree`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BuildProgress {
+    /// The number of `Cluster`s finished so far, i.e. whose subtrees have been
+    /// built.
+    pub clusters_built: usize,
+    /// The number of instances which have been assigned to a leaf.
+    pub instances_assigned: usize,
+    /// The number of instances in the `Cluster` being partitioned, i.e. in
+    /// the whole dataset when building a `Tree`.
+    pub cardinality: usize,
+    /// The time since the build started. This is `None` on `wasm32`, where
+    /// clocks are not available.
+    pub elapsed: Option<Duration>,
+}
+
+impl BuildProgress {
+    /// The fraction of instances which have been assigned to a leaf, between
+    /// `0.0` and `1.0`.
+    #[must_use]
+    pub fn fraction(&self) -> f64 {
+        if self.cardinality == 0 {
+            1.0
+        } else {
+            self.instances_assigned.as_f64() / self.cardinality.as_f64()
+        }
+    }
+
+    /// An estimate of the time until the build finishes.
+    ///
+    /// This assumes that the remaining instances take as long to assign as
+    /// those assigned so far. The deepest subtrees are built last, so this is
+    /// usually an underestimate early in the build.
+    #[must_use]
+    pub fn eta(&self) -> Option<Duration> {
+        let fraction = self.fraction();
+        if fraction <= 0.0 {
+            return None;
+        }
+        self.elapsed
+            .map(|e| Duration::from_secs_f64(e.as_secs_f64() * (1.0 - fraction) / fraction))
+    }
+}
+
+/// Receives reports of the progress of building a `Tree`, e.g. to drive a
+/// progress bar.
+///
+/// Subtrees are built in parallel, so `report` may be called from many
+/// threads at once, and once for every `Cluster` in the tree. It should return
+/// quickly, e.g. by only updating the display every so often.
+pub trait ProgressReporter: Send + Sync {
+    /// Receives the progress after a `Cluster` is finished.
+    fn report(&self, progress: &BuildProgress);
+}
+
+/// Any closure which takes a `BuildProgress` is a reporter.
+impl<F: Fn(&BuildProgress) + Send + Sync> ProgressReporter for F {
+    fn report(&self, progress: &BuildProgress) {
+        self(progress);
+    }
+}
+
+/// Counts the progress of one build and passes it on to a reporter.
+pub struct Tracker<'a> {
+    /// The reporter.
+    reporter: &'a dyn ProgressReporter,
+    /// The number of instances in the `Cluster` being partitioned.
+    cardinality: usize,
+    /// The number of `Cluster`s finished so far.
+    clusters_built: AtomicUsize,
+    /// The number of instances assigned to a leaf so far.
+    instances_assigned: AtomicUsize,
+    /// When the build started.
+    #[cfg(not(target_arch = "wasm32"))]
+    start: Instant,
+}
+
+impl<'a> Tracker<'a> {
+    /// Starts tracking a build of a `Cluster` with `cardinality` instances.
+    pub fn new(reporter: &'a dyn ProgressReporter, cardinality: usize) -> Self {
+        Self {
+            reporter,
+            cardinality,
+            clusters_built: AtomicUsize::new(0),
+            instances_assigned: AtomicUsize::new(0),
+            #[cfg(not(target_arch = "wasm32"))]
+            start: Instant::now(),
+        }
+    }
+
+    /// Records a finished `Cluster`, with the number of its instances which
+    /// were assigned to it as a leaf, and reports the progress.
+    pub fn on_cluster(&self, assigned: usize) {
+        let clusters_built = self.clusters_built.fetch_add(1, Ordering::Relaxed) + 1;
+        let instances_assigned = self.instances_assigned.fetch_add(assigned, Ordering::Relaxed) + assigned;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let elapsed = Some(self.start.elapsed());
+        #[cfg(target_arch = "wasm32")]
+        let elapsed = None;
+
+        self.reporter.report(&BuildProgress {
+            clusters_built,
+            instances_assigned,
+            cardinality: self.cardinality,
+            elapsed,
+        });
+    }
+}
@@ -0,0 +1,190 @@
+//! A structured summary of the shape of a `Tree`, for diagnosing bad trees.
+
+use core::fmt::{Display, Formatter};
+
+use distances::Number;
+use serde::{Deserialize, Serialize};
+
+use crate::{Cluster, Dataset, Instance, Tree};
+
+/// Statistics for a collection of values.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    /// The smallest value.
+    pub min: f64,
+    /// The median value, i.e. the mean of the two middle values if there is an
+    /// even number of values.
+    pub median: f64,
+    /// The mean value.
+    pub mean: f64,
+    /// The largest value.
+    pub max: f64,
+}
+
+impl Summary {
+    /// Summarizes a collection of values, or returns `None` if there are no
+    /// values.
+    fn new(mut values: Vec<f64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(f64::total_cmp);
+
+        let n = values.len();
+        let median = if n % 2 == 0 {
+            (values[n / 2 - 1] + values[n / 2]) / 2.0
+        } else {
+            values[n / 2]
+        };
+        Some(Self {
+            min: values[0],
+            median,
+            mean: values.iter().sum::<f64>() / n.as_f64(),
+            max: values[n - 1],
+        })
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "{:.4} / {:.4} / {:.4} / {:.4}",
+            self.min, self.median, self.mean, self.max
+        )
+    }
+}
+
+/// The `Cluster`s at one depth of a `Tree`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DepthReport {
+    /// The depth.
+    pub depth: usize,
+    /// The number of `Cluster`s at this depth.
+    pub num_clusters: usize,
+    /// The number of leaf `Cluster`s at this depth.
+    pub num_leaves: usize,
+    /// The radii of the `Cluster`s at this depth.
+    pub radius: Summary,
+    /// The local fractal dimensions of the `Cluster`s at this depth.
+    pub lfd: Summary,
+}
+
+/// A summary of the shape of a `Tree`, as returned by `Tree::report`.
+///
+/// It is printed as a table with `Display`, and may be serialized with
+/// `to_json` or any `serde` format.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TreeReport {
+    /// The number of instances in the `Tree`, including removed ones.
+    pub cardinality: usize,
+    /// The number of instances which have been removed but not yet compacted
+    /// away.
+    pub num_removed: usize,
+    /// The depth of the deepest leaf.
+    pub depth: usize,
+    /// The number of `Cluster`s in the `Tree`.
+    pub num_clusters: usize,
+    /// The number of leaf `Cluster`s in the `Tree`.
+    pub num_leaves: usize,
+    /// The cardinalities of the leaf `Cluster`s.
+    pub leaf_cardinality: Summary,
+    /// The `Cluster`s at each depth, from the root down.
+    pub depths: Vec<DepthReport>,
+}
+
+impl TreeReport {
+    /// Serializes the report as pretty-printed JSON.
+    ///
+    /// # Errors
+    ///
+    /// * If the report could not be serialized.
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+
+    /// Deserializes a report from JSON.
+    ///
+    /// # Errors
+    ///
+    /// * If the JSON is not a valid report.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+}
+
+impl Display for TreeReport {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        writeln!(
+            f,
+            "cardinality: {} ({} removed), depth: {}, clusters: {}, leaves: {}",
+            self.cardinality, self.num_removed, self.depth, self.num_clusters, self.num_leaves
+        )?;
+        writeln!(
+            f,
+            "leaf cardinality (min / median / mean / max): {}",
+            self.leaf_cardinality
+        )?;
+        writeln!(
+            f,
+            "{:>5} {:>8} {:>6}  {:<43}  lfd (min / median / mean / max)",
+            "depth", "clusters", "leaves", "radius (min / median / mean / max)"
+        )?;
+        for d in &self.depths {
+            writeln!(
+                f,
+                "{:>5} {:>8} {:>6}  {:<43}  {}",
+                d.depth,
+                d.num_clusters,
+                d.num_leaves,
+                d.radius.to_string(),
+                d.lfd
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
+    /// Summarizes the shape of the `Tree`: the number of `Cluster`s and
+    /// leaves, the distribution of leaf cardinalities, and the radii and local
+    /// fractal dimensions of the `Cluster`s at each depth.
+    pub fn report(&self) -> TreeReport {
+        let clusters = self.root.subtree();
+        let leaves = clusters.iter().filter(|c| c.is_leaf()).collect::<Vec<_>>();
+        let max_depth = clusters.iter().map(|c| c.depth()).max().unwrap_or_default();
+
+        let mut by_depth = vec![Vec::new(); max_depth + 1];
+        for c in &clusters {
+            by_depth[c.depth()].push(*c);
+        }
+        let depths = by_depth
+            .into_iter()
+            .enumerate()
+            .filter_map(|(depth, clusters)| {
+                let radius = Summary::new(clusters.iter().map(|c| c.radius().as_f64()).collect())?;
+                let lfd = Summary::new(clusters.iter().map(|c| c.lfd()).collect())?;
+                Some(DepthReport {
+                    depth,
+                    num_clusters: clusters.len(),
+                    num_leaves: clusters.iter().filter(|c| c.is_leaf()).count(),
+                    radius,
+                    lfd,
+                })
+            })
+            .collect();
+
+        let leaf_cardinality = Summary::new(leaves.iter().map(|c| c.cardinality().as_f64()).collect())
+            .unwrap_or_else(|| unreachable!("A `Tree` has at least one leaf."));
+
+        TreeReport {
+            cardinality: self.cardinality(),
+            num_removed: self.num_removed(),
+            depth: max_depth,
+            num_clusters: clusters.len(),
+            num_leaves: leaves.len(),
+            leaf_cardinality,
+            depths,
+        }
+    }
+}
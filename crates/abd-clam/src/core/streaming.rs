@@ -0,0 +1,557 @@
+//! Building a `Tree` over a dataset which does not fit in memory.
+//!
+//! The instances are read from a source which can be streamed twice, e.g. a
+//! large file read from the start each time.
+//!
+//! 1. The first pass counts the instances and keeps a uniform random sample
+//!    of them. The upper levels of the tree, its skeleton, are built in memory
+//!    from the sample, until each leaf of the skeleton is expected to hold
+//!    few enough instances to fit in memory.
+//! 2. The second pass routes each instance down the skeleton to the child of
+//!    its nearest pole at each level, records its distance from the center of
+//!    each `Cluster` on the way, and appends it to a partition file for its
+//!    leaf of the skeleton.
+//!
+//! Each partition is then read back on its own, partitioned in memory, and
+//! written, in the order of its subtree, to the file of a `LeafStore`. The
+//! subtrees are grafted onto the skeleton, whose radii are those measured in
+//! the second pass, so that every instance is within the radius of each of
+//! its ancestors and the tree may be searched exactly.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use distances::Number;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+#[cfg(feature = "parallel")]
+use crate::par::ThreadPool;
+use crate::{
+    core::{cluster::in_thread_pool, dataset::LeafStoreWriter},
+    par::prelude::*,
+    Cluster, Dataset, Instance, LeafStore, PartitionCriterion, SharedMetric, Tree, UniBall, VecDataset,
+};
+
+/// The default number of instances sampled in the first pass.
+const SAMPLE_SIZE: usize = 100_000;
+
+/// The default maximum expected cardinality of a partition.
+const MAX_PARTITION_CARDINALITY: usize = 1_000_000;
+
+/// The default maximum number of bytes of leaves cached by the `LeafStore`.
+const CACHE_BYTES: usize = 1 << 30;
+
+/// The number of instances routed together in the second pass.
+const CHUNK_SIZE: usize = 4096;
+
+/// The criterion for partitioning the skeleton: a `Cluster` of the sample is
+/// partitioned while its expected cardinality in the full dataset is too large
+/// for a partition.
+struct SkeletonCriterion<'a, P> {
+    /// The cardinality of the sample at or below which a `Cluster` becomes a
+    /// leaf of the skeleton.
+    min_cardinality: usize,
+    /// The criteria of the full tree, whose fan-out, sequential cutoff and
+    /// thread pool are used for the skeleton as well.
+    criteria: &'a P,
+}
+
+impl<U: Number, P: PartitionCriterion<U>> PartitionCriterion<U> for SkeletonCriterion<'_, P> {
+    fn check(&self, c: &UniBall<U>) -> bool {
+        !c.is_singleton() && c.cardinality() > self.min_cardinality
+    }
+
+    fn fan_out(&self) -> usize {
+        self.criteria.fan_out()
+    }
+
+    fn sequential_cutoff(&self) -> usize {
+        self.criteria.sequential_cutoff()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn thread_pool(&self) -> Option<&ThreadPool> {
+        self.criteria.thread_pool()
+    }
+}
+
+/// The position of the instance farthest from the center of each non-leaf
+/// `Cluster` of the skeleton, and its distance from the center, keyed by the
+/// offset and cardinality of the `Cluster` in the sample.
+type Radii<U> = HashMap<(usize, usize), (usize, U)>;
+
+/// The skeleton of a tree, built from a sample of the instances.
+struct Skeleton<I: Instance, U: Number> {
+    /// The tree over the sample.
+    tree: Tree<I, U, VecDataset<I, U, usize>, UniBall<U>>,
+    /// The position, in the source, of each instance in the sample.
+    positions: Vec<usize>,
+}
+
+impl<I: Instance, U: Number> Skeleton<I, U> {
+    /// The position, in the source, of an instance in the sample.
+    fn position_of(&self, index: usize) -> usize {
+        self.positions[self.tree.data().original_index(index)]
+    }
+
+    /// The leaves of the skeleton, in order of their offsets.
+    fn leaves(&self) -> Vec<&UniBall<U>> {
+        let mut leaves = self.tree.leaves();
+        leaves.sort_by_key(|c| c.offset());
+        leaves
+    }
+
+    /// The second pass: routes each instance to a leaf of the skeleton and
+    /// appends it, with its position in the source, to the partition file of
+    /// that leaf.
+    ///
+    /// # Returns
+    ///
+    /// For each non-leaf `Cluster` of the skeleton, keyed by its offset and
+    /// cardinality in the sample, the position of the instance farthest from
+    /// its center, and the distance to that instance.
+    fn distribute<S, P>(
+        &self,
+        source: S,
+        criteria: &P,
+        partitions_dir: &Path,
+        cardinality: usize,
+    ) -> Result<Radii<U>, String>
+    where
+        S: Iterator<Item = Result<I, String>>,
+        P: PartitionCriterion<U>,
+    {
+        let leaves = self
+            .leaves()
+            .into_iter()
+            .enumerate()
+            .map(|(i, c)| ((c.offset(), c.cardinality()), i))
+            .collect::<HashMap<_, _>>();
+        let mut writers = (0..leaves.len())
+            .map(|i| {
+                File::create(partitions_dir.join(i.to_string()))
+                    .map(BufWriter::new)
+                    .map_err(|e| e.to_string())
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut radii = self
+            .tree
+            .root()
+            .subtree()
+            .into_iter()
+            .filter(|c| !c.is_leaf())
+            .map(|c| {
+                (
+                    (c.offset(), c.cardinality()),
+                    (self.position_of(c.arg_center()), U::zero()),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+
+        let data = self.tree.data();
+        let mut source = source.enumerate().peekable();
+        let mut count = 0;
+        while source.peek().is_some() {
+            let chunk = source
+                .by_ref()
+                .take(CHUNK_SIZE)
+                .map(|(i, instance)| instance.map(|instance| (i, instance)))
+                .collect::<Result<Vec<_>, _>>()?;
+            count += chunk.len();
+
+            let routes = in_thread_pool(criteria, || {
+                chunk
+                    .par_iter()
+                    .map(|(_, instance)| {
+                        let mut path = self.tree.root().route(data, instance);
+                        let leaf = path.pop().unwrap_or_else(|| self.tree.root());
+                        let distances = path
+                            .into_iter()
+                            .map(|c| {
+                                (
+                                    (c.offset(), c.cardinality()),
+                                    data.query_to_one(instance, c.arg_center()),
+                                )
+                            })
+                            .collect::<Vec<_>>();
+                        (leaves[&(leaf.offset(), leaf.cardinality())], distances)
+                    })
+                    .collect::<Vec<_>>()
+            });
+
+            for ((i, instance), (leaf, distances)) in chunk.into_iter().zip(routes) {
+                for (key, distance) in distances {
+                    if let Some(radial) = radii.get_mut(&key) {
+                        if distance > radial.1 {
+                            *radial = (i, distance);
+                        }
+                    }
+                }
+                write_record(&mut writers[leaf], i, &instance)?;
+            }
+        }
+
+        if count != cardinality {
+            return Err(format!(
+                "The source yielded {cardinality} instances in the first pass but {count} in the second"
+            ));
+        }
+        for mut writer in writers {
+            writer.flush().map_err(|e| e.to_string())?;
+        }
+
+        Ok(radii)
+    }
+}
+
+/// Builds a `Tree` over a dataset too large to fit in memory, with two
+/// streaming passes over its instances. See the module documentation for
+/// details.
+///
+/// The instances end up on disk in a `LeafStore`, in the order of the tree.
+/// Only the sample, one partition at a time, and the byte offset and original
+/// index of each instance are held in memory.
+///
+/// The leaves of the skeleton are split with `PartitionStrategy::MaxSeparation`
+/// so that the instances may be routed to them. The `criteria` decide how the
+/// partitions below them are split. The local fractal dimensions of the
+/// `Cluster`s in the skeleton are those of the sample.
+///
+/// # Type Parameters
+///
+/// - `I`: The type of the instances.
+/// - `U`: The type of the distance values between instances.
+#[derive(Debug, Clone)]
+pub struct StreamingBuilder<I: Instance, U: Number> {
+    /// The name of the dataset.
+    name: String,
+    /// The metric and its properties.
+    metric: SharedMetric<I, U>,
+    /// The number of instances sampled in the first pass.
+    sample_size: usize,
+    /// The maximum expected cardinality of a partition.
+    max_partition_cardinality: usize,
+    /// The maximum number of bytes of leaves cached by the `LeafStore`.
+    cache_bytes: usize,
+    /// The seed for the random number generator.
+    seed: Option<u64>,
+}
+
+impl<I: Instance, U: Number> StreamingBuilder<I, U> {
+    /// Creates a new `StreamingBuilder` with a sample of 100,000 instances,
+    /// partitions of at most about 1,000,000 instances, and a cache of 1 GiB.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the dataset.
+    /// * `metric`: The metric and its properties.
+    #[must_use]
+    pub fn new(name: String, metric: impl Into<SharedMetric<I, U>>) -> Self {
+        Self {
+            name,
+            metric: metric.into(),
+            sample_size: SAMPLE_SIZE,
+            max_partition_cardinality: MAX_PARTITION_CARDINALITY,
+            cache_bytes: CACHE_BYTES,
+            seed: None,
+        }
+    }
+
+    /// Sets the number of instances sampled in the first pass. A larger
+    /// sample gives a skeleton closer to the tree which would be built in
+    /// memory, but must itself fit in memory.
+    #[must_use]
+    pub const fn with_sample_size(mut self, sample_size: usize) -> Self {
+        self.sample_size = if sample_size < 1 { 1 } else { sample_size };
+        self
+    }
+
+    /// Sets the maximum expected cardinality of a partition. Each partition
+    /// is held in memory while its subtree is built. The actual cardinalities
+    /// vary around this with the sampling.
+    #[must_use]
+    pub const fn with_max_partition_cardinality(mut self, cardinality: usize) -> Self {
+        self.max_partition_cardinality = cardinality;
+        self
+    }
+
+    /// Sets the maximum number of bytes of leaves cached by the `LeafStore`.
+    #[must_use]
+    pub const fn with_cache_bytes(mut self, cache_bytes: usize) -> Self {
+        self.cache_bytes = cache_bytes;
+        self
+    }
+
+    /// Sets the seed for the sampling and the partitioning. Without one, the
+    /// seed of the criteria, if any, is used.
+    #[must_use]
+    pub const fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Builds a `Tree` from the instances of a source.
+    ///
+    /// The partition files are written to a directory next to `path`, with
+    /// the extension `partitions`, and are removed once the tree is built.
+    /// There is one partition file open for each leaf of the skeleton during
+    /// the second pass.
+    ///
+    /// # Arguments
+    ///
+    /// * `source`: Called at the start of each pass, to stream the instances
+    ///   from the beginning. It must yield the same instances in the same
+    ///   order each time.
+    /// * `criteria`: The criteria used to partition the partitions.
+    /// * `path`: The path to the file of the `LeafStore` to create.
+    ///
+    /// # Errors
+    ///
+    /// * If the source, or any instance from it, is an error.
+    /// * If the source yields no instances, or different numbers of instances
+    ///   in the two passes.
+    /// * If any file cannot be written to or read from.
+    #[allow(clippy::type_complexity)]
+    pub fn build<F, S, P>(
+        &self,
+        mut source: F,
+        criteria: &P,
+        path: &Path,
+    ) -> Result<Tree<I, U, LeafStore<I, U>, UniBall<U>>, String>
+    where
+        F: FnMut() -> Result<S, String>,
+        S: Iterator<Item = Result<I, String>>,
+        P: PartitionCriterion<U>,
+    {
+        let seed = self.seed.or_else(|| criteria.rng_seed());
+
+        let (sample, positions, cardinality) = self.sample(source()?, seed)?;
+        if cardinality == 0 {
+            return Err("Cannot build a tree from a source with no instances".to_string());
+        }
+        let skeleton = self.skeleton(sample, positions, cardinality, criteria, seed);
+
+        let mut partitions_dir = path.as_os_str().to_owned();
+        partitions_dir.push(".partitions");
+        let partitions_dir = PathBuf::from(partitions_dir);
+        fs::create_dir_all(&partitions_dir).map_err(|e| e.to_string())?;
+
+        let result = skeleton
+            .distribute(source()?, criteria, &partitions_dir, cardinality)
+            .and_then(|radii| self.assemble(&skeleton, &radii, &partitions_dir, criteria, seed, path));
+        fs::remove_dir_all(&partitions_dir).map_err(|e| e.to_string())?;
+
+        let (root, data) = result?;
+        Ok(Tree::from_root_and_data(root, data))
+    }
+
+    /// The first pass: counts the instances and samples them uniformly at
+    /// random, by reservoir sampling.
+    ///
+    /// # Returns
+    ///
+    /// The sample, the position of each sampled instance in the source, and
+    /// the number of instances.
+    fn sample<S>(&self, source: S, seed: Option<u64>) -> Result<(Vec<I>, Vec<usize>, usize), String>
+    where
+        S: Iterator<Item = Result<I, String>>,
+    {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed.unwrap_or_else(rand::random));
+        let mut sample = Vec::new();
+        let mut positions = Vec::new();
+        let mut cardinality = 0;
+
+        for (i, instance) in source.enumerate() {
+            let instance = instance?;
+            if sample.len() < self.sample_size {
+                sample.push(instance);
+                positions.push(i);
+            } else {
+                let j = rng.gen_range(0..=i);
+                if j < self.sample_size {
+                    sample[j] = instance;
+                    positions[j] = i;
+                }
+            }
+            cardinality = i + 1;
+        }
+
+        Ok((sample, positions, cardinality))
+    }
+
+    /// Builds the skeleton from the sample.
+    fn skeleton<P: PartitionCriterion<U>>(
+        &self,
+        sample: Vec<I>,
+        positions: Vec<usize>,
+        cardinality: usize,
+        criteria: &P,
+        seed: Option<u64>,
+    ) -> Skeleton<I, U> {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let min_cardinality =
+            (self.max_partition_cardinality.as_f64() * sample.len().as_f64() / cardinality.as_f64()) as usize;
+        let skeleton_criterion = SkeletonCriterion {
+            min_cardinality,
+            criteria,
+        };
+
+        let data = VecDataset::from_metric(format!("{}-sample", self.name), sample, self.metric.clone());
+        let tree = Tree::build(data, &skeleton_criterion, seed);
+        Skeleton { tree, positions }
+    }
+
+    /// Builds the subtree of each partition, writes the instances to the
+    /// `LeafStore` in the order of the tree, and grafts the subtrees onto the
+    /// skeleton.
+    fn assemble<P: PartitionCriterion<U>>(
+        &self,
+        skeleton: &Skeleton<I, U>,
+        radii: &Radii<U>,
+        partitions_dir: &Path,
+        criteria: &P,
+        seed: Option<u64>,
+        path: &Path,
+    ) -> Result<(UniBall<U>, LeafStore<I, U>), String> {
+        // The positions of the instances whose indices in the tree are
+        // needed for the centers, radial instances and poles of the skeleton.
+        let mut needed = HashSet::new();
+        for c in skeleton.tree.root().subtree() {
+            if let Some(arg_poles) = c.arg_poles() {
+                needed.insert(skeleton.position_of(c.arg_center()));
+                needed.extend(arg_poles.into_iter().map(|p| skeleton.position_of(p)));
+            }
+        }
+        needed.extend(radii.values().map(|&(i, _)| i));
+
+        let mut writer = LeafStoreWriter::create(path)?;
+        let mut indices = HashMap::new();
+        let mut permutation = Vec::new();
+        let mut blocks = Vec::new();
+        let mut subtrees = Vec::new();
+        for (i, leaf) in skeleton.leaves().into_iter().enumerate() {
+            let (positions, instances) = read_partition(&partitions_dir.join(i.to_string()))?;
+            let mut data =
+                VecDataset::from_metric(format!("{}-partition-{i}", self.name), instances, self.metric.clone());
+            let mut subtree = UniBall::new_subtree(&mut data, criteria, seed, leaf.depth());
+
+            let offset = writer.cardinality();
+            subtree.shift(offset);
+            blocks.extend(
+                subtree
+                    .subtree()
+                    .into_iter()
+                    .filter(|c| c.is_leaf())
+                    .map(Cluster::offset),
+            );
+            for j in 0..data.cardinality() {
+                writer.write(&*data.get(j))?;
+                let position = positions[data.original_index(j)];
+                if needed.contains(&position) {
+                    indices.insert(position, offset + j);
+                }
+                permutation.push(position);
+            }
+            subtrees.push(subtree);
+        }
+
+        let data = writer.finish(
+            self.name.clone(),
+            blocks,
+            self.cache_bytes,
+            self.metric.clone(),
+            Some(permutation),
+        )?;
+        let root = graft(
+            skeleton,
+            skeleton.tree.root(),
+            &mut subtrees.into_iter(),
+            radii,
+            &indices,
+        )?;
+        Ok((root, data))
+    }
+}
+
+/// Replaces the leaves of the skeleton, in order, with the subtrees of the
+/// partitions, and the indices in the sample with those in the tree.
+fn graft<I: Instance, U: Number>(
+    skeleton: &Skeleton<I, U>,
+    c: &UniBall<U>,
+    subtrees: &mut impl Iterator<Item = UniBall<U>>,
+    radii: &Radii<U>,
+    indices: &HashMap<usize, usize>,
+) -> Result<UniBall<U>, String> {
+    let Some(children) = c.children() else {
+        return subtrees
+            .next()
+            .ok_or_else(|| "There are fewer partitions than leaves of the skeleton".to_string());
+    };
+
+    let children = children
+        .into_iter()
+        .map(|child| graft(skeleton, child, subtrees, radii, indices))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let index_of = |position: usize| {
+        indices
+            .get(&position)
+            .copied()
+            .ok_or_else(|| format!("The instance at position {position} was not found in any partition"))
+    };
+    let arg_center = index_of(skeleton.position_of(c.arg_center()))?;
+    let (radial, radius) = radii
+        .get(&(c.offset(), c.cardinality()))
+        .copied()
+        .ok_or_else(|| format!("The radius of {c} was not measured"))?;
+    let arg_poles = c
+        .arg_poles()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|p| index_of(skeleton.position_of(p)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(c.with_children(children, arg_center, (index_of(radial)?, radius), arg_poles))
+}
+
+/// Appends an instance, with its position in the source, to a partition file.
+fn write_record<I: Instance, W: Write>(writer: &mut W, position: usize, instance: &I) -> Result<(), String> {
+    let bytes = instance.to_bytes();
+    writer
+        .write_all(&(position as u64).to_le_bytes())
+        .and_then(|()| writer.write_all(&(bytes.len() as u64).to_le_bytes()))
+        .and_then(|()| writer.write_all(&bytes))
+        .map_err(|e| e.to_string())
+}
+
+/// Reads the instances, and their positions in the source, from a partition
+/// file.
+fn read_partition<I: Instance>(path: &Path) -> Result<(Vec<usize>, Vec<I>), String> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let mut positions = Vec::new();
+    let mut instances = Vec::new();
+
+    let mut header = [0; 16];
+    loop {
+        match reader.read_exact(&mut header) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.to_string()),
+        }
+        let position = <u64 as Number>::from_le_bytes(&header[..8]);
+        let num_bytes = <u64 as Number>::from_le_bytes(&header[8..]);
+        let mut bytes = vec![0; usize::try_from(num_bytes).map_err(|e| e.to_string())?];
+        reader.read_exact(&mut bytes).map_err(|e| e.to_string())?;
+
+        positions.push(usize::try_from(position).map_err(|e| e.to_string())?);
+        instances.push(I::from_bytes(&bytes)?);
+    }
+
+    Ok((positions, instances))
+}
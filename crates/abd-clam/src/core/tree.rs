@@ -2,11 +2,20 @@
 
 use core::marker::PhantomData;
 
-use std::path::Path;
+use std::{
+    borrow::Cow,
+    collections::BTreeSet,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
 
 use distances::Number;
+use serde::{de::DeserializeOwned, Serialize};
 
-use crate::{Cluster, Dataset, Instance, PartitionCriterion};
+use crate::{
+    utils, CancellationToken, Cluster, Dataset, Error, Instance, LeafStore, PartitionCriterion, UniBall, VecDataset,
+};
 
 /// A `Tree` represents a hierarchy of `Cluster`s, i.e. "similar" instances
 /// from a metric-`Space`.
@@ -19,7 +28,7 @@ use crate::{Cluster, Dataset, Instance, PartitionCriterion};
 /// - `T`: The type of the instances in the `Tree`.
 /// - `U`: The type of the distance values between instances.
 /// - `D`: The type of the `Dataset` from which the `Tree` is built.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Tree<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> {
     /// The dataset from which the tree is built.
     pub(crate) data: D,
@@ -27,6 +36,9 @@ pub struct Tree<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> {
     pub(crate) root: C,
     /// The depth of the tree.
     pub(crate) depth: usize,
+    /// The indices of instances which have been removed but are still in the
+    /// dataset.
+    pub(crate) tombstones: BTreeSet<usize>,
     /// To satisfy the `Instance` trait bound.
     _i: PhantomData<I>,
     /// To satisfy the `Number` trait bound.
@@ -39,6 +51,10 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
     ///
     /// # Arguments
     /// dataset: The dataset from which the tree will be built
+    ///
+    /// # Panics
+    ///
+    /// * If the dataset is empty. Use `try_new` to get an error instead.
     pub fn new(data: D, seed: Option<u64>) -> Self {
         let root = C::new_root(&data, seed);
         let depth = root.max_leaf_depth();
@@ -46,11 +62,82 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
             data,
             root,
             depth,
+            tombstones: BTreeSet::new(),
             _i: PhantomData,
             _u: PhantomData,
         }
     }
 
+    /// Constructs a new `Tree` for a given dataset and partitions it with the
+    /// given criteria.
+    ///
+    /// This is the same as `Tree::new` followed by `partition`, except that the
+    /// root is built with the `criteria` as well, e.g. with their
+    /// `sequential_cutoff`, while `Tree::new` uses the defaults.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The dataset from which the tree will be built.
+    /// * `criteria` - The criteria used to decide when to partition a `Cluster`.
+    /// * `seed` - The seed for the random number generator. If `None`, the
+    ///   seed of the `criteria`, if any, is used instead.
+    ///
+    /// If the token of the `criteria` is cancelled, the build stops early and
+    /// the `Tree` is shallower than the criteria ask for. Use `try_build` to
+    /// get an error instead.
+    ///
+    /// # Panics
+    ///
+    /// * If the dataset is empty.
+    pub fn build<P: PartitionCriterion<U>>(data: D, criteria: &P, seed: Option<u64>) -> Self {
+        let seed = seed.or_else(|| criteria.rng_seed());
+        let root = C::new_root_with(&data, criteria, seed);
+        Self::from_root_and_data(root, data).partition(criteria, seed)
+    }
+
+    /// Constructs and partitions a new `Tree`, as `Tree::build`, or returns an
+    /// error instead of panicking or returning a partial `Tree`.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The dataset from which the tree will be built.
+    /// * `criteria` - The criteria used to decide when to partition a `Cluster`.
+    /// * `seed` - The seed for the random number generator. If `None`, the
+    ///   seed of the `criteria`, if any, is used instead.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::EmptyDataset` if the dataset has no instances.
+    /// * `Error::Cancelled` if the token of the `criteria` was cancelled
+    ///   before the build finished.
+    pub fn try_build<P: PartitionCriterion<U>>(data: D, criteria: &P, seed: Option<u64>) -> Result<Self, Error> {
+        if data.cardinality() == 0 {
+            return Err(Error::EmptyDataset);
+        }
+        let tree = Self::build(data, criteria, seed);
+        if criteria.cancellation().is_some_and(CancellationToken::is_cancelled) {
+            return Err(Error::Cancelled);
+        }
+        Ok(tree)
+    }
+
+    /// Constructs a new `Tree` for a given dataset, without partitioning it.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The dataset from which the tree will be built.
+    /// * `seed` - The seed for the random number generator.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::EmptyDataset` if the dataset has no instances.
+    pub fn try_new(data: D, seed: Option<u64>) -> Result<Self, Error> {
+        if data.cardinality() == 0 {
+            return Err(Error::EmptyDataset);
+        }
+        Ok(Self::new(data, seed))
+    }
+
     /// Constructs a new `Tree` from a given root `Cluster` and dataset.
     pub fn from_root_and_data(root: C, data: D) -> Self {
         let depth = root.max_leaf_depth();
@@ -58,6 +145,7 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
             data,
             root,
             depth,
+            tombstones: BTreeSet::new(),
             _i: PhantomData,
             _u: PhantomData,
         }
@@ -65,20 +153,66 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
 
     /// Recursively partitions the root `Cluster` using the given criteria.
     ///
+    /// If no `seed` is given, the seed of the `criteria`, if any, is used
+    /// instead, and an unpartitioned root is rebuilt with it, so that the
+    /// whole tree is reproducible.
+    ///
+    /// The dataset is reordered, depth-first, so that the instances of each
+    /// `Cluster` have contiguous indices. `Dataset::permutation` maps these
+    /// indices to the original indices of the instances and back. To build a
+    /// `Tree` without moving the instances, wrap the dataset in an
+    /// `IndirectDataset`.
+    ///
     /// # Arguments
     ///
     /// * `criteria`: the criteria used to decide when to partition a `Cluster`.
+    /// * `seed`: the seed for the random number generator, if any.
     ///
     /// # Returns
     ///
     /// The `Tree` after partitioning.
     #[must_use]
     pub fn partition<P: PartitionCriterion<U>>(mut self, criteria: &P, seed: Option<u64>) -> Self {
+        if seed.is_none() && criteria.rng_seed().is_some() && self.root.is_leaf() {
+            self.root = C::new_root_with(&self.data, criteria, criteria.rng_seed());
+        }
+        let seed = seed.or_else(|| criteria.rng_seed());
         self.root = self.root.partition(&mut self.data, criteria, seed);
         self.depth = self.root.max_leaf_depth();
         self
     }
 
+    /// Moves the instances of the `Tree` to a file on disk, keeping only the
+    /// `Cluster`s in memory.
+    ///
+    /// The instances are written in the order of the `Tree`, with the
+    /// instances in each leaf stored contiguously. During search, the
+    /// instances in a leaf are read together and kept in an LRU cache of at
+    /// most `cache_bytes`. See `LeafStore` for more details.
+    ///
+    /// This should be called after the `Tree` has been partitioned.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path to the file to create.
+    /// * `cache_bytes`: The maximum number of bytes of leaves to cache.
+    ///
+    /// # Errors
+    ///
+    /// * If the file cannot be written to or opened.
+    pub fn with_leaf_store(self, path: &Path, cache_bytes: usize) -> Result<Tree<I, U, LeafStore<I, U>, C>, String> {
+        let blocks = self.leaves().into_iter().map(Cluster::offset).collect();
+        let data = LeafStore::from_dataset(&self.data, blocks, path, cache_bytes)?;
+        Ok(Tree {
+            data,
+            root: self.root,
+            depth: self.depth,
+            tombstones: self.tombstones,
+            _i: PhantomData,
+            _u: PhantomData,
+        })
+    }
+
     /// Returns the `Cluster` with the given `offset` and `cardinality`.
     ///
     /// # Arguments
@@ -100,6 +234,9 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
     }
 
     /// The cardinality of the `Tree`, i.e. the number of instances in the data.
+    ///
+    /// This includes any instances which have been removed but not yet
+    /// compacted away.
     pub fn cardinality(&self) -> usize {
         self.root.cardinality()
     }
@@ -119,6 +256,80 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
         self.depth
     }
 
+    /// The leaf `Cluster`s of the `Tree`, in the order of their indices.
+    pub fn leaves(&self) -> Vec<&C> {
+        self.root.subtree().into_iter().filter(|c| c.is_leaf()).collect()
+    }
+
+    /// The instance at the center of a `Cluster` in the `Tree`.
+    ///
+    /// # Arguments
+    ///
+    /// * `c` - A `Cluster` in the `Tree`.
+    pub fn center_of(&self, c: &C) -> Cow<'_, I> {
+        self.data.get(c.center_index())
+    }
+
+    /// Removes the instance at the given `index` from the `Tree`.
+    ///
+    /// The removal is lazy: the instance is marked with a tombstone and is
+    /// skipped by all search algorithms, but it remains in the dataset and in
+    /// the `Cluster`s until the `Tree` is compacted.
+    ///
+    /// # Arguments
+    ///
+    /// * `index`: The index of the instance in the dataset, as returned by
+    ///   search.
+    ///
+    /// # Errors
+    ///
+    /// * If `index` is not a valid index into the dataset.
+    /// * If the instance at `index` has already been removed.
+    pub fn remove(&mut self, index: usize) -> Result<(), String> {
+        let cardinality = self.cardinality();
+        if index >= cardinality {
+            return Err(format!(
+                "Invalid index. Expected an index less than {cardinality}, got {index}"
+            ));
+        }
+        if self.tombstones.insert(index) {
+            Ok(())
+        } else {
+            Err(format!("Instance {index} has already been removed"))
+        }
+    }
+
+    /// Whether the instance at the given `index` has been removed.
+    pub fn is_removed(&self, index: usize) -> bool {
+        self.tombstones.contains(&index)
+    }
+
+    /// The number of instances which have been removed but not yet compacted
+    /// away.
+    pub fn num_removed(&self) -> usize {
+        self.tombstones.len()
+    }
+
+    /// The number of removed instances in the given range of the dataset.
+    pub(crate) fn num_removed_in(&self, offset: usize, cardinality: usize) -> usize {
+        if self.tombstones.is_empty() {
+            0
+        } else {
+            self.tombstones.range(offset..(offset + cardinality)).count()
+        }
+    }
+
+    /// The number of instances in the `Cluster` which have not been removed.
+    pub(crate) fn num_live_in(&self, c: &C) -> usize {
+        c.cardinality() - self.num_removed_in(c.offset(), c.cardinality())
+    }
+
+    /// The indices of the instances in the `Cluster` which have not been
+    /// removed.
+    pub(crate) fn live_indices<'a>(&'a self, c: &'a C) -> impl Iterator<Item = usize> + 'a {
+        c.indices().filter(move |&i| !self.is_removed(i))
+    }
+
     /// Saves a tree to a given location
     ///
     /// The path given will point to a newly created folder which will
@@ -130,8 +341,13 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
     /// /user/given/path/
     ///    |- dataset      <-- The serialized dataset.
     ///    |- clusters     <-- Clusters are serialized to a single file.
+    ///    |- permutation  <-- The reordering of the dataset, if any.
+    ///    |- tombstones   <-- The indices of removed instances, if any.
     /// ```
     ///
+    /// The `permutation` allows the `Tree` to be loaded with `load_with_data`
+    /// over the dataset in its original order.
+    ///
     /// # Arguments
     ///
     /// * `path` - The path to save the tree to.
@@ -141,16 +357,26 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
     /// * If `path` does not exist.
     /// * If `path` cannot be written to.
     /// * If there are any serialization errors with the dataset.
-    pub fn save(&self, path: &Path) -> Result<(), String> {
+    ///
+    /// All of these are reported as `Error::Io`.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
         if !path.exists() {
-            return Err("Given path does not exist".to_string());
+            return Err(Error::Io("Given path does not exist".to_string()));
         }
 
         let dataset_path = path.join("dataset");
-        self.data.save(&dataset_path)?;
+        self.data.save(&dataset_path).map_err(Error::Io)?;
 
         let cluster_path = path.join("clusters");
-        self.root.save(&cluster_path)?;
+        self.root.save(&cluster_path).map_err(Error::Io)?;
+
+        if let Some(permutation) = self.data.permuted_indices() {
+            save_bincode(&path.join("permutation"), &permutation)?;
+        }
+
+        if !self.tombstones.is_empty() {
+            save_bincode(&path.join("tombstones"), &self.tombstones)?;
+        }
 
         Ok(())
     }
@@ -176,9 +402,11 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
     /// * If the `path` cannot be read from.
     /// * If there are any deserialization errors with the dataset.
     /// * If there are any deserialization errors with the clusters.
-    pub fn load(path: &Path, metric: fn(&I, &I) -> U, is_expensive: bool) -> Result<Self, String> {
+    ///
+    /// All of these are reported as `Error::Io`.
+    pub fn load(path: &Path, metric: fn(&I, &I) -> U, is_expensive: bool) -> Result<Self, Error> {
         if !path.exists() {
-            return Err("Given path does not exist".to_string());
+            return Err(Error::Io("Given path does not exist".to_string()));
         }
 
         // Aliases to relevant paths
@@ -186,18 +414,265 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
         let dataset_path = path.join("dataset");
 
         if !(cluster_path.exists() && dataset_path.exists()) {
-            return Err("Saved tree is malformed".to_string());
+            return Err(Error::Io("Saved tree is malformed".to_string()));
         }
 
-        let data = D::load(&dataset_path, metric, is_expensive)?;
-        let root = C::load(&cluster_path)?;
+        let data = D::load(&dataset_path, metric, is_expensive).map_err(Error::Io)?;
+        let root = C::load(&cluster_path).map_err(Error::Io)?;
+
+        let tombstones_path = path.join("tombstones");
+        let tombstones = if tombstones_path.exists() {
+            load_bincode(&tombstones_path)?
+        } else {
+            BTreeSet::new()
+        };
 
         Ok(Self {
             data,
             depth: root.max_leaf_depth(),
             root,
+            tombstones,
             _i: PhantomData,
             _u: PhantomData,
         })
     }
+
+    /// Reconstructs a `Tree` from a directory `path` over the given dataset,
+    /// without loading the dataset saved with the `Tree`.
+    ///
+    /// The `data` may be given either in its original order or in the order
+    /// in which it was saved with the `Tree`. In the former case, the saved
+    /// permutation is applied to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to load the tree from.
+    /// * `data` - The dataset over which the tree was built.
+    ///
+    /// # Returns
+    ///
+    /// The reconstructed tree.
+    ///
+    /// # Errors
+    ///
+    /// * If `path` does not exist.
+    /// * If `path` does not contain the saved clusters.
+    /// * If there are any deserialization errors with the clusters.
+    /// * If the cardinality of `data` does not match that of the saved tree.
+    /// * If `data` was reordered differently from the saved dataset.
+    ///
+    /// Errors in reading the saved tree are reported as `Error::Io`, and the
+    /// others as `Error::Other`.
+    pub fn load_with_data(path: &Path, mut data: D) -> Result<Self, Error> {
+        if !path.exists() {
+            return Err(Error::Io("Given path does not exist".to_string()));
+        }
+
+        let cluster_path = path.join("clusters");
+        if !cluster_path.exists() {
+            return Err(Error::Io("Saved tree is malformed".to_string()));
+        }
+
+        let root = C::load(&cluster_path).map_err(Error::Io)?;
+        if root.cardinality() != data.cardinality() {
+            return Err(Error::Other(format!(
+                "Cardinality mismatch. The saved tree has {} instances but the dataset has {}",
+                root.cardinality(),
+                data.cardinality()
+            )));
+        }
+
+        let permutation_path = path.join("permutation");
+        let permutation = if permutation_path.exists() {
+            Some(load_bincode::<Vec<usize>>(&permutation_path)?)
+        } else {
+            None
+        };
+        match (data.permuted_indices(), permutation) {
+            (None, Some(permutation)) => data.permute_instances(&permutation)?,
+            (Some(current), Some(permutation)) if current != permutation.as_slice() => {
+                return Err(Error::Other(
+                    "The dataset was reordered differently from the saved tree".to_string(),
+                ));
+            }
+            (Some(_), None) => {
+                return Err(Error::Other(
+                    "The dataset was reordered but the saved tree was not".to_string(),
+                ));
+            }
+            _ => (),
+        }
+
+        let tombstones_path = path.join("tombstones");
+        let tombstones = if tombstones_path.exists() {
+            load_bincode(&tombstones_path)?
+        } else {
+            BTreeSet::new()
+        };
+
+        Ok(Self {
+            data,
+            depth: root.max_leaf_depth(),
+            root,
+            tombstones,
+            _i: PhantomData,
+            _u: PhantomData,
+        })
+    }
+}
+
+/// Serializes a value to a file at the given `path`.
+///
+/// # Errors
+///
+/// * `Error::Io` if the file cannot be created or written to.
+pub fn save_bincode<T: Serialize>(path: &Path, value: &T) -> Result<(), Error> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    bincode::serialize_into(&mut writer, value).map_err(|e| Error::Io(e.to_string()))
+}
+
+/// Deserializes a value from a file at the given `path`.
+///
+/// # Errors
+///
+/// * `Error::Io` if the file cannot be read or does not hold a `T`.
+pub fn load_bincode<T: DeserializeOwned>(path: &Path) -> Result<T, Error> {
+    let reader = BufReader::new(File::open(path)?);
+    bincode::deserialize_from(reader).map_err(|e| Error::Io(e.to_string()))
+}
+
+impl<I: Instance, U: Number, M: Instance> Tree<I, U, VecDataset<I, U, M>, UniBall<U>> {
+    /// Inserts a new instance into the `Tree` without rebuilding it.
+    ///
+    /// The instance is routed down to the appropriate leaf, the `Cluster`s
+    /// containing it are updated, and the leaf is partitioned if it now meets
+    /// the `criteria`. The original index of the new instance, as reported by
+    /// `Dataset::original_index`, is the cardinality of the `Tree` before the
+    /// insertion.
+    ///
+    /// The local fractal dimensions of the ancestors of the leaf are not
+    /// updated, so a `Tree` which has received many insertions may benefit
+    /// from being rebuilt.
+    ///
+    /// # Arguments
+    ///
+    /// * `instance`: The instance to insert.
+    /// * `metadata`: The metadata of the instance.
+    /// * `criteria`: The criteria used to decide whether to partition the leaf.
+    /// * `seed`: The seed to use if the leaf is partitioned.
+    ///
+    /// # Errors
+    ///
+    /// * If the instance could not be inserted into the dataset.
+    pub fn insert<P: PartitionCriterion<U>>(
+        &mut self,
+        instance: I,
+        metadata: M,
+        criteria: &P,
+        seed: Option<u64>,
+    ) -> Result<(), String> {
+        let seed = seed.or_else(|| criteria.rng_seed());
+        let index = self.root.insertion_index(&self.data, &instance);
+        self.data.insert(index, instance, metadata)?;
+        let reordered = self.root.accommodate_insertion(&mut self.data, index, criteria, seed);
+        self.depth = self.root.max_leaf_depth();
+
+        // Removed instances may have been shifted or reordered.
+        if !self.tombstones.is_empty() {
+            let shift = |i: usize| if i >= index { i + 1 } else { i };
+            let remap = |i: usize| {
+                reordered.as_ref().map_or(i, |(offset, permutation)| {
+                    utils::position_of(permutation, i).map_or(i, |p| offset + p)
+                })
+            };
+            self.tombstones = self.tombstones.iter().map(|&i| remap(shift(i))).collect();
+        }
+
+        Ok(())
+    }
+
+    /// Inserts a batch of new instances into the `Tree`.
+    ///
+    /// The instances are inserted in order, so their original indices follow
+    /// on from the cardinality of the `Tree` before the insertion.
+    ///
+    /// # Arguments
+    ///
+    /// * `instances`: The instances to insert, along with their metadata.
+    /// * `criteria`: The criteria used to decide whether to partition leaves.
+    /// * `seed`: The seed to use if any leaves are partitioned.
+    ///
+    /// # Errors
+    ///
+    /// * See `insert`.
+    pub fn insert_batch<P: PartitionCriterion<U>>(
+        &mut self,
+        instances: Vec<(I, M)>,
+        criteria: &P,
+        seed: Option<u64>,
+    ) -> Result<(), String> {
+        instances
+            .into_iter()
+            .try_for_each(|(instance, metadata)| self.insert(instance, metadata, criteria, seed))
+    }
+
+    /// Compacts the `Tree` by permanently removing instances which were
+    /// removed with `remove`.
+    ///
+    /// Only the affected subtrees are rebuilt. A subtree is affected if the
+    /// fraction of its instances which were removed exceeds the `threshold`,
+    /// if one of its children has had all of its instances removed, or if its
+    /// center, radial instance or poles were removed. Removed instances which
+    /// are not in any affected subtree are kept as tombstones.
+    ///
+    /// Compaction shifts instances in the dataset, so indices returned by
+    /// search before compaction are no longer valid after it.
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold`: The fraction of removed instances, in the range
+    ///   `[0, 1)`, above which a subtree is rebuilt.
+    /// * `criteria`: The criteria used to partition the rebuilt subtrees.
+    /// * `seed`: The seed to use when rebuilding subtrees.
+    ///
+    /// # Errors
+    ///
+    /// * If all instances in the `Tree` have been removed.
+    pub fn compact<P: PartitionCriterion<U>>(
+        &mut self,
+        threshold: f64,
+        criteria: &P,
+        seed: Option<u64>,
+    ) -> Result<(), String> {
+        if self.tombstones.len() == self.cardinality() {
+            return Err("Cannot compact a tree in which all instances have been removed".to_string());
+        }
+
+        let ranges = self.root.compaction_ranges(&self.tombstones, threshold);
+        let removed = ranges
+            .iter()
+            .flat_map(|&(offset, cardinality)| self.tombstones.range(offset..(offset + cardinality)))
+            .copied()
+            .collect::<Vec<_>>();
+        if removed.is_empty() {
+            return Ok(());
+        }
+
+        let seed = seed.or_else(|| criteria.rng_seed());
+        self.data.remove(&removed)?;
+        self.root
+            .accommodate_removal(&mut self.data, &removed, &ranges, criteria, seed);
+        self.depth = self.root.max_leaf_depth();
+
+        // The remaining tombstones are outside the rebuilt subtrees, so they
+        // only need to be shifted.
+        self.tombstones = self
+            .tombstones
+            .iter()
+            .filter(|&i| removed.binary_search(i).is_err())
+            .map(|&i| i - removed.partition_point(|&r| r < i))
+            .collect();
+
+        Ok(())
+    }
 }
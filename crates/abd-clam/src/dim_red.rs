@@ -0,0 +1,304 @@
+//! Dimension reduction by embedding the cluster `Graph` in a few dimensions.
+//!
+//! The `Cluster`s in a `Graph` are placed so that the Euclidean distances
+//! between them approximate the lengths of the shortest paths between them in
+//! the `Graph`, where each edge is as long as the distance between the centers
+//! of its `Cluster`s. This is a force-directed layout in which every pair of
+//! `Cluster`s in a `Component` is joined by a spring whose rest length is the
+//! distance between them along the manifold. The positions are found by
+//! stochastic gradient descent on the stress of the springs, as described in
+//! Zheng, Pawar and Goodman, "Graph Drawing by Stochastic Gradient Descent"
+//! (2018). The `Component`s are laid out on their own and then placed side by
+//! side along the first axis.
+//!
+//! Each instance is then placed around the position of the `Cluster` which
+//! contains it, at its distance from the center of the `Cluster`, in a random
+//! direction.
+
+use core::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+
+use distances::Number;
+use ordered_float::OrderedFloat;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    chaoda::{Component, Graph},
+    par::prelude::*,
+    Cluster, Dataset, Instance, Tree,
+};
+
+/// The `offset` and `cardinality` of a `Cluster` in a `Graph`.
+type ClusterKey = (usize, usize);
+
+/// The ratio of the smallest to the largest step size in the last epoch of
+/// the layout.
+const MIN_STEP_RATIO: f32 = 0.1;
+
+/// An embedding of the `Cluster`s in a `Graph` into `DIM` dimensions.
+///
+/// # Type Parameters
+///
+/// - `DIM`: The number of dimensions of the embedding, e.g. 2 or 3 for
+///   visualization.
+#[derive(Debug, Clone)]
+pub struct Embedding<const DIM: usize> {
+    /// The position of each `Cluster` in the `Graph`.
+    positions: BTreeMap<ClusterKey, [f32; DIM]>,
+    /// The seed for placing instances around their `Cluster`s.
+    seed: u64,
+}
+
+impl<const DIM: usize> Embedding<DIM> {
+    /// Embeds the `Cluster`s in a `Graph`.
+    ///
+    /// # Arguments
+    ///
+    /// * `graph` - The `Graph` to embed.
+    /// * `num_epochs` - The number of passes over all pairs of `Cluster`s in
+    ///   each `Component`. A few dozen are usually enough.
+    /// * `seed` - The seed for the random initial positions and the order of
+    ///   the updates.
+    #[must_use]
+    pub fn new<U: Number>(graph: &Graph<U>, num_epochs: usize, seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(rand::random);
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+        // `Component`s are separated by the longest edge in the `Graph`.
+        let gap = graph
+            .iter_neighbors()
+            .flat_map(|neighbors| neighbors.values().map(|d| d.as_f32()))
+            .fold(0.0, f32::max);
+        let gap = if gap > 0.0 { gap } else { 1.0 };
+
+        let mut positions = BTreeMap::new();
+        let mut shift = 0.0;
+        for component in graph.iter_components() {
+            let keys = component.iter_clusters().copied().collect::<Vec<_>>();
+            let layout = layout::<U, DIM>(component, &keys, num_epochs, &mut rng);
+
+            let (min, max) = layout
+                .iter()
+                .filter_map(|p| p.first().copied())
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), x| {
+                    (min.min(x), max.max(x))
+                });
+            for (key, mut position) in keys.into_iter().zip(layout) {
+                if let Some(x) = position.first_mut() {
+                    *x += shift - min;
+                }
+                positions.insert(key, position);
+            }
+            if min <= max {
+                shift += max - min + gap;
+            }
+        }
+
+        Self { positions, seed }
+    }
+
+    /// Returns the position of a `Cluster`, if it is in the embedded `Graph`.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The `offset` and `cardinality` of the `Cluster`.
+    #[must_use]
+    pub fn position(&self, key: &ClusterKey) -> Option<&[f32; DIM]> {
+        self.positions.get(key)
+    }
+
+    /// Iterates over the `Cluster`s in the embedded `Graph` and their
+    /// positions.
+    pub fn iter_positions(&self) -> impl Iterator<Item = (&ClusterKey, &[f32; DIM])> {
+        self.positions.iter()
+    }
+
+    /// Returns the number of `Cluster`s in the embedding.
+    #[must_use]
+    pub fn cardinality(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Places the instances of a `Tree` around the `Cluster`s which contain
+    /// them.
+    ///
+    /// Each instance is at its distance from the center of its `Cluster`, in a
+    /// random direction, from the position of the `Cluster`.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree` - The `Tree` from which the embedded `Graph` was built.
+    ///
+    /// # Returns
+    ///
+    /// The coordinates of each instance, in the order of the original indices
+    /// of the instances, or `None` for instances which are not in any `Cluster`
+    /// in the `Graph`.
+    #[must_use]
+    pub fn project<I, U, D, C>(&self, tree: &Tree<I, U, D, C>) -> Vec<Option<[f32; DIM]>>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        let data = tree.data();
+
+        let mut seen = BTreeSet::new();
+        let clusters = tree
+            .root()
+            .subtree()
+            .into_iter()
+            .filter_map(|c| {
+                let key = (c.offset(), c.cardinality());
+                self.positions
+                    .get(&key)
+                    .filter(|_| seen.insert(key))
+                    .map(|&position| (c, position))
+            })
+            .collect::<Vec<_>>();
+
+        let radii = clusters
+            .par_iter()
+            .map(|(c, _)| data.one_to_many(c.arg_center(), &c.indices().collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        let mut coordinates = vec![None; data.cardinality()];
+        for ((c, center), radii) in clusters.into_iter().zip(radii) {
+            for (i, r) in c.indices().zip(radii) {
+                let direction = random_direction::<_, DIM>(&mut rng);
+                let mut position = center;
+                for (x, d) in position.iter_mut().zip(direction) {
+                    *x += d * r.as_f32();
+                }
+                coordinates[data.original_index(i)] = Some(position);
+            }
+        }
+        coordinates
+    }
+}
+
+/// Lays out the `Cluster`s in a `Component`, in the order of `keys`.
+fn layout<U: Number, const DIM: usize>(
+    component: &Component<U>,
+    keys: &[ClusterKey],
+    num_epochs: usize,
+    rng: &mut ChaCha8Rng,
+) -> Vec<[f32; DIM]> {
+    let n = keys.len();
+    if n < 2 {
+        return vec![[0.0; DIM]; n];
+    }
+
+    let index_of = keys
+        .iter()
+        .enumerate()
+        .map(|(i, &k)| (k, i))
+        .collect::<BTreeMap<_, _>>();
+    let adjacency = keys
+        .iter()
+        .map(|k| {
+            component.neighbors_of(k).map_or_else(Vec::new, |neighbors| {
+                neighbors
+                    .iter()
+                    .filter_map(|(j, d)| index_of.get(j).map(|&j| (j, d.as_f32())))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect::<Vec<_>>();
+    let targets = (0..n)
+        .into_par_iter()
+        .map(|i| shortest_paths(&adjacency, i))
+        .collect::<Vec<_>>();
+
+    let mut pairs = (0..n)
+        .flat_map(|i| ((i + 1)..n).map(move |j| (i, j)))
+        .filter(|&(i, j)| targets[i][j].is_finite())
+        .collect::<Vec<_>>();
+    let (min_target, max_target) = pairs
+        .iter()
+        .map(|&(i, j)| targets[i][j])
+        .filter(|&d| d > 0.0)
+        .fold((f32::INFINITY, 0.0_f32), |(min, max), d| (min.min(d), max.max(d)));
+    if max_target <= 0.0 {
+        return vec![[0.0; DIM]; n];
+    }
+
+    let mut positions = (0..n)
+        .map(|_| core::array::from_fn(|_| rng.gen_range(-0.5..0.5) * max_target))
+        .collect::<Vec<[f32; DIM]>>();
+
+    // The step size decays exponentially, from one which moves the clusters
+    // in the longest springs all the way to their rest length, to one which
+    // only moves those in the shortest springs a little.
+    let max_step = max_target.powi(2);
+    let min_step = MIN_STEP_RATIO * min_target.powi(2);
+    let decay = if num_epochs > 1 {
+        (max_step / min_step).ln() / (num_epochs - 1).as_f32()
+    } else {
+        0.0
+    };
+
+    for epoch in 0..num_epochs {
+        let step = max_step * (-decay * epoch.as_f32()).exp();
+        pairs.shuffle(rng);
+        for &(i, j) in &pairs {
+            let target = targets[i][j];
+            let difference: [f32; DIM] = core::array::from_fn(|k| positions[i][k] - positions[j][k]);
+            let distance = difference.iter().map(|x| x * x).sum::<f32>().sqrt();
+            if distance <= 0.0 {
+                continue;
+            }
+
+            let weight = if target > 0.0 {
+                (step / target.powi(2)).min(1.0)
+            } else {
+                1.0
+            };
+            let scale = weight * (distance - target) / (2.0 * distance);
+            for (k, x) in difference.iter().enumerate() {
+                positions[i][k] -= scale * x;
+                positions[j][k] += scale * x;
+            }
+        }
+    }
+
+    positions
+}
+
+/// Computes the lengths of the shortest paths from one vertex to all others in
+/// a weighted graph, with `f32::INFINITY` for unreachable vertices.
+fn shortest_paths(adjacency: &[Vec<(usize, f32)>], source: usize) -> Vec<f32> {
+    let mut distances = vec![f32::INFINITY; adjacency.len()];
+    distances[source] = 0.0;
+
+    let mut frontier = BinaryHeap::from([Reverse((OrderedFloat(0.0), source))]);
+    while let Some(Reverse((OrderedFloat(d), i))) = frontier.pop() {
+        if d > distances[i] {
+            continue;
+        }
+        for &(j, w) in &adjacency[i] {
+            if d + w < distances[j] {
+                distances[j] = d + w;
+                frontier.push(Reverse((OrderedFloat(d + w), j)));
+            }
+        }
+    }
+
+    distances
+}
+
+/// Returns a uniformly random unit vector.
+fn random_direction<R: Rng, const DIM: usize>(rng: &mut R) -> [f32; DIM] {
+    // Points drawn uniformly from the unit ball are projected onto its surface.
+    for _ in 0..64 {
+        let point: [f32; DIM] = core::array::from_fn(|_| rng.gen_range(-1.0..1.0));
+        let norm = point.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > f32::EPSILON && norm <= 1.0 {
+            return point.map(|x| x / norm);
+        }
+    }
+    core::array::from_fn(|k| if k == 0 { 1.0 } else { 0.0 })
+}
@@ -0,0 +1,176 @@
+//! Measures of the quality of search results against ground truth.
+//!
+//! Results and ground truth are `(index, distance)` pairs, as returned by the
+//! searches in `cakes`. The ground truth for a query is the list of its true
+//! `k` nearest neighbors, e.g. from a `Linear` search or an `AnnBenchmark`.
+//!
+//! When several instances are at the same distance from a query, any of them
+//! is a correct answer. These measures therefore compare distances as well as
+//! indices: a hit is a true neighbor if it is in the ground truth or if it is
+//! no farther than the farthest true neighbor. Distances are compared with a
+//! small relative tolerance, because the ground truth may have been computed
+//! by a different implementation of the metric.
+
+use core::cmp::Ordering;
+
+use std::collections::HashSet;
+
+use distances::Number;
+
+/// The relative tolerance within which a distance is considered equal to a
+/// threshold.
+const TOLERANCE: f64 = 1e-4;
+
+/// Whether a distance is at most a threshold, up to `TOLERANCE`.
+fn within<U: Number>(distance: U, threshold: U) -> bool {
+    let threshold = threshold.as_f64();
+    distance.as_f64() <= threshold.abs().mul_add(TOLERANCE, threshold)
+}
+
+/// Returns the hits with distinct indices, sorted by increasing distance.
+fn sorted_distinct<U: Number>(hits: &[(usize, U)]) -> Vec<(usize, U)> {
+    let mut seen = HashSet::new();
+    let mut hits = hits
+        .iter()
+        .copied()
+        .filter(|&(i, _)| seen.insert(i))
+        .collect::<Vec<_>>();
+    hits.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Greater));
+    hits
+}
+
+/// Computes the recall of search results against the true `k` nearest
+/// neighbors, where `k` is the length of the ground truth.
+///
+/// Results which tie with the farthest true neighbor count as true neighbors,
+/// duplicate indices count once, and at most `k` results count at all.
+///
+/// # Arguments
+///
+/// * `results`: The hits of a search, in any order.
+/// * `ground_truth`: The true `k` nearest neighbors, in any order.
+///
+/// # Returns
+///
+/// The fraction of the true neighbors found, in `[0, 1]`. This is `1` if the
+/// ground truth is empty.
+#[must_use]
+pub fn recall_at_k<U: Number>(results: &[(usize, U)], ground_truth: &[(usize, U)]) -> f64 {
+    let ground_truth = sorted_distinct(ground_truth);
+    let k = ground_truth.len();
+    let Some(&(_, threshold)) = ground_truth.last() else {
+        return 1.0;
+    };
+    let true_indices = ground_truth.iter().map(|&(i, _)| i).collect::<HashSet<_>>();
+
+    let found = sorted_distinct(results)
+        .into_iter()
+        .filter(|&(i, d)| true_indices.contains(&i) || within(d, threshold))
+        .count();
+
+    found.min(k).as_f64() / k.as_f64()
+}
+
+/// Computes the reciprocal of the rank of the first true nearest neighbor in
+/// ranked search results.
+///
+/// A result is the true nearest neighbor if it is the nearest instance in the
+/// ground truth, or if it ties with that instance.
+///
+/// # Arguments
+///
+/// * `results`: The hits of a search, in order of rank. Those of the searches
+///   in `cakes` are not ranked, and should be sorted by distance or by some
+///   other score first.
+/// * `ground_truth`: The true nearest neighbors, in any order.
+///
+/// # Returns
+///
+/// `1 / r`, where `r` is the 1-based rank of the first true nearest neighbor
+/// in the results, or `0` if there is none. This is `1` if the ground truth is
+/// empty.
+#[must_use]
+pub fn reciprocal_rank<U: Number>(results: &[(usize, U)], ground_truth: &[(usize, U)]) -> f64 {
+    let Some(&(nearest, threshold)) = sorted_distinct(ground_truth).first() else {
+        return 1.0;
+    };
+
+    results
+        .iter()
+        .position(|&(i, d)| i == nearest || within(d, threshold))
+        .map_or(0.0, |r| (r + 1).as_f64().recip())
+}
+
+/// Computes the mean of `reciprocal_rank` over a batch of queries.
+///
+/// # Arguments
+///
+/// * `results`: The ranked hits of a search for each query.
+/// * `ground_truth`: The true nearest neighbors of each query.
+///
+/// # Errors
+///
+/// * If there are no queries.
+/// * If `results` and `ground_truth` have different lengths.
+pub fn mean_reciprocal_rank<U: Number>(
+    results: &[Vec<(usize, U)>],
+    ground_truth: &[Vec<(usize, U)>],
+) -> Result<f64, String> {
+    if results.len() != ground_truth.len() {
+        return Err(format!(
+            "Results for {} queries cannot be compared with ground truth for {} queries",
+            results.len(),
+            ground_truth.len()
+        ));
+    }
+    if results.is_empty() {
+        return Err("Cannot compute the mean reciprocal rank of no queries".to_string());
+    }
+
+    let total = results
+        .iter()
+        .zip(ground_truth)
+        .map(|(r, g)| reciprocal_rank(r, g))
+        .sum::<f64>();
+    Ok(total / results.len().as_f64())
+}
+
+/// Computes the ratio of the distances to the hits of a search to those to
+/// the true nearest neighbors.
+///
+/// Both are sorted by distance, and the sum of the distances to the nearest
+/// `k` distinct results is divided by the sum of the distances to the same
+/// number of true neighbors, where `k` is the length of the ground truth. If
+/// there are fewer than `k` results, only that many true neighbors are used.
+///
+/// # Arguments
+///
+/// * `results`: The hits of a search, in any order.
+/// * `ground_truth`: The true `k` nearest neighbors, in any order.
+///
+/// # Returns
+///
+/// The ratio, which is at least `1` for correct distances and is `1` exactly
+/// when the results are as near as the true neighbors. This is `1` if either
+/// list is empty, and infinite if the true neighbors are all at distance `0`
+/// but the results are not.
+#[must_use]
+pub fn distance_ratio<U: Number>(results: &[(usize, U)], ground_truth: &[(usize, U)]) -> f64 {
+    let results = sorted_distinct(results);
+    let ground_truth = sorted_distinct(ground_truth);
+
+    let (found, expected) = results
+        .iter()
+        .zip(ground_truth.iter())
+        .fold((0.0, 0.0), |(f, e), (&(_, r), &(_, g))| {
+            (f + r.as_f64(), e + g.as_f64())
+        });
+
+    if expected > 0.0 {
+        found / expected
+    } else if found > 0.0 {
+        f64::INFINITY
+    } else {
+        1.0
+    }
+}
@@ -0,0 +1,333 @@
+//! A C interface for building and searching with `Cakes`.
+//!
+//! These are compiled with the `ffi` feature, and declared for C and C++ in
+//! `include/abd_clam.h`. A shared library may be built with, e.g.,
+//! `cargo rustc --release --features ffi --crate-type cdylib`.
+//!
+//! Instances are the rows of a flat, row-major buffer of `float`. The indices
+//! of hits are those of rows in that buffer.
+//!
+//! Functions which can fail return a `ClamStatus`, write their results through
+//! a pointer passed as their last argument, and never unwind into the caller.
+//! The reason for a failure is available from `clam_last_error` on the same
+//! thread. Every `ClamIndex` and `ClamHits` returned must be freed with
+//! `clam_free` and `clam_hits_free` respectively.
+
+use core::{cell::RefCell, ffi::c_char, panic::AssertUnwindSafe, ptr};
+use std::{
+    ffi::{CStr, CString},
+    panic,
+};
+
+use crate::{knn, metrics::vectors::dense_metric, rnn, Cakes, PartitionCriteria, VecDataset};
+
+/// The `Cakes` type behind a `ClamIndex`.
+type F32Cakes = Cakes<Vec<f32>, f32, VecDataset<Vec<f32>, f32, usize>>;
+
+thread_local! {
+    /// The message of the last error on this thread.
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// The outcome of a call.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClamStatus {
+    /// The call succeeded.
+    Ok = 0,
+    /// An argument was null or invalid, e.g. the name of an unknown metric.
+    InvalidArgument = 1,
+    /// The library panicked. Any index passed to the call may only be freed.
+    Panic = 2,
+}
+
+/// Records an error message and returns its status.
+fn fail(status: ClamStatus, message: &str) -> ClamStatus {
+    // Interior nul bytes cannot be represented, so they are dropped.
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+    status
+}
+
+/// Runs the body of a function, so that errors and panics are recorded for
+/// `clam_last_error` and returned as a status instead of unwinding into C.
+fn guard<F: FnOnce() -> Result<(), String>>(body: F) -> ClamStatus {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => ClamStatus::Ok,
+        Ok(Err(message)) => fail(ClamStatus::InvalidArgument, &message),
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|m| (*m).to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            fail(ClamStatus::Panic, &format!("Panicked: {message}"))
+        }
+    }
+}
+
+/// Checks that an output pointer is not null, and clears what it points to.
+///
+/// # Safety
+///
+/// `out` must be null or point to a writable pointer.
+unsafe fn output<'a, T>(out: *mut *mut T) -> Result<&'a mut *mut T, String> {
+    let out = out.as_mut().ok_or("The output pointer is null")?;
+    *out = ptr::null_mut();
+    Ok(out)
+}
+
+/// Reads an optional C string argument.
+///
+/// # Safety
+///
+/// `s` must be null or a valid nul-terminated string.
+unsafe fn read_str(s: *const c_char, default: &str) -> Result<&str, String> {
+    if s.is_null() {
+        Ok(default)
+    } else {
+        CStr::from_ptr(s).to_str().map_err(|e| e.to_string())
+    }
+}
+
+/// An opaque search index over the rows of a buffer.
+pub struct ClamIndex {
+    /// The index.
+    cakes: F32Cakes,
+    /// The dimensionality of the instances.
+    dim: usize,
+}
+
+impl ClamIndex {
+    /// Reads a query, checking that it is not null.
+    ///
+    /// # Safety
+    ///
+    /// `query` must be null or point to `dim` readable floats.
+    unsafe fn query(&self, query: *const f32) -> Result<Vec<f32>, String> {
+        if query.is_null() {
+            Err("The query is null".to_string())
+        } else {
+            Ok(core::slice::from_raw_parts(query, self.dim).to_vec())
+        }
+    }
+
+    /// Maps hits to the indices of rows in the buffer used to build the index,
+    /// sorts them by increasing distance, and moves them to the heap.
+    fn hits(&self, hits: Vec<(usize, f32)>) -> *mut ClamHits {
+        let mut hits = hits
+            .into_iter()
+            .map(|(i, d)| (self.cakes.original_index(i), d))
+            .collect::<Vec<_>>();
+        hits.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        let (indices, distances): (Vec<_>, Vec<_>) = hits.into_iter().unzip();
+        let len = indices.len();
+        Box::into_raw(Box::new(ClamHits {
+            indices: Box::into_raw(indices.into_boxed_slice()).cast(),
+            distances: Box::into_raw(distances.into_boxed_slice()).cast(),
+            len,
+        }))
+    }
+}
+
+/// The hits of a search, sorted by increasing distance.
+#[repr(C)]
+pub struct ClamHits {
+    /// The indices of the hits.
+    pub indices: *mut usize,
+    /// The distances from the query to the hits.
+    pub distances: *mut f32,
+    /// The number of hits.
+    pub len: usize,
+}
+
+/// Returns the message of the last error on the calling thread, or null if
+/// there has been none.
+///
+/// The message is owned by the library and remains valid until the next call
+/// which fails on the same thread.
+#[no_mangle]
+pub extern "C" fn clam_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
+
+/// Builds an index over the rows of a buffer.
+///
+/// The rows are copied, so the buffer may be freed after this returns.
+///
+/// # Arguments
+///
+/// * `data`: A row-major buffer of `cardinality * dim` floats.
+/// * `cardinality`: The number of rows.
+/// * `dim`: The number of columns.
+/// * `metric`: One of `"euclidean"`, `"manhattan"` or `"cosine"`, or null
+///   for `"euclidean"`.
+/// * `seed`: A pointer to the seed for building the tree, or null for a
+///   random seed.
+/// * `min_cardinality`: Clusters with fewer instances are not partitioned.
+/// * `out`: Where to write the index, or null on failure.
+///
+/// # Safety
+///
+/// * `data` must point to `cardinality * dim` readable floats.
+/// * `metric` must be null or a valid nul-terminated string.
+/// * `seed` must be null or point to a readable `uint64_t`.
+/// * `out` must point to a writable `ClamIndex *`.
+#[no_mangle]
+pub unsafe extern "C" fn clam_build(
+    data: *const f32,
+    cardinality: usize,
+    dim: usize,
+    metric: *const c_char,
+    seed: *const u64,
+    min_cardinality: usize,
+    out: *mut *mut ClamIndex,
+) -> ClamStatus {
+    guard(|| {
+        let out = output(out)?;
+        if data.is_null() {
+            return Err("The data buffer is null".to_string());
+        }
+        if cardinality == 0 || dim == 0 {
+            return Err("Cannot build a tree on an empty buffer".to_string());
+        }
+        let len = cardinality.checked_mul(dim).ok_or("The buffer is too large")?;
+        let metric = read_str(metric, "euclidean").and_then(dense_metric)?;
+        let seed = seed.as_ref().copied();
+
+        let rows = core::slice::from_raw_parts(data, len)
+            .chunks_exact(dim)
+            .map(<[f32]>::to_vec)
+            .collect();
+        let data = VecDataset::from_metric("ffi".to_string(), rows, metric);
+        let criteria = PartitionCriteria::new(true).with_min_cardinality(min_cardinality);
+        let cakes = Cakes::new(data, seed, &criteria);
+
+        *out = Box::into_raw(Box::new(ClamIndex { cakes, dim }));
+        Ok(())
+    })
+}
+
+/// Returns the number of instances in an index.
+///
+/// # Safety
+///
+/// `index` must have been returned by `clam_build` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn clam_cardinality(index: *const ClamIndex) -> usize {
+    index.as_ref().map_or(0, |index| index.cakes.total_cardinality())
+}
+
+/// Returns the dimensionality of the instances in an index.
+///
+/// # Safety
+///
+/// `index` must have been returned by `clam_build` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn clam_dim(index: *const ClamIndex) -> usize {
+    index.as_ref().map_or(0, |index| index.dim)
+}
+
+/// Searches for the `k` nearest neighbors of a query.
+///
+/// # Arguments
+///
+/// * `index`: The index to search.
+/// * `query`: A buffer of `clam_dim(index)` floats.
+/// * `k`: The number of neighbors to search for.
+/// * `algorithm`: The name of a `knn::Algorithm`, or null for the default.
+/// * `out`: Where to write the hits, to be freed with `clam_hits_free`, or
+///   null on failure.
+///
+/// # Safety
+///
+/// * `index` must have been returned by `clam_build` and not yet freed.
+/// * `query` must point to `clam_dim(index)` readable floats.
+/// * `algorithm` must be null or a valid nul-terminated string.
+/// * `out` must point to a writable `ClamHits *`.
+#[no_mangle]
+pub unsafe extern "C" fn clam_knn_search(
+    index: *const ClamIndex,
+    query: *const f32,
+    k: usize,
+    algorithm: *const c_char,
+    out: *mut *mut ClamHits,
+) -> ClamStatus {
+    guard(|| {
+        let out = output(out)?;
+        let index = index.as_ref().ok_or("The index is null")?;
+        let algo = read_str(algorithm, knn::Algorithm::default().name()).and_then(knn::Algorithm::from_name)?;
+        let query = index.query(query)?;
+        *out = index.hits(index.cakes.knn_search(&query, k, algo));
+        Ok(())
+    })
+}
+
+/// Searches for all neighbors of a query within a radius.
+///
+/// # Arguments
+///
+/// * `index`: The index to search.
+/// * `query`: A buffer of `clam_dim(index)` floats.
+/// * `radius`: The radius to search within.
+/// * `algorithm`: The name of an `rnn::Algorithm`, or null for the default.
+/// * `out`: Where to write the hits, to be freed with `clam_hits_free`, or
+///   null on failure.
+///
+/// # Safety
+///
+/// * `index` must have been returned by `clam_build` and not yet freed.
+/// * `query` must point to `clam_dim(index)` readable floats.
+/// * `algorithm` must be null or a valid nul-terminated string.
+/// * `out` must point to a writable `ClamHits *`.
+#[no_mangle]
+pub unsafe extern "C" fn clam_rnn_search(
+    index: *const ClamIndex,
+    query: *const f32,
+    radius: f32,
+    algorithm: *const c_char,
+    out: *mut *mut ClamHits,
+) -> ClamStatus {
+    guard(|| {
+        let out = output(out)?;
+        let index = index.as_ref().ok_or("The index is null")?;
+        let algo = read_str(algorithm, rnn::Algorithm::default().name()).and_then(rnn::Algorithm::from_name)?;
+        let query = index.query(query)?;
+        *out = index.hits(index.cakes.rnn_search(&query, radius, algo));
+        Ok(())
+    })
+}
+
+/// Frees the hits of a search. Passing null does nothing.
+///
+/// # Safety
+///
+/// `hits` must be null or have been returned by a search and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn clam_hits_free(hits: *mut ClamHits) -> ClamStatus {
+    guard(|| {
+        if !hits.is_null() {
+            let hits = Box::from_raw(hits);
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(hits.indices, hits.len)));
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(hits.distances, hits.len)));
+        }
+        Ok(())
+    })
+}
+
+/// Frees an index. Passing null does nothing.
+///
+/// # Safety
+///
+/// `index` must be null or have been returned by `clam_build` and not yet
+/// freed.
+#[no_mangle]
+pub unsafe extern "C" fn clam_free(index: *mut ClamIndex) -> ClamStatus {
+    guard(|| {
+        if !index.is_null() {
+            drop(Box::from_raw(index));
+        }
+        Ok(())
+    })
+}
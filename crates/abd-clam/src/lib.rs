@@ -16,21 +16,61 @@
 )]
 #![doc = include_str!("../README.md")]
 
+pub mod bench;
 pub mod cakes;
 pub mod chaoda;
 mod core;
+pub mod dim_red;
+pub mod eval;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod metrics;
+pub mod msa;
 pub mod pancakes;
+mod par;
+pub mod pq;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "serve")]
+pub mod serve;
+#[cfg(feature = "test-utils")]
+pub mod synthetic;
 pub mod utils;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 pub use crate::{
-    cakes::{knn, rnn, Cakes},
+    cakes::{knn, rnn, Cakes, IdCakes, LiveCakes, PayloadCakes, ShardedCakes, SharedCakes},
     // chaoda::graph,
     core::{
-        cluster::{Cluster, MaxDepth, MinCardinality, PartitionCriteria, PartitionCriterion, UniBall},
-        dataset::{Dataset, Instance, VecDataset},
+        cancel::CancellationToken,
+        cluster::{
+            Cluster, MaxDepth, MinCardinality, MinRadius, PartitionCriteria, PartitionCriterion, PartitionStrategy,
+            UniBall,
+        },
+        dataset::{
+            euclidean_i8, BlockKernel, BlockedDataset, Dataset, IndirectDataset, Instance, LeafStore, MmapDataset,
+            NonFinite, NonFinitePolicy, Permutation, QuantizedDataset, ScalarQuantizer, SequenceDataset, SliceDataset,
+            VecDataset, BLOCK_LANES,
+        },
+        dendrogram::{Dendrogram, DendrogramNode},
+        error::Error,
+        flat::Cut,
+        memory::MemoryEstimate,
+        metric::{FnMetric, Metric, SharedMetric, SimdKernel},
+        progress::{BuildProgress, ProgressReporter},
+        report::{DepthReport, Summary, TreeReport},
+        streaming::StreamingBuilder,
         tree::Tree,
     },
 };
 
+#[cfg(feature = "hdf5")]
+pub use crate::core::dataset::AnnBenchmark;
+#[cfg(feature = "arrow")]
+pub use crate::core::dataset::ArrowFloat;
+#[cfg(feature = "parallel")]
+pub use crate::par::{ThreadPool, ThreadPoolBuilder};
+
 /// The current version of the crate.
 pub const VERSION: &str = "0.31.0";
@@ -0,0 +1,271 @@
+//! Distance functions for packed bit vectors.
+//!
+//! A `BitVector` may have any number of bits. Binary hashes of a fixed width
+//! may instead be stored as arrays of words, e.g. `[u64; 4]` for 256-bit
+//! hashes, which a `VecDataset` or `MmapDataset` keeps contiguously in memory
+//! with no allocation per instance. See `pack_hashes` and `hamming_words`.
+//!
+//! Population counts use the `popcnt` instruction on `x86_64` CPUs which
+//! support it, detected at runtime, and the native instructions on other
+//! architectures.
+
+use distances::{
+    number::{Float, UInt},
+    Number,
+};
+
+use crate::{FnMetric, Instance};
+
+/// A vector of bits, packed into 64-bit words.
+///
+/// Distances are computed a word at a time with population counts, instead of
+/// a bit at a time. The bits past `len` in the last word are always zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitVector {
+    /// The packed bits, with bit `i` at position `i % 64` of word `i / 64`.
+    words: Vec<u64>,
+    /// The number of bits in the vector.
+    len: usize,
+}
+
+impl BitVector {
+    /// Creates a new `BitVector` from the given bits.
+    ///
+    /// # Arguments
+    ///
+    /// * `bits` - The bits of the vector.
+    #[must_use]
+    pub fn from_bools(bits: &[bool]) -> Self {
+        let words = bits
+            .chunks(64)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0_u64, |word, (i, &b)| word | (<u64 as From<bool>>::from(b) << i))
+            })
+            .collect();
+        Self { words, len: bits.len() }
+    }
+
+    /// Creates a new `BitVector` from already-packed words.
+    ///
+    /// Any bits past `len` in the last word are cleared.
+    ///
+    /// # Arguments
+    ///
+    /// * `words` - The packed bits, with bit `i` at position `i % 64` of word `i / 64`.
+    /// * `len` - The number of bits in the vector.
+    ///
+    /// # Errors
+    ///
+    /// If the number of words does not match `len`.
+    pub fn from_words(mut words: Vec<u64>, len: usize) -> Result<Self, String> {
+        if words.len() != len.div_ceil(64) {
+            return Err(format!(
+                "Expected {} words for {len} bits, got {}",
+                len.div_ceil(64),
+                words.len()
+            ));
+        }
+
+        if let Some(last) = words.last_mut() {
+            let used = len % 64;
+            if used != 0 {
+                *last &= (1 << used) - 1;
+            }
+        }
+
+        Ok(Self { words, len })
+    }
+
+    /// Returns the packed words of the vector.
+    #[must_use]
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// Returns the number of bits in the vector.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the vector has no bits.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the bit at index `i`, or `None` if `i` is out of bounds.
+    #[must_use]
+    pub fn get(&self, i: usize) -> Option<bool> {
+        (i < self.len).then(|| (self.words[i / 64] >> (i % 64)) & 1 == 1)
+    }
+
+    /// Returns the number of set bits.
+    #[must_use]
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+}
+
+impl Instance for BitVector {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.len.to_le_bytes().to_vec();
+        bytes.extend(self.words.iter().flat_map(|w| w.to_le_bytes()));
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let num_bytes = <usize as Number>::num_bytes();
+        if bytes.len() < num_bytes {
+            return Err(format!("Expected at least {num_bytes} bytes, got {}", bytes.len()));
+        }
+
+        let (len, words) = bytes.split_at(num_bytes);
+        let len = <usize as Number>::from_le_bytes(len);
+        let words = Vec::<u64>::from_bytes(words)?;
+        Self::from_words(words, len)
+    }
+
+    fn type_name() -> String {
+        "BitVector".to_string()
+    }
+}
+
+/// Computes the Hamming distance between two bit vectors.
+///
+/// The Hamming distance is the number of positions at which the bits differ.
+/// The vectors are assumed to have the same length.
+///
+/// This is a metric, so all knn algorithms may be used with it.
+///
+/// # Arguments
+///
+/// * `x` - A bit vector.
+/// * `y` - A bit vector.
+#[must_use]
+pub fn hamming<U: UInt>(x: &BitVector, y: &BitVector) -> U {
+    U::from(xor_count(&x.words, &y.words))
+}
+
+/// Computes the Hamming distance between two binary hashes packed into
+/// arrays of words.
+///
+/// This is a metric, so all knn algorithms may be used with it.
+///
+/// # Arguments
+///
+/// * `x` - A packed hash.
+/// * `y` - A packed hash.
+#[must_use]
+pub fn hamming_words<U: UInt, const N: usize>(x: &[u64; N], y: &[u64; N]) -> U {
+    U::from(xor_count(x, y))
+}
+
+/// Packs binary hashes of `8 * N` bytes each, e.g. as read from a file, into
+/// arrays of words for use with `hamming_words`.
+///
+/// The bytes of each word are little-endian, so bit `i` of a hash is bit
+/// `i % 8` of its byte `i / 8`, as for `BitVector`.
+///
+/// # Arguments
+///
+/// * `bytes` - The hashes, one after another.
+///
+/// # Errors
+///
+/// If the number of bytes is not a multiple of the size of a hash.
+pub fn pack_hashes<const N: usize>(bytes: &[u8]) -> Result<Vec<[u64; N]>, String> {
+    let size = 8 * N;
+    if size == 0 || bytes.len() % size != 0 {
+        return Err(format!(
+            "Expected a multiple of {size} bytes for hashes of {N} words, got {}",
+            bytes.len()
+        ));
+    }
+
+    Ok(bytes
+        .chunks_exact(size)
+        .map(|hash| {
+            let mut words = [0; N];
+            for (w, b) in words.iter_mut().zip(hash.chunks_exact(8)) {
+                *w = <u64 as Number>::from_le_bytes(b);
+            }
+            words
+        })
+        .collect())
+}
+
+/// Counts the bits which differ between two slices of words.
+fn xor_count(x: &[u64], y: &[u64]) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("popcnt") {
+        // SAFETY: The required CPU feature was detected.
+        return unsafe { xor_count_popcnt(x, y) };
+    }
+
+    xor_count_portable(x, y)
+}
+
+/// Counts the bits which differ between two slices of words, with the
+/// `popcnt` instruction.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "popcnt")]
+unsafe fn xor_count_popcnt(x: &[u64], y: &[u64]) -> u32 {
+    xor_count_portable(x, y)
+}
+
+/// Counts the bits which differ between two slices of words, with whichever
+/// instructions the caller is compiled for.
+// This must be inlined into `xor_count_popcnt` for `count_ones` to be compiled
+// to the `popcnt` instruction there.
+#[allow(clippy::inline_always)]
+#[inline(always)]
+fn xor_count_portable(x: &[u64], y: &[u64]) -> u32 {
+    x.iter().zip(y.iter()).map(|(a, b)| (a ^ b).count_ones()).sum()
+}
+
+/// Computes the Jaccard distance between two bit vectors, treated as sets of
+/// the indices of their set bits.
+///
+/// The distance between two vectors with no set bits is `0.0`. The vectors
+/// are assumed to have the same length.
+///
+/// This is a metric, so all knn algorithms may be used with it.
+///
+/// # Arguments
+///
+/// * `x` - A bit vector.
+/// * `y` - A bit vector.
+#[must_use]
+pub fn jaccard<U: Float>(x: &BitVector, y: &BitVector) -> U {
+    let [intersection, union] = x.words.iter().zip(y.words.iter()).fold([0_u32; 2], |[i, u], (a, b)| {
+        [i + (a & b).count_ones(), u + (a | b).count_ones()]
+    });
+
+    if intersection == union {
+        U::zero()
+    } else {
+        U::one() - U::from(intersection) / U::from(union)
+    }
+}
+
+/// Returns the `hamming` distance function, declared as a metric.
+#[must_use]
+pub fn hamming_metric<U: UInt>() -> FnMetric<BitVector, U> {
+    FnMetric::new(hamming)
+}
+
+/// Returns the `hamming_words` distance function, declared as a metric.
+#[must_use]
+pub fn hamming_words_metric<U: UInt, const N: usize>() -> FnMetric<[u64; N], U> {
+    FnMetric::new(hamming_words)
+}
+
+/// Returns the `jaccard` distance function, declared as a metric.
+#[must_use]
+pub fn jaccard_metric<U: Float>() -> FnMetric<BitVector, U> {
+    FnMetric::new(jaccard)
+}
@@ -0,0 +1,187 @@
+//! Great-circle distances between points on the Earth, in meters.
+//!
+//! Points are created from latitudes and longitudes in degrees, which are
+//! checked to be in range, and all distances, radii and hits are in meters.
+//! This keeps the units of a geospatial search in one place, rather than in
+//! every caller.
+
+use distances::{number::Float, Number};
+
+use crate::{
+    cakes::{knn, rnn},
+    Cluster, Dataset, FnMetric, Instance, Tree, VecDataset,
+};
+
+/// The mean radius of the Earth, in meters.
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// A point on the Earth, given by its latitude and longitude.
+///
+/// The angles are kept in radians, along with the cosine of the latitude, so
+/// that `haversine` does not have to recompute them for every pair of points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatLon {
+    /// The latitude, in radians.
+    lat: f64,
+    /// The longitude, in radians.
+    lon: f64,
+    /// The cosine of the latitude.
+    cos_lat: f64,
+}
+
+impl LatLon {
+    /// Creates a new `LatLon` from a latitude and longitude in degrees.
+    ///
+    /// # Arguments
+    ///
+    /// * `lat` - The latitude, in degrees north of the equator.
+    /// * `lon` - The longitude, in degrees east of the prime meridian.
+    ///
+    /// # Errors
+    ///
+    /// * If the latitude is not in `[-90, 90]`.
+    /// * If the longitude is not in `[-180, 180]`.
+    pub fn new(lat: f64, lon: f64) -> Result<Self, String> {
+        if !(-90. ..=90.).contains(&lat) {
+            return Err(format!("Expected a latitude in [-90, 90] degrees, got {lat}"));
+        }
+        if !(-180. ..=180.).contains(&lon) {
+            return Err(format!("Expected a longitude in [-180, 180] degrees, got {lon}"));
+        }
+        Ok(Self::from_parts(lat.to_radians(), lon.to_radians()))
+    }
+
+    /// Creates a `LatLon` from validated angles in radians.
+    fn from_parts(lat: f64, lon: f64) -> Self {
+        Self {
+            lat,
+            lon,
+            cos_lat: lat.cos(),
+        }
+    }
+
+    /// Returns the latitude, in degrees.
+    #[must_use]
+    pub fn lat(&self) -> f64 {
+        self.lat.to_degrees()
+    }
+
+    /// Returns the longitude, in degrees.
+    #[must_use]
+    pub fn lon(&self) -> f64 {
+        self.lon.to_degrees()
+    }
+}
+
+impl Instance for LatLon {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.lat.to_le_bytes().to_vec();
+        bytes.extend(self.lon.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let num_bytes = f64::num_bytes();
+        if bytes.len() != 2 * num_bytes {
+            return Err(format!("Expected {} bytes, got {}", 2 * num_bytes, bytes.len()));
+        }
+        let (lat, lon) = bytes.split_at(num_bytes);
+        let (lat, lon) = (<f64 as Number>::from_le_bytes(lat), <f64 as Number>::from_le_bytes(lon));
+        if !(lat.abs() <= core::f64::consts::FRAC_PI_2 && lon.abs() <= core::f64::consts::PI) {
+            return Err(format!(
+                "Expected a latitude and longitude in radians, got {lat} and {lon}"
+            ));
+        }
+        Ok(Self::from_parts(lat, lon))
+    }
+
+    fn type_name() -> String {
+        "LatLon".to_string()
+    }
+}
+
+/// Computes the great-circle distance between two points, in meters, with the
+/// haversine formula.
+///
+/// The Earth is taken to be a sphere of radius `EARTH_RADIUS`, which is within
+/// about half a percent of the distance on the ellipsoid.
+///
+/// This is a metric, so all knn algorithms may be used with it.
+///
+/// # Arguments
+///
+/// * `x` - A point.
+/// * `y` - A point.
+#[must_use]
+pub fn haversine<U: Float>(x: &LatLon, y: &LatLon) -> U {
+    let sin_lat = ((y.lat - x.lat) / 2.).sin();
+    let sin_lon = ((y.lon - x.lon) / 2.).sin();
+    let h = (x.cos_lat * y.cos_lat).mul_add(sin_lon * sin_lon, sin_lat * sin_lat);
+    U::from(2. * EARTH_RADIUS * h.sqrt().min(1.).asin())
+}
+
+/// Returns the `haversine` distance function, declared as a metric.
+#[must_use]
+pub fn haversine_metric<U: Float>() -> FnMetric<LatLon, U> {
+    FnMetric::new(haversine)
+}
+
+/// Creates a dataset of points from latitudes and longitudes in degrees, with
+/// distances in meters.
+///
+/// # Arguments
+///
+/// * `name` - The name of the dataset.
+/// * `points` - The latitude and longitude of each point, in degrees.
+///
+/// # Errors
+///
+/// * If any point is out of range. See `LatLon::new`.
+pub fn dataset(name: String, points: &[(f64, f64)]) -> Result<VecDataset<LatLon, f64, usize>, String> {
+    let points = points
+        .iter()
+        .map(|&(lat, lon)| LatLon::new(lat, lon))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(VecDataset::from_metric(name, points, haversine_metric()))
+}
+
+/// Finds all points within a distance of a center, with the default `rnn`
+/// algorithm.
+///
+/// # Arguments
+///
+/// * `tree` - The tree of points to search.
+/// * `center` - The center of the search.
+/// * `meters` - The distance to search within, in meters.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of a point and
+/// the second element is its distance from the center, in meters.
+pub fn within<D, C>(tree: &Tree<LatLon, f64, D, C>, center: &LatLon, meters: f64) -> Vec<(usize, f64)>
+where
+    D: Dataset<LatLon, f64>,
+    C: Cluster<f64>,
+{
+    rnn::Algorithm::default().search(center, meters, tree)
+}
+
+/// Finds the `k` points nearest to a point, with the default `knn` algorithm.
+///
+/// # Arguments
+///
+/// * `tree` - The tree of points to search.
+/// * `point` - The point to search around.
+/// * `k` - The number of points to find.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of a point and
+/// the second element is its distance from `point`, in meters.
+pub fn nearest<D, C>(tree: &Tree<LatLon, f64, D, C>, point: &LatLon, k: usize) -> Vec<(usize, f64)>
+where
+    D: Dataset<LatLon, f64>,
+    C: Cluster<f64>,
+{
+    knn::Algorithm::default().search(tree, point, k)
+}
@@ -0,0 +1,211 @@
+//! Distance functions for vectors stored in half precision.
+
+use half::{bf16, f16, slice::HalfBitsSliceExt, slice::HalfFloatSliceExt};
+
+use crate::{FnMetric, Instance};
+
+/// The number of elements widened to `f32` at a time.
+const CHUNK: usize = 64;
+
+/// A 16-bit floating point format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HalfFormat {
+    /// IEEE 754 half precision, with 5 exponent and 10 mantissa bits. This is
+    /// the more precise format for values of moderate magnitude, such as the
+    /// elements of normalized embeddings.
+    F16,
+    /// The "brain" floating point format, with the 8 exponent bits of an `f32`
+    /// and 7 mantissa bits. This has the range of an `f32`, so it never
+    /// overflows.
+    Bf16,
+}
+
+impl HalfFormat {
+    /// Narrows an `f32` to this format, rounding to the nearest value.
+    fn encode(self, v: f32) -> u16 {
+        match self {
+            Self::F16 => f16::from_f32(v).to_bits(),
+            Self::Bf16 => bf16::from_f32(v).to_bits(),
+        }
+    }
+
+    /// Widens the given values of this format into `out`, which must have
+    /// the same length.
+    fn decode_into(self, bits: &[u16], out: &mut [f32]) {
+        match self {
+            // This uses the hardware conversion instructions, if available.
+            Self::F16 => bits.reinterpret_cast::<f16>().convert_to_f32_slice(out),
+            Self::Bf16 => bits.reinterpret_cast::<bf16>().convert_to_f32_slice(out),
+        }
+    }
+
+    /// The byte with which the format is saved.
+    const fn code(self) -> u8 {
+        match self {
+            Self::F16 => 0,
+            Self::Bf16 => 1,
+        }
+    }
+}
+
+/// A vector of 16-bit floats, which take half the memory of an `f32` vector.
+///
+/// The format is chosen when the vector is created, so a dataset may use
+/// either format. Distances are computed in `f32`, widening a chunk of each
+/// vector at a time, so the only loss of precision is in storing the vectors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HalfVec {
+    /// The format of the elements.
+    format: HalfFormat,
+    /// The bits of the elements.
+    bits: Vec<u16>,
+}
+
+impl HalfVec {
+    /// Creates a new `HalfVec`, rounding each value to the nearest value in
+    /// the given format.
+    ///
+    /// # Arguments
+    ///
+    /// * `values` - The elements of the vector.
+    /// * `format` - The format in which to store them.
+    #[must_use]
+    pub fn new(values: &[f32], format: HalfFormat) -> Self {
+        let bits = values.iter().map(|&v| format.encode(v)).collect();
+        Self { format, bits }
+    }
+
+    /// Returns the format of the elements.
+    #[must_use]
+    pub const fn format(&self) -> HalfFormat {
+        self.format
+    }
+
+    /// Returns the bits of the elements.
+    #[must_use]
+    pub fn bits(&self) -> &[u16] {
+        &self.bits
+    }
+
+    /// Returns the number of elements.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.bits.len()
+    }
+
+    /// Returns whether the vector has no elements.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.bits.is_empty()
+    }
+
+    /// Widens the elements to `f32`.
+    #[must_use]
+    pub fn to_f32(&self) -> Vec<f32> {
+        let mut values = vec![0.; self.bits.len()];
+        self.format.decode_into(&self.bits, &mut values);
+        values
+    }
+}
+
+impl Instance for HalfVec {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.format.code()];
+        bytes.extend(self.bits.to_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let (&code, bits) = bytes.split_first().ok_or("Expected at least 1 byte, got 0")?;
+        let format = match code {
+            0 => HalfFormat::F16,
+            1 => HalfFormat::Bf16,
+            _ => return Err(format!("Unknown half-precision format {code}")),
+        };
+        let bits = Vec::<u16>::from_bytes(bits)?;
+        Ok(Self { format, bits })
+    }
+
+    fn type_name() -> String {
+        "HalfVec".to_string()
+    }
+}
+
+/// Widens both vectors a chunk at a time, and calls `f` with each pair of
+/// chunks.
+#[allow(clippy::many_single_char_names)]
+fn for_each_chunk(x: &HalfVec, y: &HalfVec, mut f: impl FnMut(&[f32], &[f32])) {
+    let (mut a, mut b) = ([0.; CHUNK], [0.; CHUNK]);
+    for (cx, cy) in x.bits.chunks(CHUNK).zip(y.bits.chunks(CHUNK)) {
+        let n = cx.len().min(cy.len());
+        x.format.decode_into(&cx[..n], &mut a[..n]);
+        y.format.decode_into(&cy[..n], &mut b[..n]);
+        f(&a[..n], &b[..n]);
+    }
+}
+
+/// Computes the euclidean distance between two half-precision vectors.
+///
+/// The vectors are assumed to have the same length, but need not have the
+/// same format.
+///
+/// This is a metric, so all knn algorithms may be used with it.
+///
+/// # Arguments
+///
+/// * `x` - A half-precision vector.
+/// * `y` - A half-precision vector.
+#[must_use]
+pub fn euclidean(x: &HalfVec, y: &HalfVec) -> f32 {
+    let mut sum = 0.;
+    for_each_chunk(x, y, |a, b| sum += distances::simd::euclidean_sq_f32(a, b));
+    sum.sqrt()
+}
+
+/// Computes the cosine distance between two half-precision vectors.
+///
+/// The distance follows the same rules as `distances::simd::cosine_f32`, e.g.
+/// it is `1.0` if either vector is zero.
+///
+/// This is not a metric because it does not obey the triangle inequality. Only
+/// `Linear` and `Approximate` knn search should be used with it.
+///
+/// # Arguments
+///
+/// * `x` - A half-precision vector.
+/// * `y` - A half-precision vector.
+#[must_use]
+#[allow(clippy::suboptimal_flops)]
+pub fn cosine(x: &HalfVec, y: &HalfVec) -> f32 {
+    let [mut xx, mut yy, mut xy] = [0_f32; 3];
+    for_each_chunk(x, y, |a, b| {
+        for (&p, &q) in a.iter().zip(b.iter()) {
+            xx += p * p;
+            yy += q * q;
+            xy += p * q;
+        }
+    });
+
+    if xx < f32::EPSILON || yy < f32::EPSILON || xy < f32::EPSILON {
+        1.
+    } else {
+        let d = 1. - xy / (xx * yy).sqrt();
+        if d < f32::EPSILON {
+            0.
+        } else {
+            d
+        }
+    }
+}
+
+/// Returns the `euclidean` distance function, declared as a metric.
+#[must_use]
+pub fn euclidean_metric() -> FnMetric<HalfVec, f32> {
+    FnMetric::new(euclidean)
+}
+
+/// Returns the `cosine` distance function, declared as not a metric.
+#[must_use]
+pub fn cosine_metric() -> FnMetric<HalfVec, f32> {
+    FnMetric::new(cosine).with_is_metric(false)
+}
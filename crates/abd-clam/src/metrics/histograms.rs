@@ -0,0 +1,368 @@
+//! The Earth Mover's distance between histograms, with a cheap lower bound.
+
+use distances::{number::Float, Number};
+
+use crate::{FnMetric, Instance};
+
+/// The amount of mass below which a supply, demand or flow is treated as zero.
+const EPSILON: f64 = 1e-12;
+
+/// A histogram, i.e. a distribution of mass over points, which are the
+/// centers of its bins.
+///
+/// This is what is called a signature in the literature on the Earth Mover's
+/// distance: the points may be anywhere, and different histograms may have
+/// different points. The masses are normalized to sum to one, and bins with
+/// no mass are dropped. The center of mass is computed once, when the
+/// histogram is created, for use in `centroid_distance`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// The dimensionality of the points.
+    dim: usize,
+    /// The points, one after another.
+    points: Vec<f32>,
+    /// The mass at each point.
+    masses: Vec<f32>,
+    /// The center of mass, in `f64` so that `centroid_distance` is not
+    /// pushed above `emd` by rounding.
+    centroid: Vec<f64>,
+}
+
+impl Histogram {
+    /// Creates a new `Histogram` with the given masses at the given points.
+    ///
+    /// # Arguments
+    ///
+    /// * `points` - The centers of the bins.
+    /// * `masses` - The mass in each bin.
+    ///
+    /// # Errors
+    ///
+    /// * If there are not as many masses as points.
+    /// * If the points do not all have the same dimensionality.
+    /// * If any mass is negative or not finite, or the total mass is zero.
+    pub fn new(points: Vec<Vec<f32>>, masses: Vec<f32>) -> Result<Self, String> {
+        if points.len() != masses.len() {
+            return Err(format!("Expected {} masses, got {}", points.len(), masses.len()));
+        }
+        let dim = points.first().map_or(0, Vec::len);
+        if let Some((i, p)) = points.iter().enumerate().find(|(_, p)| p.len() != dim) {
+            return Err(format!("Point {i} has dimensionality {}, expected {dim}", p.len()));
+        }
+        if let Some(m) = masses.iter().find(|m| !m.is_finite() || **m < 0.) {
+            return Err(format!("Expected finite, non-negative masses, got {m}"));
+        }
+        let total = masses.iter().map(|&m| m.as_f64()).sum::<f64>();
+        if total <= 0. {
+            return Err("Expected a positive total mass".to_string());
+        }
+
+        let (points, masses): (Vec<_>, Vec<_>) = points
+            .into_iter()
+            .zip(masses)
+            .filter(|&(_, m)| m > 0.)
+            .map(|(p, m)| {
+                #[allow(clippy::cast_possible_truncation)]
+                let m = (m.as_f64() / total) as f32;
+                (p, m)
+            })
+            .unzip();
+        Ok(Self::from_parts(dim, points.concat(), masses))
+    }
+
+    /// Creates a new one-dimensional `Histogram` with bins of unit width, at
+    /// `0, 1, 2, ...`.
+    ///
+    /// # Arguments
+    ///
+    /// * `masses` - The mass in each bin.
+    ///
+    /// # Errors
+    ///
+    /// * See `Histogram::new`.
+    pub fn from_bins(masses: Vec<f32>) -> Result<Self, String> {
+        let points = (0..masses.len()).map(|i| vec![i.as_f32()]).collect();
+        Self::new(points, masses)
+    }
+
+    /// Creates a `Histogram` from validated parts, computing its centroid.
+    ///
+    /// The centroid is weighted by the masses as `transport` sees them, i.e.
+    /// widened to `f64` and divided by their sum.
+    fn from_parts(dim: usize, points: Vec<f32>, masses: Vec<f32>) -> Self {
+        let weights = normalized(&masses);
+        let mut centroid = vec![0.; dim];
+        for (p, &w) in points.chunks_exact(dim.max(1)).zip(weights.iter()) {
+            for (c, &v) in centroid.iter_mut().zip(p.iter()) {
+                *c = w.mul_add(v.as_f64(), *c);
+            }
+        }
+        Self {
+            dim,
+            points,
+            masses,
+            centroid,
+        }
+    }
+
+    /// Returns the number of non-empty bins.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.masses.len()
+    }
+
+    /// Returns whether the histogram has no bins.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.masses.is_empty()
+    }
+
+    /// Returns the dimensionality of the points.
+    #[must_use]
+    pub const fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Returns the center of the bin at index `i`.
+    #[must_use]
+    pub fn point(&self, i: usize) -> &[f32] {
+        &self.points[i * self.dim..(i + 1) * self.dim]
+    }
+
+    /// Returns the normalized mass in each bin.
+    #[must_use]
+    pub fn masses(&self) -> &[f32] {
+        &self.masses
+    }
+
+    /// Returns the center of mass.
+    #[must_use]
+    pub fn centroid(&self) -> &[f64] {
+        &self.centroid
+    }
+}
+
+impl Instance for Histogram {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.dim.to_le_bytes().to_vec();
+        bytes.extend(self.masses.len().to_le_bytes());
+        bytes.extend(self.points.to_bytes());
+        bytes.extend(self.masses.to_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let num_bytes = <usize as Number>::num_bytes();
+        if bytes.len() < 2 * num_bytes {
+            return Err(format!(
+                "Expected at least {} bytes, got {}",
+                2 * num_bytes,
+                bytes.len()
+            ));
+        }
+
+        let (header, bytes) = bytes.split_at(2 * num_bytes);
+        let dim = <usize as Number>::from_le_bytes(&header[..num_bytes]);
+        let len = <usize as Number>::from_le_bytes(&header[num_bytes..]);
+        let expected = (dim + 1) * len * f32::num_bytes();
+        if bytes.len() != expected {
+            return Err(format!(
+                "Expected {expected} bytes after the header, got {}",
+                bytes.len()
+            ));
+        }
+
+        let (points, masses) = bytes.split_at(dim * len * f32::num_bytes());
+        Ok(Self::from_parts(
+            dim,
+            Vec::from_bytes(points)?,
+            Vec::from_bytes(masses)?,
+        ))
+    }
+
+    fn type_name() -> String {
+        "Histogram".to_string()
+    }
+}
+
+/// Computes the Earth Mover's distance between two histograms, with the
+/// euclidean distance between their points as the ground distance.
+///
+/// This is the least total cost of moving the mass of `x` to match `y`, where
+/// moving a unit of mass costs the distance it is moved. It is found by
+/// solving a transportation problem with successive shortest paths, which
+/// takes roughly cubic time in the number of bins, so this is expensive for
+/// histograms with many bins. Use `emd_metric` to pair it with
+/// `centroid_distance` as a lower bound.
+///
+/// Since the masses are normalized, this is the 1-Wasserstein distance, which
+/// is a metric, so all knn algorithms may be used with it.
+///
+/// # Arguments
+///
+/// * `x` - A histogram.
+/// * `y` - A histogram with points of the same dimensionality.
+#[must_use]
+pub fn emd<U: Float>(x: &Histogram, y: &Histogram) -> U {
+    U::from(transport(x, y))
+}
+
+/// Computes the distance between the centers of mass of two histograms.
+///
+/// This is a lower bound on `emd`, and takes time linear in the
+/// dimensionality rather than cubic in the number of bins.
+///
+/// # Arguments
+///
+/// * `x` - A histogram.
+/// * `y` - A histogram with points of the same dimensionality.
+#[must_use]
+pub fn centroid_distance<U: Float>(x: &Histogram, y: &Histogram) -> U {
+    U::from(
+        x.centroid
+            .iter()
+            .zip(y.centroid.iter())
+            .map(|(&p, &q)| (p - q).powi(2))
+            .sum::<f64>()
+            .sqrt(),
+    )
+}
+
+/// Returns the `emd` distance function, declared as an expensive metric with
+/// `centroid_distance` as its lower bound.
+#[must_use]
+pub fn emd_metric<U: Float>() -> FnMetric<Histogram, U> {
+    FnMetric::new(emd)
+        .with_is_expensive(true)
+        .with_lower_bound(centroid_distance)
+}
+
+/// The euclidean distance between two points.
+///
+/// This is computed in `f64`, as is `centroid_distance`, so that rounding does
+/// not push the lower bound above the distance.
+fn ground_distance(a: &[f32], b: &[f32]) -> f64 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(&p, &q)| (p.as_f64() - q.as_f64()).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Widens the masses to `f64` and divides them by their sum, so that the
+/// rounding of the stored `f32` masses does not change the total mass.
+fn normalized(masses: &[f32]) -> Vec<f64> {
+    let total = masses.iter().map(|&m| m.as_f64()).sum::<f64>();
+    masses.iter().map(|&m| m.as_f64() / total).collect()
+}
+
+/// Solves the transportation problem from `x` to `y`, returning the least
+/// total cost.
+///
+/// The bins of `x` are the sources, numbered `0..n`, and the bins of `y` are
+/// the sinks, numbered `n..n + m`. Each step sends as much mass as it can
+/// along a cheapest path in the residual network, which is found with
+/// Dijkstra's algorithm on costs reduced by node potentials. Paths may undo
+/// earlier flows, which is what makes the result optimal.
+#[allow(clippy::many_single_char_names)]
+fn transport(x: &Histogram, y: &Histogram) -> f64 {
+    let (n, m) = (x.len(), y.len());
+    if n == 0 || m == 0 {
+        return 0.;
+    }
+
+    let cost = (0..n)
+        .flat_map(|i| (0..m).map(move |j| ground_distance(x.point(i), y.point(j))))
+        .collect::<Vec<_>>();
+    let mut supply = normalized(&x.masses);
+    let mut demand = normalized(&y.masses);
+    let mut flow = vec![0.; n * m];
+    let mut potential = vec![0.; n + m];
+
+    // The total masses may still differ by rounding, so the smaller is moved.
+    let mut remaining = supply.iter().sum::<f64>().min(demand.iter().sum());
+    loop {
+        if remaining <= EPSILON {
+            break;
+        }
+
+        let mut dist = vec![f64::INFINITY; n + m];
+        let mut prev = vec![usize::MAX; n + m];
+        let mut done = vec![false; n + m];
+        for i in (0..n).filter(|&i| supply[i] > EPSILON) {
+            dist[i] = 0.;
+        }
+
+        let target = loop {
+            let Some(u) = (0..n + m)
+                .filter(|&v| !done[v] && dist[v].is_finite())
+                .min_by(|&a, &b| dist[a].total_cmp(&dist[b]))
+            else {
+                break None;
+            };
+            done[u] = true;
+
+            if u < n {
+                // Mass may be sent from a source to any sink.
+                for j in (0..m).filter(|&j| !done[n + j]) {
+                    let d = dist[u] + cost[u * m + j] + potential[u] - potential[n + j];
+                    if d < dist[n + j] {
+                        dist[n + j] = d;
+                        prev[n + j] = u;
+                    }
+                }
+            } else if demand[u - n] > EPSILON {
+                break Some(u);
+            } else {
+                // Mass already sent to a sink may be sent back to its source.
+                let j = u - n;
+                for i in (0..n).filter(|&i| !done[i] && flow[i * m + j] > EPSILON) {
+                    let d = dist[u] - cost[i * m + j] + potential[u] - potential[i];
+                    if d < dist[i] {
+                        dist[i] = d;
+                        prev[i] = u;
+                    }
+                }
+            }
+        };
+        let Some(target) = target else {
+            break;
+        };
+
+        // Capping the distances at that of the target keeps the reduced costs
+        // of all residual edges non-negative.
+        let cap = dist[target];
+        for (p, &d) in potential.iter_mut().zip(dist.iter()) {
+            *p += d.min(cap);
+        }
+
+        // Find the most mass which may be sent along the path.
+        let mut amount = demand[target - n];
+        let mut v = target;
+        while prev[v] != usize::MAX {
+            let u = prev[v];
+            if u >= n {
+                amount = amount.min(flow[v * m + (u - n)]);
+            }
+            v = u;
+        }
+        let source = v;
+        amount = amount.min(supply[source]);
+
+        let mut v = target;
+        while prev[v] != usize::MAX {
+            let u = prev[v];
+            if u < n {
+                flow[u * m + (v - n)] += amount;
+            } else {
+                flow[v * m + (u - n)] -= amount;
+            }
+            v = u;
+        }
+        supply[source] -= amount;
+        demand[target - n] -= amount;
+        remaining -= amount;
+    }
+
+    flow.iter().zip(cost.iter()).map(|(&f, &c)| f.max(0.) * c).sum()
+}
@@ -0,0 +1,435 @@
+//! Mahalanobis and weighted euclidean distances, by whitening the vectors.
+//!
+//! Both distances are the euclidean distance after a linear transformation of
+//! the vectors. A `Whitening` holds that transformation, as computed once from
+//! a covariance matrix or from weights. Each instance is whitened when it is
+//! created, so that distances cost no more than the euclidean distance, and
+//! queries must be whitened by the same `Whitening`. A `WhitenedDataset` keeps
+//! the `Whitening` with the instances, saves it alongside them, and checks on
+//! loading that it matches their dimensionality.
+
+use core::ops::Index;
+
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Dataset, Error, FnMetric, Instance, SharedMetric, VecDataset};
+
+/// The linear transformation which whitens vectors for a distance.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum Transform {
+    /// Scales each dimension by the square root of its weight.
+    Diagonal(Vec<f64>),
+    /// Solves `L y = x` for `y`, where `L` is the lower-triangular Cholesky
+    /// factor of the covariance matrix, stored by rows.
+    Cholesky(Vec<f64>),
+}
+
+/// A linear transformation of vectors, after which the euclidean distance is
+/// the Mahalanobis or weighted euclidean distance between the original
+/// vectors.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Whitening {
+    /// The dimensionality of the vectors.
+    dim: usize,
+    /// The transformation.
+    transform: Transform,
+}
+
+impl Whitening {
+    /// Creates a `Whitening` for the Mahalanobis distance with the given
+    /// covariance matrix, i.e. `sqrt((x - y)^T S^-1 (x - y))` for a covariance
+    /// matrix `S`.
+    ///
+    /// # Arguments
+    ///
+    /// * `covariance` - The covariance matrix, by rows.
+    ///
+    /// # Errors
+    ///
+    /// * If the matrix is not square, or has any value which is not finite.
+    /// * If the matrix is not symmetric.
+    /// * If the matrix is not positive definite.
+    pub fn mahalanobis(covariance: &[Vec<f64>]) -> Result<Self, String> {
+        let dim = covariance.len();
+        if let Some((i, row)) = covariance.iter().enumerate().find(|(_, row)| row.len() != dim) {
+            return Err(format!(
+                "Row {i} of the covariance matrix has {} values, expected {dim}",
+                row.len()
+            ));
+        }
+        if covariance.iter().flatten().any(|v| !v.is_finite()) {
+            return Err("Expected a covariance matrix with finite values".to_string());
+        }
+        for (i, row) in covariance.iter().enumerate() {
+            for (j, &a) in row.iter().enumerate().take(i) {
+                let b = covariance[j][i];
+                if (a - b).abs() > 1e-9 * a.abs().max(b.abs()).max(1.) {
+                    return Err(format!("The covariance matrix is not symmetric at ({i}, {j})"));
+                }
+            }
+        }
+
+        // The Cholesky-Banachiewicz algorithm, row by row.
+        let mut lower = vec![0.; dim * dim];
+        for i in 0..dim {
+            for j in 0..=i {
+                let dot = (0..j).map(|k| lower[i * dim + k] * lower[j * dim + k]).sum::<f64>();
+                if i == j {
+                    let pivot = covariance[i][i] - dot;
+                    if pivot <= 0. {
+                        return Err("The covariance matrix is not positive definite".to_string());
+                    }
+                    lower[i * dim + i] = pivot.sqrt();
+                } else {
+                    lower[i * dim + j] = (covariance[i][j] - dot) / lower[j * dim + j];
+                }
+            }
+        }
+
+        Ok(Self {
+            dim,
+            transform: Transform::Cholesky(lower),
+        })
+    }
+
+    /// Creates a `Whitening` for the weighted euclidean distance with the
+    /// given weights, i.e. `sqrt(sum(w_i * (x_i - y_i)^2))`.
+    ///
+    /// This is the Mahalanobis distance with a diagonal covariance matrix of
+    /// the reciprocals of the weights, but whitening with it takes linear
+    /// rather than quadratic time.
+    ///
+    /// # Arguments
+    ///
+    /// * `weights` - The weight of each dimension.
+    ///
+    /// # Errors
+    ///
+    /// * If any weight is negative or not finite.
+    pub fn weighted(weights: &[f64]) -> Result<Self, String> {
+        if let Some(w) = weights.iter().find(|w| !w.is_finite() || **w < 0.) {
+            return Err(format!("Expected finite, non-negative weights, got {w}"));
+        }
+        Ok(Self {
+            dim: weights.len(),
+            transform: Transform::Diagonal(weights.iter().map(|w| w.sqrt()).collect()),
+        })
+    }
+
+    /// Returns the dimensionality of the vectors.
+    #[must_use]
+    pub const fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Whitens a vector.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - A vector of the same dimensionality as the `Whitening`.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::DimensionMismatch` if the vector has a different dimensionality.
+    pub fn apply(&self, x: &[f32]) -> Result<Whitened, Error> {
+        if x.len() != self.dim {
+            return Err(Error::DimensionMismatch {
+                expected: self.dim,
+                found: x.len(),
+            });
+        }
+
+        #[allow(clippy::cast_possible_truncation)]
+        let values = match &self.transform {
+            Transform::Diagonal(scales) => x
+                .iter()
+                .zip(scales.iter())
+                .map(|(&v, &s)| (f64::from(v) * s) as f32)
+                .collect(),
+            Transform::Cholesky(lower) => {
+                // Forward substitution.
+                let mut y = vec![0_f64; self.dim];
+                for i in 0..self.dim {
+                    let row = &lower[i * self.dim..(i + 1) * self.dim];
+                    let dot = row[..i].iter().zip(y.iter()).map(|(&l, &v)| l * v).sum::<f64>();
+                    y[i] = (f64::from(x[i]) - dot) / row[i];
+                }
+                y.into_iter().map(|v| v as f32).collect()
+            }
+        };
+        Ok(Whitened(values))
+    }
+
+    /// Checks that the transformation has the size of its dimensionality, and
+    /// may be applied, e.g. after it was deserialized.
+    fn validate(&self) -> Result<(), String> {
+        let (values, expected) = match &self.transform {
+            Transform::Diagonal(scales) => (scales, self.dim),
+            Transform::Cholesky(lower) => (lower, self.dim * self.dim),
+        };
+        if values.len() != expected {
+            return Err(format!(
+                "Expected {expected} values in a whitening of dimensionality {}, got {}",
+                self.dim,
+                values.len()
+            ));
+        }
+        if let Transform::Cholesky(lower) = &self.transform {
+            if (0..self.dim).any(|i| lower[i * self.dim + i] <= 0.) {
+                return Err("Expected a Cholesky factor with a positive diagonal".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A vector which has been whitened by a `Whitening`.
+///
+/// The euclidean distance between two whitened vectors is the Mahalanobis or
+/// weighted euclidean distance between the original vectors, as long as both
+/// were whitened by the same `Whitening`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Whitened(Vec<f32>);
+
+impl Whitened {
+    /// Returns the whitened values.
+    #[must_use]
+    pub fn values(&self) -> &[f32] {
+        &self.0
+    }
+}
+
+impl Instance for Whitened {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.0.to_bytes()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        Vec::from_bytes(bytes).map(Self)
+    }
+
+    fn type_name() -> String {
+        "Whitened".to_string()
+    }
+}
+
+/// Computes the euclidean distance between two whitened vectors, which is the
+/// Mahalanobis or weighted euclidean distance between the original vectors.
+///
+/// This is a metric, so all knn algorithms may be used with it. With some
+/// weights of zero, distinct vectors may be at a distance of zero.
+///
+/// # Arguments
+///
+/// * `x` - A whitened vector.
+/// * `y` - A vector whitened by the same `Whitening`.
+#[must_use]
+pub fn euclidean(x: &Whitened, y: &Whitened) -> f32 {
+    distances::simd::euclidean_f32(&x.0, &y.0)
+}
+
+/// Returns the `euclidean` distance function, declared as a metric.
+#[must_use]
+pub fn euclidean_metric() -> FnMetric<Whitened, f32> {
+    FnMetric::new(euclidean)
+}
+
+/// A `Dataset` of whitened vectors, along with the `Whitening` which whitened
+/// them.
+///
+/// Queries must be whitened with `WhitenedDataset::whiten` before searching.
+///
+/// # Type Parameters
+///
+/// - `M`: The type of the metadata associated with each instance.
+#[derive(Debug, Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct WhitenedDataset<M: Instance = usize> {
+    /// The whitened instances.
+    rows: VecDataset<Whitened, f32, M>,
+    /// The transformation which whitened them.
+    whitening: Whitening,
+}
+
+impl WhitenedDataset<usize> {
+    /// Creates a new dataset, whitening the given vectors.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the dataset.
+    /// * `data`: The vectors, before whitening.
+    /// * `whitening`: The transformation for the distance.
+    ///
+    /// # Errors
+    ///
+    /// * If any vector does not have the dimensionality of the `Whitening`.
+    pub fn new(name: String, data: &[Vec<f32>], whitening: Whitening) -> Result<Self, String> {
+        let data = data
+            .iter()
+            .enumerate()
+            .map(|(i, x)| whitening.apply(x).map_err(|e| format!("Instance {i}: {e}")))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            rows: VecDataset::from_metric(name, data, euclidean_metric()),
+            whitening,
+        })
+    }
+}
+
+impl<M: Instance> WhitenedDataset<M> {
+    /// Assigns metadata to the dataset. See `VecDataset::assign_metadata`.
+    ///
+    /// # Errors
+    ///
+    /// * If the metadata is not the same length as the dataset.
+    pub fn assign_metadata<Mn: Instance>(self, metadata: Vec<Mn>) -> Result<WhitenedDataset<Mn>, String> {
+        Ok(WhitenedDataset {
+            rows: self.rows.assign_metadata(metadata)?,
+            whitening: self.whitening,
+        })
+    }
+
+    /// Returns the transformation which whitened the instances.
+    #[must_use]
+    pub const fn whitening(&self) -> &Whitening {
+        &self.whitening
+    }
+
+    /// Returns the whitened instances.
+    #[must_use]
+    pub const fn rows(&self) -> &VecDataset<Whitened, f32, M> {
+        &self.rows
+    }
+
+    /// Whitens a query, so that it may be searched for in the dataset.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::DimensionMismatch` if the query does not have the dimensionality
+    ///   of the instances.
+    pub fn whiten(&self, query: &[f32]) -> Result<Whitened, Error> {
+        self.whitening.apply(query)
+    }
+
+    /// Returns the path of the file in which the `Whitening` is saved,
+    /// alongside the instances at `path`.
+    fn sidecar_path(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".whitening");
+        PathBuf::from(name)
+    }
+}
+
+impl<M: Instance> Index<usize> for WhitenedDataset<M> {
+    type Output = Whitened;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        self.rows.index(index)
+    }
+}
+
+impl<M: Instance> Dataset<Whitened, f32> for WhitenedDataset<M> {
+    fn clone_with_new_metric(&self, metric: fn(&Whitened, &Whitened) -> f32, is_expensive: bool, name: String) -> Self {
+        Self {
+            rows: self.rows.clone_with_new_metric(metric, is_expensive, name),
+            whitening: self.whitening.clone(),
+        }
+    }
+
+    fn type_name() -> String {
+        format!("WhitenedDataset<{}>", M::type_name())
+    }
+
+    fn name(&self) -> &str {
+        self.rows.name()
+    }
+
+    fn cardinality(&self) -> usize {
+        self.rows.cardinality()
+    }
+
+    fn get(&self, index: usize) -> Cow<'_, Whitened> {
+        Cow::Borrowed(&self[index])
+    }
+
+    fn is_metric_expensive(&self) -> bool {
+        self.rows.is_metric_expensive()
+    }
+
+    fn metric(&self) -> &SharedMetric<Whitened, f32> {
+        self.rows.metric()
+    }
+
+    fn is_metric(&self) -> bool {
+        self.rows.is_metric()
+    }
+
+    fn is_metric_symmetric(&self) -> bool {
+        self.rows.is_metric_symmetric()
+    }
+
+    fn weights(&self) -> Option<&[f64]> {
+        self.rows.weights()
+    }
+
+    fn set_permuted_indices(&mut self, indices: Option<&[usize]>) {
+        self.rows.set_permuted_indices(indices);
+    }
+
+    fn swap(&mut self, left: usize, right: usize) -> Result<(), String> {
+        self.rows.swap(left, right)
+    }
+
+    fn permuted_indices(&self) -> Option<&[usize]> {
+        self.rows.permuted_indices()
+    }
+
+    fn permute_instances(&mut self, permutation: &[usize]) -> Result<(), String> {
+        self.rows.permute_instances(permutation)
+    }
+
+    fn query_to_many(&self, query: &Whitened, indices: &[usize]) -> Vec<f32> {
+        self.rows.query_to_many(query, indices)
+    }
+
+    fn make_shards(self, max_cardinality: usize) -> Vec<Self> {
+        let Self { rows, whitening } = self;
+        rows.make_shards(max_cardinality)
+            .into_iter()
+            .map(|rows| Self {
+                rows,
+                whitening: whitening.clone(),
+            })
+            .collect()
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        self.rows.save(path)?;
+        let handle = BufWriter::new(File::create(Self::sidecar_path(path)).map_err(|e| e.to_string())?);
+        bincode::serialize_into(handle, &self.whitening).map_err(|e| e.to_string())
+    }
+
+    fn load(path: &Path, metric: fn(&Whitened, &Whitened) -> f32, is_expensive: bool) -> Result<Self, String> {
+        let handle = BufReader::new(File::open(Self::sidecar_path(path)).map_err(|e| e.to_string())?);
+        let whitening: Whitening = bincode::deserialize_from(handle).map_err(|e| e.to_string())?;
+        whitening.validate()?;
+
+        let rows = VecDataset::load(path, metric, is_expensive)?;
+        if let Some((i, x)) = rows.data.iter().enumerate().find(|(_, x)| x.0.len() != whitening.dim) {
+            return Err(format!(
+                "Instance {i} has dimensionality {}, but the whitening has dimensionality {}",
+                x.0.len(),
+                whitening.dim
+            ));
+        }
+
+        Ok(Self { rows, whitening })
+    }
+}
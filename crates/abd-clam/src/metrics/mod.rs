@@ -0,0 +1,53 @@
+//! Built-in distance functions with specialized instance representations.
+//!
+//! Each distance function here operates on an instance type which caches or
+//! packs whatever the function needs, so that it does not have to be
+//! recomputed for every pair of instances. The `*_metric` functions return a
+//! `FnMetric` with the properties of the distance function declared, for use
+//! with `VecDataset::from_metric`.
+//!
+//! The clustered `knn` and `rnn` algorithms prune `Cluster`s with the triangle
+//! inequality, so they are only exact for distance functions which are metrics.
+//! For other distance functions, they fall back to `Linear` search.
+//!
+//! | Function                 | Instance     | Metric | Valid `knn` algorithms  |
+//! |--------------------------|--------------|--------|-------------------------|
+//! | `vectors::euclidean`     | `Vec<T>`     | Yes    | All                     |
+//! | `vectors::cosine`        | `NormedVec`  | No     | `Linear`, `Approximate` |
+//! | `halves::euclidean`      | `HalfVec`    | Yes    | All                     |
+//! | `halves::cosine`         | `HalfVec`    | No     | `Linear`, `Approximate` |
+//! | `sparse::euclidean`      | `SparseVec`  | Yes    | All                     |
+//! | `sparse::cosine`         | `SparseVec`  | No     | `Linear`, `Approximate` |
+//! | `sets::jaccard`          | `SortedSet`  | Yes    | All                     |
+//! | `bits::jaccard`          | `BitVector`  | Yes    | All                     |
+//! | `bits::hamming`          | `BitVector`  | Yes    | All                     |
+//! | `bits::hamming_words`    | `[u64; N]`   | Yes    | All                     |
+//! | `histograms::emd`        | `Histogram`  | Yes    | All                     |
+//! | `geo::haversine`         | `LatLon`     | Yes    | All                     |
+//! | `mahalanobis::euclidean` | `Whitened`   | Yes    | All                     |
+//! | `time_series::dtw`       | `TimeSeries` | No     | `Linear`, `Approximate` |
+//! | `sequences::*`           | Byte string  | Yes    | All                     |
+//!
+//! `Approximate` search may be used with non-metric distance functions, but
+//! its recall target is no longer meaningful.
+
+pub mod bits;
+pub mod geo;
+pub mod halves;
+pub mod histograms;
+pub mod mahalanobis;
+pub mod sequences;
+pub mod sets;
+pub mod sparse;
+pub mod time_series;
+pub mod vectors;
+
+pub use bits::BitVector;
+pub use geo::LatLon;
+pub use halves::{HalfFormat, HalfVec};
+pub use histograms::Histogram;
+pub use mahalanobis::{Whitened, WhitenedDataset, Whitening};
+pub use sets::SortedSet;
+pub use sparse::{SparseRow, SparseVec, SparseVecDataset};
+pub use time_series::TimeSeries;
+pub use vectors::NormedVec;
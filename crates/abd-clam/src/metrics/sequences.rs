@@ -0,0 +1,168 @@
+//! Distance functions for byte strings, e.g. genomic sequences.
+//!
+//! The functions are generic over the representation of the strings, so that
+//! `levenshtein::<Vec<u8>, u32>` may be used with a `SequenceDataset` and
+//! `levenshtein::<String, u32>` with a `VecDataset` of `String`s.
+//!
+//! The edit distances are computed with a banded dynamic program. An alignment
+//! which strays `t` positions from the main diagonal needs at least `t` gaps,
+//! so only a band of diagonals around it is filled in. The band is doubled
+//! until the distance is small enough that no alignment outside the band could
+//! be better. This costs `O(n * d)` rather than `O(n * m)` for strings of
+//! lengths `n <= m` at a distance of `d`.
+
+use distances::number::UInt;
+
+use crate::FnMetric;
+
+/// The half-width of the first band of diagonals to fill in.
+const INITIAL_BAND: usize = 8;
+
+/// A cost larger than that of any alignment, which still leaves room for
+/// adding penalties without overflowing.
+const INFINITY: usize = usize::MAX / 4;
+
+/// Computes the Hamming distance between two byte strings.
+///
+/// This is the number of positions at which the bytes differ, plus the
+/// difference in lengths, so that strings of different lengths are never at a
+/// distance of zero.
+///
+/// This is a metric, so all knn algorithms may be used with it.
+///
+/// # Arguments
+///
+/// * `x` - A byte string.
+/// * `y` - A byte string.
+#[must_use]
+pub fn hamming<S: AsRef<[u8]> + ?Sized, U: UInt>(x: &S, y: &S) -> U {
+    let (x, y) = (x.as_ref(), y.as_ref());
+    let mismatches = x.iter().zip(y.iter()).filter(|(a, b)| a != b).count();
+    U::from(mismatches + x.len().abs_diff(y.len()))
+}
+
+/// Computes the Levenshtein distance between two byte strings.
+///
+/// This is the minimum number of insertions, deletions and substitutions of
+/// single bytes needed to turn one string into the other. It is the same as
+/// `needleman_wunsch` with unit penalties.
+///
+/// This is a metric, so all knn algorithms may be used with it.
+///
+/// # Arguments
+///
+/// * `x` - A byte string.
+/// * `y` - A byte string.
+#[must_use]
+pub fn levenshtein<S: AsRef<[u8]> + ?Sized, U: UInt>(x: &S, y: &S) -> U {
+    U::from(edit_distance(x.as_ref(), y.as_ref(), 1, 1))
+}
+
+/// Computes the Needleman-Wunsch edit distance between two byte strings.
+///
+/// This is the minimum total penalty of an alignment of the strings, where a
+/// match costs nothing, a mismatch costs `MISMATCH` and a gap costs `GAP`.
+/// With the default penalties of `distances::strings::Penalties`, this is the
+/// same as `levenshtein`.
+///
+/// This is a metric as long as `GAP > 0` and `MISMATCH <= 2 * GAP`, i.e. a
+/// substitution is never more expensive than a deletion and an insertion.
+///
+/// # Type Parameters
+///
+/// * `MISMATCH` - The penalty for aligning two different bytes.
+/// * `GAP` - The penalty for aligning a byte with a gap.
+///
+/// # Arguments
+///
+/// * `x` - A byte string.
+/// * `y` - A byte string.
+#[must_use]
+pub fn needleman_wunsch<S: AsRef<[u8]> + ?Sized, U: UInt, const MISMATCH: usize, const GAP: usize>(x: &S, y: &S) -> U {
+    U::from(edit_distance(x.as_ref(), y.as_ref(), MISMATCH, GAP))
+}
+
+/// Returns the `hamming` distance function, declared as a metric.
+#[must_use]
+pub fn hamming_metric<S: AsRef<[u8]>, U: UInt>() -> FnMetric<S, U> {
+    FnMetric::new(hamming)
+}
+
+/// Returns the `levenshtein` distance function, declared as an expensive
+/// metric.
+#[must_use]
+pub fn levenshtein_metric<S: AsRef<[u8]>, U: UInt>() -> FnMetric<S, U> {
+    FnMetric::new(levenshtein).with_is_expensive(true)
+}
+
+/// Returns the `needleman_wunsch` distance function, declared as expensive,
+/// and as a metric if the penalties allow it.
+#[must_use]
+pub fn needleman_wunsch_metric<S: AsRef<[u8]>, U: UInt, const MISMATCH: usize, const GAP: usize>() -> FnMetric<S, U> {
+    FnMetric::new(needleman_wunsch::<S, U, MISMATCH, GAP>)
+        .with_is_expensive(true)
+        .with_is_metric(GAP > 0 && MISMATCH <= 2 * GAP)
+}
+
+/// Computes the edit distance between two byte strings, doubling the band of
+/// diagonals until the result is exact.
+fn edit_distance(x: &[u8], y: &[u8], mismatch: usize, gap: usize) -> usize {
+    if x.is_empty() || y.is_empty() || gap == 0 {
+        return gap * x.len().max(y.len());
+    }
+
+    let length_difference = x.len().abs_diff(y.len());
+    let mut band = INITIAL_BAND;
+    loop {
+        let d = banded_edit_distance(x, y, mismatch, gap, band);
+
+        // An alignment outside the band has at least `length_difference +
+        // 2 * (band + 1)` gaps, so it cannot be better than `d` if `d` is
+        // smaller than the cost of those gaps.
+        if band >= x.len().max(y.len()) || d < gap * (length_difference + 2 * (band + 1)) {
+            return d;
+        }
+        band *= 2;
+    }
+}
+
+/// Computes the cost of the best alignment of two non-empty byte strings
+/// which stays within `band` diagonals of those between the ends of the
+/// strings.
+fn banded_edit_distance(x: &[u8], y: &[u8], mismatch: usize, gap: usize, band: usize) -> usize {
+    let (n, m) = (x.len(), y.len());
+
+    // The diagonals `j - i` in the band are `-(below + band) ..= above + band`.
+    let (below, above) = if m >= n { (0, m - n) } else { (n - m, 0) };
+    let first = |i: usize| i.saturating_sub(below + band);
+    let last = |i: usize| (i + above + band).min(m);
+
+    let mut previous = vec![INFINITY; m + 1];
+    let mut current = vec![INFINITY; m + 1];
+    for (j, cell) in previous.iter_mut().enumerate().take(last(0) + 1) {
+        *cell = gap * j;
+    }
+
+    for i in 1..=n {
+        let (start, end) = (first(i), last(i));
+        if start > 0 {
+            current[start - 1] = INFINITY;
+        } else {
+            current[0] = gap * i;
+        }
+
+        for j in start.max(1)..=end {
+            let substitution = if x[i - 1] == y[j - 1] { 0 } else { mismatch };
+            current[j] = (previous[j - 1] + substitution)
+                .min(previous[j] + gap)
+                .min(current[j - 1] + gap);
+        }
+        if end < m {
+            current[end + 1] = INFINITY;
+        }
+
+        core::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[m]
+}
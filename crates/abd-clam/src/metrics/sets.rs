@@ -0,0 +1,117 @@
+//! Distance functions for sets stored as sorted vectors.
+
+use core::cmp::Ordering;
+
+use distances::number::{Float, Int};
+
+use crate::{FnMetric, Instance};
+
+/// A set of integers, stored as a sorted vector without duplicates.
+///
+/// Keeping the elements sorted lets the intersection and union of two sets be
+/// counted in a single merge pass, without building any intermediate sets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortedSet<T: Int> {
+    /// The sorted, unique elements of the set.
+    elements: Vec<T>,
+}
+
+impl<T: Int> SortedSet<T> {
+    /// Creates a new `SortedSet`, sorting and removing duplicates from the
+    /// given elements.
+    ///
+    /// # Arguments
+    ///
+    /// * `elements` - The elements of the set, in any order.
+    #[must_use]
+    pub fn new(mut elements: Vec<T>) -> Self {
+        elements.sort_unstable();
+        elements.dedup();
+        Self { elements }
+    }
+
+    /// Returns the sorted elements of the set.
+    #[must_use]
+    pub fn elements(&self) -> &[T] {
+        &self.elements
+    }
+
+    /// Returns the number of elements in the set.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    /// Returns whether the set is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// Returns the number of elements shared by two sets.
+    fn intersection_len(&self, other: &Self) -> usize {
+        let (mut i, mut j, mut count) = (0, 0, 0);
+        while i < self.elements.len() && j < other.elements.len() {
+            match self.elements[i].cmp(&other.elements[j]) {
+                Ordering::Less => i += 1,
+                Ordering::Greater => j += 1,
+                Ordering::Equal => {
+                    count += 1;
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        count
+    }
+}
+
+impl<T: Int> From<Vec<T>> for SortedSet<T> {
+    fn from(elements: Vec<T>) -> Self {
+        Self::new(elements)
+    }
+}
+
+impl<T: Int> Instance for SortedSet<T> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.elements.to_bytes()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        Vec::<T>::from_bytes(bytes).map(Self::new)
+    }
+
+    fn type_name() -> String {
+        format!("SortedSet<{}>", T::type_name())
+    }
+}
+
+/// Computes the Jaccard distance between two sets.
+///
+/// The Jaccard distance is `1.0` minus the ratio of the cardinality of the
+/// intersection to the cardinality of the union of the sets. The distance
+/// between two empty sets is `0.0`.
+///
+/// This is a metric, so all knn algorithms may be used with it.
+///
+/// # Arguments
+///
+/// * `x` - A set.
+/// * `y` - A set.
+#[must_use]
+pub fn jaccard<T: Int, U: Float>(x: &SortedSet<T>, y: &SortedSet<T>) -> U {
+    let intersection = x.intersection_len(y);
+    let union = x.len() + y.len() - intersection;
+
+    if intersection == union {
+        U::zero()
+    } else {
+        U::one() - U::from(intersection) / U::from(union)
+    }
+}
+
+/// Returns the `jaccard` distance function, declared as a metric.
+#[must_use]
+pub fn jaccard_metric<T: Int, U: Float>() -> FnMetric<SortedSet<T>, U> {
+    FnMetric::new(jaccard)
+}
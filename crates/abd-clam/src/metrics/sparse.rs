@@ -0,0 +1,817 @@
+//! Distance functions for sparse vectors.
+
+use core::cmp::Ordering;
+
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use distances::{number::Float, Number};
+
+use crate::{Dataset, FnMetric, Instance, Metric, SharedMetric, SimdKernel};
+
+/// A sparse vector, stored as the sorted positions and values of its non-zero
+/// elements, along with its cached euclidean norm.
+///
+/// This is one row of a matrix in compressed sparse row (CSR) format. Distances
+/// are computed in a single merge pass over the non-zero elements of two
+/// vectors, so their cost depends on the number of non-zero elements rather
+/// than on the dimensionality.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseVec<T: Number> {
+    /// The dimensionality of the vector.
+    dim: usize,
+    /// The positions of the non-zero elements, in increasing order.
+    indices: Vec<u32>,
+    /// The values of the non-zero elements.
+    values: Vec<T>,
+    /// The euclidean norm of the vector.
+    norm: f64,
+}
+
+impl<T: Number> SparseVec<T> {
+    /// Creates a new `SparseVec` from its non-zero elements.
+    ///
+    /// The elements may be given in any order. Elements whose value is zero
+    /// are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `dim` - The dimensionality of the vector.
+    /// * `elements` - The positions and values of the non-zero elements.
+    ///
+    /// # Errors
+    ///
+    /// * If a position is out of bounds for `dim`.
+    /// * If a position appears more than once.
+    pub fn new(dim: usize, mut elements: Vec<(u32, T)>) -> Result<Self, String> {
+        elements.retain(|&(_, v)| v != T::zero());
+        elements.sort_by_key(|&(i, _)| i);
+
+        if let Some(&(i, _)) = elements.iter().find(|&&(i, _)| i as usize >= dim) {
+            return Err(format!("Position {i} is out of bounds for dimensionality {dim}"));
+        }
+        if let Some(w) = elements.windows(2).find(|w| w[0].0 == w[1].0) {
+            return Err(format!("Position {} appears more than once", w[0].0));
+        }
+
+        let (indices, values): (Vec<_>, Vec<_>) = elements.into_iter().unzip();
+        let norm = values.iter().map(|v| v.as_f64().powi(2)).sum::<f64>().sqrt();
+        Ok(Self {
+            dim,
+            indices,
+            values,
+            norm,
+        })
+    }
+
+    /// Creates a new `SparseVec` from a dense vector.
+    ///
+    /// # Arguments
+    ///
+    /// * `dense` - The elements of the vector, including zeros.
+    ///
+    /// # Errors
+    ///
+    /// If the vector is too long for its positions to fit in 32 bits.
+    pub fn from_dense(dense: &[T]) -> Result<Self, String> {
+        let elements = dense
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| u32::try_from(i).map(|i| (i, v)).map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(dense.len(), elements)
+    }
+
+    /// Returns the vector with its zeros filled in.
+    #[must_use]
+    pub fn to_dense(&self) -> Vec<T> {
+        let mut dense = vec![T::zero(); self.dim];
+        for (&i, &v) in self.indices.iter().zip(self.values.iter()) {
+            dense[i as usize] = v;
+        }
+        dense
+    }
+
+    /// Returns the dimensionality of the vector.
+    #[must_use]
+    pub const fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Returns the number of non-zero elements.
+    #[must_use]
+    pub fn nnz(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// Returns the positions of the non-zero elements, in increasing order.
+    #[must_use]
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    /// Returns the values of the non-zero elements.
+    #[must_use]
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+
+    /// Returns the euclidean norm of the vector.
+    #[must_use]
+    pub const fn norm(&self) -> f64 {
+        self.norm
+    }
+
+    /// Borrows the vector as a `SparseRow`.
+    #[must_use]
+    pub fn as_row(&self) -> SparseRow<'_, T> {
+        SparseRow {
+            dim: self.dim,
+            indices: &self.indices,
+            values: &self.values,
+            norm: self.norm,
+        }
+    }
+}
+
+/// A borrowed sparse vector, e.g. a row of a `SparseVecDataset`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SparseRow<'a, T: Number> {
+    /// The dimensionality of the vector.
+    dim: usize,
+    /// The positions of the non-zero elements, in increasing order.
+    indices: &'a [u32],
+    /// The values of the non-zero elements.
+    values: &'a [T],
+    /// The euclidean norm of the vector.
+    norm: f64,
+}
+
+impl<'a, T: Number> SparseRow<'a, T> {
+    /// Returns the dimensionality of the vector.
+    #[must_use]
+    pub const fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Returns the positions of the non-zero elements, in increasing order.
+    #[must_use]
+    pub const fn indices(&self) -> &'a [u32] {
+        self.indices
+    }
+
+    /// Returns the values of the non-zero elements.
+    #[must_use]
+    pub const fn values(&self) -> &'a [T] {
+        self.values
+    }
+
+    /// Returns the euclidean norm of the vector.
+    #[must_use]
+    pub const fn norm(&self) -> f64 {
+        self.norm
+    }
+
+    /// Copies the vector into a `SparseVec`.
+    #[must_use]
+    pub fn to_vec(&self) -> SparseVec<T> {
+        SparseVec {
+            dim: self.dim,
+            indices: self.indices.to_vec(),
+            values: self.values.to_vec(),
+            norm: self.norm,
+        }
+    }
+
+    /// Returns the vector with its zeros filled in.
+    #[must_use]
+    pub fn to_dense(&self) -> Vec<T> {
+        let mut dense = vec![T::zero(); self.dim];
+        for (&i, &v) in self.indices.iter().zip(self.values.iter()) {
+            dense[i as usize] = v;
+        }
+        dense
+    }
+
+    /// Computes the dot product with another vector. See `sparse::dot`.
+    #[must_use]
+    pub fn dot(self, other: Self) -> f64 {
+        let mut xy = 0.;
+        self.merge(other, |a, b| xy = a.mul_add(b, xy));
+        xy
+    }
+
+    /// Computes the euclidean distance to another vector. See
+    /// `sparse::euclidean`.
+    #[must_use]
+    pub fn euclidean(self, other: Self) -> f64 {
+        let mut d = 0.;
+        self.merge(other, |a, b| d += (a - b).powi(2));
+        d.sqrt()
+    }
+
+    /// Computes the cosine distance to another vector. See `sparse::cosine`.
+    #[must_use]
+    pub fn cosine(self, other: Self) -> f64 {
+        let denominator = self.norm * other.norm;
+        if denominator < f64::EPSILON {
+            return 1.;
+        }
+
+        let d = 1.0 - self.dot(other) / denominator;
+        if d < f64::EPSILON {
+            0.
+        } else {
+            d
+        }
+    }
+
+    /// Calls `f` with the values of both vectors at every position where
+    /// either is non-zero.
+    fn merge(self, other: Self, mut f: impl FnMut(f64, f64)) {
+        let (mut i, mut j) = (0, 0);
+        while i < self.indices.len() && j < other.indices.len() {
+            match self.indices[i].cmp(&other.indices[j]) {
+                Ordering::Less => {
+                    f(self.values[i].as_f64(), 0.);
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    f(0., other.values[j].as_f64());
+                    j += 1;
+                }
+                Ordering::Equal => {
+                    f(self.values[i].as_f64(), other.values[j].as_f64());
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        self.values[i..].iter().for_each(|v| f(v.as_f64(), 0.));
+        other.values[j..].iter().for_each(|v| f(0., v.as_f64()));
+    }
+}
+
+impl<T: Number> Instance for SparseVec<T> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.dim.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&self.indices.len().to_le_bytes());
+        bytes.extend(self.indices.iter().flat_map(|i| i.to_le_bytes()));
+        bytes.extend(self.values.iter().flat_map(|v| v.to_le_bytes()));
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let num_bytes = <usize as Number>::num_bytes();
+        if bytes.len() < 2 * num_bytes {
+            return Err(format!(
+                "Expected at least {} bytes, got {}",
+                2 * num_bytes,
+                bytes.len()
+            ));
+        }
+
+        let (dim, rest) = bytes.split_at(num_bytes);
+        let (nnz, rest) = rest.split_at(num_bytes);
+        let dim = <usize as Number>::from_le_bytes(dim);
+        let nnz = <usize as Number>::from_le_bytes(nnz);
+
+        if rest.len() != nnz * (4 + T::num_bytes()) {
+            return Err(format!(
+                "Expected {} bytes for {nnz} elements, got {}",
+                nnz * (4 + T::num_bytes()),
+                rest.len()
+            ));
+        }
+
+        let (indices, values) = rest.split_at(nnz * 4);
+        let indices = Vec::<u32>::from_bytes(indices)?;
+        let values = Vec::<T>::from_bytes(values)?;
+        Self::new(dim, indices.into_iter().zip(values).collect())
+    }
+
+    fn type_name() -> String {
+        format!("SparseVec<{}>", T::type_name())
+    }
+}
+
+/// A `Dataset` of sparse vectors, stored as a matrix in compressed sparse row
+/// (CSR) format.
+///
+/// The positions and values of the non-zero elements of all rows are stored
+/// in two contiguous buffers, with the offsets at which each row starts in
+/// `indptr`, instead of in a separate allocation per vector. `row` borrows a
+/// row as a `SparseRow`, and distances between rows and to queries are
+/// computed on these views, without copying, for metrics with a `SimdKernel`,
+/// e.g. `sparse::euclidean_metric` and `sparse::cosine_metric`. For other
+/// metrics, and through `Dataset::get`, each row is copied into a `SparseVec`.
+///
+/// Building a `Tree` swaps instances many times, so swaps only reorder the
+/// rows, while the buffers keep the order in which the rows were given.
+/// `permute_instances` and `make_shards` repack the buffers in the order of
+/// the instances.
+///
+/// # Type Parameters
+///
+/// - `T`: The type of the values of the non-zero elements.
+/// - `U`: The type of the distance values between instances.
+/// - `M`: The type of the metadata associated with each instance.
+#[derive(Debug, Clone)]
+pub struct SparseVecDataset<T: Number, U: Number, M: Instance = usize> {
+    /// The name of the dataset.
+    name: String,
+    /// The number of columns in the matrix.
+    dim: usize,
+    /// The offsets in `indices` and `values` at which each stored row starts,
+    /// followed by the total number of non-zero elements.
+    indptr: Vec<usize>,
+    /// The column of each non-zero element.
+    indices: Vec<u32>,
+    /// The value of each non-zero element.
+    values: Vec<T>,
+    /// The euclidean norm of each stored row.
+    norms: Vec<f64>,
+    /// The stored row of each instance.
+    rows: Vec<usize>,
+    /// The metric of the dataset.
+    metric: SharedMetric<SparseVec<T>, U>,
+    /// The reordering of the dataset after building the tree.
+    permuted_indices: Option<Vec<usize>>,
+    /// Metadata about the dataset.
+    metadata: Vec<M>,
+}
+
+impl<T: Number, U: Number> SparseVecDataset<T, U, usize> {
+    /// Creates a new dataset of sparse vectors from a matrix in compressed
+    /// sparse row (CSR) format.
+    ///
+    /// The non-zero elements of a row may be given in any order. Elements whose
+    /// value is zero are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the dataset.
+    /// * `dim`: The number of columns in the matrix.
+    /// * `indptr`: The offsets in `indices` and `values` at which each row
+    ///   starts, followed by the total number of non-zero elements.
+    /// * `indices`: The column of each non-zero element.
+    /// * `values`: The value of each non-zero element.
+    /// * `metric`: The distance function and its properties.
+    ///
+    /// # Errors
+    ///
+    /// * If `indptr` is empty, decreasing, or does not end at the number of
+    ///   non-zero elements.
+    /// * If `indices` and `values` have different lengths.
+    /// * If any row is not a valid `SparseVec`.
+    pub fn from_csr(
+        name: String,
+        dim: usize,
+        indptr: &[usize],
+        indices: &[u32],
+        values: &[T],
+        metric: impl Into<SharedMetric<SparseVec<T>, U>>,
+    ) -> Result<Self, String> {
+        let rows = Self::csr_rows(dim, indptr, indices, values)?;
+        let mut data = Self::empty(name, dim, metric.into());
+        for (i, row) in rows.iter().enumerate() {
+            data.push(row.as_row(), i);
+        }
+        Ok(data)
+    }
+
+    /// Creates a new dataset from sparse vectors, which are copied into
+    /// contiguous storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The name of the dataset.
+    /// * `rows`: The sparse vectors.
+    /// * `metric`: The distance function and its properties.
+    ///
+    /// # Errors
+    ///
+    /// * If the vectors do not all have the same dimensionality.
+    pub fn from_rows(
+        name: String,
+        rows: &[SparseVec<T>],
+        metric: impl Into<SharedMetric<SparseVec<T>, U>>,
+    ) -> Result<Self, String> {
+        let dim = rows.first().map_or(0, SparseVec::dim);
+        if let Some(row) = rows.iter().find(|row| row.dim() != dim) {
+            return Err(format!(
+                "Expected vectors of dimensionality {dim}, got one of dimensionality {}",
+                row.dim()
+            ));
+        }
+
+        let mut data = Self::empty(name, dim, metric.into());
+        for (i, row) in rows.iter().enumerate() {
+            data.push(row.as_row(), i);
+        }
+        Ok(data)
+    }
+}
+
+impl<T: Number, U: Number, M: Instance> SparseVecDataset<T, U, M> {
+    /// Returns the number of columns in the matrix.
+    #[must_use]
+    pub const fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Returns the total number of non-zero elements.
+    #[must_use]
+    pub fn nnz(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// Borrows the instance at the given index.
+    ///
+    /// # Panics
+    ///
+    /// * If `index` is not a valid index into the dataset.
+    #[must_use]
+    pub fn row(&self, index: usize) -> SparseRow<'_, T> {
+        let row = self.rows[index];
+        let (start, end) = (self.indptr[row], self.indptr[row + 1]);
+        SparseRow {
+            dim: self.dim,
+            indices: &self.indices[start..end],
+            values: &self.values[start..end],
+            norm: self.norms[row],
+        }
+    }
+
+    /// Returns the instances as a matrix in compressed sparse row (CSR)
+    /// format, i.e. as `(indptr, indices, values)`, in their current order.
+    #[must_use]
+    pub fn to_csr(&self) -> (Vec<usize>, Vec<u32>, Vec<T>) {
+        let packed = self.gather(0..self.cardinality());
+        (packed.indptr, packed.indices, packed.values)
+    }
+
+    /// A reference to the underlying metadata.
+    #[must_use]
+    pub fn metadata(&self) -> &[M] {
+        &self.metadata
+    }
+
+    /// Assigns metadata to the dataset.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata`: The metadata to assign to the dataset, in the original
+    ///   order of the instances.
+    ///
+    /// # Errors
+    ///
+    /// * If the metadata is not the same length as the dataset.
+    pub fn assign_metadata<Mn: Instance>(self, metadata: Vec<Mn>) -> Result<SparseVecDataset<T, U, Mn>, String> {
+        if metadata.len() != self.cardinality() {
+            return Err(format!(
+                "Invalid metadata. Expected metadata of length {}, got metadata of length {}",
+                self.cardinality(),
+                metadata.len()
+            ));
+        }
+
+        // If there is a permutation, permute the metadata as well.
+        let metadata = if let Some(permutation) = self.permuted_indices.as_ref() {
+            permutation.iter().map(|&index| metadata[index].clone()).collect()
+        } else {
+            metadata
+        };
+
+        Ok(SparseVecDataset {
+            name: self.name,
+            dim: self.dim,
+            indptr: self.indptr,
+            indices: self.indices,
+            values: self.values,
+            norms: self.norms,
+            rows: self.rows,
+            metric: self.metric,
+            permuted_indices: self.permuted_indices,
+            metadata,
+        })
+    }
+
+    /// Creates a dataset with no instances.
+    fn empty(name: String, dim: usize, metric: SharedMetric<SparseVec<T>, U>) -> Self {
+        Self {
+            name,
+            dim,
+            indptr: vec![0],
+            indices: Vec::new(),
+            values: Vec::new(),
+            norms: Vec::new(),
+            rows: Vec::new(),
+            metric,
+            permuted_indices: None,
+            metadata: Vec::new(),
+        }
+    }
+
+    /// Appends an instance, copying its row to the end of the buffers.
+    fn push(&mut self, row: SparseRow<'_, T>, metadata: M) {
+        self.rows.push(self.norms.len());
+        self.indices.extend_from_slice(row.indices);
+        self.values.extend_from_slice(row.values);
+        self.indptr.push(self.indices.len());
+        self.norms.push(row.norm);
+        self.metadata.push(metadata);
+    }
+
+    /// Checks a matrix in compressed sparse row (CSR) format and returns its
+    /// rows. See `SparseVecDataset::from_csr`.
+    fn csr_rows(dim: usize, indptr: &[usize], indices: &[u32], values: &[T]) -> Result<Vec<SparseVec<T>>, String> {
+        if indices.len() != values.len() {
+            return Err(format!(
+                "Expected as many indices as values, got {} and {}",
+                indices.len(),
+                values.len()
+            ));
+        }
+        if indptr.last() != Some(&indices.len()) {
+            return Err(format!("Expected `indptr` to end at {}", indices.len()));
+        }
+        if let Some([start, ..]) = indptr.windows(2).find(|w| w[0] > w[1]) {
+            return Err(format!("`indptr` is decreasing at {start}"));
+        }
+
+        indptr
+            .windows(2)
+            .map(|w| {
+                let elements = indices[w[0]..w[1]]
+                    .iter()
+                    .copied()
+                    .zip(values[w[0]..w[1]].iter().copied())
+                    .collect();
+                SparseVec::new(dim, elements)
+            })
+            .collect()
+    }
+
+    /// Copies the instances at the given indices, in order, into a new
+    /// dataset whose buffers hold only their rows.
+    fn gather(&self, indices: impl Iterator<Item = usize>) -> Self {
+        let mut packed = Self::empty(self.name.clone(), self.dim, self.metric.clone());
+        for index in indices {
+            packed.push(self.row(index), self.metadata[index].clone());
+        }
+        packed
+    }
+
+    /// Computes the distance between two rows, without copying them if the
+    /// metric has a kernel.
+    fn distance(&self, a: SparseRow<'_, T>, b: SparseRow<'_, T>) -> U {
+        match self.metric.kernel() {
+            Some(SimdKernel::Euclidean) => U::from(a.euclidean(b)),
+            Some(SimdKernel::SquaredEuclidean) => U::from(a.euclidean(b).powi(2)),
+            Some(SimdKernel::Cosine) => U::from(a.cosine(b)),
+            None => self.metric.distance(&a.to_vec(), &b.to_vec()),
+        }
+    }
+}
+
+impl<T: Number, U: Number, M: Instance> Dataset<SparseVec<T>, U> for SparseVecDataset<T, U, M> {
+    fn clone_with_new_metric(
+        &self,
+        metric: fn(&SparseVec<T>, &SparseVec<T>) -> U,
+        is_expensive: bool,
+        name: String,
+    ) -> Self {
+        Self {
+            name,
+            metric: FnMetric::new(metric).with_is_expensive(is_expensive).into(),
+            ..self.clone()
+        }
+    }
+
+    fn type_name() -> String {
+        format!(
+            "SparseVecDataset<{}, {}, {}>",
+            T::type_name(),
+            U::type_name(),
+            M::type_name()
+        )
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn cardinality(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn get(&self, index: usize) -> Cow<'_, SparseVec<T>> {
+        Cow::Owned(self.row(index).to_vec())
+    }
+
+    fn metric(&self) -> &SharedMetric<SparseVec<T>, U> {
+        &self.metric
+    }
+
+    fn one_to_one(&self, left: usize, right: usize) -> U {
+        self.distance(self.row(left), self.row(right))
+    }
+
+    fn query_to_one(&self, query: &SparseVec<T>, index: usize) -> U {
+        self.distance(query.as_row(), self.row(index))
+    }
+
+    fn set_permuted_indices(&mut self, indices: Option<&[usize]>) {
+        self.permuted_indices = indices.map(<[usize]>::to_vec);
+    }
+
+    fn swap(&mut self, left: usize, right: usize) -> Result<(), String> {
+        self.rows.swap(left, right);
+        self.metadata.swap(left, right);
+        Ok(())
+    }
+
+    fn permuted_indices(&self) -> Option<&[usize]> {
+        self.permuted_indices.as_deref()
+    }
+
+    fn permute_instances(&mut self, permutation: &[usize]) -> Result<(), String> {
+        if permutation.len() != self.cardinality() {
+            return Err(format!(
+                "Invalid permutation. Expected permutation of length {}, got permutation of length {}",
+                self.cardinality(),
+                permutation.len()
+            ));
+        }
+
+        let packed = self.gather(permutation.iter().copied());
+        *self = Self {
+            permuted_indices: Some(permutation.to_vec()),
+            ..packed
+        };
+        Ok(())
+    }
+
+    fn make_shards(self, max_cardinality: usize) -> Vec<Self> {
+        let cardinality = self.cardinality();
+        let mut shards = Vec::new();
+
+        // As `VecDataset::make_shards`, shards are split off from the end.
+        let mut end = cardinality;
+        while end > max_cardinality {
+            let at = end - max_cardinality;
+            let mut shard = self.gather(at..end);
+            shard.name = format!("{}-shard-{}", self.name, shards.len());
+            shards.push(shard);
+            end = at;
+        }
+
+        let mut shard = self.gather(0..end);
+        shard.name = format!("{}-shard-{}", self.name, shards.len());
+        shard.permuted_indices = self.permuted_indices.map(|mut p| {
+            p.truncate(end);
+            p
+        });
+        shards.push(shard);
+
+        shards
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        let (indptr, indices, values) = self.to_csr();
+        let values = values.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<_>>();
+        let metadata = self.metadata.iter().map(Instance::to_bytes).collect::<Vec<_>>();
+        let contents = (
+            Self::type_name(),
+            &self.name,
+            self.dim,
+            indptr,
+            indices,
+            values,
+            metadata,
+            &self.permuted_indices,
+            [self.metric.is_metric(), self.metric.is_symmetric()],
+        );
+
+        let handle = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+        bincode::serialize_into(handle, &contents).map_err(|e| e.to_string())
+    }
+
+    fn load(path: &Path, metric: fn(&SparseVec<T>, &SparseVec<T>) -> U, is_expensive: bool) -> Result<Self, String> {
+        #[allow(clippy::type_complexity)]
+        let (type_name, name, dim, indptr, indices, values, metadata, permuted_indices, [is_metric, is_symmetric]): (
+            String,
+            String,
+            usize,
+            Vec<usize>,
+            Vec<u32>,
+            Vec<u8>,
+            Vec<Vec<u8>>,
+            Option<Vec<usize>>,
+            [bool; 2],
+        ) = {
+            let handle = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+            bincode::deserialize_from(handle).map_err(|e| e.to_string())?
+        };
+
+        let actual_type_name = Self::type_name();
+        if type_name != actual_type_name {
+            return Err(format!(
+                "Invalid type. File has data of type {type_name} but dataset was constructed with type {actual_type_name}"
+            ));
+        }
+
+        let metric = FnMetric::new(metric)
+            .with_is_expensive(is_expensive)
+            .with_is_metric(is_metric)
+            .with_is_symmetric(is_symmetric);
+        let values = Vec::<T>::from_bytes(&values)?;
+        let metadata = metadata
+            .iter()
+            .map(|m| M::from_bytes(m))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let rows = Self::csr_rows(dim, &indptr, &indices, &values)?;
+        if metadata.len() != rows.len() {
+            return Err(format!(
+                "Expected metadata for {} instances, got {}",
+                rows.len(),
+                metadata.len()
+            ));
+        }
+
+        let mut data = Self::empty(name, dim, metric.into());
+        for (row, metadata) in rows.iter().zip(metadata) {
+            data.push(row.as_row(), metadata);
+        }
+        data.permuted_indices = permuted_indices;
+        Ok(data)
+    }
+}
+
+/// Computes the dot product of two sparse vectors.
+///
+/// # Arguments
+///
+/// * `x` - A sparse vector.
+/// * `y` - A sparse vector.
+#[must_use]
+pub fn dot<T: Number, U: Float>(x: &SparseVec<T>, y: &SparseVec<T>) -> U {
+    U::from(x.as_row().dot(y.as_row()))
+}
+
+/// Computes the euclidean distance between two sparse vectors.
+///
+/// The vectors are assumed to have the same dimensionality.
+///
+/// This is a metric, so all knn algorithms may be used with it.
+///
+/// # Arguments
+///
+/// * `x` - A sparse vector.
+/// * `y` - A sparse vector.
+#[must_use]
+pub fn euclidean<T: Number, U: Float>(x: &SparseVec<T>, y: &SparseVec<T>) -> U {
+    U::from(x.as_row().euclidean(y.as_row()))
+}
+
+/// Computes the cosine distance between two sparse vectors, using their
+/// cached norms.
+///
+/// The cosine distance is defined as `1.0 - c` where `c` is the cosine
+/// similarity. If either vector has a norm of zero, the distance is `1.0`.
+///
+/// This is not a metric because it does not obey the triangle inequality. Only
+/// `Linear` and `Approximate` knn search should be used with it.
+///
+/// # Arguments
+///
+/// * `x` - A sparse vector.
+/// * `y` - A sparse vector.
+#[must_use]
+pub fn cosine<T: Number, U: Float>(x: &SparseVec<T>, y: &SparseVec<T>) -> U {
+    U::from(x.as_row().cosine(y.as_row()))
+}
+
+/// Returns the `euclidean` distance function, declared as a metric.
+///
+/// Its kernel lets a `SparseVecDataset` compute it on borrowed rows.
+#[must_use]
+pub fn euclidean_metric<T: Number, U: Float>() -> FnMetric<SparseVec<T>, U> {
+    FnMetric::new(euclidean).with_kernel(SimdKernel::Euclidean)
+}
+
+/// Returns the `cosine` distance function, declared as a non-metric.
+///
+/// Its kernel lets a `SparseVecDataset` compute it on borrowed rows.
+#[must_use]
+pub fn cosine_metric<T: Number, U: Float>() -> FnMetric<SparseVec<T>, U> {
+    FnMetric::new(cosine)
+        .with_is_metric(false)
+        .with_kernel(SimdKernel::Cosine)
+}
@@ -0,0 +1,202 @@
+//! Dynamic Time Warping between time series, with the `LB_Keogh` lower bound.
+
+use distances::{number::Float, Number};
+
+use crate::{FnMetric, Instance};
+
+/// A time series, along with the envelope used by `lb_keogh`.
+///
+/// The warping of the series is constrained to a Sakoe-Chiba band, i.e. a
+/// point may only be matched to points of the other series which are at most
+/// `band` positions away. The envelope holds the least and greatest values
+/// within the band around each point. It is computed once, when the series is
+/// created, and takes twice as much memory as the values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeSeries {
+    /// The half-width of the band.
+    band: usize,
+    /// The values of the series.
+    values: Vec<f32>,
+    /// The least value within the band around each point.
+    lower: Vec<f32>,
+    /// The greatest value within the band around each point.
+    upper: Vec<f32>,
+}
+
+impl TimeSeries {
+    /// Creates a new `TimeSeries`.
+    ///
+    /// # Arguments
+    ///
+    /// * `values` - The values of the series.
+    /// * `band` - The half-width of the Sakoe-Chiba band. A band of `0`
+    ///   allows no warping, and a band at least as long as the series allows
+    ///   any warping.
+    ///
+    /// # Errors
+    ///
+    /// * If the series is empty.
+    /// * If any value is not finite.
+    pub fn new(values: Vec<f32>, band: usize) -> Result<Self, String> {
+        if values.is_empty() {
+            return Err("Expected a non-empty time series".to_string());
+        }
+        if let Some(v) = values.iter().find(|v| !v.is_finite()) {
+            return Err(format!("Expected finite values, got {v}"));
+        }
+
+        let band = band.min(values.len());
+        let (lower, upper) = (0..values.len())
+            .map(|i| {
+                let window = &values[i.saturating_sub(band)..(i + band + 1).min(values.len())];
+                window.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &v| {
+                    (lo.min(v), hi.max(v))
+                })
+            })
+            .unzip();
+        Ok(Self {
+            band,
+            values,
+            lower,
+            upper,
+        })
+    }
+
+    /// Returns the number of points in the series.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns whether the series has no points. This is never the case.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns the half-width of the Sakoe-Chiba band.
+    #[must_use]
+    pub const fn band(&self) -> usize {
+        self.band
+    }
+
+    /// Returns the values of the series.
+    #[must_use]
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+}
+
+impl Instance for TimeSeries {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.band.to_le_bytes().to_vec();
+        bytes.extend(self.values.to_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let num_bytes = <usize as Number>::num_bytes();
+        if bytes.len() < num_bytes {
+            return Err(format!("Expected at least {num_bytes} bytes, got {}", bytes.len()));
+        }
+        let (band, values) = bytes.split_at(num_bytes);
+        Self::new(Vec::from_bytes(values)?, <usize as Number>::from_le_bytes(band))
+    }
+
+    fn type_name() -> String {
+        "TimeSeries".to_string()
+    }
+}
+
+/// Computes the Dynamic Time Warping distance between two time series.
+///
+/// This is the least total absolute difference between matched points, over
+/// all warpings of the two series onto each other. The warping is constrained
+/// to the narrower of the two bands, widened if needed to the difference in
+/// lengths so that series of different lengths may still be matched end to
+/// end. This takes `O(n * w)` time for series of length `n` and a band of
+/// half-width `w`, so it is expensive for long series with wide bands. Use
+/// `dtw_metric` to pair it with `lb_keogh` as a lower bound.
+///
+/// This is not a metric because it does not obey the triangle inequality. Only
+/// `Linear` and `Approximate` knn search should be used with it, and both skip
+/// instances by the lower bound.
+///
+/// # Arguments
+///
+/// * `x` - A time series.
+/// * `y` - A time series.
+#[must_use]
+pub fn dtw<U: Float>(x: &TimeSeries, y: &TimeSeries) -> U {
+    let (n, m) = (x.len(), y.len());
+    let band = x.band.min(y.band).max(n.abs_diff(m));
+
+    // Only the previous row of the dynamic program is kept.
+    let mut prev = vec![f64::INFINITY; m + 1];
+    let mut curr = vec![f64::INFINITY; m + 1];
+    prev[0] = 0.;
+    for i in 1..=n {
+        curr.fill(f64::INFINITY);
+        for j in i.saturating_sub(band).max(1)..=(i + band).min(m) {
+            let cost = (x.values[i - 1].as_f64() - y.values[j - 1].as_f64()).abs();
+            curr[j] = cost + prev[j - 1].min(prev[j]).min(curr[j - 1]);
+        }
+        core::mem::swap(&mut prev, &mut curr);
+    }
+    U::from(prev[m])
+}
+
+/// Computes the `LB_Keogh` lower bound on `dtw` between two time series.
+///
+/// Every point of one series is matched to a point of the other within the
+/// band, so it costs at least its distance to the envelope of the other series.
+/// This is summed over the points of each series in turn, against the envelope
+/// of the other, and the larger sum is the bound. It takes linear time.
+///
+/// The envelopes only line up for series of the same length. For series of
+/// different lengths, the bound is the cost of matching the first points and
+/// the last points, which every warping does.
+///
+/// # Arguments
+///
+/// * `x` - A time series.
+/// * `y` - A time series.
+#[must_use]
+pub fn lb_keogh<U: Float>(x: &TimeSeries, y: &TimeSeries) -> U {
+    let bound = if x.len() == y.len() {
+        keogh(x, y).max(keogh(y, x))
+    } else {
+        let first = (x.values[0].as_f64() - y.values[0].as_f64()).abs();
+        let last = (x.values[x.len() - 1].as_f64() - y.values[y.len() - 1].as_f64()).abs();
+        first + last
+    };
+    U::from(bound)
+}
+
+/// Returns the `dtw` distance function, declared as an expensive non-metric
+/// with `lb_keogh` as its lower bound.
+#[must_use]
+pub fn dtw_metric<U: Float>() -> FnMetric<TimeSeries, U> {
+    FnMetric::new(dtw)
+        .with_is_metric(false)
+        .with_is_expensive(true)
+        .with_lower_bound(lb_keogh)
+}
+
+/// The total distance from the points of `y` to the envelope of `x`, which
+/// must have the same length.
+fn keogh(x: &TimeSeries, y: &TimeSeries) -> f64 {
+    y.values
+        .iter()
+        .zip(x.lower.iter().zip(x.upper.iter()))
+        .map(|(&v, (&lo, &hi))| {
+            if v < lo {
+                lo.as_f64() - v.as_f64()
+            } else if v > hi {
+                v.as_f64() - hi.as_f64()
+            } else {
+                0.
+            }
+        })
+        .sum()
+}
@@ -0,0 +1,171 @@
+//! Distance functions for dense vectors, with or without cached norms.
+
+use distances::{number::Float, Number};
+
+use crate::{FnMetric, Instance, SimdKernel};
+
+/// A vector along with its cached euclidean norm.
+///
+/// The norm is computed once, when the vector is created, instead of every
+/// time a distance is calculated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NormedVec<T: Number> {
+    /// The elements of the vector.
+    values: Vec<T>,
+    /// The euclidean norm of the vector.
+    norm: f64,
+}
+
+impl<T: Number> NormedVec<T> {
+    /// Creates a new `NormedVec`, computing its norm.
+    ///
+    /// # Arguments
+    ///
+    /// * `values` - The elements of the vector.
+    #[must_use]
+    pub fn new(values: Vec<T>) -> Self {
+        let norm = values.iter().map(|&v| v.as_f64().powi(2)).sum::<f64>().sqrt();
+        Self { values, norm }
+    }
+
+    /// Returns the elements of the vector.
+    #[must_use]
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+
+    /// Returns the euclidean norm of the vector.
+    #[must_use]
+    pub const fn norm(&self) -> f64 {
+        self.norm
+    }
+}
+
+impl<T: Number> From<Vec<T>> for NormedVec<T> {
+    fn from(values: Vec<T>) -> Self {
+        Self::new(values)
+    }
+}
+
+impl<T: Number> Instance for NormedVec<T> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.values.to_bytes()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        Vec::<T>::from_bytes(bytes).map(Self::new)
+    }
+
+    fn type_name() -> String {
+        format!("NormedVec<{}>", T::type_name())
+    }
+}
+
+/// Computes the cosine distance between two vectors, using their cached norms.
+///
+/// The cosine distance is defined as `1.0 - c` where `c` is the cosine
+/// similarity. If either vector has a norm of zero, the distance is `1.0`.
+///
+/// This is not a metric because it does not obey the triangle inequality. Only
+/// `Linear` and `Approximate` knn search should be used with it.
+///
+/// # Arguments
+///
+/// * `x` - A vector with its norm.
+/// * `y` - A vector with its norm.
+#[must_use]
+pub fn cosine<T: Number, U: Float>(x: &NormedVec<T>, y: &NormedVec<T>) -> U {
+    let denominator = x.norm * y.norm;
+    if denominator < f64::EPSILON {
+        return U::one();
+    }
+
+    let xy = x
+        .values
+        .iter()
+        .zip(y.values.iter())
+        .fold(0.0, |xy, (&a, &b)| a.as_f64().mul_add(b.as_f64(), xy));
+
+    let d = 1.0 - xy / denominator;
+    if d < f64::EPSILON {
+        U::zero()
+    } else {
+        U::from(d)
+    }
+}
+
+/// Returns the `cosine` distance function, declared as a non-metric.
+#[must_use]
+pub fn cosine_metric<T: Number, U: Float>() -> FnMetric<NormedVec<T>, U> {
+    FnMetric::new(cosine).with_is_metric(false)
+}
+
+/// Computes the euclidean distance between two dense vectors, accumulating in
+/// the distance type `U`.
+///
+/// The elements are widened to `U` before they are subtracted, so this cannot
+/// overflow for vectors of narrow integers, e.g. `euclidean::<u8, f32>` for
+/// byte-quantized vectors. `U` must be able to hold every element. See the
+/// `distances::vectors` module.
+///
+/// This is a metric, so all knn algorithms may be used with it.
+///
+/// # Arguments
+///
+/// * `x` - A vector.
+/// * `y` - A vector.
+#[allow(clippy::ptr_arg)]
+#[must_use]
+pub fn euclidean<T: Number, U: Float>(x: &Vec<T>, y: &Vec<T>) -> U {
+    distances::vectors::euclidean(x, y)
+}
+
+/// Returns the `euclidean` distance function, declared as a metric.
+///
+/// Distances between vectors of `f32`s or `f64`s are computed with the SIMD
+/// kernels in `distances::simd`, in the precision of the elements.
+#[must_use]
+pub fn euclidean_metric<T: Number, U: Float>() -> FnMetric<Vec<T>, U> {
+    FnMetric::new(euclidean).with_kernel(SimdKernel::Euclidean)
+}
+
+/// Euclidean distance between two dense vectors of `f32`.
+#[allow(clippy::ptr_arg)]
+fn dense_euclidean(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    distances::simd::euclidean_f32(x, y)
+}
+
+/// Manhattan distance between two dense vectors of `f32`.
+#[allow(clippy::ptr_arg)]
+fn dense_manhattan(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    distances::vectors::manhattan(x, y)
+}
+
+/// Cosine distance between two dense vectors of `f32`.
+#[allow(clippy::ptr_arg)]
+fn dense_cosine(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    distances::simd::cosine_f32(x, y)
+}
+
+/// Returns a distance function for dense vectors of `f32` by name.
+///
+/// This is used by the language bindings, which pass metrics by name.
+///
+/// # Arguments
+///
+/// * `name` - One of `"euclidean"`, `"manhattan"` (or `"cityblock"`) and
+///   `"cosine"`, case-insensitive. Only `"cosine"` is declared a non-metric.
+///
+/// # Errors
+///
+/// * If the name is not recognized.
+pub fn dense_metric(name: &str) -> Result<FnMetric<Vec<f32>, f32>, String> {
+    match name.to_lowercase().as_str() {
+        "euclidean" => Ok(FnMetric::new(dense_euclidean).with_kernel(SimdKernel::Euclidean)),
+        "manhattan" | "cityblock" => Ok(FnMetric::new(dense_manhattan)),
+        "cosine" => Ok(FnMetric::new(dense_cosine)
+            .with_is_metric(false)
+            .with_kernel(SimdKernel::Cosine)),
+        _ => Err(format!("Unknown metric: {name}")),
+    }
+}
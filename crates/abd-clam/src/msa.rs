@@ -0,0 +1,297 @@
+//! Progressive multiple sequence alignment guided by the cluster tree.
+//!
+//! The hierarchy of a `Tree` over sequences is used as the guide tree. The
+//! sequences in each leaf are aligned to the center of the leaf. Then, from
+//! the leaves up, the alignments of the children of each `Cluster` are merged
+//! by aligning their centers to the center of the parent. Each merge keeps the
+//! columns of both alignments, so a gap, once inserted, is never removed.
+//!
+//! Pairs of sequences are aligned by minimizing the number of substitutions,
+//! insertions and deletions, i.e. under the Levenshtein distance.
+
+use distances::Number;
+
+use crate::{par::prelude::*, Cluster, Dataset, Instance, Tree};
+
+/// The byte used for gaps in an `Alignment`.
+pub const GAP: u8 = b'-';
+
+/// A multiple sequence alignment.
+///
+/// Every row has the same width. Removing the gaps from a row gives back the
+/// sequence from which it was aligned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alignment {
+    /// The original indices of the aligned sequences, in increasing order.
+    indices: Vec<usize>,
+    /// The aligned sequences, in the same order as `indices`.
+    rows: Vec<Vec<u8>>,
+}
+
+impl Alignment {
+    /// Returns the number of aligned sequences.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Returns whether there are no aligned sequences.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Returns the number of columns in the alignment.
+    #[must_use]
+    pub fn width(&self) -> usize {
+        self.rows.first().map_or(0, Vec::len)
+    }
+
+    /// Returns the original indices of the aligned sequences, in increasing
+    /// order.
+    #[must_use]
+    pub fn indices(&self) -> &[usize] {
+        &self.indices
+    }
+
+    /// Returns the aligned sequences, in the same order as `indices`.
+    #[must_use]
+    pub fn rows(&self) -> &[Vec<u8>] {
+        &self.rows
+    }
+
+    /// Returns the aligned sequence for an original index, if it is in the
+    /// alignment.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The original index of a sequence.
+    #[must_use]
+    pub fn row(&self, index: usize) -> Option<&[u8]> {
+        self.indices.binary_search(&index).ok().map(|i| self.rows[i].as_slice())
+    }
+}
+
+/// Aligns all sequences in a tree, except those which were removed.
+///
+/// The sequences must not contain the `GAP` byte.
+///
+/// # Arguments
+///
+/// * `tree` - A tree over sequences. The better the tree, i.e. the more
+///   similar the sequences in each `Cluster` are to its center, the better the
+///   alignment.
+#[must_use]
+pub fn align<I, U, D, C>(tree: &Tree<I, U, D, C>) -> Alignment
+where
+    I: Instance + AsRef<[u8]>,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let data = tree.data();
+    let block = align_cluster(data, tree.root());
+
+    let mut rows = block
+        .rows
+        .into_iter()
+        .filter(|&(i, _)| !tree.is_removed(i))
+        .map(|(i, row)| (data.original_index(i), row))
+        .collect::<Vec<_>>();
+    rows.sort_by_key(|&(i, _)| i);
+
+    // Columns which only held gaps and removed sequences are dropped.
+    let width = rows.first().map_or(0, |(_, row)| row.len());
+    let kept = (0..width)
+        .filter(|&c| rows.iter().any(|(_, row)| row[c] != GAP))
+        .collect::<Vec<_>>();
+
+    let (indices, rows) = rows
+        .into_iter()
+        .map(|(i, row)| (i, kept.iter().map(|&c| row[c]).collect()))
+        .unzip();
+    Alignment { indices, rows }
+}
+
+/// The alignment of the sequences in a `Cluster`, as the dataset index and
+/// aligned sequence of each row.
+struct Block {
+    /// The rows of the alignment.
+    rows: Vec<(usize, Vec<u8>)>,
+}
+
+impl Block {
+    /// Creates an alignment of a single sequence.
+    fn single<I: Instance + AsRef<[u8]>, U: Number, D: Dataset<I, U>>(data: &D, index: usize) -> Self {
+        let instance = data.get(index);
+        Self {
+            rows: vec![(index, <I as AsRef<[u8]>>::as_ref(&instance).to_vec())],
+        }
+    }
+
+    /// Returns the aligned sequence at a dataset index, which must be a row in
+    /// the alignment.
+    fn anchor(&self, index: usize) -> &[u8] {
+        self.rows
+            .iter()
+            .find_map(|(i, row)| (*i == index).then_some(row.as_slice()))
+            .unwrap_or_default()
+    }
+
+    /// Merges two alignments by aligning the sequence at `anchor` in `self`
+    /// with that at `other_anchor` in `other`.
+    fn merge(mut self, anchor: usize, other: Self, other_anchor: usize) -> Self {
+        let (left, right) = (self.anchor(anchor), other.anchor(other_anchor));
+        let steps = {
+            let x = left.iter().copied().filter(|&b| b != GAP).collect::<Vec<_>>();
+            let y = right.iter().copied().filter(|&b| b != GAP).collect::<Vec<_>>();
+            align_pair(&x, &y)
+        };
+
+        // The columns of the merged alignment, as columns of `self` and of
+        // `other`. Columns in which an anchor has a gap are kept as they are,
+        // and the others are placed by the alignment of the anchors.
+        let mut columns = Vec::with_capacity(left.len() + right.len());
+        let (mut i, mut j) = (0, 0);
+        for step in steps.into_iter().map(Some).chain(core::iter::once(None)) {
+            while i < left.len() && left[i] == GAP {
+                columns.push((Some(i), None));
+                i += 1;
+            }
+            while j < right.len() && right[j] == GAP {
+                columns.push((None, Some(j)));
+                j += 1;
+            }
+            match step {
+                Some(Step::Match) => {
+                    columns.push((Some(i), Some(j)));
+                    i += 1;
+                    j += 1;
+                }
+                Some(Step::Delete) => {
+                    columns.push((Some(i), None));
+                    i += 1;
+                }
+                Some(Step::Insert) => {
+                    columns.push((None, Some(j)));
+                    j += 1;
+                }
+                None => (),
+            }
+        }
+
+        let spread = |row: &[u8], pick: fn(&Column) -> Option<usize>| {
+            columns
+                .iter()
+                .map(|c| pick(c).map_or(GAP, |c| row[c]))
+                .collect::<Vec<_>>()
+        };
+        for (_, row) in &mut self.rows {
+            *row = spread(row, |&(l, _)| l);
+        }
+        self.rows
+            .extend(other.rows.into_iter().map(|(i, row)| (i, spread(&row, |&(_, r)| r))));
+        self
+    }
+}
+
+/// A column of a merged alignment, as the columns of the two alignments which
+/// were merged, or `None` for a gap.
+type Column = (Option<usize>, Option<usize>);
+
+/// Aligns the sequences in a `Cluster`, with its center as the anchor.
+fn align_cluster<I, U, D, C>(data: &D, c: &C) -> Block
+where
+    I: Instance + AsRef<[u8]>,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let center = c.arg_center();
+    let Some(children) = c.children() else {
+        return c
+            .indices()
+            .filter(|&i| i != center)
+            .fold(Block::single(data, center), |block, i| {
+                block.merge(center, Block::single(data, i), i)
+            });
+    };
+
+    let mut blocks = children
+        .into_par_iter()
+        .map(|child| {
+            (
+                child.arg_center(),
+                child.indices().contains(&center),
+                align_cluster(data, child),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // The child holding the center of the parent is the one to which the
+    // others are merged.
+    let first = blocks.iter().position(|&(_, has_center, _)| has_center).unwrap_or(0);
+    let (first_center, _, first) = blocks.swap_remove(first);
+    let anchor = if first.rows.iter().any(|&(i, _)| i == center) {
+        center
+    } else {
+        first_center
+    };
+    blocks.into_iter().fold(first, |block, (child_center, _, child)| {
+        block.merge(anchor, child, child_center)
+    })
+}
+
+/// A step in the alignment of two sequences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    /// A byte of each sequence in the same column.
+    Match,
+    /// A byte of the first sequence against a gap.
+    Delete,
+    /// A byte of the second sequence against a gap.
+    Insert,
+}
+
+/// Aligns two sequences with the fewest substitutions, insertions and
+/// deletions, returning the steps of the alignment from the start.
+fn align_pair(x: &[u8], y: &[u8]) -> Vec<Step> {
+    let (x_len, y_len) = (x.len(), y.len());
+
+    // The cost of aligning the first `i` bytes of `x` with the first `j` bytes
+    // of `y` is at `costs[i * (y_len + 1) + j]`.
+    let mut costs = vec![0_usize; (x_len + 1) * (y_len + 1)];
+    let at = |i: usize, j: usize| i * (y_len + 1) + j;
+    let mismatch = |i: usize, j: usize| <usize as From<bool>>::from(x[i - 1] != y[j - 1]);
+    for j in 0..=y_len {
+        costs[at(0, j)] = j;
+    }
+    for i in 1..=x_len {
+        costs[at(i, 0)] = i;
+        for j in 1..=y_len {
+            costs[at(i, j)] = (costs[at(i - 1, j - 1)] + mismatch(i, j))
+                .min(costs[at(i - 1, j)] + 1)
+                .min(costs[at(i, j - 1)] + 1);
+        }
+    }
+
+    // Trace back from the end, preferring substitutions over gaps.
+    let mut steps = Vec::with_capacity(x_len.max(y_len));
+    let (mut i, mut j) = (x_len, y_len);
+    while i > 0 || j > 0 {
+        let cost = costs[at(i, j)];
+        if i > 0 && j > 0 && cost == costs[at(i - 1, j - 1)] + mismatch(i, j) {
+            steps.push(Step::Match);
+            i -= 1;
+            j -= 1;
+        } else if i > 0 && cost == costs[at(i - 1, j)] + 1 {
+            steps.push(Step::Delete);
+            i -= 1;
+        } else {
+            steps.push(Step::Insert);
+            j -= 1;
+        }
+    }
+    steps.reverse();
+    steps
+}
@@ -8,14 +8,16 @@ use core::{
 };
 
 use distances::{number::UInt, Number};
-use rayon::prelude::*;
 use serde::{
     de::{MapAccess, SeqAccess, Visitor},
     ser::SerializeStruct,
     Deserialize, Deserializer, Serialize, Serializer,
 };
 
-use crate::{core::cluster::Children, Cluster, Dataset, Instance, PartitionCriterion, UniBall};
+use crate::par::prelude::*;
+use crate::{
+    core::cluster::Children, Cluster, Dataset, Instance, Metric, PartitionCriterion, PartitionStrategy, UniBall,
+};
 
 /// A `SquishyBall` is a `Cluster` that supports compression.
 #[derive(Debug, Clone)]
@@ -48,23 +50,18 @@ impl<U: UInt> SquishyBall<U> {
         match uni_ball.children {
             Some(children) => {
                 uni_ball.children = None;
-                let (left, right) = rayon::join(
-                    || Box::new(Self::from_uni_ball(*children.left, data)),
-                    || Box::new(Self::from_uni_ball(*children.right, data)),
-                );
+                let clusters = children
+                    .clusters
+                    .into_par_iter()
+                    .map(|c| Box::new(Self::from_uni_ball(*c, data)))
+                    .collect::<Vec<_>>();
 
                 let recursive_cost = {
                     // TODO: Incorporate the `bytes_per_unit_distance` into the cost calculation.
-                    let [l_center, r_center, c_center] = [
-                        &data[left.arg_center()],
-                        &data[right.arg_center()],
-                        &data[uni_ball.arg_center()],
-                    ];
-                    let (l_cost, r_cost) = rayon::join(
-                        || Number::as_u64(data.metric()(c_center, l_center)),
-                        || Number::as_u64(data.metric()(c_center, r_center)),
-                    );
-                    l_cost + left.min_cost + r_cost + right.min_cost
+                    clusters
+                        .par_iter()
+                        .map(|c| Number::as_u64(data.one_to_one(uni_ball.arg_center(), c.arg_center())) + c.min_cost)
+                        .sum::<u64>()
                 };
 
                 let (squish, min_cost) = if unitary_cost <= recursive_cost {
@@ -74,11 +71,10 @@ impl<U: UInt> SquishyBall<U> {
                 };
 
                 let children = Children {
-                    left,
-                    right,
-                    arg_l: children.arg_l,
-                    arg_r: children.arg_r,
+                    clusters,
+                    arg_poles: children.arg_poles,
                     polar_distance: children.polar_distance,
+                    strategy: children.strategy,
                 };
 
                 Self {
@@ -145,8 +141,9 @@ impl<U: UInt> SquishyBall<U> {
         if !self.squish {
             if let Some(children) = self.children.as_ref() {
                 // If the cluster has children, recursively check the children.
-                clusters.extend(children.left.compressible_subtree());
-                clusters.extend(children.right.compressible_subtree());
+                for c in &children.clusters {
+                    clusters.extend(c.compressible_subtree());
+                }
             }
         }
         clusters
@@ -161,8 +158,9 @@ impl<U: UInt> SquishyBall<U> {
             clusters.push(self);
         } else if let Some(children) = self.children.as_ref() {
             // If the cluster has children, recursively check the children.
-            clusters.extend(children.left.compressible_leaves());
-            clusters.extend(children.right.compressible_leaves());
+            for c in &children.clusters {
+                clusters.extend(c.compressible_leaves());
+            }
         }
         clusters
     }
@@ -176,8 +174,9 @@ impl<U: UInt> SquishyBall<U> {
             clusters.push(self);
         } else if let Some(children) = self.children.as_mut() {
             // If the cluster has children, recursively check the children.
-            clusters.extend(children.left.as_mut().compressible_leaves_mut());
-            clusters.extend(children.right.as_mut().compressible_leaves_mut());
+            for c in &mut children.clusters {
+                clusters.extend(c.as_mut().compressible_leaves_mut());
+            }
         }
         clusters
     }
@@ -187,9 +186,11 @@ impl<U: UInt> SquishyBall<U> {
     /// The cost is estimated as the sum of distances from the center to all instances in the cluster.
     fn calculate_unitary_cost<I: Instance, D: Dataset<I, U>>(c: &UniBall<U>, data: &D) -> u64 {
         // TODO: Incorporate the `bytes_per_unit_distance` into the cost calculation.
-        let center = &data[c.arg_center()];
-        let instances = c.indices().into_par_iter().map(|i| &data[i]);
-        let distances = instances.map(|i| data.metric()(center, i)).map(Number::as_u64);
+        let center = data.get(c.arg_center());
+        let instances = c.indices().into_par_iter().map(|i| data.get(i));
+        let distances = instances
+            .map(|i| data.metric().distance(&center, &i))
+            .map(Number::as_u64);
         distances.sum()
     }
 
@@ -198,7 +199,7 @@ impl<U: UInt> SquishyBall<U> {
         if self.squish {
             self.children = None;
         } else if let Some(children) = self.children.as_mut() {
-            rayon::join(|| children.left.trim(), || children.right.trim());
+            children.clusters.par_iter_mut().for_each(|c| c.trim());
         }
     }
 }
@@ -217,6 +218,24 @@ impl<U: UInt> Cluster<U> for SquishyBall<U> {
         }
     }
 
+    fn new_root_with<I, D, P>(data: &D, criteria: &P, seed: Option<u64>) -> Self
+    where
+        I: Instance,
+        D: Dataset<I, U>,
+        P: PartitionCriterion<U>,
+    {
+        let uni_ball = UniBall::new_root_with(data, criteria, seed);
+        Self {
+            uni_ball,
+            recursive_cost: 0,
+            unitary_cost: 0,
+            min_cost: 0,
+            children: None,
+            squish: false,
+            codec_offset: None,
+        }
+    }
+
     fn partition<I, D, P>(self, data: &mut D, criteria: &P, seed: Option<u64>) -> Self
     where
         I: Instance,
@@ -255,17 +274,23 @@ impl<U: UInt> Cluster<U> for SquishyBall<U> {
         self.uni_ball.lfd()
     }
 
-    fn children(&self) -> Option<[&Self; 2]> {
-        self.children.as_ref().map(|c| [c.left.as_ref(), c.right.as_ref()])
+    fn children(&self) -> Option<Vec<&Self>> {
+        self.children
+            .as_ref()
+            .map(|c| c.clusters.iter().map(AsRef::as_ref).collect())
     }
 
     fn polar_distance(&self) -> Option<U> {
         self.uni_ball.polar_distance()
     }
 
-    fn arg_poles(&self) -> Option<[usize; 2]> {
+    fn arg_poles(&self) -> Option<Vec<usize>> {
         self.uni_ball.arg_poles()
     }
+
+    fn strategy(&self) -> Option<PartitionStrategy> {
+        self.children.as_ref().map(|c| c.strategy)
+    }
 }
 
 impl<U: UInt> PartialEq for SquishyBall<U> {
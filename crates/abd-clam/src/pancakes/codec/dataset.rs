@@ -7,7 +7,7 @@ use std::{
 
 use distances::{number::UInt, Number};
 
-use crate::{Cluster, Dataset, Instance};
+use crate::{Cluster, Dataset, FnMetric, Instance, Metric, SharedMetric};
 
 use super::{DecoderFn, EncoderFn, SquishyBall};
 
@@ -22,10 +22,8 @@ pub struct CodecData<I: Instance, U: UInt, M: Instance> {
     encoder: EncoderFn<I>,
     /// The compressed data for the squished clusters.
     leaf_data: LeafData<I>,
-    /// The distance function.
-    metric: fn(&I, &I) -> U,
-    /// Whether the distance function is expensive to compute.
-    is_expensive: bool,
+    /// The distance function and its properties.
+    metric: SharedMetric<I, U>,
     /// Metadata for the dataset.
     metadata: Vec<M>,
     /// The reordering of the dataset after building the tree.
@@ -69,7 +67,7 @@ impl<I: Instance, U: UInt, M: Instance> CodecData<I, U, M> {
         let centers = subtree.iter().map(|c| c.arg_center()).collect::<HashSet<_>>();
         let centers = centers
             .into_iter()
-            .map(|i| (i, data[i].clone()))
+            .map(|i| (i, data.get(i).into_owned()))
             .collect::<HashMap<_, _>>();
 
         // Build the leaves' data
@@ -79,10 +77,10 @@ impl<I: Instance, U: UInt, M: Instance> CodecData<I, U, M> {
             leaf.set_codec_offset(bytes.len());
 
             // Encode the points in the leaf in terms of the center.
-            let center = &data[leaf.arg_center()];
+            let center = data.get(leaf.arg_center());
             let encodings = leaf
                 .indices()
-                .map(|i| encoder(center, &data[i]))
+                .map(|i| encoder(&center, &data.get(i)))
                 .collect::<Result<Vec<_>, _>>()?;
 
             // Write the number of encodings.
@@ -104,8 +102,7 @@ impl<I: Instance, U: UInt, M: Instance> CodecData<I, U, M> {
             centers,
             encoder,
             leaf_data,
-            metric: data.metric(),
-            is_expensive: data.is_metric_expensive(),
+            metric: data.metric().clone(),
             metadata,
             permuted_indices,
         })
@@ -150,14 +147,14 @@ impl<I: Instance, U: UInt, M: Instance> CodecData<I, U, M> {
         &self.permuted_indices
     }
 
-    /// Returns the distance function.
-    pub fn metric(&self) -> fn(&I, &I) -> U {
-        self.metric
+    /// Returns the distance function and its properties.
+    pub const fn metric(&self) -> &SharedMetric<I, U> {
+        &self.metric
     }
 
     /// Returns whether the distance function is expensive to compute.
-    pub const fn is_expensive(&self) -> bool {
-        self.is_expensive
+    pub fn is_expensive(&self) -> bool {
+        self.metric.is_expensive()
     }
 
     /// Saves the `CodecData` to disk.
@@ -303,8 +300,7 @@ impl<I: Instance, U: UInt, M: Instance> CodecData<I, U, M> {
             centers,
             encoder,
             leaf_data,
-            metric,
-            is_expensive,
+            metric: FnMetric::new(metric).with_is_expensive(is_expensive).into(),
             metadata,
             permuted_indices,
         })
@@ -351,11 +347,11 @@ fn encode_centers<I: Instance, U: UInt>(
 fn index_pairs<U: UInt>(c: &SquishyBall<U>) -> Vec<(usize, usize)> {
     let mut pairs = Vec::new();
     if !c.squish() {
-        if let Some([left, right]) = c.children() {
-            pairs.push((c.arg_center(), left.arg_center()));
-            pairs.push((c.arg_center(), right.arg_center()));
-            pairs.append(&mut index_pairs(left));
-            pairs.append(&mut index_pairs(right));
+        if let Some(children) = c.children() {
+            pairs.extend(children.iter().map(|child| (c.arg_center(), child.arg_center())));
+            for child in children {
+                pairs.append(&mut index_pairs(child));
+            }
         }
     }
     pairs
@@ -2,6 +2,7 @@
 
 mod cluster;
 mod dataset;
+mod sparse;
 
 pub use cluster::SquishyBall;
 #[allow(clippy::module_name_repetitions)]
@@ -13,6 +14,7 @@ use distances::{
         Penalties,
     },
 };
+pub use sparse::{decode_sparse, encode_sparse};
 
 /// A function that encodes a `Instance` into a `Box<[u8]>`.
 pub type EncoderFn<I> = fn(&I, &I) -> Result<Box<[u8]>, String>;
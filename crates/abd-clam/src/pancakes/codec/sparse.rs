@@ -0,0 +1,87 @@
+//! Sparse-delta compression for vectors.
+
+use distances::Number;
+
+/// Encodes a target vector as a sparse delta from a reference vector.
+///
+/// The encoding starts with the length of the target, as a 64-bit integer so
+/// that it may be decoded on any platform, followed by a pair for
+/// each position at which the target differs from the reference. Each pair is
+/// the position, as a 32-bit integer, and the value of the target at that
+/// position. Values are stored exactly, so decoding is lossless.
+///
+/// # Arguments
+///
+/// * `reference`: The reference vector.
+/// * `target`: The target vector.
+///
+/// # Errors
+///
+/// * If the target is too long for its positions to fit in 32 bits.
+///
+/// # Returns
+///
+/// A byte array encoding the target in terms of the reference.
+#[allow(clippy::ptr_arg)]
+pub fn encode_sparse<T: Number>(reference: &Vec<T>, target: &Vec<T>) -> Result<Box<[u8]>, String> {
+    let Ok(len) = u32::try_from(target.len()) else {
+        return Err(format!("Vector is too long to encode: {}", target.len()));
+    };
+
+    let mut bytes = <u64 as From<u32>>::from(len).to_le_bytes().to_vec();
+    for (i, &t) in target.iter().enumerate() {
+        if reference.get(i).map_or(true, |&r| r != t) {
+            #[allow(clippy::cast_possible_truncation)]
+            bytes.extend_from_slice(&(i as u32).to_le_bytes());
+            bytes.extend_from_slice(&t.to_le_bytes());
+        }
+    }
+
+    Ok(bytes.into_boxed_slice())
+}
+
+/// Decodes a target vector from a reference vector and a sparse delta.
+///
+/// # Arguments
+///
+/// * `reference`: The reference vector.
+/// * `encoding`: The byte array encoding the target, as produced by `encode_sparse`.
+///
+/// # Errors
+///
+/// * If the byte array is not a valid encoding of a sparse delta.
+///
+/// # Returns
+///
+/// The target vector.
+#[allow(clippy::ptr_arg)]
+pub fn decode_sparse<T: Number>(reference: &Vec<T>, encoding: &[u8]) -> Result<Vec<T>, String> {
+    let header = u64::num_bytes();
+    if encoding.len() < header {
+        return Err("Encoding is too short to contain a length.".to_string());
+    }
+    let len = <u64 as Number>::from_le_bytes(&encoding[..header]);
+    let len = usize::try_from(len).map_err(|_| format!("Encoded length {len} is too large for this platform."))?;
+
+    let pair = 4 + T::num_bytes();
+    let deltas = &encoding[header..];
+    if deltas.len() % pair != 0 {
+        return Err(format!(
+            "Encoding has a partial delta: {} trailing bytes.",
+            deltas.len() % pair
+        ));
+    }
+
+    let mut target = reference.iter().copied().take(len).collect::<Vec<_>>();
+    target.resize(len, T::zero());
+    for chunk in deltas.chunks_exact(pair) {
+        let (index, value) = chunk.split_at(4);
+        let index = u32::from_le_bytes([index[0], index[1], index[2], index[3]]) as usize;
+        if index >= len {
+            return Err(format!("Delta index {index} is out of bounds for length {len}."));
+        }
+        target[index] = T::from_le_bytes(value);
+    }
+
+    Ok(target)
+}
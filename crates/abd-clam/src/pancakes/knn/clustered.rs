@@ -0,0 +1,68 @@
+//! Clustered K-NN search in a compressed space.
+
+use distances::number::UInt;
+
+use crate::{
+    cakes::knn::{Hits, RevNumber},
+    pancakes::{CodecData, SquishyBall},
+    Cluster, Instance, Metric,
+};
+
+/// Perform a clustered search in a compressed space.
+///
+/// Clusters are visited in order of the theoretical minimum distance from the
+/// query to any of their instances, so only the squished clusters which might
+/// contain one of the `k` nearest neighbors are ever decompressed.
+pub fn search<I, U, M>(query: &I, k: usize, data: &CodecData<I, U, M>) -> Vec<(usize, U)>
+where
+    I: Instance,
+    U: UInt,
+    M: Instance,
+{
+    let mut candidates = priority_queue::PriorityQueue::<&SquishyBall<U>, RevNumber<U>>::new();
+    let mut hits = Hits::new(k);
+
+    let root = data.root();
+    candidates.push(root, RevNumber(d_min(query, root, data)));
+
+    while let Some((c, RevNumber(d))) = candidates.pop() {
+        if hits.len() == k && hits.peek() <= d {
+            break;
+        }
+
+        if c.squish() {
+            let points = data
+                .load_leaf_data(c)
+                .unwrap_or_else(|e| unreachable!("Leaf data not found: {e}"));
+            points.into_iter().zip(c.indices()).for_each(|(point, index)| {
+                hits.push(index, data.metric().distance(query, &point));
+            });
+        } else {
+            let children = c
+                .children()
+                .unwrap_or_else(|| unreachable!("Non-leaf node without children"));
+            for child in children {
+                candidates.push(child, RevNumber(d_min(query, child, data)));
+            }
+        }
+    }
+
+    hits.extract()
+}
+
+/// Returns the theoretical minimum distance from the query to any instance in
+/// the cluster, using only the cluster's center.
+fn d_min<I, U, M>(query: &I, c: &SquishyBall<U>, data: &CodecData<I, U, M>) -> U
+where
+    I: Instance,
+    U: UInt,
+    M: Instance,
+{
+    let center = &data.centers()[&c.arg_center()];
+    let d = data.metric().distance(center, query);
+    if d < c.radius() {
+        U::zero()
+    } else {
+        d - c.radius()
+    }
+}
@@ -1,6 +1,6 @@
 //! Linear K-NN search in a compressed space.
 
-use crate::{pancakes::CodecData, Cluster, Instance};
+use crate::{pancakes::CodecData, Cluster, Instance, Metric};
 use distances::number::UInt;
 
 use crate::cakes::knn::Hits;
@@ -21,7 +21,7 @@ where
             .load_leaf_data(leaf)
             .unwrap_or_else(|e| unreachable!("Impossible by construction.: {e}"));
         points.into_iter().zip(leaf.indices()).for_each(|(point, index)| {
-            let distance = data.metric()(query, &point);
+            let distance = data.metric().distance(query, &point);
             hits.push(index, distance);
         });
     }
@@ -1,5 +1,6 @@
 //! K-Nearest Neighbors search in a compressed space.
 
+mod clustered;
 mod linear;
 
 use distances::number::UInt;
@@ -12,6 +13,8 @@ use super::CodecData;
 pub enum Algorithm {
     /// Use linear search on the dataset.
     Linear,
+    /// Use a clustered search, which only decompresses the clusters it visits.
+    Clustered,
 }
 
 impl Default for Algorithm {
@@ -41,6 +44,7 @@ impl Algorithm {
     {
         match self {
             Self::Linear => linear::search(query, k, data),
+            Self::Clustered => clustered::search(query, k, data),
         }
     }
 
@@ -49,6 +53,7 @@ impl Algorithm {
     pub const fn name(&self) -> &str {
         match self {
             Self::Linear => "Linear",
+            Self::Clustered => "Clustered",
         }
     }
 
@@ -70,10 +75,17 @@ impl Algorithm {
     pub fn from_name(s: &str) -> Result<Self, String> {
         match s.to_lowercase().as_str() {
             "linear" => Ok(Self::Linear),
+            "clustered" => Ok(Self::Clustered),
             _ => Err(format!("Unknown algorithm: {s}")),
         }
     }
 
+    /// Returns a list of all the algorithms, excluding Linear.
+    #[must_use]
+    pub fn variants() -> Box<[Self]> {
+        vec![Self::Clustered].into_boxed_slice()
+    }
+
     /// Returns the baseline algorithm, which is Linear
     #[must_use]
     pub const fn baseline() -> Self {
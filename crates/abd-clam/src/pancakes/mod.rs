@@ -5,4 +5,6 @@ pub mod knn;
 pub mod rnn;
 mod search;
 
-pub use codec::{decode_general, encode_general, CodecData, DecoderFn, EncoderFn, SquishyBall};
+pub use codec::{
+    decode_general, decode_sparse, encode_general, encode_sparse, CodecData, DecoderFn, EncoderFn, SquishyBall,
+};
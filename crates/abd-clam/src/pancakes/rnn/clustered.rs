@@ -1,11 +1,11 @@
 //! Clustered Ranged Nearest Neighbor search in a compressed space.
 
 use distances::number::UInt;
-use rayon::prelude::*;
 
+use crate::par::prelude::*;
 use crate::{
     pancakes::{CodecData, SquishyBall},
-    Cluster, Instance,
+    Cluster, Instance, Metric,
 };
 
 /// Perform a clustered search in a compressed space.
@@ -49,7 +49,7 @@ where
             .into_par_iter()
             .map(|c| {
                 let center = &data.centers()[&c.arg_center()];
-                let distance = data.metric()(center, query);
+                let distance = data.metric().distance(center, query);
                 (c, distance)
             })
             .filter(|&(c, d)| d <= (c.radius() + radius))
@@ -65,10 +65,10 @@ where
                 // if d < c.radius() {
                 //     c.overlapping_children(data, query, radius)
                 // } else {
-                //     c.children().map_or_else(|| unreachable!("Non-leaf node without children"), |v| v.to_vec())
+                //     c.children().unwrap_or_else(|| unreachable!("Non-leaf node without children"))
                 // }
                 c.children()
-                    .map_or_else(|| unreachable!("Non-leaf node without children"), |v| v.to_vec())
+                    .unwrap_or_else(|| unreachable!("Non-leaf node without children"))
             })
             .collect();
     }
@@ -98,7 +98,10 @@ where
                 let points = data
                     .load_leaf_data(leaf)
                     .unwrap_or_else(|e| unreachable!("Leaf data not found: {e}"));
-                points.into_iter().map(|p| data.metric()(query, &p)).zip(leaf.indices())
+                points
+                    .into_iter()
+                    .map(|p| data.metric().distance(query, &p))
+                    .zip(leaf.indices())
             })
             .map(|(d, i)| (i, d))
     });
@@ -112,7 +115,10 @@ where
                     let points = data
                         .load_leaf_data(leaf)
                         .unwrap_or_else(|e| unreachable!("Leaf data not found: {e}"));
-                    points.into_iter().map(|p| data.metric()(query, &p)).zip(leaf.indices())
+                    points
+                        .into_iter()
+                        .map(|p| data.metric().distance(query, &p))
+                        .zip(leaf.indices())
                 })
                 .filter(|(d, _)| *d <= radius)
                 .map(|(d, i)| (i, d))
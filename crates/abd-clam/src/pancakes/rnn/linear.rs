@@ -1,9 +1,9 @@
 //! Linear RNN search in a compressed space.
 
 use distances::number::UInt;
-use rayon::prelude::*;
 
-use crate::{pancakes::CodecData, Cluster, Instance};
+use crate::par::prelude::*;
+use crate::{pancakes::CodecData, Cluster, Instance, Metric};
 
 /// Perform a linear search in a compressed space.
 pub fn search<I, U, M>(query: &I, radius: U, data: &CodecData<I, U, M>) -> Vec<(usize, U)>
@@ -24,7 +24,7 @@ where
                 .into_par_iter()
                 .zip(leaf.indices().into_par_iter())
                 .filter_map(|(point, index)| {
-                    let distance = data.metric()(query, &point);
+                    let distance = data.metric().distance(query, &point);
                     if distance <= radius {
                         Some((index, distance))
                     } else {
@@ -42,12 +42,12 @@ impl<I: Instance, U: UInt, M: Instance> CodecData<I, U, M> {
 
 #[cfg(test)]
 mod tests {
-    use distances::strings::levenshtein;
+    use distances::{strings::levenshtein, Number};
 
     use super::*;
 
     use crate::{
-        pancakes::{decode_general, encode_general, CodecData, SquishyBall},
+        pancakes::{decode_general, decode_sparse, encode_general, encode_sparse, CodecData, SquishyBall},
         Cluster, PartitionCriteria, VecDataset,
     };
 
@@ -113,7 +113,7 @@ mod tests {
         let query = "NAJIBEATSPEPPERS".to_string();
         let k = 2;
 
-        for algo in [knn::Algorithm::Linear] {
+        for algo in [knn::Algorithm::Linear, knn::Algorithm::Clustered] {
             let result = codec_dataset.knn_search(&query, k, &algo);
 
             println!("{}: {result:?}", algo.name());
@@ -125,4 +125,73 @@ mod tests {
 
         Ok(())
     }
+
+    #[allow(clippy::ptr_arg)]
+    fn hamming_metric(x: &Vec<u8>, y: &Vec<u8>) -> u32 {
+        distances::vectors::hamming(x, y)
+    }
+
+    #[test]
+    fn test_sparse_codec() -> Result<(), String> {
+        let reference = vec![1_u8, 2, 3, 4, 5, 6, 7, 8];
+        for target in [
+            vec![1_u8, 2, 3, 4, 5, 6, 7, 8],
+            vec![1, 2, 0, 4, 5, 6, 9, 8],
+            vec![1, 2, 3],
+            vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+        ] {
+            let encoding = encode_sparse(&reference, &target)?;
+            assert_eq!(decode_sparse(&reference, &encoding)?, target);
+        }
+
+        // Only the two differing positions are stored.
+        let encoding = encode_sparse(&reference, &vec![1, 2, 0, 4, 5, 6, 9, 8])?;
+        assert_eq!(encoding.len(), usize::num_bytes() + 2 * (4 + 1));
+
+        assert!(decode_sparse::<u8>(&reference, &encoding[..encoding.len() - 1]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sparse_knn_search() -> Result<(), String> {
+        let base = (0..32).collect::<Vec<u8>>();
+        let vectors = (0..64_u8)
+            .map(|i| {
+                let mut v = base.clone();
+                v[<usize as From<u8>>::from(i % 32)] = 100 + i;
+                if i >= 32 {
+                    v[0] = 200;
+                }
+                v
+            })
+            .collect::<Vec<_>>();
+
+        let mut dataset = VecDataset::new("test-sparse".to_string(), vectors, hamming_metric, false);
+        let criteria = PartitionCriteria::default();
+        let seed = Some(42);
+        let root = SquishyBall::new_root(&dataset, seed).partition(&mut dataset, &criteria, seed);
+
+        let metadata = dataset.metadata().to_vec();
+        let codec_dataset = CodecData::new(root, &dataset, encode_sparse::<u8>, decode_sparse::<u8>, metadata)?;
+
+        let query = base;
+        let k = 5;
+
+        let linear = codec_dataset.knn_search(&query, k, &knn::Algorithm::Linear);
+        let clustered = codec_dataset.knn_search(&query, k, &knn::Algorithm::Clustered);
+
+        let mut linear_distances = linear.iter().map(|&(_, d)| d).collect::<Vec<_>>();
+        let mut clustered_distances = clustered.iter().map(|&(_, d)| d).collect::<Vec<_>>();
+        linear_distances.sort_unstable();
+        clustered_distances.sort_unstable();
+        assert_eq!(linear_distances, clustered_distances);
+        assert_eq!(clustered_distances, vec![1; k]);
+
+        for (i, d) in clustered {
+            assert_eq!(hamming_metric(&query, &dataset[i]), d);
+        }
+
+        Ok(())
+    }
 }
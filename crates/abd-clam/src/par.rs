@@ -0,0 +1,90 @@
+//! Parallel iteration with `rayon`, or sequential iteration in its place.
+//!
+//! With the `parallel` feature, which is enabled by default, this re-exports
+//! the parts of `rayon` used in the crate. Without it, e.g. for WebAssembly,
+//! the same names run sequentially on the calling thread, so the rest of the
+//! crate is written against this module instead of `rayon`.
+
+#[cfg(feature = "parallel")]
+pub use rayon::{current_num_threads, prelude, ThreadPool, ThreadPoolBuilder};
+
+#[cfg(not(feature = "parallel"))]
+pub use sequential::{current_num_threads, prelude};
+
+/// Sequential stand-ins for the parts of `rayon` used in the crate.
+#[cfg(not(feature = "parallel"))]
+mod sequential {
+    /// Returns the number of threads, which is always one.
+    pub const fn current_num_threads() -> usize {
+        1
+    }
+
+    /// Iterator traits with the method names of `rayon::prelude`.
+    pub mod prelude {
+        /// Sequential stand-in for `rayon::iter::IntoParallelIterator`.
+        pub trait IntoParallelIterator {
+            /// The sequential iterator.
+            type Iter: Iterator<Item = Self::Item>;
+            /// The items of the iterator.
+            type Item;
+
+            /// Converts `self` into an iterator.
+            fn into_par_iter(self) -> Self::Iter;
+        }
+
+        impl<T: IntoIterator> IntoParallelIterator for T {
+            type Iter = T::IntoIter;
+            type Item = T::Item;
+
+            fn into_par_iter(self) -> Self::Iter {
+                self.into_iter()
+            }
+        }
+
+        /// Sequential stand-in for `rayon::iter::IntoParallelRefIterator`.
+        pub trait IntoParallelRefIterator<'a> {
+            /// The sequential iterator.
+            type Iter: Iterator<Item = Self::Item>;
+            /// The items of the iterator.
+            type Item: 'a;
+
+            /// Iterates over references to the items of `self`.
+            fn par_iter(&'a self) -> Self::Iter;
+        }
+
+        impl<'a, T: 'a + ?Sized> IntoParallelRefIterator<'a> for T
+        where
+            &'a T: IntoIterator,
+        {
+            type Iter = <&'a T as IntoIterator>::IntoIter;
+            type Item = <&'a T as IntoIterator>::Item;
+
+            fn par_iter(&'a self) -> Self::Iter {
+                self.into_iter()
+            }
+        }
+
+        /// Sequential stand-in for `rayon::iter::IntoParallelRefMutIterator`.
+        pub trait IntoParallelRefMutIterator<'a> {
+            /// The sequential iterator.
+            type Iter: Iterator<Item = Self::Item>;
+            /// The items of the iterator.
+            type Item: 'a;
+
+            /// Iterates over mutable references to the items of `self`.
+            fn par_iter_mut(&'a mut self) -> Self::Iter;
+        }
+
+        impl<'a, T: 'a + ?Sized> IntoParallelRefMutIterator<'a> for T
+        where
+            &'a mut T: IntoIterator,
+        {
+            type Iter = <&'a mut T as IntoIterator>::IntoIter;
+            type Item = <&'a mut T as IntoIterator>::Item;
+
+            fn par_iter_mut(&'a mut self) -> Self::Iter {
+                self.into_iter()
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! A search index which stores product-quantized codes in the leaves of a tree.
+
+use std::collections::HashMap;
+
+use crate::{
+    cakes::knn::{Hits, RevNumber},
+    Cluster, Dataset, Tree,
+};
+
+use super::{DistanceTable, ProductQuantizer};
+
+/// The codes of the instances in a leaf `Cluster`.
+#[derive(Debug, Clone)]
+struct Leaf {
+    /// The position, in `PqIndex::quantizers`, of the quantizer for the leaf.
+    quantizer: usize,
+    /// The indices of the instances in the leaf.
+    indices: Vec<usize>,
+    /// The codes of the instances, stored contiguously.
+    codes: Vec<u8>,
+}
+
+/// A tree whose leaves store product-quantized codes instead of instances.
+///
+/// The index keeps the tree's clusters and their centers, so that the tree is
+/// traversed with exact distances from the query to the centers, while the
+/// distances to the instances in leaves are computed asymmetrically: the query
+/// is not quantized, and its distances to the centroids of each codebook are
+/// precomputed in a `DistanceTable`. The dataset itself is not needed after
+/// the index is built.
+///
+/// Each subtree rooted at `codebook_depth` has its own `ProductQuantizer`,
+/// trained on only the instances in that subtree. Since these instances are
+/// close together, the codebooks of deeper subtrees fit them more tightly.
+///
+/// The tree must have been built with the euclidean distance. Search is
+/// approximate, since the distances to instances are those to their
+/// reconstructions.
+#[derive(Debug, Clone)]
+pub struct PqIndex<C: Cluster<f32>> {
+    /// The root of the tree.
+    root: C,
+    /// The centers of all clusters, keyed by their indices.
+    centers: HashMap<usize, Vec<f32>>,
+    /// The quantizers of the subtrees rooted at `codebook_depth`.
+    quantizers: Vec<ProductQuantizer>,
+    /// The codes in each leaf, keyed by the offset of the leaf.
+    leaves: HashMap<usize, Leaf>,
+}
+
+impl<C: Cluster<f32>> PqIndex<C> {
+    /// Builds an index by training codebooks on, and encoding, the instances
+    /// in a tree.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree`: The tree to index.
+    /// * `num_subspaces`: The number of subspaces, i.e. bytes per code.
+    /// * `num_centroids`: The number of centroids in each codebook.
+    /// * `codebook_depth`: The depth of the subtrees which have their own
+    ///   codebooks. With `0`, one codebook is trained for the whole tree.
+    ///   Leaves shallower than this depth have their own codebooks.
+    /// * `seed`: The seed for training the codebooks.
+    ///
+    /// # Errors
+    ///
+    /// * See `ProductQuantizer::train`.
+    pub fn new<D: Dataset<Vec<f32>, f32>>(
+        tree: &Tree<Vec<f32>, f32, D, C>,
+        num_subspaces: usize,
+        num_centroids: usize,
+        codebook_depth: usize,
+        seed: Option<u64>,
+    ) -> Result<Self, String> {
+        let (data, root) = (tree.data(), tree.root());
+
+        let centers = root
+            .subtree()
+            .into_iter()
+            .map(|c| (c.arg_center(), data.get(c.arg_center()).into_owned()))
+            .collect();
+
+        let mut quantizers = Vec::new();
+        let mut leaves = HashMap::new();
+
+        let mut stack = vec![root];
+        while let Some(c) = stack.pop() {
+            match c.children() {
+                Some(children) if c.depth() < codebook_depth => stack.extend(children),
+                _ => {
+                    let rows = c.indices().map(|i| data.get(i)).collect::<Vec<_>>();
+                    let instances = rows.iter().map(|row| row.as_slice()).collect::<Vec<_>>();
+                    let quantizer = ProductQuantizer::train(&instances, num_subspaces, num_centroids, seed)?;
+
+                    for leaf in c.subtree().into_iter().filter(|l| l.is_leaf()) {
+                        let indices = leaf.indices().filter(|&i| !tree.is_removed(i)).collect::<Vec<_>>();
+                        let codes = indices.iter().flat_map(|&i| quantizer.encode(&data.get(i))).collect();
+                        let leaf_codes = Leaf {
+                            quantizer: quantizers.len(),
+                            indices,
+                            codes,
+                        };
+                        leaves.insert(leaf.offset(), leaf_codes);
+                    }
+
+                    quantizers.push(quantizer);
+                }
+            }
+        }
+
+        Ok(Self {
+            root: root.clone(),
+            centers,
+            quantizers,
+            leaves,
+        })
+    }
+
+    /// Returns the root of the tree.
+    #[must_use]
+    pub const fn root(&self) -> &C {
+        &self.root
+    }
+
+    /// Returns the quantizers of the subtrees rooted at `codebook_depth`.
+    #[must_use]
+    pub fn quantizers(&self) -> &[ProductQuantizer] {
+        &self.quantizers
+    }
+
+    /// Returns the total number of bytes in the codes of all instances.
+    #[must_use]
+    pub fn code_bytes(&self) -> usize {
+        self.leaves.values().map(|l| l.codes.len()).sum()
+    }
+
+    /// Performs an approximate KNN search.
+    ///
+    /// Clusters are visited in order of the theoretical minimum distance from
+    /// the query to any of their instances, and the search stops once that is
+    /// no smaller than the distance to the `k`-th nearest hit.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query, which is not quantized.
+    /// * `k` - The number of neighbors to search for.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 2-tuples, where the first element is the index of the
+    /// instance and the second element is the asymmetric distance from the
+    /// query to the instance.
+    #[must_use]
+    pub fn knn_search(&self, query: &[f32], k: usize) -> Vec<(usize, f32)> {
+        let mut tables = vec![None::<DistanceTable>; self.quantizers.len()];
+        let mut candidates = priority_queue::PriorityQueue::<&C, RevNumber<f32>>::new();
+        let mut hits = Hits::new(k);
+
+        candidates.push(&self.root, RevNumber(self.d_min(query, &self.root)));
+
+        while let Some((c, RevNumber(d))) = candidates.pop() {
+            if hits.len() == k && hits.peek() <= d {
+                break;
+            }
+
+            if let Some(children) = c.children() {
+                for child in children {
+                    candidates.push(child, RevNumber(self.d_min(query, child)));
+                }
+            } else {
+                let leaf = &self.leaves[&c.offset()];
+                let quantizer = &self.quantizers[leaf.quantizer];
+                let table = tables[leaf.quantizer].get_or_insert_with(|| quantizer.distance_table(query));
+                let codes = leaf.codes.chunks_exact(quantizer.num_subspaces());
+                hits.push_batch(leaf.indices.iter().copied().zip(codes.map(|code| table.distance(code))));
+            }
+        }
+
+        hits.extract()
+    }
+
+    /// Returns the theoretical minimum distance from the query to any instance
+    /// in the cluster.
+    fn d_min(&self, query: &[f32], c: &C) -> f32 {
+        let d = distances::simd::euclidean_f32(&self.centers[&c.arg_center()], query);
+        (d - c.radius()).max(0.)
+    }
+}
@@ -0,0 +1,7 @@
+//! Product quantization of vectors stored in the leaves of a tree.
+
+mod index;
+mod quantizer;
+
+pub use index::PqIndex;
+pub use quantizer::{DistanceTable, ProductQuantizer};
@@ -0,0 +1,243 @@
+//! Training, encoding and asymmetric distances for product quantization.
+
+use core::ops::Range;
+
+use rand::prelude::*;
+
+use crate::par::prelude::*;
+
+/// The number of iterations of Lloyd's algorithm used to train each codebook.
+const KMEANS_ITERATIONS: usize = 16;
+
+/// A product quantizer for vectors of `f32`.
+///
+/// A vector is split into `num_subspaces` contiguous sub-vectors, and each
+/// sub-vector is replaced by the index of its nearest centroid in the
+/// codebook for its subspace. A vector is thus encoded in one byte per
+/// subspace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProductQuantizer {
+    /// The dimensionality of the vectors.
+    dim: usize,
+    /// The ranges of dimensions in each subspace.
+    subspaces: Vec<Range<usize>>,
+    /// The centroids of each subspace, each stored contiguously.
+    codebooks: Vec<Vec<f32>>,
+}
+
+impl ProductQuantizer {
+    /// Trains a product quantizer with k-means on each subspace.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The vectors to train on.
+    /// * `num_subspaces`: The number of subspaces, and so the number of bytes
+    ///   in each code. This may not exceed the dimensionality.
+    /// * `num_centroids`: The number of centroids in each codebook, at most
+    ///   256. If there are fewer training vectors, each is a centroid.
+    /// * `seed`: The seed for choosing the initial centroids.
+    ///
+    /// # Errors
+    ///
+    /// * If `data` is empty.
+    /// * If the vectors do not all have the same dimensionality.
+    /// * If `num_subspaces` is zero or larger than the dimensionality.
+    /// * If `num_centroids` is zero or larger than 256.
+    pub fn train(
+        data: &[&[f32]],
+        num_subspaces: usize,
+        num_centroids: usize,
+        seed: Option<u64>,
+    ) -> Result<Self, String> {
+        let dim = data
+            .first()
+            .ok_or("Cannot train a quantizer on an empty dataset")?
+            .len();
+        if let Some((i, x)) = data.iter().enumerate().find(|(_, x)| x.len() != dim) {
+            return Err(format!("Instance {i} has dimensionality {}, expected {dim}", x.len()));
+        }
+        if num_subspaces == 0 || num_subspaces > dim {
+            return Err(format!(
+                "Number of subspaces must be between 1 and {dim}, got {num_subspaces}"
+            ));
+        }
+        if num_centroids == 0 || num_centroids > 256 {
+            return Err(format!(
+                "Number of centroids must be between 1 and 256, got {num_centroids}"
+            ));
+        }
+
+        let subspaces = (0..num_subspaces)
+            .map(|s| (dim * s / num_subspaces)..(dim * (s + 1) / num_subspaces))
+            .collect::<Vec<_>>();
+
+        let num_centroids = num_centroids.min(data.len());
+        let codebooks = subspaces
+            .par_iter()
+            .enumerate()
+            .map(|(s, range)| {
+                let seed = seed.map(|seed| seed + s as u64);
+                kmeans(data, range, num_centroids, seed)
+            })
+            .collect();
+
+        Ok(Self {
+            dim,
+            subspaces,
+            codebooks,
+        })
+    }
+
+    /// Returns the dimensionality of the vectors.
+    #[must_use]
+    pub const fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Returns the number of subspaces, i.e. the number of bytes in a code.
+    #[must_use]
+    pub fn num_subspaces(&self) -> usize {
+        self.subspaces.len()
+    }
+
+    /// Returns the centroids of a subspace.
+    fn centroids(&self, s: usize) -> impl Iterator<Item = &[f32]> {
+        self.codebooks[s].chunks_exact(self.subspaces[s].len())
+    }
+
+    /// Encodes a vector as the index of the nearest centroid in each subspace.
+    ///
+    /// # Arguments
+    ///
+    /// * `x`: A vector with the dimensionality of the quantizer.
+    #[must_use]
+    pub fn encode(&self, x: &[f32]) -> Vec<u8> {
+        self.subspaces
+            .iter()
+            .enumerate()
+            .map(|(s, range)| {
+                let (c, _) = nearest(self.centroids(s), &x[range.clone()]);
+                #[allow(clippy::cast_possible_truncation)]
+                let c = c as u8;
+                c
+            })
+            .collect()
+    }
+
+    /// Reconstructs an approximation of a vector from its code.
+    ///
+    /// # Arguments
+    ///
+    /// * `code`: The code of a vector, as produced by `encode`.
+    #[must_use]
+    pub fn decode(&self, code: &[u8]) -> Vec<f32> {
+        code.iter()
+            .enumerate()
+            .flat_map(|(s, &c)| {
+                let width = self.subspaces[s].len();
+                let start = usize::from(c) * width;
+                self.codebooks[s][start..(start + width)].iter().copied()
+            })
+            .collect()
+    }
+
+    /// Precomputes the squared distances from each sub-vector of a query to
+    /// every centroid in its subspace.
+    ///
+    /// # Arguments
+    ///
+    /// * `query`: A vector with the dimensionality of the quantizer.
+    #[must_use]
+    pub fn distance_table(&self, query: &[f32]) -> DistanceTable {
+        let table = self
+            .subspaces
+            .iter()
+            .enumerate()
+            .map(|(s, range)| {
+                let q = &query[range.clone()];
+                self.centroids(s)
+                    .map(|c| distances::simd::euclidean_sq_f32(q, c))
+                    .collect()
+            })
+            .collect();
+        DistanceTable { table }
+    }
+}
+
+/// The squared distances from the sub-vectors of a query to the centroids of
+/// a `ProductQuantizer`.
+///
+/// This allows the asymmetric distance from the query to an encoded vector,
+/// i.e. the euclidean distance to its reconstruction, to be computed with one
+/// lookup per subspace.
+#[derive(Debug, Clone)]
+pub struct DistanceTable {
+    /// The squared distances, indexed by subspace and then by centroid.
+    table: Vec<Vec<f32>>,
+}
+
+impl DistanceTable {
+    /// Returns the euclidean distance from the query to the reconstruction of
+    /// an encoded vector.
+    ///
+    /// # Arguments
+    ///
+    /// * `code`: The code of a vector.
+    #[must_use]
+    pub fn distance(&self, code: &[u8]) -> f32 {
+        code.iter()
+            .zip(self.table.iter())
+            .map(|(&c, row)| row[usize::from(c)])
+            .sum::<f32>()
+            .sqrt()
+    }
+}
+
+/// Returns the index of, and squared distance to, the centroid nearest to `x`.
+fn nearest<'a>(centroids: impl Iterator<Item = &'a [f32]>, x: &[f32]) -> (usize, f32) {
+    centroids
+        .map(|c| distances::simd::euclidean_sq_f32(x, c))
+        .enumerate()
+        .fold(
+            (0, f32::INFINITY),
+            |best, (i, d)| if d < best.1 { (i, d) } else { best },
+        )
+}
+
+/// Runs Lloyd's algorithm on one subspace of the data, returning the
+/// centroids stored contiguously.
+fn kmeans(data: &[&[f32]], range: &Range<usize>, k: usize, seed: Option<u64>) -> Vec<f32> {
+    let width = range.len();
+
+    let mut rng = seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
+    let mut centroids = data
+        .choose_multiple(&mut rng, k)
+        .flat_map(|x| x[range.clone()].iter().copied())
+        .collect::<Vec<_>>();
+
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut sums = vec![0_f32; k * width];
+        let mut counts = vec![0_usize; k];
+        for x in data {
+            let x = &x[range.clone()];
+            let (c, _) = nearest(centroids.chunks_exact(width), x);
+            counts[c] += 1;
+            sums[c * width..(c + 1) * width]
+                .iter_mut()
+                .zip(x.iter())
+                .for_each(|(s, &v)| *s += v);
+        }
+
+        // Centroids which were not assigned any vectors are left in place.
+        for (c, &count) in counts.iter().enumerate().filter(|(_, &count)| count > 0) {
+            #[allow(clippy::cast_precision_loss)]
+            let count = count as f32;
+            centroids[c * width..(c + 1) * width]
+                .iter_mut()
+                .zip(sums[c * width..(c + 1) * width].iter())
+                .for_each(|(m, &s)| *m = s / count);
+        }
+    }
+
+    centroids
+}
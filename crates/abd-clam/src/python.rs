@@ -0,0 +1,245 @@
+//! Python bindings for building and searching with `Cakes`.
+//!
+//! These are compiled with the `python` feature. The `abd_clam` function is
+//! the entry point of the Python module, so the bindings may be built as an
+//! extension module with, e.g.,
+//! `cargo rustc --release --features python --crate-type cdylib`.
+//!
+//! Instances are rows of a 2-d `numpy` array of `float32`. Arrays are read in
+//! place, without conversion, so they must already have that dtype. Since the
+//! tree permutes its dataset, the rows are copied into it once when it is
+//! built. Each query is copied into a `Vec<f32>`, the type of the instances,
+//! before it is searched.
+
+// The `pyo3` macros expand to `impl` blocks inside functions.
+#![allow(non_local_definitions)]
+
+use numpy::{ndarray::ArrayView1, IntoPyArray, PyArray1, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+use crate::par::prelude::*;
+use crate::{knn, metrics::vectors::dense_metric, rnn, Cakes, PartitionCriteria, VecDataset};
+
+/// The `Cakes` type exposed to Python.
+type F32Cakes = Cakes<Vec<f32>, f32, VecDataset<Vec<f32>, f32, usize>>;
+
+/// The indices of, and distances to, the hits of a search.
+type PyHits = (Py<PyArray1<usize>>, Py<PyArray1<f32>>);
+
+/// Registers the classes of the bindings in a Python module.
+///
+/// # Arguments
+///
+/// * `py`: A token for holding the GIL.
+/// * `m`: The module to register the classes in.
+///
+/// # Errors
+///
+/// * If a class cannot be added to the module.
+pub fn register(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyCakes>()?;
+    Ok(())
+}
+
+/// The `abd_clam` module implemented in Rust.
+#[pymodule]
+fn abd_clam(py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    register(py, m)?;
+    m.add("__version__", crate::VERSION)?;
+    Ok(())
+}
+
+/// Copies a query into a vector, checking its dimensionality.
+fn query_vec(query: &ArrayView1<f32>, dim: usize) -> PyResult<Vec<f32>> {
+    if query.len() != dim {
+        return Err(PyValueError::new_err(format!(
+            "Query has dimensionality {}, expected {dim}",
+            query.len()
+        )));
+    }
+    Ok(query.to_vec())
+}
+
+/// Converts the hits of a search into a pair of `numpy` arrays.
+fn hits_to_py(py: Python<'_>, hits: Vec<(usize, f32)>) -> PyHits {
+    let (indices, distances): (Vec<_>, Vec<_>) = hits.into_iter().unzip();
+    (
+        indices.into_pyarray(py).to_owned(),
+        distances.into_pyarray(py).to_owned(),
+    )
+}
+
+/// A search index over the rows of a 2-d array.
+///
+/// The indices of hits are those of rows in the array used to build the index.
+#[pyclass(name = "Cakes", module = "abd_clam")]
+pub struct PyCakes {
+    /// The index.
+    cakes: F32Cakes,
+    /// The dimensionality of the instances.
+    dim: usize,
+}
+
+// `pyo3` extracts arguments by value.
+#[allow(clippy::needless_pass_by_value)]
+#[pymethods]
+impl PyCakes {
+    /// Builds the tree over the rows of `data`.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: A 2-d array of `float32` with one instance per row.
+    /// * `metric`: One of `"euclidean"`, `"manhattan"` or `"cosine"`.
+    /// * `seed`: The seed for building the tree.
+    /// * `min_cardinality`: Clusters with fewer instances are not partitioned.
+    /// * `max_depth`: Clusters at this depth are not partitioned.
+    #[new]
+    #[pyo3(signature = (data, metric = "euclidean", seed = None, min_cardinality = 1, max_depth = None))]
+    fn new(
+        py: Python<'_>,
+        data: PyReadonlyArray2<'_, f32>,
+        metric: &str,
+        seed: Option<u64>,
+        min_cardinality: usize,
+        max_depth: Option<usize>,
+    ) -> PyResult<Self> {
+        let view = data.as_array();
+        let (cardinality, dim) = view.dim();
+        if cardinality == 0 || dim == 0 {
+            return Err(PyValueError::new_err("Cannot build a tree on an empty array"));
+        }
+        let metric = dense_metric(metric).map_err(PyValueError::new_err)?;
+        let rows = view.outer_iter().map(|row| row.to_vec()).collect::<Vec<_>>();
+
+        let cakes = py.allow_threads(|| {
+            let data = VecDataset::from_metric("python".to_string(), rows, metric);
+            let criteria = PartitionCriteria::new(true).with_min_cardinality(min_cardinality);
+            let criteria = match max_depth {
+                Some(depth) => criteria.with_max_depth(depth),
+                None => criteria,
+            };
+            Cakes::new(data, seed, &criteria)
+        });
+
+        Ok(Self { cakes, dim })
+    }
+
+    /// The number of instances in the index.
+    #[getter]
+    fn cardinality(&self) -> usize {
+        self.cakes.total_cardinality()
+    }
+
+    /// The dimensionality of the instances.
+    #[getter]
+    const fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// The depth of the tree.
+    #[getter]
+    fn depth(&self) -> usize {
+        self.cakes.trees().iter().map(|t| t.depth()).max().unwrap_or_default()
+    }
+
+    /// Returns the `k` nearest neighbors of `query` as a pair of arrays of
+    /// indices and distances, sorted by increasing distance.
+    #[pyo3(signature = (query, k, algorithm = "GreedySieve"))]
+    fn knn_search(
+        &self,
+        py: Python<'_>,
+        query: PyReadonlyArray1<'_, f32>,
+        k: usize,
+        algorithm: &str,
+    ) -> PyResult<PyHits> {
+        let algo = knn::Algorithm::from_name(algorithm).map_err(PyValueError::new_err)?;
+        let query = query_vec(&query.as_array(), self.dim)?;
+        let hits = py.allow_threads(|| self.cakes.knn_search(&query, k, algo));
+        Ok(hits_to_py(py, sorted(&self.cakes, hits)))
+    }
+
+    /// Returns all neighbors of `query` within `radius` as a pair of arrays of
+    /// indices and distances, sorted by increasing distance.
+    #[pyo3(signature = (query, radius, algorithm = "Clustered"))]
+    fn rnn_search(
+        &self,
+        py: Python<'_>,
+        query: PyReadonlyArray1<'_, f32>,
+        radius: f32,
+        algorithm: &str,
+    ) -> PyResult<PyHits> {
+        let algo = rnn::Algorithm::from_name(algorithm).map_err(PyValueError::new_err)?;
+        let query = query_vec(&query.as_array(), self.dim)?;
+        let hits = py.allow_threads(|| self.cakes.rnn_search(&query, radius, algo));
+        Ok(hits_to_py(py, sorted(&self.cakes, hits)))
+    }
+
+    /// Runs `knn_search` for each row of `queries`, in parallel.
+    #[pyo3(signature = (queries, k, algorithm = "GreedySieve"))]
+    fn batch_knn_search(
+        &self,
+        py: Python<'_>,
+        queries: PyReadonlyArray2<'_, f32>,
+        k: usize,
+        algorithm: &str,
+    ) -> PyResult<Vec<PyHits>> {
+        let algo = knn::Algorithm::from_name(algorithm).map_err(PyValueError::new_err)?;
+        let queries = queries
+            .as_array()
+            .outer_iter()
+            .map(|q| query_vec(&q, self.dim))
+            .collect::<PyResult<Vec<_>>>()?;
+        let hits = py.allow_threads(|| {
+            queries
+                .par_iter()
+                .map(|q| sorted(&self.cakes, self.cakes.knn_search(q, k, algo)))
+                .collect::<Vec<_>>()
+        });
+        Ok(hits.into_iter().map(|h| hits_to_py(py, h)).collect())
+    }
+
+    /// Runs `rnn_search` for each row of `queries`, in parallel.
+    #[pyo3(signature = (queries, radius, algorithm = "Clustered"))]
+    fn batch_rnn_search(
+        &self,
+        py: Python<'_>,
+        queries: PyReadonlyArray2<'_, f32>,
+        radius: f32,
+        algorithm: &str,
+    ) -> PyResult<Vec<PyHits>> {
+        let algo = rnn::Algorithm::from_name(algorithm).map_err(PyValueError::new_err)?;
+        let queries = queries
+            .as_array()
+            .outer_iter()
+            .map(|q| query_vec(&q, self.dim))
+            .collect::<PyResult<Vec<_>>>()?;
+        let hits = py.allow_threads(|| {
+            queries
+                .par_iter()
+                .map(|q| sorted(&self.cakes, self.cakes.rnn_search(q, radius, algo)))
+                .collect::<Vec<_>>()
+        });
+        Ok(hits.into_iter().map(|h| hits_to_py(py, h)).collect())
+    }
+
+    /// The number of instances in the index.
+    fn __len__(&self) -> usize {
+        self.cardinality()
+    }
+
+    /// A summary of the index.
+    fn __repr__(&self) -> String {
+        format!("Cakes(cardinality={}, dim={})", self.cardinality(), self.dim)
+    }
+}
+
+/// Maps hits to the indices of rows in the array used to build the index,
+/// and sorts them by increasing distance.
+fn sorted(cakes: &F32Cakes, hits: Vec<(usize, f32)>) -> Vec<(usize, f32)> {
+    let mut hits = hits
+        .into_iter()
+        .map(|(i, d)| (cakes.original_index(i), d))
+        .collect::<Vec<_>>();
+    hits.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+    hits
+}
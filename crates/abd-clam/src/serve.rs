@@ -0,0 +1,421 @@
+//! Remote search over TCP, with a client which fans out to many servers.
+//!
+//! These are compiled with the `serve` feature. Each server holds one index,
+//! e.g. one shard of a dataset which is too large for a single machine, and
+//! answers searches from a bounded number of connections at once. A `Client`
+//! connects to the servers for all shards, sends each search to all of them
+//! in parallel, and merges their hits, as `ShardedCakes` does for shards in
+//! the same process.
+//!
+//! # Protocol
+//!
+//! Every message is a frame: its length in bytes, as a little-endian `u32`,
+//! followed by that many bytes. All numbers are little-endian, and instances
+//! are encoded with `Instance::to_bytes`. A request starts with a byte for the
+//! operation:
+//!
+//! * `0`: the number of instances in the index, with no arguments.
+//! * `1`: a KNN search, followed by `k` as a `u64` and then the query.
+//! * `2`: an RNN search, followed by the radius and then the query.
+//!
+//! A response starts with a byte which is `0` on success and `1` on failure.
+//! A failure is followed by a UTF-8 message. The number of instances is sent
+//! as a `u64`. Hits are sent as their number, as a `u64`, followed by the
+//! index, as a `u64`, and the distance of each. The indices of hits are the
+//! original indices of the instances in the index of the server.
+//!
+//! A server closes any connection which sends a frame larger than a request
+//! with a query of the size of the instances in its index, and rejects any
+//! query with a different dimensionality than those instances.
+
+use core::marker::PhantomData;
+
+use std::{
+    io::{BufReader, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use distances::Number;
+
+use crate::{knn, Dataset, Instance, SharedCakes};
+
+/// The operation asking for the number of instances in the index.
+const OP_CARDINALITY: u8 = 0;
+/// The operation asking for a KNN search.
+const OP_KNN: u8 = 1;
+/// The operation asking for an RNN search.
+const OP_RNN: u8 = 2;
+
+/// The status of a successful response.
+const OK: u8 = 0;
+/// The status of a failed response.
+const FAILED: u8 = 1;
+
+/// The default number of connections which a server answers at once.
+pub const DEFAULT_WORKERS: usize = 16;
+
+/// The largest query, in bytes, which a server accepts if the instances in
+/// its index have no dimensionality, e.g. strings. See `Instance::dim`.
+pub const MAX_VARIABLE_QUERY_BYTES: usize = 1 << 20;
+
+/// The largest failure message, in bytes, which a client accepts.
+const MAX_MESSAGE_BYTES: usize = 1 << 16;
+
+/// Serves searches against an index until the `listener` fails, answering
+/// up to `DEFAULT_WORKERS` connections at once.
+///
+/// See `serve_with_workers`.
+///
+/// # Arguments
+///
+/// * `listener` - The listener on which to accept connections.
+/// * `cakes` - The index to search.
+///
+/// # Errors
+///
+/// * If a connection could not be accepted.
+pub fn serve<I, U, D>(listener: &TcpListener, cakes: &SharedCakes<I, U, D>) -> Result<(), String>
+where
+    I: Instance + 'static,
+    U: Number + 'static,
+    D: Dataset<I, U> + 'static,
+{
+    serve_with_workers(listener, cakes, DEFAULT_WORKERS)
+}
+
+/// Serves searches against an index until the `listener` fails.
+///
+/// Each connection is handled by one of a fixed pool of `workers` threads,
+/// until it is closed, and each search is made with the tuned algorithm for
+/// its `k` or radius. Once every worker is busy, further connections wait
+/// to be accepted until one of the open connections is closed.
+///
+/// # Arguments
+///
+/// * `listener` - The listener on which to accept connections.
+/// * `cakes` - The index to search.
+/// * `workers` - The number of connections to answer at once. At least one
+///   worker is used.
+///
+/// # Errors
+///
+/// * If a connection could not be accepted. The workers finish the
+///   connections they are answering, and then stop.
+pub fn serve_with_workers<I, U, D>(
+    listener: &TcpListener,
+    cakes: &SharedCakes<I, U, D>,
+    workers: usize,
+) -> Result<(), String>
+where
+    I: Instance + 'static,
+    U: Number + 'static,
+    D: Dataset<I, U> + 'static,
+{
+    let max_request_bytes = max_request_bytes(cakes);
+    let workers = workers.max(1);
+    let (sender, receiver) = mpsc::sync_channel::<TcpStream>(0);
+    let receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..workers {
+        let cakes = cakes.clone();
+        let receiver = Arc::clone(&receiver);
+        thread::spawn(move || {
+            // The lock is released before the connection is answered, so
+            // that the other workers may take the next connection.
+            let next = || receiver.lock().ok().and_then(|r| r.recv().ok());
+            while let Some(stream) = next() {
+                // A connection which fails, or panics, only ends itself.
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| handle(&cakes, stream, max_request_bytes)));
+            }
+        });
+    }
+
+    for stream in listener.incoming() {
+        let stream = stream.map_err(|e| e.to_string())?;
+        sender
+            .send(stream)
+            .map_err(|_| "Every worker has stopped".to_string())?;
+    }
+    Ok(())
+}
+
+/// Returns the size of the largest request which a server accepts: an
+/// operation, a `k` or radius, and a query of the size of the instances in
+/// the index.
+fn max_request_bytes<I: Instance, U: Number, D: Dataset<I, U>>(cakes: &SharedCakes<I, U, D>) -> usize {
+    let instance = cakes.get(0);
+    let query_bytes = if instance.dim().is_some() {
+        instance.to_bytes().len()
+    } else {
+        MAX_VARIABLE_QUERY_BYTES
+    };
+    1 + u64::num_bytes().max(U::num_bytes()) + query_bytes
+}
+
+/// Answers the requests on one connection until it is closed.
+fn handle<I: Instance, U: Number, D: Dataset<I, U>>(
+    cakes: &SharedCakes<I, U, D>,
+    stream: TcpStream,
+    max_request_bytes: usize,
+) -> Result<(), String> {
+    stream.set_nodelay(true).map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+    let mut writer = stream;
+    while let Some(request) = read_frame(&mut reader, max_request_bytes)? {
+        let response = match answer(cakes, &request) {
+            Ok(mut response) => {
+                response.insert(0, OK);
+                response
+            }
+            Err(message) => core::iter::once(FAILED).chain(message.into_bytes()).collect(),
+        };
+        write_frame(&mut writer, &response)?;
+    }
+    Ok(())
+}
+
+/// Answers a request, without its status byte.
+fn answer<I: Instance, U: Number, D: Dataset<I, U>>(
+    cakes: &SharedCakes<I, U, D>,
+    request: &[u8],
+) -> Result<Vec<u8>, String> {
+    let (&op, rest) = request.split_first().ok_or("Empty request")?;
+    let hits = match op {
+        OP_CARDINALITY => return Ok(cakes.total_cardinality().as_u64().to_le_bytes().to_vec()),
+        OP_KNN => {
+            let (k, query) = split_number::<u64>(rest)?;
+            // A `k` beyond the number of instances would only reserve room
+            // for hits which cannot be found.
+            let k = usize::try_from(k).unwrap_or(usize::MAX).min(cakes.total_cardinality());
+            let query = I::from_bytes(query)?;
+            cakes.try_knn_search(&query, k, cakes.tuned_knn_algorithm_for(k))?
+        }
+        OP_RNN => {
+            let (radius, query) = split_number::<U>(rest)?;
+            let query = I::from_bytes(query)?;
+            cakes.try_rnn_search(&query, radius, cakes.tuned_rnn_algorithm_for(radius))?
+        }
+        _ => return Err(format!("Unknown operation: {op}")),
+    };
+
+    let mut response = hits.len().as_u64().to_le_bytes().to_vec();
+    for (i, d) in hits {
+        response.extend(cakes.original_index(i).as_u64().to_le_bytes());
+        response.extend(d.to_le_bytes());
+    }
+    Ok(response)
+}
+
+/// A client which searches the indices of several servers as shards of one
+/// dataset.
+///
+/// The indices of hits are offset by the total number of instances on the
+/// servers before the one which holds the instance, in the order in which the
+/// servers were given.
+///
+/// # Type Parameters
+///
+/// - `I`: The type of the instances.
+/// - `U`: The type of the distance values.
+#[derive(Debug)]
+pub struct Client<I: Instance, U: Number> {
+    /// The connection to each server.
+    servers: Vec<TcpStream>,
+    /// The index at which the instances of each server start.
+    offsets: Vec<usize>,
+    /// The number of instances on all servers.
+    cardinality: usize,
+    /// To satisfy the type parameters.
+    _p: PhantomData<(I, U)>,
+}
+
+impl<I: Instance, U: Number> Client<I, U> {
+    /// Connects to the servers for all shards.
+    ///
+    /// # Arguments
+    ///
+    /// * `addresses` - The address of the server for each shard.
+    ///
+    /// # Errors
+    ///
+    /// * If any server could not be reached.
+    pub fn connect<A: ToSocketAddrs>(addresses: &[A]) -> Result<Self, String> {
+        let mut servers = addresses
+            .iter()
+            .map(|address| {
+                let server = TcpStream::connect(address).map_err(|e| e.to_string())?;
+                server.set_nodelay(true).map_err(|e| e.to_string())?;
+                Ok::<_, String>(server)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut offsets = Vec::with_capacity(servers.len());
+        let mut cardinality = 0;
+        for server in &mut servers {
+            let response = request(server, &[OP_CARDINALITY], MAX_MESSAGE_BYTES)?;
+            let (n, _) = split_number::<u64>(&response)?;
+            offsets.push(cardinality);
+            cardinality += usize::try_from(n).map_err(|e| e.to_string())?;
+        }
+
+        Ok(Self {
+            servers,
+            offsets,
+            cardinality,
+            _p: PhantomData,
+        })
+    }
+
+    /// Returns the number of servers.
+    #[must_use]
+    pub fn num_shards(&self) -> usize {
+        self.servers.len()
+    }
+
+    /// Returns the index at which the instances of each server start.
+    #[must_use]
+    pub fn offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    /// Returns the number of instances on all servers.
+    #[must_use]
+    pub const fn total_cardinality(&self) -> usize {
+        self.cardinality
+    }
+
+    /// Performs a KNN search on all servers, and keeps the `k` nearest hits.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of nearest neighbors to return.
+    ///
+    /// # Errors
+    ///
+    /// * If any server could not be reached, or failed to search.
+    pub fn knn_search(&mut self, query: &I, k: usize) -> Result<Vec<(usize, U)>, String> {
+        let k = k.min(self.cardinality);
+        let mut message = vec![OP_KNN];
+        message.extend(k.as_u64().to_le_bytes());
+        message.extend(query.to_bytes());
+        let hits = self.fan_out(&message)?;
+        Ok(knn::Hits::from_vec(k, hits).extract())
+    }
+
+    /// Performs an RNN search on all servers.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `radius` - The search radius.
+    ///
+    /// # Errors
+    ///
+    /// * If any server could not be reached, or failed to search.
+    pub fn rnn_search(&mut self, query: &I, radius: U) -> Result<Vec<(usize, U)>, String> {
+        let mut message = vec![OP_RNN];
+        message.extend(radius.to_le_bytes());
+        message.extend(query.to_bytes());
+        self.fan_out(&message)
+    }
+
+    /// Sends a search to all servers at once, and collects their hits.
+    fn fan_out(&mut self, message: &[u8]) -> Result<Vec<(usize, U)>, String> {
+        // No server can send more hits than there are instances.
+        let max_response_bytes = (1 + u64::num_bytes())
+            .saturating_add(self.cardinality.saturating_mul(u64::num_bytes() + U::num_bytes()))
+            .max(MAX_MESSAGE_BYTES);
+        let responses = thread::scope(|s| {
+            let handles = self
+                .servers
+                .iter_mut()
+                .map(|server| s.spawn(move || request(server, message, max_response_bytes)))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap_or_else(|_| Err("A request panicked".to_string())))
+                .collect::<Vec<_>>()
+        });
+
+        let mut hits = Vec::new();
+        for (response, &offset) in responses.into_iter().zip(&self.offsets) {
+            let response = response?;
+            let (n, mut rest) = split_number::<u64>(&response)?;
+            for _ in 0..n {
+                let (i, after) = split_number::<u64>(rest)?;
+                let (d, after) = split_number::<U>(after)?;
+                let i = usize::try_from(i).map_err(|e| e.to_string())?;
+                hits.push((offset + i, d));
+                rest = after;
+            }
+        }
+        Ok(hits)
+    }
+}
+
+/// Sends a request to a server and returns the response, without its status
+/// byte.
+fn request(server: &mut TcpStream, message: &[u8], max_response_bytes: usize) -> Result<Vec<u8>, String> {
+    write_frame(server, message)?;
+    let response = read_frame(server, max_response_bytes)?.ok_or("The server closed the connection")?;
+    match response.split_first() {
+        Some((&OK, rest)) => Ok(rest.to_vec()),
+        Some((_, message)) => Err(String::from_utf8_lossy(message).into_owned()),
+        None => Err("Empty response".to_string()),
+    }
+}
+
+/// Reads a frame, or returns `None` if the connection was closed before it.
+///
+/// A frame longer than `max_bytes` is refused before it is read, so that a
+/// corrupt or malicious length cannot make the reader allocate more.
+fn read_frame<R: Read>(reader: &mut R, max_bytes: usize) -> Result<Option<Vec<u8>>, String> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length) {
+        Ok(()) => (),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    }
+    let length = usize::try_from(u32::from_le_bytes(length)).map_err(|e| e.to_string())?;
+    if length > max_bytes {
+        return Err(format!(
+            "Frame of {length} bytes is larger than the limit of {max_bytes}"
+        ));
+    }
+
+    // The frame grows as its bytes arrive, instead of being allocated in full
+    // up front for a peer which may never send them.
+    let mut frame = Vec::new();
+    reader
+        .take(length.as_u64())
+        .read_to_end(&mut frame)
+        .map_err(|e| e.to_string())?;
+    if frame.len() < length {
+        return Err("The connection was closed in the middle of a frame".to_string());
+    }
+    Ok(Some(frame))
+}
+
+/// Writes a frame, in one write, and flushes it.
+fn write_frame<W: Write>(writer: &mut W, frame: &[u8]) -> Result<(), String> {
+    let length = u32::try_from(frame.len()).map_err(|e| e.to_string())?;
+    let bytes = length
+        .to_le_bytes()
+        .into_iter()
+        .chain(frame.iter().copied())
+        .collect::<Vec<_>>();
+    writer.write_all(&bytes).map_err(|e| e.to_string())?;
+    writer.flush().map_err(|e| e.to_string())
+}
+
+/// Splits a number off the front of a message.
+fn split_number<T: Number>(bytes: &[u8]) -> Result<(T, &[u8]), String> {
+    if bytes.len() < T::num_bytes() {
+        return Err("Truncated message".to_string());
+    }
+    let (number, rest) = bytes.split_at(T::num_bytes());
+    Ok((T::from_le_bytes(number), rest))
+}
@@ -0,0 +1,221 @@
+//! Generators of synthetic datasets, for examples, tests and experiments.
+//!
+//! Every generator takes a seed and uses a `ChaCha8Rng`, so the same
+//! arguments give the same data on every platform. The vectors may be passed
+//! straight to `VecDataset::new`, and the strings to `VecDataset::new` with a
+//! string distance from `distances::strings`.
+
+use core::f32::consts::TAU;
+
+use distances::Number;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+/// Generates vectors drawn uniformly from a hypercube.
+///
+/// # Arguments
+///
+/// * `cardinality` - The number of vectors.
+/// * `dimensionality` - The number of dimensions of each vector.
+/// * `min` - The least value in each dimension.
+/// * `max` - The greatest value in each dimension.
+/// * `seed` - The seed for the random number generator.
+///
+/// # Errors
+///
+/// * If `min` is not less than `max`, or either is not finite.
+pub fn uniform_hypercube(
+    cardinality: usize,
+    dimensionality: usize,
+    min: f32,
+    max: f32,
+    seed: u64,
+) -> Result<Vec<Vec<f32>>, String> {
+    if !(min.is_finite() && max.is_finite() && min < max) {
+        return Err(format!("Expected finite bounds with min < max, got {min} and {max}"));
+    }
+
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    Ok((0..cardinality)
+        .map(|_| (0..dimensionality).map(|_| rng.gen_range(min..max)).collect())
+        .collect())
+}
+
+/// Generates vectors from a mixture of isotropic Gaussians.
+///
+/// The means of the components are drawn uniformly from `[-1, 1]` in each
+/// dimension, and each vector is drawn from a component chosen uniformly at
+/// random. With a small `spread`, the components are well separated clusters.
+///
+/// # Arguments
+///
+/// * `cardinality` - The number of vectors.
+/// * `dimensionality` - The number of dimensions of each vector.
+/// * `num_components` - The number of Gaussians in the mixture.
+/// * `spread` - The standard deviation of each Gaussian, in every dimension.
+/// * `seed` - The seed for the random number generator.
+///
+/// # Returns
+///
+/// The vectors, and the index of the component from which each was drawn,
+/// which may be used as metadata or as labels for clustering.
+///
+/// # Errors
+///
+/// * If `num_components` is zero.
+/// * If `spread` is negative or not finite.
+pub fn gaussian_mixture(
+    cardinality: usize,
+    dimensionality: usize,
+    num_components: usize,
+    spread: f32,
+    seed: u64,
+) -> Result<(Vec<Vec<f32>>, Vec<usize>), String> {
+    if num_components == 0 {
+        return Err("Expected at least one component".to_string());
+    }
+    if !(spread.is_finite() && spread >= 0.0) {
+        return Err(format!("Expected a finite, non-negative spread, got {spread}"));
+    }
+
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let means = (0..num_components)
+        .map(|_| {
+            (0..dimensionality)
+                .map(|_| rng.gen_range(-1.0..1.0))
+                .collect::<Vec<f32>>()
+        })
+        .collect::<Vec<_>>();
+    Ok((0..cardinality)
+        .map(|_| {
+            let c = rng.gen_range(0..num_components);
+            let x = means[c]
+                .iter()
+                .map(|&m| spread.mul_add(standard_normal(&mut rng), m))
+                .collect();
+            (x, c)
+        })
+        .unzip())
+}
+
+/// Generates vectors on a low-dimensional manifold embedded in a
+/// high-dimensional space.
+///
+/// The manifold is a flat torus: each vector has `intrinsic_dim` angles drawn
+/// uniformly, each of which contributes its sine and cosine to a point in
+/// `2 * intrinsic_dim` dimensions. That point is embedded by a random linear
+/// map into `ambient_dim` dimensions, and Gaussian noise is added in every
+/// ambient dimension. The local fractal dimension of the data is therefore
+/// close to `intrinsic_dim` at scales above the `noise`, whatever the
+/// `ambient_dim`.
+///
+/// # Arguments
+///
+/// * `cardinality` - The number of vectors.
+/// * `intrinsic_dim` - The dimensionality of the manifold.
+/// * `ambient_dim` - The number of dimensions of each vector.
+/// * `noise` - The standard deviation of the noise in each ambient dimension.
+/// * `seed` - The seed for the random number generator.
+///
+/// # Errors
+///
+/// * If `intrinsic_dim` is zero.
+/// * If `ambient_dim` is less than `2 * intrinsic_dim`, so that the torus
+///   could not be embedded.
+/// * If `noise` is negative or not finite.
+pub fn manifold(
+    cardinality: usize,
+    intrinsic_dim: usize,
+    ambient_dim: usize,
+    noise: f32,
+    seed: u64,
+) -> Result<Vec<Vec<f32>>, String> {
+    if intrinsic_dim == 0 {
+        return Err("Expected a positive intrinsic dimensionality".to_string());
+    }
+    if ambient_dim < 2 * intrinsic_dim {
+        return Err(format!(
+            "Expected an ambient dimensionality of at least {}, got {ambient_dim}",
+            2 * intrinsic_dim
+        ));
+    }
+    if !(noise.is_finite() && noise >= 0.0) {
+        return Err(format!("Expected a finite, non-negative noise, got {noise}"));
+    }
+
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+    // The columns of the embedding have unit length in expectation.
+    let scale = ambient_dim.as_f32().sqrt().recip();
+    let embedding = (0..ambient_dim)
+        .map(|_| {
+            (0..2 * intrinsic_dim)
+                .map(|_| scale * standard_normal(&mut rng))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    Ok((0..cardinality)
+        .map(|_| {
+            let point = (0..intrinsic_dim)
+                .flat_map(|_| {
+                    let angle = rng.gen_range(0.0..TAU);
+                    [angle.sin(), angle.cos()]
+                })
+                .collect::<Vec<_>>();
+            embedding
+                .iter()
+                .map(|row| {
+                    let x = row.iter().zip(&point).map(|(&a, &p)| a * p).sum::<f32>();
+                    noise.mul_add(standard_normal(&mut rng), x)
+                })
+                .collect()
+        })
+        .collect())
+}
+
+/// Generates random strings over an alphabet.
+///
+/// # Arguments
+///
+/// * `cardinality` - The number of strings.
+/// * `min_len` - The least length of a string, in characters.
+/// * `max_len` - The greatest length of a string, in characters.
+/// * `alphabet` - The characters to draw from, uniformly.
+/// * `seed` - The seed for the random number generator.
+///
+/// # Errors
+///
+/// * If the `alphabet` is empty.
+/// * If `min_len` is greater than `max_len`.
+pub fn random_strings(
+    cardinality: usize,
+    min_len: usize,
+    max_len: usize,
+    alphabet: &[char],
+    seed: u64,
+) -> Result<Vec<String>, String> {
+    if alphabet.is_empty() {
+        return Err("Expected a non-empty alphabet".to_string());
+    }
+    if min_len > max_len {
+        return Err(format!("Expected min_len <= max_len, got {min_len} and {max_len}"));
+    }
+
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    Ok((0..cardinality)
+        .map(|_| {
+            let len = rng.gen_range(min_len..=max_len);
+            (0..len).map(|_| alphabet[rng.gen_range(0..alphabet.len())]).collect()
+        })
+        .collect())
+}
+
+/// Draws a value from the standard normal distribution, with the Box-Muller
+/// transform.
+fn standard_normal<R: Rng>(rng: &mut R) -> f32 {
+    // `1 - u` is in `(0, 1]`, so its logarithm is finite.
+    let u = 1.0 - rng.gen::<f32>();
+    let v = rng.gen::<f32>();
+    (-2.0 * u.ln()).sqrt() * (TAU * v).cos()
+}
@@ -0,0 +1,180 @@
+//! WebAssembly bindings for building and searching with `Cakes`.
+//!
+//! These are compiled with the `wasm` feature. For use in a browser, build
+//! for `wasm32-unknown-unknown` without the default `parallel` feature, e.g.
+//! with `wasm-pack build --no-default-features --features wasm`, so that
+//! everything runs on the calling thread. Trees are built and searched
+//! entirely in memory; none of the bindings read or write files.
+//!
+//! Instances are the rows of a flat, row-major `Float32Array`. The indices of
+//! hits are those of rows in that array.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{knn, metrics::vectors::dense_metric, rnn, Cakes, PartitionCriteria, VecDataset};
+
+/// The `Cakes` type exposed to JavaScript.
+type F32Cakes = Cakes<Vec<f32>, f32, VecDataset<Vec<f32>, f32, usize>>;
+
+/// A search index over the rows of an array.
+#[wasm_bindgen(js_name = Cakes)]
+pub struct WasmCakes {
+    /// The index.
+    cakes: F32Cakes,
+    /// The dimensionality of the instances.
+    dim: usize,
+}
+
+/// The hits of a search, sorted by increasing distance.
+#[wasm_bindgen(js_name = Hits)]
+pub struct WasmHits {
+    /// The indices of the hits.
+    indices: Vec<u32>,
+    /// The distances from the query to the hits.
+    distances: Vec<f32>,
+}
+
+#[wasm_bindgen(js_class = Hits)]
+impl WasmHits {
+    /// The indices of the hits.
+    #[must_use]
+    #[wasm_bindgen(getter)]
+    pub fn indices(&self) -> Vec<u32> {
+        self.indices.clone()
+    }
+
+    /// The distances from the query to the hits.
+    #[must_use]
+    #[wasm_bindgen(getter)]
+    pub fn distances(&self) -> Vec<f32> {
+        self.distances.clone()
+    }
+
+    /// The number of hits.
+    #[must_use]
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.indices.len()
+    }
+}
+
+#[wasm_bindgen(js_class = Cakes)]
+impl WasmCakes {
+    /// Builds the tree over the rows of `data`.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: A row-major array with `dim` columns.
+    /// * `dim`: The dimensionality of the instances.
+    /// * `metric`: One of `"euclidean"`, `"manhattan"` or `"cosine"`.
+    /// * `seed`: The seed for building the tree, or `undefined` for a random
+    ///   seed.
+    ///
+    /// # Errors
+    ///
+    /// * If `data` is empty or its length is not a multiple of `dim`.
+    /// * If the metric is not recognized.
+    #[wasm_bindgen(constructor)]
+    pub fn new(data: &[f32], dim: usize, metric: &str, seed: Option<u32>) -> Result<Self, JsError> {
+        if dim == 0 || data.is_empty() || data.len() % dim != 0 {
+            return Err(JsError::new(&format!(
+                "Expected a non-empty array with a multiple of {dim} elements, got {}",
+                data.len()
+            )));
+        }
+        let metric = dense_metric(metric).map_err(|e| JsError::new(&e))?;
+
+        let rows = data.chunks_exact(dim).map(<[f32]>::to_vec).collect();
+        let data = VecDataset::from_metric("wasm".to_string(), rows, metric);
+        let criteria = PartitionCriteria::default();
+        let cakes = Cakes::new(data, seed.map(u64::from), &criteria);
+
+        Ok(Self { cakes, dim })
+    }
+
+    /// The number of instances in the index.
+    #[must_use]
+    #[wasm_bindgen(getter)]
+    pub fn cardinality(&self) -> usize {
+        self.cakes.total_cardinality()
+    }
+
+    /// The dimensionality of the instances.
+    #[must_use]
+    #[allow(clippy::missing_const_for_fn)]
+    #[wasm_bindgen(getter)]
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Searches for the `k` nearest neighbors of a query.
+    ///
+    /// # Arguments
+    ///
+    /// * `query`: An array of `dim` elements.
+    /// * `k`: The number of neighbors to search for.
+    /// * `algorithm`: The name of a `knn::Algorithm`, or `undefined` for the
+    ///   default.
+    ///
+    /// # Errors
+    ///
+    /// * If the query does not have `dim` elements.
+    /// * If the algorithm is not recognized.
+    #[wasm_bindgen(js_name = knnSearch)]
+    pub fn knn_search(&self, query: &[f32], k: usize, algorithm: Option<String>) -> Result<WasmHits, JsError> {
+        let algo = algorithm.map_or_else(|| Ok(knn::Algorithm::default()), |a| knn::Algorithm::from_name(&a));
+        let algo = algo.map_err(|e| JsError::new(&e))?;
+        let query = self.query(query)?;
+        Ok(self.hits(self.cakes.knn_search(&query, k, algo)))
+    }
+
+    /// Searches for all neighbors of a query within a radius.
+    ///
+    /// # Arguments
+    ///
+    /// * `query`: An array of `dim` elements.
+    /// * `radius`: The radius to search within.
+    /// * `algorithm`: The name of an `rnn::Algorithm`, or `undefined` for the
+    ///   default.
+    ///
+    /// # Errors
+    ///
+    /// * If the query does not have `dim` elements.
+    /// * If the algorithm is not recognized.
+    #[wasm_bindgen(js_name = rnnSearch)]
+    pub fn rnn_search(&self, query: &[f32], radius: f32, algorithm: Option<String>) -> Result<WasmHits, JsError> {
+        let algo = algorithm.map_or_else(|| Ok(rnn::Algorithm::default()), |a| rnn::Algorithm::from_name(&a));
+        let algo = algo.map_err(|e| JsError::new(&e))?;
+        let query = self.query(query)?;
+        Ok(self.hits(self.cakes.rnn_search(&query, radius, algo)))
+    }
+}
+
+impl WasmCakes {
+    /// Copies a query, checking its dimensionality.
+    fn query(&self, query: &[f32]) -> Result<Vec<f32>, JsError> {
+        if query.len() == self.dim {
+            Ok(query.to_vec())
+        } else {
+            Err(JsError::new(&format!(
+                "Query has dimensionality {}, expected {}",
+                query.len(),
+                self.dim
+            )))
+        }
+    }
+
+    /// Maps hits to the indices of rows in the array used to build the index,
+    /// and sorts them by increasing distance.
+    fn hits(&self, hits: Vec<(usize, f32)>) -> WasmHits {
+        let mut hits = hits
+            .into_iter()
+            .map(|(i, d)| (self.cakes.original_index(i), d))
+            .collect::<Vec<_>>();
+        hits.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        #[allow(clippy::cast_possible_truncation)]
+        let (indices, distances) = hits.into_iter().map(|(i, d)| (i as u32, d)).unzip();
+        WasmHits { indices, distances }
+    }
+}
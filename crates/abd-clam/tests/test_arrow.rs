@@ -0,0 +1,113 @@
+//! Tests for ingesting datasets from Arrow and Parquet.
+
+#![cfg(feature = "arrow")]
+
+use std::{fs::File, sync::Arc};
+
+use abd_clam::{Dataset, FnMetric, VecDataset};
+use arrow_array::{types::Float32Type, Array, FixedSizeListArray, Float64Array, Int32Array, RecordBatch};
+use arrow_schema::{DataType, Field};
+use parquet::{arrow::ArrowWriter, file::properties::WriterProperties};
+use rand::prelude::*;
+use tempdir::TempDir;
+
+#[allow(clippy::ptr_arg)]
+fn euclidean(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    distances::simd::euclidean_f32(x, y)
+}
+
+fn random_rows(cardinality: usize, dim: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..cardinality)
+        .map(|_| (0..dim).map(|_| rng.gen_range(-1_f32..1.)).collect())
+        .collect()
+}
+
+fn to_batch(rows: &[Vec<f32>]) -> RecordBatch {
+    let dim = rows[0].len();
+    let embeddings = FixedSizeListArray::from_iter_primitive::<Float32Type, _, _>(
+        rows.iter().map(|r| Some(r.iter().copied().map(Some))),
+        i32::try_from(dim).unwrap(),
+    );
+    let ids = Int32Array::from_iter_values(0..i32::try_from(rows.len()).unwrap());
+    RecordBatch::try_from_iter([
+        ("id", Arc::new(ids) as Arc<dyn Array>),
+        ("embedding", Arc::new(embeddings) as Arc<dyn Array>),
+    ])
+    .unwrap()
+}
+
+#[test]
+fn from_arrow() -> Result<(), String> {
+    let rows = random_rows(100, 8, 42);
+    let batch = to_batch(&rows);
+    let metric = FnMetric::new(euclidean);
+
+    let data = VecDataset::from_arrow("arrow".to_string(), &batch, "embedding", metric)?;
+    assert_eq!(data.cardinality(), 100);
+    assert_eq!(data.data(), &rows);
+
+    // Only the rows of a sliced batch are read.
+    let data = VecDataset::from_arrow("arrow".to_string(), &batch.slice(10, 20), "embedding", metric)?;
+    assert_eq!(data.data(), &rows[10..30]);
+
+    assert!(VecDataset::from_arrow("arrow".to_string(), &batch, "missing", metric).is_err());
+    assert!(VecDataset::from_arrow("arrow".to_string(), &batch, "id", metric).is_err());
+
+    // The element type must match the instances.
+    let values = Float64Array::from(vec![0.; 8]);
+    let field = Arc::new(Field::new("item", DataType::Float64, true));
+    let doubles = FixedSizeListArray::new(field, 4, Arc::new(values), None);
+    let batch = RecordBatch::try_from_iter([("embedding", Arc::new(doubles) as Arc<dyn Array>)]).unwrap();
+    let err = VecDataset::from_arrow("arrow".to_string(), &batch, "embedding", metric).unwrap_err();
+    assert_eq!(err, "Column embedding must hold Float32, but it holds Float64");
+    let data = VecDataset::<Vec<f64>, f64, usize>::from_arrow(
+        "arrow".to_string(),
+        &batch,
+        "embedding",
+        FnMetric::new(|x: &Vec<f64>, y: &Vec<f64>| distances::vectors::euclidean(x, y)),
+    )?;
+    assert_eq!(data.cardinality(), 2);
+
+    // Null lists are rejected.
+    let embeddings =
+        FixedSizeListArray::from_iter_primitive::<Float32Type, _, _>([Some(vec![Some(1_f32), Some(2.)]), None], 2);
+    let batch = RecordBatch::try_from_iter([("embedding", Arc::new(embeddings) as Arc<dyn Array>)]).unwrap();
+    let err = VecDataset::from_arrow("arrow".to_string(), &batch, "embedding", metric).unwrap_err();
+    assert_eq!(err, "Column embedding has a null list at row 1");
+
+    Ok(())
+}
+
+#[test]
+fn from_parquet() -> Result<(), String> {
+    let rows = random_rows(1000, 16, 42);
+    let batch = to_batch(&rows);
+
+    let tmp_dir = TempDir::new("parquet").map_err(|e| e.to_string())?;
+    let path = tmp_dir.path().join("embeddings.parquet");
+
+    // Small row groups, so that the file is read in several batches.
+    let props = WriterProperties::builder().set_max_row_group_size(128).build();
+    let file = File::create(&path).map_err(|e| e.to_string())?;
+    let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(props)).map_err(|e| e.to_string())?;
+    writer.write(&batch).map_err(|e| e.to_string())?;
+    writer.close().map_err(|e| e.to_string())?;
+
+    let metric = FnMetric::new(euclidean);
+    let data = VecDataset::from_parquet("parquet".to_string(), &path, "embedding", metric)?;
+    assert_eq!(data.cardinality(), 1000);
+    assert_eq!(data.data(), &rows);
+
+    assert!(VecDataset::from_parquet("parquet".to_string(), &path, "missing", metric).is_err());
+    assert!(VecDataset::from_parquet("parquet".to_string(), &path, "id", metric).is_err());
+    assert!(VecDataset::from_parquet(
+        "parquet".to_string(),
+        &tmp_dir.path().join("missing"),
+        "embedding",
+        metric
+    )
+    .is_err());
+
+    Ok(())
+}
@@ -0,0 +1,59 @@
+//! Tests for the async searches.
+
+#![cfg(feature = "tokio")]
+
+use abd_clam::{knn, rnn, Cakes, PartitionCriteria, SharedCakes};
+use tokio_stream::StreamExt;
+
+mod utils;
+
+/// Sorts hits by index, since tied hits may be found in any order.
+fn sorted(mut hits: Vec<(usize, f32)>) -> Vec<usize> {
+    hits.sort_by_key(|&(i, _)| i);
+    hits.into_iter().map(|(i, _)| i).collect()
+}
+
+#[test]
+fn async_search() {
+    let data = utils::gen_dataset(1000, 10, 42, utils::euclidean);
+    let queries = utils::gen_dataset(20, 10, 0, utils::euclidean).data().to_vec();
+    let cakes = SharedCakes::new(Cakes::new(data, Some(42), &PartitionCriteria::default()));
+
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    runtime.block_on(async {
+        let algo = knn::Algorithm::GreedySieve;
+        let hits = cakes.knn_search_async(queries[0].clone(), 10, algo).await.unwrap();
+        assert_eq!(sorted(hits), sorted(cakes.knn_search(&queries[0], 10, algo)));
+
+        let hits = cakes
+            .rnn_search_async(queries[0].clone(), 0.5, rnn::Algorithm::Clustered)
+            .await
+            .unwrap();
+        assert_eq!(
+            hits.len(),
+            cakes.rnn_search(&queries[0], 0.5, rnn::Algorithm::Clustered).len()
+        );
+
+        let streamed = cakes
+            .batch_knn_search_stream(queries.clone(), 10, algo)
+            .collect::<Vec<_>>()
+            .await;
+        let refs = queries.iter().collect::<Vec<_>>();
+        let expected = cakes.batch_knn_search(&refs, 10, algo);
+        assert_eq!(streamed.len(), expected.len());
+        for (s, e) in streamed.into_iter().zip(expected) {
+            assert_eq!(sorted(s), sorted(e));
+        }
+
+        let streamed = cakes
+            .batch_rnn_search_stream(queries.clone(), 0.5, rnn::Algorithm::Clustered)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(streamed.len(), queries.len());
+
+        // Dropping the stream early stops the search without blocking.
+        let mut stream = cakes.batch_knn_search_stream(queries.clone(), 10, algo);
+        assert!(stream.next().await.is_some());
+        drop(stream);
+    });
+}
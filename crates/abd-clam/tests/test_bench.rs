@@ -0,0 +1,56 @@
+//! Tests for the benchmark harness.
+
+use abd_clam::{bench::Sweep, knn, rnn, Cakes, PartitionCriteria};
+
+mod utils;
+
+#[test]
+fn sweep() {
+    let data = utils::gen_dataset(1000, 10, 42, utils::euclidean);
+    let queries = utils::gen_dataset(10, 10, 0, utils::euclidean);
+    let queries = (0..10).map(|i| &queries[i]).collect::<Vec<_>>();
+    let cakes = Cakes::new(data, Some(42), &PartitionCriteria::default());
+
+    let sweep = Sweep::new()
+        .with_knn_algorithms(&[
+            knn::Algorithm::Linear,
+            knn::Algorithm::GreedySieve,
+            knn::Algorithm::Approximate { recall: 0.5 },
+        ])
+        .with_ks(&[1, 10])
+        .with_rnn_algorithms(&[rnn::Algorithm::Linear, rnn::Algorithm::Clustered])
+        .with_radii(&[0.5, 1.0])
+        .with_repeats(2);
+    let report = sweep.run(&cakes, &queries).unwrap();
+
+    assert_eq!(report.cardinality, 1000);
+    assert_eq!(report.num_queries, 10);
+    assert_eq!(report.measurements.len(), 2 * 3 + 2 * 2);
+    for m in &report.measurements {
+        assert!(m.seconds >= 0.0 && m.recall <= 1.0, "{m:?}");
+        if m.algorithm != "Approximate" {
+            assert_eq!(m.recall, 1.0, "{m:?}");
+        }
+        if m.search == "knn" {
+            assert_eq!(m.mean_hits, m.k.unwrap() as f64, "{m:?}");
+        }
+    }
+    assert!(report.fastest_knn(10).is_some_and(|m| m.algorithm != "Approximate"));
+    assert!(report.fastest_rnn(0.5_f32).is_some());
+    assert!(report.fastest_knn(5).is_none());
+
+    let json = report.to_json().unwrap();
+    assert_eq!(abd_clam::bench::BenchReport::from_json(&json).unwrap(), report);
+
+    let csv = report.to_csv();
+    let lines = csv.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 1 + report.measurements.len());
+    assert_eq!(
+        lines[0],
+        "search,algorithm,k,radius,seconds,throughput,mean_hits,recall"
+    );
+    assert!(lines[1].starts_with("knn,Linear,1,,"));
+    assert!(lines.last().unwrap().starts_with("rnn,Clustered,,1,"));
+
+    assert!(sweep.run(&cakes, &[]).is_err());
+}
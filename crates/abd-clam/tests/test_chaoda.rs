@@ -0,0 +1,36 @@
+//! Tests for CHAODA.
+
+use abd_clam::{
+    chaoda::{Chaoda, Vertex},
+    PartitionCriteria, Tree,
+};
+use rand::prelude::*;
+
+mod utils;
+
+#[test]
+fn fit_score() {
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut data = symagen::random_data::random_tabular(1000, 2, -1., 1., &mut rng);
+    let outliers = [[20., 20.], [-20., 25.], [30., -20.], [-25., -30.], [0., 40.]];
+    data.extend(outliers.iter().map(|o| o.to_vec()));
+    let data = utils::gen_dataset_from(data, utils::euclidean::<f32, f32>, vec![0_usize; 1005]);
+
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, Vertex<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+
+    let mut chaoda = Chaoda::default();
+    assert!(chaoda.score().is_empty());
+
+    chaoda.fit(&tree);
+    let scores = chaoda.score();
+    assert_eq!(scores.len(), 1005);
+    assert!(scores.iter().all(|s| s.is_finite()));
+
+    let mean_inlier = scores[..1000].iter().sum::<f64>() / 1000.;
+    let mean_outlier = scores[1000..].iter().sum::<f64>() / 5.;
+    assert!(
+        mean_outlier > mean_inlier,
+        "Outliers: {mean_outlier}, Inliers: {mean_inlier}"
+    );
+}
@@ -1,6 +1,19 @@
 //! Tests for the dataset module.
 
-use abd_clam::{Dataset, VecDataset};
+use std::{
+    borrow::Cow,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use abd_clam::{
+    cakes::{knn, rnn},
+    BlockKernel, BlockedDataset, Cakes, Dataset, FnMetric, IndirectDataset, Instance, LeafStore, Metric, MmapDataset,
+    NonFinitePolicy, PartitionCriteria, Permutation, SequenceDataset, SharedMetric, SliceDataset, Tree, UniBall,
+    VecDataset,
+};
+use distances::Number;
+use float_cmp::assert_approx_eq;
 use rand::prelude::*;
 use tempdir::TempDir;
 use test_case::test_case;
@@ -129,3 +142,707 @@ fn load_errors() {
     let other = VecDataset::<Vec<f32>, f32, usize>::load(&tmp_file, utils::euclidean, false);
     assert!(other.is_err());
 }
+
+/// Euclidean distance between two arrays.
+fn euclidean_array<const N: usize>(x: &[f32; N], y: &[f32; N]) -> f32 {
+    distances::vectors::euclidean(x, y)
+}
+
+#[test]
+fn mmap_dataset() {
+    let (cardinality, dimensionality) = (1_000, 10);
+    let data = utils::gen_dataset(cardinality, dimensionality, 42, utils::euclidean);
+
+    let tmp_dir = TempDir::new("mmap_dataset").unwrap();
+    let tmp_file = tmp_dir.path().join("data.clam");
+    let mmap_data = MmapDataset::<f32, f32, 10>::from_vec_dataset(&data, &tmp_file, euclidean_array, false).unwrap();
+
+    assert_eq!(mmap_data.cardinality(), cardinality);
+    assert_eq!(mmap_data.name(), data.name());
+    for i in 0..cardinality {
+        assert_eq!(mmap_data[i].as_slice(), data[i].as_slice());
+    }
+
+    // The file must be rejected if the dimensionality or element type do not match.
+    assert!(MmapDataset::<f32, f32, 9>::open(&tmp_file, euclidean_array, false).is_err());
+    assert!(MmapDataset::<u32, f32, 10>::open(&tmp_file, |_, _| 0., false).is_err());
+
+    // Building a tree reorders the instances in the file.
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(mmap_data, Some(42)).partition(&criteria, Some(42));
+    for i in 0..cardinality {
+        let original = tree.data().original_index(i);
+        assert_eq!(tree.data()[i].as_slice(), data[original].as_slice());
+    }
+
+    for query in data.data().iter().take(10) {
+        let query: [f32; 10] = query.as_slice().try_into().unwrap();
+        let linear_hits = knn::Algorithm::Linear.search(&tree, &query, 10);
+        let hits = knn::Algorithm::GreedySieve.search(&tree, &query, 10);
+        assert_approx_eq!(f32, utils::compute_recall(hits, linear_hits), 1.0);
+    }
+
+    // The reordering persists in the file and the permutation is saved.
+    let save_file = tmp_dir.path().join("dataset.save");
+    tree.data().save(&save_file).unwrap();
+    let loaded = MmapDataset::<f32, f32, 10>::load(&save_file, euclidean_array, false).unwrap();
+    assert_eq!(loaded.permuted_indices(), tree.data().permuted_indices());
+    for i in 0..cardinality {
+        assert_eq!(loaded[i], tree.data()[i]);
+    }
+
+    // Shards are views into the same file.
+    let shards = loaded.make_shards(300);
+    assert_eq!(shards.iter().map(Dataset::cardinality).sum::<usize>(), cardinality);
+    let shard = shards.iter().find(|s| s.cardinality() == 100).unwrap();
+    for i in 0..shard.cardinality() {
+        assert_eq!(shard[i], tree.data()[i]);
+    }
+
+    // Shards share one memory map and may each be reordered, in parallel.
+    let mut shards = shards;
+    let expected = shards.iter().map(|s| (s[0], s[1])).collect::<Vec<_>>();
+    std::thread::scope(|scope| {
+        for s in &mut shards {
+            scope.spawn(|| s.swap(0, 1).unwrap());
+        }
+    });
+    for (s, (first, second)) in shards.iter().zip(expected) {
+        assert_eq!((s[0], s[1]), (second, first));
+    }
+
+    // A dataset cannot be reordered while it shares its instances with a
+    // clone.
+    let mut shard = shards.pop().unwrap();
+    let clone = shard.clone_with_new_metric(euclidean_array, false, "clone".to_string());
+    assert_eq!(clone[0], shard[0]);
+    assert!(shard.swap(0, 1).is_err());
+    drop(clone);
+    assert!(shard.swap(0, 1).is_ok());
+}
+
+#[test]
+fn slice_dataset() {
+    let (cardinality, dimensionality) = (1_000, 10);
+    let data = utils::gen_dataset(cardinality, dimensionality, 42, utils::euclidean);
+    let rows = data.data().to_vec();
+
+    // The tree over the borrowed rows is the same as that over the owned rows.
+    let criteria = PartitionCriteria::default();
+    let slice_data = SliceDataset::new("slice".to_string(), &rows, utils::euclidean, false);
+    let tree = Tree::<_, _, _, UniBall<_>>::new(slice_data, Some(42)).partition(&criteria, Some(42));
+    let vec_tree = Tree::<_, _, _, UniBall<_>>::new(data.clone(), Some(42)).partition(&criteria, Some(42));
+    assert_eq!(tree.data().permuted_indices(), vec_tree.data().permuted_indices());
+
+    // The rows are borrowed in their original order, and only the index layer
+    // is reordered.
+    assert!(core::ptr::eq(tree.data().rows(), rows.as_slice()));
+    for i in 0..cardinality {
+        assert_eq!(tree.data()[i], rows[tree.data().original_index(i)]);
+    }
+
+    for query in rows.iter().take(10) {
+        let linear_hits = knn::Algorithm::Linear.search(&tree, query, 10);
+        let hits = knn::Algorithm::GreedySieve.search(&tree, query, 10);
+        assert_approx_eq!(f32, utils::compute_recall(hits, linear_hits), 1.0);
+    }
+
+    // A saved tree is loaded over a new view of the same rows.
+    let tmp_dir = TempDir::new("slice_dataset").unwrap();
+    tree.save(tmp_dir.path()).unwrap();
+    let view = SliceDataset::new("slice".to_string(), &rows, utils::euclidean::<f32, f32>, false);
+    let loaded = Tree::<_, _, _, UniBall<_>>::load_with_data(tmp_dir.path(), view).unwrap();
+    for i in 0..cardinality {
+        assert_eq!(loaded.data()[i], tree.data()[i]);
+    }
+    let dataset_path = tmp_dir.path().join("dataset");
+    assert!(SliceDataset::<Vec<f32>, f32>::load(&dataset_path, utils::euclidean, false).is_err());
+
+    // Shards are views into the same rows.
+    let shards = tree.data().clone().make_shards(300);
+    assert_eq!(shards.iter().map(Dataset::cardinality).sum::<usize>(), cardinality);
+    assert!(shards.iter().all(|s| core::ptr::eq(s.rows(), rows.as_slice())));
+
+    // A flat buffer is viewed as fixed-width rows without copying.
+    let flat = rows.iter().flatten().copied().collect::<Vec<_>>();
+    let metric = FnMetric::new(euclidean_array::<10> as fn(&[f32; 10], &[f32; 10]) -> f32);
+    let flat_data = SliceDataset::from_flat("flat".to_string(), &flat, metric).unwrap();
+    assert_eq!(flat_data.cardinality(), cardinality);
+    assert_eq!(flat_data[3].as_slice(), rows[3].as_slice());
+    assert!(
+        SliceDataset::<[f32; 7], f32>::from_flat("flat".to_string(), &flat[..10], FnMetric::new(euclidean_array))
+            .is_err()
+    );
+}
+
+/// A dataset which stores its instances as bytes and decodes them on demand.
+#[derive(Debug)]
+struct Encoded {
+    rows: Vec<Vec<u8>>,
+    num_decoded: AtomicUsize,
+    metric: SharedMetric<Vec<f32>, f32>,
+    permuted_indices: Option<Vec<usize>>,
+}
+
+impl Encoded {
+    fn new(rows: &[Vec<f32>]) -> Self {
+        Self {
+            rows: rows.iter().map(Instance::to_bytes).collect(),
+            num_decoded: AtomicUsize::new(0),
+            metric: SharedMetric::from(utils::euclidean as fn(&Vec<f32>, &Vec<f32>) -> f32),
+            permuted_indices: None,
+        }
+    }
+}
+
+impl Dataset<Vec<f32>, f32> for Encoded {
+    fn clone_with_new_metric(&self, _: fn(&Vec<f32>, &Vec<f32>) -> f32, _: bool, _: String) -> Self {
+        unimplemented!()
+    }
+
+    fn type_name() -> String {
+        "Encoded".to_string()
+    }
+
+    fn name(&self) -> &str {
+        "encoded"
+    }
+
+    fn cardinality(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn get(&self, index: usize) -> Cow<'_, Vec<f32>> {
+        self.num_decoded.fetch_add(1, Ordering::Relaxed);
+        Cow::Owned(Vec::from_bytes(&self.rows[index]).unwrap())
+    }
+
+    fn is_metric_expensive(&self) -> bool {
+        false
+    }
+
+    fn metric(&self) -> &SharedMetric<Vec<f32>, f32> {
+        &self.metric
+    }
+
+    fn set_permuted_indices(&mut self, indices: Option<&[usize]>) {
+        self.permuted_indices = indices.map(<[usize]>::to_vec);
+    }
+
+    fn swap(&mut self, left: usize, right: usize) -> Result<(), String> {
+        self.rows.swap(left, right);
+        Ok(())
+    }
+
+    fn permuted_indices(&self) -> Option<&[usize]> {
+        self.permuted_indices.as_deref()
+    }
+
+    fn make_shards(self, _: usize) -> Vec<Self> {
+        vec![self]
+    }
+
+    fn save(&self, _: &Path) -> Result<(), String> {
+        Err("Not supported".to_string())
+    }
+
+    fn load(_: &Path, _: fn(&Vec<f32>, &Vec<f32>) -> f32, _: bool) -> Result<Self, String> {
+        Err("Not supported".to_string())
+    }
+}
+
+#[test]
+fn lazy_instances() {
+    let (cardinality, dimensionality) = (1_000, 10);
+    let data = utils::gen_dataset(cardinality, dimensionality, 42, utils::euclidean);
+    let queries = utils::gen_dataset(10, dimensionality, 0, utils::euclidean).data_owned();
+
+    let criteria = PartitionCriteria::default();
+    let encoded = Encoded::new(data.data());
+    let tree = Tree::<_, _, _, UniBall<_>>::new(encoded, Some(42)).partition(&criteria, Some(42));
+    let vec_tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+    assert_eq!(tree.data().permuted_indices(), vec_tree.data().permuted_indices());
+
+    for query in &queries {
+        let expected = knn::Algorithm::Linear.search(&vec_tree, query, 10);
+        for algo in knn::Algorithm::variants() {
+            let hits = algo.search(&tree, query, 10);
+            assert_approx_eq!(f32, utils::compute_recall(hits, expected.clone()), 1.0);
+        }
+
+        let radius = expected.last().map_or(0.0, |&(_, d)| d);
+        let mut expected = rnn::Algorithm::Linear.search(query, radius, &vec_tree);
+        let mut hits = rnn::Algorithm::Clustered.search(query, radius, &tree);
+        expected.sort_by_key(|&(i, _)| i);
+        hits.sort_by_key(|&(i, _)| i);
+        assert_eq!(hits.len(), expected.len());
+        assert!(hits.iter().zip(&expected).all(|(h, e)| h.0 == e.0));
+    }
+
+    // The dataset cannot be indexed into, so every instance was decoded on
+    // demand and dropped after use.
+    let num_decoded = tree.data().num_decoded.load(Ordering::Relaxed);
+    assert!(num_decoded > 0);
+    assert_eq!(*tree.data().get(0), vec_tree.data()[0]);
+    assert_eq!(tree.data().num_decoded.load(Ordering::Relaxed), num_decoded + 1);
+}
+
+#[test]
+fn sequence_dataset() {
+    let sequences = symagen::random_data::random_string(500, 40, 60, "ACTG", 42);
+    let metric = abd_clam::metrics::sequences::levenshtein_metric::<Vec<u8>, u16>();
+    let data = SequenceDataset::from_metric("sequences".to_string(), &sequences, metric);
+    assert_eq!(data.cardinality(), sequences.len());
+    assert_eq!(data.num_bytes(), sequences.iter().map(String::len).sum::<usize>());
+    assert_eq!(data.sequence(3), sequences[3].as_bytes());
+
+    // Swapping sequences of different lengths moves those between them.
+    let mut swapped = data.clone();
+    swapped.swap(7, 2).unwrap();
+    assert_eq!(swapped.sequence(2), sequences[7].as_bytes());
+    assert_eq!(swapped.sequence(7), sequences[2].as_bytes());
+    for i in (0..2).chain(3..7).chain(8..sequences.len()) {
+        assert_eq!(swapped.sequence(i), sequences[i].as_bytes());
+    }
+    assert!(swapped.swap(0, sequences.len()).is_err());
+
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+    for i in 0..sequences.len() {
+        assert_eq!(
+            tree.data().sequence(i),
+            sequences[tree.data().original_index(i)].as_bytes()
+        );
+    }
+
+    for query in sequences.iter().step_by(50).map(|s| s.as_bytes().to_vec()) {
+        let linear_hits = knn::Algorithm::Linear.search(&tree, &query, 10);
+        for algo in knn::Algorithm::variants() {
+            let hits = algo.search(&tree, &query, 10);
+            assert_approx_eq!(f32, utils::compute_recall(hits, linear_hits.clone()), 1.0);
+        }
+    }
+
+    let tmp_dir = TempDir::new("sequence_dataset").unwrap();
+    let tmp_file = tmp_dir.path().join("dataset.save");
+    tree.data().save(&tmp_file).unwrap();
+    let loaded = SequenceDataset::<u16>::load(&tmp_file, metric.function(), true).unwrap();
+    assert_eq!(loaded.permuted_indices(), tree.data().permuted_indices());
+    for i in 0..sequences.len() {
+        assert_eq!(loaded.sequence(i), tree.data().sequence(i));
+    }
+    assert!(SequenceDataset::<u32>::load(&tmp_file, abd_clam::metrics::sequences::levenshtein, true).is_err());
+
+    let shards = loaded.make_shards(150);
+    assert_eq!(shards.len(), 4);
+    let resharded = shards
+        .iter()
+        .rev()
+        .flat_map(|s| (0..s.cardinality()).map(|i| s.sequence(i)));
+    assert!(resharded.eq((0..sequences.len()).map(|i| tree.data().sequence(i))));
+}
+
+#[test]
+fn leaf_store() {
+    let (cardinality, dimensionality) = (2_000, 10);
+    let data = utils::gen_dataset(cardinality, dimensionality, 42, utils::euclidean);
+    let queries = utils::gen_dataset(10, dimensionality, 0, utils::euclidean).data_owned();
+
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data.clone(), Some(42)).partition(&criteria, Some(42));
+    let expected = queries
+        .iter()
+        .map(|q| knn::Algorithm::Linear.search(&tree, q, 10))
+        .collect::<Vec<_>>();
+    let num_leaves = tree.leaves().len();
+
+    // Each leaf of 10-dimensional `f32`s takes 40 bytes per instance, so the
+    // cache holds only a few leaves.
+    let tmp_dir = TempDir::new("leaf_store").unwrap();
+    let cache_bytes = 4_000;
+    let tree = tree
+        .with_leaf_store(&tmp_dir.path().join("leaves"), cache_bytes)
+        .unwrap();
+    assert_eq!(tree.data().num_blocks(), num_leaves);
+    assert_eq!(tree.data().cardinality(), cardinality);
+    assert!(tree.data().permuted_indices().is_some());
+
+    for (query, expected) in queries.iter().zip(expected) {
+        for algo in knn::Algorithm::variants() {
+            let hits = algo.search(&tree, query, 10);
+            assert_approx_eq!(f32, utils::compute_recall(hits, expected.clone()), 1.0);
+        }
+        assert!(tree.data().cached_bytes() <= cache_bytes);
+    }
+    assert!(tree.data().num_block_reads() > 0);
+
+    // The store returns the same instances, in the same order, as the tree,
+    // and keeps none of them outside the cache.
+    for i in 0..cardinality {
+        assert_eq!(*tree.data().get(i), data[tree.data().original_index(i)]);
+    }
+    assert!(tree.data().cached_bytes() <= cache_bytes);
+
+    // A search whose leaves are all cached does not read from the file.
+    let large = Tree::<_, _, _, UniBall<_>>::new(data, Some(42))
+        .partition(&criteria, Some(42))
+        .with_leaf_store(&tmp_dir.path().join("large"), usize::MAX)
+        .unwrap();
+    let _ = knn::Algorithm::GreedySieve.search(&large, &queries[0], 10);
+    let num_reads = large.data().num_block_reads();
+    let _ = knn::Algorithm::GreedySieve.search(&large, &queries[0], 10);
+    assert_eq!(large.data().num_block_reads(), num_reads);
+
+    // The tree and its leaf store can be saved and loaded, without rewriting
+    // the instances.
+    let save_dir = tmp_dir.path().join("tree");
+    std::fs::create_dir(&save_dir).unwrap();
+    tree.save(&save_dir).unwrap();
+    let loaded = Tree::<_, _, LeafStore<_, _>, UniBall<_>>::load(&save_dir, utils::euclidean, false).unwrap();
+    assert_eq!(loaded.data().permuted_indices(), tree.data().permuted_indices());
+    for i in 0..cardinality {
+        assert_eq!(loaded.data().get(i), tree.data().get(i));
+    }
+    let hits = knn::Algorithm::GreedySieve.search(&loaded, &queries[0], 10);
+    let expected = knn::Algorithm::Linear.search(&tree, &queries[0], 10);
+    assert_approx_eq!(f32, utils::compute_recall(hits, expected), 1.0);
+
+    // The instances in the file cannot be reordered.
+    let mut store = LeafStore::<Vec<f32>, f32>::load(&save_dir.join("dataset"), utils::euclidean, false).unwrap();
+    assert!(store.swap(0, 1).is_err());
+}
+
+/// A deliberately asymmetric distance function.
+fn shifted(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    utils::euclidean_sq(x, y) + x[0].max(0.0)
+}
+
+#[test]
+fn metric_properties() {
+    let euclidean: fn(&Vec<f32>, &Vec<f32>) -> f32 = utils::euclidean;
+    assert!(euclidean.is_metric() && euclidean.is_symmetric() && !euclidean.is_expensive());
+    assert_approx_eq!(f32, euclidean.distance(&vec![0., 0.], &vec![3., 4.]), 5.0);
+
+    // Squared euclidean distance does not obey the triangle inequality.
+    let metric = FnMetric::new(utils::euclidean_sq as fn(&Vec<f32>, &Vec<f32>) -> f32).with_is_metric(false);
+    let data = symagen::random_data::random_tabular(1000, 10, -1., 1., &mut rand::rngs::StdRng::seed_from_u64(42));
+    let data = VecDataset::from_metric("squared".to_string(), data, metric);
+    assert!(!data.is_metric() && data.is_metric_symmetric());
+    assert!(!data.metric().is_metric());
+
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+
+    let query = &vec![0.; 10];
+    let linear = knn::Algorithm::Linear.search(&tree, query, 10);
+    for variant in knn::Algorithm::variants() {
+        let hits = variant.search(&tree, query, 10);
+        assert_approx_eq!(f32, utils::compute_recall(hits, linear.clone()), 1.0);
+    }
+
+    let linear = rnn::Algorithm::Linear.search(query, 1.0, &tree);
+    let hits = rnn::Algorithm::Clustered.search(query, 1.0, &tree);
+    assert_eq!(hits.len(), linear.len());
+
+    // Pairwise distances are calculated in both directions for asymmetric functions.
+    let metric = FnMetric::new(shifted as fn(&Vec<f32>, &Vec<f32>) -> f32).with_is_symmetric(false);
+    let data = vec![vec![1., 0.], vec![0., 0.], vec![2., 1.]];
+    let data = VecDataset::from_metric("shifted".to_string(), data, metric);
+    let matrix = data.pairwise(&[0, 1, 2]);
+    for i in 0..3 {
+        for j in 0..3 {
+            assert_approx_eq!(f32, matrix[i][j], shifted(&data[i], &data[j]));
+        }
+    }
+}
+
+#[test]
+fn metric_properties_round_trip() {
+    let tmp_dir = TempDir::new("metric_properties_round_trip").unwrap();
+    let metric = FnMetric::new(shifted as fn(&Vec<f32>, &Vec<f32>) -> f32)
+        .with_is_metric(false)
+        .with_is_symmetric(false);
+    let data = symagen::random_data::random_tabular(100, 10, -1., 1., &mut rand::rngs::StdRng::seed_from_u64(42));
+    let data = VecDataset::from_metric("shifted".to_string(), data, metric);
+
+    // The properties are saved with the dataset, as the function is not.
+    let tmp_file = tmp_dir.path().join("dataset.save");
+    data.save(&tmp_file).unwrap();
+    let loaded = VecDataset::<Vec<f32>, f32, usize>::load(&tmp_file, shifted, false).unwrap();
+    assert!(!loaded.is_metric() && !loaded.is_metric_symmetric());
+
+    // Shards keep the properties of the dataset.
+    for shard in loaded.make_shards(30) {
+        assert!(!shard.is_metric() && !shard.is_metric_symmetric());
+    }
+
+    // A new metric brings its own properties.
+    let squared = FnMetric::new(utils::euclidean_sq as fn(&Vec<f32>, &Vec<f32>) -> f32).with_is_metric(false);
+    let squared = data.clone_with_metric(squared, "squared".to_string());
+    assert!(!squared.is_metric() && squared.is_metric_symmetric());
+    let euclidean = squared.clone_with_new_metric(utils::euclidean, false, "euclidean".to_string());
+    assert!(euclidean.is_metric() && euclidean.is_metric_symmetric());
+}
+
+/// A euclidean distance with a weight for each dimension, which is not a plain
+/// function.
+#[derive(Debug)]
+struct Weighted {
+    weights: Vec<f32>,
+}
+
+impl Metric<Vec<f32>, f32> for Weighted {
+    fn distance(&self, x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+        x.iter()
+            .zip(y)
+            .zip(&self.weights)
+            .map(|((a, b), w)| w * (a - b).powi(2))
+            .sum::<f32>()
+            .sqrt()
+    }
+
+    fn is_expensive(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn custom_metric() {
+    let weights = (1..=10).map(|w| w.as_f32()).collect::<Vec<_>>();
+    let metric = SharedMetric::new(Weighted { weights });
+    let data = symagen::random_data::random_tabular(1000, 10, -1., 1., &mut rand::rngs::StdRng::seed_from_u64(42));
+    let data = VecDataset::from_metric("weighted".to_string(), data, metric);
+    assert!(data.is_metric() && data.is_metric_expensive());
+    assert!(data.metric().as_fn_metric().is_none());
+
+    let query = vec![0.; 10];
+    let expected = (1..=10)
+        .map(|w| w.as_f32() * data[0][w - 1].powi(2))
+        .sum::<f32>()
+        .sqrt();
+    assert_approx_eq!(f32, data.query_to_one(&query, 0), expected);
+
+    // Shards and clones share the metric.
+    for shard in data.clone().make_shards(300) {
+        assert!(shard.is_metric_expensive());
+        assert_approx_eq!(
+            f32,
+            shard.query_to_one(&query, 0),
+            data.metric().distance(&query, &shard[0])
+        );
+    }
+
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+    let linear = knn::Algorithm::Linear.search(&tree, &query, 10);
+    for variant in knn::Algorithm::variants() {
+        let hits = variant.search(&tree, &query, 10);
+        assert_approx_eq!(f32, utils::compute_recall(hits, linear.clone()), 1.0);
+    }
+}
+
+#[test]
+fn pairwise_submatrix() {
+    let data = utils::gen_dataset(100, 10, 42, utils::euclidean);
+    let indices = [3, 97, 0, 42, 15, 64];
+    let n = indices.len();
+
+    let matrix = data.pairwise(&indices);
+    let condensed = data.pairwise_submatrix(&indices);
+    assert_eq!(condensed.len(), n * (n - 1) / 2);
+    for i in 0..n {
+        for j in (i + 1)..n {
+            assert_approx_eq!(f32, condensed[n * i - i * (i + 1) / 2 + (j - i - 1)], matrix[i][j]);
+        }
+    }
+
+    assert!(data.pairwise_submatrix(&[7]).is_empty());
+    assert!(data.pairwise_submatrix(&[]).is_empty());
+}
+
+#[test]
+fn weights() {
+    let metric = utils::euclidean_sq::<u32>;
+    let data = vec![vec![0], vec![1], vec![2], vec![10]];
+    let dataset = VecDataset::new("weighted".to_string(), data, metric, false);
+    assert!(dataset.weights().is_none());
+    assert_approx_eq!(f64, dataset.weight(3), 1.0);
+    assert_eq!(dataset.median(&[0, 1, 2, 3]), Some(2));
+
+    // A heavy instance pulls the median towards itself.
+    let mut dataset = dataset.with_weights(vec![1.0, 1.0, 1.0, 100.0]).unwrap();
+    assert_eq!(dataset.median(&[0, 1, 2, 3]), Some(3));
+    assert_eq!(dataset.par_median(&[0, 1, 2, 3]), Some(3));
+    assert!(dataset.clone().with_weights(vec![1.0]).is_err());
+    assert!(dataset.clone().with_weights(vec![1.0, 1.0, f64::NAN, 1.0]).is_err());
+    assert!(dataset.set_weight(0, -1.0).is_err());
+    assert!(dataset.set_weight(4, 1.0).is_err());
+
+    // The weights follow the instances through permutation and saving.
+    dataset.set_weight(0, 2.0).unwrap();
+    dataset.permute_instances(&[3, 2, 1, 0]).unwrap();
+    assert_eq!(dataset.weights(), Some([100.0, 1.0, 1.0, 2.0].as_slice()));
+
+    let tmp_dir = TempDir::new("weights").unwrap();
+    let tmp_file = tmp_dir.path().join("dataset.save");
+    dataset.save(&tmp_file).unwrap();
+    let other = VecDataset::<_, _, usize>::load(&tmp_file, metric, false).unwrap();
+    assert_eq!(other.weights(), dataset.weights());
+
+    // The weights are returned with search hits, by their original index.
+    let data = utils::gen_dataset(1000, 10, 42, utils::euclidean);
+    let weights = (0..1000).map(|i| i.as_f64()).collect::<Vec<_>>();
+    let data = data.with_weights(weights).unwrap();
+    let cakes = Cakes::new(data, Some(42), &PartitionCriteria::default());
+    let data = cakes.trees()[0].data();
+    let query = &utils::gen_dataset(1, 10, 0, utils::euclidean)[0];
+    let hits = cakes.knn_search_weighted(query, 10, knn::Algorithm::default());
+    assert_eq!(hits.len(), 10);
+    for (i, _, w) in hits {
+        assert_approx_eq!(f64, w, data.original_index(i).as_f64());
+    }
+}
+
+#[test]
+fn permutation() {
+    let (cardinality, dimensionality) = (1_000, 10);
+    let data = utils::gen_dataset(cardinality, dimensionality, 42, utils::euclidean);
+    let rows = data.data().to_vec();
+    let labels = (0..cardinality).collect::<Vec<_>>();
+
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data.clone(), Some(42)).partition(&criteria, Some(42));
+    let permutation = tree.data().permutation();
+    assert_eq!(permutation.len(), cardinality);
+    assert!(!permutation.is_identity());
+    assert_eq!(permutation.apply(&labels).unwrap(), permutation.as_slice());
+    assert!(permutation.apply(&labels[..10]).is_err());
+    for i in 0..cardinality {
+        let original = permutation.to_original(i);
+        assert_eq!(tree.data()[i], rows[original]);
+        assert_eq!(permutation.from_original(original), Some(i));
+    }
+    assert_eq!(permutation.from_original(cardinality), None);
+    assert!(data.permutation().is_identity());
+    assert!(Permutation::new(vec![0, 2, 2]).is_err());
+
+    // Without reordering the instances, the tree and its search results are
+    // the same.
+    let indirect = IndirectDataset::new(data);
+    let indirect_tree = Tree::<_, _, _, UniBall<_>>::new(indirect, Some(42)).partition(&criteria, Some(42));
+    assert_eq!(indirect_tree.data().permutation(), permutation);
+    assert_eq!(indirect_tree.data().inner().data(), rows.as_slice());
+    assert_eq!(indirect_tree.data().order(), permutation.as_slice());
+    for query in rows.iter().take(10) {
+        let hits = knn::Algorithm::GreedySieve.search(&indirect_tree, query, 10);
+        let linear_hits = knn::Algorithm::Linear.search(&tree, query, 10);
+        assert_approx_eq!(f32, utils::compute_recall(hits, linear_hits), 1.0);
+    }
+
+    // Reordering is a separate step, which gives the same tree.
+    let shards = indirect_tree.data().clone().make_shards(300);
+    assert!(shards
+        .iter()
+        .all(|s| std::sync::Arc::ptr_eq(s.inner(), indirect_tree.data().inner())));
+    drop(shards);
+    let reordered = indirect_tree.reorder().unwrap();
+    assert_eq!(reordered.data().data(), tree.data().data());
+    assert_eq!(reordered.data().permutation(), permutation);
+}
+
+#[test]
+fn blocked_dataset() {
+    let (cardinality, dimensionality) = (1_000, 20);
+    let rows = utils::gen_dataset(cardinality, dimensionality, 42, utils::euclidean)
+        .data()
+        .to_vec();
+    assert!(BlockedDataset::new(
+        "ragged".to_string(),
+        vec![vec![0.; 3], vec![0.; 2]],
+        BlockKernel::Euclidean
+    )
+    .is_err());
+
+    let blocked = BlockedDataset::new("blocked".to_string(), rows.clone(), BlockKernel::Euclidean).unwrap();
+    assert_eq!(blocked.dim(), dimensionality);
+    assert!(blocked.is_metric());
+
+    let criteria = PartitionCriteria::default();
+    let blocked_tree = Tree::<_, _, _, UniBall<_>>::new(blocked, Some(42)).partition(&criteria, Some(42));
+    assert!(!blocked_tree.data().permutation().is_identity());
+
+    // The blocks follow the instances as they are reordered, so scans of any
+    // contiguous range, aligned to the blocks or not, give the same distances.
+    let check = |data: &BlockedDataset, query: &Vec<f32>, indices: &[usize]| {
+        let distances = data.query_to_many(query, indices);
+        assert_eq!(distances.len(), indices.len());
+        for (&i, &d) in indices.iter().zip(distances.iter()) {
+            assert_approx_eq!(f32, d, data.query_to_one(query, i), epsilon = 1e-4);
+        }
+    };
+    for query in rows.iter().take(10) {
+        check(blocked_tree.data(), query, &(3..50).collect::<Vec<_>>());
+        check(blocked_tree.data(), query, &(8..16).collect::<Vec<_>>());
+        check(blocked_tree.data(), query, &[5, 1, 900]);
+
+        let hits = knn::Algorithm::GreedySieve.search(&blocked_tree, query, 10);
+        let linear_hits = knn::Algorithm::Linear.search(&blocked_tree, query, 10);
+        assert_approx_eq!(f32, utils::compute_recall(hits, linear_hits), 1.0);
+    }
+
+    let mut swapped = blocked_tree.data().clone();
+    swapped.swap(0, 9).unwrap();
+    assert_eq!(swapped[0], blocked_tree.data()[9]);
+    check(&swapped, &rows[0], &(0..10).collect::<Vec<_>>());
+
+    let cosine = BlockedDataset::new("cosine".to_string(), rows.clone(), BlockKernel::Cosine).unwrap();
+    assert!(!cosine.is_metric());
+    let cosines = cosine.query_to_many(&rows[0], &(0..30).collect::<Vec<_>>());
+    for (x, &d) in rows.iter().zip(cosines.iter()) {
+        assert_approx_eq!(f32, d, distances::simd::cosine_f32(&rows[0], x), epsilon = 1e-4);
+    }
+
+    // The kernel is saved with the instances, and the blocks are rebuilt.
+    let tmp_dir = TempDir::new("blocked_dataset").unwrap();
+    let path = tmp_dir.path().join("blocked");
+    blocked_tree.data().save(&path).unwrap();
+    let loaded = BlockedDataset::load(&path, utils::euclidean, false).unwrap();
+    assert_eq!(loaded.kernel(), Some(BlockKernel::Euclidean));
+    assert_eq!(loaded.permutation(), blocked_tree.data().permutation());
+    check(&loaded, &rows[0], &(0..cardinality).collect::<Vec<_>>());
+
+    let shards = loaded.make_shards(300);
+    assert_eq!(shards.iter().map(Dataset::cardinality).sum::<usize>(), cardinality);
+    for shard in &shards {
+        check(shard, &rows[0], &(0..shard.cardinality()).collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn non_finite() {
+    let instances = vec![vec![1., 2.], vec![f32::NAN, 4.], vec![3., f32::INFINITY], vec![5., 6.]];
+    let data = VecDataset::new("nan".to_string(), instances, utils::euclidean::<f32, f32>, false)
+        .assign_metadata(vec![10_usize, 11, 12, 13])
+        .unwrap();
+    assert_eq!(data.non_finite(), vec![1, 2]);
+
+    let err = data.clone().validate(NonFinitePolicy::Reject).unwrap_err();
+    assert!(err.contains("[1, 2]"), "{err}");
+
+    let (imputed, found) = data.clone().validate(NonFinitePolicy::Impute).unwrap();
+    assert_eq!(found.indices, vec![1, 2]);
+    assert!(found.quarantined.is_empty());
+    assert_eq!(
+        imputed.data(),
+        &[vec![1., 2.], vec![3., 4.], vec![3., 4.], vec![5., 6.]]
+    );
+
+    let (quarantined, found) = data.validate(NonFinitePolicy::Quarantine).unwrap();
+    assert_eq!(quarantined.cardinality(), 2);
+    assert_eq!(quarantined.metadata(), &[10, 13]);
+    assert_eq!(quarantined.original_index(1), 3);
+    assert_eq!(found.quarantined.len(), 2);
+    assert_eq!(found.quarantined[0].1, 11);
+    assert!(found.quarantined[1].0[1].is_infinite());
+    assert!(quarantined.non_finite().is_empty());
+}
@@ -0,0 +1,83 @@
+//! Tests for embedding the cluster graph in low dimensions.
+
+use abd_clam::{
+    chaoda::{Graph, Vertex},
+    dim_red::Embedding,
+    Cluster, Dataset, PartitionCriteria, Tree,
+};
+use rand::prelude::*;
+
+mod utils;
+
+#[test]
+fn embedding() {
+    // Two blobs, far apart from each other.
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut data = symagen::random_data::random_tabular(300, 3, -1., 1., &mut rng);
+    data.extend(symagen::random_data::random_tabular(300, 3, 99., 101., &mut rng));
+    let data = utils::gen_dataset_from(data, utils::euclidean::<f32, f32>, vec![0_usize; 600]);
+
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, Vertex<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+    let scorer = |clusters: &[&Vertex<f32>]| clusters.iter().map(|c| c.depth() as f32).collect();
+    let graph = Graph::from_tree(tree.root(), tree.data(), scorer, 4);
+
+    let embedding = Embedding::<2>::new(&graph, 50, Some(42));
+    assert_eq!(embedding.cardinality(), graph.cardinality());
+    for key in graph.iter_clusters() {
+        let position = embedding.position(key);
+        assert!(position.is_some_and(|p| p.iter().all(|x| x.is_finite())));
+    }
+
+    // The same seed gives the same embedding.
+    let again = Embedding::<2>::new(&graph, 50, Some(42));
+    assert!(embedding.iter_positions().eq(again.iter_positions()));
+
+    // Each instance is at its distance from the center of its cluster.
+    let coordinates = embedding.project(&tree);
+    assert_eq!(coordinates.len(), 600);
+    for i in 0..600 {
+        let key = graph
+            .cluster_containing(i)
+            .unwrap_or_else(|| unreachable!("Every instance is covered."));
+        let cluster = tree
+            .root()
+            .subtree()
+            .into_iter()
+            .find(|c| (c.offset(), c.cardinality()) == *key)
+            .unwrap_or_else(|| unreachable!("Every cluster in the graph is in the tree."));
+        let center = embedding
+            .position(key)
+            .unwrap_or_else(|| unreachable!("Every cluster is embedded."));
+        let point =
+            coordinates[tree.data().original_index(i)].unwrap_or_else(|| unreachable!("Every instance is covered."));
+
+        let distance = center
+            .iter()
+            .zip(point)
+            .map(|(c, p)| (c - p).powi(2))
+            .sum::<f32>()
+            .sqrt();
+        let expected = tree.data().one_to_one(cluster.arg_center(), i);
+        assert!((distance - expected).abs() <= 1e-3 * expected.max(1.0));
+    }
+
+    // The blobs are in different components, and stay apart in the embedding.
+    let blob_of = |i: usize| usize::from(i >= 300);
+    let centroids = [0, 1].map(|blob| {
+        let points = coordinates
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| blob_of(i) == blob)
+            .filter_map(|(_, p)| *p)
+            .collect::<Vec<_>>();
+        let n = points.len() as f32;
+        [0, 1].map(|k| points.iter().map(|p| p[k]).sum::<f32>() / n)
+    });
+    let spread = coordinates
+        .iter()
+        .enumerate()
+        .filter_map(|(i, p)| p.map(|p| (p[0] - centroids[blob_of(i)][0]).abs()))
+        .fold(0.0, f32::max);
+    assert!((centroids[0][0] - centroids[1][0]).abs() > spread);
+}
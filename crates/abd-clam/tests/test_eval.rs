@@ -0,0 +1,77 @@
+//! Tests for the evaluation of search results.
+
+use abd_clam::{eval, knn, Cakes, PartitionCriteria};
+use float_cmp::assert_approx_eq;
+use test_case::test_case;
+
+mod utils;
+
+#[test_case(&[(0, 1.), (1, 2.), (2, 3.)], 1.; "exact")]
+#[test_case(&[(2, 3.), (0, 1.), (1, 2.)], 1.; "unordered")]
+#[test_case(&[(0, 1.), (1, 2.), (9, 4.)], 2. / 3.; "one miss")]
+#[test_case(&[(0, 1.), (7, 3.), (2, 3.)], 1.; "tie at k")]
+#[test_case(&[(0, 1.), (0, 1.), (0, 1.)], 1. / 3.; "duplicates")]
+#[test_case(&[(0, 1.), (1, 2.), (7, 3.), (2, 3.)], 1.; "extra ties")]
+#[test_case(&[], 0.; "empty")]
+fn recall_at_k(results: &[(usize, f32)], expected: f64) {
+    let ground_truth = [(0, 1.), (1, 2.), (2, 3.)];
+    assert_approx_eq!(f64, eval::recall_at_k(results, &ground_truth), expected);
+}
+
+#[test_case(&[(0, 1.), (1, 2.)], 1.; "first")]
+#[test_case(&[(5, 4.), (1, 2.), (0, 1.)], 1. / 3.; "third")]
+#[test_case(&[(5, 4.), (6, 1.)], 0.5; "tie")]
+#[test_case(&[(5, 4.), (1, 2.)], 0.; "missing")]
+fn reciprocal_rank(results: &[(usize, f32)], expected: f64) {
+    let ground_truth = [(1, 2.), (0, 1.), (2, 3.)];
+    assert_approx_eq!(f64, eval::reciprocal_rank(results, &ground_truth), expected);
+}
+
+#[test_case(&[(0, 1.), (1, 2.), (2, 3.)], 1.; "exact")]
+#[test_case(&[(0, 1.), (1, 2.), (9, 6.)], 1.5; "one miss")]
+#[test_case(&[(0, 1.), (1, 2.), (2, 3.), (9, 6.)], 1.; "extra")]
+#[test_case(&[(1, 2.)], 2.; "fewer")]
+#[test_case(&[], 1.; "empty")]
+fn distance_ratio(results: &[(usize, f32)], expected: f64) {
+    let ground_truth = [(0, 1.), (1, 2.), (2, 3.)];
+    assert_approx_eq!(f64, eval::distance_ratio(results, &ground_truth), expected);
+}
+
+#[test]
+fn edge_cases() {
+    let none: [(usize, f32); 0] = [];
+    assert_approx_eq!(f64, eval::recall_at_k(&[(0, 1.)], &none), 1.);
+    assert_approx_eq!(f64, eval::reciprocal_rank(&[(0, 1.)], &none), 1.);
+
+    let zeros = [(0, 0_f32), (1, 0.)];
+    assert_approx_eq!(f64, eval::distance_ratio(&zeros, &zeros), 1.);
+    assert!(eval::distance_ratio(&[(2, 1_f32), (3, 1.)], &zeros).is_infinite());
+
+    // Integer distances are supported.
+    assert_approx_eq!(f64, eval::recall_at_k(&[(0, 1_u32), (3, 2)], &[(0, 1), (1, 2)]), 1.);
+
+    let results = vec![vec![(0, 1_f32)], vec![(5, 3.), (1, 1.)]];
+    let ground_truth = vec![vec![(0, 1_f32)], vec![(1, 1.)]];
+    assert_approx_eq!(f64, eval::mean_reciprocal_rank(&results, &ground_truth).unwrap(), 0.75);
+    assert!(eval::mean_reciprocal_rank(&results, &ground_truth[..1]).is_err());
+    assert!(eval::mean_reciprocal_rank::<f32>(&[], &[]).is_err());
+}
+
+#[test]
+fn search() {
+    let data = utils::gen_dataset(1000, 10, 42, utils::euclidean);
+    let queries = utils::gen_dataset(10, 10, 0, utils::euclidean);
+    let cakes = Cakes::new(data, Some(42), &PartitionCriteria::default());
+    let k = 10;
+
+    for query in queries.data() {
+        let ground_truth = cakes.knn_search(query, k, knn::Algorithm::Linear);
+        let hits = cakes.knn_search(query, k, knn::Algorithm::GreedySieve);
+        assert_approx_eq!(f64, eval::recall_at_k(&hits, &ground_truth), 1.);
+        assert_approx_eq!(f64, eval::distance_ratio(&hits, &ground_truth), 1.);
+
+        let mut ranked = hits.clone();
+        ranked.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        assert_approx_eq!(f64, eval::reciprocal_rank(&ranked, &ground_truth), 1.);
+    }
+}
@@ -0,0 +1,139 @@
+//! Tests for the C interface.
+
+#![cfg(feature = "ffi")]
+
+use core::ptr;
+use std::ffi::{CStr, CString};
+
+use abd_clam::ffi::{
+    clam_build, clam_cardinality, clam_dim, clam_free, clam_hits_free, clam_knn_search, clam_last_error,
+    clam_rnn_search, ClamHits, ClamIndex, ClamStatus,
+};
+use float_cmp::assert_approx_eq;
+use rand::prelude::*;
+
+fn euclidean(x: &[f32], y: &[f32]) -> f32 {
+    distances::simd::euclidean_f32(x, y)
+}
+
+/// Copies the hits of a successful search and frees them.
+unsafe fn take_hits(status: ClamStatus, hits: *mut ClamHits) -> Vec<(usize, f32)> {
+    assert_eq!(status, ClamStatus::Ok);
+    assert!(!hits.is_null());
+    let h = &*hits;
+    let indices = core::slice::from_raw_parts(h.indices, h.len);
+    let distances = core::slice::from_raw_parts(h.distances, h.len);
+    let hits_vec = indices.iter().copied().zip(distances.iter().copied()).collect();
+    assert_eq!(clam_hits_free(hits), ClamStatus::Ok);
+    hits_vec
+}
+
+unsafe fn last_error() -> String {
+    CStr::from_ptr(clam_last_error()).to_string_lossy().into_owned()
+}
+
+#[test]
+fn search() {
+    let (cardinality, dim) = (1000, 10);
+    let mut rng = StdRng::seed_from_u64(42);
+    let data = (0..cardinality * dim)
+        .map(|_| rng.gen_range(-1_f32..1.))
+        .collect::<Vec<_>>();
+    let rows = data.chunks_exact(dim).collect::<Vec<_>>();
+    let seed = 42_u64;
+
+    unsafe {
+        let mut index: *mut ClamIndex = ptr::null_mut();
+        let status = clam_build(data.as_ptr(), cardinality, dim, ptr::null(), &seed, 1, &mut index);
+        assert_eq!(status, ClamStatus::Ok);
+        assert!(!index.is_null());
+        assert_eq!(clam_cardinality(index), cardinality);
+        assert_eq!(clam_dim(index), dim);
+
+        let query = rows[7];
+        let mut expected = rows
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (i, euclidean(query, x)))
+            .collect::<Vec<_>>();
+        expected.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        // Indices are those of rows in the buffer.
+        let mut hits = ptr::null_mut();
+        let status = clam_knn_search(index, query.as_ptr(), 10, ptr::null(), &mut hits);
+        let hits = take_hits(status, hits);
+        assert_eq!(hits.len(), 10);
+        assert_eq!(hits[0].0, 7);
+        for ((i, d), (j, e)) in hits.iter().zip(expected.iter()) {
+            assert_eq!(i, j);
+            assert_approx_eq!(f32, *d, *e);
+        }
+
+        let algo = CString::new("Linear").unwrap();
+        let radius = expected[20].1;
+        let mut hits = ptr::null_mut();
+        let status = clam_rnn_search(index, query.as_ptr(), radius, algo.as_ptr(), &mut hits);
+        let hits = take_hits(status, hits);
+        assert_eq!(hits.len(), 21);
+        assert!(hits.windows(2).all(|w| w[0].1 <= w[1].1));
+
+        // Failures clear the output and record the error.
+        let algo = CString::new("Nonexistent").unwrap();
+        let mut hits = ptr::NonNull::dangling().as_ptr();
+        let status = clam_knn_search(index, query.as_ptr(), 10, algo.as_ptr(), &mut hits);
+        assert_eq!(status, ClamStatus::InvalidArgument);
+        assert!(hits.is_null());
+        assert_eq!(last_error(), "Unknown algorithm: Nonexistent");
+        let status = clam_knn_search(index, ptr::null(), 10, ptr::null(), &mut hits);
+        assert_eq!(status, ClamStatus::InvalidArgument);
+        assert_eq!(last_error(), "The query is null");
+        let status = clam_rnn_search(index, query.as_ptr(), radius, ptr::null(), ptr::null_mut());
+        assert_eq!(status, ClamStatus::InvalidArgument);
+        assert_eq!(last_error(), "The output pointer is null");
+
+        assert_eq!(clam_free(index), ClamStatus::Ok);
+    }
+}
+
+#[test]
+fn invalid_build() {
+    let data = [0_f32; 12];
+    unsafe {
+        let mut index = ptr::null_mut();
+        let status = clam_build(ptr::null(), 4, 3, ptr::null(), ptr::null(), 1, &mut index);
+        assert_eq!(status, ClamStatus::InvalidArgument);
+        assert!(index.is_null());
+        let status = clam_build(data.as_ptr(), 0, 3, ptr::null(), ptr::null(), 1, &mut index);
+        assert_eq!(status, ClamStatus::InvalidArgument);
+        assert_eq!(last_error(), "Cannot build a tree on an empty buffer");
+
+        let metric = CString::new("hamming").unwrap();
+        let status = clam_build(data.as_ptr(), 4, 3, metric.as_ptr(), ptr::null(), 1, &mut index);
+        assert_eq!(status, ClamStatus::InvalidArgument);
+        assert_eq!(last_error(), "Unknown metric: hamming");
+
+        // Freeing null does nothing.
+        assert_eq!(clam_free(ptr::null_mut()), ClamStatus::Ok);
+        assert_eq!(clam_hits_free(ptr::null_mut()), ClamStatus::Ok);
+    }
+}
+
+#[test]
+fn header() {
+    // The header declares every exported function.
+    let header = include_str!("../include/abd_clam.h");
+    let source = include_str!("../src/ffi.rs");
+    for name in source
+        .lines()
+        .filter_map(|l| {
+            l.strip_prefix("pub unsafe extern \"C\" fn ")
+                .or_else(|| l.strip_prefix("pub extern \"C\" fn "))
+        })
+        .filter_map(|l| l.split('(').next())
+    {
+        assert!(
+            header.contains(&format!(" *{name}(")) || header.contains(&format!(" {name}(")),
+            "{name}"
+        );
+    }
+}
@@ -0,0 +1,52 @@
+//! Tests for offloading leaf scans to a GPU.
+
+#![cfg(feature = "gpu")]
+
+use abd_clam::{
+    cakes::gpu::{self, GpuContext, GpuMetric},
+    knn, rnn, Dataset, FnMetric, PartitionCriteria, Tree, UniBall, VecDataset,
+};
+use float_cmp::assert_approx_eq;
+use test_case::test_case;
+
+mod utils;
+
+fn cosine(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    distances::vectors::cosine(x, y)
+}
+
+#[test_case(GpuMetric::Euclidean; "euclidean")]
+#[test_case(GpuMetric::Cosine; "cosine")]
+fn batch_search(metric: GpuMetric) {
+    let mut data = utils::gen_dataset(2_000, 10, 42, utils::euclidean);
+    if metric == GpuMetric::Cosine {
+        // The cosine distance does not obey the triangle inequality, so every
+        // leaf is scanned.
+        let rows = data.data().to_vec();
+        data = VecDataset::from_metric("cosine".to_string(), rows, FnMetric::new(cosine).with_is_metric(false));
+    }
+    let queries = utils::gen_dataset(50, 10, 0, utils::euclidean);
+    let queries = (0..queries.cardinality()).map(|i| &queries[i]).collect::<Vec<_>>();
+    let criteria = PartitionCriteria::default();
+    let mut tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+    tree.remove(0).unwrap();
+
+    // Without a GPU, e.g. in CI, the searches fall back to the CPU.
+    let context = GpuContext::new().ok();
+    for gpu in [None, context.as_ref()] {
+        let radius = if metric == GpuMetric::Cosine { 0.05 } else { 0.5 };
+        let hits = gpu::batch_rnn_search(gpu, metric, &tree, &queries, radius);
+        for (query, hits) in queries.iter().zip(hits) {
+            let expected = rnn::Algorithm::Clustered.search(*query, radius, &tree);
+            assert_eq!(hits.len(), expected.len());
+            assert!(hits.iter().all(|&(i, _)| !tree.is_removed(i)));
+        }
+
+        let hits = gpu::batch_knn_search(gpu, metric, &tree, &queries, 10);
+        for (query, hits) in queries.iter().zip(hits) {
+            let expected = knn::Algorithm::Linear.search(&tree, *query, 10);
+            assert_eq!(hits.len(), 10);
+            assert_approx_eq!(f32, utils::compute_recall(hits, expected), 1.0);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Tests for building a `Graph` from a layer of clusters.
+
+use abd_clam::{
+    chaoda::{Graph, Vertex},
+    Cluster, PartitionCriteria, Tree,
+};
+use rand::prelude::*;
+
+mod utils;
+
+#[test]
+fn graph_queries() {
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut data = symagen::random_data::random_tabular(500, 2, -1., 1., &mut rng);
+    data.push(vec![100., 100.]);
+    let data = utils::gen_dataset_from(data, utils::euclidean::<f32, f32>, vec![0_usize; 501]);
+
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, Vertex<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+
+    // Prefer the deepest clusters, i.e. select the leaves.
+    let scorer = |clusters: &[&Vertex<f32>]| clusters.iter().map(|c| c.depth() as f32).collect();
+    let graph = Graph::from_tree(tree.root(), tree.data(), scorer, 1);
+
+    // The selected clusters cover every instance exactly once.
+    assert_eq!(graph.population(), 501);
+    for i in 0..501 {
+        let key = graph.cluster_containing(i);
+        assert!(key.is_some(), "Instance {i} is not covered by the graph.");
+    }
+    assert!(graph.cluster_containing(501).is_none());
+
+    assert_eq!(graph.cardinality(), graph.iter_clusters().count());
+    assert_eq!(graph.num_components(), graph.iter_components().count());
+    assert_eq!(
+        graph.cardinality(),
+        graph.iter_components().map(|c| c.cardinality()).sum::<usize>()
+    );
+
+    // The outlier is far from everything else, so it sits in its own component.
+    assert!(graph.num_components() >= 2);
+    let outlier = (0..501)
+        .find(|&i| tree.data()[i][0] > 50.)
+        .and_then(|i| graph.cluster_containing(i))
+        .copied()
+        .unwrap_or_else(|| unreachable!("Every instance is covered."));
+    let component = graph
+        .component_of(&outlier)
+        .unwrap_or_else(|| unreachable!("Every cluster is in a component."));
+    assert_eq!(component.cardinality(), 1);
+    assert_eq!(component.num_edges(), 0);
+    assert!(graph.neighbors_of(&outlier).is_some_and(|n| n.is_empty()));
+
+    // Edges are symmetric and only exist between clusters in the same component.
+    let mut num_edges = 0;
+    for key in graph.iter_clusters() {
+        let neighbors = graph
+            .neighbors_of(key)
+            .unwrap_or_else(|| unreachable!("Every cluster has a neighbor list."));
+        let component = graph
+            .component_of(key)
+            .unwrap_or_else(|| unreachable!("Every cluster is in a component."));
+        for (other, &d) in neighbors {
+            assert!(component.contains(other));
+            assert_eq!(graph.neighbors_of(other).and_then(|n| n.get(key)), Some(&d));
+        }
+        num_edges += neighbors.len();
+    }
+    assert_eq!(graph.num_edges(), num_edges / 2);
+    assert!(graph.neighbors_of(&(501, 1)).is_none());
+}
@@ -0,0 +1,132 @@
+//! Tests for loading datasets in the ann-benchmarks HDF5 layout.
+
+#![cfg(feature = "hdf5")]
+
+use std::path::Path;
+
+use abd_clam::{knn, AnnBenchmark, Cakes, Dataset, Error, FnMetric, PartitionCriteria};
+use hdf5::{types::VarLenUnicode, File};
+use rand::prelude::*;
+use tempdir::TempDir;
+
+fn euclidean(x: &[f32], y: &[f32]) -> f32 {
+    distances::simd::euclidean_f32(x, y)
+}
+
+fn random_rows(cardinality: usize, dim: usize, rng: &mut StdRng) -> Vec<Vec<f32>> {
+    (0..cardinality)
+        .map(|_| (0..dim).map(|_| rng.gen_range(-1_f32..1.)).collect())
+        .collect()
+}
+
+/// Writes rows to a 2-dimensional dataset.
+fn write_rows<T: hdf5::H5Type + Copy>(file: &File, name: &str, rows: &[Vec<T>]) -> Result<(), String> {
+    let dim = rows.first().map_or(0, Vec::len);
+    let values = rows.iter().flatten().copied().collect::<Vec<_>>();
+    file.new_dataset::<T>()
+        .shape((rows.len(), dim))
+        .create(name)
+        .and_then(|d| d.write_raw(&values))
+        .map_err(|e| e.to_string())
+}
+
+/// Writes a file in the ann-benchmarks layout, with exact ground truth for `k`
+/// neighbors.
+fn write_benchmark(path: &Path, train: &[Vec<f32>], test: &[Vec<f32>], k: usize) -> Result<(), String> {
+    let mut neighbors = Vec::new();
+    let mut distances = Vec::new();
+    for query in test {
+        let mut hits = train
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (i, euclidean(query, x)))
+            .collect::<Vec<_>>();
+        hits.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        hits.truncate(k);
+        neighbors.push(hits.iter().map(|&(i, _)| i32::try_from(i).unwrap()).collect::<Vec<_>>());
+        distances.push(hits.iter().map(|&(_, d)| d).collect::<Vec<_>>());
+    }
+
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    write_rows(&file, "train", train)?;
+    write_rows(&file, "test", test)?;
+    write_rows(&file, "neighbors", &neighbors)?;
+    write_rows(&file, "distances", &distances)?;
+    let distance = "euclidean".parse::<VarLenUnicode>().map_err(|e| e.to_string())?;
+    file.new_attr::<VarLenUnicode>()
+        .create("distance")
+        .and_then(|a| a.write_scalar(&distance))
+        .map_err(|e| e.to_string())
+}
+
+#[test]
+fn load() -> Result<(), String> {
+    let mut rng = StdRng::seed_from_u64(42);
+    let train = random_rows(1000, 10, &mut rng);
+    let test = random_rows(20, 10, &mut rng);
+    let k = 10;
+
+    let tmp_dir = TempDir::new("hdf5").map_err(|e| e.to_string())?;
+    let path = tmp_dir.path().join("random-10-euclidean.hdf5");
+    write_benchmark(&path, &train, &test, k)?;
+
+    let benchmark = AnnBenchmark::load(&path)?;
+    assert_eq!(benchmark.distance(), "euclidean");
+    assert_eq!(benchmark.data().name(), "random-10-euclidean");
+    assert_eq!(benchmark.data().data(), &train);
+    assert_eq!(benchmark.queries(), &test);
+    assert_eq!(benchmark.neighbors().len(), test.len());
+    assert!(benchmark.neighbors().iter().all(|n| n.len() == k));
+
+    // Searching the loaded dataset finds the ground truth.
+    let (data, queries, _, _) = benchmark.clone().into_parts();
+    let cakes = Cakes::new(data, Some(42), &PartitionCriteria::default());
+    for (i, query) in queries.iter().enumerate() {
+        let mut hits = cakes
+            .knn_search(query, k, knn::Algorithm::Linear)
+            .into_iter()
+            .map(|(j, _)| cakes.original_index(j))
+            .collect::<Vec<_>>();
+        hits.sort_unstable();
+        let mut expected = benchmark
+            .ground_truth(i, k)
+            .into_iter()
+            .map(|(j, _)| j)
+            .collect::<Vec<_>>();
+        expected.sort_unstable();
+        assert_eq!(hits, expected);
+    }
+
+    assert_eq!(benchmark.ground_truth(0, 3).len(), 3);
+    assert_eq!(benchmark.ground_truth(0, 100).len(), k);
+
+    Ok(())
+}
+
+#[test]
+fn invalid() -> Result<(), String> {
+    let mut rng = StdRng::seed_from_u64(42);
+    let tmp_dir = TempDir::new("hdf5").map_err(|e| e.to_string())?;
+
+    assert!(AnnBenchmark::load(&tmp_dir.path().join("missing.hdf5")).is_err());
+
+    // The queries must have the dimensionality of the instances.
+    let path = tmp_dir.path().join("dim.hdf5");
+    let file = File::create(&path).map_err(|e| e.to_string())?;
+    write_rows(&file, "train", &random_rows(100, 10, &mut rng))?;
+    write_rows(&file, "test", &random_rows(5, 8, &mut rng))?;
+    write_rows(&file, "neighbors", &vec![vec![0_i32; 3]; 5])?;
+    write_rows(&file, "distances", &vec![vec![0_f32; 3]; 5])?;
+    drop(file);
+    let err = AnnBenchmark::load_with_metric(&path, FnMetric::new(|x, y| euclidean(x, y))).unwrap_err();
+    assert_eq!(err, Error::DimensionMismatch { expected: 10, found: 8 });
+
+    // The distance function must be named.
+    let path = tmp_dir.path().join("no-distance.hdf5");
+    let file = File::create(&path).map_err(|e| e.to_string())?;
+    write_rows(&file, "train", &random_rows(100, 10, &mut rng))?;
+    drop(file);
+    assert!(AnnBenchmark::load(&path).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,759 @@
+//! Tests for the built-in metrics.
+
+use abd_clam::{
+    cakes::{knn, rnn},
+    metrics::{
+        bits, geo, halves, histograms, mahalanobis, sequences, sets, sparse, time_series, vectors, BitVector,
+        HalfFormat, HalfVec, Histogram, LatLon, NormedVec, SortedSet, SparseVec, SparseVecDataset, TimeSeries, Whitened,
+        WhitenedDataset, Whitening,
+    },
+    Dataset, FnMetric, Instance, Metric, PartitionCriteria, SharedMetric, SimdKernel, Tree, UniBall, VecDataset,
+};
+use float_cmp::assert_approx_eq;
+use rand::prelude::*;
+use tempdir::TempDir;
+
+mod utils;
+
+#[test]
+fn cosine() {
+    let x = NormedVec::new(vec![1_f32, 0., 0.]);
+    let y = NormedVec::new(vec![0_f32, 1., 0.]);
+    let z = NormedVec::new(vec![2_f32, 0., 0.]);
+
+    assert_approx_eq!(f64, x.norm(), 1.0);
+    assert_approx_eq!(f32, vectors::cosine(&x, &y), 1.0);
+    assert_approx_eq!(f32, vectors::cosine(&x, &z), 0.0);
+
+    let mut rng = StdRng::seed_from_u64(42);
+    for _ in 0..100 {
+        let a = (0..10).map(|_| rng.gen_range(-1_f32..1.)).collect::<Vec<_>>();
+        let b = (0..10).map(|_| rng.gen_range(-1_f32..1.)).collect::<Vec<_>>();
+        let [aa, bb, ab] = a.iter().zip(b.iter()).fold([0_f32; 3], |[aa, bb, ab], (&x, &y)| {
+            [x.mul_add(x, aa), y.mul_add(y, bb), x.mul_add(y, ab)]
+        });
+        let expected = 1. - ab / (aa * bb).sqrt();
+        let actual: f32 = vectors::cosine(&NormedVec::new(a), &NormedVec::new(b));
+        assert_approx_eq!(f32, actual, expected, epsilon = 1e-5);
+    }
+
+    let bytes = z.to_bytes();
+    assert_eq!(NormedVec::<f32>::from_bytes(&bytes), Ok(z));
+    assert!(!vectors::cosine_metric::<f32, f32>().is_metric());
+}
+
+#[test]
+fn jaccard_sets() {
+    let x = SortedSet::new(vec![3_u32, 1, 2, 2]);
+    let y = SortedSet::new(vec![2_u32, 3, 4]);
+    let empty = SortedSet::<u32>::new(vec![]);
+
+    assert_eq!(x.elements(), &[1, 2, 3]);
+    assert_approx_eq!(f32, sets::jaccard(&x, &y), 0.5);
+    assert_approx_eq!(f32, sets::jaccard(&x, &x), 0.0);
+    assert_approx_eq!(f32, sets::jaccard(&x, &empty), 1.0);
+    assert_approx_eq!(f32, sets::jaccard(&empty, &empty), 0.0);
+
+    let bytes = y.to_bytes();
+    assert_eq!(SortedSet::<u32>::from_bytes(&bytes), Ok(y));
+    assert!(sets::jaccard_metric::<u32, f32>().is_metric());
+}
+
+#[test]
+fn bit_vectors() {
+    let bools = (0..130).map(|i| i % 3 == 0).collect::<Vec<_>>();
+    let x = BitVector::from_bools(&bools);
+    assert_eq!(x.len(), 130);
+    assert_eq!(x.count_ones(), bools.iter().filter(|&&b| b).count());
+    assert!(bools.iter().enumerate().all(|(i, &b)| x.get(i) == Some(b)));
+    assert_eq!(x.get(130), None);
+
+    let flipped = bools.iter().map(|b| !b).collect::<Vec<_>>();
+    let y = BitVector::from_bools(&flipped);
+    assert_eq!(bits::hamming::<u32>(&x, &y), 130);
+    assert_eq!(bits::hamming::<u32>(&x, &x), 0);
+    assert_approx_eq!(f32, bits::jaccard(&x, &y), 1.0);
+    assert_approx_eq!(f32, bits::jaccard(&x, &x), 0.0);
+
+    let z = BitVector::from_words(vec![u64::MAX, u64::MAX, u64::MAX], 130).unwrap();
+    assert_eq!(z.count_ones(), 130);
+    assert!(BitVector::from_words(vec![0], 130).is_err());
+
+    let bytes = x.to_bytes();
+    assert_eq!(BitVector::from_bytes(&bytes), Ok(x));
+}
+
+#[test]
+fn half_vectors() {
+    let mut rng = StdRng::seed_from_u64(42);
+    let data = (0..500)
+        .map(|_| (0..100).map(|_| rng.gen_range(-1_f32..1.)).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    for (format, tolerance) in [(HalfFormat::F16, 1e-3), (HalfFormat::Bf16, 1e-2)] {
+        let encoded = data.iter().map(|x| HalfVec::new(x, format)).collect::<Vec<_>>();
+        for (x, h) in data.iter().zip(encoded.iter()) {
+            assert_eq!(h.format(), format);
+            assert_eq!(h.len(), x.len());
+            assert!(x.iter().zip(h.to_f32()).all(|(a, b)| (a - b).abs() <= tolerance));
+        }
+
+        // Distances are computed on the widened vectors, so they are within
+        // rounding of the distances between the full-precision vectors.
+        for (i, j) in [(0, 1), (2, 3), (4, 4)] {
+            let expected = distances::simd::euclidean_f32(&data[i], &data[j]);
+            assert_approx_eq!(
+                f32,
+                halves::euclidean(&encoded[i], &encoded[j]),
+                expected,
+                epsilon = 0.1
+            );
+            let expected = distances::simd::cosine_f32(&data[i], &data[j]);
+            assert_approx_eq!(f32, halves::cosine(&encoded[i], &encoded[j]), expected, epsilon = 0.01);
+        }
+
+        let bytes = encoded[0].to_bytes();
+        assert_eq!(HalfVec::from_bytes(&bytes), Ok(encoded[0].clone()));
+
+        let query = encoded[0].clone();
+        let dataset = VecDataset::from_metric("halves".to_string(), encoded, halves::euclidean_metric());
+        let criteria = PartitionCriteria::default();
+        let tree = Tree::<_, _, _, UniBall<_>>::new(dataset, Some(42)).partition(&criteria, Some(42));
+
+        let linear = knn::Algorithm::Linear.search(&tree, &query, 10);
+        for variant in knn::Algorithm::variants() {
+            let hits = variant.search(&tree, &query, 10);
+            let mut distances = hits.iter().map(|&(_, d)| d).collect::<Vec<_>>();
+            let mut expected = linear.iter().map(|&(_, d)| d).collect::<Vec<_>>();
+            distances.sort_by(f32::total_cmp);
+            expected.sort_by(f32::total_cmp);
+            for (d, e) in distances.into_iter().zip(expected) {
+                assert_approx_eq!(f32, d, e, epsilon = 1e-5);
+            }
+        }
+    }
+
+    assert!(HalfVec::from_bytes(&[2, 0, 0]).is_err());
+    assert!(halves::euclidean_metric().is_metric());
+    assert!(!halves::cosine_metric().is_metric());
+}
+
+#[test]
+fn earth_movers() {
+    let mut rng = StdRng::seed_from_u64(42);
+
+    // In one dimension, the distance is the area between the cumulative
+    // distributions.
+    for _ in 0..20 {
+        let [x, y] = [0; 2].map(|_| Histogram::from_bins((0..10).map(|_| rng.gen_range(0_f32..1.)).collect()).unwrap());
+        let cdf = |h: &Histogram| {
+            (0..h.len())
+                .scan(0., |acc, i| {
+                    *acc += f64::from(h.masses()[i]);
+                    Some((h.point(i)[0], *acc))
+                })
+                .collect::<Vec<_>>()
+        };
+        let (cx, cy) = (cdf(&x), cdf(&y));
+        let area = (0..9_u8)
+            .map(|b| {
+                let at = |c: &[(f32, f64)]| {
+                    c.iter()
+                        .take_while(|(p, _)| *p <= f32::from(b))
+                        .last()
+                        .map_or(0., |&(_, m)| m)
+                };
+                (at(&cx) - at(&cy)).abs()
+            })
+            .sum::<f64>();
+        let d: f64 = histograms::emd(&x, &y);
+        assert_approx_eq!(f64, d, area, epsilon = 1e-6);
+        assert!(histograms::centroid_distance::<f64>(&x, &y) <= d + 1e-9);
+        assert_approx_eq!(f64, histograms::emd(&x, &x), 0.0, epsilon = 1e-9);
+    }
+
+    // Moving all of the mass by the same vector costs the length of the vector.
+    let points = (0..6)
+        .map(|_| vec![rng.gen_range(-1_f32..1.), rng.gen_range(-1_f32..1.)])
+        .collect::<Vec<_>>();
+    let masses = (0..6).map(|_| rng.gen_range(0_f32..1.)).collect::<Vec<_>>();
+    let shifted = points.iter().map(|p| vec![p[0] + 3., p[1] + 4.]).collect();
+    let x = Histogram::new(points, masses.clone()).unwrap();
+    let y = Histogram::new(shifted, masses).unwrap();
+    assert_approx_eq!(f64, histograms::emd(&x, &y), 5.0, epsilon = 1e-5);
+    assert_approx_eq!(f64, histograms::centroid_distance(&x, &y), 5.0, epsilon = 1e-5);
+
+    let bytes = x.to_bytes();
+    assert_eq!(Histogram::from_bytes(&bytes), Ok(x));
+    assert!(Histogram::from_bins(vec![0., 0.]).is_err());
+    assert!(Histogram::from_bins(vec![1., -1.]).is_err());
+    assert!(Histogram::new(vec![vec![0.], vec![0., 1.]], vec![1., 1.]).is_err());
+    assert!(Histogram::new(vec![vec![0.]], vec![1., 1.]).is_err());
+
+    let metric = histograms::emd_metric::<f64>();
+    assert!(metric.is_metric() && metric.is_expensive());
+    assert!(metric.lower_bound().is_some());
+
+    // The lower bound skips distance computations without changing the hits.
+    let data = (0..300)
+        .map(|_| {
+            let points = (0..8)
+                .map(|_| vec![rng.gen_range(0_f32..4.), rng.gen_range(0_f32..4.)])
+                .collect();
+            let masses = (0..8).map(|_| rng.gen_range(0_f32..1.)).collect();
+            Histogram::new(points, masses).unwrap()
+        })
+        .collect::<Vec<_>>();
+    let query = data[0].clone();
+    let criteria = PartitionCriteria::default();
+    let bounded = VecDataset::from_metric("bounded".to_string(), data.clone(), metric);
+    let bounded = Tree::<_, _, _, UniBall<_>>::new(bounded, Some(42)).partition(&criteria, Some(42));
+    let exact = FnMetric::new(histograms::emd).with_is_expensive(true);
+    let exact = VecDataset::from_metric("exact".to_string(), data, exact);
+    let exact = Tree::<_, _, _, UniBall<_>>::new(exact, Some(42)).partition(&criteria, Some(42));
+
+    for variant in [knn::Algorithm::GreedySieve, knn::Algorithm::DepthFirstSieve] {
+        let (hits, bounded_stats) = variant.search_with_stats(&bounded, &query, 10);
+        let (expected, exact_stats) = variant.search_with_stats(&exact, &query, 10);
+        let mut distances = hits.iter().map(|&(_, d)| d).collect::<Vec<_>>();
+        let mut expected = expected.iter().map(|&(_, d)| d).collect::<Vec<_>>();
+        distances.sort_by(f64::total_cmp);
+        expected.sort_by(f64::total_cmp);
+        assert_eq!(distances, expected, "{variant:?}");
+        assert!(bounded_stats.distance_computations <= exact_stats.distance_computations);
+    }
+
+    let radius = 0.5;
+    let mut hits = rnn::Algorithm::Clustered
+        .search(&query, radius, &bounded)
+        .into_iter()
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    let mut expected = rnn::Algorithm::Linear
+        .search(&query, radius, &exact)
+        .into_iter()
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    hits.sort_unstable();
+    expected.sort_unstable();
+    assert!(!hits.is_empty());
+    assert_eq!(hits, expected);
+}
+
+#[test]
+fn dynamic_time_warping() {
+    let x = TimeSeries::new(vec![0., 1., 2.], 1).unwrap();
+    let y = TimeSeries::new(vec![0., 0., 1., 2.], 1).unwrap();
+    assert_approx_eq!(f64, time_series::dtw(&x, &y), 0.0);
+    assert_approx_eq!(f64, time_series::lb_keogh(&x, &y), 0.0);
+
+    let mut rng = StdRng::seed_from_u64(42);
+    let walk = |rng: &mut StdRng, len: usize, band: usize| {
+        let values = (0..len)
+            .scan(0_f32, |v, _| {
+                *v += rng.gen_range(-1_f32..1.);
+                Some(*v)
+            })
+            .collect();
+        TimeSeries::new(values, band).unwrap()
+    };
+
+    for _ in 0..20 {
+        let (x, y) = (walk(&mut rng, 32, 4), walk(&mut rng, 32, 4));
+        let d: f64 = time_series::dtw(&x, &y);
+        assert!(time_series::lb_keogh::<f64>(&x, &y) <= d + 1e-9);
+        assert_approx_eq!(f64, d, time_series::dtw(&y, &x), epsilon = 1e-9);
+
+        // Without warping, this is the manhattan distance, and a wider band
+        // may only shorten the distance.
+        let unwarped = |t: &TimeSeries| TimeSeries::new(t.values().to_vec(), 0).unwrap();
+        let manhattan = x
+            .values()
+            .iter()
+            .zip(y.values())
+            .map(|(&a, &b)| f64::from((a - b).abs()))
+            .sum::<f64>();
+        assert_approx_eq!(
+            f64,
+            time_series::dtw(&unwarped(&x), &unwarped(&y)),
+            manhattan,
+            epsilon = 1e-4
+        );
+        let free = |t: &TimeSeries| TimeSeries::new(t.values().to_vec(), 32).unwrap();
+        assert!(time_series::dtw::<f64>(&free(&x), &free(&y)) <= d);
+
+        let z = walk(&mut rng, 40, 4);
+        assert!(time_series::lb_keogh::<f64>(&x, &z) <= time_series::dtw(&x, &z));
+    }
+
+    let x = walk(&mut rng, 10, 3);
+    assert_eq!(TimeSeries::from_bytes(&x.to_bytes()), Ok(x));
+    assert!(TimeSeries::new(vec![], 1).is_err());
+    assert!(TimeSeries::new(vec![0., f32::NAN], 1).is_err());
+
+    // Linear search skips most instances by the lower bound, without changing
+    // the hits.
+    let data = (0..500).map(|_| walk(&mut rng, 32, 4)).collect::<Vec<_>>();
+    let query = walk(&mut rng, 32, 4);
+    let criteria = PartitionCriteria::default();
+    let bounded = VecDataset::from_metric("bounded".to_string(), data.clone(), time_series::dtw_metric::<f64>());
+    let bounded = Tree::<_, _, _, UniBall<_>>::new(bounded, Some(42)).partition(&criteria, Some(42));
+    let exact = FnMetric::new(time_series::dtw)
+        .with_is_metric(false)
+        .with_is_expensive(true);
+    let exact = VecDataset::from_metric("exact".to_string(), data, exact);
+    let exact = Tree::<_, _, _, UniBall<_>>::new(exact, Some(42)).partition(&criteria, Some(42));
+
+    let (mut hits, bounded_stats) = knn::Algorithm::Linear.search_with_stats(&bounded, &query, 10);
+    let (mut expected, exact_stats) = knn::Algorithm::Linear.search_with_stats(&exact, &query, 10);
+    hits.sort_by_key(|&(i, _)| i);
+    expected.sort_by_key(|&(i, _)| i);
+    assert_eq!(hits, expected);
+    assert_eq!(exact_stats.distance_computations, 500);
+    assert!(bounded_stats.distance_computations < 500);
+}
+
+#[test]
+fn haversine() {
+    let paris = LatLon::new(48.8566, 2.3522).unwrap();
+    let london = LatLon::new(51.5074, -0.1278).unwrap();
+    let d: f64 = geo::haversine(&paris, &london);
+    assert!((d - 343_500.).abs() < 1_000., "Paris to London is {d} meters.");
+    assert_approx_eq!(f64, geo::haversine(&paris, &paris), 0.0);
+
+    // A quarter of the way around the equator, and across the antimeridian.
+    let (a, b) = (LatLon::new(0., 0.).unwrap(), LatLon::new(0., 90.).unwrap());
+    let quarter = core::f64::consts::FRAC_PI_2 * geo::EARTH_RADIUS;
+    assert_approx_eq!(f64, geo::haversine(&a, &b), quarter, epsilon = 1e-6);
+    let (a, b) = (LatLon::new(10., 179.5).unwrap(), LatLon::new(10., -179.5).unwrap());
+    assert!(geo::haversine::<f64>(&a, &b) < 120_000.);
+
+    assert!(LatLon::new(91., 0.).is_err());
+    assert!(LatLon::new(0., -181.).is_err());
+    assert!(LatLon::new(f64::NAN, 0.).is_err());
+    let bytes = paris.to_bytes();
+    let decoded = LatLon::from_bytes(&bytes).unwrap();
+    assert_approx_eq!(f64, decoded.lat(), 48.8566, epsilon = 1e-9);
+    assert_eq!(decoded, paris);
+    assert!(LatLon::from_bytes(&bytes[1..]).is_err());
+
+    // Radius and knn queries in meters, around a point in a grid of about a
+    // kilometer on each side.
+    let mut rng = StdRng::seed_from_u64(42);
+    let points = (0..2_000)
+        .map(|_| (rng.gen_range(40.0..40.01), rng.gen_range(-74.0..-73.99)))
+        .collect::<Vec<_>>();
+    let data = geo::dataset("grid".to_string(), &points).unwrap();
+    assert!(geo::dataset("bad".to_string(), &[(0., 200.)]).is_err());
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&PartitionCriteria::default(), Some(42));
+
+    let center = LatLon::new(40.005, -73.995).unwrap();
+    let mut hits = geo::within(&tree, &center, 200.);
+    hits.sort_by_key(|&(i, _)| i);
+    let mut expected = rnn::Algorithm::Linear.search(&center, 200., &tree);
+    expected.sort_by_key(|&(i, _)| i);
+    assert!(!hits.is_empty());
+    assert_eq!(hits, expected);
+    assert!(hits.iter().all(|&(_, d)| d <= 200.));
+
+    let nearest = geo::nearest(&tree, &center, 5);
+    assert_eq!(nearest.len(), 5);
+    let farthest = nearest.iter().map(|&(_, d)| d).fold(0., f64::max);
+    let linear = knn::Algorithm::Linear.search(&tree, &center, 5);
+    assert_approx_eq!(f64, farthest, linear.iter().map(|&(_, d)| d).fold(0., f64::max));
+}
+
+#[test]
+fn whitened_vectors() {
+    let weighted = Whitening::weighted(&[1., 4.]).unwrap();
+    let (x, y) = (weighted.apply(&[0., 0.]).unwrap(), weighted.apply(&[1., 1.]).unwrap());
+    assert_approx_eq!(f32, mahalanobis::euclidean(&x, &y), 5_f32.sqrt());
+
+    // The inverse of this covariance matrix is [[2, -1], [-1, 2]] / 3.
+    let whitening = Whitening::mahalanobis(&[vec![2., 1.], vec![1., 2.]]).unwrap();
+    assert_eq!(whitening.dim(), 2);
+    let (x, y, z) = (
+        whitening.apply(&[0., 0.]).unwrap(),
+        whitening.apply(&[1., 0.]).unwrap(),
+        whitening.apply(&[1., 1.]).unwrap(),
+    );
+    assert_approx_eq!(f32, mahalanobis::euclidean(&x, &y), (2_f32 / 3.).sqrt(), epsilon = 1e-6);
+    assert_approx_eq!(f32, mahalanobis::euclidean(&x, &z), (2_f32 / 3.).sqrt(), epsilon = 1e-6);
+    assert_approx_eq!(f32, mahalanobis::euclidean(&y, &z), (2_f32 / 3.).sqrt(), epsilon = 1e-6);
+    assert_eq!(Whitened::from_bytes(&z.to_bytes()), Ok(z));
+
+    // A diagonal covariance matrix is the same as the reciprocal weights.
+    let diagonal = Whitening::mahalanobis(&[vec![1., 0.], vec![0., 0.25]]).unwrap();
+    let (x, y) = (diagonal.apply(&[0., 0.]).unwrap(), diagonal.apply(&[1., 1.]).unwrap());
+    assert_approx_eq!(f32, mahalanobis::euclidean(&x, &y), 5_f32.sqrt(), epsilon = 1e-6);
+
+    assert!(whitening.apply(&[0., 0., 0.]).is_err());
+    assert!(Whitening::weighted(&[1., -1.]).is_err());
+    assert!(Whitening::mahalanobis(&[vec![1., 2.], vec![2., 1.]]).is_err());
+    assert!(Whitening::mahalanobis(&[vec![1., 0.5], vec![0., 1.]]).is_err());
+    assert!(Whitening::mahalanobis(&[vec![1., 0.], vec![0.]]).is_err());
+
+    let mut rng = StdRng::seed_from_u64(42);
+    let data = (0..1_000)
+        .map(|_| (0..3).map(|_| rng.gen_range(-1_f32..1.)).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let covariance = vec![vec![1., 0.3, 0.], vec![0.3, 2., 0.5], vec![0., 0.5, 0.5]];
+    let whitening = Whitening::mahalanobis(&covariance).unwrap();
+    let dataset = WhitenedDataset::new("sensors".to_string(), &data, whitening.clone()).unwrap();
+    assert!(WhitenedDataset::new("bad".to_string(), &[vec![0., 0.]], whitening).is_err());
+
+    let query = dataset.whiten(&[0.1, 0.2, 0.3]).unwrap();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(dataset, Some(42)).partition(&PartitionCriteria::default(), Some(42));
+    let linear = knn::Algorithm::Linear.search(&tree, &query, 10);
+    for &variant in knn::Algorithm::variants() {
+        let hits = variant.search(&tree, &query, 10);
+        assert_approx_eq!(f32, utils::compute_recall(hits, linear.clone()), 1.0);
+    }
+
+    // The whitening is saved with the instances, and must match them.
+    let tmp_dir = TempDir::new("whitened").unwrap();
+    let path = tmp_dir.path().join("sensors.save");
+    tree.data().save(&path).unwrap();
+    let loaded = WhitenedDataset::<usize>::load(&path, mahalanobis::euclidean, false).unwrap();
+    assert_eq!(loaded.whitening(), tree.data().whitening());
+    assert_eq!(loaded.whiten(&[0.1, 0.2, 0.3]), Ok(query));
+
+    let other = tmp_dir.path().join("other.save");
+    let weighted = WhitenedDataset::new("weighted".to_string(), &[vec![0., 0.]], weighted).unwrap();
+    weighted.save(&other).unwrap();
+    std::fs::copy(
+        other.with_extension("save.whitening"),
+        path.with_extension("save.whitening"),
+    )
+    .unwrap();
+    assert!(WhitenedDataset::<usize>::load(&path, mahalanobis::euclidean, false).is_err());
+}
+
+#[test]
+fn widened_vectors() {
+    // Neither the differences nor their squares fit in the elements.
+    let (x, y) = (vec![i8::MAX; 1_000], vec![i8::MIN; 1_000]);
+    assert_approx_eq!(f64, vectors::euclidean(&x, &y), 255. * 1_000_f64.sqrt(), epsilon = 1e-9);
+
+    let mut rng = StdRng::seed_from_u64(42);
+    let data = (0..1_000)
+        .map(|_| (0..32).map(|_| rng.gen::<u8>()).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let query = data[0].clone();
+    let reference = |x: &Vec<u8>| {
+        x.iter()
+            .zip(query.iter())
+            .map(|(&a, &b)| (f64::from(a) - f64::from(b)).powi(2))
+            .sum::<f64>()
+            .sqrt()
+    };
+
+    let dataset = VecDataset::from_metric(
+        "bytes".to_string(),
+        data.clone(),
+        vectors::euclidean_metric::<u8, f32>(),
+    );
+    let tree = Tree::<_, _, _, UniBall<_>>::new(dataset, Some(42)).partition(&PartitionCriteria::default(), Some(42));
+    let mut expected = data.iter().map(reference).collect::<Vec<_>>();
+    expected.sort_by(f64::total_cmp);
+
+    for &variant in knn::Algorithm::variants() {
+        let mut hits = variant
+            .search(&tree, &query, 10)
+            .into_iter()
+            .map(|(_, d)| d)
+            .collect::<Vec<_>>();
+        hits.sort_by(f32::total_cmp);
+        for (&d, &e) in hits.iter().zip(expected.iter()) {
+            assert_approx_eq!(f64, f64::from(d), e, epsilon = 1e-3);
+        }
+    }
+}
+
+#[test]
+fn sparse_vectors() {
+    let x = SparseVec::new(6, vec![(4, 2_f32), (1, 1.), (3, 0.)]).unwrap();
+    assert_eq!(x.indices(), &[1, 4]);
+    assert_eq!(x.to_dense(), vec![0., 1., 0., 0., 2., 0.]);
+    assert!(SparseVec::new(6, vec![(6, 1_f32)]).is_err());
+    assert!(SparseVec::new(6, vec![(2, 1_f32), (2, 3.)]).is_err());
+
+    let mut rng = StdRng::seed_from_u64(42);
+    for _ in 0..100 {
+        let [a, b] = [0; 2].map(|_| {
+            (0..20)
+                .map(|_| {
+                    if rng.gen_bool(0.3) {
+                        rng.gen_range(-1_f32..1.)
+                    } else {
+                        0.
+                    }
+                })
+                .collect::<Vec<_>>()
+        });
+        let [sa, sb] = [&a, &b].map(|v| SparseVec::from_dense(v).unwrap());
+        assert_eq!(sa.to_dense(), a);
+
+        let [aa, bb, ab, dd] = a.iter().zip(b.iter()).fold([0_f32; 4], |[aa, bb, ab, dd], (&x, &y)| {
+            [
+                x.mul_add(x, aa),
+                y.mul_add(y, bb),
+                x.mul_add(y, ab),
+                (x - y).mul_add(x - y, dd),
+            ]
+        });
+        assert_approx_eq!(f32, sparse::dot(&sa, &sb), ab, epsilon = 1e-5);
+        assert_approx_eq!(f32, sparse::euclidean(&sa, &sb), dd.sqrt(), epsilon = 1e-5);
+        if aa > 0. && bb > 0. {
+            let expected = 1. - ab / (aa * bb).sqrt();
+            assert_approx_eq!(f32, sparse::cosine(&sa, &sb), expected, epsilon = 1e-5);
+        }
+
+        let bytes = sa.to_bytes();
+        assert_eq!(SparseVec::<f32>::from_bytes(&bytes), Ok(sa));
+    }
+
+    assert!(sparse::euclidean_metric::<f32, f32>().is_metric());
+    assert!(!sparse::cosine_metric::<f32, f32>().is_metric());
+
+    // Rows: [1, 0, 2], [], [0, 3, 0]
+    let data = SparseVecDataset::from_csr(
+        "csr".to_string(),
+        3,
+        &[0, 2, 2, 3],
+        &[0, 2, 1],
+        &[1_f32, 2., 3.],
+        sparse::euclidean_metric::<f32, f32>(),
+    )
+    .unwrap();
+    assert_eq!(data.cardinality(), 3);
+    assert_eq!(data.nnz(), 3);
+    assert_eq!(data.row(0).to_dense(), vec![1., 0., 2.]);
+    assert!(data.row(1).indices().is_empty());
+    assert_eq!(data.row(2).to_dense(), vec![0., 3., 0.]);
+    assert_eq!(data.get(2).into_owned(), data.row(2).to_vec());
+    assert_eq!(data.to_csr(), (vec![0, 2, 2, 3], vec![0, 2, 1], vec![1., 2., 3.]));
+
+    let metric = sparse::euclidean_metric::<f32, f32>;
+    assert!(SparseVecDataset::from_csr("bad".to_string(), 3, &[0, 2], &[0], &[1_f32], metric()).is_err());
+    assert!(SparseVecDataset::from_csr("bad".to_string(), 3, &[0, 2, 1], &[0], &[1_f32], metric()).is_err());
+    assert!(SparseVecDataset::from_csr("bad".to_string(), 3, &[0, 1], &[3], &[1_f32], metric()).is_err());
+}
+
+#[test]
+fn sparse_search() {
+    let mut rng = StdRng::seed_from_u64(42);
+    let data = (0..1000)
+        .map(|_| {
+            let mut elements = Vec::new();
+            for i in 0..1000_u32 {
+                if rng.gen_bool(0.01) {
+                    elements.push((i, rng.gen_range(0_f32..1.)));
+                }
+            }
+            SparseVec::new(1000, elements).unwrap()
+        })
+        .collect::<Vec<_>>();
+    let query = data[0].clone();
+
+    // The rows are compared with the metric on copies of them.
+    let dense = VecDataset::from_metric(
+        "dense".to_string(),
+        data.clone(),
+        FnMetric::new(sparse::euclidean::<f32, f32>),
+    );
+    let data = SparseVecDataset::from_rows("sparse".to_string(), &data, sparse::euclidean_metric::<f32, f32>()).unwrap();
+    for i in [0, 1, 500, 999] {
+        assert_eq!(data.one_to_many(i, &[0, 2, 400]), dense.one_to_many(i, &[0, 2, 400]));
+    }
+
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+    for i in 0..tree.cardinality() {
+        let original = tree.data().original_index(i);
+        assert_eq!(tree.data().row(i).to_vec(), dense.data()[original]);
+        assert_eq!(tree.data().metadata()[i], original);
+    }
+
+    // Permuting and sharding repack the buffers in the order of the instances.
+    let tmp = TempDir::new("sparse").unwrap();
+    let path = tmp.path().join("data.bin");
+    tree.data().save(&path).unwrap();
+    let loaded = SparseVecDataset::<f32, f32>::load(&path, sparse::euclidean::<f32, f32>, false).unwrap();
+    assert_eq!(loaded.permuted_indices(), tree.data().permuted_indices());
+    let shards = loaded.clone().make_shards(300);
+    assert_eq!(
+        shards.iter().map(Dataset::cardinality).collect::<Vec<_>>(),
+        vec![300, 300, 300, 100]
+    );
+    for i in 0..loaded.cardinality() {
+        assert_eq!(loaded.row(i), tree.data().row(i));
+        // Shards are split off from the end.
+        let shard = (999 - i) / 300;
+        let start = 1000_usize.saturating_sub(300 * (shard + 1));
+        assert_eq!(shards[shard].row(i - start), loaded.row(i));
+    }
+
+    let linear = knn::Algorithm::Linear.search(&tree, &query, 10);
+    for variant in knn::Algorithm::variants() {
+        let hits = variant.search(&tree, &query, 10);
+        let mut distances = hits.iter().map(|&(_, d)| d).collect::<Vec<_>>();
+        let mut expected = linear.iter().map(|&(_, d)| d).collect::<Vec<_>>();
+        distances.sort_by(f32::total_cmp);
+        expected.sort_by(f32::total_cmp);
+        for (d, e) in distances.into_iter().zip(expected) {
+            assert_approx_eq!(f32, d, e, epsilon = 1e-5);
+        }
+    }
+}
+
+#[test]
+fn search() {
+    let mut rng = StdRng::seed_from_u64(42);
+    let data = (0..1000)
+        .map(|_| (0..256).map(|_| rng.gen_bool(0.5)).collect::<Vec<_>>())
+        .map(|b| BitVector::from_bools(&b))
+        .collect::<Vec<_>>();
+    let query = data[0].clone();
+
+    let data = VecDataset::from_metric("bits".to_string(), data, bits::hamming_metric::<u32>());
+    assert!(data.is_metric());
+
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+
+    let linear = knn::Algorithm::Linear.search(&tree, &query, 10);
+    for variant in knn::Algorithm::variants() {
+        let hits = variant.search(&tree, &query, 10);
+        let mut distances = hits.iter().map(|&(_, d)| d).collect::<Vec<_>>();
+        let mut expected = linear.iter().map(|&(_, d)| d).collect::<Vec<_>>();
+        distances.sort_unstable();
+        expected.sort_unstable();
+        assert_eq!(distances, expected, "{variant:?}");
+    }
+}
+
+#[test]
+fn simd_kernels() {
+    let data = utils::gen_dataset(1000, 10, 42, utils::euclidean).data().to_vec();
+    let query = data[0].clone();
+
+    let scalar: fn(&Vec<f32>, &Vec<f32>) -> f32 = utils::euclidean;
+    let tagged = SharedMetric::from(FnMetric::new(scalar).with_kernel(SimdKernel::Euclidean));
+    assert_eq!(tagged.kernel(), Some(SimdKernel::Euclidean));
+    assert_eq!(
+        vectors::euclidean_metric::<f32, f32>().kernel(),
+        Some(SimdKernel::Euclidean)
+    );
+    for (a, b) in data.iter().zip(data.iter().skip(1)).take(100) {
+        assert_approx_eq!(f32, tagged.distance(a, b), scalar(a, b), epsilon = 1e-5);
+        let expected = distances::simd::cosine_f32(a, b);
+        assert_approx_eq!(f32, SimdKernel::Cosine.distance(a, b).unwrap_or_default(), expected);
+    }
+
+    // The kernels only apply to vectors of floats of the same length.
+    let ints = (vec![1_u8, 2, 3], vec![4_u8, 6, 3]);
+    assert_eq!(SimdKernel::Euclidean.distance::<_, f32>(&ints.0, &ints.1), None);
+    assert_eq!(
+        SimdKernel::Euclidean.distance::<_, f32>(&vec![1_f32], &vec![1_f32, 2.]),
+        None
+    );
+    let widened = SharedMetric::from(vectors::euclidean_metric::<u8, f32>());
+    assert_approx_eq!(f32, widened.distance(&ints.0, &ints.1), 5.0);
+    let doubles = ([0_f64, 3.], [4_f64, 0.]);
+    assert_approx_eq!(
+        f64,
+        SimdKernel::Euclidean
+            .distance(&doubles.0, &doubles.1)
+            .unwrap_or_default(),
+        5.0
+    );
+
+    let criteria = PartitionCriteria::default();
+    let scalar = VecDataset::new("scalar".to_string(), data.clone(), scalar, false);
+    let scalar = Tree::<_, _, _, UniBall<_>>::new(scalar, Some(42)).partition(&criteria, Some(42));
+    let tagged = VecDataset::from_metric("tagged".to_string(), data, tagged);
+    let tagged = Tree::<_, _, _, UniBall<_>>::new(tagged, Some(42)).partition(&criteria, Some(42));
+
+    let expected = knn::Algorithm::Linear.search(&scalar, &query, 10);
+    for variant in knn::Algorithm::variants() {
+        let hits = variant.search(&tagged, &query, 10);
+        let recall = utils::compute_recall(hits, expected.clone());
+        assert_approx_eq!(f32, recall, 1.0);
+    }
+}
+
+#[test]
+fn packed_hashes() {
+    let mut rng = StdRng::seed_from_u64(42);
+    let bytes = (0..1000 * 32).map(|_| rng.gen::<u8>()).collect::<Vec<_>>();
+    let data = bits::pack_hashes::<4>(&bytes).unwrap();
+    assert_eq!(data.len(), 1000);
+    assert!(bits::pack_hashes::<4>(&bytes[..40]).is_err());
+
+    // The words are the same as those of the `BitVector`s of the same hashes.
+    let bit_vectors = bytes
+        .chunks_exact(32)
+        .map(|hash| {
+            let bools = (0..256).map(|i| (hash[i / 8] >> (i % 8)) & 1 == 1).collect::<Vec<_>>();
+            BitVector::from_bools(&bools)
+        })
+        .collect::<Vec<_>>();
+    for (i, j) in [(0, 1), (2, 3), (4, 4)] {
+        assert_eq!(bit_vectors[i].words(), &data[i]);
+        assert_eq!(
+            bits::hamming_words::<u32, 4>(&data[i], &data[j]),
+            bits::hamming::<u32>(&bit_vectors[i], &bit_vectors[j])
+        );
+    }
+
+    let query = data[0];
+    let data = VecDataset::from_metric("hashes".to_string(), data, bits::hamming_words_metric::<u32, 4>());
+    assert!(data.is_metric());
+
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+
+    let linear = knn::Algorithm::Linear.search(&tree, &query, 10);
+    for variant in knn::Algorithm::variants() {
+        let hits = variant.search(&tree, &query, 10);
+        let mut distances = hits.iter().map(|&(_, d)| d).collect::<Vec<_>>();
+        let mut expected = linear.iter().map(|&(_, d)| d).collect::<Vec<_>>();
+        distances.sort_unstable();
+        expected.sort_unstable();
+        assert_eq!(distances, expected, "{variant:?}");
+    }
+}
+
+#[test]
+fn sequence_distances() {
+    use distances::strings::{needleman_wunsch::nw_distance_custom, Penalties};
+
+    assert_eq!(
+        sequences::levenshtein::<_, u32>("NAJIBEATSPEPPERS", "NAJIBPEPPERSEATS"),
+        8
+    );
+    assert_eq!(sequences::levenshtein::<_, u32>("", "ACTG"), 4);
+    assert_eq!(sequences::hamming::<_, u32>("ACTG", "ACGGTT"), 3);
+
+    // Long, dissimilar strings of different lengths need the band to be
+    // doubled several times.
+    let mut rng = StdRng::seed_from_u64(42);
+    let nw_2_1 = nw_distance_custom(Penalties::new(0_u32, 2, 1));
+    for _ in 0..100 {
+        let [x, y] = [rng.gen_range(0..200), rng.gen_range(0..200)].map(|len| {
+            (0..len)
+                .map(|_| *b"ACTG".choose(&mut rng).unwrap() as char)
+                .collect::<String>()
+        });
+
+        let expected: u32 = distances::strings::levenshtein(&x, &y);
+        assert_eq!(sequences::levenshtein::<_, u32>(&x, &y), expected);
+        assert_eq!(sequences::needleman_wunsch::<_, u32, 1, 1>(&x, &y), expected);
+        assert_eq!(sequences::needleman_wunsch::<_, u32, 2, 1>(&x, &y), nw_2_1(&x, &y));
+    }
+
+    assert!(sequences::levenshtein_metric::<Vec<u8>, u32>().is_metric());
+    assert!(sequences::needleman_wunsch_metric::<Vec<u8>, u32, 2, 1>().is_metric());
+    assert!(!sequences::needleman_wunsch_metric::<Vec<u8>, u32, 3, 1>().is_metric());
+}
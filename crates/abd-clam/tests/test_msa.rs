@@ -0,0 +1,62 @@
+//! Tests for the msa module.
+
+use abd_clam::{
+    metrics::sequences::levenshtein_metric,
+    msa::{self, GAP},
+    Dataset, PartitionCriteria, SequenceDataset, Tree, UniBall,
+};
+
+fn tree(sequences: &[String]) -> Tree<Vec<u8>, u16, SequenceDataset<u16>, UniBall<u16>> {
+    let data = SequenceDataset::from_metric("sequences".to_string(), sequences, levenshtein_metric());
+    let criteria = PartitionCriteria::default();
+    Tree::new(data, Some(42)).partition(&criteria, Some(42))
+}
+
+fn ungapped(row: &[u8]) -> Vec<u8> {
+    row.iter().copied().filter(|&b| b != GAP).collect()
+}
+
+#[test]
+fn align() {
+    let sequences = symagen::random_data::random_string(200, 30, 50, "ACTG", 42);
+    let mut tree = tree(&sequences);
+
+    let alignment = msa::align(&tree);
+    assert_eq!(alignment.len(), sequences.len());
+    assert_eq!(alignment.indices(), (0..sequences.len()).collect::<Vec<_>>());
+    assert!(alignment.width() >= sequences.iter().map(String::len).max().unwrap_or_default());
+    for (i, row) in alignment.indices().iter().zip(alignment.rows()) {
+        assert_eq!(row.len(), alignment.width());
+        assert_eq!(ungapped(row), sequences[*i].as_bytes());
+        assert_eq!(alignment.row(*i), Some(row.as_slice()));
+    }
+    for c in 0..alignment.width() {
+        assert!(alignment.rows().iter().any(|row| row[c] != GAP));
+    }
+
+    // Removed sequences are left out of the alignment.
+    let removed = (0..sequences.len()).step_by(3).collect::<Vec<_>>();
+    for &i in &removed {
+        tree.remove(i).unwrap();
+    }
+    let alignment = msa::align(&tree);
+    assert_eq!(alignment.len(), sequences.len() - removed.len());
+    for &i in &removed {
+        let original = tree.data().original_index(i);
+        assert!(alignment.row(original).is_none());
+    }
+    for (i, row) in alignment.indices().iter().zip(alignment.rows()) {
+        assert_eq!(ungapped(row), sequences[*i].as_bytes());
+    }
+    for c in 0..alignment.width() {
+        assert!(alignment.rows().iter().any(|row| row[c] != GAP));
+    }
+}
+
+#[test]
+fn identical() {
+    let sequences = vec!["ACGTTGCA".to_string(); 50];
+    let alignment = msa::align(&tree(&sequences));
+    assert_eq!(alignment.width(), sequences[0].len());
+    assert!(alignment.rows().iter().all(|row| row == sequences[0].as_bytes()));
+}
@@ -0,0 +1,125 @@
+//! Tests for product quantization.
+
+use abd_clam::{
+    pq::{PqIndex, ProductQuantizer},
+    Dataset, PartitionCriteria, Tree, UniBall, VecDataset,
+};
+use float_cmp::assert_approx_eq;
+use rand::prelude::*;
+
+fn random_data(cardinality: usize, dim: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..cardinality)
+        .map(|_| (0..dim).map(|_| rng.gen_range(-1_f32..1.)).collect())
+        .collect()
+}
+
+#[allow(clippy::ptr_arg)]
+fn euclidean(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    distances::simd::euclidean_f32(x, y)
+}
+
+#[test]
+fn quantizer() -> Result<(), String> {
+    let data = random_data(2000, 10, 42);
+    let refs = data.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+    // 10 dimensions are split into subspaces of 3, 3 and 4 dimensions.
+    let pq = ProductQuantizer::train(&refs, 3, 64, Some(42))?;
+    assert_eq!(pq.dim(), 10);
+    assert_eq!(pq.num_subspaces(), 3);
+
+    let query = &data[0];
+    let table = pq.distance_table(query);
+    let mut error = 0.;
+    for x in &data {
+        let code = pq.encode(x);
+        assert_eq!(code.len(), 3);
+
+        // The asymmetric distance is the distance to the reconstruction.
+        let y = pq.decode(&code);
+        assert_approx_eq!(f32, table.distance(&code), euclidean(query, &y), epsilon = 1e-4);
+
+        error += euclidean(x, &y);
+    }
+    // Reconstructions are much closer than a typical pair of instances.
+    #[allow(clippy::cast_precision_loss)]
+    let mean_error = error / data.len() as f32;
+    assert!(mean_error < 0.5 * euclidean(&data[0], &data[1]), "{mean_error}");
+
+    // With at least as many centroids as instances, codes are lossless.
+    let pq = ProductQuantizer::train(&refs[..100], 5, 256, Some(42))?;
+    for x in &data[..100] {
+        assert_eq!(&pq.decode(&pq.encode(x)), x);
+    }
+
+    assert!(ProductQuantizer::train(&[], 2, 16, None).is_err());
+    assert!(ProductQuantizer::train(&refs, 11, 16, None).is_err());
+    assert!(ProductQuantizer::train(&refs, 2, 257, None).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn lossless_search() -> Result<(), String> {
+    // With fewer instances in each subtree than centroids, the asymmetric
+    // distances are exact and so is the search.
+    let data = random_data(1000, 8, 7);
+    let queries = random_data(10, 8, 0);
+
+    let dataset = VecDataset::new("pq".to_string(), data, euclidean, false);
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(dataset, Some(42)).partition(&criteria, Some(42));
+
+    let index = PqIndex::new(&tree, 4, 256, 3, Some(42))?;
+    assert_eq!(index.quantizers().len(), 8);
+    assert_eq!(index.code_bytes(), 4 * 1000);
+
+    for query in &queries {
+        let hits = index.knn_search(query, 10);
+        let expected = tree.data().linear_knn(query, 10);
+        for ((i, d), (j, e)) in hits.into_iter().zip(expected) {
+            assert_eq!(i, j);
+            assert_approx_eq!(f32, d, e, epsilon = 1e-4);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn approximate_search() -> Result<(), String> {
+    let data = random_data(5000, 16, 42);
+    let queries = random_data(20, 16, 0);
+    let k = 10;
+
+    let dataset = VecDataset::new("pq".to_string(), data, euclidean, false);
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(dataset, Some(42)).partition(&criteria, Some(42));
+
+    // Per-subtree codebooks fit their instances more tightly than a single
+    // codebook for the whole tree, so they recall more of the true neighbors.
+    let recalls = [0, 4]
+        .into_iter()
+        .map(|depth| {
+            let index = PqIndex::new(&tree, 8, 64, depth, Some(42))?;
+            let recalled = queries
+                .iter()
+                .map(|query| {
+                    let expected = tree.data().linear_knn(query, k);
+                    let hits = index.knn_search(query, k);
+                    assert_eq!(hits.len(), k);
+                    hits.iter()
+                        .filter(|(i, _)| expected.iter().any(|(j, _)| i == j))
+                        .count()
+                })
+                .sum::<usize>();
+            Ok(recalled)
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    assert!(recalls[0] >= queries.len() * k / 4, "{recalls:?}");
+    assert!(recalls[1] >= recalls[0], "{recalls:?}");
+
+    Ok(())
+}
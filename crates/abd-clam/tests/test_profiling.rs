@@ -0,0 +1,77 @@
+//! Tests for tracing search with a `Probe`.
+
+#![cfg(feature = "profiling")]
+
+use std::sync::Mutex;
+
+use abd_clam::{
+    cakes::{
+        knn,
+        probe::{Counter, Probe},
+        rnn,
+    },
+    Cluster, PartitionCriteria, Tree, UniBall,
+};
+use distances::Number;
+
+mod utils;
+
+/// Records the depths of the `Cluster`s visited and the cardinalities of the
+/// leaves scanned.
+#[derive(Default)]
+struct Tracer {
+    visited: Mutex<Vec<usize>>,
+    scanned: Mutex<Vec<usize>>,
+}
+
+impl Probe for Tracer {
+    fn on_cluster_visited<U: Number, C: Cluster<U>>(&self, c: &C) {
+        self.visited.lock().unwrap().push(c.depth());
+    }
+
+    fn on_leaf_scanned<U: Number, C: Cluster<U>>(&self, c: &C) {
+        self.scanned.lock().unwrap().push(c.cardinality());
+    }
+}
+
+#[test]
+fn probe() {
+    let seed = 42;
+    let data = utils::gen_dataset(10_000, 3, seed, utils::euclidean);
+    let query = &vec![0.; 3];
+
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(seed)).partition(&criteria, Some(seed));
+
+    for &algorithm in knn::Algorithm::variants() {
+        let tracer = Tracer::default();
+        let hits = algorithm.search_with_probe(&tree, query, 10, &tracer);
+        assert_eq!(hits, algorithm.search(&tree, query, 10));
+
+        let name = algorithm.name();
+        let visited = tracer.visited.into_inner().unwrap();
+        let scanned = tracer.scanned.into_inner().unwrap();
+        if matches!(algorithm, knn::Algorithm::Linear) {
+            assert!(visited.is_empty() && scanned.is_empty());
+        } else {
+            assert!(visited.contains(&0), "{name} did not visit the root.");
+            assert!(!scanned.is_empty(), "{name} scanned no leaves.");
+            assert!(scanned.iter().sum::<usize>() < tree.cardinality());
+        }
+    }
+
+    // A single `Counter` accumulates the counts of many searches.
+    let counter = Counter::default();
+    let radius = tree.radius() / 10.0;
+    let mut expected = 0;
+    for algorithm in [rnn::Algorithm::Linear, rnn::Algorithm::Clustered] {
+        let hits = algorithm.search_with_probe(query, radius, &tree, &counter);
+        assert_eq!(hits.len(), algorithm.search(query, radius, &tree).len());
+        expected += hits.len();
+    }
+    let stats = counter.reset();
+    assert!(stats.distance_computations >= expected);
+    assert!(stats.distance_computations > tree.cardinality());
+    assert!(stats.clusters_visited >= 1);
+    assert_eq!(counter.stats(), Default::default());
+}
@@ -0,0 +1,122 @@
+//! Tests for quantized datasets.
+
+use abd_clam::{
+    cakes::knn, euclidean_i8, Cakes, Dataset, Instance, PartitionCriteria, QuantizedDataset, ScalarQuantizer,
+};
+use distances::Number;
+use float_cmp::assert_approx_eq;
+use rand::prelude::*;
+use tempdir::TempDir;
+
+fn random_data(cardinality: usize, dim: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..cardinality)
+        .map(|_| {
+            (0..dim)
+                .map(|j| rng.gen_range(-1_f32..1.) * (1. + (j % 2).as_f32()))
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn scalar_quantizer() {
+    let data = random_data(1000, 8, 42);
+    let quantizer = ScalarQuantizer::fit(&data).unwrap();
+    assert_eq!(quantizer.dim(), 8);
+
+    // Each dimension is reconstructed to within half of its quantization step.
+    for x in &data {
+        let codes = quantizer.quantize(x);
+        let y = quantizer.dequantize(&codes);
+        for (j, (a, b)) in x.iter().zip(y.iter()).enumerate() {
+            let step = 2. * (1. + (j % 2).as_f32()) / 254.;
+            assert!((a - b).abs() <= step / 2. + 1e-5, "{a} vs {b} at {j}");
+        }
+    }
+
+    // Out-of-range values are clamped.
+    assert!(quantizer.quantize(&[100.; 8]).iter().all(|&c| c == 127));
+
+    let bytes = quantizer.to_bytes();
+    assert_eq!(ScalarQuantizer::from_bytes(&bytes), Ok(quantizer));
+
+    assert!(ScalarQuantizer::fit(&[]).is_err());
+    assert!(ScalarQuantizer::fit(&[vec![1.], vec![1., 2.]]).is_err());
+
+    assert_approx_eq!(f32, euclidean_i8(&vec![0, 3], &vec![4, 0]), 5.);
+
+    // The sum of squares would overflow an `i32`.
+    let (x, y) = (vec![i8::MAX; 100_000], vec![i8::MIN; 100_000]);
+    assert_approx_eq!(f32, euclidean_i8(&x, &y), 255. * 100_000_f32.sqrt(), epsilon = 1e-2);
+}
+
+#[test]
+fn rescored_search() -> Result<(), String> {
+    let data = random_data(2000, 16, 42);
+    let queries = random_data(10, 16, 0);
+    let k = 10;
+
+    let dataset = QuantizedDataset::new("sq8".to_string(), data.clone(), true)?;
+    assert_eq!(dataset.cardinality(), data.len());
+    let cakes = Cakes::new(dataset, Some(42), &PartitionCriteria::default());
+
+    for query in &queries {
+        let mut expected = data
+            .iter()
+            .map(|x| distances::simd::euclidean_f32(query, x))
+            .collect::<Vec<_>>();
+        expected.sort_by(f32::total_cmp);
+        expected.truncate(k);
+
+        let hits = cakes.knn_search_rescored(query, k, 10 * k, knn::Algorithm::GreedySieve)?;
+        assert_eq!(hits.len(), k);
+
+        // Rescored distances are exact, and the full-precision instances were
+        // reordered along with the codes.
+        for &(i, d) in &hits {
+            let original = &data[cakes.original_index(i)];
+            assert_approx_eq!(f32, distances::simd::euclidean_f32(query, original), d);
+        }
+
+        // The rescored hits should recover most of the true neighbors.
+        let threshold = expected[k - 1];
+        let recalled = hits.iter().filter(|&&(_, d)| d <= threshold).count();
+        assert!(recalled >= k - 2, "recalled {recalled} of {k}");
+    }
+
+    let dataset = QuantizedDataset::new("sq8".to_string(), data, false)?;
+    let cakes = Cakes::new(dataset, Some(42), &PartitionCriteria::default());
+    assert!(cakes
+        .knn_search_rescored(&queries[0], k, k, knn::Algorithm::Linear)
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn sharded_and_saved() -> Result<(), String> {
+    let data = random_data(1000, 8, 7);
+    let query = &data[17];
+
+    let dataset = QuantizedDataset::new("sq8".to_string(), data.clone(), true)?;
+    let shards = dataset.make_shards(300);
+    assert_eq!(shards.len(), 4);
+    let cakes = Cakes::new_randomly_sharded(shards, Some(42), &PartitionCriteria::default());
+
+    let hits = cakes.knn_search_rescored(query, 1, 10, knn::Algorithm::Linear)?;
+    assert_eq!(hits.len(), 1);
+    assert_approx_eq!(f32, hits[0].1, 0.);
+    assert_eq!(cakes[hits[0].0], cakes.shards()[0].quantize(query));
+
+    let tmp_dir = TempDir::new("sq8").map_err(|e| e.to_string())?;
+    let path = tmp_dir.path().join("dataset");
+    let dataset = QuantizedDataset::new("sq8".to_string(), data, true)?;
+    dataset.save(&path)?;
+    let loaded = QuantizedDataset::<usize>::load(&path, euclidean_i8, false)?;
+    assert_eq!(loaded.quantizer(), dataset.quantizer());
+    assert_eq!(loaded.codes().data(), dataset.codes().data());
+    assert_eq!(loaded.full(), dataset.full());
+
+    Ok(())
+}
@@ -1,8 +1,9 @@
 //! Tests for the Search algorithms.
 
-use abd_clam::{cakes::knn, cakes::rnn, PartitionCriteria, Tree, UniBall};
+use abd_clam::{cakes::knn, cakes::rnn, Cluster, Dataset, FnMetric, PartitionCriteria, Tree, UniBall, VecDataset};
 use distances::Number;
 use float_cmp::assert_approx_eq;
+use rand::prelude::*;
 use test_case::test_case;
 
 mod utils;
@@ -97,3 +98,221 @@ fn variants(cardinality: usize, dimensionality: usize) {
         }
     }
 }
+
+#[test_case(10_000, 10; "10k_10")]
+#[test_case(10_000, 100; "10k_100")]
+fn approximate(cardinality: usize, dimensionality: usize) {
+    let seed = 42;
+
+    let data = utils::gen_dataset(cardinality, dimensionality, seed, utils::euclidean);
+    let query = &vec![0.; dimensionality];
+
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(seed)).partition(&criteria, Some(seed));
+
+    for k in [1, 10, 100] {
+        let linear_nn = knn::Algorithm::Linear.search(&tree, query, k);
+
+        let exact_nn = knn::Algorithm::Approximate { recall: 1.0 }.search(&tree, query, k);
+        assert_eq!(exact_nn.len(), k);
+        let recall = utils::compute_recall(exact_nn, linear_nn.clone());
+        assert_approx_eq!(f32, recall, 1.0);
+
+        for target in [0.5, 0.9] {
+            let approx_nn = knn::Algorithm::Approximate { recall: target }.search(&tree, query, k);
+            assert_eq!(approx_nn.len(), k, "Approximate search with recall {target} returned too few hits.");
+            let recall = utils::compute_recall(approx_nn, linear_nn.clone());
+            assert!(
+                recall >= target,
+                "Approximate search with target {target} had recall {recall}."
+            );
+        }
+    }
+}
+
+#[test_case(1000, 10; "1k_10")]
+#[test_case(10_000, 10; "10k_10")]
+fn rnn_count(cardinality: usize, dimensionality: usize) {
+    let seed = 42;
+
+    let data = utils::gen_dataset(cardinality, dimensionality, seed, utils::euclidean);
+    let query = &vec![0.; dimensionality];
+
+    let criteria = PartitionCriteria::default();
+    let mut tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(seed)).partition(&criteria, Some(seed));
+
+    for step in 0..2 {
+        if step == 1 {
+            // Remove some instances and check that the counts skip them.
+            for index in (0..cardinality).step_by(7) {
+                tree.remove(index).unwrap();
+            }
+        }
+
+        for radius in [0.5, 1.0, 1.5, 2.0] {
+            let expected = rnn::Algorithm::Linear.search(query, radius, &tree).len();
+
+            for variant in rnn::Algorithm::variants() {
+                let count = variant.count(query, radius, &tree);
+                assert_eq!(
+                    count,
+                    expected,
+                    "{} count returned {count} instead of {expected} at step {step}.",
+                    variant.name()
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn search_stats() {
+    let seed = 42;
+    let (cardinality, dimensionality) = (10_000, 3);
+
+    let data = utils::gen_dataset(cardinality, dimensionality, seed, utils::euclidean);
+    let query = &vec![0.; dimensionality];
+
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(seed)).partition(&criteria, Some(seed));
+
+    let (linear_nn, stats) = knn::Algorithm::Linear.search_with_stats(&tree, query, 10);
+    assert_eq!(stats.distance_computations, cardinality);
+    assert_eq!(stats.clusters_visited, 0);
+    assert_eq!(stats.leaves_scanned, 0);
+
+    let algorithms = knn::Algorithm::variants()
+        .iter()
+        .copied()
+        .chain([knn::Algorithm::Approximate { recall: 0.9 }]);
+    for algorithm in algorithms {
+        let (hits, stats) = algorithm.search_with_stats(&tree, query, 10);
+        assert_eq!(hits.len(), 10);
+        if !matches!(algorithm, knn::Algorithm::Approximate { .. }) {
+            assert_approx_eq!(f32, utils::compute_recall(hits, linear_nn.clone()), 1.0);
+        }
+
+        // On low-dimensional data, the clustered algorithms prune most of the tree.
+        let name = algorithm.name();
+        assert!(stats.clusters_visited >= 1, "{name} visited no clusters.");
+        assert!(stats.leaves_scanned >= 1, "{name} scanned no leaves.");
+        assert!(stats.distance_computations >= stats.clusters_visited);
+        assert!(
+            stats.distance_computations < cardinality / 2,
+            "{name} computed {} distances.",
+            stats.distance_computations
+        );
+    }
+}
+
+#[test]
+fn bounded_metric() {
+    // The largest difference in any dimension is a lower bound on the
+    // euclidean distance, and the sum of the differences is an upper bound.
+    #[allow(clippy::ptr_arg)]
+    fn chebyshev(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+        distances::vectors::chebyshev(x, y)
+    }
+    #[allow(clippy::ptr_arg)]
+    fn manhattan(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+        distances::vectors::manhattan(x, y)
+    }
+
+    let seed = 42;
+    let (cardinality, dimensionality) = (5_000, 10);
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let data = symagen::random_data::random_tabular(cardinality, dimensionality, -1., 1., &mut rng);
+    let queries = data.iter().step_by(500).cloned().collect::<Vec<_>>();
+
+    let exact = VecDataset::new("exact".to_string(), data.clone(), utils::euclidean, false);
+    let metric = FnMetric::new(utils::euclidean)
+        .with_lower_bound(chebyshev)
+        .with_upper_bound(manhattan);
+    let bounded = VecDataset::from_metric("bounded".to_string(), data, metric);
+    assert!(bounded.upper_bound().is_some());
+
+    let criteria = PartitionCriteria::new(true).with_min_cardinality(20);
+    let exact = Tree::<_, _, _, UniBall<_>>::new(exact, Some(seed)).partition(&criteria, Some(seed));
+    let bounded = Tree::<_, _, _, UniBall<_>>::new(bounded, Some(seed)).partition(&criteria, Some(seed));
+
+    for query in &queries {
+        let linear_nn = knn::Algorithm::Linear.search(&exact, query, 5);
+        for &algorithm in knn::Algorithm::variants() {
+            let name = algorithm.name();
+            let (hits, bounded_stats) = algorithm.search_with_stats(&bounded, query, 5);
+            let (_, exact_stats) = algorithm.search_with_stats(&exact, query, 5);
+            assert_approx_eq!(f32, utils::compute_recall(hits, linear_nn.clone()), 1.0);
+            assert!(
+                bounded_stats.distance_computations <= exact_stats.distance_computations,
+                "{name} computed {} distances with bounds and {} without.",
+                bounded_stats.distance_computations,
+                exact_stats.distance_computations
+            );
+        }
+    }
+}
+
+#[test]
+fn sieve_low_dimensional() {
+    // In low dimensions, many grains straddle the threshold of a sieve, so its
+    // partition step must not move the threshold onto an unsorted grain.
+    for seed in 0..8 {
+        let data = utils::gen_dataset(500, 2, seed, utils::euclidean);
+        let queries = utils::gen_dataset(10, 2, seed + 1000, utils::euclidean);
+        let criteria = PartitionCriteria::default();
+        let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(seed)).partition(&criteria, Some(seed));
+
+        for i in 0..queries.cardinality() {
+            for k in [5, 10] {
+                let linear_nn = knn::Algorithm::Linear.search(&tree, &queries[i], k);
+                let sieves = [
+                    knn::Algorithm::Sieve {
+                        lfd_threshold: knn::DEFAULT_LFD_THRESHOLD,
+                    },
+                    knn::Algorithm::SieveSepCenter {
+                        lfd_threshold: knn::DEFAULT_LFD_THRESHOLD,
+                    },
+                ];
+                for variant in sieves {
+                    let variant_nn = variant.search(&tree, &queries[i], k);
+                    let recall = utils::compute_recall(linear_nn.clone(), variant_nn);
+                    assert_approx_eq!(f32, recall, 1.0);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn knn_removed_subtree() {
+    let seed = 42;
+    let data = utils::gen_dataset(2_000, 10, seed, utils::euclidean);
+
+    let criteria = PartitionCriteria::default();
+    let mut tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(seed)).partition(&criteria, Some(seed));
+
+    // Remove every instance of one child of the root, and search around
+    // the removed instances so that the closest `Cluster`s hold no hits.
+    let children = tree.root().children().unwrap();
+    let (offset, cardinality) = (children[0].offset(), children[0].cardinality());
+    let queries = (offset..(offset + cardinality))
+        .step_by(cardinality / 5)
+        .map(|i| tree.data()[i].clone())
+        .collect::<Vec<_>>();
+    for i in offset..(offset + cardinality) {
+        tree.remove(i).unwrap();
+    }
+
+    for query in &queries {
+        for k in [1, 10, 100] {
+            let linear_nn = knn::Algorithm::Linear.search(&tree, query, k);
+            for &variant in knn::Algorithm::variants() {
+                let hits = variant.search(&tree, query, k);
+                assert_eq!(hits.len(), k, "{} returned {} hits.", variant.name(), hits.len());
+                assert!(hits.iter().all(|&(i, _)| !tree.is_removed(i)));
+                assert_approx_eq!(f32, utils::compute_recall(hits, linear_nn.clone()), 1.0);
+            }
+        }
+    }
+}
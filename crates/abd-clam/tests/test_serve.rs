@@ -0,0 +1,137 @@
+//! Tests for remote search over TCP.
+
+#![cfg(feature = "serve")]
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use abd_clam::{knn, rnn, serve, Cakes, Dataset, Instance, PartitionCriteria, ShardedCakes};
+
+mod utils;
+
+fn sorted(mut hits: Vec<(usize, f32)>) -> Vec<(usize, f32)> {
+    hits.sort_by(|(i, a), (j, b)| a.total_cmp(b).then(i.cmp(j)));
+    hits
+}
+
+#[test]
+fn client() {
+    let criteria = PartitionCriteria::default();
+    let datasets = [300, 500]
+        .into_iter()
+        .zip(0..)
+        .map(|(cardinality, seed)| utils::gen_dataset(cardinality, 10, seed, utils::euclidean))
+        .collect::<Vec<_>>();
+
+    // Each shard is served from its own listener.
+    let addresses = datasets
+        .iter()
+        .map(|data| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            let cakes = Cakes::new(data.clone(), Some(42), &criteria).into_shared();
+            thread::spawn(move || serve::serve(&listener, &cakes));
+            address
+        })
+        .collect::<Vec<_>>();
+    let local = ShardedCakes::new(
+        datasets
+            .into_iter()
+            .map(|data| Cakes::new(data, Some(42), &criteria))
+            .collect(),
+    );
+
+    let mut client = serve::Client::<Vec<f32>, f32>::connect(&addresses).unwrap();
+    assert_eq!(client.num_shards(), 2);
+    assert_eq!(client.offsets(), &[0, 300]);
+    assert_eq!(client.total_cardinality(), 800);
+
+    let queries = utils::gen_dataset(10, 10, 42, utils::euclidean);
+    for i in 0..queries.cardinality() {
+        let query = &queries[i];
+
+        let expected = local
+            .knn_search(query, 10, knn::Algorithm::default())
+            .into_iter()
+            .map(|(i, d)| (local.original_index(i), d))
+            .collect();
+        let hits = client.knn_search(query, 10).unwrap();
+        assert_eq!(sorted(hits), sorted(expected));
+
+        let expected = local
+            .rnn_search(query, 0.5, rnn::Algorithm::default())
+            .into_iter()
+            .map(|(i, d)| (local.original_index(i), d))
+            .collect();
+        let hits = client.rnn_search(query, 0.5).unwrap();
+        assert_eq!(sorted(hits), sorted(expected));
+    }
+
+    // A `k` beyond the number of instances is clamped, by the client and by
+    // each server.
+    let query = &queries[0];
+    assert_eq!(client.knn_search(query, usize::MAX).map(|hits| hits.len()), Ok(800));
+    let mut request = vec![1_u8];
+    request.extend(u64::MAX.to_le_bytes());
+    request.extend(query.to_bytes());
+    let mut stream = TcpStream::connect(addresses[0]).unwrap();
+    stream
+        .write_all(&u32::try_from(request.len()).unwrap().to_le_bytes())
+        .unwrap();
+    stream.write_all(&request).unwrap();
+    let mut response = [0; 13];
+    stream.read_exact(&mut response).unwrap();
+    assert_eq!(response[4], 0);
+    assert_eq!(u64::from_le_bytes(response[5..].try_into().unwrap()), 300);
+
+    // A malformed query is reported by the server, and the connection stays
+    // usable.
+    let mut client = serve::Client::<Vec<u16>, f32>::connect(&addresses[..1]).unwrap();
+    assert!(client.knn_search(&vec![1_u16; 3], 5).is_err());
+    assert_eq!(client.knn_search(&vec![0_u16; 20], 5).map(|hits| hits.len()), Ok(5));
+}
+
+#[test]
+fn limits() {
+    let data = utils::gen_dataset(300, 10, 42, utils::euclidean);
+    let cakes = Cakes::new(data, Some(42), &PartitionCriteria::default()).into_shared();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || serve::serve_with_workers(&listener, &cakes, 1));
+
+    // A query of the wrong dimensionality is rejected.
+    let mut client = serve::Client::<Vec<f32>, f32>::connect(&[address]).unwrap();
+    assert_eq!(
+        client.knn_search(&vec![0.; 8], 5),
+        Err("Expected dimensionality 10, got 8".to_string())
+    );
+    assert_eq!(
+        client.rnn_search(&vec![0.; 8], 0.5).map(|hits| hits.len()),
+        Err("Expected dimensionality 10, got 8".to_string())
+    );
+    assert_eq!(client.knn_search(&vec![0.; 10], 5).map(|hits| hits.len()), Ok(5));
+
+    // With one worker, a second connection is only answered once the first
+    // is closed.
+    let (sender, receiver) = mpsc::channel();
+    let waiting = thread::spawn(move || {
+        let mut other = serve::Client::<Vec<f32>, f32>::connect(&[address]).unwrap();
+        sender.send(()).unwrap();
+        other.knn_search(&vec![0.; 10], 5).map(|hits| hits.len())
+    });
+    assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+    drop(client);
+    assert_eq!(waiting.join().unwrap(), Ok(5));
+
+    // A frame longer than the largest request closes the connection without
+    // a response.
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(&(1_u32 << 30).to_le_bytes()).unwrap();
+    let mut response = Vec::new();
+    assert!(stream.read_to_end(&mut response).map_or(true, |_| response.is_empty()));
+}
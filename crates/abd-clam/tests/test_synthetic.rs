@@ -0,0 +1,73 @@
+//! Tests for the synthetic dataset generators.
+
+#![cfg(feature = "test-utils")]
+
+use abd_clam::{knn, synthetic, Cakes, Cluster, PartitionCriteria, VecDataset};
+
+mod utils;
+
+#[test]
+fn uniform_hypercube() {
+    let data = synthetic::uniform_hypercube(100, 5, -2.0, 3.0, 42).unwrap();
+    assert_eq!(data.len(), 100);
+    assert!(data.iter().all(|x| x.len() == 5));
+    assert!(data.iter().flatten().all(|&v| (-2.0..3.0).contains(&v)));
+    assert_eq!(data, synthetic::uniform_hypercube(100, 5, -2.0, 3.0, 42).unwrap());
+    assert_ne!(data, synthetic::uniform_hypercube(100, 5, -2.0, 3.0, 43).unwrap());
+
+    assert!(synthetic::uniform_hypercube(10, 5, 1.0, 1.0, 42).is_err());
+    assert!(synthetic::uniform_hypercube(10, 5, 0.0, f32::INFINITY, 42).is_err());
+}
+
+#[test]
+fn gaussian_mixture() {
+    let (data, labels) = synthetic::gaussian_mixture(1000, 10, 4, 0.01, 42).unwrap();
+    assert_eq!(data.len(), 1000);
+    assert_eq!(labels.len(), 1000);
+    assert!(labels.iter().all(|&c| c < 4));
+
+    // With a small spread, the nearest neighbors of each instance are from
+    // the same component.
+    let dataset = VecDataset::new("mixture".to_string(), data.clone(), utils::euclidean::<f32, f32>, false);
+    let cakes = Cakes::new(dataset, Some(42), &PartitionCriteria::default());
+    for (i, x) in data.iter().enumerate().step_by(50) {
+        for (j, _) in cakes.knn_search(x, 5, knn::Algorithm::Linear) {
+            let j = cakes.original_index(j);
+            assert_eq!(labels[i], labels[j]);
+        }
+    }
+
+    assert!(synthetic::gaussian_mixture(10, 5, 0, 0.1, 42).is_err());
+    assert!(synthetic::gaussian_mixture(10, 5, 2, -0.1, 42).is_err());
+}
+
+#[test]
+fn manifold() {
+    let data = synthetic::manifold(2000, 2, 100, 0.0, 42).unwrap();
+    assert_eq!(data.len(), 2000);
+    assert!(data.iter().all(|x| x.len() == 100));
+
+    // The local fractal dimension near the root is that of the manifold, not
+    // of the ambient space.
+    let dataset = VecDataset::new("manifold".to_string(), data, utils::euclidean::<f32, f32>, false);
+    let cakes = Cakes::new(dataset, Some(42), &PartitionCriteria::default());
+    let lfd = cakes.trees()[0].root().lfd();
+    assert!(lfd < 10.0, "{lfd}");
+
+    assert!(synthetic::manifold(10, 0, 10, 0.0, 42).is_err());
+    assert!(synthetic::manifold(10, 3, 5, 0.0, 42).is_err());
+    assert!(synthetic::manifold(10, 2, 5, f32::NAN, 42).is_err());
+}
+
+#[test]
+fn random_strings() {
+    let alphabet = ['A', 'C', 'G', 'T'];
+    let strings = synthetic::random_strings(100, 5, 20, &alphabet, 42).unwrap();
+    assert_eq!(strings.len(), 100);
+    assert!(strings.iter().all(|s| (5..=20).contains(&s.len())));
+    assert!(strings.iter().all(|s| s.chars().all(|c| alphabet.contains(&c))));
+    assert_eq!(strings, synthetic::random_strings(100, 5, 20, &alphabet, 42).unwrap());
+
+    assert!(synthetic::random_strings(10, 5, 20, &[], 42).is_err());
+    assert!(synthetic::random_strings(10, 20, 5, &alphabet, 42).is_err());
+}
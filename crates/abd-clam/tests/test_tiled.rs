@@ -0,0 +1,78 @@
+//! Tests for the batch searches which scan each leaf once per batch.
+
+use abd_clam::{cakes::tiled, knn, Dataset, Instance, PartitionCriteria, Tree, UniBall, VecDataset};
+use distances::Number;
+use float_cmp::assert_approx_eq;
+
+mod utils;
+
+#[test]
+fn batch_search() {
+    let data = utils::gen_dataset(2_000, 10, 42, utils::euclidean);
+    let queries = utils::gen_dataset(50, 10, 0, utils::euclidean);
+    let mut queries = (0..queries.cardinality()).map(|i| &queries[i]).collect::<Vec<_>>();
+    // Repeated queries reach the same leaves.
+    queries.extend(queries.clone());
+    let criteria = PartitionCriteria::default();
+    let mut tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+    tree.remove(0).unwrap();
+
+    let hits = tiled::batch_rnn_search(&tree, &queries, 0.5);
+    assert_eq!(hits.len(), queries.len());
+    for (query, hits) in queries.iter().zip(hits) {
+        let mut hits = hits.into_iter().map(|(i, _)| i).collect::<Vec<_>>();
+        hits.sort_unstable();
+        assert_eq!(hits, linear_rnn(&tree, query, 0.5));
+    }
+
+    for k in [1, 10, 100] {
+        let hits = tiled::batch_knn_search(&tree, &queries, k);
+        assert_eq!(hits.len(), queries.len());
+        for (query, hits) in queries.iter().zip(hits) {
+            let expected = knn::Algorithm::Linear.search(&tree, *query, k);
+            assert_eq!(hits.len(), k);
+            assert!(hits.windows(2).all(|w| w[0].1 <= w[1].1));
+            assert_approx_eq!(f32, utils::compute_recall(hits, expected), 1.0);
+        }
+    }
+
+    assert!(tiled::batch_knn_search(&tree, &queries, 0).iter().all(Vec::is_empty));
+}
+
+#[test]
+fn unsigned_distances() {
+    let data = symagen::random_data::random_string(500, 50, 50, "ACTG", 42);
+    let data = VecDataset::new("strings".to_string(), data, utils::hamming::<u16>, false);
+    let queries = symagen::random_data::random_string(20, 50, 50, "ACTG", 43);
+    let queries = queries.iter().collect::<Vec<_>>();
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+
+    // Queries close to a `Cluster` must not underflow its lower bound.
+    let hits = tiled::batch_rnn_search(&tree, &queries, 30);
+    for (query, hits) in queries.iter().zip(hits) {
+        let mut hits = hits.into_iter().map(|(i, _)| i).collect::<Vec<_>>();
+        hits.sort_unstable();
+        assert_eq!(hits, linear_rnn(&tree, query, 30));
+    }
+
+    for k in [1, 10] {
+        let hits = tiled::batch_knn_search(&tree, &queries, k);
+        for (query, hits) in queries.iter().zip(hits) {
+            let expected = knn::Algorithm::Linear.search(&tree, *query, k);
+            assert_eq!(hits.last().map(|&(_, d)| d), expected.iter().map(|&(_, d)| d).max());
+        }
+    }
+}
+
+/// The sorted indices of the instances, which have not been removed, within
+/// `radius` of the `query`, by a linear scan.
+fn linear_rnn<I: Instance, U: Number, D: Dataset<I, U>>(
+    tree: &Tree<I, U, D, UniBall<U>>,
+    query: &I,
+    radius: U,
+) -> Vec<usize> {
+    (0..tree.data().cardinality())
+        .filter(|&i| !tree.is_removed(i) && tree.data().query_to_one(query, i) <= radius)
+        .collect()
+}
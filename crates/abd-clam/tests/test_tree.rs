@@ -1,8 +1,16 @@
 //! Tests on the tree module.
 
-use abd_clam::{Cluster, Dataset, Instance, PartitionCriteria, Tree, UniBall, VecDataset};
+use std::collections::BTreeSet;
+
+use abd_clam::{
+    cakes::{knn, rnn},
+    BuildProgress, CancellationToken, Cluster, Cut, Dataset, Error, FnMetric, Instance, PartitionCriteria,
+    PartitionCriterion, PartitionStrategy, StreamingBuilder, Tree, UniBall, VecDataset,
+};
 use distances::Number;
+use float_cmp::assert_approx_eq;
 use tempdir::TempDir;
+use test_case::test_case;
 
 mod utils;
 
@@ -62,8 +70,8 @@ fn reordering() {
 
 #[test]
 fn save_load() {
-    let data = utils::gen_dataset(1000, 10, 42, utils::euclidean);
-    let metric = data.metric();
+    let metric: fn(&Vec<f32>, &Vec<f32>) -> f32 = utils::euclidean;
+    let data = utils::gen_dataset(1000, 10, 42, metric);
 
     let criteria = PartitionCriteria::default();
     let raw_tree = Tree::new(data, Some(42)).partition(&criteria, Some(42));
@@ -87,6 +95,44 @@ fn save_load() {
     );
 }
 
+#[test]
+fn save_load_with_data() {
+    let metric: fn(&Vec<f32>, &Vec<f32>) -> f32 = utils::euclidean;
+    let data = utils::gen_dataset(1000, 10, 42, metric);
+
+    let criteria = PartitionCriteria::default();
+    let raw_tree = Tree::new(data, Some(42)).partition(&criteria, Some(42));
+
+    let tree_dir = TempDir::new("tree_with_data").unwrap();
+    raw_tree.save(tree_dir.path()).unwrap();
+
+    // Recover the tree over the data in its original order.
+    let data = utils::gen_dataset(1000, 10, 42, utils::euclidean);
+    let rec_tree = Tree::<_, _, _, UniBall<_>>::load_with_data(tree_dir.path(), data).unwrap();
+    assert_eq!(raw_tree.depth(), rec_tree.depth(), "Tree depths not equal.");
+    assert_subtree_equal(
+        raw_tree.root(),
+        raw_tree.data(),
+        rec_tree.root(),
+        rec_tree.data(),
+        metric,
+    );
+
+    // Recover the tree over the already reordered data.
+    let rec_tree = Tree::<_, _, _, UniBall<_>>::load_with_data(tree_dir.path(), raw_tree.data().clone()).unwrap();
+    assert_subtree_equal(
+        raw_tree.root(),
+        raw_tree.data(),
+        rec_tree.root(),
+        rec_tree.data(),
+        metric,
+    );
+
+    // The data must match the saved tree.
+    let data = utils::gen_dataset(999, 10, 42, utils::euclidean);
+    assert!(Tree::<_, _, _, UniBall<_>>::load_with_data(tree_dir.path(), data).is_err());
+}
+
 /// Asserts that two clusters are equal.
 fn assert_subtree_equal<I: Instance, U: Number, M: Instance>(
     raw_cluster: &UniBall<U>,
@@ -115,13 +161,15 @@ fn assert_subtree_equal<I: Instance, U: Number, M: Instance>(
 
     match raw_children {
         None => assert!(rec_children.is_none(), "One cluster has children, the other does not"),
-        Some([left_1, right_1]) => {
+        Some(children_1) => {
             assert!(rec_children.is_some(), "One cluster has children, the other does not");
 
-            let [left_2, right_2] = rec_children.unwrap();
+            let children_2 = rec_children.as_ref().unwrap();
+            assert_eq!(children_1.len(), children_2.len(), "Numbers of children are not equal.");
 
-            assert_subtree_equal(left_1, raw_data, left_2, rec_data, metric);
-            assert_subtree_equal(right_1, raw_data, right_2, rec_data, metric);
+            for (child_1, child_2) in children_1.iter().zip(children_2) {
+                assert_subtree_equal(child_1, raw_data, child_2, rec_data, metric);
+            }
         }
     }
 }
@@ -147,3 +195,882 @@ fn get_cluster() {
         }
     }
 }
+
+#[test]
+fn insert() {
+    let (cardinality, dimensionality) = (1_000, 10);
+    let data = utils::gen_dataset(cardinality, dimensionality, 42, utils::euclidean);
+    let new_data = utils::gen_dataset(200, dimensionality, 43, utils::euclidean);
+    let all_data = data.data().iter().chain(new_data.data()).cloned().collect::<Vec<_>>();
+
+    let criteria = PartitionCriteria::default();
+    let mut tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+
+    let instances = new_data.data().iter().cloned().zip(cardinality..).collect::<Vec<_>>();
+    tree.insert_batch(instances, &criteria, Some(42)).unwrap();
+
+    assert_eq!(tree.cardinality(), all_data.len());
+    assert_eq!(tree.data().cardinality(), all_data.len());
+    assert_eq!(tree.depth(), tree.root().max_leaf_depth());
+
+    // The permutation and metadata must still map back to the original instances.
+    for i in 0..tree.cardinality() {
+        let original = tree.data().original_index(i);
+        assert_eq!(&tree.data()[i], &all_data[original]);
+        assert_eq!(*tree.data().metadata_of(i), original);
+    }
+
+    // Every cluster must contain its instances within its radius, and children
+    // must partition their parent.
+    for c in tree.root().subtree() {
+        for i in c.indices() {
+            let d = tree.data().one_to_one(c.arg_center(), i);
+            assert!(d <= c.radius(), "Instance {i} is outside {c}.");
+        }
+        if let Some(&[left, right]) = c.children().as_deref() {
+            assert_eq!(left.offset(), c.offset());
+            assert_eq!(right.offset(), left.offset() + left.cardinality());
+            assert_eq!(left.cardinality() + right.cardinality(), c.cardinality());
+        }
+    }
+
+    // Search must still find the inserted instances.
+    for query in new_data.data() {
+        let linear_hits = knn::Algorithm::Linear.search(&tree, query, 10);
+        let hits = knn::Algorithm::GreedySieve.search(&tree, query, 10);
+        assert_eq!(hits.len(), linear_hits.len());
+        assert!(hits.iter().any(|&(_, d)| d == 0.0));
+        assert_approx_eq!(f32, utils::compute_recall(hits, linear_hits), 1.0);
+    }
+}
+
+#[test]
+fn remove_and_compact() {
+    let (cardinality, dimensionality) = (1_000, 10);
+    let data = utils::gen_dataset(cardinality, dimensionality, 42, utils::euclidean);
+    let queries = data.data().iter().step_by(10).cloned().collect::<Vec<_>>();
+
+    let criteria = PartitionCriteria::default();
+    let mut tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+
+    let removed = (0..cardinality).step_by(3).collect::<Vec<_>>();
+    for &i in &removed {
+        tree.remove(i).unwrap();
+    }
+    assert!(tree.remove(0).is_err());
+    assert!(tree.remove(cardinality).is_err());
+    assert_eq!(tree.num_removed(), removed.len());
+
+    let removed_originals = removed
+        .iter()
+        .map(|&i| tree.data().original_index(i))
+        .collect::<Vec<_>>();
+
+    let check_search = |tree: &VecTree| {
+        for query in &queries {
+            let linear_hits = knn::Algorithm::Linear.search(tree, query, 10);
+            assert_eq!(linear_hits.len(), 10);
+            for algorithm in knn::Algorithm::variants() {
+                let hits = algorithm.search(tree, query, 10);
+                assert_eq!(hits.len(), 10, "{} returned too few hits.", algorithm.name());
+                assert!(hits.iter().all(|&(i, _)| !tree.is_removed(i)));
+                assert_approx_eq!(f32, utils::compute_recall(hits, linear_hits.clone()), 1.0);
+            }
+
+            let hits = rnn::Algorithm::Clustered.search(query, 0.5, tree);
+            let linear_hits = rnn::Algorithm::Linear.search(query, 0.5, tree);
+            assert!(hits.iter().all(|&(i, _)| !tree.is_removed(i)));
+            assert_eq!(hits.len(), linear_hits.len());
+        }
+    };
+
+    check_search(&tree);
+
+    tree.compact(0.2, &criteria, Some(42)).unwrap();
+    assert!(tree.num_removed() < removed.len());
+    assert_eq!(tree.cardinality(), tree.data().cardinality());
+    assert_eq!(tree.cardinality() - tree.num_removed(), cardinality - removed.len());
+    assert_eq!(tree.depth(), tree.root().max_leaf_depth());
+
+    // Live instances must never have been removed, and every cluster must
+    // contain its instances within its radius.
+    for i in (0..tree.cardinality()).filter(|&i| !tree.is_removed(i)) {
+        assert!(!removed_originals.contains(&tree.data().original_index(i)));
+    }
+    for c in tree.root().subtree() {
+        for i in c.indices() {
+            let d = tree.data().one_to_one(c.arg_center(), i);
+            assert!(d <= c.radius(), "Instance {i} is outside {c}.");
+        }
+        if let Some(&[left, right]) = c.children().as_deref() {
+            assert_eq!(left.offset(), c.offset());
+            assert_eq!(right.offset(), left.offset() + left.cardinality());
+            assert_eq!(left.cardinality() + right.cardinality(), c.cardinality());
+        }
+    }
+
+    check_search(&tree);
+
+    // Compacting with a threshold of zero removes every tombstone.
+    tree.compact(0.0, &criteria, Some(42)).unwrap();
+    assert_eq!(tree.num_removed(), 0);
+    assert_eq!(tree.cardinality(), cardinality - removed.len());
+    check_search(&tree);
+}
+
+#[test]
+fn traversal_api() {
+    let data = utils::gen_dataset(1000, 5, 42, utils::euclidean);
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+
+    // The leaves cover the dataset, in order.
+    let leaves = tree.leaves();
+    let leaf_indices = leaves.iter().flat_map(|c| c.indices()).collect::<Vec<_>>();
+    assert_eq!(leaf_indices, (0..tree.cardinality()).collect::<Vec<_>>());
+    assert!(leaves.iter().all(|c| c.is_leaf()));
+
+    for c in tree.root().subtree() {
+        assert_eq!(c.center_index(), c.arg_center());
+        assert!(c.indices().contains(&c.center_index()));
+        assert_eq!(c.index_range(), c.indices());
+        if let Some(children) = c.children() {
+            // The ranges of the children split the range of the parent, in order.
+            let child_indices = children.iter().flat_map(|ch| ch.index_range()).collect::<Vec<_>>();
+            assert_eq!(child_indices, c.index_range().collect::<Vec<_>>());
+        }
+        assert!(c.lfd().is_finite() && c.lfd() >= 0.);
+        let center = tree.center_of(c);
+        for i in c.indices() {
+            assert!(utils::euclidean::<f32, f32>(&center, &tree.data()[i]) <= c.radius());
+        }
+    }
+
+    // A depth-first nearest-neighbor search written only against the public accessors.
+    let query = vec![0.; 5];
+    let mut best = (usize::MAX, f32::MAX);
+    let mut stack = vec![tree.root()];
+    while let Some(c) = stack.pop() {
+        let d = utils::euclidean::<f32, f32>(&tree.center_of(c), &query);
+        if d - c.radius() > best.1 {
+            continue;
+        }
+        match c.children() {
+            Some(children) => stack.extend(children),
+            None => {
+                for i in c.indices() {
+                    let d = utils::euclidean::<f32, f32>(&tree.data()[i], &query);
+                    if d < best.1 {
+                        best = (i, d);
+                    }
+                }
+            }
+        }
+    }
+
+    let linear = knn::Algorithm::Linear.search(&tree, &query, 1);
+    assert_eq!(linear.len(), 1);
+    assert_approx_eq!(f32, best.1, linear[0].1);
+}
+
+#[test]
+fn report() -> Result<(), String> {
+    let data = utils::gen_dataset(1000, 5, 42, utils::euclidean);
+    let criteria = PartitionCriteria::default();
+    let mut tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+    tree.remove(3)?;
+
+    let report = tree.report();
+    let clusters = tree.root().subtree();
+    let leaves = tree.leaves();
+    assert_eq!(report.cardinality, 1000);
+    assert_eq!(report.num_removed, 1);
+    assert_eq!(report.depth, tree.depth());
+    assert_eq!(report.num_clusters, clusters.len());
+    assert_eq!(report.num_leaves, leaves.len());
+
+    // There is one row per depth, and the rows add up to the whole tree.
+    assert_eq!(report.depths.len(), tree.depth() + 1);
+    assert_eq!(report.depths[0].num_clusters, 1);
+    assert_approx_eq!(f64, report.depths[0].radius.max, tree.radius().as_f64());
+    assert_eq!(
+        report.depths.iter().map(|d| d.num_clusters).sum::<usize>(),
+        clusters.len()
+    );
+    assert_eq!(report.depths.iter().map(|d| d.num_leaves).sum::<usize>(), leaves.len());
+    for d in &report.depths {
+        assert!(d.radius.min <= d.radius.median && d.radius.median <= d.radius.max);
+        assert!(d.lfd.min <= d.lfd.mean && d.lfd.mean <= d.lfd.max);
+    }
+
+    let cardinalities = leaves.iter().map(|c| c.cardinality()).collect::<Vec<_>>();
+    assert_approx_eq!(f64, report.leaf_cardinality.mean, 1000. / leaves.len().as_f64());
+    assert_approx_eq!(
+        f64,
+        report.leaf_cardinality.max,
+        cardinalities.iter().max().copied().unwrap_or_default().as_f64()
+    );
+
+    // The report survives a round trip through JSON, and prints one line per depth.
+    let json = report.to_json()?;
+    assert_eq!(abd_clam::TreeReport::from_json(&json)?, report);
+    assert!(abd_clam::TreeReport::from_json("{}").is_err());
+    let table = report.to_string();
+    assert_eq!(table.lines().count(), 3 + report.depths.len());
+
+    Ok(())
+}
+
+#[test]
+fn coreset() -> Result<(), String> {
+    let data = utils::gen_dataset(1000, 5, 42, utils::euclidean);
+    let criteria = PartitionCriteria::default();
+    let mut tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+    for i in (0..tree.cardinality()).step_by(7) {
+        tree.remove(i)?;
+    }
+    let live = tree.cardinality() - tree.num_removed();
+
+    for size in [1, 10, 100, 500] {
+        let coreset = tree.coreset(size);
+        assert!(!coreset.is_empty() && coreset.len() <= size);
+        assert!(coreset.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(coreset.iter().all(|&(i, w)| !tree.is_removed(i) && w > 0));
+        assert_eq!(coreset.iter().map(|&(_, w)| w).sum::<usize>(), live);
+    }
+    assert_eq!(tree.coreset(1)[0].1, live);
+
+    // With room for every instance, each represents only itself.
+    let coreset = tree.coreset(tree.cardinality());
+    assert_eq!(coreset.len(), live);
+    assert!(coreset.iter().all(|&(_, w)| w == 1));
+    assert!(tree.coreset(0).is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn dendrogram() -> Result<(), String> {
+    let data = utils::gen_dataset(1000, 5, 42, utils::euclidean);
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+
+    let dendrogram = tree.dendrogram();
+    let clusters = tree.root().subtree();
+    assert_eq!(dendrogram.nodes.len(), clusters.len());
+    assert!(dendrogram.nodes[0].parent.is_none());
+    assert_eq!(dendrogram.nodes[0].cardinality, tree.cardinality());
+    for (i, node) in dendrogram.nodes.iter().enumerate() {
+        let c = tree
+            .get_cluster(node.offset, node.cardinality)
+            .ok_or("missing cluster")?;
+        assert_eq!(node.name, c.name());
+        assert_eq!(node.depth, c.depth());
+        assert_eq!(node.children.len(), c.children().map_or(0, |ch| ch.len()));
+        assert_eq!(
+            node.children
+                .iter()
+                .map(|&j| dendrogram.nodes[j].cardinality)
+                .sum::<usize>(),
+            if c.is_leaf() { 0 } else { c.cardinality() }
+        );
+        assert!(node
+            .children
+            .iter()
+            .all(|&j| j > i && dendrogram.nodes[j].parent == Some(i)));
+    }
+
+    let json = dendrogram.to_json()?;
+    assert_eq!(abd_clam::Dendrogram::from_json(&json)?, dendrogram);
+
+    // Every `Cluster` appears once in the Newick text, which is balanced.
+    let newick = dendrogram.to_newick();
+    assert!(newick.starts_with('(') && newick.ends_with(';'));
+    assert_eq!(newick.matches('(').count(), newick.matches(')').count());
+    assert_eq!(newick.matches("[&&NHX").count(), clusters.len());
+    assert_eq!(
+        newick.matches('(').count(),
+        clusters.iter().filter(|c| !c.is_leaf()).count()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn flat_clustering() -> Result<(), String> {
+    let data = utils::gen_dataset(1000, 5, 42, utils::euclidean);
+    let criteria = PartitionCriteria::default();
+    let mut tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+    tree.remove(3)?;
+    let live = (0..tree.cardinality()).filter(|&i| i != 3).collect::<Vec<_>>();
+
+    let radius = tree.radius() / 2.;
+    for cut in [
+        Cut::Depth(0),
+        Cut::Depth(3),
+        Cut::Depth(tree.depth() + 1),
+        Cut::Radius(radius),
+        Cut::NumClusters(10),
+    ] {
+        // The clusters partition the instances which were not removed.
+        let clusters = tree.flat_clustering(cut);
+        assert!(clusters.windows(2).all(|w| w[0][0] < w[1][0]));
+        assert_eq!(clusters.concat(), live, "{cut:?}");
+
+        let sizes = clusters.iter().map(Vec::len).collect::<Vec<_>>();
+        match cut {
+            Cut::Depth(0) => assert_eq!(sizes, vec![999]),
+            Cut::Depth(d) if d > tree.depth() => {
+                let leaves = tree.leaves().into_iter().filter(|c| c.indices().any(|i| i != 3));
+                assert_eq!(clusters.len(), leaves.count());
+            }
+            Cut::NumClusters(n) => assert!(clusters.len() >= n),
+            _ => (),
+        }
+    }
+
+    // The instances in each cluster cut by radius are within twice that radius
+    // of each other, up to rounding, as they are within the radius of its
+    // center.
+    let data = tree.data();
+    for cluster in tree.flat_clustering(Cut::Radius(radius)) {
+        for &i in &cluster {
+            assert!(cluster
+                .iter()
+                .all(|&j| utils::euclidean::<f32, f32>(&data[i], &data[j]) <= 2.001 * radius));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn cluster_quality() {
+    let data = utils::gen_dataset(300, 3, 42, utils::euclidean);
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+    let data = tree.data();
+    let d = |i: usize, j: usize| utils::euclidean::<f32, f32>(&data[i], &data[j]).as_f64();
+
+    for cut in [Cut::Depth(1), Cut::NumClusters(5), Cut::Radius(tree.radius() / 2.)] {
+        let clusters = tree.flat_clustering(cut);
+        let mean = |i: usize, cluster: &[usize]| cluster.iter().map(|&j| d(i, j)).sum::<f64>() / cluster.len().as_f64();
+
+        // The silhouette by brute force.
+        let clusters = &clusters;
+        let silhouettes = clusters.iter().enumerate().flat_map(|(c, cluster)| {
+            cluster.iter().map(move |&i| {
+                if cluster.len() == 1 {
+                    return 0.0;
+                }
+                let a = mean(i, cluster) * cluster.len().as_f64() / (cluster.len() - 1).as_f64();
+                let b = clusters
+                    .iter()
+                    .enumerate()
+                    .filter(|&(o, _)| o != c)
+                    .map(|(_, other)| mean(i, other))
+                    .fold(f64::INFINITY, f64::min);
+                (b - a) / a.max(b)
+            })
+        });
+        let expected = silhouettes.sum::<f64>() / tree.cardinality().as_f64();
+        let silhouette = tree.silhouette(cut);
+        assert!((-1.0..=1.0).contains(&silhouette));
+        assert_approx_eq!(f64, silhouette, expected, epsilon = 1e-6);
+
+        // The Davies-Bouldin index by brute force, with the centers from the tree.
+        let centers = clusters
+            .iter()
+            .map(|cluster| tree.get_cluster(cluster[0], cluster.len()).unwrap().arg_center())
+            .collect::<Vec<_>>();
+        let scatters = clusters
+            .iter()
+            .zip(&centers)
+            .map(|(cluster, &c)| mean(c, cluster))
+            .collect::<Vec<_>>();
+        let expected = (0..clusters.len())
+            .map(|i| {
+                (0..clusters.len())
+                    .filter(|&j| j != i)
+                    .map(|j| (scatters[i] + scatters[j]) / d(centers[i], centers[j]))
+                    .fold(0.0, f64::max)
+            })
+            .sum::<f64>()
+            / clusters.len().as_f64();
+        assert_approx_eq!(f64, tree.davies_bouldin(cut), expected, epsilon = 1e-6);
+    }
+
+    assert_approx_eq!(f64, tree.silhouette(Cut::Depth(0)), 0.0);
+    assert_approx_eq!(f64, tree.davies_bouldin(Cut::Depth(0)), 0.0);
+}
+
+#[test]
+fn reproducible() -> Result<(), String> {
+    let build = |seed: Option<u64>, criteria: &PartitionCriteria<f32>| {
+        let data = utils::gen_dataset(1000, 10, 42, utils::euclidean);
+        Tree::<_, _, _, UniBall<_>>::new(data, seed).partition(criteria, seed)
+    };
+    let save = |tree: &Tree<_, _, _, _>, name: &str| -> Result<Vec<u8>, String> {
+        let tmp_dir = TempDir::new(name).map_err(|e| e.to_string())?;
+        tree.save(tmp_dir.path())?;
+        let mut bytes = Vec::new();
+        for name in ["dataset", "clusters", "permutation"] {
+            bytes.extend(std::fs::read(tmp_dir.path().join(name)).map_err(|e| e.to_string())?);
+        }
+        Ok(bytes)
+    };
+
+    // The seed of the criteria is used when none is given explicitly.
+    let criteria = PartitionCriteria::default().with_rng_seed(7);
+    let first = save(&build(None, &criteria), "first")?;
+    let second = save(&build(None, &criteria), "second")?;
+    assert_eq!(first, second);
+    let explicit = save(&build(Some(7), &PartitionCriteria::default()), "explicit")?;
+    assert_eq!(first, explicit);
+
+    // An explicit seed takes precedence.
+    let other = save(&build(Some(8), &criteria), "other")?;
+    assert_ne!(first, other);
+
+    // The tree is the same whether it is built in parallel or not.
+    for cutoff in [0, 100, usize::MAX] {
+        let criteria = PartitionCriteria::default().with_sequential_cutoff(cutoff);
+        let tree = save(&build(Some(7), &criteria), "cutoff")?;
+        assert_eq!(first, tree);
+
+        // So is the root, when it is built with the criteria.
+        let data = utils::gen_dataset(1000, 10, 42, utils::euclidean);
+        let tree = save(&Tree::<_, _, _, UniBall<_>>::build(data, &criteria, Some(7)), "build")?;
+        assert_eq!(first, tree);
+    }
+
+    Ok(())
+}
+
+#[test_case(PartitionStrategy::MaxSeparation; "max_separation")]
+#[test_case(PartitionStrategy::Balanced; "balanced")]
+#[test_case(PartitionStrategy::MedianSplit; "median_split")]
+fn partition_strategies(strategy: PartitionStrategy) {
+    // Exponentially spaced points produce very skewed splits by separation.
+    let cardinality = 512;
+    let data = (0..cardinality).map(|i| vec![1.05_f32.powi(i)]).collect::<Vec<_>>();
+    let data = utils::gen_dataset_from(data, utils::euclidean::<f32, f32>, vec![true; 512]);
+    let queries = data.data().iter().step_by(16).cloned().collect::<Vec<_>>();
+
+    let criteria = PartitionCriteria::default().with_strategy(strategy);
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+    assert_eq!(
+        tree.root().indices().collect::<Vec<_>>(),
+        (0..cardinality as usize).collect::<Vec<_>>()
+    );
+    assert_eq!(tree.root().strategy(), Some(strategy));
+
+    // The strategy is saved with the tree.
+    let tree_dir = TempDir::new("strategy").unwrap();
+    tree.save(tree_dir.path()).unwrap();
+    let rec_tree = Tree::<_, _, _, UniBall<_>>::load_with_data(tree_dir.path(), tree.data().clone()).unwrap();
+    assert_eq!(rec_tree.root().strategy(), Some(strategy));
+
+    for c in tree.root().subtree() {
+        for i in c.indices() {
+            assert!(tree.data().one_to_one(c.arg_center(), i) <= c.radius());
+        }
+        if let Some(&[left, right]) = c.children().as_deref() {
+            assert_eq!(left.cardinality() + right.cardinality(), c.cardinality());
+            if strategy != PartitionStrategy::MaxSeparation {
+                assert!(left.cardinality() - right.cardinality() <= 1, "{c} is unbalanced.");
+            }
+        }
+    }
+    if strategy == PartitionStrategy::MaxSeparation {
+        assert!(tree.depth() > 20);
+    } else {
+        assert_eq!(tree.depth(), 9);
+    }
+
+    for query in &queries {
+        let linear_hits = knn::Algorithm::Linear.search(&tree, query, 10);
+        for algorithm in knn::Algorithm::variants() {
+            let hits = algorithm.search(&tree, query, 10);
+            assert_approx_eq!(f32, utils::compute_recall(hits, linear_hits.clone()), 1.0);
+        }
+        let hits = rnn::Algorithm::Clustered.search(query, 0.5, &tree);
+        assert_eq!(hits.len(), rnn::Algorithm::Linear.search(query, 0.5, &tree).len());
+    }
+}
+
+/// Checks that every cluster contains its instances within its radius and that
+/// its children partition it into at most `fan_out` contiguous ranges.
+type VecTree = Tree<Vec<f32>, f32, VecDataset<Vec<f32>, f32, usize>, UniBall<f32>>;
+
+fn assert_valid_tree(tree: &VecTree, fan_out: usize) {
+    assert_eq!(tree.depth(), tree.root().max_leaf_depth());
+    for c in tree.root().subtree() {
+        for i in c.indices() {
+            assert!(tree.data().one_to_one(c.arg_center(), i) <= c.radius());
+        }
+        if let Some(children) = c.children() {
+            assert!(
+                (2..=fan_out).contains(&children.len()),
+                "{c} has {} children.",
+                children.len()
+            );
+            assert_eq!(children[0].offset(), c.offset());
+            for pair in children.windows(2) {
+                assert_eq!(pair[1].offset(), pair[0].offset() + pair[0].cardinality());
+            }
+            assert_eq!(children.iter().map(|c| c.cardinality()).sum::<usize>(), c.cardinality());
+
+            let arg_poles = c.arg_poles().unwrap();
+            assert_eq!(arg_poles.len(), children.len());
+            for (child, arg_pole) in children.iter().zip(arg_poles) {
+                assert!(child.indices().contains(&arg_pole));
+            }
+        }
+    }
+}
+
+#[test_case(2, PartitionStrategy::MaxSeparation; "binary")]
+#[test_case(4, PartitionStrategy::MaxSeparation; "max_separation_4")]
+#[test_case(16, PartitionStrategy::MaxSeparation; "max_separation_16")]
+#[test_case(4, PartitionStrategy::Balanced; "balanced_4")]
+#[test_case(8, PartitionStrategy::MedianSplit; "median_split_8")]
+fn fan_out(fan_out: usize, strategy: PartitionStrategy) {
+    let (cardinality, dimensionality) = (2_000, 10);
+    let data = utils::gen_dataset(cardinality, dimensionality, 42, utils::euclidean);
+    let new_data = utils::gen_dataset(100, dimensionality, 43, utils::euclidean);
+    let queries = data.data().iter().step_by(50).cloned().collect::<Vec<_>>();
+
+    let binary = Tree::<_, _, _, UniBall<_>>::new(data.clone(), Some(42))
+        .partition(&PartitionCriteria::default().with_strategy(strategy), Some(42));
+    let criteria = PartitionCriteria::default()
+        .with_strategy(strategy)
+        .with_fan_out(fan_out);
+    let mut tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+    assert_valid_tree(&tree, fan_out);
+    assert_eq!(tree.root().children().map(|c| c.len()), Some(fan_out));
+    if fan_out > 2 {
+        assert!(tree.depth() < binary.depth());
+    }
+    if strategy != PartitionStrategy::MaxSeparation {
+        for c in tree.root().subtree() {
+            if let Some(children) = c.children() {
+                let sizes = children.iter().map(|c| c.cardinality()).collect::<Vec<_>>();
+                assert!(sizes[0] - sizes[sizes.len() - 1] <= 1, "{c} is unbalanced.");
+            }
+        }
+    }
+
+    let check_search = |tree: &VecTree| {
+        for query in &queries {
+            let linear_hits = knn::Algorithm::Linear.search(tree, query, 10);
+            for algorithm in knn::Algorithm::variants() {
+                let hits = algorithm.search(tree, query, 10);
+                assert_approx_eq!(f32, utils::compute_recall(hits, linear_hits.clone()), 1.0);
+            }
+            for radius in [0.5, 1.0] {
+                let hits = rnn::Algorithm::Clustered.search(query, radius, tree);
+                let linear_hits = rnn::Algorithm::Linear.search(query, radius, tree);
+                assert_eq!(hits.len(), linear_hits.len());
+            }
+        }
+    };
+    check_search(&tree);
+
+    // Insertion and compaction keep the fan-out.
+    let instances = new_data.data().iter().cloned().zip(cardinality..).collect::<Vec<_>>();
+    tree.insert_batch(instances, &criteria, Some(42)).unwrap();
+    for i in (0..tree.cardinality()).step_by(3) {
+        tree.remove(i).unwrap();
+    }
+    tree.compact(0.2, &criteria, Some(42)).unwrap();
+    assert_valid_tree(&tree, fan_out);
+    check_search(&tree);
+
+    // The children are saved with the tree.
+    let tree_dir = TempDir::new("fan_out").unwrap();
+    tree.save(tree_dir.path()).unwrap();
+    let rec_tree = Tree::<_, _, _, UniBall<_>>::load_with_data(tree_dir.path(), tree.data().clone()).unwrap();
+    assert_eq!(tree.root().subtree(), rec_tree.root().subtree());
+    assert_eq!(tree.root().arg_poles(), rec_tree.root().arg_poles());
+}
+
+#[test]
+fn combined_criteria() {
+    let data = utils::gen_dataset(2_000, 10, 42, utils::euclidean);
+
+    // Stop at depth 6, or below cardinality 50, or at radius 0.5.
+    let criteria = PartitionCriteria::new(true)
+        .with_max_depth(6)
+        .with_min_cardinality(49)
+        .with_min_radius(0.5);
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data.clone(), Some(42)).partition(&criteria, Some(42));
+    for c in tree.root().subtree() {
+        let stops = c.depth() == 6 || c.cardinality() < 50 || c.radius() <= 0.5;
+        assert_eq!(c.is_leaf(), stops, "{c} at depth {}.", c.depth());
+    }
+
+    // Partition down to depth 3, and further only the large clusters whose
+    // radii are not too small.
+    let criteria = PartitionCriteria::new(true)
+        .with_max_depth(3)
+        .or(PartitionCriteria::new(true)
+            .with_fn(|c| c.cardinality() > 200)
+            .and(PartitionCriteria::new(true).with_min_radius(0.5)));
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+    assert!(tree.depth() > 3);
+    for c in tree.root().subtree() {
+        let partitions = !c.is_singleton() && (c.depth() < 3 || (c.cardinality() > 200 && c.radius() > 0.5));
+        assert_eq!(c.is_leaf(), !partitions, "{c} at depth {}.", c.depth());
+    }
+}
+
+#[test_case(2; "binary")]
+#[test_case(4; "fan_out_4")]
+fn streaming(fan_out: usize) -> Result<(), String> {
+    let (cardinality, dimensionality) = (5_000, 10);
+    let data = utils::gen_dataset(cardinality, dimensionality, 42, utils::euclidean);
+    let queries = utils::gen_dataset(10, dimensionality, 0, utils::euclidean).data_owned();
+    let source = || Ok(data.data().iter().cloned().map(Ok));
+
+    // The sample of 500 is split until each leaf is expected to have at most
+    // 600 instances, i.e. 60 of the sample.
+    let builder = StreamingBuilder::new("streaming".to_string(), FnMetric::new(utils::euclidean))
+        .with_sample_size(500)
+        .with_max_partition_cardinality(600)
+        .with_seed(42);
+    let criteria = PartitionCriteria::default().with_fan_out(fan_out);
+    let tmp_dir = TempDir::new("streaming").map_err(|e| e.to_string())?;
+    let path = tmp_dir.path().join("leaves");
+    let tree = builder.build(source, &criteria, &path)?;
+    assert_eq!(std::fs::read_dir(tmp_dir.path()).map_err(|e| e.to_string())?.count(), 1);
+
+    // Every instance is in the tree once, and within the radius of each of
+    // its ancestors.
+    assert_eq!(tree.cardinality(), cardinality);
+    let mut original = (0..cardinality)
+        .map(|i| tree.data().original_index(i))
+        .collect::<Vec<_>>();
+    for (i, &j) in original.iter().enumerate() {
+        assert_eq!(*tree.data().get(i), data[j]);
+    }
+    original.sort_unstable();
+    assert_eq!(original, (0..cardinality).collect::<Vec<_>>());
+    assert_eq!(tree.data().num_blocks(), tree.leaves().len());
+
+    for c in tree.root().subtree() {
+        for i in c.indices() {
+            assert!(tree.data().one_to_one(c.arg_center(), i) <= c.radius());
+        }
+        if let Some(children) = c.children() {
+            assert!((2..=fan_out).contains(&children.len()));
+            assert_eq!(children[0].offset(), c.offset());
+            assert_eq!(children.iter().map(|c| c.cardinality()).sum::<usize>(), c.cardinality());
+            for child in children {
+                assert_eq!(child.depth(), c.depth() + 1);
+            }
+        }
+    }
+    assert!(tree.depth() > 3);
+
+    // Searches are exact.
+    for query in &queries {
+        let linear_hits = knn::Algorithm::Linear.search(&tree, query, 10);
+        for algorithm in knn::Algorithm::variants() {
+            let hits = algorithm.search(&tree, query, 10);
+            assert_approx_eq!(f32, utils::compute_recall(hits, linear_hits.clone()), 1.0);
+        }
+        let hits = rnn::Algorithm::Clustered.search(query, 0.5, &tree);
+        let linear_hits = rnn::Algorithm::Linear.search(query, 0.5, &tree);
+        assert_eq!(hits.len(), linear_hits.len());
+    }
+
+    // The same seed builds the same tree.
+    let other = builder.build(source, &criteria, &tmp_dir.path().join("other"))?;
+    assert_eq!(tree.root().subtree(), other.root().subtree());
+    assert_eq!(tree.data().permuted_indices(), other.data().permuted_indices());
+
+    // The source must yield the same instances in both passes.
+    let mut passes = 0;
+    let shrinking = || {
+        passes += 1;
+        Ok(data.data().iter().take(cardinality / passes).cloned().map(Ok))
+    };
+    let err = builder.build(shrinking, &criteria, &path).unwrap_err();
+    assert_eq!(
+        err,
+        "The source yielded 5000 instances in the first pass but 2500 in the second"
+    );
+    let empty = || Ok(core::iter::empty());
+    assert!(builder.build(empty, &criteria, &path).is_err());
+
+    Ok(())
+}
+
+#[test_case(PartitionStrategy::MaxSeparation; "max_separation")]
+#[test_case(PartitionStrategy::Balanced; "balanced")]
+#[test_case(PartitionStrategy::MedianSplit; "median_split")]
+fn duplicate_buckets(strategy: PartitionStrategy) {
+    // 500 copies of each of two instances, and 100 distinct ones.
+    let mut instances = utils::gen_dataset(100, 10, 42, utils::euclidean).data().to_vec();
+    instances.extend(core::iter::repeat(vec![0.5; 10]).take(500));
+    instances.extend(core::iter::repeat(vec![-0.5; 10]).take(500));
+    let data = VecDataset::new("duplicates".to_string(), instances, utils::euclidean, false);
+
+    let criteria = PartitionCriteria::<f32>::default().with_strategy(strategy);
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+
+    let buckets = tree
+        .root()
+        .subtree()
+        .into_iter()
+        .filter(|c| c.is_duplicate_bucket())
+        .collect::<Vec<_>>();
+    assert!(!buckets.is_empty());
+    assert!(buckets.iter().all(|c| c.is_leaf()));
+    // Splitting the copies would make a leaf of each of them.
+    assert!(tree.leaves().len() < 500, "{}", tree.leaves().len());
+
+    let query = vec![0.5; 10];
+    for &algo in knn::Algorithm::variants() {
+        let (hits, stats) = algo.search_with_stats(&tree, &query, 10);
+        assert_eq!(hits.len(), 10, "{}", algo.name());
+        assert!(hits.iter().all(|&(_, d)| d <= f32::EPSILON), "{}", algo.name());
+        assert!(stats.distance_computations < 500, "{}: {stats:?}", algo.name());
+    }
+}
+
+#[test]
+fn progress() {
+    let reports = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let sink = std::sync::Arc::clone(&reports);
+    let criteria = PartitionCriteria::default()
+        .with_sequential_cutoff(100)
+        .with_progress(move |p: &BuildProgress| sink.lock().unwrap().push(*p));
+
+    let data = utils::gen_dataset(1000, 10, 42, utils::euclidean);
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len(), tree.root().subtree().len());
+    assert!(reports.iter().all(|p| p.cardinality == 1000));
+    assert!(reports.iter().all(|p| p.instances_assigned <= 1000));
+
+    let last = reports.iter().max_by_key(|p| p.clusters_built).unwrap();
+    assert_eq!(last.instances_assigned, 1000);
+    assert_approx_eq!(f64, last.fraction(), 1.0);
+    assert_eq!(last.eta(), Some(core::time::Duration::ZERO));
+}
+
+#[test]
+fn cancellation() {
+    let data = utils::gen_dataset(1000, 10, 42, utils::euclidean);
+
+    let token = CancellationToken::new();
+    token.cancel();
+    let criteria = PartitionCriteria::default().with_cancellation(token);
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data.clone(), Some(42)).partition(&criteria, Some(42));
+    assert!(tree.root().is_leaf());
+    assert!(matches!(
+        Tree::<_, _, _, UniBall<_>>::try_build(data.clone(), &criteria, Some(42)),
+        Err(Error::Cancelled)
+    ));
+    let criteria = PartitionCriteria::default().with_cancellation(CancellationToken::new());
+    assert!(Tree::<_, _, _, UniBall<_>>::try_build(data.clone(), &criteria, Some(42)).is_ok());
+
+    // Cancel the build from the progress reporter once a few clusters are done.
+    let token = CancellationToken::new();
+    let canceller = token.clone();
+    let criteria = PartitionCriteria::default()
+        .with_sequential_cutoff(usize::MAX)
+        .with_cancellation(token)
+        .with_progress(move |p: &BuildProgress| {
+            if p.clusters_built >= 10 {
+                canceller.cancel();
+            }
+        });
+    let partial = Tree::<_, _, _, UniBall<_>>::new(data.clone(), Some(42)).partition(&criteria, Some(42));
+    let full = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&PartitionCriteria::default(), Some(42));
+    assert!(!partial.root().is_leaf());
+    assert!(partial.root().subtree().len() < full.root().subtree().len());
+
+    let query = vec![0.; 10];
+    let hits = knn::Algorithm::GreedySieve.search(&partial, &query, 10);
+    let linear = knn::Algorithm::Linear.search(&partial, &query, 10);
+    assert_approx_eq!(f32, utils::compute_recall(hits, linear), 1.0);
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn thread_pool() {
+    let data = utils::gen_dataset(2000, 10, 42, utils::euclidean);
+    let shape = |tree: &Tree<_, _, _, UniBall<f32>>| {
+        tree.root()
+            .subtree()
+            .into_iter()
+            .map(|c| (c.offset(), c.cardinality(), c.arg_center()))
+            .collect::<Vec<_>>()
+    };
+
+    // Every distance of the build, including those of the root, is computed
+    // on the given pool.
+    let pool = abd_clam::ThreadPoolBuilder::new()
+        .num_threads(2)
+        .thread_name(|i| format!("clam-build-{i}"))
+        .build()
+        .unwrap();
+    let criteria = PartitionCriteria::default()
+        .with_sequential_cutoff(0)
+        .with_thread_pool(std::sync::Arc::new(pool));
+    let recorded = VecDataset::new("recorded".to_string(), data.data().to_vec(), recorded_euclidean, false);
+    let pooled = Tree::<_, _, _, UniBall<_>>::build(recorded, &criteria, Some(42));
+    let threads = BUILD_THREADS.lock().unwrap();
+    assert!(!threads.is_empty());
+    assert!(
+        threads.iter().all(|name| name.starts_with("clam-build-")),
+        "{threads:?}"
+    );
+
+    let global = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&PartitionCriteria::default(), Some(42));
+    assert_eq!(shape(&pooled), shape(&global));
+
+    let criteria = PartitionCriteria::<f32>::default().with_max_threads(3).unwrap();
+    assert_eq!(
+        criteria.thread_pool().map(abd_clam::ThreadPool::current_num_threads),
+        Some(3)
+    );
+}
+
+/// The names of the threads on which `recorded_euclidean` has been called.
+static BUILD_THREADS: std::sync::Mutex<BTreeSet<String>> = std::sync::Mutex::new(BTreeSet::new());
+
+/// The euclidean distance, which records the thread it is computed on.
+fn recorded_euclidean(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    let thread = std::thread::current();
+    BUILD_THREADS
+        .lock()
+        .unwrap()
+        .insert(thread.name().unwrap_or_default().to_string());
+    utils::euclidean(x, y)
+}
+
+#[test]
+fn estimate_memory() {
+    let data = utils::gen_dataset(1000, 10, 42, utils::euclidean);
+    let criteria = PartitionCriteria::default();
+    let estimate = Tree::<_, _, _, UniBall<_>>::estimate_memory(&data, &criteria);
+
+    assert!(estimate.instances >= 1000 * 10 * core::mem::size_of::<f32>());
+    assert_eq!(estimate.num_clusters, 1999);
+    assert_eq!(estimate.permutation, 1000 * core::mem::size_of::<usize>());
+    assert!(estimate.peak() > estimate.resident());
+
+    // A larger fan-out holds more distances while partitioning, but each
+    // `Cluster` is still counted once in the lists of children.
+    let k_ary = Tree::<_, _, _, UniBall<_>>::estimate_memory(&data, &PartitionCriteria::default().with_fan_out(4));
+    assert_eq!(k_ary.clusters, estimate.clusters);
+    assert!(k_ary.build > estimate.build);
+
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(42)).partition(&criteria, Some(42));
+    assert!(tree.root().subtree().len() <= estimate.num_clusters);
+}
@@ -24,7 +24,7 @@ fn tiny() {
 
     assert_eq!(format!("{root}"), "0-4");
 
-    let Some([left, right]) = root.children() else {
+    let Some(&[left, right]) = root.children().as_deref() else {
         unreachable!("The root cluster has children.")
     };
     assert_eq!(format!("{left}"), "0-2");
@@ -100,3 +100,44 @@ fn serialization() {
     assert_eq!(original.radius(), deserialized.radius());
     assert_eq!(original.children(), deserialized.children());
 }
+
+#[test]
+fn binary_children_without_strategy() {
+    let mut data = utils::gen_dataset(100, 3, 42, utils::euclidean::<f32, f32>);
+    let root = UniBall::new_root(&data, Some(42)).partition(&mut data, &PartitionCriteria::default(), Some(42));
+
+    // Rewrite the children of every `Cluster` as they were saved before k-ary
+    // splits and partition strategies.
+    fn to_binary(value: &mut serde_json::Value) {
+        let Some(children) = value["children"].as_object_mut() else {
+            return;
+        };
+        children.remove("strategy");
+        let clusters = children.remove("clusters").unwrap();
+        let poles = children.remove("arg_poles").unwrap();
+        let [mut left, mut right] = <[serde_json::Value; 2]>::try_from(clusters.as_array().unwrap().clone()).unwrap();
+        to_binary(&mut left);
+        to_binary(&mut right);
+        children.insert("left".to_string(), left);
+        children.insert("right".to_string(), right);
+        children.insert("arg_l".to_string(), poles[0].clone());
+        children.insert("arg_r".to_string(), poles[1].clone());
+    }
+
+    let mut value = serde_json::to_value(&root).unwrap();
+    to_binary(&mut value);
+    assert!(value["children"]["left"].is_object());
+
+    let loaded: UniBall<f32> = serde_json::from_value(value).unwrap();
+    assert_eq!(
+        root.subtree()
+            .into_iter()
+            .map(|c| (c.name(), c.arg_poles(), c.strategy()))
+            .collect::<Vec<_>>(),
+        loaded
+            .subtree()
+            .into_iter()
+            .map(|c| (c.name(), c.arg_poles(), c.strategy()))
+            .collect::<Vec<_>>()
+    );
+}
@@ -0,0 +1,55 @@
+//! Tests for the verified search mode.
+
+#![cfg(feature = "verify")]
+
+use abd_clam::{knn, rnn, Cakes, FnMetric, PartitionCriteria, VecDataset};
+
+mod utils;
+
+/// The cube of the euclidean distance, which does not obey the triangle
+/// inequality.
+#[allow(clippy::ptr_arg)]
+fn euclidean_cubed(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    utils::euclidean::<f32, f32>(x, y).powi(3)
+}
+
+#[test]
+fn verified() {
+    let data = utils::gen_dataset(1000, 10, 42, utils::euclidean);
+    let queries = utils::gen_dataset(10, 10, 0, utils::euclidean);
+    let cakes = Cakes::new(data, Some(42), &PartitionCriteria::default());
+    let tree = cakes.trees()[0];
+
+    for i in 0..10 {
+        let query = &queries[i];
+        for &algo in knn::Algorithm::variants() {
+            let hits = algo.search_verified(tree, query, 10).unwrap();
+            assert_eq!(hits.len(), 10);
+        }
+        for algo in [rnn::Algorithm::Linear, rnn::Algorithm::Clustered] {
+            algo.search_verified(query, 0.5, tree).unwrap();
+        }
+    }
+}
+
+#[test]
+fn mislabeled_metric() {
+    // The metric is declared to obey the triangle inequality, so the tree is
+    // pruned as if it did.
+    let data = utils::gen_dataset(1000, 10, 42, utils::euclidean);
+    let data = VecDataset::from_metric("cubed".to_string(), data.data_owned(), FnMetric::new(euclidean_cubed));
+    let queries = utils::gen_dataset(10, 10, 0, utils::euclidean);
+    let cakes = Cakes::new(data, Some(42), &PartitionCriteria::default());
+    let tree = cakes.trees()[0];
+
+    let errors = (0..10)
+        .filter_map(|i| knn::Algorithm::GreedySieve.search_verified(tree, &queries[i], 10).err())
+        .collect::<Vec<_>>();
+    assert!(!errors.is_empty());
+    assert!(errors.iter().all(|e| e.starts_with("GreedySieve")), "{errors:?}");
+
+    // Linear search is always correct.
+    for i in 0..10 {
+        knn::Algorithm::Linear.search_verified(tree, &queries[i], 10).unwrap();
+    }
+}
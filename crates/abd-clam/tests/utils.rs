@@ -4,7 +4,7 @@
 
 use core::cmp::Ordering;
 
-use abd_clam::{Instance, VecDataset};
+use abd_clam::{Cakes, Dataset, Instance, PartitionCriterion, VecDataset};
 use distances::{
     number::{Float, UInt},
     Number,
@@ -80,7 +80,13 @@ pub fn compute_recall<T: Number>(mut hits: Vec<(usize, T)>, mut linear_hits: Vec
 
     let mut num_common = 0;
     while let (Some(&hit), Some(&linear_hit)) = (hits.peek(), linear_hits.peek()) {
-        if (hit - linear_hit).abs() < T::epsilon() {
+        // Unsigned distances cannot be subtracted in either order.
+        let diff = if hit > linear_hit {
+            hit - linear_hit
+        } else {
+            linear_hit - hit
+        };
+        if diff <= T::epsilon() {
             num_common += 1;
             hits.next();
             linear_hits.next();
@@ -92,3 +98,37 @@ pub fn compute_recall<T: Number>(mut hits: Vec<(usize, T)>, mut linear_hits: Vec
     }
     num_common.as_f32() / num_hits.as_f32()
 }
+
+/// Splits a dataset into `num_shards` shards of nearly equal cardinality, or
+/// returns it whole if `num_shards` is one.
+pub fn split<I: Instance, U: Number, D: Dataset<I, U>>(data: D, num_shards: usize) -> Vec<D> {
+    if num_shards == 1 {
+        vec![data]
+    } else {
+        let max_cardinality = data.cardinality().div_ceil(num_shards);
+        data.make_shards(max_cardinality)
+    }
+}
+
+/// Builds a `Cakes` with a single shard if `num_shards` is one, or with the
+/// dataset split into `num_shards` shards otherwise.
+pub fn build_cakes<I: Instance, U: Number, D: Dataset<I, U>, P: PartitionCriterion<U>>(
+    data: D,
+    num_shards: usize,
+    criteria: &P,
+) -> Cakes<I, U, D> {
+    if num_shards == 1 {
+        Cakes::new(data, Some(42), criteria)
+    } else {
+        Cakes::new_randomly_sharded(split(data, num_shards), Some(42), criteria)
+    }
+}
+
+/// Builds a `Cakes` with a single shard and one with four shards over the same
+/// dataset, for tests which check that both give the same results.
+pub fn single_and_sharded<I: Instance, U: Number, D: Dataset<I, U> + Clone, P: PartitionCriterion<U>>(
+    data: D,
+    criteria: &P,
+) -> [Cakes<I, U, D>; 2] {
+    [build_cakes(data.clone(), 1, criteria), build_cakes(data, 4, criteria)]
+}
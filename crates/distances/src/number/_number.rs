@@ -105,6 +105,22 @@ pub trait Number:
 
     /// Returns a random `Number`.
     fn next_random<R: rand::Rng>(rng: &mut R) -> Self;
+
+    /// Returns the slice as a slice of `f32`s if `Self` is `f32`.
+    ///
+    /// This lets generic code dispatch to the kernels in `simd`.
+    #[must_use]
+    fn as_f32_slice(_values: &[Self]) -> Option<&[f32]> {
+        None
+    }
+
+    /// Returns the slice as a slice of `f64`s if `Self` is `f64`.
+    ///
+    /// This lets generic code dispatch to the kernels in `simd`.
+    #[must_use]
+    fn as_f64_slice(_values: &[Self]) -> Option<&[f64]> {
+        None
+    }
 }
 
 impl Number for f32 {
@@ -193,6 +209,10 @@ impl Number for f32 {
     fn next_random<R: rand::Rng>(rng: &mut R) -> Self {
         rng.gen()
     }
+
+    fn as_f32_slice(values: &[Self]) -> Option<&[f32]> {
+        Some(values)
+    }
 }
 
 impl Number for f64 {
@@ -281,6 +301,10 @@ impl Number for f64 {
     fn next_random<R: rand::Rng>(rng: &mut R) -> Self {
         rng.gen()
     }
+
+    fn as_f64_slice(values: &[Self]) -> Option<&[f64]> {
+        Some(values)
+    }
 }
 
 /// A macro to implement the `Number` trait for primitive types.
@@ -0,0 +1,294 @@
+//! Explicit SIMD kernels for specific architectures.
+//!
+//! The kernels are selected at runtime based on the features supported by the
+//! CPU: AVX2 with FMA on `x86_64` and NEON on `aarch64`. Each function returns
+//! `None` if no kernel is available, in which case the caller should fall back
+//! to the portable implementation.
+
+/// Computes the squared euclidean distance between two `f32` vectors.
+pub fn squared_euclidean_f32(a: &[f32], b: &[f32]) -> Option<f32> {
+    assert_eq!(a.len(), b.len());
+
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+        // SAFETY: The required CPU features were detected and the slices have
+        // equal lengths.
+        return Some(unsafe { x86::squared_euclidean_f32(a, b) });
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        // SAFETY: The required CPU features were detected and the slices have
+        // equal lengths.
+        return Some(unsafe { neon::squared_euclidean_f32(a, b) });
+    }
+
+    None
+}
+
+/// Computes the squared euclidean distance between two `f64` vectors.
+pub fn squared_euclidean_f64(a: &[f64], b: &[f64]) -> Option<f64> {
+    assert_eq!(a.len(), b.len());
+
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+        // SAFETY: See `squared_euclidean_f32`.
+        return Some(unsafe { x86::squared_euclidean_f64(a, b) });
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        // SAFETY: See `squared_euclidean_f32`.
+        return Some(unsafe { neon::squared_euclidean_f64(a, b) });
+    }
+
+    None
+}
+
+/// Computes the accumulators `[x.x, y.y, x.y]` for the cosine distance between
+/// two `f32` vectors.
+pub fn cosine_acc_f32(a: &[f32], b: &[f32]) -> Option<[f32; 3]> {
+    assert_eq!(a.len(), b.len());
+
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+        // SAFETY: See `squared_euclidean_f32`.
+        return Some(unsafe { x86::cosine_acc_f32(a, b) });
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        // SAFETY: See `squared_euclidean_f32`.
+        return Some(unsafe { neon::cosine_acc_f32(a, b) });
+    }
+
+    None
+}
+
+/// Computes the accumulators `[x.x, y.y, x.y]` for the cosine distance between
+/// two `f64` vectors.
+pub fn cosine_acc_f64(a: &[f64], b: &[f64]) -> Option<[f64; 3]> {
+    assert_eq!(a.len(), b.len());
+
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+        // SAFETY: See `squared_euclidean_f32`.
+        return Some(unsafe { x86::cosine_acc_f64(a, b) });
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        // SAFETY: See `squared_euclidean_f32`.
+        return Some(unsafe { neon::cosine_acc_f64(a, b) });
+    }
+
+    None
+}
+
+/// Kernels using AVX2 and FMA.
+///
+/// # Safety
+///
+/// Callers must ensure that the CPU supports AVX2 and FMA and that the slices
+/// have equal lengths.
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use core::arch::x86_64::{
+        __m256, __m256d, _mm256_fmadd_pd, _mm256_fmadd_ps, _mm256_loadu_pd, _mm256_loadu_ps, _mm256_setzero_pd,
+        _mm256_setzero_ps, _mm256_storeu_pd, _mm256_storeu_ps, _mm256_sub_pd, _mm256_sub_ps,
+    };
+
+    /// The number of `f32` lanes in a 256-bit register.
+    const F32_LANES: usize = 8;
+
+    /// The number of `f64` lanes in a 256-bit register.
+    const F64_LANES: usize = 4;
+
+    /// Sums the lanes of a 256-bit register of `f32`s.
+    #[target_feature(enable = "avx2")]
+    unsafe fn sum_ps(v: __m256) -> f32 {
+        let mut lanes = [0.0; F32_LANES];
+        _mm256_storeu_ps(lanes.as_mut_ptr(), v);
+        lanes.iter().sum()
+    }
+
+    /// Sums the lanes of a 256-bit register of `f64`s.
+    #[target_feature(enable = "avx2")]
+    unsafe fn sum_pd(v: __m256d) -> f64 {
+        let mut lanes = [0.0; F64_LANES];
+        _mm256_storeu_pd(lanes.as_mut_ptr(), v);
+        lanes.iter().sum()
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn squared_euclidean_f32(a: &[f32], b: &[f32]) -> f32 {
+        let chunks = a.len() / F32_LANES;
+        let mut acc = _mm256_setzero_ps();
+        for i in 0..chunks {
+            let x = _mm256_loadu_ps(a.as_ptr().add(i * F32_LANES));
+            let y = _mm256_loadu_ps(b.as_ptr().add(i * F32_LANES));
+            let diff = _mm256_sub_ps(x, y);
+            acc = _mm256_fmadd_ps(diff, diff, acc);
+        }
+
+        let tail = chunks * F32_LANES;
+        a[tail..].iter().zip(&b[tail..]).fold(sum_ps(acc), |sum, (&x, &y)| {
+            let diff = x - y;
+            diff.mul_add(diff, sum)
+        })
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn squared_euclidean_f64(a: &[f64], b: &[f64]) -> f64 {
+        let chunks = a.len() / F64_LANES;
+        let mut acc = _mm256_setzero_pd();
+        for i in 0..chunks {
+            let x = _mm256_loadu_pd(a.as_ptr().add(i * F64_LANES));
+            let y = _mm256_loadu_pd(b.as_ptr().add(i * F64_LANES));
+            let diff = _mm256_sub_pd(x, y);
+            acc = _mm256_fmadd_pd(diff, diff, acc);
+        }
+
+        let tail = chunks * F64_LANES;
+        a[tail..].iter().zip(&b[tail..]).fold(sum_pd(acc), |sum, (&x, &y)| {
+            let diff = x - y;
+            diff.mul_add(diff, sum)
+        })
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn cosine_acc_f32(a: &[f32], b: &[f32]) -> [f32; 3] {
+        let chunks = a.len() / F32_LANES;
+        let [mut xx, mut yy, mut xy] = [_mm256_setzero_ps(); 3];
+        for i in 0..chunks {
+            let x = _mm256_loadu_ps(a.as_ptr().add(i * F32_LANES));
+            let y = _mm256_loadu_ps(b.as_ptr().add(i * F32_LANES));
+            xx = _mm256_fmadd_ps(x, x, xx);
+            yy = _mm256_fmadd_ps(y, y, yy);
+            xy = _mm256_fmadd_ps(x, y, xy);
+        }
+
+        let tail = chunks * F32_LANES;
+        a[tail..]
+            .iter()
+            .zip(&b[tail..])
+            .fold([sum_ps(xx), sum_ps(yy), sum_ps(xy)], |[xx, yy, xy], (&x, &y)| {
+                [x.mul_add(x, xx), y.mul_add(y, yy), x.mul_add(y, xy)]
+            })
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn cosine_acc_f64(a: &[f64], b: &[f64]) -> [f64; 3] {
+        let chunks = a.len() / F64_LANES;
+        let [mut xx, mut yy, mut xy] = [_mm256_setzero_pd(); 3];
+        for i in 0..chunks {
+            let x = _mm256_loadu_pd(a.as_ptr().add(i * F64_LANES));
+            let y = _mm256_loadu_pd(b.as_ptr().add(i * F64_LANES));
+            xx = _mm256_fmadd_pd(x, x, xx);
+            yy = _mm256_fmadd_pd(y, y, yy);
+            xy = _mm256_fmadd_pd(x, y, xy);
+        }
+
+        let tail = chunks * F64_LANES;
+        a[tail..]
+            .iter()
+            .zip(&b[tail..])
+            .fold([sum_pd(xx), sum_pd(yy), sum_pd(xy)], |[xx, yy, xy], (&x, &y)| {
+                [x.mul_add(x, xx), y.mul_add(y, yy), x.mul_add(y, xy)]
+            })
+    }
+}
+
+/// Kernels using NEON.
+///
+/// # Safety
+///
+/// Callers must ensure that the CPU supports NEON and that the slices have
+/// equal lengths.
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use core::arch::aarch64::{
+        vaddvq_f32, vaddvq_f64, vdupq_n_f32, vdupq_n_f64, vfmaq_f32, vfmaq_f64, vld1q_f32, vld1q_f64, vsubq_f32,
+        vsubq_f64,
+    };
+
+    /// The number of `f32` lanes in a 128-bit register.
+    const F32_LANES: usize = 4;
+
+    /// The number of `f64` lanes in a 128-bit register.
+    const F64_LANES: usize = 2;
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn squared_euclidean_f32(a: &[f32], b: &[f32]) -> f32 {
+        let chunks = a.len() / F32_LANES;
+        let mut acc = vdupq_n_f32(0.0);
+        for i in 0..chunks {
+            let x = vld1q_f32(a.as_ptr().add(i * F32_LANES));
+            let y = vld1q_f32(b.as_ptr().add(i * F32_LANES));
+            let diff = vsubq_f32(x, y);
+            acc = vfmaq_f32(acc, diff, diff);
+        }
+
+        let tail = chunks * F32_LANES;
+        a[tail..].iter().zip(&b[tail..]).fold(vaddvq_f32(acc), |sum, (&x, &y)| {
+            let diff = x - y;
+            diff.mul_add(diff, sum)
+        })
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn squared_euclidean_f64(a: &[f64], b: &[f64]) -> f64 {
+        let chunks = a.len() / F64_LANES;
+        let mut acc = vdupq_n_f64(0.0);
+        for i in 0..chunks {
+            let x = vld1q_f64(a.as_ptr().add(i * F64_LANES));
+            let y = vld1q_f64(b.as_ptr().add(i * F64_LANES));
+            let diff = vsubq_f64(x, y);
+            acc = vfmaq_f64(acc, diff, diff);
+        }
+
+        let tail = chunks * F64_LANES;
+        a[tail..].iter().zip(&b[tail..]).fold(vaddvq_f64(acc), |sum, (&x, &y)| {
+            let diff = x - y;
+            diff.mul_add(diff, sum)
+        })
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn cosine_acc_f32(a: &[f32], b: &[f32]) -> [f32; 3] {
+        let chunks = a.len() / F32_LANES;
+        let [mut xx, mut yy, mut xy] = [vdupq_n_f32(0.0); 3];
+        for i in 0..chunks {
+            let x = vld1q_f32(a.as_ptr().add(i * F32_LANES));
+            let y = vld1q_f32(b.as_ptr().add(i * F32_LANES));
+            xx = vfmaq_f32(xx, x, x);
+            yy = vfmaq_f32(yy, y, y);
+            xy = vfmaq_f32(xy, x, y);
+        }
+
+        let tail = chunks * F32_LANES;
+        a[tail..].iter().zip(&b[tail..]).fold(
+            [vaddvq_f32(xx), vaddvq_f32(yy), vaddvq_f32(xy)],
+            |[xx, yy, xy], (&x, &y)| [x.mul_add(x, xx), y.mul_add(y, yy), x.mul_add(y, xy)],
+        )
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn cosine_acc_f64(a: &[f64], b: &[f64]) -> [f64; 3] {
+        let chunks = a.len() / F64_LANES;
+        let [mut xx, mut yy, mut xy] = [vdupq_n_f64(0.0); 3];
+        for i in 0..chunks {
+            let x = vld1q_f64(a.as_ptr().add(i * F64_LANES));
+            let y = vld1q_f64(b.as_ptr().add(i * F64_LANES));
+            xx = vfmaq_f64(xx, x, x);
+            yy = vfmaq_f64(yy, y, y);
+            xy = vfmaq_f64(xy, x, y);
+        }
+
+        let tail = chunks * F64_LANES;
+        a[tail..].iter().zip(&b[tail..]).fold(
+            [vaddvq_f64(xx), vaddvq_f64(yy), vaddvq_f64(xy)],
+            |[xx, yy, xy], (&x, &y)| [x.mul_add(x, xx), y.mul_add(y, yy), x.mul_add(y, xy)],
+        )
+    }
+}
@@ -1,6 +1,12 @@
 //! Provides simd-accelerated euclidean distance functions for vectors.
+//!
+//! On `x86_64` CPUs with AVX2 and FMA, and on `aarch64` CPUs with NEON, these
+//! functions use explicit SIMD kernels selected at runtime. Otherwise, they
+//! fall back to portable implementations which the compiler auto-vectorizes.
 #![allow(missing_docs, clippy::missing_docs_in_private_items, clippy::must_use_candidate)]
 
+use crate::number::Float;
+
 /// Computes the euclidean distance between two vectors.
 #[must_use]
 pub fn euclidean_f32(a: &[f32], b: &[f32]) -> f32 {
@@ -40,6 +46,8 @@ pub fn cosine_f64(a: &[f64], b: &[f64]) -> f64 {
 #[macro_use]
 mod macros;
 
+mod arch;
+
 mod f32x16;
 mod f32x4;
 mod f32x8;
@@ -100,12 +108,14 @@ pub(crate) fn vector_euclidean<T: Vectorized>(a: T, b: T) -> T::Output {
 impl Vectorized for &[f32] {
     type Output = f32;
     fn squared_euclidean(self, other: Self) -> Self::Output {
-        if self.len() >= 64 {
-            // TODO will this fail on 128-bit?
-            F32x8::squared_euclidean(self, other)
-        } else {
-            F32x4::squared_euclidean(self, other)
-        }
+        arch::squared_euclidean_f32(self, other).unwrap_or_else(|| {
+            if self.len() >= 64 {
+                // TODO will this fail on 128-bit?
+                F32x8::squared_euclidean(self, other)
+            } else {
+                F32x4::squared_euclidean(self, other)
+            }
+        })
     }
 
     fn euclidean(self, other: Self) -> Self::Output {
@@ -113,22 +123,23 @@ impl Vectorized for &[f32] {
     }
 
     fn cosine(self, other: Self) -> Self::Output {
-        if self.len() >= 64 {
-            F32x8::cosine(self, other)
-        } else {
-            F32x4::cosine(self, other)
-        }
+        arch::cosine_acc_f32(self, other).map_or_else(
+            || {
+                if self.len() >= 64 {
+                    F32x8::cosine(self, other)
+                } else {
+                    F32x4::cosine(self, other)
+                }
+            },
+            cosine_from_acc,
+        )
     }
 }
 
 impl Vectorized for &Vec<f32> {
     type Output = f32;
     fn squared_euclidean(self, other: Self) -> Self::Output {
-        if self.len() >= 64 {
-            F32x8::squared_euclidean(self, other)
-        } else {
-            F32x4::squared_euclidean(self, other)
-        }
+        Vectorized::squared_euclidean(self.as_slice(), other.as_slice())
     }
 
     fn euclidean(self, other: Self) -> Self::Output {
@@ -136,22 +147,20 @@ impl Vectorized for &Vec<f32> {
     }
 
     fn cosine(self, other: Self) -> Self::Output {
-        if self.len() >= 64 {
-            F32x8::cosine(self, other)
-        } else {
-            F32x4::cosine(self, other)
-        }
+        Vectorized::cosine(self.as_slice(), other.as_slice())
     }
 }
 
 impl Vectorized for &[f64] {
     type Output = f64;
     fn squared_euclidean(self, other: Self) -> Self::Output {
-        if self.len() >= 16 {
-            F64x4::squared_euclidean(self, other)
-        } else {
-            F64x2::squared_euclidean(self, other)
-        }
+        arch::squared_euclidean_f64(self, other).unwrap_or_else(|| {
+            if self.len() >= 16 {
+                F64x4::squared_euclidean(self, other)
+            } else {
+                F64x2::squared_euclidean(self, other)
+            }
+        })
     }
 
     fn euclidean(self, other: Self) -> Self::Output {
@@ -159,22 +168,23 @@ impl Vectorized for &[f64] {
     }
 
     fn cosine(self, other: Self) -> Self::Output {
-        if self.len() >= 16 {
-            F64x4::cosine(self, other)
-        } else {
-            F64x2::cosine(self, other)
-        }
+        arch::cosine_acc_f64(self, other).map_or_else(
+            || {
+                if self.len() >= 16 {
+                    F64x4::cosine(self, other)
+                } else {
+                    F64x2::cosine(self, other)
+                }
+            },
+            cosine_from_acc,
+        )
     }
 }
 
 impl Vectorized for &Vec<f64> {
     type Output = f64;
     fn squared_euclidean(self, other: Self) -> Self::Output {
-        if self.len() >= 16 {
-            F64x4::squared_euclidean(self, other)
-        } else {
-            F64x2::squared_euclidean(self, other)
-        }
+        Vectorized::squared_euclidean(self.as_slice(), other.as_slice())
     }
 
     fn euclidean(self, other: Self) -> Self::Output {
@@ -182,10 +192,21 @@ impl Vectorized for &Vec<f64> {
     }
 
     fn cosine(self, other: Self) -> Self::Output {
-        if self.len() >= 16 {
-            F64x4::cosine(self, other)
+        Vectorized::cosine(self.as_slice(), other.as_slice())
+    }
+}
+
+/// Computes the cosine distance from the accumulators `[x.x, y.y, x.y]`.
+fn cosine_from_acc<F: Float>([xx, yy, xy]: [F; 3]) -> F {
+    let eps = F::epsilon();
+    if xx < eps || yy < eps || xy < eps {
+        F::one()
+    } else {
+        let d = F::one() - xy / (xx * yy).sqrt();
+        if d < eps {
+            F::zero()
         } else {
-            F64x2::cosine(self, other)
+            d
         }
     }
 }
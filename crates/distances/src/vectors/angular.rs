@@ -38,10 +38,10 @@ use crate::{
 ///
 /// * [Cosine similarity](https://en.wikipedia.org/wiki/Cosine_similarity)
 pub fn cosine<T: Number, U: Float>(x: &[T], y: &[T]) -> U {
-    let [xx, yy, xy] = x.iter().zip(y.iter()).fold([T::zero(); 3], |[xx, yy, xy], (&a, &b)| {
+    let [xx, yy, xy] = x.iter().zip(y.iter()).fold([U::zero(); 3], |[xx, yy, xy], (&a, &b)| {
+        let (a, b) = (U::from(a), U::from(b));
         [a.mul_add(a, xx), b.mul_add(b, yy), a.mul_add(b, xy)]
     });
-    let [xx, yy, xy] = [U::from(xx), U::from(yy), U::from(xy)];
 
     if xx < U::epsilon() || yy < U::epsilon() || xy < U::epsilon() {
         U::one()
@@ -157,13 +157,14 @@ pub fn canberra<T: Number, U: Float>(x: &[T], y: &[T]) -> U {
 ///
 /// * [Bray-Curtis Distance](https://docs.scipy.org/doc/scipy/reference/generated/scipy.spatial.distance.braycurtis.html#scipy.spatial.distance.braycurtis)
 pub fn bray_curtis<T: Number, U: Float>(x: &[T], y: &[T]) -> U {
-    let [numerator, denominator] = x.iter().zip(y.iter()).fold([T::zero(); 2], |[n, d], (&a, &b)| {
+    let [numerator, denominator] = x.iter().zip(y.iter()).fold([U::zero(); 2], |[n, d], (&a, &b)| {
+        let (a, b) = (U::from(a), U::from(b));
         [n + a.abs_diff(b), d + (a + b).abs()]
     });
 
     if denominator <= numerator {
         U::zero()
     } else {
-        U::from(numerator) / U::from(denominator)
+        numerator / denominator
     }
 }
@@ -4,7 +4,7 @@ use core::cmp::Ordering;
 
 use crate::{number::Float, Number};
 
-use super::utils::abs_diff_iter;
+use super::utils::{abs_diff_iter, abs_diff_iter_as};
 
 /// Euclidean distance between two vectors.
 ///
@@ -63,7 +63,7 @@ pub fn euclidean<T: Number, U: Float>(x: &[T], y: &[T]) -> U {
 /// assert!((distance - 27.0).abs() <= f64::EPSILON);
 /// ```
 pub fn euclidean_sq<T: Number, U: Number>(x: &[T], y: &[T]) -> U {
-    abs_diff_iter(x, y).map(U::from).map(|v| v * v).sum()
+    abs_diff_iter_as::<T, U>(x, y).map(|v| v * v).sum()
 }
 
 /// Manhattan distance between two vectors.
@@ -122,7 +122,7 @@ pub fn manhattan<T: Number>(x: &[T], y: &[T]) -> T {
 /// assert!((distance - (81.0_f64).cbrt()).abs() <= f64::EPSILON);
 /// ```
 pub fn l3_norm<T: Number, U: Float>(x: &[T], y: &[T]) -> U {
-    abs_diff_iter(x, y).map(U::from).map(|v| v * v * v).sum::<U>().cbrt()
+    abs_diff_iter_as::<T, U>(x, y).map(|v| v * v * v).sum::<U>().cbrt()
 }
 
 /// L4-norm between two vectors.
@@ -151,8 +151,7 @@ pub fn l3_norm<T: Number, U: Float>(x: &[T], y: &[T]) -> U {
 /// assert!((distance - (243.0_f64).sqrt().sqrt()).abs() <= f64::EPSILON);
 /// ```
 pub fn l4_norm<T: Number, U: Float>(x: &[T], y: &[T]) -> U {
-    abs_diff_iter(x, y)
-        .map(U::from)
+    abs_diff_iter_as::<T, U>(x, y)
         .map(|v| v * v)
         .map(|v| v * v)
         .sum::<U>()
@@ -218,7 +217,7 @@ pub fn chebyshev<T: Number>(x: &[T], y: &[T]) -> T {
 /// assert!((distance - 81.0).abs() <= 1e-12);
 /// ```
 pub fn minkowski_p<T: Number, U: Float>(p: i32) -> impl Fn(&[T], &[T]) -> U {
-    move |x: &[T], y: &[T]| abs_diff_iter(x, y).map(U::from).map(|v| v.powi(p)).sum()
+    move |x: &[T], y: &[T]| abs_diff_iter_as::<T, U>(x, y).map(|v| v.powi(p)).sum()
 }
 
 /// General Lp-norm between two vectors.
@@ -5,6 +5,16 @@
 //! dimensionality may give unexpected results. Specifically, when one vector is
 //! shorter than the other, elements in the longer vector past the end of the
 //! shorter vector will be ignored.
+//!
+//! # Widening
+//! The functions which are generic over an output type `U` convert the
+//! elements to `U` before any arithmetic, so `U` may be wider than the type
+//! `T` of the elements. For example, `euclidean::<u8, f32>` and
+//! `euclidean_sq::<i8, i32>` cannot overflow for byte-quantized vectors, even
+//! though the difference between two `i8`s may not fit in an `i8`. `U` must
+//! be able to hold every element, so it must be signed if `T` is. The
+//! functions which return `T`, i.e. `manhattan` and `chebyshev`, compute in
+//! `T`.
 
 mod angular;
 mod lp_norms;
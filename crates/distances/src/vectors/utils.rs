@@ -10,6 +10,29 @@ pub fn abs_diff_iter<'a, T: Number>(x: &'a [T], y: &'a [T]) -> impl Iterator<Ite
     x.iter().zip(y.iter()).map(|(a, &b)| a.abs_diff(b))
 }
 
+/// An iterator over the absolute differences between the corresponding
+/// elements of two vectors, computed in the type `U`.
+///
+/// The elements are widened to `U` before they are subtracted, so the
+/// difference cannot overflow `T` as long as `U` can hold both elements. For
+/// example, the difference between `127_i8` and `-128_i8` is `255`, which does
+/// not fit in an `i8` but does fit in an `i16` or an `f32`.
+pub fn abs_diff_iter_as<'a, T: Number, U: Number>(x: &'a [T], y: &'a [T]) -> impl Iterator<Item = U> + 'a {
+    x.iter().zip(y.iter()).map(|(&a, &b)| abs_diff_as(a, b))
+}
+
+/// The absolute difference between two numbers, computed in the type `U`. See
+/// `abs_diff_iter_as`.
+pub fn abs_diff_as<T: Number, U: Number>(a: T, b: T) -> U {
+    let (a, b) = (U::from(a), U::from(b));
+    // Subtracting the smaller from the larger also works for unsigned `U`.
+    if a < b {
+        b - a
+    } else {
+        a - b
+    }
+}
+
 // /// An iterator over the differences between the corresponding elements of two
 // /// slices. The elements of the second slice are subtracted from those of the
 // /// first. It is the user's responsibility to ensure that there is no overflow.
@@ -75,3 +75,26 @@ fn simd_distances_f64(naive: fn(&[f64], &[f64]) -> f64, simd: fn(&[f64], &[f64])
         &failures[..5]
     );
 }
+
+#[test]
+fn simd_distances_tails() {
+    let mut rng = rand::thread_rng();
+
+    // Lengths which are not multiples of the number of lanes exercise the tails
+    // of the kernels.
+    for dimensionality in 1..=67 {
+        let data = random_data::random_tabular(2, dimensionality, -10_f32, 10., &mut rng);
+        let (x, y) = (&data[0], &data[1]);
+        let expected: f32 = euclidean_sq(x, y);
+        assert!((expected - simd::euclidean_sq_f32(x, y)).abs() <= 1e-3 * expected.max(1.));
+        let expected: f32 = cosine(x, y);
+        assert!((expected - simd::cosine_f32(x, y)).abs() <= 1e-4);
+
+        let data = random_data::random_tabular(2, dimensionality, -10_f64, 10., &mut rng);
+        let (x, y) = (&data[0], &data[1]);
+        let expected: f64 = euclidean_sq(x, y);
+        assert!((expected - simd::euclidean_sq_f64(x, y)).abs() <= 1e-9 * expected.max(1.));
+        let expected: f64 = cosine(x, y);
+        assert!((expected - simd::cosine_f64(x, y)).abs() <= 1e-9);
+    }
+}
@@ -0,0 +1,43 @@
+use distances::vectors::{bray_curtis, cosine, euclidean, euclidean_sq, l3_norm, minkowski};
+
+#[test]
+fn widening_i8() {
+    // The differences do not fit in an `i8`.
+    let x = vec![i8::MAX; 1_000];
+    let y = vec![i8::MIN; 1_000];
+
+    let d: i32 = euclidean_sq(&x, &y);
+    assert_eq!(d, 255 * 255 * 1_000);
+
+    let d: f64 = euclidean(&x, &y);
+    assert!((d - 255. * 1_000_f64.sqrt()).abs() <= 1e-6, "{d}");
+
+    let d: f64 = l3_norm(&x, &y);
+    assert!((d - 255. * 1_000_f64.cbrt()).abs() <= 1e-6, "{d}");
+
+    let d: f64 = minkowski(5)(&x, &y);
+    assert!((d - 255. * 1_000_f64.powf(0.2)).abs() <= 1e-6, "{d}");
+}
+
+#[test]
+fn widening_u8() {
+    // The squares and sums do not fit in a `u8`.
+    let x = vec![u8::MAX; 1_000];
+    let y = vec![0_u8; 1_000];
+
+    let d: u32 = euclidean_sq(&x, &y);
+    assert_eq!(d, 255 * 255 * 1_000);
+    let d: u32 = euclidean_sq(&y, &x);
+    assert_eq!(d, 255 * 255 * 1_000);
+
+    let d: f32 = cosine(&x, &x);
+    assert!(d.abs() <= 1e-5, "{d}");
+
+    let z = vec![u8::MAX / 5; 1_000];
+    let d: f32 = cosine(&x, &z);
+    assert!(d.abs() <= 1e-5, "{d}");
+
+    let w = vec![u8::MAX / 3; 1_000];
+    let d: f32 = bray_curtis(&x, &w);
+    assert!((d - 0.5).abs() <= f32::EPSILON, "{d}");
+}